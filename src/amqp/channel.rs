@@ -0,0 +1,640 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::BTreeMap;
+
+use crate::broker::Session;
+use crate::protocol::{ErrorCode, LeasedMessage};
+
+use super::error::{Error, Result};
+use super::frame::{content_frames, ContentHeader, Frame};
+use super::gateway::{Gateway, Settle};
+use super::message;
+use super::method::{reply, Close, Method, Routing};
+
+/// The most messages leased for a consumer at a time, which is all a consumer
+/// without a prefetch limit is sent per poll.
+const MAX_LEASE: u32 = 100;
+
+/// What a channel needs of its connection to serve a frame.
+pub(super) struct Context<'a> {
+    pub gateway: &'a Gateway,
+    pub session: &'a mut Session,
+    pub connection: u64,
+    /// The frames to send the client once the frame has been served.
+    pub out: &'a mut Vec<Frame>,
+}
+
+/// A message published on a channel whose content is still being received.
+struct Publish {
+    routing: Routing,
+    mandatory: bool,
+    header: Option<ContentHeader>,
+    body: Vec<u8>,
+}
+
+/// A consumer started on a channel with basic.consume.
+struct Consumer {
+    tag: String,
+    queue: String,
+    no_ack: bool,
+    /// The most unacknowledged messages the consumer may hold, or zero for
+    /// no limit.
+    prefetch: u16,
+    unacked: usize,
+}
+
+/// A message delivered on a channel that awaits acknowledgement.
+struct Unacked {
+    /// The consumer it was delivered to, or [None] if it was fetched with
+    /// basic.get.
+    consumer: Option<String>,
+    queue: String,
+    partition: u32,
+    offset: u64,
+}
+
+/// A single open AMQP channel.
+///
+/// Each channel consumes queues as its own rift queue consumer, holding the
+/// leases of the messages it delivered until they are acknowledged or
+/// rejected. Delivery tags are numbered per channel, as are the sequence
+/// numbers of published messages once publisher confirms are enabled.
+pub(super) struct Channel {
+    id: u16,
+    consumer_id: String,
+    frame_max: u32,
+    /// Whether the server closed the channel and awaits channel.close-ok.
+    closing: bool,
+    /// Whether deliveries have been paused with channel.flow.
+    paused: bool,
+    publish: Option<Publish>,
+    /// The sequence number of the next published message, once publisher
+    /// confirms are enabled.
+    confirms: Option<u64>,
+    /// The prefetch limit of consumers started from now on.
+    prefetch: u16,
+    /// The most unacknowledged messages the whole channel may hold, or zero
+    /// for no limit.
+    global_prefetch: u16,
+    consumers: Vec<Consumer>,
+    unacked: BTreeMap<u64, Unacked>,
+    delivery_tags: u64,
+    consumer_tags: u64,
+}
+
+impl Channel {
+    /// Create a newly opened channel of the supplied connection.
+    pub(super) fn new(connection: u64, id: u16, frame_max: u32) -> Channel {
+        Channel {
+            id,
+            consumer_id: format!("amqp-{}-{}", connection, id),
+            frame_max,
+            closing: false,
+            paused: false,
+            publish: None,
+            confirms: None,
+            prefetch: 0,
+            global_prefetch: 0,
+            consumers: Vec::new(),
+            unacked: BTreeMap::new(),
+            delivery_tags: 0,
+            consumer_tags: 0,
+        }
+    }
+
+    /// Returns whether the server closed the channel and awaits
+    /// channel.close-ok, ignoring every other frame until then.
+    pub(super) fn is_closing(&self) -> bool {
+        self.closing
+    }
+
+    /// Serve a single frame sent on this channel.
+    pub(super) fn handle(&mut self, ctx: &mut Context, frame: Frame) -> Result<()> {
+        match frame {
+            Frame::Method(_, method) => {
+                if self.publish.is_some() {
+                    return Err(unexpected_frame("expected message content"));
+                }
+                self.method(ctx, method)
+            }
+            Frame::Header(_, header) => match &mut self.publish {
+                Some(publish) if publish.header.is_none() => {
+                    let complete = header.body_size == 0;
+                    publish.header = Some(header);
+                    match complete {
+                        true => self.publish(ctx),
+                        false => Ok(()),
+                    }
+                }
+                _ => Err(unexpected_frame("unexpected content header")),
+            },
+            Frame::Body(_, body) => {
+                let publish = match &mut self.publish {
+                    Some(publish) => publish,
+                    None => return Err(unexpected_frame("unexpected content body")),
+                };
+                let body_size = match &publish.header {
+                    Some(header) => header.body_size,
+                    None => return Err(unexpected_frame("expected a content header")),
+                };
+                publish.body.extend_from_slice(&body);
+                match (publish.body.len() as u64).cmp(&body_size) {
+                    std::cmp::Ordering::Less => Ok(()),
+                    std::cmp::Ordering::Equal => self.publish(ctx),
+                    std::cmp::Ordering::Greater => Err(Error::Malformed {
+                        reason: "content body exceeds the size in its header",
+                    }),
+                }
+            }
+            Frame::Heartbeat => Ok(()),
+        }
+    }
+
+    /// Deliver the messages each consumer has room for, unless deliveries
+    /// are paused. Consumers of queues that were deleted are cancelled.
+    pub(super) fn deliver(&mut self, ctx: &mut Context) -> Result<()> {
+        if self.closing || self.paused {
+            return Ok(());
+        }
+        let mut cancelled = Vec::new();
+        for idx in 0..self.consumers.len() {
+            let consumer = &self.consumers[idx];
+            let mut max_messages = MAX_LEASE;
+            if !consumer.no_ack {
+                if consumer.prefetch > 0 {
+                    let room = (consumer.prefetch as usize).saturating_sub(consumer.unacked);
+                    max_messages = max_messages.min(room as u32);
+                }
+                if self.global_prefetch > 0 {
+                    let room = (self.global_prefetch as usize).saturating_sub(self.unacked.len());
+                    max_messages = max_messages.min(room as u32);
+                }
+            }
+            if max_messages == 0 {
+                continue;
+            }
+
+            let leased = ctx.gateway.lease(
+                ctx.connection,
+                &consumer.queue,
+                &self.consumer_id,
+                max_messages,
+            );
+            let messages = match leased {
+                Ok(messages) => messages,
+                Err(Error::Broker(err)) if err.code == ErrorCode::TopicNotFound => {
+                    cancelled.push(consumer.tag.clone());
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            let (tag, queue, no_ack) = (
+                consumer.tag.clone(),
+                consumer.queue.clone(),
+                consumer.no_ack,
+            );
+            for message in messages {
+                let delivery_tag = self.track(ctx, Some(&tag), &queue, &message, no_ack)?;
+                let method = Method::BasicDeliver {
+                    consumer_tag: tag.clone(),
+                    delivery_tag,
+                    redelivered: message.delivery_count > 1,
+                    routing: message::routing(&message.record.record, &queue),
+                };
+                self.send_content(ctx, method, message);
+            }
+        }
+
+        for tag in cancelled {
+            self.consumers.retain(|consumer| consumer.tag != tag);
+            ctx.out.push(Frame::Method(
+                self.id,
+                Method::BasicCancel {
+                    consumer_tag: tag,
+                    nowait: true,
+                },
+            ));
+        }
+        Ok(())
+    }
+
+    /// Close the channel after a channel exception, releasing its deliveries
+    /// and consumers and telling the client why.
+    pub(super) fn fail(&mut self, ctx: &mut Context, code: u16, text: String, method: (u16, u16)) {
+        self.release(ctx.gateway);
+        self.closing = true;
+        ctx.out.push(Frame::Method(
+            self.id,
+            Method::ChannelClose(Close {
+                reply_code: code,
+                reply_text: text,
+                class_id: method.0,
+                method_id: method.1,
+            }),
+        ));
+    }
+
+    /// Release the channel's unacknowledged deliveries for redelivery and
+    /// cancel its consumers, once it is closed.
+    pub(super) fn release(&mut self, gateway: &Gateway) {
+        let unacked = std::mem::take(&mut self.unacked).into_values().collect();
+        if let Err(err) = self.settle(gateway, unacked, Settle::Requeue) {
+            warn!(gateway.logger, "Failed to requeue unacknowledged AMQP deliveries."; "consumer_id" => &self.consumer_id, "error" => err.to_string());
+        }
+        for consumer in self.consumers.drain(..) {
+            gateway.remove_consumer(&consumer.queue);
+        }
+        self.publish = None;
+    }
+
+    fn method(&mut self, ctx: &mut Context, method: Method) -> Result<()> {
+        let gateway = ctx.gateway;
+        let reply = match method {
+            Method::ChannelFlow { active } => {
+                self.paused = !active;
+                Some(Method::ChannelFlowOk { active })
+            }
+            Method::ExchangeDeclare {
+                exchange,
+                kind,
+                passive,
+                nowait,
+                ..
+            } => {
+                gateway.declare_exchange(&exchange, &kind, passive)?;
+                (!nowait).then_some(Method::ExchangeDeclareOk)
+            }
+            Method::ExchangeDelete {
+                exchange,
+                if_unused,
+                nowait,
+            } => {
+                gateway.delete_exchange(&exchange, if_unused)?;
+                (!nowait).then_some(Method::ExchangeDeleteOk)
+            }
+            Method::QueueDeclare {
+                queue,
+                passive,
+                exclusive,
+                auto_delete,
+                nowait,
+                ..
+            } => {
+                let (queue, consumer_count) = gateway.declare_queue(
+                    ctx.connection,
+                    ctx.session,
+                    &queue,
+                    passive,
+                    exclusive,
+                    auto_delete,
+                )?;
+                let message_count = gateway.message_count(&queue)?;
+                (!nowait).then_some(Method::QueueDeclareOk {
+                    queue,
+                    message_count,
+                    consumer_count,
+                })
+            }
+            Method::QueueBind {
+                queue,
+                exchange,
+                routing_key,
+                nowait,
+                arguments,
+            } => {
+                gateway.bind(
+                    ctx.connection,
+                    &queue,
+                    &exchange,
+                    &routing_key,
+                    &arguments,
+                    true,
+                )?;
+                (!nowait).then_some(Method::QueueBindOk)
+            }
+            Method::QueueUnbind {
+                queue,
+                exchange,
+                routing_key,
+                arguments,
+            } => {
+                gateway.bind(
+                    ctx.connection,
+                    &queue,
+                    &exchange,
+                    &routing_key,
+                    &arguments,
+                    false,
+                )?;
+                Some(Method::QueueUnbindOk)
+            }
+            Method::QueueDelete {
+                queue,
+                if_unused,
+                nowait,
+                ..
+            } => {
+                let message_count = gateway.delete_queue(ctx.connection, &queue, if_unused)?;
+                (!nowait).then_some(Method::QueueDeleteOk { message_count })
+            }
+            Method::BasicQos {
+                prefetch_count,
+                global,
+                ..
+            } => {
+                match global {
+                    true => self.global_prefetch = prefetch_count,
+                    false => self.prefetch = prefetch_count,
+                }
+                Some(Method::BasicQosOk)
+            }
+            Method::BasicConsume {
+                queue,
+                consumer_tag,
+                no_ack,
+                nowait,
+                ..
+            } => {
+                let tag = match consumer_tag.is_empty() {
+                    true => {
+                        self.consumer_tags += 1;
+                        format!("amq.ctag-{}", self.consumer_tags)
+                    }
+                    false => consumer_tag,
+                };
+                if self.consumers.iter().any(|consumer| consumer.tag == tag) {
+                    return Err(Error::Refused {
+                        code: reply::NOT_ALLOWED,
+                        text: format!("consumer tag '{}' is already in use", tag),
+                    });
+                }
+                gateway.add_consumer(ctx.connection, &queue)?;
+                self.consumers.push(Consumer {
+                    tag: tag.clone(),
+                    queue,
+                    no_ack,
+                    prefetch: self.prefetch,
+                    unacked: 0,
+                });
+                (!nowait).then_some(Method::BasicConsumeOk { consumer_tag: tag })
+            }
+            Method::BasicCancel {
+                consumer_tag,
+                nowait,
+            } => {
+                // Messages already delivered to the consumer remain unacknowledged.
+                if let Some(idx) = self
+                    .consumers
+                    .iter()
+                    .position(|consumer| consumer.tag == consumer_tag)
+                {
+                    let consumer = self.consumers.remove(idx);
+                    gateway.remove_consumer(&consumer.queue);
+                }
+                (!nowait).then_some(Method::BasicCancelOk { consumer_tag })
+            }
+            Method::BasicPublish {
+                routing,
+                mandatory,
+                immediate,
+            } => {
+                if immediate {
+                    return Err(Error::Refused {
+                        code: reply::NOT_IMPLEMENTED,
+                        text: String::from("immediate=true"),
+                    });
+                }
+                self.publish = Some(Publish {
+                    routing,
+                    mandatory,
+                    header: None,
+                    body: Vec::new(),
+                });
+                None
+            }
+            Method::BasicGet { queue, no_ack } => {
+                let mut messages = gateway.lease(ctx.connection, &queue, &self.consumer_id, 1)?;
+                match messages.pop() {
+                    Some(message) => {
+                        let delivery_tag = self.track(ctx, None, &queue, &message, no_ack)?;
+                        let method = Method::BasicGetOk {
+                            delivery_tag,
+                            redelivered: message.delivery_count > 1,
+                            routing: message::routing(&message.record.record, &queue),
+                            message_count: gateway.message_count(&queue)?,
+                        };
+                        self.send_content(ctx, method, message);
+                        None
+                    }
+                    None => Some(Method::BasicGetEmpty),
+                }
+            }
+            Method::BasicAck {
+                delivery_tag,
+                multiple,
+            } => {
+                let unacked = self.take(delivery_tag, multiple)?;
+                self.settle(gateway, unacked, Settle::Ack)?;
+                None
+            }
+            Method::BasicReject {
+                delivery_tag,
+                requeue,
+            } => {
+                let unacked = self.take(delivery_tag, false)?;
+                self.settle(gateway, unacked, rejected(requeue))?;
+                None
+            }
+            Method::BasicNack {
+                delivery_tag,
+                multiple,
+                requeue,
+            } => {
+                let unacked = self.take(delivery_tag, multiple)?;
+                self.settle(gateway, unacked, rejected(requeue))?;
+                None
+            }
+            // Redelivering to the original consumer is not supported, so
+            // messages are always requeued.
+            Method::BasicRecover { .. } => {
+                let unacked = self.take(0, true)?;
+                self.settle(gateway, unacked, Settle::Requeue)?;
+                Some(Method::BasicRecoverOk)
+            }
+            Method::ConfirmSelect { nowait } => {
+                self.confirms = Some(self.confirms.unwrap_or(1));
+                (!nowait).then_some(Method::ConfirmSelectOk)
+            }
+            method => {
+                let (class_id, method_id) = method.id();
+                return Err(Error::Refused {
+                    code: reply::COMMAND_INVALID,
+                    text: format!("unexpected method {}.{}", class_id, method_id),
+                });
+            }
+        };
+        if let Some(reply) = reply {
+            ctx.out.push(Frame::Method(self.id, reply));
+        }
+        Ok(())
+    }
+
+    /// Route a message whose content has been received in full, returning it
+    /// to the client if it was mandatory but unroutable, and confirming it if
+    /// publisher confirms are enabled.
+    fn publish(&mut self, ctx: &mut Context) -> Result<()> {
+        let publish = match self.publish.take() {
+            Some(publish) => publish,
+            None => return Ok(()),
+        };
+        let properties = publish
+            .header
+            .map(|header| header.properties)
+            .unwrap_or_default();
+        let returned = publish
+            .mandatory
+            .then(|| (properties.clone(), publish.body.clone()));
+        let record = message::to_record(&properties, publish.body);
+        let routing = publish.routing;
+        let routed = ctx
+            .gateway
+            .publish(&routing.exchange, &routing.routing_key, record)?;
+
+        if let (false, Some((properties, body))) = (routed, returned) {
+            let method = Method::BasicReturn {
+                reply_code: reply::NO_ROUTE,
+                reply_text: String::from("NO_ROUTE"),
+                routing,
+            };
+            ctx.out.extend(content_frames(
+                self.id,
+                method,
+                properties,
+                &body,
+                self.frame_max,
+            ));
+        }
+        if let Some(sequence) = self.confirms.as_mut() {
+            ctx.out.push(Frame::Method(
+                self.id,
+                Method::BasicAck {
+                    delivery_tag: *sequence,
+                    multiple: false,
+                },
+            ));
+            *sequence += 1;
+        }
+        Ok(())
+    }
+
+    /// Assign a leased message its delivery tag, tracking it until it is
+    /// acknowledged, or acknowledging it right away if no acknowledgement is
+    /// expected.
+    fn track(
+        &mut self,
+        ctx: &mut Context,
+        consumer: Option<&str>,
+        queue: &str,
+        message: &LeasedMessage,
+        no_ack: bool,
+    ) -> Result<u64> {
+        self.delivery_tags += 1;
+        let unacked = Unacked {
+            consumer: consumer.map(str::to_owned),
+            queue: queue.to_owned(),
+            partition: message.partition,
+            offset: message.record.offset,
+        };
+        if no_ack {
+            self.settle(ctx.gateway, vec![unacked], Settle::Ack)?;
+        } else {
+            if let Some(consumer) = self.consumer_mut(consumer) {
+                consumer.unacked += 1;
+            }
+            self.unacked.insert(self.delivery_tags, unacked);
+        }
+        Ok(self.delivery_tags)
+    }
+
+    fn send_content(&mut self, ctx: &mut Context, method: Method, message: LeasedMessage) {
+        let record = message.record.record;
+        let properties = message::to_properties(&record);
+        let body = record.value.unwrap_or_default();
+        ctx.out.extend(content_frames(
+            self.id,
+            method,
+            properties,
+            &body,
+            self.frame_max,
+        ));
+    }
+
+    /// Stop tracking the deliveries an acknowledgement or rejection refers
+    /// to. Multiple deliveries up to and including the tag are taken at once
+    /// if `multiple` is set, all of them if the tag is zero.
+    fn take(&mut self, delivery_tag: u64, multiple: bool) -> Result<Vec<Unacked>> {
+        let taken: Vec<Unacked> = if multiple {
+            let end = match delivery_tag {
+                0 => u64::MAX,
+                delivery_tag => delivery_tag,
+            };
+            let tags: Vec<u64> = self.unacked.range(..=end).map(|(tag, _)| *tag).collect();
+            tags.iter()
+                .filter_map(|tag| self.unacked.remove(tag))
+                .collect()
+        } else {
+            self.unacked.remove(&delivery_tag).into_iter().collect()
+        };
+        if taken.is_empty() && delivery_tag != 0 {
+            return Err(Error::Refused {
+                code: reply::PRECONDITION_FAILED,
+                text: format!("unknown delivery tag {}", delivery_tag),
+            });
+        }
+        for unacked in &taken {
+            if let Some(consumer) = self.consumer_mut(unacked.consumer.as_deref()) {
+                consumer.unacked = consumer.unacked.saturating_sub(1);
+            }
+        }
+        Ok(taken)
+    }
+
+    /// Acknowledge deliveries, or release them for redelivery if `requeue`
+    /// is set.
+    fn settle(&self, gateway: &Gateway, unacked: Vec<Unacked>, settle: Settle) -> Result<()> {
+        let mut partitions: BTreeMap<(String, u32), Vec<u64>> = BTreeMap::new();
+        for unacked in unacked {
+            partitions
+                .entry((unacked.queue, unacked.partition))
+                .or_default()
+                .push(unacked.offset);
+        }
+        for ((queue, partition), offsets) in partitions {
+            gateway.settle(&queue, &self.consumer_id, partition, offsets, settle)?;
+        }
+        Ok(())
+    }
+
+    fn consumer_mut(&mut self, tag: Option<&str>) -> Option<&mut Consumer> {
+        let tag = tag?;
+        self.consumers
+            .iter_mut()
+            .find(|consumer| consumer.tag == tag)
+    }
+}
+
+/// Returns how messages rejected by basic.reject or basic.nack are settled.
+fn rejected(requeue: bool) -> Settle {
+    match requeue {
+        true => Settle::Requeue,
+        false => Settle::Reject,
+    }
+}
+
+fn unexpected_frame(text: &str) -> Error {
+    Error::Refused {
+        code: reply::UNEXPECTED_FRAME,
+        text: text.to_owned(),
+    }
+}
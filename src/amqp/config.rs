@@ -0,0 +1,84 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::net::SocketAddr;
+
+use structopt::StructOpt;
+
+#[derive(Debug, Clone, StructOpt)]
+/// Rift AMQP listener configuration.
+pub struct Config {
+    #[structopt(
+        long = "amqp-listen-address",
+        env = "RIFT_AMQP_LISTEN_ADDRESS",
+        help = "The address to listen for AMQP connections on.",
+        long_help = "Sets the address and port an AMQP 0-9-1 listener binds to. AMQP clients are only served when this is set.",
+        takes_value = true
+    )]
+    /// Define the address to listen for AMQP connections on, if any.
+    pub amqp_listen_address: Option<SocketAddr>,
+
+    #[structopt(
+        long = "amqp-frame-max",
+        env = "RIFT_AMQP_FRAME_MAX",
+        help = "The maximum size of a single AMQP frame.",
+        long_help = "Sets the largest AMQP frame, in bytes, offered to clients while tuning a connection. Clients may lower it but not raise it.",
+        default_value = "131072",
+        takes_value = true
+    )]
+    /// Define the maximum AMQP frame size in bytes.
+    pub amqp_frame_max: u32,
+
+    #[structopt(
+        long = "amqp-channel-max",
+        env = "RIFT_AMQP_CHANNEL_MAX",
+        help = "The most channels an AMQP connection may open.",
+        long_help = "Sets the highest channel number offered to clients while tuning a connection. Clients may lower it but not raise it.",
+        default_value = "2047",
+        takes_value = true
+    )]
+    /// Define the maximum number of channels per AMQP connection.
+    pub amqp_channel_max: u16,
+
+    #[structopt(
+        long = "amqp-heartbeat-secs",
+        env = "RIFT_AMQP_HEARTBEAT_SECS",
+        help = "The heartbeat interval offered to AMQP clients.",
+        long_help = "Sets the heartbeat interval, in seconds, offered to clients while tuning a connection. Connections that stay silent for two intervals are closed. Zero disables heartbeats unless the client asks for them.",
+        default_value = "60",
+        takes_value = true
+    )]
+    /// Define the AMQP heartbeat interval in seconds.
+    pub amqp_heartbeat_secs: u16,
+
+    #[structopt(
+        long = "amqp-partitions",
+        env = "RIFT_AMQP_PARTITIONS",
+        help = "The number of partitions of queues declared by AMQP clients.",
+        long_help = "Sets how many partitions the topic backing an AMQP queue is created with. AMQP clients expect queues to be ordered, which only a single partition guarantees.",
+        default_value = "1",
+        takes_value = true
+    )]
+    /// Define the number of partitions of topics created for AMQP queues.
+    pub amqp_partitions: u32,
+}
+
+impl Config {
+    /// Returns whether or not AMQP clients are served, which requires a
+    /// listen address.
+    pub fn enabled(&self) -> bool {
+        self.amqp_listen_address.is_some()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            amqp_listen_address: None,
+            amqp_frame_max: 131072,
+            amqp_channel_max: 2047,
+            amqp_heartbeat_secs: 60,
+            amqp_partitions: 1,
+        }
+    }
+}
@@ -0,0 +1,482 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::BTreeMap,
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::{Shutdown, TcpStream},
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::broker::Session;
+use crate::protocol::ErrorCode;
+
+use super::channel::{Channel, Context};
+use super::error::{Error, Result};
+use super::frame::{read_frame, write_frame, Frame, MIN_FRAME_MAX, PROTOCOL_HEADER};
+use super::gateway::Gateway;
+use super::method::{reply, Close, Method};
+use super::table::{FieldValue, Table};
+
+/// How long a client has to complete the connection handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the server waits for connection.close-ok after closing a
+/// connection.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How often the queues consumed on a connection are checked for new
+/// messages.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// The authentication mechanisms offered to clients, which are accepted
+/// whatever the credentials.
+const MECHANISMS: [&str; 2] = ["PLAIN", "AMQPLAIN"];
+
+type Frames = Receiver<Result<Option<Frame>>>;
+
+/// Serve a single AMQP client connection until it is closed.
+///
+/// The connection must start with the AMQP 0-9-1 protocol header and the
+/// connection handshake, after which frames are read on a separate thread so
+/// that messages can be delivered to the client while it is idle. Once the
+/// connection closes, however it closes, the messages delivered on it that
+/// were not acknowledged are requeued and its exclusive queues deleted.
+pub(super) fn serve(logger: slog::Logger, stream: TcpStream, gateway: Arc<Gateway>) -> Result<()> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream.try_clone()?);
+    let mut header = [0; 8];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+        Err(err) => return Err(err.into()),
+    }
+    if header != PROTOCOL_HEADER {
+        // Clients are told which protocol is supported before disconnecting.
+        writer.write_all(&PROTOCOL_HEADER)?;
+        writer.flush()?;
+        return Err(Error::UnsupportedProtocol { header });
+    }
+
+    let tuning = match handshake(&gateway, &mut reader, &mut writer) {
+        Ok(Some(tuning)) => tuning,
+        Ok(None) => return Ok(()),
+        Err(err) => {
+            if let Some((code, text)) = exception(&err) {
+                let close = Method::ConnectionClose(Close {
+                    reply_code: code,
+                    reply_text: text,
+                    class_id: 0,
+                    method_id: 0,
+                });
+                write_frame(&mut writer, &Frame::Method(0, close))?;
+                writer.flush()?;
+            }
+            return Err(err);
+        }
+    };
+    stream.set_read_timeout(None)?;
+
+    let (sender, frames) = mpsc::channel();
+    let frame_max = tuning.frame_max;
+    thread::spawn(move || loop {
+        let frame = read_frame(&mut reader, frame_max);
+        let more = matches!(frame, Ok(Some(_)));
+        if sender.send(frame).is_err() || !more {
+            return;
+        }
+    });
+
+    let (id, session) = gateway.connect();
+    let mut connection = Connection {
+        logger: logger.new(o!("connection" => id)),
+        gateway: gateway.clone(),
+        writer,
+        id,
+        session,
+        tuning,
+        channels: BTreeMap::new(),
+        out: Vec::new(),
+        method: (0, 0),
+        sent_at: Instant::now(),
+        received_at: Instant::now(),
+    };
+    let result = connection.run(&frames);
+    if let Err(err) = &result {
+        if let Some((code, text)) = exception(err) {
+            connection.close(&frames, code, text);
+        }
+    }
+    connection.release();
+    let _ = stream.shutdown(Shutdown::Both);
+    if result.is_ok() {
+        debug!(connection.logger, "AMQP client disconnected.");
+    }
+    result
+}
+
+/// Returns the reply code and text an error is reported to the client with,
+/// if the connection is still usable.
+fn exception(err: &Error) -> Option<(u16, String)> {
+    let code = match err {
+        Error::Refused { code, text } => return Some((*code, text.clone())),
+        Error::Broker(err) => match err.code {
+            ErrorCode::TopicNotFound | ErrorCode::ExchangeNotFound => reply::NOT_FOUND,
+            ErrorCode::InvalidTopic
+            | ErrorCode::TopicAlreadyExists
+            | ErrorCode::InvalidConfig
+            | ErrorCode::InvalidRequest
+            | ErrorCode::InvalidQueue
+            | ErrorCode::InvalidExchange => reply::PRECONDITION_FAILED,
+            _ => reply::INTERNAL_ERROR,
+        },
+        Error::Codec(_) => reply::SYNTAX_ERROR,
+        Error::Malformed { .. } | Error::FrameTooLarge { .. } => reply::FRAME_ERROR,
+        Error::UnsupportedMethod { .. } => reply::NOT_IMPLEMENTED,
+        Error::Io(_)
+        | Error::Bind { .. }
+        | Error::Accept(_)
+        | Error::UnsupportedProtocol { .. }
+        | Error::Closed { .. }
+        | Error::HeartbeatTimeout => return None,
+    };
+    Some((code, err.to_string()))
+}
+
+/// Returns whether a reply code closes only the channel the method that
+/// caused it was sent on, rather than the whole connection.
+fn is_channel_exception(code: u16) -> bool {
+    matches!(
+        code,
+        reply::NO_ROUTE
+            | reply::ACCESS_REFUSED
+            | reply::NOT_FOUND
+            | reply::RESOURCE_LOCKED
+            | reply::PRECONDITION_FAILED
+    )
+}
+
+/// The limits negotiated with a client during the handshake.
+#[derive(Debug, Clone, Copy)]
+struct Tuning {
+    channel_max: u16,
+    frame_max: u32,
+    heartbeat: Option<Duration>,
+}
+
+/// Walk the client through the connection handshake, returning the limits
+/// negotiated with it or [None] if it disconnected.
+fn handshake(
+    gateway: &Gateway,
+    reader: &mut BufReader<TcpStream>,
+    writer: &mut BufWriter<TcpStream>,
+) -> Result<Option<Tuning>> {
+    let start = Method::ConnectionStart {
+        server_properties: server_properties(),
+        mechanisms: MECHANISMS.join(" ").into_bytes(),
+        locales: b"en_US".to_vec(),
+    };
+    write_frame(writer, &Frame::Method(0, start))?;
+    writer.flush()?;
+    match next_method(reader, writer, MIN_FRAME_MAX)? {
+        Some(Method::ConnectionStartOk { mechanism, .. }) => {
+            if !MECHANISMS.contains(&mechanism.as_str()) {
+                return Err(Error::Refused {
+                    code: reply::ACCESS_REFUSED,
+                    text: format!("unsupported mechanism '{}'", mechanism),
+                });
+            }
+        }
+        Some(_) => return Err(command_invalid("expected connection.start-ok")),
+        None => return Ok(None),
+    }
+
+    let tune = Method::ConnectionTune {
+        channel_max: gateway.channel_max,
+        frame_max: gateway.frame_max,
+        heartbeat: gateway.heartbeat,
+    };
+    write_frame(writer, &Frame::Method(0, tune))?;
+    writer.flush()?;
+    let tuning = match next_method(reader, writer, MIN_FRAME_MAX)? {
+        Some(Method::ConnectionTuneOk {
+            channel_max,
+            frame_max,
+            heartbeat,
+        }) => Tuning {
+            channel_max: negotiate(gateway.channel_max, channel_max),
+            frame_max: negotiate(gateway.frame_max, frame_max),
+            heartbeat: (heartbeat > 0).then(|| Duration::from_secs(heartbeat as u64)),
+        },
+        Some(_) => return Err(command_invalid("expected connection.tune-ok")),
+        None => return Ok(None),
+    };
+    if tuning.frame_max != 0 && tuning.frame_max < MIN_FRAME_MAX {
+        return Err(Error::Refused {
+            code: reply::NOT_ALLOWED,
+            text: format!("frame_max must be at least {}", MIN_FRAME_MAX),
+        });
+    }
+
+    match next_method(reader, writer, tuning.frame_max)? {
+        Some(Method::ConnectionOpen { .. }) => {}
+        Some(_) => return Err(command_invalid("expected connection.open")),
+        None => return Ok(None),
+    }
+    write_frame(writer, &Frame::Method(0, Method::ConnectionOpenOk))?;
+    writer.flush()?;
+    Ok(Some(tuning))
+}
+
+/// Read the next method of the handshake, which must be sent on channel
+/// zero, returning [None] if the client disconnected or closed the
+/// connection instead.
+fn next_method(
+    reader: &mut BufReader<TcpStream>,
+    writer: &mut BufWriter<TcpStream>,
+    frame_max: u32,
+) -> Result<Option<Method>> {
+    loop {
+        match read_frame(reader, frame_max)? {
+            None => return Ok(None),
+            Some(Frame::Heartbeat) => continue,
+            Some(Frame::Method(0, Method::ConnectionClose(_))) => {
+                write_frame(writer, &Frame::Method(0, Method::ConnectionCloseOk))?;
+                writer.flush()?;
+                return Ok(None);
+            }
+            Some(Frame::Method(0, method)) => return Ok(Some(method)),
+            Some(_) => return Err(command_invalid("expected a connection method")),
+        }
+    }
+}
+
+/// Returns the lower of the server's and client's limits, where zero means
+/// no limit.
+fn negotiate<T: Copy + Ord + Default>(server: T, client: T) -> T {
+    match (server == T::default(), client == T::default()) {
+        (true, _) => client,
+        (_, true) => server,
+        _ => server.min(client),
+    }
+}
+
+fn server_properties() -> Table {
+    let capabilities = ["publisher_confirms", "basic.nack", "consumer_cancel_notify"]
+        .into_iter()
+        .map(|capability| (capability.to_owned(), FieldValue::Bool(true)))
+        .collect();
+    let string = |value: &str| FieldValue::LongString(value.as_bytes().to_vec());
+    vec![
+        (String::from("product"), string("rift")),
+        (String::from("version"), string(env!("CARGO_PKG_VERSION"))),
+        (String::from("platform"), string("Rust")),
+        (
+            String::from("capabilities"),
+            FieldValue::Table(capabilities),
+        ),
+    ]
+}
+
+fn command_invalid(text: &str) -> Error {
+    Error::Refused {
+        code: reply::COMMAND_INVALID,
+        text: text.to_owned(),
+    }
+}
+
+/// An established AMQP connection along with its open channels.
+struct Connection {
+    logger: slog::Logger,
+    gateway: Arc<Gateway>,
+    writer: BufWriter<TcpStream>,
+    id: u64,
+    /// The broker session holding the server named queues declared on this
+    /// connection, which are deleted when it is dropped.
+    session: Session,
+    tuning: Tuning,
+    channels: BTreeMap<u16, Channel>,
+    /// The frames to send once the current frame has been served.
+    out: Vec<Frame>,
+    /// The class and method ids of the last method received, which
+    /// exceptions are reported against.
+    method: (u16, u16),
+    sent_at: Instant,
+    received_at: Instant,
+}
+
+impl Connection {
+    /// Serve frames and deliver messages until the client closes the
+    /// connection or a connection exception occurs.
+    fn run(&mut self, frames: &Frames) -> Result<()> {
+        loop {
+            match frames.recv_timeout(POLL_INTERVAL) {
+                Ok(Ok(Some(frame))) => {
+                    self.received_at = Instant::now();
+                    let open = self.handle(frame);
+                    self.flush()?;
+                    if !open? {
+                        return Ok(());
+                    }
+                }
+                Ok(Ok(None)) | Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                Ok(Err(err)) => return Err(err),
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+
+            self.method = (0, 0);
+            for channel in self.channels.values_mut() {
+                let mut ctx = Context {
+                    gateway: &self.gateway,
+                    session: &mut self.session,
+                    connection: self.id,
+                    out: &mut self.out,
+                };
+                channel.deliver(&mut ctx)?;
+            }
+            if let Some(interval) = self.tuning.heartbeat {
+                if self.received_at.elapsed() > interval * 2 {
+                    return Err(Error::HeartbeatTimeout);
+                }
+                if self.out.is_empty() && self.sent_at.elapsed() >= interval {
+                    self.out.push(Frame::Heartbeat);
+                }
+            }
+            self.flush()?;
+        }
+    }
+
+    /// Serve a single frame, returning whether the connection remains open.
+    fn handle(&mut self, frame: Frame) -> Result<bool> {
+        if let Frame::Method(_, method) = &frame {
+            self.method = method.id();
+        }
+        match frame {
+            Frame::Heartbeat => {}
+            Frame::Method(0, Method::ConnectionClose(close)) => {
+                self.out.push(Frame::Method(0, Method::ConnectionCloseOk));
+                if close.reply_code != reply::SUCCESS {
+                    self.flush()?;
+                    return Err(Error::Closed {
+                        code: close.reply_code,
+                        text: close.reply_text,
+                    });
+                }
+                return Ok(false);
+            }
+            Frame::Method(0, _) => return Err(command_invalid("unexpected method on channel 0")),
+            Frame::Header(0, _) | Frame::Body(0, _) => {
+                return Err(Error::Refused {
+                    code: reply::UNEXPECTED_FRAME,
+                    text: String::from("content frames must not be sent on channel 0"),
+                })
+            }
+            Frame::Method(id, Method::ChannelOpen) => {
+                if id > self.tuning.channel_max || self.channels.contains_key(&id) {
+                    return Err(Error::Refused {
+                        code: reply::CHANNEL_ERROR,
+                        text: format!("channel {} cannot be opened", id),
+                    });
+                }
+                let channel = Channel::new(self.id, id, self.tuning.frame_max);
+                self.channels.insert(id, channel);
+                self.out.push(Frame::Method(id, Method::ChannelOpenOk));
+            }
+            Frame::Method(id, Method::ChannelClose(_)) => {
+                if let Some(mut channel) = self.channels.remove(&id) {
+                    channel.release(&self.gateway);
+                }
+                self.out.push(Frame::Method(id, Method::ChannelCloseOk));
+            }
+            Frame::Method(id, Method::ChannelCloseOk) => {
+                if self
+                    .channels
+                    .get(&id)
+                    .is_some_and(|channel| channel.is_closing())
+                {
+                    self.channels.remove(&id);
+                }
+            }
+            Frame::Method(id, _) | Frame::Header(id, _) | Frame::Body(id, _) => {
+                let channel = match self.channels.get_mut(&id) {
+                    Some(channel) => channel,
+                    None => {
+                        return Err(Error::Refused {
+                            code: reply::CHANNEL_ERROR,
+                            text: format!("channel {} is not open", id),
+                        })
+                    }
+                };
+                // Frames sent before the client saw the channel being closed
+                // are discarded.
+                if channel.is_closing() {
+                    return Ok(true);
+                }
+                let mut ctx = Context {
+                    gateway: &self.gateway,
+                    session: &mut self.session,
+                    connection: self.id,
+                    out: &mut self.out,
+                };
+                if let Err(err) = channel.handle(&mut ctx, frame) {
+                    match exception(&err) {
+                        Some((code, text)) if is_channel_exception(code) => {
+                            debug!(self.logger, "Closing AMQP channel."; "channel" => id, "code" => code, "error" => &text);
+                            channel.fail(&mut ctx, code, text, self.method);
+                        }
+                        _ => return Err(err),
+                    }
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Close the connection after a connection exception, waiting a little
+    /// while for the client to confirm.
+    fn close(&mut self, frames: &Frames, code: u16, text: String) {
+        let close = Method::ConnectionClose(Close {
+            reply_code: code,
+            reply_text: text,
+            class_id: self.method.0,
+            method_id: self.method.1,
+        });
+        self.out.push(Frame::Method(0, close));
+        if self.flush().is_err() {
+            return;
+        }
+        let deadline = Instant::now() + CLOSE_TIMEOUT;
+        while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+            match frames.recv_timeout(timeout) {
+                Ok(Ok(Some(Frame::Method(0, Method::ConnectionCloseOk)))) => return,
+                Ok(Ok(Some(_))) => {}
+                _ => return,
+            }
+        }
+    }
+
+    /// Release the deliveries and consumers of every channel, along with the
+    /// connection's exclusive queues, once the connection closes.
+    fn release(&mut self) {
+        for channel in self.channels.values_mut() {
+            channel.release(&self.gateway);
+        }
+        self.channels.clear();
+        self.gateway.disconnect(self.id);
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.out.is_empty() {
+            return Ok(());
+        }
+        for frame in self.out.drain(..) {
+            write_frame(&mut self.writer, &frame)?;
+        }
+        self.writer.flush()?;
+        self.sent_at = Instant::now();
+        Ok(())
+    }
+}
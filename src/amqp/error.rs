@@ -0,0 +1,106 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{io, net::SocketAddr, result};
+
+use thiserror::Error;
+
+use crate::codec;
+use crate::protocol::ResponseError;
+
+/// Custom Result wrapper to simplify usage.
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents errors serving AMQP clients.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// Handles failures binding the listening socket.
+    #[error("failed to bind AMQP listener to '{address}': {source}")]
+    Bind {
+        /// The address that could not be bound.
+        address: SocketAddr,
+        /// The initial error cause.
+        source: io::Error,
+    },
+    /// Handles failures accepting new connections.
+    #[error("failed to accept AMQP connection: {0}")]
+    Accept(#[source] io::Error),
+    /// Handles OS level errors on the underlying connection.
+    #[error("connection error: {0}")]
+    Io(#[from] io::Error),
+    /// Handles frames whose contents could not be decoded.
+    #[error("malformed frame: {0}")]
+    Codec(#[from] codec::Error),
+    /// Handles frames that violate the AMQP specification.
+    #[error("malformed frame: {reason}")]
+    Malformed {
+        /// Why the frame was rejected.
+        reason: &'static str,
+    },
+    /// Handles clients speaking a protocol other than AMQP 0-9-1.
+    #[error("unsupported protocol header {header:?}")]
+    UnsupportedProtocol {
+        /// The protocol header the client sent.
+        header: [u8; 8],
+    },
+    /// Handles frames that exceed the negotiated maximum frame size.
+    #[error("frame of {size} bytes exceeds the maximum frame size of {max} bytes")]
+    FrameTooLarge {
+        /// The size of the rejected frame.
+        size: usize,
+        /// The negotiated maximum frame size.
+        max: u32,
+    },
+    /// Handles methods the listener does not implement.
+    #[error("unsupported method {class_id}.{method_id}")]
+    UnsupportedMethod {
+        /// The class of the method.
+        class_id: u16,
+        /// The id of the method within its class.
+        method_id: u16,
+    },
+    /// Handles methods refused with an AMQP reply code, closing either the
+    /// channel or the connection they were sent on.
+    #[error("refused with {code}: {text}")]
+    Refused {
+        /// The AMQP reply code.
+        code: u16,
+        /// Why the method was refused.
+        text: String,
+    },
+    /// Handles clients that closed the connection with an error, or that were
+    /// disconnected with one.
+    #[error("connection closed with {code}: {text}")]
+    Closed {
+        /// The AMQP reply code.
+        code: u16,
+        /// Why the connection was closed.
+        text: String,
+    },
+    /// Handles clients that stayed silent for longer than two heartbeats.
+    #[error("no frames received for two heartbeat intervals")]
+    HeartbeatTimeout,
+    /// Handles failures serving the rift requests AMQP methods translate to.
+    #[error(transparent)]
+    Broker(#[from] ResponseError),
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let err = Error::FrameTooLarge { size: 10, max: 4 };
+        assert_eq!(
+            "frame of 10 bytes exceeds the maximum frame size of 4 bytes",
+            err.to_string()
+        );
+        let err = Error::UnsupportedMethod {
+            class_id: 90,
+            method_id: 10,
+        };
+        assert_eq!("unsupported method 90.10", err.to_string());
+    }
+}
@@ -0,0 +1,399 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::io::{Read, Write};
+
+use crate::codec::{Reader, Writer};
+
+use super::error::{Error, Result};
+use super::method::Method;
+use super::table::{get_short_string, get_table, put_short_string, put_table, Table};
+
+/// The header AMQP 0-9-1 clients open connections with.
+pub(super) const PROTOCOL_HEADER: [u8; 8] = *b"AMQP\x00\x00\x09\x01";
+
+/// The smallest maximum frame size a client may negotiate.
+pub(super) const MIN_FRAME_MAX: u32 = 4096;
+
+/// The bytes every frame adds to its payload: its type, channel, and size
+/// up front and the end marker after.
+const FRAME_OVERHEAD: usize = 8;
+const FRAME_END: u8 = 0xCE;
+
+const FRAME_METHOD: u8 = 1;
+const FRAME_HEADER: u8 = 2;
+const FRAME_BODY: u8 = 3;
+const FRAME_HEARTBEAT: u8 = 8;
+
+/// A single AMQP frame, along with the channel it was sent on.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Frame {
+    Method(u16, Method),
+    Header(u16, ContentHeader),
+    Body(u16, Vec<u8>),
+    Heartbeat,
+}
+
+/// The header announcing the size and properties of the content that follows
+/// a content carrying method.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct ContentHeader {
+    pub class_id: u16,
+    pub body_size: u64,
+    pub properties: Properties,
+}
+
+/// The properties of a message sent with the basic class.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(super) struct Properties {
+    pub content_type: Option<String>,
+    pub content_encoding: Option<String>,
+    pub headers: Option<Table>,
+    pub delivery_mode: Option<u8>,
+    pub priority: Option<u8>,
+    pub correlation_id: Option<String>,
+    pub reply_to: Option<String>,
+    pub expiration: Option<String>,
+    pub message_id: Option<String>,
+    /// Seconds since the epoch.
+    pub timestamp: Option<u64>,
+    pub kind: Option<String>,
+    pub user_id: Option<String>,
+    pub app_id: Option<String>,
+}
+
+// The property flags, from the most significant bit down in the order the
+// properties are encoded.
+const CONTENT_TYPE: u16 = 1 << 15;
+const CONTENT_ENCODING: u16 = 1 << 14;
+const HEADERS: u16 = 1 << 13;
+const DELIVERY_MODE: u16 = 1 << 12;
+const PRIORITY: u16 = 1 << 11;
+const CORRELATION_ID: u16 = 1 << 10;
+const REPLY_TO: u16 = 1 << 9;
+const EXPIRATION: u16 = 1 << 8;
+const MESSAGE_ID: u16 = 1 << 7;
+const TIMESTAMP: u16 = 1 << 6;
+const TYPE: u16 = 1 << 5;
+const USER_ID: u16 = 1 << 4;
+const APP_ID: u16 = 1 << 3;
+const CLUSTER_ID: u16 = 1 << 2;
+
+impl Properties {
+    fn decode(reader: &mut Reader) -> Result<Properties> {
+        let flags = reader.get_u16()?;
+        if flags & 1 != 0 {
+            return Err(Error::Malformed {
+                reason: "basic properties do not continue past the first flags",
+            });
+        }
+        let string = |flag: u16, reader: &mut Reader| -> Result<Option<String>> {
+            match flags & flag {
+                0 => Ok(None),
+                _ => Ok(Some(get_short_string(reader)?)),
+            }
+        };
+        let content_type = string(CONTENT_TYPE, reader)?;
+        let content_encoding = string(CONTENT_ENCODING, reader)?;
+        let headers = match flags & HEADERS {
+            0 => None,
+            _ => Some(get_table(reader)?),
+        };
+        let delivery_mode = match flags & DELIVERY_MODE {
+            0 => None,
+            _ => Some(reader.get_u8()?),
+        };
+        let priority = match flags & PRIORITY {
+            0 => None,
+            _ => Some(reader.get_u8()?),
+        };
+        let correlation_id = string(CORRELATION_ID, reader)?;
+        let reply_to = string(REPLY_TO, reader)?;
+        let expiration = string(EXPIRATION, reader)?;
+        let message_id = string(MESSAGE_ID, reader)?;
+        let timestamp = match flags & TIMESTAMP {
+            0 => None,
+            _ => Some(reader.get_u64()?),
+        };
+        let kind = string(TYPE, reader)?;
+        let user_id = string(USER_ID, reader)?;
+        let app_id = string(APP_ID, reader)?;
+        // The cluster id is reserved and dropped.
+        string(CLUSTER_ID, reader)?;
+        Ok(Properties {
+            content_type,
+            content_encoding,
+            headers,
+            delivery_mode,
+            priority,
+            correlation_id,
+            reply_to,
+            expiration,
+            message_id,
+            timestamp,
+            kind,
+            user_id,
+            app_id,
+        })
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let flag = |present: bool, flag: u16| if present { flag } else { 0 };
+        let flags = flag(self.content_type.is_some(), CONTENT_TYPE)
+            | flag(self.content_encoding.is_some(), CONTENT_ENCODING)
+            | flag(self.headers.is_some(), HEADERS)
+            | flag(self.delivery_mode.is_some(), DELIVERY_MODE)
+            | flag(self.priority.is_some(), PRIORITY)
+            | flag(self.correlation_id.is_some(), CORRELATION_ID)
+            | flag(self.reply_to.is_some(), REPLY_TO)
+            | flag(self.expiration.is_some(), EXPIRATION)
+            | flag(self.message_id.is_some(), MESSAGE_ID)
+            | flag(self.timestamp.is_some(), TIMESTAMP)
+            | flag(self.kind.is_some(), TYPE)
+            | flag(self.user_id.is_some(), USER_ID)
+            | flag(self.app_id.is_some(), APP_ID);
+        buf.put_u16(flags);
+
+        let put_string = |buf: &mut Vec<u8>, value: &Option<String>| {
+            if let Some(value) = value {
+                put_short_string(buf, value);
+            }
+        };
+        put_string(buf, &self.content_type);
+        put_string(buf, &self.content_encoding);
+        if let Some(headers) = &self.headers {
+            put_table(buf, headers);
+        }
+        if let Some(delivery_mode) = self.delivery_mode {
+            buf.put_u8(delivery_mode);
+        }
+        if let Some(priority) = self.priority {
+            buf.put_u8(priority);
+        }
+        put_string(buf, &self.correlation_id);
+        put_string(buf, &self.reply_to);
+        put_string(buf, &self.expiration);
+        put_string(buf, &self.message_id);
+        if let Some(timestamp) = self.timestamp {
+            buf.put_u64(timestamp);
+        }
+        put_string(buf, &self.kind);
+        put_string(buf, &self.user_id);
+        put_string(buf, &self.app_id);
+    }
+}
+
+impl ContentHeader {
+    fn decode(payload: &[u8]) -> Result<ContentHeader> {
+        let mut reader = Reader::new(payload);
+        let class_id = reader.get_u16()?;
+        let _weight = reader.get_u16()?;
+        Ok(ContentHeader {
+            class_id,
+            body_size: reader.get_u64()?,
+            properties: Properties::decode(&mut reader)?,
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.put_u16(self.class_id);
+        buf.put_u16(0);
+        buf.put_u64(self.body_size);
+        self.properties.encode(&mut buf);
+        buf
+    }
+}
+
+/// Read a single frame, or [None] if the connection was closed before one
+/// started. Frames larger than the supplied maximum are rejected, unless it
+/// is zero.
+pub(super) fn read_frame(reader: &mut impl Read, frame_max: u32) -> Result<Option<Frame>> {
+    let mut kind = [0; 1];
+    if reader.read(&mut kind)? == 0 {
+        return Ok(None);
+    }
+    let mut header = [0; 6];
+    reader.read_exact(&mut header)?;
+    let channel = u16::from_be_bytes([header[0], header[1]]);
+    let len = u32::from_be_bytes([header[2], header[3], header[4], header[5]]) as usize;
+    let size = len + FRAME_OVERHEAD;
+    if frame_max != 0 && size > frame_max as usize {
+        return Err(Error::FrameTooLarge {
+            size,
+            max: frame_max,
+        });
+    }
+
+    let mut payload = vec![0; len + 1];
+    reader.read_exact(&mut payload)?;
+    if payload.pop() != Some(FRAME_END) {
+        return Err(Error::Malformed {
+            reason: "frames must end with 0xCE",
+        });
+    }
+    let frame = match kind[0] {
+        FRAME_METHOD => Frame::Method(channel, Method::decode(&payload)?),
+        FRAME_HEADER => Frame::Header(channel, ContentHeader::decode(&payload)?),
+        FRAME_BODY => Frame::Body(channel, payload),
+        FRAME_HEARTBEAT if channel == 0 => Frame::Heartbeat,
+        FRAME_HEARTBEAT => {
+            return Err(Error::Malformed {
+                reason: "heartbeats must be sent on channel zero",
+            })
+        }
+        _ => {
+            return Err(Error::Malformed {
+                reason: "unknown frame type",
+            })
+        }
+    };
+    Ok(Some(frame))
+}
+
+/// Write a single frame without flushing the writer.
+pub(super) fn write_frame(writer: &mut impl Write, frame: &Frame) -> Result<()> {
+    let (kind, channel, payload) = match frame {
+        Frame::Method(channel, method) => (FRAME_METHOD, *channel, method.encode()),
+        Frame::Header(channel, header) => (FRAME_HEADER, *channel, header.encode()),
+        Frame::Body(channel, body) => (FRAME_BODY, *channel, body.clone()),
+        Frame::Heartbeat => (FRAME_HEARTBEAT, 0, Vec::new()),
+    };
+    let mut buf = Vec::with_capacity(payload.len() + FRAME_OVERHEAD);
+    buf.put_u8(kind);
+    buf.put_u16(channel);
+    buf.put_u32(payload.len() as u32);
+    buf.put_raw(&payload);
+    buf.put_u8(FRAME_END);
+    writer.write_all(&buf)?;
+    Ok(())
+}
+
+/// Returns the frames carrying a content carrying method along with its
+/// content, splitting the body so that no frame exceeds the supplied maximum.
+pub(super) fn content_frames(
+    channel: u16,
+    method: Method,
+    properties: Properties,
+    body: &[u8],
+    frame_max: u32,
+) -> Vec<Frame> {
+    let (class_id, _) = method.id();
+    let mut frames = vec![
+        Frame::Method(channel, method),
+        Frame::Header(
+            channel,
+            ContentHeader {
+                class_id,
+                body_size: body.len() as u64,
+                properties,
+            },
+        ),
+    ];
+    let chunk = match frame_max {
+        0 => body.len().max(1),
+        frame_max => frame_max as usize - FRAME_OVERHEAD,
+    };
+    frames.extend(
+        body.chunks(chunk)
+            .map(|chunk| Frame::Body(channel, chunk.to_vec())),
+    );
+    frames
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+    use crate::amqp::method::{Routing, BASIC};
+    use crate::amqp::table::FieldValue;
+
+    fn round_trip(frame: Frame) {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, &frame).unwrap();
+        let mut reader = &buf[..];
+        assert_eq!(Some(frame), read_frame(&mut reader, 0).unwrap());
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        round_trip(Frame::Method(0, Method::ConnectionOpenOk));
+        round_trip(Frame::Heartbeat);
+        round_trip(Frame::Body(3, b"payload".to_vec()));
+        round_trip(Frame::Header(
+            1,
+            ContentHeader {
+                class_id: BASIC,
+                body_size: 7,
+                properties: Properties::default(),
+            },
+        ));
+        round_trip(Frame::Header(
+            1,
+            ContentHeader {
+                class_id: BASIC,
+                body_size: 7,
+                properties: Properties {
+                    content_type: Some(String::from("text/plain")),
+                    content_encoding: Some(String::from("utf-8")),
+                    headers: Some(vec![(String::from("retries"), FieldValue::I32(2))]),
+                    delivery_mode: Some(2),
+                    priority: Some(5),
+                    correlation_id: Some(String::from("req-1")),
+                    reply_to: Some(String::from("replies")),
+                    expiration: Some(String::from("60000")),
+                    message_id: Some(String::from("msg-1")),
+                    timestamp: Some(1_600_000_000),
+                    kind: Some(String::from("order.created")),
+                    user_id: Some(String::from("guest")),
+                    app_id: Some(String::from("shop")),
+                },
+            },
+        ));
+    }
+
+    #[test]
+    fn test_read_errors() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, &Frame::Body(1, vec![0; 100])).unwrap();
+        assert!(matches!(
+            read_frame(&mut &buf[..], 64),
+            Err(Error::FrameTooLarge { size: 108, max: 64 })
+        ));
+
+        *buf.last_mut().unwrap() = 0;
+        assert!(matches!(
+            read_frame(&mut &buf[..], 0),
+            Err(Error::Malformed { .. })
+        ));
+
+        let mut empty: &[u8] = &[];
+        assert!(read_frame(&mut empty, 0).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_content_frames() {
+        let method = Method::BasicPublish {
+            routing: Routing {
+                exchange: String::new(),
+                routing_key: String::from("jobs"),
+            },
+            mandatory: false,
+            immediate: false,
+        };
+        let frames = content_frames(1, method.clone(), Properties::default(), &[7; 20], 16);
+        assert_eq!(5, frames.len());
+        assert_eq!(Frame::Method(1, method.clone()), frames[0]);
+        assert!(matches!(
+            &frames[1],
+            Frame::Header(1, ContentHeader { body_size: 20, .. })
+        ));
+        assert_eq!(Frame::Body(1, vec![7; 8]), frames[2]);
+        assert_eq!(Frame::Body(1, vec![7; 4]), frames[4]);
+
+        // Empty bodies are sent without any body frames.
+        let frames = content_frames(1, method, Properties::default(), &[], 16);
+        assert_eq!(2, frames.len());
+    }
+}
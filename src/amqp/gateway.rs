@@ -0,0 +1,491 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
+use crate::broker::{Broker, Session, TEMPORARY_QUEUE_PREFIX};
+use crate::exchange::{Binding, ExchangeType};
+use crate::protocol::{
+    AckRequest, BindRequest, CreateTopicRequest, DeclareExchangeRequest, DeleteExchangeRequest,
+    DeleteTopicRequest, ErrorCode, LeaseRequest, LeasedMessage, NackRequest, ProduceRequest,
+    PublishRequest, Request, Response, ResponseError,
+};
+use crate::record::{Compression, Header, Record};
+use crate::topic::Partitioning;
+
+use super::config::Config;
+use super::error::{Error, Result};
+use super::method::reply;
+use super::table::Table;
+
+/// The exchanges every AMQP broker declares, which clients may use but not
+/// declare with another type or delete.
+const STANDARD_EXCHANGES: [(&str, ExchangeType); 4] = [
+    ("amq.direct", ExchangeType::Direct),
+    ("amq.fanout", ExchangeType::Fanout),
+    ("amq.topic", ExchangeType::Topic),
+    ("amq.headers", ExchangeType::Headers),
+];
+/// The prefix of exchange and queue names reserved by AMQP.
+const RESERVED_PREFIX: &str = "amq.";
+
+/// How a consumer settles messages leased to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Settle {
+    /// The messages were processed, and are removed from the queue.
+    Ack,
+    /// The messages are released for redelivery.
+    Requeue,
+    /// The messages are given up on, and moved to the dead-letter topic of
+    /// their queue's topic or dropped if it has none.
+    Reject,
+}
+
+/// How an AMQP queue was declared, tracked while the server runs.
+#[derive(Debug, Clone, Default)]
+struct Declared {
+    /// The connection an exclusive queue belongs to.
+    owner: Option<u64>,
+    /// Whether the queue is deleted once its last consumer is cancelled.
+    auto_delete: bool,
+    consumers: u32,
+}
+
+/// The state shared by every AMQP connection, translating AMQP methods into
+/// the rift requests served by the broker.
+///
+/// Every AMQP queue is a rift topic of the same name, consumed as a rift
+/// queue of that name too, so that messages produced natively are delivered
+/// to AMQP consumers and the reverse. Exchanges are rift exchanges, apart
+/// from the default exchange which produces straight to the topic named by
+/// the routing key.
+pub(super) struct Gateway {
+    pub logger: slog::Logger,
+    pub frame_max: u32,
+    pub channel_max: u16,
+    pub heartbeat: u16,
+    broker: Arc<Broker>,
+    partitions: u32,
+    visibility_timeout_ms: u32,
+    queues: Mutex<BTreeMap<String, Declared>>,
+    connection_ids: AtomicU64,
+}
+
+impl Gateway {
+    /// Open the gateway, declaring the standard exchanges if needed.
+    pub(super) fn open(logger: slog::Logger, cfg: &Config, broker: Arc<Broker>) -> Result<Gateway> {
+        for (name, kind) in STANDARD_EXCHANGES {
+            broker.handle(Request::DeclareExchange(DeclareExchangeRequest {
+                name: name.to_owned(),
+                kind,
+            }))?;
+        }
+        let max_visibility_timeout_ms = broker.queues().config().max_visibility_timeout_ms;
+        Ok(Gateway {
+            logger,
+            frame_max: cfg.amqp_frame_max,
+            channel_max: cfg.amqp_channel_max.max(1),
+            heartbeat: cfg.amqp_heartbeat_secs,
+            visibility_timeout_ms: max_visibility_timeout_ms.min(u32::MAX as u64) as u32,
+            broker,
+            partitions: cfg.amqp_partitions.max(1),
+            queues: Mutex::new(BTreeMap::new()),
+            connection_ids: AtomicU64::new(0),
+        })
+    }
+
+    /// Returns a unique id for a new connection, along with the broker
+    /// session holding the server named queues it declares.
+    pub(super) fn connect(&self) -> (u64, Session) {
+        let id = self.connection_ids.fetch_add(1, Ordering::Relaxed);
+        (id, Session::new(self.broker.clone()))
+    }
+
+    /// Declare an exchange, or only check that it exists if passive.
+    pub(super) fn declare_exchange(&self, name: &str, kind: &str, passive: bool) -> Result<()> {
+        if passive {
+            if name.is_empty() {
+                return Ok(());
+            }
+            self.broker
+                .exchanges()
+                .exchange(name)
+                .map_err(ResponseError::from)?;
+            return Ok(());
+        }
+        let kind = ExchangeType::from_name(kind).ok_or_else(|| Error::Refused {
+            code: reply::COMMAND_INVALID,
+            text: format!("unknown exchange type '{}'", kind),
+        })?;
+        let standard = STANDARD_EXCHANGES
+            .iter()
+            .any(|(standard, _)| *standard == name);
+        if name.is_empty() || (name.starts_with(RESERVED_PREFIX) && !standard) {
+            return Err(reserved("exchange", name));
+        }
+        self.broker
+            .handle(Request::DeclareExchange(DeclareExchangeRequest {
+                name: name.to_owned(),
+                kind,
+            }))?;
+        Ok(())
+    }
+
+    /// Delete an exchange along with its bindings. Exchanges that do not
+    /// exist are ignored, as they are by RabbitMQ.
+    pub(super) fn delete_exchange(&self, name: &str, if_unused: bool) -> Result<()> {
+        if name.is_empty() || name.starts_with(RESERVED_PREFIX) {
+            return Err(reserved("exchange", name));
+        }
+        match self.broker.exchanges().bindings(name) {
+            Ok(bindings) if if_unused && !bindings.is_empty() => {
+                return Err(Error::Refused {
+                    code: reply::PRECONDITION_FAILED,
+                    text: format!("exchange '{}' in use", name),
+                })
+            }
+            Ok(_) => {}
+            Err(_) => return Ok(()),
+        }
+        let request = Request::DeleteExchange(DeleteExchangeRequest {
+            name: name.to_owned(),
+        });
+        match self.broker.handle(request) {
+            Err(err) if err.code != ErrorCode::ExchangeNotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Declare a queue for the supplied connection, or only check that it
+    /// exists if passive, returning its name and how many consumers it has.
+    ///
+    /// Queues declared without a name are named by the server, and deleted
+    /// along with the session they were created through. Exclusive queues
+    /// may only be used by the connection that declared them, and are deleted
+    /// once it closes.
+    pub(super) fn declare_queue(
+        &self,
+        connection: u64,
+        session: &mut Session,
+        name: &str,
+        passive: bool,
+        exclusive: bool,
+        auto_delete: bool,
+    ) -> Result<(String, u32)> {
+        if passive {
+            self.check_owner(connection, name)?;
+            if self.broker.topics().get(name).is_err() {
+                return Err(Error::Refused {
+                    code: reply::NOT_FOUND,
+                    text: format!("no queue '{}'", name),
+                });
+            }
+            let consumers = self.declared().get(name).map_or(0, |queue| queue.consumers);
+            return Ok((name.to_owned(), consumers));
+        }
+
+        let name = if name.is_empty() {
+            match session.handle(Request::CreateTemporaryQueue)? {
+                Response::CreateTemporaryQueue(resp) => resp.topic,
+                _ => return Err(unexpected("create temporary queue")),
+            }
+        } else {
+            if name.starts_with(RESERVED_PREFIX) {
+                return Err(reserved("queue", name));
+            }
+            self.check_owner(connection, name)?;
+            // Server named queues may be redeclared by name, but only the broker
+            // creates topics with their prefix.
+            let redeclared =
+                name.starts_with(TEMPORARY_QUEUE_PREFIX) && self.broker.topics().get(name).is_ok();
+            if !redeclared {
+                let create = Request::CreateTopic(CreateTopicRequest {
+                    name: name.to_owned(),
+                    partitions: self.partitions,
+                    config: Vec::new(),
+                });
+                match self.broker.handle(create) {
+                    Ok(_) => {
+                        info!(self.logger, "Created topic for AMQP queue."; "topic" => name);
+                    }
+                    Err(err) if err.code == ErrorCode::TopicAlreadyExists => {}
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            name.to_owned()
+        };
+
+        let mut queues = self.declared();
+        let queue = queues.entry(name.clone()).or_insert_with(|| Declared {
+            owner: exclusive.then_some(connection),
+            auto_delete,
+            consumers: 0,
+        });
+        Ok((name, queue.consumers))
+    }
+
+    /// Returns the number of messages of a queue waiting to be delivered,
+    /// which excludes those leased to consumers but not yet acknowledged.
+    pub(super) fn message_count(&self, queue: &str) -> Result<u32> {
+        match self.broker.queue_backlog(queue, queue) {
+            Ok(backlog) => Ok(backlog.min(u32::MAX as u64) as u32),
+            Err(err) if err.code == ErrorCode::TopicNotFound => Ok(0),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Delete a queue along with its messages, returning how many messages
+    /// were waiting to be delivered. Queues that do not exist are ignored, as
+    /// they are by RabbitMQ.
+    pub(super) fn delete_queue(&self, connection: u64, name: &str, if_unused: bool) -> Result<u32> {
+        self.check_owner(connection, name)?;
+        let consumers = self.declared().get(name).map_or(0, |queue| queue.consumers);
+        if if_unused && consumers > 0 {
+            return Err(Error::Refused {
+                code: reply::PRECONDITION_FAILED,
+                text: format!("queue '{}' in use", name),
+            });
+        }
+        let message_count = self.message_count(name)?;
+        self.delete_topic(name)?;
+        Ok(message_count)
+    }
+
+    /// Bind a queue to an exchange, or remove the binding if `bind` is false.
+    pub(super) fn bind(
+        &self,
+        connection: u64,
+        queue: &str,
+        exchange: &str,
+        routing_key: &str,
+        arguments: &Table,
+        bind: bool,
+    ) -> Result<()> {
+        if exchange.is_empty() {
+            return Err(reserved("exchange", exchange));
+        }
+        self.check_owner(connection, queue)?;
+        let binding = Binding {
+            arguments: arguments
+                .iter()
+                .filter_map(|(key, value)| {
+                    Some(Header {
+                        key: key.clone(),
+                        value: value.to_header()?,
+                    })
+                })
+                .collect(),
+            ..Binding::new(exchange, queue, routing_key)
+        };
+        let request = match bind {
+            true => Request::Bind(BindRequest { binding }),
+            false => Request::Unbind(BindRequest { binding }),
+        };
+        self.broker.handle(request)?;
+        Ok(())
+    }
+
+    /// Publish a message to an exchange, returning whether it was routed to
+    /// at least one queue.
+    pub(super) fn publish(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        record: Record,
+    ) -> Result<bool> {
+        if exchange.is_empty() {
+            let request = Request::Produce(ProduceRequest {
+                topic: routing_key.to_owned(),
+                partitioning: Partitioning::Key,
+                records: vec![record],
+                producer: None,
+                compression: Compression::None,
+            });
+            return match self.broker.handle(request) {
+                Ok(_) => Ok(true),
+                Err(err)
+                    if matches!(err.code, ErrorCode::TopicNotFound | ErrorCode::InvalidTopic) =>
+                {
+                    Ok(false)
+                }
+                Err(err) => Err(err.into()),
+            };
+        }
+        let request = Request::Publish(PublishRequest {
+            exchange: exchange.to_owned(),
+            routing_key: routing_key.to_owned(),
+            record,
+        });
+        match self.broker.handle(request)? {
+            Response::Publish(resp) => Ok(!resp.records.is_empty()),
+            _ => Err(unexpected("publish")),
+        }
+    }
+
+    /// Lease up to the supplied number of messages from a queue, which are
+    /// held by the consumer until they are acknowledged, rejected, or the
+    /// longest visibility timeout allowed passes.
+    pub(super) fn lease(
+        &self,
+        connection: u64,
+        queue: &str,
+        consumer_id: &str,
+        max_messages: u32,
+    ) -> Result<Vec<LeasedMessage>> {
+        self.check_owner(connection, queue)?;
+        let request = Request::Lease(LeaseRequest {
+            queue: queue.to_owned(),
+            consumer_id: consumer_id.to_owned(),
+            topic: queue.to_owned(),
+            partitions: Vec::new(),
+            max_messages,
+            visibility_timeout_ms: self.visibility_timeout_ms,
+        });
+        match self.broker.handle(request)? {
+            Response::Lease(resp) => Ok(resp.messages),
+            _ => Err(unexpected("lease")),
+        }
+    }
+
+    /// Settle leased messages as the consumer decided. Messages whose lease
+    /// already ended are skipped.
+    pub(super) fn settle(
+        &self,
+        queue: &str,
+        consumer_id: &str,
+        partition: u32,
+        offsets: Vec<u64>,
+        settle: Settle,
+    ) -> Result<()> {
+        let nack = |error: &str| NackRequest {
+            queue: queue.to_owned(),
+            consumer_id: consumer_id.to_owned(),
+            topic: queue.to_owned(),
+            partition,
+            offsets: offsets.clone(),
+            error: error.to_owned(),
+        };
+        let result = match settle {
+            Settle::Ack => self.broker.handle(Request::Ack(AckRequest {
+                queue: queue.to_owned(),
+                consumer_id: consumer_id.to_owned(),
+                topic: queue.to_owned(),
+                partition,
+                offsets: offsets.clone(),
+            })),
+            Settle::Requeue => self
+                .broker
+                .handle(Request::Nack(nack("requeued by AMQP consumer"))),
+            Settle::Reject => self
+                .broker
+                .reject(nack("rejected by AMQP consumer"))
+                .map(|_| Response::Nack),
+        };
+        match result {
+            Err(err) if !matches!(err.code, ErrorCode::NotLeased | ErrorCode::TopicNotFound) => {
+                Err(err.into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Count a new consumer of a queue.
+    pub(super) fn add_consumer(&self, connection: u64, queue: &str) -> Result<()> {
+        self.check_owner(connection, queue)?;
+        if self.broker.topics().get(queue).is_err() {
+            return Err(Error::Refused {
+                code: reply::NOT_FOUND,
+                text: format!("no queue '{}'", queue),
+            });
+        }
+        self.declared()
+            .entry(queue.to_owned())
+            .or_default()
+            .consumers += 1;
+        Ok(())
+    }
+
+    /// Stop counting a consumer of a queue, deleting the queue if it was
+    /// declared auto-delete and this was its last consumer.
+    pub(super) fn remove_consumer(&self, queue: &str) {
+        let delete = match self.declared().get_mut(queue) {
+            Some(declared) => {
+                declared.consumers = declared.consumers.saturating_sub(1);
+                declared.auto_delete && declared.consumers == 0
+            }
+            None => false,
+        };
+        if delete {
+            if let Err(err) = self.delete_topic(queue) {
+                warn!(self.logger, "Failed to delete auto-delete AMQP queue."; "queue" => queue, "error" => err.to_string());
+            }
+        }
+    }
+
+    /// Delete the exclusive queues of a connection once it closes.
+    pub(super) fn disconnect(&self, connection: u64) {
+        let exclusive: Vec<String> = self
+            .declared()
+            .iter()
+            .filter(|(_, declared)| declared.owner == Some(connection))
+            .map(|(name, _)| name.clone())
+            .collect();
+        for queue in exclusive {
+            if let Err(err) = self.delete_topic(&queue) {
+                warn!(self.logger, "Failed to delete exclusive AMQP queue."; "queue" => &queue, "error" => err.to_string());
+            }
+        }
+    }
+
+    fn delete_topic(&self, name: &str) -> Result<()> {
+        self.declared().remove(name);
+        let request = Request::DeleteTopic(DeleteTopicRequest {
+            name: name.to_owned(),
+        });
+        match self.broker.handle(request) {
+            Err(err) if err.code != ErrorCode::TopicNotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Ensure a queue is not exclusive to another connection.
+    fn check_owner(&self, connection: u64, queue: &str) -> Result<()> {
+        match self
+            .declared()
+            .get(queue)
+            .and_then(|declared| declared.owner)
+        {
+            Some(owner) if owner != connection => Err(Error::Refused {
+                code: reply::RESOURCE_LOCKED,
+                text: format!("queue '{}' is exclusive to another connection", queue),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn declared(&self) -> MutexGuard<'_, BTreeMap<String, Declared>> {
+        self.queues
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn reserved(kind: &str, name: &str) -> Error {
+    Error::Refused {
+        code: reply::ACCESS_REFUSED,
+        text: format!("{} name '{}' is reserved", kind, name),
+    }
+}
+
+fn unexpected(request: &str) -> Error {
+    Error::Broker(ResponseError::new(
+        ErrorCode::Unknown,
+        format!("unexpected response to a {} request", request),
+    ))
+}
@@ -0,0 +1,748 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    net::{SocketAddr, TcpListener},
+    sync::Arc,
+    thread,
+};
+
+use crate::broker::Broker;
+use crate::server::{self, OpenConnection};
+
+use super::config::Config;
+use super::connection;
+use super::error::{Error, Result};
+use super::gateway::Gateway;
+
+/// The port AMQP is served on when no listen address is configured.
+const DEFAULT_PORT: u16 = 5672;
+
+/// Accepts AMQP 0-9-1 client connections, serving each connection on its own
+/// thread.
+///
+/// AMQP queues are rift topics consumed as rift queues of the same name, and
+/// AMQP exchanges are rift exchanges, so that AMQP clients and native clients
+/// share messages. The standard `amq.*` exchanges are declared when the
+/// listener is bound.
+pub struct Server {
+    logger: slog::Logger,
+    listener: TcpListener,
+    gateway: Arc<Gateway>,
+}
+
+impl Server {
+    /// Bind a new listener using the supplied configuration.
+    pub fn bind(logger: slog::Logger, cfg: &Config, broker: Arc<Broker>) -> Result<Server> {
+        let address = cfg
+            .amqp_listen_address
+            .unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], DEFAULT_PORT)));
+        let listener =
+            TcpListener::bind(address).map_err(|source| Error::Bind { address, source })?;
+        let gateway = Gateway::open(logger.clone(), cfg, broker)?;
+        Ok(Server {
+            logger,
+            listener,
+            gateway: Arc::new(gateway),
+        })
+    }
+
+    /// Returns the address this listener is bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.listener.local_addr().map_err(Error::Accept)
+    }
+
+    /// Accept and serve connections until the process exits.
+    pub fn serve(self) -> ! {
+        loop {
+            let (stream, peer) = server::accept(&self.logger, &self.listener);
+            let logger = self.logger.new(o!("peer" => peer.to_string()));
+            let gateway = self.gateway.clone();
+
+            thread::spawn(move || {
+                let _open = OpenConnection::new("amqp");
+                debug!(logger, "Accepted AMQP connection.");
+                if let Err(err) = stream.set_nodelay(true) {
+                    warn!(logger, "Failed to disable nagle's algorithm."; "error" => err.to_string());
+                }
+                match connection::serve(logger.clone(), stream, gateway) {
+                    Ok(()) => debug!(logger, "AMQP connection closed."),
+                    Err(err) => {
+                        warn!(logger, "AMQP connection failed."; "error" => err.to_string())
+                    }
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use std::{
+        io::{BufReader, Read, Write},
+        net::TcpStream,
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::amqp::frame::{
+        content_frames, read_frame, write_frame, Frame, Properties, PROTOCOL_HEADER,
+    };
+    use crate::amqp::method::{reply, Close, Method, Routing};
+    use crate::amqp::table::{FieldValue, Table};
+    use crate::protocol::{CreateTopicRequest, Request};
+    use crate::storage::LogConfig;
+    use crate::{group, producer, queue, schedule, topic};
+
+    fn open_broker(dir: &std::path::Path) -> Arc<Broker> {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let topics = Arc::new(topic::Manager::open(dir, LogConfig::default()).unwrap());
+        Arc::new(
+            Broker::open(
+                logger,
+                topics,
+                group::Config::default(),
+                producer::Config::default(),
+                queue::Config::default(),
+                schedule::Config::default(),
+            )
+            .unwrap(),
+        )
+    }
+
+    fn start(broker: Arc<Broker>) -> SocketAddr {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let cfg = Config {
+            amqp_listen_address: Some("127.0.0.1:0".parse().unwrap()),
+            ..Config::default()
+        };
+        let server = Server::bind(logger, &cfg, broker).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+        addr
+    }
+
+    struct TestClient {
+        stream: BufReader<TcpStream>,
+    }
+
+    impl TestClient {
+        /// Connect and complete the handshake, returning the client and the
+        /// server properties it was sent.
+        fn connect(addr: SocketAddr) -> (TestClient, Table) {
+            let stream = TcpStream::connect(addr).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(10)))
+                .unwrap();
+            let mut client = TestClient {
+                stream: BufReader::new(stream),
+            };
+            client.stream.get_mut().write_all(&PROTOCOL_HEADER).unwrap();
+            let server_properties = match client.recv_method(0) {
+                Method::ConnectionStart {
+                    server_properties, ..
+                } => server_properties,
+                method => panic!("expected connection.start, got {:?}", method),
+            };
+            client.send(
+                0,
+                Method::ConnectionStartOk {
+                    client_properties: Table::new(),
+                    mechanism: String::from("PLAIN"),
+                    response: b"\0guest\0guest".to_vec(),
+                    locale: String::from("en_US"),
+                },
+            );
+            let (channel_max, frame_max) = match client.recv_method(0) {
+                Method::ConnectionTune {
+                    channel_max,
+                    frame_max,
+                    ..
+                } => (channel_max, frame_max),
+                method => panic!("expected connection.tune, got {:?}", method),
+            };
+            client.send(
+                0,
+                Method::ConnectionTuneOk {
+                    channel_max,
+                    frame_max,
+                    heartbeat: 0,
+                },
+            );
+            client.send(
+                0,
+                Method::ConnectionOpen {
+                    virtual_host: String::from("/"),
+                },
+            );
+            assert_eq!(Method::ConnectionOpenOk, client.recv_method(0));
+            client.open_channel(1);
+            (client, server_properties)
+        }
+
+        fn send(&mut self, channel: u16, method: Method) {
+            write_frame(self.stream.get_mut(), &Frame::Method(channel, method)).unwrap();
+        }
+
+        fn recv(&mut self) -> Frame {
+            read_frame(&mut self.stream, 0).unwrap().unwrap()
+        }
+
+        fn recv_method(&mut self, channel: u16) -> Method {
+            match self.recv() {
+                Frame::Method(received, method) if received == channel => method,
+                frame => panic!("expected a method on channel {}, got {:?}", channel, frame),
+            }
+        }
+
+        /// Send a method and expect its reply.
+        fn call(&mut self, channel: u16, method: Method) -> Method {
+            self.send(channel, method);
+            self.recv_method(channel)
+        }
+
+        /// Receive a content carrying method along with its content.
+        fn recv_content(&mut self, channel: u16) -> (Method, Properties, Vec<u8>) {
+            let method = self.recv_method(channel);
+            let header = match self.recv() {
+                Frame::Header(_, header) => header,
+                frame => panic!("expected a content header, got {:?}", frame),
+            };
+            let mut body = Vec::new();
+            while (body.len() as u64) < header.body_size {
+                match self.recv() {
+                    Frame::Body(_, chunk) => body.extend(chunk),
+                    frame => panic!("expected a content body, got {:?}", frame),
+                }
+            }
+            (method, header.properties, body)
+        }
+
+        /// Asserts the server sends nothing for a little while.
+        fn assert_silent(&mut self) {
+            self.stream
+                .get_ref()
+                .set_read_timeout(Some(Duration::from_millis(300)))
+                .unwrap();
+            assert!(read_frame(&mut self.stream, 0).is_err());
+            self.stream
+                .get_ref()
+                .set_read_timeout(Some(Duration::from_secs(10)))
+                .unwrap();
+        }
+
+        fn open_channel(&mut self, channel: u16) {
+            assert_eq!(
+                Method::ChannelOpenOk,
+                self.call(channel, Method::ChannelOpen)
+            );
+        }
+
+        fn declare_queue(&mut self, channel: u16, queue: &str, exclusive: bool) -> Method {
+            self.call(
+                channel,
+                Method::QueueDeclare {
+                    queue: queue.to_owned(),
+                    passive: false,
+                    durable: true,
+                    exclusive,
+                    auto_delete: false,
+                    nowait: false,
+                    arguments: Table::new(),
+                },
+            )
+        }
+
+        fn consume(&mut self, channel: u16, queue: &str, consumer_tag: &str) {
+            let consume_ok = self.call(
+                channel,
+                Method::BasicConsume {
+                    queue: queue.to_owned(),
+                    consumer_tag: consumer_tag.to_owned(),
+                    no_local: false,
+                    no_ack: false,
+                    exclusive: false,
+                    nowait: false,
+                    arguments: Table::new(),
+                },
+            );
+            let expected = Method::BasicConsumeOk {
+                consumer_tag: consumer_tag.to_owned(),
+            };
+            assert_eq!(expected, consume_ok);
+        }
+
+        fn publish(
+            &mut self,
+            channel: u16,
+            exchange: &str,
+            routing_key: &str,
+            properties: Properties,
+            body: &[u8],
+        ) {
+            let method = Method::BasicPublish {
+                routing: Routing {
+                    exchange: exchange.to_owned(),
+                    routing_key: routing_key.to_owned(),
+                },
+                mandatory: true,
+                immediate: false,
+            };
+            for frame in content_frames(channel, method, properties, body, 4096) {
+                write_frame(self.stream.get_mut(), &frame).unwrap();
+            }
+        }
+
+        /// Expects a channel exception, confirms it, and returns its reply code.
+        fn expect_channel_close(&mut self, channel: u16) -> u16 {
+            match self.recv_method(channel) {
+                Method::ChannelClose(close) => {
+                    self.send(channel, Method::ChannelCloseOk);
+                    close.reply_code
+                }
+                method => panic!("expected channel.close, got {:?}", method),
+            }
+        }
+    }
+
+    fn delivery_tag(method: &Method) -> u64 {
+        match method {
+            Method::BasicDeliver { delivery_tag, .. } | Method::BasicGetOk { delivery_tag, .. } => {
+                *delivery_tag
+            }
+            method => panic!("expected a delivery, got {:?}", method),
+        }
+    }
+
+    #[test]
+    fn test_handshake() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = open_broker(dir.path());
+        let addr = start(broker.clone());
+
+        let (mut client, server_properties) = TestClient::connect(addr);
+        let capabilities = server_properties
+            .iter()
+            .find(|(key, _)| key == "capabilities")
+            .map(|(_, value)| value.clone());
+        match capabilities {
+            Some(FieldValue::Table(capabilities)) => assert!(capabilities
+                .contains(&(String::from("publisher_confirms"), FieldValue::Bool(true)))),
+            capabilities => panic!("expected capabilities, got {:?}", capabilities),
+        }
+        for exchange in ["amq.direct", "amq.fanout", "amq.topic", "amq.headers"] {
+            assert!(broker.exchanges().exchange(exchange).is_ok());
+        }
+
+        // Opening a channel twice is a connection exception.
+        client.send(1, Method::ChannelOpen);
+        match client.recv_method(0) {
+            Method::ConnectionClose(close) => assert_eq!(reply::CHANNEL_ERROR, close.reply_code),
+            method => panic!("expected connection.close, got {:?}", method),
+        }
+        client.send(0, Method::ConnectionCloseOk);
+
+        // Closing cleanly is confirmed.
+        let (mut client, _) = TestClient::connect(addr);
+        let close = Method::ConnectionClose(Close {
+            reply_code: reply::SUCCESS,
+            reply_text: String::from("bye"),
+            class_id: 0,
+            method_id: 0,
+        });
+        assert_eq!(Method::ConnectionCloseOk, client.call(0, close));
+
+        // Other protocols are told which protocol is spoken.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"AMQP\x00\x00\x0A\x00").unwrap();
+        let mut header = [0; 8];
+        stream.read_exact(&mut header).unwrap();
+        assert_eq!(PROTOCOL_HEADER, header);
+    }
+
+    #[test]
+    fn test_publish_consume() {
+        let dir = tempfile::tempdir().unwrap();
+        let addr = start(open_broker(dir.path()));
+        let (mut client, _) = TestClient::connect(addr);
+
+        let declare = Method::ExchangeDeclare {
+            exchange: String::from("logs"),
+            kind: String::from("topic"),
+            passive: false,
+            durable: true,
+            auto_delete: false,
+            internal: false,
+            nowait: false,
+            arguments: Table::new(),
+        };
+        assert_eq!(Method::ExchangeDeclareOk, client.call(1, declare));
+        let declare_ok = client.declare_queue(1, "errors", false);
+        let expected = Method::QueueDeclareOk {
+            queue: String::from("errors"),
+            message_count: 0,
+            consumer_count: 0,
+        };
+        assert_eq!(expected, declare_ok);
+        let bind = Method::QueueBind {
+            queue: String::from("errors"),
+            exchange: String::from("logs"),
+            routing_key: String::from("*.error"),
+            nowait: false,
+            arguments: Table::new(),
+        };
+        assert_eq!(Method::QueueBindOk, client.call(1, bind));
+        let confirm = Method::ConfirmSelect { nowait: false };
+        assert_eq!(Method::ConfirmSelectOk, client.call(1, confirm));
+
+        // Routed messages are confirmed, while unroutable mandatory messages
+        // are returned first.
+        let properties = Properties {
+            content_type: Some(String::from("text/plain")),
+            headers: Some(vec![(
+                String::from("tenant"),
+                FieldValue::LongString(b"acme".to_vec()),
+            )]),
+            priority: Some(3),
+            correlation_id: Some(String::from("req-1")),
+            ..Properties::default()
+        };
+        client.publish(1, "logs", "app.error", properties.clone(), b"disk full");
+        let ack = Method::BasicAck {
+            delivery_tag: 1,
+            multiple: false,
+        };
+        assert_eq!(ack, client.recv_method(1));
+        client.publish(1, "logs", "app.info", Properties::default(), b"started");
+        let (method, _, body) = client.recv_content(1);
+        assert!(matches!(
+            method,
+            Method::BasicReturn {
+                reply_code: reply::NO_ROUTE,
+                ..
+            }
+        ));
+        assert_eq!(b"started".to_vec(), body);
+        let ack = Method::BasicAck {
+            delivery_tag: 2,
+            multiple: false,
+        };
+        assert_eq!(ack, client.recv_method(1));
+        client.publish(1, "", "errors", Properties::default(), b"db down");
+        let ack = Method::BasicAck {
+            delivery_tag: 3,
+            multiple: false,
+        };
+        assert_eq!(ack, client.recv_method(1));
+
+        // Consumers are sent no more than their prefetch limit.
+        let qos = Method::BasicQos {
+            prefetch_size: 0,
+            prefetch_count: 1,
+            global: false,
+        };
+        assert_eq!(Method::BasicQosOk, client.call(1, qos));
+        client.consume(1, "errors", "worker");
+        let (method, delivered, body) = client.recv_content(1);
+        let expected = Method::BasicDeliver {
+            consumer_tag: String::from("worker"),
+            delivery_tag: 1,
+            redelivered: false,
+            routing: Routing {
+                exchange: String::from("logs"),
+                routing_key: String::from("app.error"),
+            },
+        };
+        assert_eq!(expected, method);
+        assert_eq!(properties, delivered);
+        assert_eq!(b"disk full".to_vec(), body);
+        client.assert_silent();
+
+        // Rejected messages are redelivered when requeued.
+        let reject = Method::BasicReject {
+            delivery_tag: 1,
+            requeue: true,
+        };
+        client.send(1, reject);
+        let (method, _, body) = client.recv_content(1);
+        assert!(matches!(
+            method,
+            Method::BasicDeliver {
+                delivery_tag: 2,
+                redelivered: true,
+                ..
+            }
+        ));
+        assert_eq!(b"disk full".to_vec(), body);
+        let ack = Method::BasicAck {
+            delivery_tag: 2,
+            multiple: false,
+        };
+        client.send(1, ack);
+        let (method, _, body) = client.recv_content(1);
+        let expected = Method::BasicDeliver {
+            consumer_tag: String::from("worker"),
+            delivery_tag: 3,
+            redelivered: false,
+            routing: Routing {
+                exchange: String::new(),
+                routing_key: String::from("errors"),
+            },
+        };
+        assert_eq!(expected, method);
+        assert_eq!(b"db down".to_vec(), body);
+        let ack = Method::BasicAck {
+            delivery_tag: 3,
+            multiple: false,
+        };
+        client.send(1, ack);
+        client.assert_silent();
+
+        let cancel = Method::BasicCancel {
+            consumer_tag: String::from("worker"),
+            nowait: false,
+        };
+        let expected = Method::BasicCancelOk {
+            consumer_tag: String::from("worker"),
+        };
+        assert_eq!(expected, client.call(1, cancel));
+    }
+
+    #[test]
+    fn test_errors_and_exclusive_queues() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = open_broker(dir.path());
+        let addr = start(broker.clone());
+        let (mut client, _) = TestClient::connect(addr);
+
+        // Channel exceptions close only the channel.
+        let passive = Method::QueueDeclare {
+            queue: String::from("missing"),
+            passive: true,
+            durable: false,
+            exclusive: false,
+            auto_delete: false,
+            nowait: false,
+            arguments: Table::new(),
+        };
+        client.send(1, passive);
+        assert_eq!(reply::NOT_FOUND, client.expect_channel_close(1));
+        client.open_channel(1);
+        let declare = Method::ExchangeDeclare {
+            exchange: String::from("amq.custom"),
+            kind: String::from("direct"),
+            passive: false,
+            durable: false,
+            auto_delete: false,
+            internal: false,
+            nowait: false,
+            arguments: Table::new(),
+        };
+        client.send(1, declare);
+        assert_eq!(reply::ACCESS_REFUSED, client.expect_channel_close(1));
+        client.open_channel(1);
+        let ack = Method::BasicAck {
+            delivery_tag: 7,
+            multiple: false,
+        };
+        client.send(1, ack);
+        assert_eq!(reply::PRECONDITION_FAILED, client.expect_channel_close(1));
+        client.open_channel(1);
+
+        // Exclusive queues are locked to their connection and deleted with it.
+        client.declare_queue(1, "private", true);
+        let (mut other, _) = TestClient::connect(addr);
+        other.send(
+            1,
+            Method::QueueDeclare {
+                queue: String::from("private"),
+                passive: true,
+                durable: false,
+                exclusive: false,
+                auto_delete: false,
+                nowait: false,
+                arguments: Table::new(),
+            },
+        );
+        assert_eq!(reply::RESOURCE_LOCKED, other.expect_channel_close(1));
+        other.open_channel(1);
+
+        // Server named queues can be fetched from one message at a time.
+        let queue = match client.declare_queue(1, "", false) {
+            Method::QueueDeclareOk { queue, .. } => queue,
+            method => panic!("expected queue.declare-ok, got {:?}", method),
+        };
+        assert!(queue.starts_with("tmp."));
+        match client.declare_queue(1, &queue, false) {
+            Method::QueueDeclareOk {
+                queue: redeclared, ..
+            } => assert_eq!(queue, redeclared),
+            method => panic!("expected queue.declare-ok, got {:?}", method),
+        }
+        let get = Method::BasicGet {
+            queue: queue.clone(),
+            no_ack: false,
+        };
+        assert_eq!(Method::BasicGetEmpty, client.call(1, get.clone()));
+        other.publish(1, "", &queue, Properties::default(), b"reply");
+        let (method, _, body) = loop {
+            client.send(1, get.clone());
+            match client.recv_method(1) {
+                Method::BasicGetEmpty => thread::sleep(Duration::from_millis(10)),
+                method => {
+                    let header = match client.recv() {
+                        Frame::Header(_, header) => header,
+                        frame => panic!("expected a content header, got {:?}", frame),
+                    };
+                    let body = match client.recv() {
+                        Frame::Body(_, body) => body,
+                        frame => panic!("expected a content body, got {:?}", frame),
+                    };
+                    break (method, header.properties, body);
+                }
+            }
+        };
+        assert_eq!(b"reply".to_vec(), body);
+        let ack = Method::BasicAck {
+            delivery_tag: delivery_tag(&method),
+            multiple: false,
+        };
+        client.send(1, ack);
+
+        let close = Method::ConnectionClose(Close {
+            reply_code: reply::SUCCESS,
+            reply_text: String::new(),
+            class_id: 0,
+            method_id: 0,
+        });
+        assert_eq!(Method::ConnectionCloseOk, client.call(0, close));
+        let deleted =
+            || broker.topics().get("private").is_err() && broker.topics().get(&queue).is_err();
+        for _ in 0..100 {
+            if deleted() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(deleted());
+
+        // Unsupported methods close the connection.
+        // A tx.select on channel 1.
+        let frame = [1, 0, 1, 0, 0, 0, 4, 0, 90, 0, 10, 0xCE];
+        other.stream.get_mut().write_all(&frame).unwrap();
+        match other.recv_method(0) {
+            Method::ConnectionClose(close) => assert_eq!(reply::NOT_IMPLEMENTED, close.reply_code),
+            method => panic!("expected connection.close, got {:?}", method),
+        }
+    }
+
+    #[test]
+    fn test_reject_dead_letters() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = open_broker(dir.path());
+        for (name, config) in [
+            ("jobs-dlq", Vec::new()),
+            (
+                "jobs",
+                vec![(
+                    topic::QUEUE_DEAD_LETTER_TOPIC.to_owned(),
+                    String::from("jobs-dlq"),
+                )],
+            ),
+        ] {
+            let create = CreateTopicRequest {
+                name: name.to_owned(),
+                partitions: 1,
+                config,
+            };
+            broker.handle(Request::CreateTopic(create)).unwrap();
+        }
+        let dead_letters =
+            || broker.topics().get("jobs-dlq").unwrap().partitions()[0].next_offset();
+        let addr = start(broker.clone());
+        let (mut client, _) = TestClient::connect(addr);
+        client.publish(1, "", "jobs", Properties::default(), b"job-1");
+        client.publish(1, "", "jobs", Properties::default(), b"job-2");
+        let message_count = |client: &mut TestClient| match client.declare_queue(1, "jobs", false) {
+            Method::QueueDeclareOk { message_count, .. } => message_count,
+            method => panic!("expected queue.declare-ok, got {:?}", method),
+        };
+        assert_eq!(2, message_count(&mut client));
+
+        // Messages rejected without requeueing move to the dead-letter topic.
+        let get = Method::BasicGet {
+            queue: String::from("jobs"),
+            no_ack: false,
+        };
+        client.send(1, get.clone());
+        let (method, _, body) = client.recv_content(1);
+        assert_eq!(b"job-1".to_vec(), body);
+        assert!(matches!(
+            method,
+            Method::BasicGetOk {
+                delivery_tag: 1,
+                message_count: 1,
+                ..
+            }
+        ));
+        let reject = Method::BasicReject {
+            delivery_tag: 1,
+            requeue: false,
+        };
+        client.send(1, reject);
+        assert_eq!(1, message_count(&mut client));
+        assert_eq!(1, dead_letters());
+
+        client.send(1, get);
+        let (_, _, body) = client.recv_content(1);
+        assert_eq!(b"job-2".to_vec(), body);
+        let nack = Method::BasicNack {
+            delivery_tag: 2,
+            multiple: false,
+            requeue: false,
+        };
+        client.send(1, nack);
+        assert_eq!(0, message_count(&mut client));
+        assert_eq!(2, dead_letters());
+    }
+
+    #[test]
+    fn test_requeue_on_close() {
+        let dir = tempfile::tempdir().unwrap();
+        let addr = start(open_broker(dir.path()));
+        let (mut client, _) = TestClient::connect(addr);
+        client.declare_queue(1, "jobs", false);
+        client.publish(1, "", "jobs", Properties::default(), b"job-1");
+        client.consume(1, "jobs", "first");
+        let (method, _, body) = client.recv_content(1);
+        assert_eq!(b"job-1".to_vec(), body);
+        assert!(matches!(
+            method,
+            Method::BasicDeliver {
+                redelivered: false,
+                ..
+            }
+        ));
+
+        // Closing the channel requeues the messages it did not acknowledge.
+        let close = Method::ChannelClose(Close {
+            reply_code: reply::SUCCESS,
+            reply_text: String::new(),
+            class_id: 0,
+            method_id: 0,
+        });
+        assert_eq!(Method::ChannelCloseOk, client.call(1, close));
+        client.open_channel(2);
+        client.consume(2, "jobs", "second");
+        let (method, _, body) = client.recv_content(2);
+        assert_eq!(b"job-1".to_vec(), body);
+        assert!(matches!(
+            method,
+            Method::BasicDeliver {
+                redelivered: true,
+                ..
+            }
+        ));
+    }
+}
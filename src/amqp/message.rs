@@ -0,0 +1,212 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::exchange::{EXCHANGE_HEADER, ROUTING_KEY_HEADER};
+use crate::record::{Record, PRIORITY_HEADER};
+
+use super::frame::Properties;
+use super::method::Routing;
+use super::table::{FieldValue, Table};
+
+/// The header holding the content type of an AMQP message.
+pub const CONTENT_TYPE_HEADER: &str = "amqp.content_type";
+/// The header holding the message id of an AMQP message.
+pub const MESSAGE_ID_HEADER: &str = "amqp.message_id";
+
+/// The prefix of the headers carrying AMQP properties of a message, which
+/// are not passed on to AMQP consumers in the headers table.
+const HEADER_PREFIX: &str = "amqp.";
+/// The prefix of the headers rift itself interprets.
+const RIFT_PREFIX: &str = "rift.";
+const CONTENT_ENCODING_HEADER: &str = "amqp.content_encoding";
+const DELIVERY_MODE_HEADER: &str = "amqp.delivery_mode";
+const TYPE_HEADER: &str = "amqp.type";
+const USER_ID_HEADER: &str = "amqp.user_id";
+const APP_ID_HEADER: &str = "amqp.app_id";
+
+/// Returns the record an AMQP message is stored as.
+///
+/// Properties rift has its own notion of, such as the priority, correlation
+/// id, reply to, and expiration, are stored in rift's headers so that native
+/// clients and queues honour them. The remaining properties are kept in AMQP
+/// specific headers, while entries of the headers table become headers of
+/// their own if their values have a textual form.
+pub(super) fn to_record(properties: &Properties, body: Vec<u8>) -> Record {
+    let mut record = Record::new(body);
+    if let Some(priority) = properties.priority {
+        record = record.with_priority(priority);
+    }
+    if let Some(correlation_id) = &properties.correlation_id {
+        record = record.with_correlation_id(correlation_id);
+    }
+    if let Some(reply_to) = &properties.reply_to {
+        record = record.with_reply_to(reply_to);
+    }
+    if let Some(ttl_ms) = properties
+        .expiration
+        .as_ref()
+        .and_then(|expiration| expiration.parse().ok())
+    {
+        record = record.with_ttl(ttl_ms);
+    }
+    if let Some(timestamp) = properties.timestamp {
+        record = record.with_timestamp((timestamp as i64).saturating_mul(1000));
+    }
+
+    let strings = [
+        (CONTENT_TYPE_HEADER, &properties.content_type),
+        (CONTENT_ENCODING_HEADER, &properties.content_encoding),
+        (MESSAGE_ID_HEADER, &properties.message_id),
+        (TYPE_HEADER, &properties.kind),
+        (USER_ID_HEADER, &properties.user_id),
+        (APP_ID_HEADER, &properties.app_id),
+    ];
+    for (key, value) in strings {
+        if let Some(value) = value {
+            record = record.with_header(key, value.as_str());
+        }
+    }
+    if let Some(delivery_mode) = properties.delivery_mode {
+        record = record.with_header(DELIVERY_MODE_HEADER, delivery_mode.to_string());
+    }
+    for (key, value) in properties.headers.iter().flatten() {
+        if let Some(value) = value.to_header() {
+            record = record.with_header(key.as_str(), value);
+        }
+    }
+    record
+}
+
+/// Returns the AMQP properties a record is delivered with, reversing
+/// [to_record]. Headers other than those describing the message in AMQP or
+/// rift terms are passed on in the headers table as long strings.
+pub(super) fn to_properties(record: &Record) -> Properties {
+    let string = |key| {
+        record
+            .header(key)
+            .and_then(|value| String::from_utf8(value.to_vec()).ok())
+    };
+    let headers: Table = record
+        .headers
+        .iter()
+        .filter(|header| {
+            !header.key.starts_with(HEADER_PREFIX) && !header.key.starts_with(RIFT_PREFIX)
+        })
+        .map(|header| {
+            (
+                header.key.clone(),
+                FieldValue::LongString(header.value.clone()),
+            )
+        })
+        .collect();
+    Properties {
+        content_type: string(CONTENT_TYPE_HEADER),
+        content_encoding: string(CONTENT_ENCODING_HEADER),
+        headers: (!headers.is_empty()).then_some(headers),
+        delivery_mode: string(DELIVERY_MODE_HEADER).and_then(|mode| mode.parse().ok()),
+        priority: record.header(PRIORITY_HEADER).map(|_| record.priority()),
+        correlation_id: record.correlation_id().map(str::to_owned),
+        reply_to: record.reply_to().map(str::to_owned),
+        expiration: record.ttl_ms().map(|ttl_ms| ttl_ms.to_string()),
+        message_id: string(MESSAGE_ID_HEADER),
+        timestamp: record.timestamp.map(|timestamp| (timestamp / 1000) as u64),
+        kind: string(TYPE_HEADER),
+        user_id: string(USER_ID_HEADER),
+        app_id: string(APP_ID_HEADER),
+    }
+}
+
+/// Returns the exchange and routing key a record consumed from the supplied
+/// queue is delivered with. Records that were not published to an exchange,
+/// such as those produced by native clients, appear to have been published to
+/// the default exchange with the queue as their routing key.
+pub(super) fn routing(record: &Record, queue: &str) -> Routing {
+    let string = |key| {
+        record
+            .header(key)
+            .and_then(|value| String::from_utf8(value.to_vec()).ok())
+    };
+    match (string(EXCHANGE_HEADER), string(ROUTING_KEY_HEADER)) {
+        (Some(exchange), Some(routing_key)) => Routing {
+            exchange,
+            routing_key,
+        },
+        _ => Routing {
+            exchange: String::new(),
+            routing_key: queue.to_owned(),
+        },
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_mapping() {
+        let properties = Properties {
+            content_type: Some(String::from("application/json")),
+            content_encoding: Some(String::from("gzip")),
+            headers: Some(vec![(
+                String::from("tenant"),
+                FieldValue::LongString(b"acme".to_vec()),
+            )]),
+            delivery_mode: Some(2),
+            priority: Some(4),
+            correlation_id: Some(String::from("req-1")),
+            reply_to: Some(String::from("replies")),
+            expiration: Some(String::from("60000")),
+            message_id: Some(String::from("msg-1")),
+            timestamp: Some(1_600_000_000),
+            kind: Some(String::from("order.created")),
+            user_id: Some(String::from("guest")),
+            app_id: Some(String::from("shop")),
+        };
+        let record = to_record(&properties, b"{}".to_vec());
+        assert_eq!(Some(b"{}".to_vec()), record.value);
+        assert_eq!(4, record.priority());
+        assert_eq!(Some("req-1"), record.correlation_id());
+        assert_eq!(Some("replies"), record.reply_to());
+        assert_eq!(Some(60000), record.ttl_ms());
+        assert_eq!(Some(1_600_000_000_000), record.timestamp);
+        assert_eq!(Some(&b"acme"[..]), record.header("tenant"));
+        assert_eq!(Some(&b"msg-1"[..]), record.header(MESSAGE_ID_HEADER));
+        assert_eq!(properties, to_properties(&record));
+
+        // Headers without a textual form are dropped, and priorities are
+        // capped at the highest rift supports.
+        let properties = Properties {
+            headers: Some(vec![(
+                String::from("nested"),
+                FieldValue::Table(Table::new()),
+            )]),
+            priority: Some(200),
+            ..Properties::default()
+        };
+        let record = to_record(&properties, Vec::new());
+        let expected = Properties {
+            priority: Some(9),
+            ..Properties::default()
+        };
+        assert_eq!(expected, to_properties(&record));
+    }
+
+    #[test]
+    fn test_routing() {
+        let record = Record::new("value")
+            .with_header(EXCHANGE_HEADER, "logs")
+            .with_header(ROUTING_KEY_HEADER, "app.error");
+        let expected = Routing {
+            exchange: String::from("logs"),
+            routing_key: String::from("app.error"),
+        };
+        assert_eq!(expected, routing(&record, "errors"));
+
+        let expected = Routing {
+            exchange: String::new(),
+            routing_key: String::from("errors"),
+        };
+        assert_eq!(expected, routing(&Record::new("value"), "errors"));
+    }
+}
@@ -0,0 +1,1048 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::codec::{Reader, Writer};
+
+use super::error::{Error, Result};
+use super::table::{
+    get_long_string, get_short_string, get_table, put_short_string, put_table, Table,
+};
+
+/// The class of connection methods.
+pub(super) const CONNECTION: u16 = 10;
+/// The class of channel methods.
+pub(super) const CHANNEL: u16 = 20;
+/// The class of exchange methods.
+pub(super) const EXCHANGE: u16 = 40;
+/// The class of queue methods.
+pub(super) const QUEUE: u16 = 50;
+/// The class of basic methods, whose content headers carry [super::frame::Properties].
+pub(super) const BASIC: u16 = 60;
+/// The class of publisher confirm methods.
+pub(super) const CONFIRM: u16 = 85;
+
+/// The reply codes of AMQP 0-9-1 used by rift.
+pub(super) mod reply {
+    /// The connection or channel closed normally.
+    pub const SUCCESS: u16 = 200;
+    /// A mandatory message could not be routed to any queue.
+    pub const NO_ROUTE: u16 = 312;
+    /// The client tried to work with a reserved entity.
+    pub const ACCESS_REFUSED: u16 = 403;
+    /// The entity does not exist.
+    pub const NOT_FOUND: u16 = 404;
+    /// Another connection holds the exclusive queue.
+    pub const RESOURCE_LOCKED: u16 = 405;
+    /// The request conflicts with the state of an entity or is invalid.
+    pub const PRECONDITION_FAILED: u16 = 406;
+    /// A frame could not be decoded or was too large.
+    pub const FRAME_ERROR: u16 = 501;
+    /// A frame held a field value that could not be decoded.
+    pub const SYNTAX_ERROR: u16 = 502;
+    /// The method is not allowed at this point of the conversation.
+    pub const COMMAND_INVALID: u16 = 503;
+    /// The channel is not open or its number is invalid.
+    pub const CHANNEL_ERROR: u16 = 504;
+    /// A content frame arrived when none was expected, or the reverse.
+    pub const UNEXPECTED_FRAME: u16 = 505;
+    /// The method conflicts with the state of the connection.
+    pub const NOT_ALLOWED: u16 = 530;
+    /// The method is not implemented.
+    pub const NOT_IMPLEMENTED: u16 = 540;
+    /// The server failed to serve the method.
+    pub const INTERNAL_ERROR: u16 = 541;
+}
+
+/// Why a connection or channel is being closed, along with the method that
+/// caused it, if any.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Close {
+    pub reply_code: u16,
+    pub reply_text: String,
+    pub class_id: u16,
+    pub method_id: u16,
+}
+
+impl Close {
+    fn decode(reader: &mut Reader) -> Result<Close> {
+        Ok(Close {
+            reply_code: reader.get_u16()?,
+            reply_text: get_short_string(reader)?,
+            class_id: reader.get_u16()?,
+            method_id: reader.get_u16()?,
+        })
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u16(self.reply_code);
+        put_short_string(buf, &self.reply_text);
+        buf.put_u16(self.class_id);
+        buf.put_u16(self.method_id);
+    }
+}
+
+/// A delivered message's routing, shared by basic.deliver, basic.get-ok, and
+/// basic.return.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Routing {
+    pub exchange: String,
+    pub routing_key: String,
+}
+
+/// A single AMQP 0-9-1 method, limited to those rift serves or sends.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Method {
+    ConnectionStart {
+        server_properties: Table,
+        mechanisms: Vec<u8>,
+        locales: Vec<u8>,
+    },
+    ConnectionStartOk {
+        client_properties: Table,
+        mechanism: String,
+        response: Vec<u8>,
+        locale: String,
+    },
+    ConnectionTune {
+        channel_max: u16,
+        frame_max: u32,
+        heartbeat: u16,
+    },
+    ConnectionTuneOk {
+        channel_max: u16,
+        frame_max: u32,
+        heartbeat: u16,
+    },
+    ConnectionOpen {
+        virtual_host: String,
+    },
+    ConnectionOpenOk,
+    ConnectionClose(Close),
+    ConnectionCloseOk,
+    ChannelOpen,
+    ChannelOpenOk,
+    ChannelFlow {
+        active: bool,
+    },
+    ChannelFlowOk {
+        active: bool,
+    },
+    ChannelClose(Close),
+    ChannelCloseOk,
+    ExchangeDeclare {
+        exchange: String,
+        kind: String,
+        passive: bool,
+        durable: bool,
+        auto_delete: bool,
+        internal: bool,
+        nowait: bool,
+        arguments: Table,
+    },
+    ExchangeDeclareOk,
+    ExchangeDelete {
+        exchange: String,
+        if_unused: bool,
+        nowait: bool,
+    },
+    ExchangeDeleteOk,
+    QueueDeclare {
+        queue: String,
+        passive: bool,
+        durable: bool,
+        exclusive: bool,
+        auto_delete: bool,
+        nowait: bool,
+        arguments: Table,
+    },
+    QueueDeclareOk {
+        queue: String,
+        message_count: u32,
+        consumer_count: u32,
+    },
+    QueueBind {
+        queue: String,
+        exchange: String,
+        routing_key: String,
+        nowait: bool,
+        arguments: Table,
+    },
+    QueueBindOk,
+    QueueDelete {
+        queue: String,
+        if_unused: bool,
+        if_empty: bool,
+        nowait: bool,
+    },
+    QueueDeleteOk {
+        message_count: u32,
+    },
+    QueueUnbind {
+        queue: String,
+        exchange: String,
+        routing_key: String,
+        arguments: Table,
+    },
+    QueueUnbindOk,
+    BasicQos {
+        prefetch_size: u32,
+        prefetch_count: u16,
+        global: bool,
+    },
+    BasicQosOk,
+    BasicConsume {
+        queue: String,
+        consumer_tag: String,
+        no_local: bool,
+        no_ack: bool,
+        exclusive: bool,
+        nowait: bool,
+        arguments: Table,
+    },
+    BasicConsumeOk {
+        consumer_tag: String,
+    },
+    BasicCancel {
+        consumer_tag: String,
+        nowait: bool,
+    },
+    BasicCancelOk {
+        consumer_tag: String,
+    },
+    BasicPublish {
+        routing: Routing,
+        mandatory: bool,
+        immediate: bool,
+    },
+    BasicReturn {
+        reply_code: u16,
+        reply_text: String,
+        routing: Routing,
+    },
+    BasicDeliver {
+        consumer_tag: String,
+        delivery_tag: u64,
+        redelivered: bool,
+        routing: Routing,
+    },
+    BasicGet {
+        queue: String,
+        no_ack: bool,
+    },
+    BasicGetOk {
+        delivery_tag: u64,
+        redelivered: bool,
+        routing: Routing,
+        message_count: u32,
+    },
+    BasicGetEmpty,
+    BasicAck {
+        delivery_tag: u64,
+        multiple: bool,
+    },
+    BasicReject {
+        delivery_tag: u64,
+        requeue: bool,
+    },
+    BasicRecover {
+        requeue: bool,
+    },
+    BasicRecoverOk,
+    BasicNack {
+        delivery_tag: u64,
+        multiple: bool,
+        requeue: bool,
+    },
+    ConfirmSelect {
+        nowait: bool,
+    },
+    ConfirmSelectOk,
+}
+
+impl Method {
+    /// Returns the class and method ids identifying this method.
+    pub(super) fn id(&self) -> (u16, u16) {
+        match self {
+            Method::ConnectionStart { .. } => (CONNECTION, 10),
+            Method::ConnectionStartOk { .. } => (CONNECTION, 11),
+            Method::ConnectionTune { .. } => (CONNECTION, 30),
+            Method::ConnectionTuneOk { .. } => (CONNECTION, 31),
+            Method::ConnectionOpen { .. } => (CONNECTION, 40),
+            Method::ConnectionOpenOk => (CONNECTION, 41),
+            Method::ConnectionClose(_) => (CONNECTION, 50),
+            Method::ConnectionCloseOk => (CONNECTION, 51),
+            Method::ChannelOpen => (CHANNEL, 10),
+            Method::ChannelOpenOk => (CHANNEL, 11),
+            Method::ChannelFlow { .. } => (CHANNEL, 20),
+            Method::ChannelFlowOk { .. } => (CHANNEL, 21),
+            Method::ChannelClose(_) => (CHANNEL, 40),
+            Method::ChannelCloseOk => (CHANNEL, 41),
+            Method::ExchangeDeclare { .. } => (EXCHANGE, 10),
+            Method::ExchangeDeclareOk => (EXCHANGE, 11),
+            Method::ExchangeDelete { .. } => (EXCHANGE, 20),
+            Method::ExchangeDeleteOk => (EXCHANGE, 21),
+            Method::QueueDeclare { .. } => (QUEUE, 10),
+            Method::QueueDeclareOk { .. } => (QUEUE, 11),
+            Method::QueueBind { .. } => (QUEUE, 20),
+            Method::QueueBindOk => (QUEUE, 21),
+            Method::QueueDelete { .. } => (QUEUE, 40),
+            Method::QueueDeleteOk { .. } => (QUEUE, 41),
+            Method::QueueUnbind { .. } => (QUEUE, 50),
+            Method::QueueUnbindOk => (QUEUE, 51),
+            Method::BasicQos { .. } => (BASIC, 10),
+            Method::BasicQosOk => (BASIC, 11),
+            Method::BasicConsume { .. } => (BASIC, 20),
+            Method::BasicConsumeOk { .. } => (BASIC, 21),
+            Method::BasicCancel { .. } => (BASIC, 30),
+            Method::BasicCancelOk { .. } => (BASIC, 31),
+            Method::BasicPublish { .. } => (BASIC, 40),
+            Method::BasicReturn { .. } => (BASIC, 50),
+            Method::BasicDeliver { .. } => (BASIC, 60),
+            Method::BasicGet { .. } => (BASIC, 70),
+            Method::BasicGetOk { .. } => (BASIC, 71),
+            Method::BasicGetEmpty => (BASIC, 72),
+            Method::BasicAck { .. } => (BASIC, 80),
+            Method::BasicReject { .. } => (BASIC, 90),
+            Method::BasicRecover { .. } => (BASIC, 110),
+            Method::BasicRecoverOk => (BASIC, 111),
+            Method::BasicNack { .. } => (BASIC, 120),
+            Method::ConfirmSelect { .. } => (CONFIRM, 10),
+            Method::ConfirmSelectOk => (CONFIRM, 11),
+        }
+    }
+
+    /// Decode the payload of a method frame.
+    pub(super) fn decode(payload: &[u8]) -> Result<Method> {
+        let mut reader = Reader::new(payload);
+        let r = &mut reader;
+        let (class_id, method_id) = (r.get_u16()?, r.get_u16()?);
+        let method = match (class_id, method_id) {
+            (CONNECTION, 10) => {
+                let (_major, _minor) = (r.get_u8()?, r.get_u8()?);
+                Method::ConnectionStart {
+                    server_properties: get_table(r)?,
+                    mechanisms: get_long_string(r)?,
+                    locales: get_long_string(r)?,
+                }
+            }
+            (CONNECTION, 11) => Method::ConnectionStartOk {
+                client_properties: get_table(r)?,
+                mechanism: get_short_string(r)?,
+                response: get_long_string(r)?,
+                locale: get_short_string(r)?,
+            },
+            (CONNECTION, 30) => Method::ConnectionTune {
+                channel_max: r.get_u16()?,
+                frame_max: r.get_u32()?,
+                heartbeat: r.get_u16()?,
+            },
+            (CONNECTION, 31) => Method::ConnectionTuneOk {
+                channel_max: r.get_u16()?,
+                frame_max: r.get_u32()?,
+                heartbeat: r.get_u16()?,
+            },
+            (CONNECTION, 40) => {
+                let virtual_host = get_short_string(r)?;
+                get_short_string(r)?;
+                get_bits(r)?;
+                Method::ConnectionOpen { virtual_host }
+            }
+            (CONNECTION, 41) => {
+                get_short_string(r)?;
+                Method::ConnectionOpenOk
+            }
+            (CONNECTION, 50) => Method::ConnectionClose(Close::decode(r)?),
+            (CONNECTION, 51) => Method::ConnectionCloseOk,
+            (CHANNEL, 10) => {
+                get_short_string(r)?;
+                Method::ChannelOpen
+            }
+            (CHANNEL, 11) => {
+                get_long_string(r)?;
+                Method::ChannelOpenOk
+            }
+            (CHANNEL, 20) => Method::ChannelFlow {
+                active: get_bits(r)?[0],
+            },
+            (CHANNEL, 21) => Method::ChannelFlowOk {
+                active: get_bits(r)?[0],
+            },
+            (CHANNEL, 40) => Method::ChannelClose(Close::decode(r)?),
+            (CHANNEL, 41) => Method::ChannelCloseOk,
+            (EXCHANGE, 10) => {
+                r.get_u16()?;
+                let (exchange, kind) = (get_short_string(r)?, get_short_string(r)?);
+                let bits = get_bits(r)?;
+                Method::ExchangeDeclare {
+                    exchange,
+                    kind,
+                    passive: bits[0],
+                    durable: bits[1],
+                    auto_delete: bits[2],
+                    internal: bits[3],
+                    nowait: bits[4],
+                    arguments: get_table(r)?,
+                }
+            }
+            (EXCHANGE, 11) => Method::ExchangeDeclareOk,
+            (EXCHANGE, 20) => {
+                r.get_u16()?;
+                let exchange = get_short_string(r)?;
+                let bits = get_bits(r)?;
+                Method::ExchangeDelete {
+                    exchange,
+                    if_unused: bits[0],
+                    nowait: bits[1],
+                }
+            }
+            (EXCHANGE, 21) => Method::ExchangeDeleteOk,
+            (QUEUE, 10) => {
+                r.get_u16()?;
+                let queue = get_short_string(r)?;
+                let bits = get_bits(r)?;
+                Method::QueueDeclare {
+                    queue,
+                    passive: bits[0],
+                    durable: bits[1],
+                    exclusive: bits[2],
+                    auto_delete: bits[3],
+                    nowait: bits[4],
+                    arguments: get_table(r)?,
+                }
+            }
+            (QUEUE, 11) => Method::QueueDeclareOk {
+                queue: get_short_string(r)?,
+                message_count: r.get_u32()?,
+                consumer_count: r.get_u32()?,
+            },
+            (QUEUE, 20) => {
+                r.get_u16()?;
+                Method::QueueBind {
+                    queue: get_short_string(r)?,
+                    exchange: get_short_string(r)?,
+                    routing_key: get_short_string(r)?,
+                    nowait: get_bits(r)?[0],
+                    arguments: get_table(r)?,
+                }
+            }
+            (QUEUE, 21) => Method::QueueBindOk,
+            (QUEUE, 40) => {
+                r.get_u16()?;
+                let queue = get_short_string(r)?;
+                let bits = get_bits(r)?;
+                Method::QueueDelete {
+                    queue,
+                    if_unused: bits[0],
+                    if_empty: bits[1],
+                    nowait: bits[2],
+                }
+            }
+            (QUEUE, 41) => Method::QueueDeleteOk {
+                message_count: r.get_u32()?,
+            },
+            (QUEUE, 50) => {
+                r.get_u16()?;
+                Method::QueueUnbind {
+                    queue: get_short_string(r)?,
+                    exchange: get_short_string(r)?,
+                    routing_key: get_short_string(r)?,
+                    arguments: get_table(r)?,
+                }
+            }
+            (QUEUE, 51) => Method::QueueUnbindOk,
+            (BASIC, 10) => Method::BasicQos {
+                prefetch_size: r.get_u32()?,
+                prefetch_count: r.get_u16()?,
+                global: get_bits(r)?[0],
+            },
+            (BASIC, 11) => Method::BasicQosOk,
+            (BASIC, 20) => {
+                r.get_u16()?;
+                let (queue, consumer_tag) = (get_short_string(r)?, get_short_string(r)?);
+                let bits = get_bits(r)?;
+                Method::BasicConsume {
+                    queue,
+                    consumer_tag,
+                    no_local: bits[0],
+                    no_ack: bits[1],
+                    exclusive: bits[2],
+                    nowait: bits[3],
+                    arguments: get_table(r)?,
+                }
+            }
+            (BASIC, 21) => Method::BasicConsumeOk {
+                consumer_tag: get_short_string(r)?,
+            },
+            (BASIC, 30) => Method::BasicCancel {
+                consumer_tag: get_short_string(r)?,
+                nowait: get_bits(r)?[0],
+            },
+            (BASIC, 31) => Method::BasicCancelOk {
+                consumer_tag: get_short_string(r)?,
+            },
+            (BASIC, 40) => {
+                r.get_u16()?;
+                let routing = get_routing(r)?;
+                let bits = get_bits(r)?;
+                Method::BasicPublish {
+                    routing,
+                    mandatory: bits[0],
+                    immediate: bits[1],
+                }
+            }
+            (BASIC, 50) => Method::BasicReturn {
+                reply_code: r.get_u16()?,
+                reply_text: get_short_string(r)?,
+                routing: get_routing(r)?,
+            },
+            (BASIC, 60) => Method::BasicDeliver {
+                consumer_tag: get_short_string(r)?,
+                delivery_tag: r.get_u64()?,
+                redelivered: get_bits(r)?[0],
+                routing: get_routing(r)?,
+            },
+            (BASIC, 70) => {
+                r.get_u16()?;
+                Method::BasicGet {
+                    queue: get_short_string(r)?,
+                    no_ack: get_bits(r)?[0],
+                }
+            }
+            (BASIC, 71) => Method::BasicGetOk {
+                delivery_tag: r.get_u64()?,
+                redelivered: get_bits(r)?[0],
+                routing: get_routing(r)?,
+                message_count: r.get_u32()?,
+            },
+            (BASIC, 72) => {
+                get_short_string(r)?;
+                Method::BasicGetEmpty
+            }
+            (BASIC, 80) => Method::BasicAck {
+                delivery_tag: r.get_u64()?,
+                multiple: get_bits(r)?[0],
+            },
+            (BASIC, 90) => Method::BasicReject {
+                delivery_tag: r.get_u64()?,
+                requeue: get_bits(r)?[0],
+            },
+            (BASIC, 110) => Method::BasicRecover {
+                requeue: get_bits(r)?[0],
+            },
+            (BASIC, 111) => Method::BasicRecoverOk,
+            (BASIC, 120) => {
+                let delivery_tag = r.get_u64()?;
+                let bits = get_bits(r)?;
+                Method::BasicNack {
+                    delivery_tag,
+                    multiple: bits[0],
+                    requeue: bits[1],
+                }
+            }
+            (CONFIRM, 10) => Method::ConfirmSelect {
+                nowait: get_bits(r)?[0],
+            },
+            (CONFIRM, 11) => Method::ConfirmSelectOk,
+            (class_id, method_id) => {
+                return Err(Error::UnsupportedMethod {
+                    class_id,
+                    method_id,
+                })
+            }
+        };
+        Ok(method)
+    }
+
+    /// Encode the method as the payload of a method frame.
+    pub(super) fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let (class_id, method_id) = self.id();
+        buf.put_u16(class_id);
+        buf.put_u16(method_id);
+        let b = &mut buf;
+        match self {
+            Method::ConnectionStart {
+                server_properties,
+                mechanisms,
+                locales,
+            } => {
+                b.put_u8(0);
+                b.put_u8(9);
+                put_table(b, server_properties);
+                b.put_bytes(mechanisms);
+                b.put_bytes(locales);
+            }
+            Method::ConnectionStartOk {
+                client_properties,
+                mechanism,
+                response,
+                locale,
+            } => {
+                put_table(b, client_properties);
+                put_short_string(b, mechanism);
+                b.put_bytes(response);
+                put_short_string(b, locale);
+            }
+            Method::ConnectionTune {
+                channel_max,
+                frame_max,
+                heartbeat,
+            }
+            | Method::ConnectionTuneOk {
+                channel_max,
+                frame_max,
+                heartbeat,
+            } => {
+                b.put_u16(*channel_max);
+                b.put_u32(*frame_max);
+                b.put_u16(*heartbeat);
+            }
+            Method::ConnectionOpen { virtual_host } => {
+                put_short_string(b, virtual_host);
+                put_short_string(b, "");
+                put_bits(b, &[false]);
+            }
+            Method::ConnectionOpenOk | Method::ChannelOpen | Method::BasicGetEmpty => {
+                put_short_string(b, "")
+            }
+            Method::ChannelOpenOk => b.put_bytes(&[]),
+            Method::ConnectionClose(close) | Method::ChannelClose(close) => close.encode(b),
+            Method::ConnectionCloseOk
+            | Method::ChannelCloseOk
+            | Method::ExchangeDeclareOk
+            | Method::ExchangeDeleteOk
+            | Method::QueueBindOk
+            | Method::QueueUnbindOk
+            | Method::BasicQosOk
+            | Method::BasicRecoverOk
+            | Method::ConfirmSelectOk => {}
+            Method::ChannelFlow { active } | Method::ChannelFlowOk { active } => {
+                put_bits(b, &[*active])
+            }
+            Method::ExchangeDeclare {
+                exchange,
+                kind,
+                passive,
+                durable,
+                auto_delete,
+                internal,
+                nowait,
+                arguments,
+            } => {
+                b.put_u16(0);
+                put_short_string(b, exchange);
+                put_short_string(b, kind);
+                put_bits(b, &[*passive, *durable, *auto_delete, *internal, *nowait]);
+                put_table(b, arguments);
+            }
+            Method::ExchangeDelete {
+                exchange,
+                if_unused,
+                nowait,
+            } => {
+                b.put_u16(0);
+                put_short_string(b, exchange);
+                put_bits(b, &[*if_unused, *nowait]);
+            }
+            Method::QueueDeclare {
+                queue,
+                passive,
+                durable,
+                exclusive,
+                auto_delete,
+                nowait,
+                arguments,
+            } => {
+                b.put_u16(0);
+                put_short_string(b, queue);
+                put_bits(b, &[*passive, *durable, *exclusive, *auto_delete, *nowait]);
+                put_table(b, arguments);
+            }
+            Method::QueueDeclareOk {
+                queue,
+                message_count,
+                consumer_count,
+            } => {
+                put_short_string(b, queue);
+                b.put_u32(*message_count);
+                b.put_u32(*consumer_count);
+            }
+            Method::QueueBind {
+                queue,
+                exchange,
+                routing_key,
+                nowait,
+                arguments,
+            } => {
+                b.put_u16(0);
+                put_short_string(b, queue);
+                put_short_string(b, exchange);
+                put_short_string(b, routing_key);
+                put_bits(b, &[*nowait]);
+                put_table(b, arguments);
+            }
+            Method::QueueDelete {
+                queue,
+                if_unused,
+                if_empty,
+                nowait,
+            } => {
+                b.put_u16(0);
+                put_short_string(b, queue);
+                put_bits(b, &[*if_unused, *if_empty, *nowait]);
+            }
+            Method::QueueDeleteOk { message_count } => b.put_u32(*message_count),
+            Method::QueueUnbind {
+                queue,
+                exchange,
+                routing_key,
+                arguments,
+            } => {
+                b.put_u16(0);
+                put_short_string(b, queue);
+                put_short_string(b, exchange);
+                put_short_string(b, routing_key);
+                put_table(b, arguments);
+            }
+            Method::BasicQos {
+                prefetch_size,
+                prefetch_count,
+                global,
+            } => {
+                b.put_u32(*prefetch_size);
+                b.put_u16(*prefetch_count);
+                put_bits(b, &[*global]);
+            }
+            Method::BasicConsume {
+                queue,
+                consumer_tag,
+                no_local,
+                no_ack,
+                exclusive,
+                nowait,
+                arguments,
+            } => {
+                b.put_u16(0);
+                put_short_string(b, queue);
+                put_short_string(b, consumer_tag);
+                put_bits(b, &[*no_local, *no_ack, *exclusive, *nowait]);
+                put_table(b, arguments);
+            }
+            Method::BasicConsumeOk { consumer_tag } | Method::BasicCancelOk { consumer_tag } => {
+                put_short_string(b, consumer_tag)
+            }
+            Method::BasicCancel {
+                consumer_tag,
+                nowait,
+            } => {
+                put_short_string(b, consumer_tag);
+                put_bits(b, &[*nowait]);
+            }
+            Method::BasicPublish {
+                routing,
+                mandatory,
+                immediate,
+            } => {
+                b.put_u16(0);
+                put_routing(b, routing);
+                put_bits(b, &[*mandatory, *immediate]);
+            }
+            Method::BasicReturn {
+                reply_code,
+                reply_text,
+                routing,
+            } => {
+                b.put_u16(*reply_code);
+                put_short_string(b, reply_text);
+                put_routing(b, routing);
+            }
+            Method::BasicDeliver {
+                consumer_tag,
+                delivery_tag,
+                redelivered,
+                routing,
+            } => {
+                put_short_string(b, consumer_tag);
+                b.put_u64(*delivery_tag);
+                put_bits(b, &[*redelivered]);
+                put_routing(b, routing);
+            }
+            Method::BasicGet { queue, no_ack } => {
+                b.put_u16(0);
+                put_short_string(b, queue);
+                put_bits(b, &[*no_ack]);
+            }
+            Method::BasicGetOk {
+                delivery_tag,
+                redelivered,
+                routing,
+                message_count,
+            } => {
+                b.put_u64(*delivery_tag);
+                put_bits(b, &[*redelivered]);
+                put_routing(b, routing);
+                b.put_u32(*message_count);
+            }
+            Method::BasicAck {
+                delivery_tag,
+                multiple: flag,
+            }
+            | Method::BasicReject {
+                delivery_tag,
+                requeue: flag,
+            } => {
+                b.put_u64(*delivery_tag);
+                put_bits(b, &[*flag]);
+            }
+            Method::BasicRecover { requeue } => put_bits(b, &[*requeue]),
+            Method::BasicNack {
+                delivery_tag,
+                multiple,
+                requeue,
+            } => {
+                b.put_u64(*delivery_tag);
+                put_bits(b, &[*multiple, *requeue]);
+            }
+            Method::ConfirmSelect { nowait } => put_bits(b, &[*nowait]),
+        }
+        buf
+    }
+}
+
+fn get_routing(reader: &mut Reader) -> Result<Routing> {
+    Ok(Routing {
+        exchange: get_short_string(reader)?,
+        routing_key: get_short_string(reader)?,
+    })
+}
+
+fn put_routing(buf: &mut Vec<u8>, routing: &Routing) {
+    put_short_string(buf, &routing.exchange);
+    put_short_string(buf, &routing.routing_key);
+}
+
+/// Decode up to eight consecutive bit fields, which share a single octet.
+fn get_bits(reader: &mut Reader) -> Result<[bool; 8]> {
+    let octet = reader.get_u8()?;
+    let mut bits = [false; 8];
+    for (idx, bit) in bits.iter_mut().enumerate() {
+        *bit = octet & (1 << idx) != 0;
+    }
+    Ok(bits)
+}
+
+fn put_bits(buf: &mut Vec<u8>, bits: &[bool]) {
+    let octet = bits
+        .iter()
+        .enumerate()
+        .fold(0u8, |octet, (idx, bit)| octet | (*bit as u8) << idx);
+    buf.put_u8(octet);
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+    use crate::amqp::table::FieldValue;
+
+    #[test]
+    fn test_round_trip() {
+        let table: Table = vec![(
+            String::from("x-match"),
+            FieldValue::LongString(b"any".to_vec()),
+        )];
+        let routing = Routing {
+            exchange: String::from("logs"),
+            routing_key: String::from("app.error"),
+        };
+        let close = Close {
+            reply_code: reply::NOT_FOUND,
+            reply_text: String::from("NOT_FOUND - no queue 'jobs'"),
+            class_id: QUEUE,
+            method_id: 10,
+        };
+        let methods = vec![
+            Method::ConnectionStart {
+                server_properties: table.clone(),
+                mechanisms: b"PLAIN".to_vec(),
+                locales: b"en_US".to_vec(),
+            },
+            Method::ConnectionStartOk {
+                client_properties: table.clone(),
+                mechanism: String::from("PLAIN"),
+                response: b"\0guest\0guest".to_vec(),
+                locale: String::from("en_US"),
+            },
+            Method::ConnectionTune {
+                channel_max: 2047,
+                frame_max: 131072,
+                heartbeat: 60,
+            },
+            Method::ConnectionTuneOk {
+                channel_max: 10,
+                frame_max: 4096,
+                heartbeat: 0,
+            },
+            Method::ConnectionOpen {
+                virtual_host: String::from("/"),
+            },
+            Method::ConnectionOpenOk,
+            Method::ConnectionClose(close.clone()),
+            Method::ConnectionCloseOk,
+            Method::ChannelOpen,
+            Method::ChannelOpenOk,
+            Method::ChannelFlow { active: false },
+            Method::ChannelFlowOk { active: true },
+            Method::ChannelClose(close),
+            Method::ChannelCloseOk,
+            Method::ExchangeDeclare {
+                exchange: String::from("logs"),
+                kind: String::from("topic"),
+                passive: false,
+                durable: true,
+                auto_delete: false,
+                internal: false,
+                nowait: true,
+                arguments: table.clone(),
+            },
+            Method::ExchangeDeclareOk,
+            Method::ExchangeDelete {
+                exchange: String::from("logs"),
+                if_unused: true,
+                nowait: false,
+            },
+            Method::ExchangeDeleteOk,
+            Method::QueueDeclare {
+                queue: String::from("jobs"),
+                passive: true,
+                durable: false,
+                exclusive: true,
+                auto_delete: false,
+                nowait: true,
+                arguments: Table::new(),
+            },
+            Method::QueueDeclareOk {
+                queue: String::from("jobs"),
+                message_count: 3,
+                consumer_count: 1,
+            },
+            Method::QueueBind {
+                queue: String::from("jobs"),
+                exchange: String::from("logs"),
+                routing_key: String::from("*.error"),
+                nowait: false,
+                arguments: table.clone(),
+            },
+            Method::QueueBindOk,
+            Method::QueueDelete {
+                queue: String::from("jobs"),
+                if_unused: false,
+                if_empty: true,
+                nowait: false,
+            },
+            Method::QueueDeleteOk { message_count: 2 },
+            Method::QueueUnbind {
+                queue: String::from("jobs"),
+                exchange: String::from("logs"),
+                routing_key: String::from("*.error"),
+                arguments: table.clone(),
+            },
+            Method::QueueUnbindOk,
+            Method::BasicQos {
+                prefetch_size: 0,
+                prefetch_count: 10,
+                global: true,
+            },
+            Method::BasicQosOk,
+            Method::BasicConsume {
+                queue: String::from("jobs"),
+                consumer_tag: String::from("worker"),
+                no_local: false,
+                no_ack: true,
+                exclusive: false,
+                nowait: false,
+                arguments: table,
+            },
+            Method::BasicConsumeOk {
+                consumer_tag: String::from("worker"),
+            },
+            Method::BasicCancel {
+                consumer_tag: String::from("worker"),
+                nowait: true,
+            },
+            Method::BasicCancelOk {
+                consumer_tag: String::from("worker"),
+            },
+            Method::BasicPublish {
+                routing: routing.clone(),
+                mandatory: true,
+                immediate: false,
+            },
+            Method::BasicReturn {
+                reply_code: reply::NO_ROUTE,
+                reply_text: String::from("NO_ROUTE"),
+                routing: routing.clone(),
+            },
+            Method::BasicDeliver {
+                consumer_tag: String::from("worker"),
+                delivery_tag: u64::MAX,
+                redelivered: true,
+                routing: routing.clone(),
+            },
+            Method::BasicGet {
+                queue: String::from("jobs"),
+                no_ack: true,
+            },
+            Method::BasicGetOk {
+                delivery_tag: 1,
+                redelivered: false,
+                routing,
+                message_count: 0,
+            },
+            Method::BasicGetEmpty,
+            Method::BasicAck {
+                delivery_tag: 3,
+                multiple: true,
+            },
+            Method::BasicReject {
+                delivery_tag: 4,
+                requeue: false,
+            },
+            Method::BasicRecover { requeue: true },
+            Method::BasicRecoverOk,
+            Method::BasicNack {
+                delivery_tag: 5,
+                multiple: false,
+                requeue: true,
+            },
+            Method::ConfirmSelect { nowait: false },
+            Method::ConfirmSelectOk,
+        ];
+        for method in methods {
+            assert_eq!(method, Method::decode(&method.encode()).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        // tx.select is not supported.
+        let err = Method::decode(&[0, 90, 0, 10]).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::UnsupportedMethod {
+                class_id: 90,
+                method_id: 10
+            }
+        ));
+        assert!(Method::decode(&[0, 10, 0, 30, 0]).is_err());
+    }
+
+    #[test]
+    fn test_bits() {
+        let mut buf = Vec::new();
+        put_bits(&mut buf, &[true, false, true, true]);
+        assert_eq!(vec![0b1101], buf);
+        let bits = get_bits(&mut Reader::new(&buf)).unwrap();
+        assert_eq!([true, false, true, true, false, false, false, false], bits);
+    }
+}
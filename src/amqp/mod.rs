@@ -0,0 +1,18 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod channel;
+mod config;
+mod connection;
+mod error;
+mod frame;
+mod gateway;
+mod listener;
+mod message;
+mod method;
+mod table;
+
+pub use self::config::Config;
+pub use self::error::{Error, Result};
+pub use self::listener::Server;
+pub use self::message::{CONTENT_TYPE_HEADER, MESSAGE_ID_HEADER};
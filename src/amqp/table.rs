@@ -0,0 +1,300 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::codec::{self, Reader, Writer};
+
+/// A value of an AMQP field table or field array, tagged on the wire with the
+/// types used by RabbitMQ.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum FieldValue {
+    Bool(bool),
+    I8(i8),
+    U8(u8),
+    I16(i16),
+    U16(u16),
+    I32(i32),
+    U32(u32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    /// A decimal with the supplied scale and unscaled value.
+    Decimal(u8, u32),
+    LongString(Vec<u8>),
+    Array(Vec<FieldValue>),
+    /// Seconds since the epoch.
+    Timestamp(u64),
+    Table(Table),
+    Void,
+    Bytes(Vec<u8>),
+}
+
+/// An AMQP field table, keeping the order entries were encoded in.
+pub(super) type Table = Vec<(String, FieldValue)>;
+
+impl FieldValue {
+    /// Returns the value as the bytes of a rift header, which is the UTF-8 text
+    /// of scalar values. Tables, arrays, and voids have no such form.
+    pub(super) fn to_header(&self) -> Option<Vec<u8>> {
+        let text = match self {
+            FieldValue::LongString(value) | FieldValue::Bytes(value) => return Some(value.clone()),
+            FieldValue::Bool(value) => value.to_string(),
+            FieldValue::I8(value) => value.to_string(),
+            FieldValue::U8(value) => value.to_string(),
+            FieldValue::I16(value) => value.to_string(),
+            FieldValue::U16(value) => value.to_string(),
+            FieldValue::I32(value) => value.to_string(),
+            FieldValue::U32(value) => value.to_string(),
+            FieldValue::I64(value) => value.to_string(),
+            FieldValue::F32(value) => value.to_string(),
+            FieldValue::F64(value) => value.to_string(),
+            FieldValue::Timestamp(value) => value.to_string(),
+            FieldValue::Decimal(scale, value) => {
+                let value = *value as f64 / 10f64.powi(*scale as i32);
+                value.to_string()
+            }
+            FieldValue::Array(_) | FieldValue::Table(_) | FieldValue::Void => return None,
+        };
+        Some(text.into_bytes())
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<FieldValue> {
+        let tag = reader.get_u8()?;
+        let value = match tag {
+            b't' => FieldValue::Bool(reader.get_bool()?),
+            b'b' => FieldValue::I8(reader.get_i8()?),
+            b'B' => FieldValue::U8(reader.get_u8()?),
+            b's' => FieldValue::I16(reader.get_i16()?),
+            b'u' => FieldValue::U16(reader.get_u16()?),
+            b'I' => FieldValue::I32(reader.get_i32()?),
+            b'i' => FieldValue::U32(reader.get_u32()?),
+            b'l' => FieldValue::I64(reader.get_i64()?),
+            b'f' => FieldValue::F32(f32::from_bits(reader.get_u32()?)),
+            b'd' => FieldValue::F64(f64::from_bits(reader.get_u64()?)),
+            b'D' => FieldValue::Decimal(reader.get_u8()?, reader.get_u32()?),
+            b'S' => FieldValue::LongString(get_long_string(reader)?),
+            b'A' => {
+                let len = reader.get_u32()? as usize;
+                let mut array = Reader::new(reader.get_raw(len)?);
+                let mut values = Vec::new();
+                while !array.is_empty() {
+                    values.push(FieldValue::decode(&mut array)?);
+                }
+                FieldValue::Array(values)
+            }
+            b'T' => FieldValue::Timestamp(reader.get_u64()?),
+            b'F' => FieldValue::Table(get_table(reader)?),
+            b'V' => FieldValue::Void,
+            b'x' => FieldValue::Bytes(get_long_string(reader)?),
+            tag => {
+                return Err(codec::Error::InvalidValue {
+                    field: "field value type",
+                    value: tag as i64,
+                })
+            }
+        };
+        Ok(value)
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            FieldValue::Bool(value) => {
+                buf.put_u8(b't');
+                buf.put_bool(*value);
+            }
+            FieldValue::I8(value) => {
+                buf.put_u8(b'b');
+                buf.put_i8(*value);
+            }
+            FieldValue::U8(value) => {
+                buf.put_u8(b'B');
+                buf.put_u8(*value);
+            }
+            FieldValue::I16(value) => {
+                buf.put_u8(b's');
+                buf.put_i16(*value);
+            }
+            FieldValue::U16(value) => {
+                buf.put_u8(b'u');
+                buf.put_u16(*value);
+            }
+            FieldValue::I32(value) => {
+                buf.put_u8(b'I');
+                buf.put_i32(*value);
+            }
+            FieldValue::U32(value) => {
+                buf.put_u8(b'i');
+                buf.put_u32(*value);
+            }
+            FieldValue::I64(value) => {
+                buf.put_u8(b'l');
+                buf.put_i64(*value);
+            }
+            FieldValue::F32(value) => {
+                buf.put_u8(b'f');
+                buf.put_u32(value.to_bits());
+            }
+            FieldValue::F64(value) => {
+                buf.put_u8(b'd');
+                buf.put_u64(value.to_bits());
+            }
+            FieldValue::Decimal(scale, value) => {
+                buf.put_u8(b'D');
+                buf.put_u8(*scale);
+                buf.put_u32(*value);
+            }
+            FieldValue::LongString(value) => {
+                buf.put_u8(b'S');
+                buf.put_bytes(value);
+            }
+            FieldValue::Array(values) => {
+                buf.put_u8(b'A');
+                let mut array = Vec::new();
+                for value in values {
+                    value.encode(&mut array);
+                }
+                buf.put_bytes(&array);
+            }
+            FieldValue::Timestamp(value) => {
+                buf.put_u8(b'T');
+                buf.put_u64(*value);
+            }
+            FieldValue::Table(table) => {
+                buf.put_u8(b'F');
+                put_table(buf, table);
+            }
+            FieldValue::Void => buf.put_u8(b'V'),
+            FieldValue::Bytes(value) => {
+                buf.put_u8(b'x');
+                buf.put_bytes(value);
+            }
+        }
+    }
+}
+
+/// Decode a string prefixed by its length as a single byte.
+pub(super) fn get_short_string(reader: &mut Reader) -> codec::Result<String> {
+    let len = reader.get_u8()? as usize;
+    String::from_utf8(reader.get_raw(len)?.to_vec()).map_err(|_| codec::Error::InvalidUtf8)
+}
+
+/// Encode a string prefixed by its length as a single byte, truncating it to
+/// the 255 bytes that allows.
+pub(super) fn put_short_string(buf: &mut Vec<u8>, value: &str) {
+    let mut len = value.len().min(u8::MAX as usize);
+    while !value.is_char_boundary(len) {
+        len -= 1;
+    }
+    buf.put_u8(len as u8);
+    buf.put_raw(&value.as_bytes()[..len]);
+}
+
+/// Decode bytes prefixed by their length as a u32.
+pub(super) fn get_long_string(reader: &mut Reader) -> codec::Result<Vec<u8>> {
+    reader.get_bytes()
+}
+
+/// Decode a field table prefixed by its length in bytes.
+pub(super) fn get_table(reader: &mut Reader) -> codec::Result<Table> {
+    let len = reader.get_u32()? as usize;
+    let mut entries = Reader::new(reader.get_raw(len)?);
+    let mut table = Table::new();
+    while !entries.is_empty() {
+        let key = get_short_string(&mut entries)?;
+        table.push((key, FieldValue::decode(&mut entries)?));
+    }
+    Ok(table)
+}
+
+/// Encode a field table prefixed by its length in bytes.
+pub(super) fn put_table(buf: &mut Vec<u8>, table: &Table) {
+    let mut entries = Vec::new();
+    for (key, value) in table {
+        put_short_string(&mut entries, key);
+        value.encode(&mut entries);
+    }
+    buf.put_bytes(&entries);
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let table: Table = vec![
+            (String::from("bool"), FieldValue::Bool(true)),
+            (String::from("i8"), FieldValue::I8(-1)),
+            (String::from("u8"), FieldValue::U8(1)),
+            (String::from("i16"), FieldValue::I16(-2)),
+            (String::from("u16"), FieldValue::U16(2)),
+            (String::from("i32"), FieldValue::I32(-3)),
+            (String::from("u32"), FieldValue::U32(3)),
+            (String::from("i64"), FieldValue::I64(-4)),
+            (String::from("f32"), FieldValue::F32(1.5)),
+            (String::from("f64"), FieldValue::F64(2.5)),
+            (String::from("decimal"), FieldValue::Decimal(2, 1234)),
+            (
+                String::from("string"),
+                FieldValue::LongString(b"text".to_vec()),
+            ),
+            (
+                String::from("array"),
+                FieldValue::Array(vec![FieldValue::U8(1), FieldValue::Void]),
+            ),
+            (
+                String::from("timestamp"),
+                FieldValue::Timestamp(1_600_000_000),
+            ),
+            (
+                String::from("table"),
+                FieldValue::Table(vec![(String::from("nested"), FieldValue::Bool(false))]),
+            ),
+            (String::from("void"), FieldValue::Void),
+            (String::from("bytes"), FieldValue::Bytes(vec![0xFF])),
+        ];
+        let mut buf = Vec::new();
+        put_table(&mut buf, &table);
+        let mut reader = Reader::new(&buf);
+        assert_eq!(table, get_table(&mut reader).unwrap());
+        assert!(reader.is_empty());
+
+        let mut buf = vec![1, b'x'];
+        buf.put_u8(b'?');
+        let mut table = Vec::new();
+        table.put_bytes(&buf);
+        assert!(get_table(&mut Reader::new(&table)).is_err());
+    }
+
+    #[test]
+    fn test_headers() {
+        assert_eq!(
+            Some(b"text".to_vec()),
+            FieldValue::LongString(b"text".to_vec()).to_header()
+        );
+        assert_eq!(Some(b"-3".to_vec()), FieldValue::I32(-3).to_header());
+        assert_eq!(Some(b"true".to_vec()), FieldValue::Bool(true).to_header());
+        assert_eq!(
+            Some(b"12.34".to_vec()),
+            FieldValue::Decimal(2, 1234).to_header()
+        );
+        assert_eq!(None, FieldValue::Void.to_header());
+    }
+
+    #[test]
+    fn test_short_string() {
+        let mut buf = Vec::new();
+        put_short_string(&mut buf, "queue");
+        assert_eq!(b"\x05queue".to_vec(), buf);
+        assert_eq!("queue", get_short_string(&mut Reader::new(&buf)).unwrap());
+
+        // Strings that are too long are cut at a character boundary.
+        let mut buf = Vec::new();
+        put_short_string(&mut buf, &"é".repeat(200));
+        assert_eq!(254, buf[0]);
+        assert_eq!(
+            &"é".repeat(127),
+            &get_short_string(&mut Reader::new(&buf)).unwrap()
+        );
+    }
+}
@@ -0,0 +1,2729 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use crate::client::{self, Client};
+use crate::exchange::{self, EXCHANGE_HEADER, ROUTING_KEY_HEADER};
+use crate::group::{self, Coordinator, Membership};
+use crate::offset;
+use crate::producer::{self, Markers};
+use crate::protocol::{
+    AddPartitionsToTxnRequest, ApiVersionsResponse, BindRequest, CommitOffsetRequest,
+    CoordinatorResponse, CreateTopicRequest, EndTxnRequest, ErrorCode, FetchOffsetsRequest,
+    FetchPartition, FetchRequest, FetchResponse, FetchedPartition, GroupAssignmentResponse,
+    InitProducerRequest, InitProducerResponse, JoinGroupRequest, LeaseRequest, LeaseResponse,
+    LeasedMessage, MetadataRequest, MetadataResponse, NackRequest, NodeMetadata,
+    OffsetsForTimesRequest, OffsetsForTimesResponse, OffsetsResponse, PartitionMetadata,
+    PartitionOffset, PartitionTimestamp, ProduceRequest, ProduceResponse, ProducedRecord,
+    PublishRequest, PublishResponse, ReplayDeadLettersRequest, ReplayDeadLettersResponse, Request,
+    ResetOffsetsRequest, Response, ResponseError, RoutedRecord, ScheduleRequest, ScheduleResponse,
+    ScheduledMessage, TimestampOffset, TopicMetadata, WriteTxnMarkersRequest,
+};
+use crate::queue::{self, DeadLetter, DeliveryPolicy};
+use crate::raft;
+use crate::record::{self, Compression, ControlMarker, Record};
+use crate::schedule::{self, Scheduler};
+use crate::selector::Selector;
+use crate::topic::{
+    self, IsolationLevel, Partition, Partitioning, Topic, TopicConfig, TopicPartition,
+};
+
+use super::error::{raft_error_code, topic_error_code, Error};
+
+/// The internal queue tracking which messages of each dead-letter topic
+/// partition have been replayed, also used as its consumer id.
+const REPLAY_QUEUE: &str = "__dead_letter_replay";
+/// How long dead letters being replayed are held for before they are retried,
+/// should replaying them fail.
+const REPLAY_TIMEOUT_MS: u64 = 30_000;
+/// The most scheduled messages released to their partitions at a time.
+const RELEASE_BATCH: usize = 1000;
+/// The prefix of the topics created as temporary reply queues, which are
+/// deleted once the connection that created them closes. Clients may not create
+/// topics with this prefix themselves.
+pub const TEMPORARY_QUEUE_PREFIX: &str = "tmp.";
+
+/// Dispatches decoded protocol requests to the subsystems that serve them.
+pub struct Broker {
+    logger: slog::Logger,
+    topics: Arc<topic::Manager>,
+    groups: Coordinator,
+    offsets: offset::Store,
+    producer_ids: producer::Allocator,
+    transactions: producer::Coordinator,
+    queues: queue::Coordinator,
+    queue_rotation: AtomicUsize,
+    scheduler: Scheduler,
+    exchanges: exchange::Registry,
+    temporary_queues: AtomicU64,
+    replication: Option<Arc<raft::Node>>,
+}
+
+impl Broker {
+    /// Open a broker serving the supplied topics, coordinating consumer groups,
+    /// transactions, queues, and scheduled messages with the supplied
+    /// configurations, recovering any committed offsets, transaction state,
+    /// queue progress, messages not yet due, and declared exchanges.
+    pub fn open(
+        logger: slog::Logger,
+        topics: Arc<topic::Manager>,
+        group_cfg: group::Config,
+        producer_cfg: producer::Config,
+        queue_cfg: queue::Config,
+        schedule_cfg: schedule::Config,
+    ) -> Result<Broker, Error> {
+        let offsets = offset::Store::open(topics.clone())?;
+        let groups = Coordinator::new(logger.clone(), group_cfg, topics.clone());
+        let producer_ids = producer::Allocator::new(topics.dir());
+        let transactions =
+            producer::Coordinator::open(logger.clone(), producer_cfg, topics.clone())?;
+        let queues = queue::Coordinator::open(queue_cfg, topics.clone())?;
+        let scheduler = Scheduler::open(schedule_cfg, topics.clone())?;
+        let exchanges = exchange::Registry::open(topics.clone())?;
+        Ok(Broker {
+            logger,
+            topics,
+            groups,
+            offsets,
+            producer_ids,
+            transactions,
+            queues,
+            queue_rotation: AtomicUsize::new(0),
+            scheduler,
+            exchanges,
+            temporary_queues: AtomicU64::new(0),
+            replication: None,
+        })
+    }
+
+    /// Replicate this broker's topics across the cluster the supplied node is a
+    /// member of, serving produce requests only for partitions it leads and
+    /// fetch requests only up to each partition's commit offset. Topics are
+    /// created and deleted through the cluster metadata, which also answers
+    /// metadata requests.
+    pub fn replicated(mut self, node: Arc<raft::Node>) -> Broker {
+        self.replication = Some(node);
+        self
+    }
+
+    /// Returns the topics served by this broker.
+    pub fn topics(&self) -> &topic::Manager {
+        &self.topics
+    }
+
+    /// Returns the consumer group coordinator of this broker.
+    pub fn groups(&self) -> &Coordinator {
+        &self.groups
+    }
+
+    /// Returns the transaction coordinator of this broker.
+    pub fn transactions(&self) -> &producer::Coordinator {
+        &self.transactions
+    }
+
+    /// Returns the queue coordinator of this broker.
+    pub fn queues(&self) -> &queue::Coordinator {
+        &self.queues
+    }
+
+    /// Returns the scheduler holding messages until their delivery time.
+    pub fn scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
+
+    /// Returns the exchanges and bindings records are published through.
+    pub fn exchanges(&self) -> &exchange::Registry {
+        &self.exchanges
+    }
+
+    /// Abort transactions that have outlived their timeout as of `now`, in
+    /// milliseconds since the epoch, and retry writing the markers of
+    /// transactions that have not been completed yet.
+    pub fn expire_transactions(&self, now: i64) {
+        for markers in self.transactions.expire(now) {
+            if let Err(err) = self.complete_txn(&markers) {
+                warn!(self.logger, "Failed to write transaction markers, will retry."; "transactional_id" => &markers.transactional_id, "error" => err.to_string());
+            }
+        }
+    }
+
+    /// Append the scheduled messages due as of `now`, in milliseconds since the
+    /// epoch, to their partitions in delivery order. Messages are only marked
+    /// as released once appended, so a failure leaves them to be retried
+    /// later, and messages whose topic or partition no longer exists are
+    /// dropped.
+    pub fn release_scheduled(&self, now: i64) {
+        loop {
+            let due = self.scheduler.due(now, RELEASE_BATCH);
+            if due.is_empty() {
+                return;
+            }
+            let exhausted = due.len() < RELEASE_BATCH;
+
+            let mut partitions: BTreeMap<TopicPartition, (Vec<u64>, Vec<Record>)> = BTreeMap::new();
+            for scheduled in due {
+                let (ids, records) = partitions.entry(scheduled.partition).or_default();
+                ids.push(scheduled.id);
+                records.push(scheduled.record);
+            }
+            for (partition, (ids, records)) in partitions {
+                match self.produce_to(&partition, records) {
+                    Ok(()) => {}
+                    Err(err)
+                        if matches!(
+                            err.code,
+                            ErrorCode::TopicNotFound | ErrorCode::PartitionNotFound
+                        ) =>
+                    {
+                        warn!(self.logger, "Dropped scheduled messages for a partition that does not exist."; "partition" => partition.to_string(), "messages" => ids.len());
+                    }
+                    Err(err) => {
+                        warn!(self.logger, "Failed to release scheduled messages, will retry."; "partition" => partition.to_string(), "error" => err.to_string());
+                        return;
+                    }
+                }
+                if let Err(err) = self.scheduler.released(&ids) {
+                    warn!(self.logger, "Failed to mark scheduled messages as released."; "partition" => partition.to_string(), "error" => err.to_string());
+                    return;
+                }
+            }
+            if exhausted {
+                return;
+            }
+        }
+    }
+
+    /// Give up on messages leased to a queue consumer that rejected them,
+    /// moving them to the topic's dead-letter topic right away whatever their
+    /// delivery count, or dropping them if the topic has none. Should writing
+    /// them to the dead-letter topic fail they are redelivered after a while.
+    pub fn reject(&self, req: NackRequest) -> Result<(), ResponseError> {
+        check_queue(&req.queue, &req.consumer_id)?;
+        let partition = self.queue_partition(&req.topic, req.partition)?;
+        let now = record::current_timestamp();
+        let topic = self.topics.get(&req.topic)?;
+        let (dead_letter_topic, dead_letter_partitions) = match self.queue_policy(&topic)?.1 {
+            Some(dead_letter) => dead_letter,
+            None => {
+                self.queues
+                    .ack(&req.queue, &req.consumer_id, &partition, &req.offsets, now)?;
+                return Ok(());
+            }
+        };
+        let letters = self.queues.reject(
+            &req.queue,
+            &req.consumer_id,
+            &partition,
+            &req.offsets,
+            &req.error,
+            now,
+        )?;
+        let target = TopicPartition::new(
+            dead_letter_topic,
+            partition.partition % dead_letter_partitions,
+        );
+        self.write_dead_letters(&req.queue, &partition, letters, &target, now)
+    }
+
+    /// Returns the number of messages of a topic waiting to be delivered to
+    /// the supplied queue, see [queue::Coordinator::backlog]. In a cluster only
+    /// the partitions this node leads are counted, as the progress of queues
+    /// through other partitions is kept by their leaders.
+    pub fn queue_backlog(&self, queue: &str, topic: &str) -> Result<u64, ResponseError> {
+        let topic = self.topics.get(topic)?;
+        let (policy, _) = self.queue_policy(&topic)?;
+        let now = record::current_timestamp();
+        let mut backlog = 0;
+        for source in topic.partitions() {
+            let partition = TopicPartition::new(topic.name(), source.id());
+            let end_offset = match self.queue_end_offset(&partition) {
+                Ok(end_offset) => end_offset,
+                Err(err) if err.code == ErrorCode::NotLeader => continue,
+                Err(err) => return Err(err),
+            };
+            backlog += self
+                .queues
+                .backlog(queue, &partition, &policy, end_offset, now)?;
+        }
+        Ok(backlog)
+    }
+
+    /// Returns the offset last committed by the supplied group for a partition.
+    pub fn committed_offset(&self, group: &str, topic: &str, partition: u32) -> Option<u64> {
+        self.offsets
+            .committed(group, &TopicPartition::new(topic, partition))
+    }
+
+    /// Serve a single request.
+    pub fn handle(&self, request: Request) -> Result<Response, ResponseError> {
+        match request {
+            Request::ApiVersions => Ok(Response::ApiVersions(ApiVersionsResponse::supported())),
+            Request::Metadata(req) => Ok(Response::Metadata(match &self.replication {
+                Some(node) => self.cluster_metadata(node, req),
+                None => self.metadata(req),
+            })),
+            Request::CreateTopic(req) => self.create_topic(req).map(|_| Response::CreateTopic),
+            Request::DeleteTopic(req) => {
+                self.delete_topic(&req.name).map(|_| Response::DeleteTopic)
+            }
+            Request::Produce(req) => self.produce(req).map(Response::Produce),
+            Request::Fetch(req) => self.fetch(req).map(Response::Fetch),
+            Request::CommitOffset(req) => {
+                self.commit_offsets(req)?;
+                Ok(Response::CommitOffset)
+            }
+            Request::Heartbeat => Ok(Response::Heartbeat),
+            Request::JoinGroup(req) => self.join_group(req).map(Response::JoinGroup),
+            Request::GroupHeartbeat(req) => {
+                self.check_coordinator(&req.group)?;
+                let membership = self.groups.heartbeat(&req.group, &req.member_id)?;
+                Ok(Response::GroupHeartbeat(assignment_response(membership)))
+            }
+            Request::LeaveGroup(req) => {
+                self.check_coordinator(&req.group)?;
+                self.groups.leave(&req.group, &req.member_id)?;
+                Ok(Response::LeaveGroup)
+            }
+            Request::FetchOffsets(req) => self.fetch_offsets(req).map(Response::FetchOffsets),
+            Request::ResetOffsets(req) => self.reset_offsets(req).map(Response::ResetOffsets),
+            Request::RaftVote(req) => Ok(Response::RaftVote(self.replication()?.handle_vote(req)?)),
+            Request::RaftAppend(req) => Ok(Response::RaftAppend(
+                self.replication()?.handle_append(req)?,
+            )),
+            Request::RaftSnapshot(req) => Ok(Response::RaftSnapshot(
+                self.replication()?.handle_snapshot(req)?,
+            )),
+            Request::MetadataWrite(req) => {
+                self.replication()?.handle_metadata_write(req)?;
+                Ok(Response::MetadataWrite)
+            }
+            Request::InitProducer(req) => self.init_producer(req).map(Response::InitProducer),
+            Request::AddPartitionsToTxn(req) => {
+                self.add_partitions_to_txn(req)?;
+                Ok(Response::AddPartitionsToTxn)
+            }
+            Request::EndTxn(req) => {
+                self.end_txn(req)?;
+                Ok(Response::EndTxn)
+            }
+            Request::WriteTxnMarkers(req) => {
+                for partition in &req.partitions {
+                    self.write_marker(partition, req.producer_id, req.epoch, req.marker)?;
+                }
+                Ok(Response::WriteTxnMarkers)
+            }
+            Request::Lease(req) => self.lease(req).map(Response::Lease),
+            Request::Ack(req) => {
+                check_queue(&req.queue, &req.consumer_id)?;
+                let partition = self.queue_partition(&req.topic, req.partition)?;
+                self.queues.ack(
+                    &req.queue,
+                    &req.consumer_id,
+                    &partition,
+                    &req.offsets,
+                    record::current_timestamp(),
+                )?;
+                Ok(Response::Ack)
+            }
+            Request::Nack(req) => {
+                self.nack(req)?;
+                Ok(Response::Nack)
+            }
+            Request::ReplayDeadLetters(req) => self
+                .replay_dead_letters(req)
+                .map(Response::ReplayDeadLetters),
+            Request::Schedule(req) => self.schedule(req).map(Response::Schedule),
+            Request::OffsetsForTimes(req) => {
+                Ok(Response::OffsetsForTimes(self.offsets_for_times(req)))
+            }
+            Request::DeclareExchange(req) => {
+                if self.exchanges.declare(&req.name, req.kind)? {
+                    info!(self.logger, "Declared exchange."; "exchange" => &req.name, "type" => req.kind.name());
+                }
+                Ok(Response::DeclareExchange)
+            }
+            Request::DeleteExchange(req) => {
+                self.exchanges.delete(&req.name)?;
+                info!(self.logger, "Deleted exchange."; "exchange" => &req.name);
+                Ok(Response::DeleteExchange)
+            }
+            Request::Bind(req) => self.bind(req).map(|_| Response::Bind),
+            Request::Unbind(req) => {
+                self.exchanges.unbind(&req.binding)?;
+                Ok(Response::Unbind)
+            }
+            Request::Publish(req) => self.publish(req).map(Response::Publish),
+            Request::CreateTemporaryQueue => Err(ResponseError::new(
+                ErrorCode::InvalidRequest,
+                "temporary queues can only be created through a connection's session",
+            )),
+            Request::FindCoordinator(req) => self
+                .find_coordinator(&req.group)
+                .map(Response::FindCoordinator),
+        }
+    }
+
+    fn init_producer(
+        &self,
+        req: InitProducerRequest,
+    ) -> Result<InitProducerResponse, ResponseError> {
+        let node_id = self.replication.as_ref().map_or(0, |node| node.id());
+        let transactional_id = match req.transactional_id {
+            Some(transactional_id) => transactional_id,
+            None => {
+                return Ok(InitProducerResponse {
+                    producer_id: self.producer_ids.allocate(node_id)?,
+                    epoch: 0,
+                })
+            }
+        };
+
+        let init = self.transactions.init(
+            &transactional_id,
+            req.transaction_timeout_ms as u64,
+            record::current_timestamp(),
+            || self.producer_ids.allocate(node_id),
+        )?;
+        if let Some(markers) = &init.aborted {
+            self.complete_txn(markers)?;
+        }
+        Ok(InitProducerResponse {
+            producer_id: init.producer_id,
+            epoch: init.epoch,
+        })
+    }
+
+    fn add_partitions_to_txn(&self, req: AddPartitionsToTxnRequest) -> Result<(), ResponseError> {
+        for partition in &req.partitions {
+            check_external(&partition.topic)?;
+            self.topics
+                .get(&partition.topic)?
+                .partition(partition.partition)?;
+        }
+        self.transactions.add_partitions(
+            &req.transactional_id,
+            req.producer_id,
+            req.epoch,
+            req.partitions,
+            record::current_timestamp(),
+        )?;
+        Ok(())
+    }
+
+    fn end_txn(&self, req: EndTxnRequest) -> Result<(), ResponseError> {
+        let markers = self.transactions.end(
+            &req.transactional_id,
+            req.producer_id,
+            req.epoch,
+            req.commit,
+            record::current_timestamp(),
+        )?;
+        self.complete_txn(&markers)?;
+        info!(self.logger, "Ended transaction."; "transactional_id" => &req.transactional_id, "commit" => req.commit, "partitions" => markers.partitions.len());
+        Ok(())
+    }
+
+    /// Write the supplied markers to every partition of their transaction, then
+    /// complete the transaction. In a cluster, markers for partitions led by
+    /// other nodes are forwarded to their leader.
+    fn complete_txn(&self, markers: &Markers) -> Result<(), ResponseError> {
+        let mut remote: BTreeMap<u32, Vec<TopicPartition>> = BTreeMap::new();
+        for partition in &markers.partitions {
+            match &self.replication {
+                Some(node) => match node.leader(partition) {
+                    Some(leader) if leader != node.id() => {
+                        remote.entry(leader).or_default().push(partition.clone())
+                    }
+                    _ => self.write_marker(
+                        partition,
+                        markers.producer_id,
+                        markers.epoch,
+                        markers.marker,
+                    )?,
+                },
+                None => self.write_marker(
+                    partition,
+                    markers.producer_id,
+                    markers.epoch,
+                    markers.marker,
+                )?,
+            }
+        }
+
+        if let Some(node) = &self.replication {
+            for (leader, partitions) in remote {
+                let req = WriteTxnMarkersRequest {
+                    producer_id: markers.producer_id,
+                    epoch: markers.epoch,
+                    marker: markers.marker,
+                    partitions,
+                };
+                forward(node, leader, |client| client.write_txn_markers(req))?;
+            }
+        }
+
+        self.transactions
+            .complete(markers, record::current_timestamp())?;
+        Ok(())
+    }
+
+    /// Write a marker to a partition this node leads, or that it holds when
+    /// replication is disabled.
+    fn write_marker(
+        &self,
+        partition: &TopicPartition,
+        producer_id: u64,
+        epoch: u16,
+        marker: ControlMarker,
+    ) -> Result<(), ResponseError> {
+        match &self.replication {
+            Some(node) => {
+                node.write_marker(partition, producer_id, epoch, marker)?;
+            }
+            None => {
+                self.topics
+                    .get(&partition.topic)?
+                    .partition(partition.partition)?
+                    .append_marker(producer_id, epoch, marker)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Lease messages from the requested partitions, or every partition this
+    /// node leads, starting from a different partition on each request so that
+    /// one busy partition does not starve the rest.
+    ///
+    /// Before leasing from a partition, messages the topic's queue policy gives
+    /// up on are moved to its dead-letter topic, see [Broker::dead_letter].
+    fn lease(&self, req: LeaseRequest) -> Result<LeaseResponse, ResponseError> {
+        check_external(&req.topic)?;
+        check_queue(&req.queue, &req.consumer_id)?;
+        let topic = self.topics.get(&req.topic)?;
+        let (policy, dead_letter) = self.queue_policy(&topic)?;
+        let partitions: Vec<u32> = if req.partitions.is_empty() {
+            topic
+                .partitions()
+                .iter()
+                .map(|partition| partition.id())
+                .filter(|id| match &self.replication {
+                    Some(node) => {
+                        node.leader(&TopicPartition::new(&req.topic, *id)) == Some(node.id())
+                    }
+                    None => true,
+                })
+                .collect()
+        } else {
+            req.partitions
+        };
+
+        let rotation = self.queue_rotation.fetch_add(1, Ordering::Relaxed);
+        let now = record::current_timestamp();
+        let mut messages = Vec::new();
+        for idx in 0..partitions.len() {
+            let remaining = (req.max_messages as usize).saturating_sub(messages.len());
+            if remaining == 0 {
+                break;
+            }
+            let id = partitions[(rotation + idx) % partitions.len()];
+            let partition = TopicPartition::new(&req.topic, id);
+            let end_offset = self.queue_end_offset(&partition)?;
+            if let Some(dead_letter) = &dead_letter {
+                self.dead_letter(&req.queue, &partition, &policy, dead_letter, now);
+            }
+            let leased = self.queues.lease(
+                &req.queue,
+                &req.consumer_id,
+                &partition,
+                remaining,
+                req.visibility_timeout_ms as u64,
+                &policy,
+                end_offset,
+                now,
+            )?;
+            messages.extend(leased.into_iter().map(|delivery| LeasedMessage {
+                partition: id,
+                delivery_count: delivery.delivery_count,
+                record: delivery.record,
+            }));
+        }
+        Ok(LeaseResponse { messages })
+    }
+
+    /// Release messages a queue consumer failed to process for redelivery.
+    /// Messages that have now been delivered as many times as the topic's
+    /// queue policy allows are moved to its dead-letter topic straight away.
+    fn nack(&self, req: NackRequest) -> Result<(), ResponseError> {
+        check_queue(&req.queue, &req.consumer_id)?;
+        let partition = self.queue_partition(&req.topic, req.partition)?;
+        let now = record::current_timestamp();
+        self.queues.nack(
+            &req.queue,
+            &req.consumer_id,
+            &partition,
+            &req.offsets,
+            &req.error,
+            now,
+        )?;
+        let topic = self.topics.get(&req.topic)?;
+        let (policy, dead_letter) = self.queue_policy(&topic)?;
+        if let Some(dead_letter) = &dead_letter {
+            if policy.max_deliveries > 0 {
+                self.dead_letter(&req.queue, &partition, &policy, dead_letter, now);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns how queues consuming a topic deliver its messages, including the
+    /// time to live of messages without a TTL header, along with the topic they
+    /// move dead letters to and its partition count, as configured on the topic
+    /// when it was created.
+    fn queue_policy(
+        &self,
+        topic: &Topic,
+    ) -> Result<(DeliveryPolicy, Option<(String, u32)>), ResponseError> {
+        let config = &topic.metadata().config;
+        let dead_letter = match config.dead_letter_topic() {
+            Some(name) => Some((
+                name.to_owned(),
+                self.dead_letter_partitions(topic.name(), name)?,
+            )),
+            None => None,
+        };
+        let policy = DeliveryPolicy {
+            max_deliveries: config.max_deliveries(),
+            message_ttl_ms: config.queue_message_ttl_ms(),
+            dead_letter: dead_letter.is_some(),
+        };
+        Ok((policy, dead_letter))
+    }
+
+    /// Move the messages of a queue's partition that the policy gives up on to
+    /// the supplied dead-letter topic. Failing to move them is only logged, as
+    /// they are retried on a later lease.
+    fn dead_letter(
+        &self,
+        queue: &str,
+        partition: &TopicPartition,
+        policy: &DeliveryPolicy,
+        (dead_letter_topic, dead_letter_partitions): &(String, u32),
+        now: i64,
+    ) {
+        let target = TopicPartition::new(
+            dead_letter_topic,
+            partition.partition % dead_letter_partitions,
+        );
+        if let Err(err) = self.move_dead_letters(queue, partition, policy, &target, now) {
+            warn!(self.logger, "Failed to move messages to the dead-letter topic, will retry."; "queue" => queue, "partition" => partition.to_string(), "dead_letter_topic" => dead_letter_topic, "error" => err.to_string());
+        }
+    }
+
+    /// Returns the number of partitions of the topic a queue moves dead letters
+    /// to, which must be another existing topic.
+    fn dead_letter_partitions(&self, topic: &str, name: &str) -> Result<u32, ResponseError> {
+        if name == topic {
+            return Err(ResponseError::new(
+                ErrorCode::InvalidConfig,
+                "queues must move dead letters to a topic other than the one they consume",
+            ));
+        }
+        check_external(name)?;
+        self.partition_count(name)
+    }
+
+    /// Returns the number of partitions of a topic. In a cluster the topic may
+    /// not be held by this node, so it is looked up in the cluster metadata.
+    fn partition_count(&self, name: &str) -> Result<u32, ResponseError> {
+        let partitions = match &self.replication {
+            Some(node) => node
+                .cluster()
+                .topic(name)
+                .map(|state| state.partitions.len()),
+            None => self
+                .topics
+                .get(name)
+                .ok()
+                .map(|topic| topic.partitions().len()),
+        };
+        match partitions {
+            Some(partitions) if partitions > 0 => Ok(partitions as u32),
+            _ => Err(topic::Error::NotFound {
+                name: name.to_owned(),
+            }
+            .into()),
+        }
+    }
+
+    /// Hold records back until their delivery time, after which they are
+    /// appended to the requested partition by [Broker::release_scheduled].
+    fn schedule(&self, req: ScheduleRequest) -> Result<ScheduleResponse, ResponseError> {
+        if req.records.is_empty() {
+            return Err(ResponseError::new(
+                ErrorCode::InvalidRequest,
+                "schedule requests must contain at least one record",
+            ));
+        }
+        check_external(&req.topic)?;
+        if req.partition >= self.partition_count(&req.topic)? {
+            return Err(topic::Error::PartitionNotFound {
+                topic: req.topic,
+                partition: req.partition,
+            }
+            .into());
+        }
+
+        let now = record::current_timestamp();
+        let messages = req
+            .records
+            .into_iter()
+            .map(|scheduled| {
+                let delayed = now.saturating_add(scheduled.delay_ms.min(i64::MAX as u64) as i64);
+                (scheduled.deliver_at_ms.max(delayed), scheduled.record)
+            })
+            .collect();
+        let partition = TopicPartition::new(req.topic, req.partition);
+        let messages = self
+            .scheduler
+            .schedule(&partition, messages, now)?
+            .into_iter()
+            .map(|scheduled| ScheduledMessage {
+                id: scheduled.id,
+                deliver_at_ms: scheduled.deliver_at_ms,
+            })
+            .collect();
+        Ok(ScheduleResponse { messages })
+    }
+
+    /// Bind an exchange to a destination topic, which must be an existing
+    /// topic not reserved for internal use.
+    fn bind(&self, req: BindRequest) -> Result<(), ResponseError> {
+        let binding = req.binding;
+        check_external(&binding.destination)?;
+        self.partition_count(&binding.destination)?;
+        if self.exchanges.bind(binding.clone())? {
+            info!(self.logger, "Bound exchange."; "exchange" => &binding.exchange, "topic" => &binding.destination, "routing_key" => &binding.routing_key);
+        }
+        Ok(())
+    }
+
+    /// Append a record to every destination topic of the exchange bindings it
+    /// matches, tagged with the exchange and routing key it was published
+    /// with. Destinations deleted since they were bound are skipped.
+    fn publish(&self, req: PublishRequest) -> Result<PublishResponse, ResponseError> {
+        let destinations =
+            self.exchanges
+                .route(&req.exchange, &req.routing_key, &req.record.headers)?;
+        let record = req
+            .record
+            .with_header(EXCHANGE_HEADER, req.exchange.as_str())
+            .with_header(ROUTING_KEY_HEADER, req.routing_key.as_str());
+
+        let mut records = Vec::with_capacity(destinations.len());
+        for topic in destinations {
+            let produced = match self.produce(ProduceRequest {
+                topic: topic.clone(),
+                partitioning: Partitioning::Key,
+                records: vec![record.clone()],
+                producer: None,
+                compression: Compression::None,
+            }) {
+                Ok(produced) => produced,
+                Err(err) if err.code == ErrorCode::TopicNotFound => {
+                    warn!(self.logger, "Skipped publishing to a topic that does not exist."; "exchange" => &req.exchange, "topic" => &topic);
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            records.extend(produced.records.into_iter().map(|produced| RoutedRecord {
+                topic: topic.clone(),
+                partition: produced.partition,
+                offset: produced.offset,
+            }));
+        }
+        Ok(PublishResponse { records })
+    }
+
+    /// Move the messages of a partition that have been delivered as many times
+    /// as the policy allows or have expired to the supplied dead-letter
+    /// partition. Every dead letter of a source partition goes to the same
+    /// dead-letter partition, so their order is kept.
+    fn move_dead_letters(
+        &self,
+        queue: &str,
+        partition: &TopicPartition,
+        policy: &DeliveryPolicy,
+        target: &TopicPartition,
+        now: i64,
+    ) -> Result<(), ResponseError> {
+        let letters = self.queues.dead_letters(queue, partition, policy, now)?;
+        self.write_dead_letters(queue, partition, letters, target, now)
+    }
+
+    /// Write dead letters claimed from a queue's partition to the supplied
+    /// dead-letter topic partition, then settle them.
+    fn write_dead_letters(
+        &self,
+        queue: &str,
+        partition: &TopicPartition,
+        letters: Vec<DeadLetter>,
+        target: &TopicPartition,
+        now: i64,
+    ) -> Result<(), ResponseError> {
+        if letters.is_empty() {
+            return Ok(());
+        }
+        self.produce_to(target, letters.iter().map(DeadLetter::to_record).collect())?;
+        let offsets: Vec<u64> = letters.iter().map(|letter| letter.record.offset).collect();
+        self.queues.dead_lettered(queue, partition, &offsets, now)?;
+        info!(self.logger, "Moved messages to the dead-letter topic."; "queue" => queue, "partition" => partition.to_string(), "dead_letter_topic" => &target.topic, "messages" => offsets.len());
+        Ok(())
+    }
+
+    /// Move messages of a dead-letter topic partition back to the partitions
+    /// they were originally written to, without the headers added when they
+    /// were dead lettered. Progress is kept by an internal queue, so each
+    /// message is replayed once, and messages that were not written by a queue
+    /// are skipped.
+    fn replay_dead_letters(
+        &self,
+        req: ReplayDeadLettersRequest,
+    ) -> Result<ReplayDeadLettersResponse, ResponseError> {
+        let partition = self.queue_partition(&req.topic, req.partition)?;
+        let end_offset = self.queue_end_offset(&partition)?;
+        let now = record::current_timestamp();
+        let timeout_ms = REPLAY_TIMEOUT_MS.min(self.queues.config().max_visibility_timeout_ms);
+        let leased = self.queues.lease(
+            REPLAY_QUEUE,
+            REPLAY_QUEUE,
+            &partition,
+            req.max_messages as usize,
+            timeout_ms,
+            &DeliveryPolicy::default(),
+            end_offset,
+            now,
+        )?;
+
+        let mut targets: BTreeMap<TopicPartition, Vec<Record>> = BTreeMap::new();
+        let mut replayed = 0;
+        for delivery in &leased {
+            if let Some((target, record)) = queue::replay_target(&delivery.record.record) {
+                targets.entry(target).or_default().push(record);
+                replayed += 1;
+            }
+        }
+        for (target, records) in targets {
+            self.produce_to(&target, records)?;
+        }
+        let offsets: Vec<u64> = leased
+            .iter()
+            .map(|delivery| delivery.record.offset)
+            .collect();
+        self.queues
+            .ack(REPLAY_QUEUE, REPLAY_QUEUE, &partition, &offsets, now)?;
+        if replayed < leased.len() {
+            warn!(self.logger, "Skipped replaying messages that were not dead lettered by a queue."; "partition" => partition.to_string(), "messages" => leased.len() - replayed);
+        }
+        info!(self.logger, "Replayed dead letters."; "partition" => partition.to_string(), "messages" => replayed);
+        Ok(ReplayDeadLettersResponse {
+            replayed: replayed as u32,
+        })
+    }
+
+    /// Append records to a single partition, forwarding them to its leader when
+    /// that is another node of the cluster. Partitions this node holds no
+    /// replica of are routed by the leader reported in the cluster metadata.
+    fn produce_to(
+        &self,
+        partition: &TopicPartition,
+        records: Vec<Record>,
+    ) -> Result<(), ResponseError> {
+        let partitioning = Partitioning::Explicit(partition.partition);
+        match &self.replication {
+            Some(node) => match node.leader(partition).or_else(|| {
+                node.cluster()
+                    .topic(&partition.topic)
+                    .and_then(|state| state.partitions.get(partition.partition as usize))
+                    .and_then(|state| state.leader)
+            }) {
+                Some(leader) if leader != node.id() => {
+                    forward(node, leader, |client| {
+                        client.produce(&partition.topic, partitioning, records)
+                    })?;
+                }
+                _ => {
+                    let topic = self.topics.get(&partition.topic)?;
+                    node.produce(&topic, partitioning, records, None, Compression::None)?;
+                }
+            },
+            None => {
+                let topic = self.topics.get(&partition.topic)?;
+                topic.produce_with(partitioning, records, |partition, records| {
+                    partition.append_batch(records, None)
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolve the partition an acknowledgement targets, which in a cluster
+    /// must be led by this node as that is where its queue progress is kept.
+    fn queue_partition(
+        &self,
+        topic: &str,
+        partition: u32,
+    ) -> Result<TopicPartition, ResponseError> {
+        check_external(topic)?;
+        let partition = TopicPartition::new(topic, partition);
+        self.queue_end_offset(&partition)?;
+        Ok(partition)
+    }
+
+    /// Returns the offset below which messages of a partition may be leased,
+    /// failing if this node does not lead the partition.
+    fn queue_end_offset(&self, partition: &TopicPartition) -> Result<u64, ResponseError> {
+        match &self.replication {
+            Some(node) => {
+                let leader = node.leader(partition);
+                if leader != Some(node.id()) {
+                    return Err(raft::Error::NotLeader {
+                        partition: partition.clone(),
+                        leader,
+                    }
+                    .into());
+                }
+                Ok(node.high_watermark(partition)?)
+            }
+            None => Ok(self
+                .topics
+                .get(&partition.topic)?
+                .partition(partition.partition)?
+                .next_offset()),
+        }
+    }
+
+    fn replication(&self) -> Result<&raft::Node, ResponseError> {
+        self.replication.as_deref().ok_or_else(|| {
+            ResponseError::new(
+                ErrorCode::InvalidRequest,
+                "replication is not enabled on this node",
+            )
+        })
+    }
+
+    fn metadata(&self, req: MetadataRequest) -> MetadataResponse {
+        let names = if req.topics.is_empty() {
+            self.topics
+                .list()
+                .iter()
+                .map(|topic| topic.name().to_owned())
+                .filter(|name| !topic::is_internal(name))
+                .collect()
+        } else {
+            req.topics
+        };
+
+        let topics = names
+            .into_iter()
+            .map(|name| match self.topics.get(&name) {
+                Ok(topic) => TopicMetadata {
+                    name,
+                    error_code: ErrorCode::None,
+                    partitions: topic
+                        .partitions()
+                        .iter()
+                        .map(|partition| PartitionMetadata {
+                            id: partition.id(),
+                            start_offset: partition.start_offset(),
+                            next_offset: partition.next_offset(),
+                            leader: None,
+                            replicas: Vec::new(),
+                            isr: Vec::new(),
+                        })
+                        .collect(),
+                },
+                Err(err) => TopicMetadata {
+                    name,
+                    error_code: topic_error_code(&err),
+                    partitions: Vec::new(),
+                },
+            })
+            .collect();
+        MetadataResponse {
+            nodes: Vec::new(),
+            topics,
+        }
+    }
+
+    /// Describe topics as recorded in the cluster metadata, with offsets taken
+    /// from this node's copy of each partition.
+    fn cluster_metadata(&self, node: &raft::Node, req: MetadataRequest) -> MetadataResponse {
+        let cluster = node.cluster();
+        let names = if req.topics.is_empty() {
+            cluster.topics().keys().cloned().collect()
+        } else {
+            req.topics
+        };
+
+        let topics = names
+            .into_iter()
+            .map(|name| match cluster.topic(&name) {
+                Some(state) => {
+                    let local = self.topics.get(&name).ok();
+                    let partitions = state
+                        .partitions
+                        .iter()
+                        .enumerate()
+                        .map(|(id, partition)| {
+                            let id = id as u32;
+                            let log = local.as_ref().and_then(|topic| topic.partition(id).ok());
+                            PartitionMetadata {
+                                id,
+                                start_offset: log.map(|log| log.start_offset()).unwrap_or(0),
+                                next_offset: log.map(|log| log.next_offset()).unwrap_or(0),
+                                leader: partition.leader,
+                                replicas: partition.replicas.clone(),
+                                isr: partition.isr.clone(),
+                            }
+                        })
+                        .collect();
+                    TopicMetadata {
+                        name,
+                        error_code: ErrorCode::None,
+                        partitions,
+                    }
+                }
+                None => TopicMetadata {
+                    name,
+                    error_code: ErrorCode::TopicNotFound,
+                    partitions: Vec::new(),
+                },
+            })
+            .collect();
+        let nodes = cluster
+            .nodes()
+            .iter()
+            .map(|(id, address)| NodeMetadata {
+                id: *id,
+                address: address.to_string(),
+            })
+            .collect();
+        MetadataResponse { nodes, topics }
+    }
+
+    fn create_topic(&self, req: CreateTopicRequest) -> Result<(), ResponseError> {
+        check_external(&req.name)?;
+        if req.name.starts_with(TEMPORARY_QUEUE_PREFIX) {
+            return Err(topic::Error::InvalidName {
+                name: req.name,
+                reason: "is reserved for temporary queues",
+            }
+            .into());
+        }
+        let mut config = TopicConfig::new();
+        for (key, value) in req.config {
+            config.set(key, value)?;
+        }
+        if config.dead_letter_topic() == Some(req.name.as_str()) {
+            return Err(topic::Error::InvalidConfig {
+                key: topic::QUEUE_DEAD_LETTER_TOPIC.to_owned(),
+                value: req.name,
+                reason: String::from("must name a topic other than the topic itself"),
+            }
+            .into());
+        }
+        self.create_unchecked(&req.name, req.partitions, config)
+    }
+
+    /// Create a topic without checking its name is one clients may use.
+    fn create_unchecked(
+        &self,
+        name: &str,
+        partitions: u32,
+        config: TopicConfig,
+    ) -> Result<(), ResponseError> {
+        match &self.replication {
+            Some(node) => node.create_topic(name, partitions, config)?,
+            None => self.topics.create(name, partitions, config)?,
+        };
+        info!(self.logger, "Created topic."; "topic" => name, "partitions" => partitions);
+        Ok(())
+    }
+
+    fn delete_topic(&self, name: &str) -> Result<(), ResponseError> {
+        check_external(name)?;
+        match &self.replication {
+            Some(node) => node.delete_topic(name)?,
+            None => self.topics.delete(name)?,
+        }
+        info!(self.logger, "Deleted topic."; "topic" => name);
+        let unbound = self.exchanges.unbind_destination(name)?;
+        if unbound > 0 {
+            info!(self.logger, "Removed bindings to deleted topic."; "topic" => name, "bindings" => unbound);
+        }
+        Ok(())
+    }
+
+    /// Create a single partition topic for a connection to receive replies on,
+    /// named after this node and the time so names are not reused across
+    /// nodes or restarts.
+    pub(super) fn create_temporary_queue(&self) -> Result<String, ResponseError> {
+        let node = self.replication.as_ref().map_or(0, |node| node.id());
+        let name = format!(
+            "{}{}.{:x}.{}",
+            TEMPORARY_QUEUE_PREFIX,
+            node,
+            record::current_timestamp(),
+            self.temporary_queues.fetch_add(1, Ordering::Relaxed)
+        );
+        self.create_unchecked(&name, 1, TopicConfig::new())?;
+        Ok(name)
+    }
+
+    /// Delete a temporary queue once the connection that created it closes.
+    pub(super) fn delete_temporary_queue(&self, name: &str) {
+        match self.delete_topic(name) {
+            Ok(()) => {}
+            Err(err) if err.code == ErrorCode::TopicNotFound => {}
+            Err(err) => {
+                warn!(self.logger, "Failed to delete temporary queue."; "topic" => name, "error" => err.to_string());
+            }
+        }
+    }
+
+    /// Delete every temporary queue held by this node, returning how many were
+    /// deleted. Temporary queues outlive their connection only if the server
+    /// stopped before it could delete them, so this should be called before
+    /// serving connections, and never in a cluster whose other nodes may hold
+    /// the temporary queues of their own connections.
+    pub fn delete_temporary_queues(&self) -> usize {
+        let mut deleted = 0;
+        for topic in self.topics.list() {
+            if !topic.name().starts_with(TEMPORARY_QUEUE_PREFIX) {
+                continue;
+            }
+            match self.delete_topic(topic.name()) {
+                Ok(()) => deleted += 1,
+                Err(err) => {
+                    warn!(self.logger, "Failed to delete temporary queue."; "topic" => topic.name(), "error" => err.to_string());
+                }
+            }
+        }
+        deleted
+    }
+
+    fn produce(&self, req: ProduceRequest) -> Result<ProduceResponse, ResponseError> {
+        if req.records.is_empty() {
+            return Err(ResponseError::new(
+                ErrorCode::InvalidRequest,
+                "produce requests must contain at least one record",
+            ));
+        }
+        check_external(&req.topic)?;
+        if req.producer.is_some() && !matches!(req.partitioning, Partitioning::Explicit(_)) {
+            return Err(ResponseError::new(
+                ErrorCode::InvalidRequest,
+                "idempotent produce requests must target an explicit partition",
+            ));
+        }
+        let topic = self.topics.get(&req.topic)?;
+        let produced = match &self.replication {
+            Some(node) => node.produce(
+                &topic,
+                req.partitioning,
+                req.records,
+                req.producer,
+                req.compression,
+            )?,
+            None => topic.produce_with(req.partitioning, req.records, |partition, records| {
+                partition.append_compressed(records, req.producer, req.compression)
+            })?,
+        };
+        let records = produced
+            .into_iter()
+            .map(|(partition, offset)| ProducedRecord { partition, offset })
+            .collect();
+        Ok(ProduceResponse { records })
+    }
+
+    fn fetch(&self, req: FetchRequest) -> Result<FetchResponse, ResponseError> {
+        let selector = match req.selector.as_str() {
+            "" => None,
+            source => Some(Selector::parse(source)?),
+        };
+        let partitions = req
+            .partitions
+            .into_iter()
+            .map(|fetch| {
+                self.fetch_partition(&fetch, req.isolation, selector.as_ref())
+                    .unwrap_or_else(|error_code| FetchedPartition {
+                        topic: fetch.topic,
+                        partition: fetch.partition,
+                        error_code,
+                        high_watermark: 0,
+                        last_stable_offset: 0,
+                        next_offset: fetch.offset,
+                        records: Vec::new(),
+                    })
+            })
+            .collect();
+        Ok(FetchResponse { partitions })
+    }
+
+    /// Read a single partition, returning its high watermark, last stable
+    /// offset, and the records visible at the supplied isolation level. Records
+    /// that have outlived their time to live or do not match the selector are
+    /// never returned. Expired records are skipped silently, they remain in the
+    /// log for other readers until retention removes them, so they are neither
+    /// dead lettered nor counted as expired. At most `max_bytes` worth of
+    /// batches are read, even if every record read is skipped, and the offset
+    /// following the last one read is returned so consumers move past skipped
+    /// records.
+    fn fetch_partition(
+        &self,
+        fetch: &FetchPartition,
+        isolation: IsolationLevel,
+        selector: Option<&Selector>,
+    ) -> Result<FetchedPartition, ErrorCode> {
+        let topic = self
+            .topics
+            .get(&fetch.topic)
+            .map_err(|e| topic_error_code(&e))?;
+        let partition = topic
+            .partition(fetch.partition)
+            .map_err(|e| topic_error_code(&e))?;
+        let high_watermark = self.high_watermark(partition)?;
+        let last_stable_offset = partition.last_stable_offset().min(high_watermark);
+        let mut records = partition
+            .read_isolated(fetch.offset, fetch.max_bytes as usize, isolation)
+            .map_err(|e| topic_error_code(&e))?;
+        records.retain(|record| record.offset < high_watermark);
+        let next_offset = records
+            .last()
+            .map_or(fetch.offset, |record| record.offset + 1);
+        if !topic::is_internal(&fetch.topic) {
+            let now = record::current_timestamp();
+            records.retain(|record| !topic.expired(record, None, now));
+        }
+        if let Some(selector) = selector {
+            records.retain(|record| selector.matches(&record.record));
+        }
+        Ok(FetchedPartition {
+            topic: fetch.topic.clone(),
+            partition: fetch.partition,
+            error_code: ErrorCode::None,
+            high_watermark,
+            last_stable_offset,
+            next_offset,
+            records,
+        })
+    }
+
+    /// Returns the offset below which a partition's records may be read, which
+    /// for replicated partitions is the offset committed by a quorum.
+    fn high_watermark(&self, partition: &Partition) -> Result<u64, ErrorCode> {
+        match &self.replication {
+            Some(node) if !topic::is_internal(partition.topic()) => node
+                .high_watermark(&TopicPartition::new(partition.topic(), partition.id()))
+                .map_err(|e| raft_error_code(&e)),
+            _ => Ok(partition.next_offset()),
+        }
+    }
+
+    fn offsets_for_times(&self, req: OffsetsForTimesRequest) -> OffsetsForTimesResponse {
+        let partitions = req
+            .partitions
+            .into_iter()
+            .map(|lookup| {
+                let (error_code, offset) = match self.offset_for_timestamp(&lookup) {
+                    Ok(offset) => (ErrorCode::None, offset),
+                    Err(code) => (code, 0),
+                };
+                TimestampOffset {
+                    topic: lookup.topic,
+                    partition: lookup.partition,
+                    error_code,
+                    offset,
+                }
+            })
+            .collect();
+        OffsetsForTimesResponse { partitions }
+    }
+
+    /// Look up the earliest offset of a single partition with a timestamp at or
+    /// after the requested time, which is never beyond its high watermark.
+    fn offset_for_timestamp(&self, lookup: &PartitionTimestamp) -> Result<u64, ErrorCode> {
+        let topic = self
+            .topics
+            .get(&lookup.topic)
+            .map_err(|e| topic_error_code(&e))?;
+        let partition = topic
+            .partition(lookup.partition)
+            .map_err(|e| topic_error_code(&e))?;
+        let high_watermark = self.high_watermark(partition)?;
+        let offset = partition
+            .offset_for_timestamp(lookup.timestamp)
+            .map_err(|e| topic_error_code(&e))?;
+        Ok(offset.min(high_watermark))
+    }
+
+    /// Commit a group's offsets, forwarding them to the group's coordinator
+    /// when that is another node of the cluster.
+    fn commit_offsets(&self, req: CommitOffsetRequest) -> Result<(), ResponseError> {
+        if let Some((node, coordinator)) = self.remote_coordinator(&req.group) {
+            return forward(node, coordinator, |client| {
+                client.commit_offsets(&req.group, req.offsets)
+            });
+        }
+        let offsets = req
+            .offsets
+            .into_iter()
+            .map(|offset| {
+                let partition = TopicPartition::new(offset.topic, offset.partition);
+                (partition, offset.offset)
+            })
+            .collect();
+        self.offsets.commit(&req.group, offsets)?;
+        Ok(())
+    }
+
+    fn fetch_offsets(&self, req: FetchOffsetsRequest) -> Result<OffsetsResponse, ResponseError> {
+        if let Some((node, coordinator)) = self.remote_coordinator(&req.group) {
+            let offsets = forward(node, coordinator, |client| {
+                client.fetch_offsets(&req.group, req.partitions)
+            })?;
+            return Ok(OffsetsResponse { offsets });
+        }
+        let offsets = self.offsets.fetch(&req.group, &req.partitions);
+        Ok(offsets_response(offsets))
+    }
+
+    fn reset_offsets(&self, req: ResetOffsetsRequest) -> Result<OffsetsResponse, ResponseError> {
+        if let Some((node, coordinator)) = self.remote_coordinator(&req.group) {
+            let offsets = forward(node, coordinator, |client| {
+                client.reset_offsets(&req.group, req.partitions, req.reset)
+            })?;
+            return Ok(OffsetsResponse { offsets });
+        }
+        let offsets = self.offsets.reset(&req.group, &req.partitions, req.reset)?;
+        info!(self.logger, "Reset committed offsets."; "group" => &req.group, "partitions" => offsets.len());
+        Ok(offsets_response(offsets))
+    }
+
+    /// Returns the node coordinating the supplied consumer group, or none when
+    /// this node is not part of a cluster.
+    fn find_coordinator(&self, group: &str) -> Result<CoordinatorResponse, ResponseError> {
+        let node = match &self.replication {
+            Some(node) => node,
+            None => return Ok(CoordinatorResponse { coordinator: None }),
+        };
+        let id = node.coordinator(group);
+        let address = node.cluster().nodes().get(&id).copied().ok_or_else(|| {
+            ResponseError::new(
+                ErrorCode::NotCoordinator,
+                format!("the address of coordinator node {} is not known", id),
+            )
+        })?;
+        Ok(CoordinatorResponse {
+            coordinator: Some(NodeMetadata {
+                id,
+                address: address.to_string(),
+            }),
+        })
+    }
+
+    /// Reject group membership requests for groups coordinated by another node
+    /// of the cluster, as only the coordinator tracks a group's members.
+    fn check_coordinator(&self, group: &str) -> Result<(), ResponseError> {
+        match self.remote_coordinator(group) {
+            Some((_, coordinator)) => Err(ResponseError::new(
+                ErrorCode::NotCoordinator,
+                format!("group '{}' is coordinated by node {}", group, coordinator),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the node coordinating the supplied consumer group when that is
+    /// another node of the cluster. Committed offsets are only kept by the
+    /// group's coordinator, so every node reads and writes the same offsets.
+    fn remote_coordinator(&self, group: &str) -> Option<(&raft::Node, u32)> {
+        let node = self.replication.as_deref()?;
+        let coordinator = node.coordinator(group);
+        (coordinator != node.id()).then_some((node, coordinator))
+    }
+
+    fn join_group(&self, req: JoinGroupRequest) -> Result<GroupAssignmentResponse, ResponseError> {
+        self.check_coordinator(&req.group)?;
+        let member_id = Some(req.member_id.as_str()).filter(|id| !id.is_empty());
+        let membership = self.groups.join(
+            &req.group,
+            member_id,
+            req.session_timeout_ms as u64,
+            req.topics,
+            req.strategies,
+        )?;
+        Ok(assignment_response(membership))
+    }
+}
+
+/// Send a request to another node of the cluster, reporting a failure to reach
+/// it as the node not leading what the request targets.
+fn forward<T>(
+    node: &raft::Node,
+    leader: u32,
+    call: impl FnOnce(&mut Client) -> client::Result<T>,
+) -> Result<T, ResponseError> {
+    let address = node
+        .cluster()
+        .nodes()
+        .get(&leader)
+        .copied()
+        .ok_or_else(|| {
+            ResponseError::new(
+                ErrorCode::NotLeader,
+                format!("the address of node {} is not known", leader),
+            )
+        })?;
+    Client::connect_timeout(&address, node.config().replication_timeout())
+        .and_then(|mut client| call(&mut client))
+        .map_err(|err| match err {
+            client::Error::Response(err) => err,
+            err => ResponseError::new(
+                ErrorCode::NotLeader,
+                format!("failed to forward request to node {}: {}", leader, err),
+            ),
+        })
+}
+
+/// Reject queue names and consumer ids reserved for internal use.
+fn check_queue(queue: &str, consumer: &str) -> Result<(), queue::Error> {
+    const RESERVED: &str = "is reserved for internal use";
+    if queue.starts_with("__") {
+        return Err(queue::Error::InvalidQueue {
+            queue: queue.to_owned(),
+            reason: RESERVED,
+        });
+    }
+    if consumer.starts_with("__") {
+        return Err(queue::Error::InvalidConsumer {
+            consumer: consumer.to_owned(),
+            reason: RESERVED,
+        });
+    }
+    Ok(())
+}
+
+fn check_external(name: &str) -> Result<(), topic::Error> {
+    if topic::is_internal(name) {
+        return Err(topic::Error::InvalidName {
+            name: name.to_owned(),
+            reason: "is reserved for internal use",
+        });
+    }
+    Ok(())
+}
+
+fn offsets_response(offsets: Vec<(TopicPartition, u64)>) -> OffsetsResponse {
+    OffsetsResponse {
+        offsets: offsets
+            .into_iter()
+            .map(|(partition, offset)| PartitionOffset {
+                topic: partition.topic,
+                partition: partition.partition,
+                offset,
+            })
+            .collect(),
+    }
+}
+
+fn assignment_response(membership: Membership) -> GroupAssignmentResponse {
+    GroupAssignmentResponse {
+        member_id: membership.member_id,
+        generation: membership.generation,
+        strategy: membership.strategy,
+        assignment: membership.assignment,
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+    use crate::exchange::{Binding, ExchangeType};
+    use crate::offset::OffsetReset;
+    use crate::protocol::{
+        AckRequest, CommitOffsetRequest, DeclareExchangeRequest, DeleteExchangeRequest,
+        DeleteTopicRequest, FetchOffsetsRequest, GroupMemberRequest, ResetOffsetsRequest,
+        ScheduledRecord, VoteRequest,
+    };
+    use crate::record::{ProducerBatch, Record};
+    use crate::storage::LogConfig;
+
+    fn broker(dir: &std::path::Path) -> Broker {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        Broker::open(
+            logger,
+            Arc::new(topic::Manager::open(dir, LogConfig::default()).unwrap()),
+            group::Config::default(),
+            producer::Config::default(),
+            queue::Config::default(),
+            schedule::Config::default(),
+        )
+        .unwrap()
+    }
+
+    fn create(broker: &Broker, name: &str, partitions: u32) {
+        let req = CreateTopicRequest {
+            name: name.to_owned(),
+            partitions,
+            config: Vec::new(),
+        };
+        assert_eq!(
+            Ok(Response::CreateTopic),
+            broker.handle(Request::CreateTopic(req))
+        );
+    }
+
+    #[test]
+    fn test_produce_fetch() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = broker(dir.path());
+        create(&broker, "events", 2);
+
+        let resp = broker
+            .handle(Request::Produce(ProduceRequest {
+                topic: String::from("events"),
+                partitioning: Partitioning::Explicit(1),
+                records: vec![
+                    Record::new("a"),
+                    Record::new("b")
+                        .with_key("key")
+                        .with_header("trace", "abc")
+                        .with_timestamp(42),
+                ],
+                producer: None,
+                compression: Compression::Zstd,
+            }))
+            .unwrap();
+        assert_eq!(
+            Response::Produce(ProduceResponse {
+                records: vec![
+                    ProducedRecord {
+                        partition: 1,
+                        offset: 0
+                    },
+                    ProducedRecord {
+                        partition: 1,
+                        offset: 1
+                    }
+                ]
+            }),
+            resp
+        );
+
+        let resp = broker.handle(Request::Fetch(FetchRequest {
+            partitions: vec![
+                FetchPartition {
+                    topic: String::from("events"),
+                    partition: 1,
+                    offset: 1,
+                    max_bytes: 1024,
+                },
+                FetchPartition {
+                    topic: String::from("missing"),
+                    partition: 0,
+                    offset: 0,
+                    max_bytes: 1024,
+                },
+            ],
+            isolation: IsolationLevel::ReadUncommitted,
+            selector: String::new(),
+        }));
+        let partitions = match resp {
+            Ok(Response::Fetch(resp)) => resp.partitions,
+            _ => unimplemented!(),
+        };
+        assert_eq!(ErrorCode::None, partitions[0].error_code);
+        assert_eq!(2, partitions[0].high_watermark);
+        assert_eq!(1, partitions[0].records.len());
+        let fetched = &partitions[0].records[0];
+        assert_eq!(Some(b"b".to_vec()), fetched.record.value);
+        assert_eq!(Some(b"key".to_vec()), fetched.record.key);
+        assert_eq!(Some(&b"abc"[..]), fetched.record.header("trace"));
+        assert_eq!(Some(42), fetched.record.timestamp);
+        assert!(fetched.timestamp > 42);
+        assert_eq!(ErrorCode::TopicNotFound, partitions[1].error_code);
+    }
+
+    #[test]
+    fn test_fetch_selector() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = broker(dir.path());
+        create(&broker, "events", 1);
+
+        let records = (0..6)
+            .map(|idx| {
+                let region = if idx % 2 == 0 { "eu" } else { "us" };
+                Record::new(idx.to_string())
+                    .with_header("region", region)
+                    .with_header("priority", idx.to_string())
+            })
+            .collect();
+        broker
+            .handle(Request::Produce(ProduceRequest {
+                topic: String::from("events"),
+                partitioning: Partitioning::Explicit(0),
+                records,
+                producer: None,
+                compression: Compression::None,
+            }))
+            .unwrap();
+
+        let fetch = |offset, selector: &str| {
+            broker
+                .handle(Request::Fetch(FetchRequest {
+                    partitions: vec![FetchPartition {
+                        topic: String::from("events"),
+                        partition: 0,
+                        offset,
+                        max_bytes: 1024 * 1024,
+                    }],
+                    isolation: IsolationLevel::ReadUncommitted,
+                    selector: selector.to_owned(),
+                }))
+                .map(|resp| match resp {
+                    Response::Fetch(resp) => (
+                        resp.partitions[0]
+                            .records
+                            .iter()
+                            .map(|record| record.offset)
+                            .collect::<Vec<_>>(),
+                        resp.partitions[0].next_offset,
+                    ),
+                    other => panic!("unexpected response {:?}", other),
+                })
+        };
+
+        assert_eq!(Ok((vec![0, 1, 2, 3, 4, 5], 6)), fetch(0, ""));
+        assert_eq!(
+            Ok((vec![2, 4], 6)),
+            fetch(0, "region = 'eu' AND priority > 1")
+        );
+        assert_eq!(Ok((vec![4], 6)), fetch(3, "region = 'eu'"));
+        // Records that do not match are still read past.
+        assert_eq!(Ok((vec![], 6)), fetch(0, "region = 'ap'"));
+
+        let err = fetch(0, "region = 'eu' AND").unwrap_err();
+        assert_eq!(ErrorCode::InvalidSelector, err.code);
+        assert_eq!(
+            "expected a value at column 18, found the end of the selector",
+            err.message
+        );
+    }
+
+    #[test]
+    fn test_fetch_selector_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = broker(dir.path());
+        create(&broker, "events", 1);
+        for idx in 0..10 {
+            broker
+                .handle(Request::Produce(ProduceRequest {
+                    topic: String::from("events"),
+                    partitioning: Partitioning::Explicit(0),
+                    records: vec![Record::new(idx.to_string()).with_header("region", "us")],
+                    producer: None,
+                    compression: Compression::None,
+                }))
+                .unwrap();
+        }
+
+        // A fetch skipping every record reads no more than its limit, and says
+        // where the next fetch should continue from.
+        let mut offset = 0;
+        let mut fetches = 0;
+        while offset < 10 {
+            let resp = broker.handle(Request::Fetch(FetchRequest {
+                partitions: vec![FetchPartition {
+                    topic: String::from("events"),
+                    partition: 0,
+                    offset,
+                    max_bytes: 1,
+                }],
+                isolation: IsolationLevel::ReadUncommitted,
+                selector: String::from("region = 'eu'"),
+            }));
+            let fetched = match resp {
+                Ok(Response::Fetch(mut resp)) => resp.partitions.remove(0),
+                other => panic!("unexpected response {:?}", other),
+            };
+            assert!(fetched.records.is_empty());
+            assert_eq!(offset + 1, fetched.next_offset);
+            offset = fetched.next_offset;
+            fetches += 1;
+        }
+        assert_eq!(10, fetches);
+    }
+
+    #[test]
+    fn test_topic_lifecycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = broker(dir.path());
+        create(&broker, "events", 2);
+
+        let err = broker
+            .handle(Request::CreateTopic(CreateTopicRequest {
+                name: String::from("events"),
+                partitions: 1,
+                config: Vec::new(),
+            }))
+            .unwrap_err();
+        assert_eq!(ErrorCode::TopicAlreadyExists, err.code);
+
+        let err = broker
+            .handle(Request::CreateTopic(CreateTopicRequest {
+                name: String::from("other"),
+                partitions: 1,
+                config: vec![(String::from("nope"), String::from("1"))],
+            }))
+            .unwrap_err();
+        assert_eq!(ErrorCode::InvalidConfig, err.code);
+
+        let resp = broker.handle(Request::Metadata(MetadataRequest::default()));
+        let topics = match resp {
+            Ok(Response::Metadata(resp)) => resp.topics,
+            _ => unimplemented!(),
+        };
+        assert_eq!(1, topics.len());
+        assert_eq!(2, topics[0].partitions.len());
+
+        let req = DeleteTopicRequest {
+            name: String::from("events"),
+        };
+        assert_eq!(
+            Ok(Response::DeleteTopic),
+            broker.handle(Request::DeleteTopic(req.clone()))
+        );
+        let err = broker.handle(Request::DeleteTopic(req)).unwrap_err();
+        assert_eq!(ErrorCode::TopicNotFound, err.code);
+    }
+
+    #[test]
+    fn test_commit_offset() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = broker(dir.path());
+        create(&broker, "events", 1);
+
+        let offset = |partition| PartitionOffset {
+            topic: String::from("events"),
+            partition,
+            offset: 5,
+        };
+        let resp = broker.handle(Request::CommitOffset(CommitOffsetRequest {
+            group: String::from("group"),
+            offsets: vec![offset(0)],
+        }));
+        assert_eq!(Ok(Response::CommitOffset), resp);
+        assert_eq!(Some(5), broker.committed_offset("group", "events", 0));
+
+        let err = broker
+            .handle(Request::CommitOffset(CommitOffsetRequest {
+                group: String::from("other"),
+                offsets: vec![offset(0), offset(1)],
+            }))
+            .unwrap_err();
+        assert_eq!(ErrorCode::PartitionNotFound, err.code);
+        assert_eq!(None, broker.committed_offset("other", "events", 0));
+
+        drop(broker);
+        let broker = self::broker(dir.path());
+        let resp = broker.handle(Request::FetchOffsets(FetchOffsetsRequest {
+            group: String::from("group"),
+            partitions: Vec::new(),
+        }));
+        assert_eq!(
+            Ok(Response::FetchOffsets(OffsetsResponse {
+                offsets: vec![offset(0)]
+            })),
+            resp
+        );
+
+        let resp = broker.handle(Request::ResetOffsets(ResetOffsetsRequest {
+            group: String::from("group"),
+            partitions: Vec::new(),
+            reset: OffsetReset::Earliest,
+        }));
+        assert_eq!(
+            Ok(Response::ResetOffsets(OffsetsResponse {
+                offsets: vec![PartitionOffset {
+                    offset: 0,
+                    ..offset(0)
+                }]
+            })),
+            resp
+        );
+        assert_eq!(Some(0), broker.committed_offset("group", "events", 0));
+    }
+
+    #[test]
+    fn test_offsets_for_times() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = broker(dir.path());
+        let req = CreateTopicRequest {
+            name: String::from("events"),
+            partitions: 1,
+            config: vec![(
+                String::from(topic::MESSAGE_TIMESTAMP_TYPE),
+                String::from("CreateTime"),
+            )],
+        };
+        broker.handle(Request::CreateTopic(req)).unwrap();
+        for timestamp in [100, 300, 200] {
+            broker
+                .handle(Request::Produce(ProduceRequest {
+                    topic: String::from("events"),
+                    partitioning: Partitioning::Explicit(0),
+                    records: vec![Record::new("a").with_timestamp(timestamp)],
+                    producer: None,
+                    compression: Compression::None,
+                }))
+                .unwrap();
+        }
+
+        let lookup = |topic: &str, timestamp| PartitionTimestamp {
+            topic: topic.to_owned(),
+            partition: 0,
+            timestamp,
+        };
+        let resp = broker.handle(Request::OffsetsForTimes(OffsetsForTimesRequest {
+            partitions: vec![
+                lookup("events", 50),
+                lookup("events", 250),
+                lookup("events", 400),
+                lookup("missing", 0),
+            ],
+        }));
+        let partitions = match resp {
+            Ok(Response::OffsetsForTimes(resp)) => resp.partitions,
+            _ => unimplemented!(),
+        };
+        let found: Vec<_> = partitions
+            .iter()
+            .map(|partition| (partition.error_code, partition.offset))
+            .collect();
+        assert_eq!(
+            vec![
+                (ErrorCode::None, 0),
+                (ErrorCode::None, 1),
+                (ErrorCode::None, 3),
+                (ErrorCode::TopicNotFound, 0)
+            ],
+            found
+        );
+
+        // Groups reset to a timestamp through the same lookup.
+        broker
+            .handle(Request::ResetOffsets(ResetOffsetsRequest {
+                group: String::from("group"),
+                partitions: vec![TopicPartition::new("events", 0)],
+                reset: OffsetReset::Timestamp(150),
+            }))
+            .unwrap();
+        assert_eq!(Some(1), broker.committed_offset("group", "events", 0));
+    }
+
+    #[test]
+    fn test_internal_topics() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = broker(dir.path());
+
+        let err = broker
+            .handle(Request::DeleteTopic(DeleteTopicRequest {
+                name: String::from(offset::OFFSETS_TOPIC),
+            }))
+            .unwrap_err();
+        assert_eq!(ErrorCode::InvalidTopic, err.code);
+
+        let err = broker
+            .handle(Request::Produce(ProduceRequest {
+                topic: String::from(offset::OFFSETS_TOPIC),
+                partitioning: Partitioning::Key,
+                records: vec![Record::new("a")],
+                producer: None,
+                compression: Compression::None,
+            }))
+            .unwrap_err();
+        assert_eq!(ErrorCode::InvalidTopic, err.code);
+
+        let resp = broker.handle(Request::Metadata(MetadataRequest::default()));
+        assert_eq!(
+            Ok(Response::Metadata(MetadataResponse {
+                nodes: Vec::new(),
+                topics: Vec::new()
+            })),
+            resp
+        );
+    }
+
+    #[test]
+    fn test_empty_produce() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = broker(dir.path());
+        create(&broker, "events", 1);
+
+        let err = broker
+            .handle(Request::Produce(ProduceRequest {
+                topic: String::from("events"),
+                partitioning: Partitioning::Key,
+                records: Vec::new(),
+                producer: None,
+                compression: Compression::None,
+            }))
+            .unwrap_err();
+        assert_eq!(ErrorCode::InvalidRequest, err.code);
+    }
+
+    #[test]
+    fn test_idempotent_produce() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = broker(dir.path());
+        create(&broker, "events", 2);
+
+        let producer = match broker
+            .handle(Request::InitProducer(InitProducerRequest {
+                transactional_id: None,
+                transaction_timeout_ms: 0,
+            }))
+            .unwrap()
+        {
+            Response::InitProducer(resp) => resp,
+            other => panic!("unexpected response {:?}", other),
+        };
+        assert_eq!(0, producer.epoch);
+        match broker
+            .handle(Request::InitProducer(InitProducerRequest {
+                transactional_id: None,
+                transaction_timeout_ms: 0,
+            }))
+            .unwrap()
+        {
+            Response::InitProducer(resp) => assert_ne!(producer.producer_id, resp.producer_id),
+            other => panic!("unexpected response {:?}", other),
+        }
+
+        let produce = |partitioning, sequence| {
+            broker.handle(Request::Produce(ProduceRequest {
+                topic: String::from("events"),
+                partitioning,
+                records: vec![Record::new("a"), Record::new("b")],
+                producer: Some(ProducerBatch {
+                    producer_id: producer.producer_id,
+                    epoch: producer.epoch,
+                    sequence,
+                    transactional: false,
+                }),
+                compression: Compression::None,
+            }))
+        };
+        let first = produce(Partitioning::Explicit(1), 0).unwrap();
+        assert_eq!(first, produce(Partitioning::Explicit(1), 0).unwrap());
+        let second = match produce(Partitioning::Explicit(1), 2).unwrap() {
+            Response::Produce(resp) => resp.records,
+            other => panic!("unexpected response {:?}", other),
+        };
+        assert_eq!(2, second[0].offset);
+        assert_eq!(
+            4,
+            broker.topics().get("events").unwrap().partitions()[1].next_offset()
+        );
+
+        // Sequences are tracked per partition.
+        assert!(produce(Partitioning::Explicit(0), 0).is_ok());
+        let err = produce(Partitioning::Explicit(1), 0).unwrap_err();
+        assert_eq!(ErrorCode::DuplicateSequence, err.code);
+        let err = produce(Partitioning::Explicit(1), 7).unwrap_err();
+        assert_eq!(ErrorCode::OutOfOrderSequence, err.code);
+        let err = produce(Partitioning::RoundRobin, 4).unwrap_err();
+        assert_eq!(ErrorCode::InvalidRequest, err.code);
+    }
+
+    #[test]
+    fn test_transactions() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = broker(dir.path());
+        create(&broker, "events", 2);
+
+        let init = |transaction_timeout_ms| {
+            broker.handle(Request::InitProducer(InitProducerRequest {
+                transactional_id: Some(String::from("txn")),
+                transaction_timeout_ms,
+            }))
+        };
+        let producer = match init(60000).unwrap() {
+            Response::InitProducer(resp) => resp,
+            other => panic!("unexpected response {:?}", other),
+        };
+        let err = init(0).unwrap_err();
+        assert_eq!(ErrorCode::InvalidTransactionTimeout, err.code);
+
+        let add = |epoch| {
+            broker.handle(Request::AddPartitionsToTxn(AddPartitionsToTxnRequest {
+                transactional_id: String::from("txn"),
+                producer_id: producer.producer_id,
+                epoch,
+                partitions: vec![
+                    TopicPartition::new("events", 0),
+                    TopicPartition::new("events", 1),
+                ],
+            }))
+        };
+        let produce = |partition, epoch, sequence| {
+            broker.handle(Request::Produce(ProduceRequest {
+                topic: String::from("events"),
+                partitioning: Partitioning::Explicit(partition),
+                records: vec![Record::new("a")],
+                producer: Some(ProducerBatch {
+                    producer_id: producer.producer_id,
+                    epoch,
+                    sequence,
+                    transactional: true,
+                }),
+                compression: Compression::None,
+            }))
+        };
+        let end = |epoch, commit| {
+            broker.handle(Request::EndTxn(EndTxnRequest {
+                transactional_id: String::from("txn"),
+                producer_id: producer.producer_id,
+                epoch,
+                commit,
+            }))
+        };
+        let fetch = |isolation| {
+            let resp = broker.handle(Request::Fetch(FetchRequest {
+                partitions: vec![FetchPartition {
+                    topic: String::from("events"),
+                    partition: 0,
+                    offset: 0,
+                    max_bytes: 1024,
+                }],
+                isolation,
+                selector: String::new(),
+            }));
+            match resp {
+                Ok(Response::Fetch(mut resp)) => resp.partitions.remove(0),
+                other => panic!("unexpected response {:?}", other),
+            }
+        };
+
+        assert_eq!(Ok(Response::AddPartitionsToTxn), add(0));
+        produce(0, 0, 0).unwrap();
+        produce(1, 0, 0).unwrap();
+        let fetched = fetch(IsolationLevel::ReadCommitted);
+        assert!(fetched.records.is_empty());
+        assert_eq!((1, 0), (fetched.high_watermark, fetched.last_stable_offset));
+        assert_eq!(1, fetch(IsolationLevel::ReadUncommitted).records.len());
+
+        assert_eq!(Ok(Response::EndTxn), end(0, true));
+        let fetched = fetch(IsolationLevel::ReadCommitted);
+        assert_eq!(1, fetched.records.len());
+        assert_eq!(2, fetched.last_stable_offset);
+
+        // Aborted records stay hidden from read committed fetches.
+        add(0).unwrap();
+        produce(0, 0, 1).unwrap();
+        assert_eq!(Ok(Response::EndTxn), end(0, false));
+        let fetched = fetch(IsolationLevel::ReadCommitted);
+        assert_eq!(1, fetched.records.len());
+        assert_eq!(4, fetched.last_stable_offset);
+        assert_eq!(2, fetch(IsolationLevel::ReadUncommitted).records.len());
+
+        // Reinitializing the producer aborts its ongoing transaction and fences
+        // off the earlier instance.
+        add(0).unwrap();
+        produce(0, 0, 2).unwrap();
+        match init(60000).unwrap() {
+            Response::InitProducer(resp) => {
+                assert_eq!((producer.producer_id, 1), (resp.producer_id, resp.epoch))
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+        assert_eq!(
+            ErrorCode::ProducerFenced,
+            produce(0, 0, 3).unwrap_err().code
+        );
+        assert_eq!(ErrorCode::ProducerFenced, end(0, true).unwrap_err().code);
+        assert_eq!(6, fetch(IsolationLevel::ReadCommitted).last_stable_offset);
+
+        // Transactions that outlive their timeout are aborted.
+        add(1).unwrap();
+        produce(0, 1, 0).unwrap();
+        assert_eq!(6, fetch(IsolationLevel::ReadCommitted).last_stable_offset);
+        broker.expire_transactions(record::current_timestamp() + 60000);
+        assert_eq!(ErrorCode::ProducerFenced, end(1, true).unwrap_err().code);
+        let fetched = fetch(IsolationLevel::ReadCommitted);
+        assert_eq!(1, fetched.records.len());
+        assert_eq!(8, fetched.last_stable_offset);
+    }
+
+    #[test]
+    fn test_queue() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = broker(dir.path());
+        create(&broker, "events", 2);
+        for partition in 0..2 {
+            broker
+                .handle(Request::Produce(ProduceRequest {
+                    topic: String::from("events"),
+                    partitioning: Partitioning::Explicit(partition),
+                    records: vec![Record::new("a"), Record::new("b")],
+                    producer: None,
+                    compression: Compression::None,
+                }))
+                .unwrap();
+        }
+
+        let lease = |consumer: &str, max_messages, visibility_timeout_ms| {
+            broker
+                .handle(Request::Lease(LeaseRequest {
+                    queue: String::from("jobs"),
+                    consumer_id: consumer.to_owned(),
+                    topic: String::from("events"),
+                    partitions: Vec::new(),
+                    max_messages,
+                    visibility_timeout_ms,
+                }))
+                .map(|resp| match resp {
+                    Response::Lease(resp) => resp
+                        .messages
+                        .into_iter()
+                        .map(|msg| (msg.partition, msg.record.offset, msg.delivery_count))
+                        .collect::<Vec<_>>(),
+                    other => panic!("unexpected response {:?}", other),
+                })
+        };
+        let ack = |consumer: &str, partition, offsets: Vec<u64>| AckRequest {
+            queue: String::from("jobs"),
+            consumer_id: consumer.to_owned(),
+            topic: String::from("events"),
+            partition,
+            offsets,
+        };
+
+        let first = lease("one", 1, 60000).unwrap();
+        assert_eq!(1, first.len());
+        let mut leased = lease("two", 5, 60000).unwrap();
+        assert_eq!(3, leased.len());
+        leased.extend(first.iter().copied());
+        leased.sort_unstable();
+        assert_eq!(vec![(0, 0, 1), (0, 1, 1), (1, 0, 1), (1, 1, 1)], leased);
+        assert!(lease("two", 5, 60000).unwrap().is_empty());
+
+        let (partition, offset, _) = first[0];
+        let err = broker
+            .handle(Request::Ack(ack("two", partition, vec![offset])))
+            .unwrap_err();
+        assert_eq!(ErrorCode::NotLeased, err.code);
+        let nack = NackRequest {
+            queue: String::from("jobs"),
+            consumer_id: String::from("one"),
+            topic: String::from("events"),
+            partition,
+            offsets: vec![offset],
+            error: String::from("failed"),
+        };
+        assert_eq!(Ok(Response::Nack), broker.handle(Request::Nack(nack)));
+        assert_eq!(
+            vec![(partition, offset, 2)],
+            lease("two", 5, 60000).unwrap()
+        );
+        assert_eq!(
+            Ok(Response::Ack),
+            broker.handle(Request::Ack(ack("two", partition, vec![offset])))
+        );
+        assert_eq!(
+            1,
+            broker
+                .queues()
+                .unacked("jobs", &TopicPartition::new("events", partition))
+        );
+
+        let err = lease("one", 1, 0).unwrap_err();
+        assert_eq!(ErrorCode::InvalidVisibilityTimeout, err.code);
+        let err = lease("", 1, 60000).unwrap_err();
+        assert_eq!(ErrorCode::InvalidQueue, err.code);
+        let err = lease("__dead_letter", 1, 60000).unwrap_err();
+        assert_eq!(ErrorCode::InvalidQueue, err.code);
+    }
+
+    #[test]
+    fn test_dead_letters() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = broker(dir.path());
+        create(&broker, "events-dlq", 1);
+        let create_with = |name: &str, dead_letter_topic: &str| {
+            broker.handle(Request::CreateTopic(CreateTopicRequest {
+                name: name.to_owned(),
+                partitions: 1,
+                config: vec![
+                    (topic::QUEUE_MAX_DELIVERIES.to_owned(), String::from("2")),
+                    (
+                        topic::QUEUE_DEAD_LETTER_TOPIC.to_owned(),
+                        dead_letter_topic.to_owned(),
+                    ),
+                ],
+            }))
+        };
+        assert_eq!(
+            Ok(Response::CreateTopic),
+            create_with("events", "events-dlq")
+        );
+        broker
+            .handle(Request::Produce(ProduceRequest {
+                topic: String::from("events"),
+                partitioning: Partitioning::Explicit(0),
+                records: vec![Record::new("poison").with_header("trace", "abc")],
+                producer: None,
+                compression: Compression::None,
+            }))
+            .unwrap();
+
+        let lease = |topic: &str| {
+            broker
+                .handle(Request::Lease(LeaseRequest {
+                    queue: String::from("jobs"),
+                    consumer_id: String::from("worker"),
+                    topic: topic.to_owned(),
+                    partitions: Vec::new(),
+                    max_messages: 10,
+                    visibility_timeout_ms: 60000,
+                }))
+                .map(|resp| match resp {
+                    Response::Lease(resp) => resp.messages,
+                    other => panic!("unexpected response {:?}", other),
+                })
+        };
+        let nack = |offset| {
+            broker.handle(Request::Nack(NackRequest {
+                queue: String::from("jobs"),
+                consumer_id: String::from("worker"),
+                topic: String::from("events"),
+                partition: 0,
+                offsets: vec![offset],
+                error: String::from("boom"),
+            }))
+        };
+        let fetch = |topic: &str| match broker.handle(Request::Fetch(FetchRequest {
+            partitions: vec![FetchPartition {
+                topic: topic.to_owned(),
+                partition: 0,
+                offset: 0,
+                max_bytes: 1024 * 1024,
+            }],
+            isolation: IsolationLevel::ReadUncommitted,
+            selector: String::new(),
+        })) {
+            Ok(Response::Fetch(resp)) => resp.partitions[0].records.clone(),
+            other => panic!("unexpected response {:?}", other),
+        };
+
+        for delivery_count in 1..=2 {
+            let leased = lease("events").unwrap();
+            assert_eq!(
+                vec![(0, delivery_count)],
+                leased
+                    .iter()
+                    .map(|msg| (msg.record.offset, msg.delivery_count))
+                    .collect::<Vec<_>>()
+            );
+            nack(0).unwrap();
+        }
+
+        // The last nack moves the message instead of it being delivered a
+        // third time.
+        assert!(lease("events").unwrap().is_empty());
+        let dead = fetch("events-dlq");
+        assert_eq!(1, dead.len());
+        let record = &dead[0].record;
+        assert_eq!(Some(b"poison".to_vec()), record.value);
+        assert_eq!(Some(&b"abc"[..]), record.header("trace"));
+        assert_eq!(Some(&b"jobs"[..]), record.header(queue::QUEUE_HEADER));
+        assert_eq!(Some(&b"events"[..]), record.header(queue::TOPIC_HEADER));
+        assert_eq!(Some(&b"0"[..]), record.header(queue::PARTITION_HEADER));
+        assert_eq!(Some(&b"0"[..]), record.header(queue::OFFSET_HEADER));
+        assert_eq!(Some(&b"2"[..]), record.header(queue::DELIVERY_COUNT_HEADER));
+        assert_eq!(Some(&b"boom"[..]), record.header(queue::ERROR_HEADER));
+        assert_eq!(
+            0,
+            broker
+                .queues()
+                .unacked("jobs", &TopicPartition::new("events", 0))
+        );
+
+        // Replaying moves the message back to the end of its partition once.
+        let replay = || {
+            broker.handle(Request::ReplayDeadLetters(ReplayDeadLettersRequest {
+                topic: String::from("events-dlq"),
+                partition: 0,
+                max_messages: 10,
+            }))
+        };
+        assert_eq!(
+            Ok(Response::ReplayDeadLetters(ReplayDeadLettersResponse {
+                replayed: 1
+            })),
+            replay()
+        );
+        assert_eq!(
+            Ok(Response::ReplayDeadLetters(ReplayDeadLettersResponse {
+                replayed: 0
+            })),
+            replay()
+        );
+        let events = fetch("events");
+        assert_eq!(2, events.len());
+        assert_eq!(
+            Record::new("poison").with_header("trace", "abc"),
+            events[1].record
+        );
+        let leased = lease("events").unwrap();
+        assert_eq!(
+            vec![(1, 1)],
+            leased
+                .iter()
+                .map(|msg| (msg.record.offset, msg.delivery_count))
+                .collect::<Vec<_>>()
+        );
+
+        // Dead-letter topics must be other valid topics, and must exist by
+        // the time messages are leased.
+        for name in ["", "other", "__queue_state"] {
+            let err = create_with("other", name).unwrap_err();
+            assert_eq!(ErrorCode::InvalidConfig, err.code);
+        }
+        assert_eq!(Ok(Response::CreateTopic), create_with("other", "missing"));
+        let err = lease("other").unwrap_err();
+        assert_eq!(ErrorCode::TopicNotFound, err.code);
+        let err = broker
+            .handle(Request::CreateTopic(CreateTopicRequest {
+                name: String::from("unbounded"),
+                partitions: 1,
+                config: vec![(topic::QUEUE_MAX_DELIVERIES.to_owned(), String::from("2"))],
+            }))
+            .unwrap_err();
+        assert_eq!(ErrorCode::InvalidConfig, err.code);
+    }
+
+    #[test]
+    fn test_schedule() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = broker(dir.path());
+        create(&broker, "events", 1);
+        create(&broker, "gone", 1);
+
+        let schedule = |topic: &str, partition, records| {
+            broker
+                .handle(Request::Schedule(ScheduleRequest {
+                    topic: topic.to_owned(),
+                    partition,
+                    records,
+                }))
+                .map(|resp| match resp {
+                    Response::Schedule(resp) => resp.messages,
+                    other => panic!("unexpected response {:?}", other),
+                })
+        };
+        let fetch = || match broker.handle(Request::Fetch(FetchRequest {
+            partitions: vec![FetchPartition {
+                topic: String::from("events"),
+                partition: 0,
+                offset: 0,
+                max_bytes: 1024 * 1024,
+            }],
+            isolation: IsolationLevel::ReadUncommitted,
+            selector: String::new(),
+        })) {
+            Ok(Response::Fetch(resp)) => resp.partitions[0]
+                .records
+                .iter()
+                .map(|record| record.record.value.clone().unwrap())
+                .collect::<Vec<_>>(),
+            other => panic!("unexpected response {:?}", other),
+        };
+
+        let now = record::current_timestamp();
+        let scheduled = schedule(
+            "events",
+            0,
+            vec![
+                ScheduledRecord::at(now + 2000, Record::new("later")),
+                ScheduledRecord::after(1000, Record::new("sooner")),
+                // The later of the two delivery times applies.
+                ScheduledRecord {
+                    deliver_at_ms: now + 3000,
+                    delay_ms: 500,
+                    record: Record::new("last"),
+                },
+            ],
+        )
+        .unwrap();
+        assert_eq!(3, scheduled.len());
+        assert_eq!(now + 2000, scheduled[0].deliver_at_ms);
+        assert!(scheduled[1].deliver_at_ms >= now + 1000);
+        assert_eq!(now + 3000, scheduled[2].deliver_at_ms);
+        schedule("gone", 0, vec![ScheduledRecord::at(0, Record::new("lost"))]).unwrap();
+
+        // Nothing is visible until it is due, then it is released in order.
+        broker.release_scheduled(now);
+        assert!(fetch().is_empty());
+        broker
+            .handle(Request::DeleteTopic(DeleteTopicRequest {
+                name: String::from("gone"),
+            }))
+            .unwrap();
+        broker.release_scheduled(now + 2500);
+        assert_eq!(vec![b"sooner".to_vec(), b"later".to_vec()], fetch());
+        assert_eq!(1, broker.scheduler().pending());
+        broker.release_scheduled(now + 3000);
+        assert_eq!(
+            vec![b"sooner".to_vec(), b"later".to_vec(), b"last".to_vec()],
+            fetch()
+        );
+        assert_eq!(0, broker.scheduler().pending());
+
+        let err =
+            schedule("events", 1, vec![ScheduledRecord::at(0, Record::new("a"))]).unwrap_err();
+        assert_eq!(ErrorCode::PartitionNotFound, err.code);
+        let err = schedule("events", 0, Vec::new()).unwrap_err();
+        assert_eq!(ErrorCode::InvalidRequest, err.code);
+        let err = schedule(
+            "events",
+            0,
+            vec![ScheduledRecord::after(u64::MAX, Record::new("a"))],
+        )
+        .unwrap_err();
+        assert_eq!(ErrorCode::InvalidDeliveryTime, err.code);
+        let err = schedule(
+            schedule::SCHEDULED_MESSAGES_TOPIC,
+            0,
+            vec![ScheduledRecord::at(0, Record::new("a"))],
+        )
+        .unwrap_err();
+        assert_eq!(ErrorCode::InvalidTopic, err.code);
+    }
+
+    #[test]
+    fn test_exchanges() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = broker(dir.path());
+        create(&broker, "orders", 1);
+        create(&broker, "audit", 2);
+
+        let declare = |name: &str, kind| {
+            broker.handle(Request::DeclareExchange(DeclareExchangeRequest {
+                name: name.to_owned(),
+                kind,
+            }))
+        };
+        let bind = |binding| broker.handle(Request::Bind(BindRequest { binding }));
+        let publish =
+            |routing_key: &str, record| match broker.handle(Request::Publish(PublishRequest {
+                exchange: String::from("events"),
+                routing_key: routing_key.to_owned(),
+                record,
+            })) {
+                Ok(Response::Publish(resp)) => resp.records,
+                other => panic!("unexpected response {:?}", other),
+            };
+
+        assert_eq!(
+            Ok(Response::DeclareExchange),
+            declare("events", ExchangeType::Topic)
+        );
+        assert_eq!(
+            Ok(Response::DeclareExchange),
+            declare("events", ExchangeType::Topic)
+        );
+        let err = declare("events", ExchangeType::Fanout).unwrap_err();
+        assert_eq!(ErrorCode::InvalidExchange, err.code);
+
+        assert_eq!(
+            Ok(Response::Bind),
+            bind(Binding::new("events", "orders", "orders.*"))
+        );
+        assert_eq!(
+            Ok(Response::Bind),
+            bind(Binding::new("events", "audit", "#"))
+        );
+        let err = bind(Binding::new("events", "missing", "#")).unwrap_err();
+        assert_eq!(ErrorCode::TopicNotFound, err.code);
+        let err = bind(Binding::new("events", "__exchanges", "#")).unwrap_err();
+        assert_eq!(ErrorCode::InvalidTopic, err.code);
+        let err = bind(Binding::new("missing", "orders", "#")).unwrap_err();
+        assert_eq!(ErrorCode::ExchangeNotFound, err.code);
+
+        // A single publish reaches every matching destination.
+        let routed = publish("orders.created", Record::new("a").with_key("k"));
+        assert_eq!(
+            vec!["orders", "audit"],
+            routed.iter().map(|r| r.topic.as_str()).collect::<Vec<_>>()
+        );
+        let routed = publish("users.created", Record::new("b"));
+        assert_eq!(1, routed.len());
+        assert_eq!("audit", routed[0].topic);
+
+        let fetched = match broker.handle(Request::Fetch(FetchRequest {
+            partitions: vec![FetchPartition {
+                topic: String::from("orders"),
+                partition: 0,
+                offset: 0,
+                max_bytes: 1024 * 1024,
+            }],
+            isolation: IsolationLevel::ReadUncommitted,
+            selector: String::new(),
+        })) {
+            Ok(Response::Fetch(resp)) => resp.partitions[0].records.clone(),
+            other => panic!("unexpected response {:?}", other),
+        };
+        assert_eq!(1, fetched.len());
+        let record = &fetched[0].record;
+        assert_eq!(Some(&b"a"[..]), record.value.as_deref());
+        assert_eq!(Some(&b"events"[..]), record.header(EXCHANGE_HEADER));
+        assert_eq!(
+            Some(&b"orders.created"[..]),
+            record.header(ROUTING_KEY_HEADER)
+        );
+
+        // Deleting a destination removes its bindings.
+        broker
+            .handle(Request::DeleteTopic(DeleteTopicRequest {
+                name: String::from("audit"),
+            }))
+            .unwrap();
+        assert_eq!(1, broker.exchanges().bindings("events").unwrap().len());
+        assert!(publish("users.created", Record::new("c")).is_empty());
+
+        assert_eq!(
+            Ok(Response::Unbind),
+            broker.handle(Request::Unbind(BindRequest {
+                binding: Binding::new("events", "orders", "orders.*"),
+            }))
+        );
+        assert!(publish("orders.created", Record::new("d")).is_empty());
+
+        assert_eq!(
+            Ok(Response::DeleteExchange),
+            broker.handle(Request::DeleteExchange(DeleteExchangeRequest {
+                name: String::from("events"),
+            }))
+        );
+        let err = broker
+            .handle(Request::Publish(PublishRequest {
+                exchange: String::from("events"),
+                routing_key: String::new(),
+                record: Record::new("e"),
+            }))
+            .unwrap_err();
+        assert_eq!(ErrorCode::ExchangeNotFound, err.code);
+    }
+
+    #[test]
+    fn test_expired() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = broker(dir.path());
+        create(&broker, "events-dlq", 1);
+        broker
+            .handle(Request::CreateTopic(CreateTopicRequest {
+                name: String::from("events"),
+                partitions: 1,
+                config: vec![(
+                    topic::QUEUE_DEAD_LETTER_TOPIC.to_owned(),
+                    String::from("events-dlq"),
+                )],
+            }))
+            .unwrap();
+        broker
+            .handle(Request::CreateTopic(CreateTopicRequest {
+                name: String::from("short"),
+                partitions: 1,
+                config: vec![(topic::MESSAGE_TTL_MS.to_owned(), String::from("0"))],
+            }))
+            .unwrap();
+
+        let produce = |topic: &str, records| {
+            broker
+                .handle(Request::Produce(ProduceRequest {
+                    topic: topic.to_owned(),
+                    partitioning: Partitioning::Explicit(0),
+                    records,
+                    producer: None,
+                    compression: Compression::None,
+                }))
+                .unwrap();
+        };
+        let fetch = |topic: &str, offset| match broker.handle(Request::Fetch(FetchRequest {
+            partitions: vec![FetchPartition {
+                topic: topic.to_owned(),
+                partition: 0,
+                offset,
+                max_bytes: 1024 * 1024,
+            }],
+            isolation: IsolationLevel::ReadUncommitted,
+            selector: String::new(),
+        })) {
+            Ok(Response::Fetch(resp)) => resp.partitions[0]
+                .records
+                .iter()
+                .map(|record| (record.offset, record.record.clone()))
+                .collect::<Vec<_>>(),
+            other => panic!("unexpected response {:?}", other),
+        };
+
+        // Fetches skip expired records, reading past runs of them.
+        produce(
+            "events",
+            vec![Record::new("a").with_ttl(0), Record::new("b").with_ttl(0)],
+        );
+        produce("events", vec![Record::new("c").with_ttl(60000)]);
+        assert_eq!(
+            vec![(2, Record::new("c").with_ttl(60000))],
+            fetch("events", 0)
+        );
+        produce("events", vec![Record::new("d").with_ttl(0)]);
+        assert!(fetch("events", 3).is_empty());
+
+        // Topics may expire records without a TTL header by default.
+        produce(
+            "short",
+            vec![Record::new("e"), Record::new("f").with_ttl(60000)],
+        );
+        assert_eq!(
+            vec![1],
+            fetch("short", 0)
+                .iter()
+                .map(|(offset, _)| *offset)
+                .collect::<Vec<_>>()
+        );
+
+        // Queues with a dead-letter topic move expired messages there.
+        let lease = || match broker.handle(Request::Lease(LeaseRequest {
+            queue: String::from("jobs"),
+            consumer_id: String::from("worker"),
+            topic: String::from("events"),
+            partitions: Vec::new(),
+            max_messages: 10,
+            visibility_timeout_ms: 60000,
+        })) {
+            Ok(Response::Lease(resp)) => resp
+                .messages
+                .iter()
+                .map(|msg| msg.record.offset)
+                .collect::<Vec<_>>(),
+            other => panic!("unexpected response {:?}", other),
+        };
+        assert_eq!(vec![2], lease());
+        assert!(lease().is_empty());
+        let dead = fetch("events-dlq", 0);
+        assert_eq!(3, dead.len());
+        for (_, record) in &dead {
+            assert_eq!(
+                Some(&b"message expired"[..]),
+                record.header(queue::ERROR_HEADER)
+            );
+            assert_eq!(Some(&b"0"[..]), record.header(queue::TTL_MS_HEADER));
+            assert_eq!(None, record.ttl_ms());
+        }
+
+        // Topics may expire messages for queues alone.
+        broker
+            .handle(Request::CreateTopic(CreateTopicRequest {
+                name: String::from("jobs"),
+                partitions: 1,
+                config: vec![(topic::QUEUE_MESSAGE_TTL_MS.to_owned(), String::from("0"))],
+            }))
+            .unwrap();
+        produce("jobs", vec![Record::new("g")]);
+        assert_eq!(1, fetch("jobs", 0).len());
+        let leased = broker.handle(Request::Lease(LeaseRequest {
+            queue: String::from("jobs"),
+            consumer_id: String::from("worker"),
+            topic: String::from("jobs"),
+            partitions: Vec::new(),
+            max_messages: 10,
+            visibility_timeout_ms: 60000,
+        }));
+        assert_eq!(
+            Ok(Response::Lease(LeaseResponse {
+                messages: Vec::new()
+            })),
+            leased
+        );
+    }
+
+    #[test]
+    fn test_group_membership() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = broker(dir.path());
+        create(&broker, "events", 2);
+
+        let join = JoinGroupRequest {
+            group: String::from("group"),
+            member_id: String::new(),
+            session_timeout_ms: 10000,
+            topics: vec![String::from("events")],
+            strategies: vec![String::from("roundrobin")],
+        };
+        let joined = match broker.handle(Request::JoinGroup(join.clone())) {
+            Ok(Response::JoinGroup(resp)) => resp,
+            _ => unimplemented!(),
+        };
+        assert_eq!(1, joined.generation);
+        assert_eq!(2, joined.assignment.len());
+
+        let member = GroupMemberRequest {
+            group: String::from("group"),
+            member_id: joined.member_id.clone(),
+        };
+        let resp = broker.handle(Request::GroupHeartbeat(member.clone()));
+        assert_eq!(Ok(Response::GroupHeartbeat(joined)), resp);
+        assert_eq!(
+            Ok(Response::LeaveGroup),
+            broker.handle(Request::LeaveGroup(member.clone()))
+        );
+
+        let err = broker.handle(Request::GroupHeartbeat(member)).unwrap_err();
+        assert_eq!(ErrorCode::UnknownMember, err.code);
+
+        let err = broker
+            .handle(Request::JoinGroup(JoinGroupRequest {
+                session_timeout_ms: 1,
+                ..join
+            }))
+            .unwrap_err();
+        assert_eq!(ErrorCode::InvalidSessionTimeout, err.code);
+    }
+
+    #[test]
+    fn test_replication_disabled() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = broker(dir.path());
+        let err = broker
+            .handle(Request::RaftVote(VoteRequest {
+                partition: TopicPartition::new("events", 0),
+                term: 1,
+                candidate: 2,
+                last_offset: 0,
+                last_term: 0,
+            }))
+            .unwrap_err();
+        assert_eq!(ErrorCode::InvalidRequest, err.code);
+    }
+}
@@ -0,0 +1,270 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use thiserror::Error;
+
+use crate::protocol::{ErrorCode, ResponseError};
+use crate::{
+    cluster, exchange, group, offset, producer, queue, raft, schedule, selector, storage, topic,
+};
+
+/// Represents errors recovering a broker's state when it is opened.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// Handles errors recovering committed consumer offsets.
+    #[error(transparent)]
+    Offset(#[from] offset::Error),
+    /// Handles errors recovering the state of transactional producers.
+    #[error(transparent)]
+    Producer(#[from] producer::Error),
+    /// Handles errors recovering the progress of queues.
+    #[error(transparent)]
+    Queue(#[from] queue::Error),
+    /// Handles errors recovering scheduled messages.
+    #[error(transparent)]
+    Schedule(#[from] schedule::Error),
+    /// Handles errors recovering exchanges and their bindings.
+    #[error(transparent)]
+    Exchange(#[from] exchange::Error),
+}
+
+/// Returns the [ErrorCode] reported to clients for the supplied topic error.
+pub fn topic_error_code(err: &topic::Error) -> ErrorCode {
+    match err {
+        topic::Error::Storage(storage::Error::OffsetOutOfRange { .. }) => {
+            ErrorCode::OffsetOutOfRange
+        }
+        topic::Error::Storage(storage::Error::EntryTooLarge { .. }) => ErrorCode::MessageTooLarge,
+        topic::Error::Storage(storage::Error::EmptyEntry) => ErrorCode::InvalidRequest,
+        topic::Error::Storage(_) | topic::Error::Io { .. } | topic::Error::Metadata { .. } => {
+            ErrorCode::Unknown
+        }
+        topic::Error::Corrupt { .. } => ErrorCode::CorruptMessage,
+        topic::Error::AlreadyExists { .. } => ErrorCode::TopicAlreadyExists,
+        topic::Error::NotFound { .. } => ErrorCode::TopicNotFound,
+        topic::Error::InvalidName { .. } => ErrorCode::InvalidTopic,
+        topic::Error::InvalidPartitions { .. } => ErrorCode::InvalidPartitions,
+        topic::Error::PartitionNotFound { .. } => ErrorCode::PartitionNotFound,
+        topic::Error::InvalidConfig { .. } => ErrorCode::InvalidConfig,
+        topic::Error::ProducerFenced { .. } => ErrorCode::ProducerFenced,
+        topic::Error::DuplicateSequence { .. } => ErrorCode::DuplicateSequence,
+        topic::Error::OutOfOrderSequence { .. } => ErrorCode::OutOfOrderSequence,
+    }
+}
+
+impl From<topic::Error> for ResponseError {
+    fn from(err: topic::Error) -> Self {
+        ResponseError::new(topic_error_code(&err), err.to_string())
+    }
+}
+
+/// Returns the [ErrorCode] reported to clients for the supplied group error.
+pub fn group_error_code(err: &group::Error) -> ErrorCode {
+    match err {
+        group::Error::InvalidGroup { .. } => ErrorCode::InvalidGroup,
+        group::Error::UnknownMember { .. } => ErrorCode::UnknownMember,
+        group::Error::InvalidSessionTimeout { .. } => ErrorCode::InvalidSessionTimeout,
+        group::Error::InconsistentStrategy { .. } => ErrorCode::InconsistentStrategy,
+    }
+}
+
+impl From<group::Error> for ResponseError {
+    fn from(err: group::Error) -> Self {
+        ResponseError::new(group_error_code(&err), err.to_string())
+    }
+}
+
+/// Returns the [ErrorCode] reported to clients for the supplied offset error.
+pub fn offset_error_code(err: &offset::Error) -> ErrorCode {
+    match err {
+        offset::Error::Topic(err) => topic_error_code(err),
+        offset::Error::Corrupt { .. } => ErrorCode::CorruptMessage,
+        offset::Error::InvalidGroup { .. } => ErrorCode::InvalidGroup,
+    }
+}
+
+impl From<offset::Error> for ResponseError {
+    fn from(err: offset::Error) -> Self {
+        ResponseError::new(offset_error_code(&err), err.to_string())
+    }
+}
+
+/// Returns the [ErrorCode] reported to clients for the supplied producer error.
+pub fn producer_error_code(err: &producer::Error) -> ErrorCode {
+    match err {
+        producer::Error::Io { .. } | producer::Error::State { .. } => ErrorCode::Unknown,
+        producer::Error::Exhausted { .. } => ErrorCode::ClusterUnavailable,
+        producer::Error::Topic(err) => topic_error_code(err),
+        producer::Error::Corrupt { .. } => ErrorCode::CorruptMessage,
+        producer::Error::InvalidTransactionalId { .. } => ErrorCode::InvalidRequest,
+        producer::Error::InvalidTimeout { .. } => ErrorCode::InvalidTransactionTimeout,
+        producer::Error::InvalidProducerIdMapping { .. } => ErrorCode::InvalidProducerIdMapping,
+        producer::Error::ProducerFenced { .. } => ErrorCode::ProducerFenced,
+        producer::Error::InvalidTxnState { .. } => ErrorCode::InvalidTxnState,
+        producer::Error::ConcurrentTransactions { .. } => ErrorCode::ConcurrentTransactions,
+    }
+}
+
+impl From<producer::Error> for ResponseError {
+    fn from(err: producer::Error) -> Self {
+        ResponseError::new(producer_error_code(&err), err.to_string())
+    }
+}
+
+/// Returns the [ErrorCode] reported to clients for the supplied queue error.
+pub fn queue_error_code(err: &queue::Error) -> ErrorCode {
+    match err {
+        queue::Error::Topic(err) => topic_error_code(err),
+        queue::Error::Corrupt { .. } => ErrorCode::CorruptMessage,
+        queue::Error::InvalidQueue { .. } | queue::Error::InvalidConsumer { .. } => {
+            ErrorCode::InvalidQueue
+        }
+        queue::Error::InvalidVisibilityTimeout { .. } => ErrorCode::InvalidVisibilityTimeout,
+        queue::Error::NotLeased { .. } => ErrorCode::NotLeased,
+    }
+}
+
+impl From<queue::Error> for ResponseError {
+    fn from(err: queue::Error) -> Self {
+        ResponseError::new(queue_error_code(&err), err.to_string())
+    }
+}
+
+/// Returns the [ErrorCode] reported to clients for the supplied schedule error.
+pub fn schedule_error_code(err: &schedule::Error) -> ErrorCode {
+    match err {
+        schedule::Error::Topic(err) => topic_error_code(err),
+        schedule::Error::Corrupt { .. } => ErrorCode::CorruptMessage,
+        schedule::Error::InvalidDeliveryTime { .. } => ErrorCode::InvalidDeliveryTime,
+    }
+}
+
+impl From<schedule::Error> for ResponseError {
+    fn from(err: schedule::Error) -> Self {
+        ResponseError::new(schedule_error_code(&err), err.to_string())
+    }
+}
+
+/// Returns the [ErrorCode] reported to clients for the supplied exchange error.
+pub fn exchange_error_code(err: &exchange::Error) -> ErrorCode {
+    match err {
+        exchange::Error::Topic(err) => topic_error_code(err),
+        exchange::Error::Corrupt { .. } => ErrorCode::CorruptMessage,
+        exchange::Error::InvalidName { .. } | exchange::Error::TypeMismatch { .. } => {
+            ErrorCode::InvalidExchange
+        }
+        exchange::Error::NotFound { .. } => ErrorCode::ExchangeNotFound,
+    }
+}
+
+impl From<exchange::Error> for ResponseError {
+    fn from(err: exchange::Error) -> Self {
+        ResponseError::new(exchange_error_code(&err), err.to_string())
+    }
+}
+
+impl From<selector::Error> for ResponseError {
+    fn from(err: selector::Error) -> Self {
+        ResponseError::new(ErrorCode::InvalidSelector, err.to_string())
+    }
+}
+
+/// Returns the [ErrorCode] reported to clients for the supplied cluster error.
+pub fn cluster_error_code(err: &cluster::Error) -> ErrorCode {
+    match err {
+        cluster::Error::InvalidConfig { .. } => ErrorCode::InvalidConfig,
+        cluster::Error::Corrupt { .. } => ErrorCode::CorruptMessage,
+        cluster::Error::InvalidRecord { .. } => ErrorCode::InvalidRequest,
+        cluster::Error::NoNodes { .. } => ErrorCode::ClusterUnavailable,
+    }
+}
+
+/// Returns the [ErrorCode] reported to clients for the supplied replication error.
+pub fn raft_error_code(err: &raft::Error) -> ErrorCode {
+    match err {
+        raft::Error::Topic(err) => topic_error_code(err),
+        raft::Error::Cluster(err) => cluster_error_code(err),
+        raft::Error::Io { .. } | raft::Error::State { .. } => ErrorCode::Unknown,
+        raft::Error::InvalidConfig { .. } => ErrorCode::InvalidConfig,
+        raft::Error::NotReplica { .. } | raft::Error::NotLeader { .. } => ErrorCode::NotLeader,
+        raft::Error::ReplicationTimeout { .. } => ErrorCode::ReplicationTimeout,
+    }
+}
+
+impl From<raft::Error> for ResponseError {
+    fn from(err: raft::Error) -> Self {
+        ResponseError::new(raft_error_code(&err), err.to_string())
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+    use crate::topic::TopicPartition;
+
+    #[test]
+    fn test_topic_error_code() {
+        let err = topic::Error::NotFound {
+            name: String::from("events"),
+        };
+        assert_eq!(ErrorCode::TopicNotFound, topic_error_code(&err));
+
+        let err = topic::Error::Storage(storage::Error::OffsetOutOfRange {
+            offset: 1,
+            start: 2,
+            end: 3,
+        });
+        let resp = ResponseError::from(err);
+        assert_eq!(ErrorCode::OffsetOutOfRange, resp.code);
+        assert_eq!("offset 1 is out of range, log spans [2, 3)", resp.message);
+    }
+
+    #[test]
+    fn test_group_error_code() {
+        let err = group::Error::UnknownMember {
+            group: String::from("group"),
+            member: String::from("member"),
+        };
+        let resp = ResponseError::from(err);
+        assert_eq!(ErrorCode::UnknownMember, resp.code);
+        assert_eq!("member 'member' is not part of group 'group'", resp.message);
+    }
+
+    #[test]
+    fn test_exchange_error_code() {
+        let err = exchange::Error::NotFound {
+            name: String::from("events"),
+        };
+        let resp = ResponseError::from(err);
+        assert_eq!(ErrorCode::ExchangeNotFound, resp.code);
+        assert_eq!("exchange 'events' does not exist", resp.message);
+
+        let err = exchange::Error::TypeMismatch {
+            name: String::from("events"),
+            existing: exchange::ExchangeType::Topic,
+            requested: exchange::ExchangeType::Direct,
+        };
+        assert_eq!(ErrorCode::InvalidExchange, exchange_error_code(&err));
+    }
+
+    #[test]
+    fn test_raft_error_code() {
+        let err = raft::Error::NotLeader {
+            partition: TopicPartition::new("events", 0),
+            leader: Some(2),
+        };
+        let resp = ResponseError::from(err);
+        assert_eq!(ErrorCode::NotLeader, resp.code);
+
+        let err = raft::Error::Topic(topic::Error::NotFound {
+            name: String::from("events"),
+        });
+        assert_eq!(ErrorCode::TopicNotFound, raft_error_code(&err));
+
+        let err = raft::Error::Cluster(cluster::Error::NoNodes {
+            topic: String::from("events"),
+        });
+        assert_eq!(ErrorCode::ClusterUnavailable, raft_error_code(&err));
+    }
+}
@@ -0,0 +1,14 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+#[allow(clippy::module_inception)]
+mod broker;
+mod error;
+mod session;
+
+pub use self::broker::{Broker, TEMPORARY_QUEUE_PREFIX};
+pub use self::error::{
+    cluster_error_code, group_error_code, offset_error_code, producer_error_code, queue_error_code,
+    raft_error_code, schedule_error_code, topic_error_code, Error,
+};
+pub use self::session::Session;
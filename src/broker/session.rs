@@ -0,0 +1,139 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::sync::Arc;
+
+use crate::protocol::{Request, Response, ResponseError, TemporaryQueueResponse};
+
+use super::broker::Broker;
+
+/// The state a broker holds for a single client connection. Requests are
+/// served by the broker, except for those scoped to the connection, such as
+/// creating temporary queues, which are deleted once the session is dropped.
+pub struct Session {
+    broker: Arc<Broker>,
+    temporary_queues: Vec<String>,
+}
+
+impl Session {
+    /// Start a session for a new connection to the supplied broker.
+    pub fn new(broker: Arc<Broker>) -> Session {
+        Session {
+            broker,
+            temporary_queues: Vec::new(),
+        }
+    }
+
+    /// Returns the temporary queues created by this session.
+    pub fn temporary_queues(&self) -> &[String] {
+        &self.temporary_queues
+    }
+
+    /// Serve a single request made through this session's connection.
+    pub fn handle(&mut self, request: Request) -> Result<Response, ResponseError> {
+        match request {
+            Request::CreateTemporaryQueue => {
+                let topic = self.broker.create_temporary_queue()?;
+                self.temporary_queues.push(topic.clone());
+                Ok(Response::CreateTemporaryQueue(TemporaryQueueResponse {
+                    topic,
+                }))
+            }
+            request => self.broker.handle(request),
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        for topic in &self.temporary_queues {
+            self.broker.delete_temporary_queue(topic);
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+    use crate::broker::TEMPORARY_QUEUE_PREFIX;
+    use crate::protocol::{CreateTopicRequest, ErrorCode};
+    use crate::storage::LogConfig;
+    use crate::{group, producer, queue, schedule, topic};
+
+    fn broker(dir: &std::path::Path) -> Arc<Broker> {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        Arc::new(
+            Broker::open(
+                logger,
+                Arc::new(topic::Manager::open(dir, LogConfig::default()).unwrap()),
+                group::Config::default(),
+                producer::Config::default(),
+                queue::Config::default(),
+                schedule::Config::default(),
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_temporary_queues() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = broker(dir.path());
+
+        let err = broker.handle(Request::CreateTemporaryQueue).unwrap_err();
+        assert_eq!(ErrorCode::InvalidRequest, err.code);
+
+        let mut session = Session::new(broker.clone());
+        let mut created = Vec::new();
+        for _ in 0..2 {
+            match session.handle(Request::CreateTemporaryQueue) {
+                Ok(Response::CreateTemporaryQueue(resp)) => created.push(resp.topic),
+                other => panic!("unexpected response {:?}", other),
+            }
+        }
+        assert_ne!(created[0], created[1]);
+        assert_eq!(created, session.temporary_queues());
+        for topic in &created {
+            assert!(topic.starts_with(TEMPORARY_QUEUE_PREFIX));
+            assert_eq!(1, broker.topics().get(topic).unwrap().partitions().len());
+        }
+
+        // Other requests are served by the broker.
+        assert_eq!(Ok(Response::Heartbeat), session.handle(Request::Heartbeat));
+
+        drop(session);
+        for topic in &created {
+            assert!(broker.topics().get(topic).is_err());
+        }
+    }
+
+    #[test]
+    fn test_delete_orphaned_temporary_queues() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = broker(dir.path());
+        let orphan = broker.create_temporary_queue().unwrap();
+        broker
+            .topics()
+            .create("events", 1, topic::TopicConfig::new())
+            .unwrap();
+
+        // Clients can not create topics that would be mistaken for temporary
+        // queues, only ones that merely look like them.
+        let create = |name: &str| {
+            broker.handle(Request::CreateTopic(CreateTopicRequest {
+                name: name.to_owned(),
+                partitions: 1,
+                config: Vec::new(),
+            }))
+        };
+        let err = create(&format!("{}orders", TEMPORARY_QUEUE_PREFIX)).unwrap_err();
+        assert_eq!(ErrorCode::InvalidTopic, err.code);
+        assert_eq!(Ok(Response::CreateTopic), create("tmp-orders"));
+
+        assert_eq!(1, broker.delete_temporary_queues());
+        assert!(broker.topics().get(&orphan).is_err());
+        assert!(broker.topics().get("events").is_ok());
+        assert!(broker.topics().get("tmp-orders").is_ok());
+    }
+}
@@ -0,0 +1,159 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::sync::Arc;
+
+use crate::topic::{self, Partition};
+
+use super::metrics::metrics;
+
+/// The reason reported for bytes reclaimed by compaction.
+const COMPACTION: &str = "compaction";
+
+/// Enforces the retention limits of every topic's partitions and compacts those
+/// of compacted topics.
+pub struct Cleaner {
+    logger: slog::Logger,
+    topics: Arc<topic::Manager>,
+}
+
+impl Cleaner {
+    /// Create a new cleaner for the supplied topics.
+    pub fn new(logger: slog::Logger, topics: Arc<topic::Manager>) -> Cleaner {
+        Cleaner { logger, topics }
+    }
+
+    /// Delete every closed segment that falls outside its topic's retention
+    /// limits as of `now`, in milliseconds since the epoch, compact the closed
+    /// segments of compacted topics, and return the number of bytes reclaimed.
+    /// Failures are logged and do not stop the remaining partitions from being
+    /// cleaned.
+    pub fn clean(&self, now: i64) -> u64 {
+        let mut reclaimed = 0;
+        for topic in self.topics.list() {
+            for partition in topic.partitions() {
+                reclaimed += self.apply_retention(partition, now);
+                reclaimed += self.compact(partition, now);
+            }
+        }
+        reclaimed
+    }
+
+    fn apply_retention(&self, partition: &Partition, now: i64) -> u64 {
+        let deleted = match partition.apply_retention(now) {
+            Ok(deleted) => deleted,
+            Err(err) => {
+                error!(self.logger, "Failed to apply retention."; "topic" => partition.topic(), "partition" => partition.id(), "error" => err.to_string());
+                return 0;
+            }
+        };
+
+        let id = partition.id().to_string();
+        deleted
+            .into_iter()
+            .map(|segment| {
+                info!(self.logger, "Deleted segment outside retention limits."; "topic" => partition.topic(), "partition" => partition.id(), "base_offset" => segment.base_offset, "bytes" => segment.size, "limit" => segment.limit.as_str());
+                metrics()
+                    .reclaimed_bytes
+                    .with_label_values(&[partition.topic(), &id, segment.limit.as_str()])
+                    .inc_by(segment.size);
+                segment.size
+            })
+            .sum()
+    }
+
+    fn compact(&self, partition: &Partition, now: i64) -> u64 {
+        let reclaimed = match partition.compact(now) {
+            Ok(Some(reclaimed)) => reclaimed,
+            Ok(None) => return 0,
+            Err(err) => {
+                error!(self.logger, "Failed to compact partition."; "topic" => partition.topic(), "partition" => partition.id(), "error" => err.to_string());
+                return 0;
+            }
+        };
+
+        info!(self.logger, "Compacted partition."; "topic" => partition.topic(), "partition" => partition.id(), "bytes" => reclaimed);
+        metrics()
+            .reclaimed_bytes
+            .with_label_values(&[partition.topic(), &partition.id().to_string(), COMPACTION])
+            .inc_by(reclaimed);
+        reclaimed
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+    use crate::record::Record;
+    use crate::storage::LogConfig;
+    use crate::topic::{TopicConfig, CLEANUP_POLICY, RETENTION_BYTES, RETENTION_MS, SEGMENT_BYTES};
+
+    #[test]
+    fn test_clean_retention() {
+        let dir = tempfile::tempdir().unwrap();
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let topics = Arc::new(topic::Manager::open(dir.path(), LogConfig::default()).unwrap());
+
+        let mut config = TopicConfig::new();
+        config.set(SEGMENT_BYTES, "128").unwrap();
+        config.set(RETENTION_MS, "-1").unwrap();
+        config.set(RETENTION_BYTES, "256").unwrap();
+        let sized = topics.create("sized", 1, config).unwrap();
+
+        let mut config = TopicConfig::new();
+        config.set(SEGMENT_BYTES, "128").unwrap();
+        let kept = topics.create("kept", 1, config).unwrap();
+
+        for topic in [&sized, &kept] {
+            let partition = topic.partition(0).unwrap();
+            for idx in 0..40 {
+                partition
+                    .append(&[Record::new(format!("value-{}", idx))])
+                    .unwrap();
+            }
+        }
+
+        let cleaner = Cleaner::new(logger, topics);
+        let reclaimed = cleaner.clean(crate::record::current_timestamp());
+        assert!(reclaimed > 0);
+
+        let partition = sized.partition(0).unwrap();
+        assert!(partition.start_offset() > 0);
+        assert_eq!(40, partition.next_offset());
+        assert_eq!(0, kept.partition(0).unwrap().start_offset());
+
+        assert_eq!(0, cleaner.clean(crate::record::current_timestamp()));
+    }
+
+    #[test]
+    fn test_clean_compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let topics = Arc::new(topic::Manager::open(dir.path(), LogConfig::default()).unwrap());
+
+        let mut config = TopicConfig::new();
+        config.set(SEGMENT_BYTES, "128").unwrap();
+        config.set(CLEANUP_POLICY, "compact").unwrap();
+        let compacted = topics.create("compacted", 1, config).unwrap();
+
+        let partition = compacted.partition(0).unwrap();
+        for idx in 0..40 {
+            partition
+                .append(&[Record::new(format!("value-{}", idx)).with_key("key")])
+                .unwrap();
+        }
+
+        let cleaner = Cleaner::new(logger, topics);
+        assert!(cleaner.clean(crate::record::current_timestamp()) > 0);
+        assert_eq!(40, partition.next_offset());
+
+        let records = partition.read(0, usize::MAX).unwrap();
+        assert!(records.len() < 40);
+        assert_eq!(
+            Some(b"value-39".to_vec()),
+            records.last().unwrap().record.value
+        );
+        assert_eq!(0, cleaner.clean(crate::record::current_timestamp()));
+    }
+}
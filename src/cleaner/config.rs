@@ -0,0 +1,28 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::time::Duration;
+
+use structopt::StructOpt;
+
+#[derive(Debug, Clone, StructOpt)]
+/// Rift log cleaner configuration.
+pub struct Config {
+    #[structopt(
+        long = "cleaner-interval-ms",
+        env = "RIFT_CLEANER_INTERVAL_MS",
+        help = "How often to enforce retention limits and compact logs.",
+        long_help = "Sets the interval in milliseconds at which the background cleaner deletes log segments that fall outside their topic's retention limits and compacts the closed segments of compacted topics.",
+        default_value = "300000",
+        takes_value = true
+    )]
+    /// Define the cleaner interval in milliseconds.
+    pub cleaner_interval_ms: u64,
+}
+
+impl Config {
+    /// Returns the interval between cleaner runs.
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.cleaner_interval_ms)
+    }
+}
@@ -0,0 +1,38 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::sync::OnceLock;
+
+use prometheus::IntCounterVec;
+
+use crate::metrics::{register_int_counter_vec, Opt};
+
+/// The series exported by the cleaner.
+pub(super) struct Metrics {
+    /// Bytes reclaimed, labelled by topic, partition, and the reason data was removed.
+    pub(super) reclaimed_bytes: IntCounterVec,
+}
+
+/// Returns the process wide cleaner metrics, registering them on first use.
+pub(super) fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(|| {
+        let opts = vec![
+            Opt::Namespace(String::from("rift")),
+            Opt::Subsystem(String::from("cleaner")),
+            Opt::Labels(vec![
+                String::from("topic"),
+                String::from("partition"),
+                String::from("reason"),
+            ]),
+        ];
+        Metrics {
+            reclaimed_bytes: register_int_counter_vec(
+                "reclaimed_bytes_total",
+                "The number of bytes of log data removed by the cleaner.",
+                Some(opts),
+            )
+            .expect("cleaner metrics registered twice"),
+        }
+    })
+}
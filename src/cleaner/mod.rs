@@ -0,0 +1,10 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+#[allow(clippy::module_inception)]
+mod cleaner;
+mod config;
+mod metrics;
+
+pub use self::cleaner::Cleaner;
+pub use self::config::Config;
@@ -0,0 +1,663 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::VecDeque,
+    io::{BufReader, BufWriter},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::codec::Reader;
+use crate::exchange::{Binding, ExchangeType};
+use crate::offset::OffsetReset;
+use crate::protocol::{
+    self, read_frame, write_frame, AckRequest, AddPartitionsToTxnRequest, ApiKey,
+    ApiVersionsResponse, AppendRequest, AppendResponse, BindRequest, CommitOffsetRequest,
+    CreateTopicRequest, DeclareExchangeRequest, DeleteExchangeRequest, DeleteTopicRequest,
+    EndTxnRequest, FetchOffsetsRequest, FetchPartition, FetchRequest, FetchedPartition,
+    FindCoordinatorRequest, Frame, GroupAssignmentResponse, GroupMemberRequest,
+    InitProducerRequest, InitProducerResponse, JoinGroupRequest, LeaseRequest, LeasedMessage,
+    MetadataRequest, MetadataResponse, NackRequest, NodeMetadata, OffsetsForTimesRequest,
+    PartitionOffset, PartitionTimestamp, ProduceRequest, ProducedRecord, PublishRequest,
+    ReplayDeadLettersRequest, Request, ResetOffsetsRequest, Response, ResponseError, RoutedRecord,
+    ScheduleRequest, ScheduledMessage, SnapshotRequest, VoteRequest, VoteResponse,
+    WriteTxnMarkersRequest,
+};
+use crate::record::{Compression, ProducerBatch, Record};
+use crate::selector::Selector;
+use crate::topic::{IsolationLevel, Partitioning, TopicPartition};
+
+use super::error::{Error, Result};
+
+/// How long to wait between fetches of the reply queue while awaiting a reply.
+const REPLY_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// The most bytes of replies read from the reply queue at a time.
+const REPLY_MAX_BYTES: u32 = 1024 * 1024;
+
+/// A blocking client for the native binary protocol.
+///
+/// Requests may be pipelined with [Client::send] and [Client::receive], or
+/// issued one at a time with [Client::call] and the typed helpers built on it.
+pub struct Client {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+    next_correlation_id: u32,
+    pending: VecDeque<(u32, ApiKey)>,
+    pub(super) compression: Compression,
+    /// The temporary queue replies to requests are read from, along with the
+    /// offset of the next reply to read, created by the first request.
+    replies: Option<(String, u64)>,
+    next_request_id: u64,
+}
+
+impl Client {
+    /// Connect to the server listening on the supplied address.
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Client> {
+        let stream = TcpStream::connect(addr).map_err(protocol::Error::from)?;
+        Client::from_stream(stream)
+    }
+
+    /// Connect to the server listening on the supplied address, failing any
+    /// connect, read, or write that takes longer than the supplied timeout.
+    pub fn connect_timeout(addr: &SocketAddr, timeout: Duration) -> Result<Client> {
+        let stream = TcpStream::connect_timeout(addr, timeout).map_err(protocol::Error::from)?;
+        stream
+            .set_read_timeout(Some(timeout))
+            .and_then(|_| stream.set_write_timeout(Some(timeout)))
+            .map_err(protocol::Error::from)?;
+        Client::from_stream(stream)
+    }
+
+    fn from_stream(stream: TcpStream) -> Result<Client> {
+        stream.set_nodelay(true).map_err(protocol::Error::from)?;
+        Ok(Client {
+            reader: BufReader::new(stream.try_clone().map_err(protocol::Error::from)?),
+            writer: BufWriter::new(stream),
+            next_correlation_id: 0,
+            pending: VecDeque::new(),
+            compression: Compression::None,
+            replies: None,
+            next_request_id: 0,
+        })
+    }
+
+    /// Ask the server to store produced records compressed with the supplied
+    /// codec, unless the topic forces its own.
+    pub fn with_compression(mut self, compression: Compression) -> Client {
+        self.compression = compression;
+        self
+    }
+
+    /// Send a request without waiting for its response, returning the
+    /// correlation id assigned to it.
+    pub fn send(&mut self, request: &Request) -> Result<u32> {
+        let correlation_id = self.next_correlation_id;
+        self.next_correlation_id = self.next_correlation_id.wrapping_add(1);
+
+        write_frame(&mut self.writer, &request.encode(correlation_id))?;
+        self.pending.push_back((correlation_id, request.api_key()));
+        Ok(correlation_id)
+    }
+
+    /// Wait for the response to the oldest outstanding request.
+    pub fn receive(&mut self) -> Result<(u32, std::result::Result<Response, ResponseError>)> {
+        let (expected, api_key) = self.pending.pop_front().ok_or(Error::NoPendingRequest)?;
+        let buf = match read_frame(&mut self.reader, u32::MAX)? {
+            Some(Frame::Complete(buf)) => buf,
+            _ => return Err(Error::Closed),
+        };
+
+        let mut reader = Reader::new(&buf);
+        let correlation_id = Response::decode_correlation_id(&mut reader)?;
+        if correlation_id != expected {
+            return Err(Error::CorrelationMismatch {
+                expected,
+                got: correlation_id,
+            });
+        }
+        Ok((correlation_id, Response::decode(api_key, &mut reader)?))
+    }
+
+    /// Send a request and wait for its response.
+    pub fn call(&mut self, request: &Request) -> Result<Response> {
+        let expected = request.api_key();
+        self.send(request)?;
+        let response = self.receive()?.1?;
+        if response.api_key() != expected {
+            return Err(Error::UnexpectedResponse {
+                expected,
+                got: response.api_key(),
+            });
+        }
+        Ok(response)
+    }
+
+    /// List the apis supported by the server.
+    pub fn api_versions(&mut self) -> Result<ApiVersionsResponse> {
+        match self.call(&Request::ApiVersions)? {
+            Response::ApiVersions(resp) => Ok(resp),
+            other => Err(unexpected(ApiKey::ApiVersions, &other)),
+        }
+    }
+
+    /// Describe the supplied topics, or every topic if empty.
+    pub fn metadata(&mut self, topics: Vec<String>) -> Result<MetadataResponse> {
+        match self.call(&Request::Metadata(MetadataRequest { topics }))? {
+            Response::Metadata(resp) => Ok(resp),
+            other => Err(unexpected(ApiKey::Metadata, &other)),
+        }
+    }
+
+    /// Create a new topic.
+    pub fn create_topic(
+        &mut self,
+        name: &str,
+        partitions: u32,
+        config: Vec<(String, String)>,
+    ) -> Result<()> {
+        let req = CreateTopicRequest {
+            name: name.to_owned(),
+            partitions,
+            config,
+        };
+        self.call(&Request::CreateTopic(req)).map(|_| ())
+    }
+
+    /// Delete a topic.
+    pub fn delete_topic(&mut self, name: &str) -> Result<()> {
+        let req = DeleteTopicRequest {
+            name: name.to_owned(),
+        };
+        self.call(&Request::DeleteTopic(req)).map(|_| ())
+    }
+
+    /// Append records to a topic, returning where each record was written.
+    pub fn produce(
+        &mut self,
+        topic: &str,
+        partitioning: Partitioning,
+        records: Vec<Record>,
+    ) -> Result<Vec<ProducedRecord>> {
+        self.send_produce(ProduceRequest {
+            topic: topic.to_owned(),
+            partitioning,
+            records,
+            producer: None,
+            compression: self.compression,
+        })
+    }
+
+    /// Create a single partition topic to receive replies on, which the server
+    /// deletes once this connection closes.
+    pub fn create_temporary_queue(&mut self) -> Result<String> {
+        match self.call(&Request::CreateTemporaryQueue)? {
+            Response::CreateTemporaryQueue(resp) => Ok(resp.topic),
+            other => Err(unexpected(ApiKey::CreateTemporaryQueue, &other)),
+        }
+    }
+
+    /// Produce a request record to a topic and wait up to `timeout` for its
+    /// reply. Requests say where to reply with a temporary queue created for
+    /// this connection on first use, and carry a correlation id the reply must
+    /// echo, see [Client::reply]. Replies to earlier requests that timed out
+    /// are discarded.
+    pub fn request(&mut self, topic: &str, record: Record, timeout: Duration) -> Result<Record> {
+        let deadline = Instant::now() + timeout;
+        let (queue, mut offset) = match self.replies.clone() {
+            Some(replies) => replies,
+            None => (self.create_temporary_queue()?, 0),
+        };
+        self.replies = Some((queue.clone(), offset));
+        let correlation_id = self.next_request_id.to_string();
+        self.next_request_id += 1;
+
+        let record = record
+            .with_reply_to(&queue)
+            .with_correlation_id(&correlation_id);
+        self.produce(topic, Partitioning::Key, vec![record])?;
+        loop {
+            let fetched = self.fetch(&queue, 0, offset, REPLY_MAX_BYTES)?;
+            for reply in fetched.records {
+                offset = reply.offset + 1;
+                self.replies = Some((queue.clone(), offset));
+                if reply.record.correlation_id() == Some(correlation_id.as_str()) {
+                    return Ok(reply.record);
+                }
+            }
+            offset = offset.max(fetched.next_offset);
+            self.replies = Some((queue.clone(), offset));
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(Error::ReplyTimeout {
+                    correlation_id,
+                    timeout,
+                });
+            }
+            thread::sleep(remaining.min(REPLY_POLL_INTERVAL));
+        }
+    }
+
+    /// Produce a reply to a request record made with [Client::request] to the
+    /// topic it says to reply to, echoing its correlation id.
+    pub fn reply(&mut self, request: &Record, reply: Record) -> Result<ProducedRecord> {
+        let topic = request.reply_to().ok_or(Error::NoReplyTo)?.to_owned();
+        let reply = match request.correlation_id() {
+            Some(correlation_id) => reply.with_correlation_id(correlation_id),
+            None => reply,
+        };
+        let mut produced = self.produce(&topic, Partitioning::Key, vec![reply])?;
+        produced.pop().ok_or(Error::UnexpectedResponse {
+            expected: ApiKey::Produce,
+            got: ApiKey::Produce,
+        })
+    }
+
+    /// Assign a new idempotent producer id.
+    pub fn init_producer(&mut self) -> Result<InitProducerResponse> {
+        self.send_init_producer(InitProducerRequest {
+            transactional_id: None,
+            transaction_timeout_ms: 0,
+        })
+    }
+
+    /// Initialize the producer of a transactional id, fencing off any earlier
+    /// instance of it and aborting the transaction it left ongoing.
+    pub fn init_transactional_producer(
+        &mut self,
+        transactional_id: &str,
+        transaction_timeout_ms: u32,
+    ) -> Result<InitProducerResponse> {
+        self.send_init_producer(InitProducerRequest {
+            transactional_id: Some(transactional_id.to_owned()),
+            transaction_timeout_ms,
+        })
+    }
+
+    fn send_init_producer(&mut self, req: InitProducerRequest) -> Result<InitProducerResponse> {
+        match self.call(&Request::InitProducer(req))? {
+            Response::InitProducer(resp) => Ok(resp),
+            other => Err(unexpected(ApiKey::InitProducer, &other)),
+        }
+    }
+
+    /// Add partitions to a transactional producer's current transaction,
+    /// beginning one if none is in progress.
+    pub fn add_partitions_to_txn(&mut self, req: AddPartitionsToTxnRequest) -> Result<()> {
+        self.call(&Request::AddPartitionsToTxn(req)).map(|_| ())
+    }
+
+    /// Commit or abort a transactional producer's current transaction.
+    pub fn end_txn(&mut self, req: EndTxnRequest) -> Result<()> {
+        self.call(&Request::EndTxn(req)).map(|_| ())
+    }
+
+    /// Write markers completing a transaction to partitions led by the server.
+    pub fn write_txn_markers(&mut self, req: WriteTxnMarkersRequest) -> Result<()> {
+        self.call(&Request::WriteTxnMarkers(req)).map(|_| ())
+    }
+
+    /// Append a batch of records written by an idempotent producer to a single
+    /// partition, returning where each record was written. Retrying the same
+    /// batch returns where it was originally written rather than writing it again.
+    pub fn produce_idempotent(
+        &mut self,
+        topic: &str,
+        partition: u32,
+        records: Vec<Record>,
+        producer: ProducerBatch,
+    ) -> Result<Vec<ProducedRecord>> {
+        self.send_produce(ProduceRequest {
+            topic: topic.to_owned(),
+            partitioning: Partitioning::Explicit(partition),
+            records,
+            producer: Some(producer),
+            compression: self.compression,
+        })
+    }
+
+    fn send_produce(&mut self, req: ProduceRequest) -> Result<Vec<ProducedRecord>> {
+        match self.call(&Request::Produce(req))? {
+            Response::Produce(resp) => Ok(resp.records),
+            other => Err(unexpected(ApiKey::Produce, &other)),
+        }
+    }
+
+    /// Read records from a single partition, converting partition level
+    /// failures into errors.
+    pub fn fetch(
+        &mut self,
+        topic: &str,
+        partition: u32,
+        offset: u64,
+        max_bytes: u32,
+    ) -> Result<FetchedPartition> {
+        self.fetch_isolated(
+            topic,
+            partition,
+            offset,
+            max_bytes,
+            IsolationLevel::ReadUncommitted,
+        )
+    }
+
+    /// Read records from a single partition like [Client::fetch], at the
+    /// supplied isolation level.
+    pub fn fetch_isolated(
+        &mut self,
+        topic: &str,
+        partition: u32,
+        offset: u64,
+        max_bytes: u32,
+        isolation: IsolationLevel,
+    ) -> Result<FetchedPartition> {
+        self.send_fetch(FetchRequest {
+            partitions: vec![FetchPartition {
+                topic: topic.to_owned(),
+                partition,
+                offset,
+                max_bytes,
+            }],
+            isolation,
+            selector: String::new(),
+        })
+    }
+
+    /// Read records from a single partition like [Client::fetch_isolated],
+    /// returning only those matching the supplied selector. Records that do not
+    /// match are skipped by the server, so the records returned may not be
+    /// contiguous, and reading should continue from the returned
+    /// [FetchedPartition::next_offset].
+    pub fn fetch_selected(
+        &mut self,
+        topic: &str,
+        partition: u32,
+        offset: u64,
+        max_bytes: u32,
+        isolation: IsolationLevel,
+        selector: &Selector,
+    ) -> Result<FetchedPartition> {
+        self.send_fetch(FetchRequest {
+            partitions: vec![FetchPartition {
+                topic: topic.to_owned(),
+                partition,
+                offset,
+                max_bytes,
+            }],
+            isolation,
+            selector: selector.as_str().to_owned(),
+        })
+    }
+
+    /// Send a fetch request for a single partition, converting partition level
+    /// failures into errors.
+    fn send_fetch(&mut self, req: FetchRequest) -> Result<FetchedPartition> {
+        let mut resp = match self.call(&Request::Fetch(req))? {
+            Response::Fetch(resp) => resp,
+            other => return Err(unexpected(ApiKey::Fetch, &other)),
+        };
+        let fetched = resp.partitions.pop().ok_or(Error::UnexpectedResponse {
+            expected: ApiKey::Fetch,
+            got: ApiKey::Fetch,
+        })?;
+        if fetched.error_code != protocol::ErrorCode::None {
+            return Err(ResponseError::new(
+                fetched.error_code,
+                format!(
+                    "failed to fetch '{}' partition {}",
+                    fetched.topic, fetched.partition
+                ),
+            )
+            .into());
+        }
+        Ok(fetched)
+    }
+
+    /// Returns the earliest offset of a single partition with a timestamp at or
+    /// after the supplied time, in milliseconds since the epoch, or its high
+    /// watermark if there is no such record. Partition level failures are
+    /// converted into errors.
+    pub fn offset_for_timestamp(
+        &mut self,
+        topic: &str,
+        partition: u32,
+        timestamp: i64,
+    ) -> Result<u64> {
+        let req = OffsetsForTimesRequest {
+            partitions: vec![PartitionTimestamp {
+                topic: topic.to_owned(),
+                partition,
+                timestamp,
+            }],
+        };
+        let mut resp = match self.call(&Request::OffsetsForTimes(req))? {
+            Response::OffsetsForTimes(resp) => resp,
+            other => return Err(unexpected(ApiKey::OffsetsForTimes, &other)),
+        };
+        let found = resp.partitions.pop().ok_or(Error::UnexpectedResponse {
+            expected: ApiKey::OffsetsForTimes,
+            got: ApiKey::OffsetsForTimes,
+        })?;
+        if found.error_code != protocol::ErrorCode::None {
+            return Err(ResponseError::new(
+                found.error_code,
+                format!("failed to look up '{}' partition {}", topic, partition),
+            )
+            .into());
+        }
+        Ok(found.offset)
+    }
+
+    /// Commit consumed offsets on behalf of a group.
+    pub fn commit_offsets(&mut self, group: &str, offsets: Vec<PartitionOffset>) -> Result<()> {
+        let req = CommitOffsetRequest {
+            group: group.to_owned(),
+            offsets,
+        };
+        self.call(&Request::CommitOffset(req)).map(|_| ())
+    }
+
+    /// Returns the offsets committed by a group for the listed partitions, or for
+    /// every partition it has committed if none are listed.
+    pub fn fetch_offsets(
+        &mut self,
+        group: &str,
+        partitions: Vec<TopicPartition>,
+    ) -> Result<Vec<PartitionOffset>> {
+        let req = FetchOffsetsRequest {
+            group: group.to_owned(),
+            partitions,
+        };
+        match self.call(&Request::FetchOffsets(req))? {
+            Response::FetchOffsets(resp) => Ok(resp.offsets),
+            other => Err(unexpected(ApiKey::FetchOffsets, &other)),
+        }
+    }
+
+    /// Move a group's committed offsets for the listed partitions, or for every
+    /// partition it has committed if none are listed, returning the new offsets.
+    pub fn reset_offsets(
+        &mut self,
+        group: &str,
+        partitions: Vec<TopicPartition>,
+        reset: OffsetReset,
+    ) -> Result<Vec<PartitionOffset>> {
+        let req = ResetOffsetsRequest {
+            group: group.to_owned(),
+            partitions,
+            reset,
+        };
+        match self.call(&Request::ResetOffsets(req))? {
+            Response::ResetOffsets(resp) => Ok(resp.offsets),
+            other => Err(unexpected(ApiKey::ResetOffsets, &other)),
+        }
+    }
+
+    /// Keep the connection alive.
+    pub fn heartbeat(&mut self) -> Result<()> {
+        self.call(&Request::Heartbeat).map(|_| ())
+    }
+
+    /// Join a consumer group, or rejoin it with a previously assigned member id.
+    pub fn join_group(&mut self, req: JoinGroupRequest) -> Result<GroupAssignmentResponse> {
+        match self.call(&Request::JoinGroup(req))? {
+            Response::JoinGroup(resp) => Ok(resp),
+            other => Err(unexpected(ApiKey::JoinGroup, &other)),
+        }
+    }
+
+    /// Keep a consumer group membership alive, returning the member's current assignment.
+    pub fn group_heartbeat(
+        &mut self,
+        group: &str,
+        member_id: &str,
+    ) -> Result<GroupAssignmentResponse> {
+        let req = GroupMemberRequest {
+            group: group.to_owned(),
+            member_id: member_id.to_owned(),
+        };
+        match self.call(&Request::GroupHeartbeat(req))? {
+            Response::GroupHeartbeat(resp) => Ok(resp),
+            other => Err(unexpected(ApiKey::GroupHeartbeat, &other)),
+        }
+    }
+
+    /// Returns the node coordinating a consumer group, or none when the server
+    /// is not part of a cluster and coordinates every group itself.
+    pub fn find_coordinator(&mut self, group: &str) -> Result<Option<NodeMetadata>> {
+        let req = FindCoordinatorRequest {
+            group: group.to_owned(),
+        };
+        match self.call(&Request::FindCoordinator(req))? {
+            Response::FindCoordinator(resp) => Ok(resp.coordinator),
+            other => Err(unexpected(ApiKey::FindCoordinator, &other)),
+        }
+    }
+
+    /// Leave a consumer group, releasing the member's partitions to the rest of the group.
+    pub fn leave_group(&mut self, group: &str, member_id: &str) -> Result<()> {
+        let req = GroupMemberRequest {
+            group: group.to_owned(),
+            member_id: member_id.to_owned(),
+        };
+        self.call(&Request::LeaveGroup(req)).map(|_| ())
+    }
+
+    /// Lease messages of a topic to a consumer of a queue until they are
+    /// acknowledged or their visibility timeout passes.
+    pub fn lease(&mut self, req: LeaseRequest) -> Result<Vec<LeasedMessage>> {
+        match self.call(&Request::Lease(req))? {
+            Response::Lease(resp) => Ok(resp.messages),
+            other => Err(unexpected(ApiKey::Lease, &other)),
+        }
+    }
+
+    /// Acknowledge messages leased to a queue consumer so they are never redelivered.
+    pub fn ack(&mut self, req: AckRequest) -> Result<()> {
+        self.call(&Request::Ack(req)).map(|_| ())
+    }
+
+    /// Release messages leased to a queue consumer for immediate redelivery.
+    pub fn nack(&mut self, req: NackRequest) -> Result<()> {
+        self.call(&Request::Nack(req)).map(|_| ())
+    }
+
+    /// Move messages of a dead-letter topic partition back to the partitions
+    /// they were originally written to, returning how many were moved.
+    pub fn replay_dead_letters(&mut self, req: ReplayDeadLettersRequest) -> Result<u32> {
+        match self.call(&Request::ReplayDeadLetters(req))? {
+            Response::ReplayDeadLetters(resp) => Ok(resp.replayed),
+            other => Err(unexpected(ApiKey::ReplayDeadLetters, &other)),
+        }
+    }
+
+    /// Schedule records for delivery to a partition, returning the id and
+    /// delivery time assigned to each of them.
+    pub fn schedule(&mut self, req: ScheduleRequest) -> Result<Vec<ScheduledMessage>> {
+        match self.call(&Request::Schedule(req))? {
+            Response::Schedule(resp) => Ok(resp.messages),
+            other => Err(unexpected(ApiKey::Schedule, &other)),
+        }
+    }
+
+    /// Declare an exchange, which succeeds without change if it already exists
+    /// with the same type.
+    pub fn declare_exchange(&mut self, name: &str, kind: ExchangeType) -> Result<()> {
+        let req = DeclareExchangeRequest {
+            name: name.to_owned(),
+            kind,
+        };
+        self.call(&Request::DeclareExchange(req)).map(|_| ())
+    }
+
+    /// Delete an exchange along with its bindings.
+    pub fn delete_exchange(&mut self, name: &str) -> Result<()> {
+        let req = DeleteExchangeRequest {
+            name: name.to_owned(),
+        };
+        self.call(&Request::DeleteExchange(req)).map(|_| ())
+    }
+
+    /// Bind an exchange to a destination topic.
+    pub fn bind(&mut self, binding: Binding) -> Result<()> {
+        self.call(&Request::Bind(BindRequest { binding }))
+            .map(|_| ())
+    }
+
+    /// Remove a binding of an exchange to a destination topic.
+    pub fn unbind(&mut self, binding: Binding) -> Result<()> {
+        self.call(&Request::Unbind(BindRequest { binding }))
+            .map(|_| ())
+    }
+
+    /// Publish a record to an exchange, returning where it was appended in each
+    /// topic it was routed to.
+    pub fn publish(
+        &mut self,
+        exchange: &str,
+        routing_key: &str,
+        record: Record,
+    ) -> Result<Vec<RoutedRecord>> {
+        let req = PublishRequest {
+            exchange: exchange.to_owned(),
+            routing_key: routing_key.to_owned(),
+            record,
+        };
+        match self.call(&Request::Publish(req))? {
+            Response::Publish(resp) => Ok(resp.records),
+            other => Err(unexpected(ApiKey::Publish, &other)),
+        }
+    }
+
+    /// Request a replica's vote in a partition leader election.
+    pub fn raft_vote(&mut self, req: VoteRequest) -> Result<VoteResponse> {
+        match self.call(&Request::RaftVote(req))? {
+            Response::RaftVote(resp) => Ok(resp),
+            other => Err(unexpected(ApiKey::RaftVote, &other)),
+        }
+    }
+
+    /// Replicate entries to a partition follower.
+    pub fn raft_append(&mut self, req: AppendRequest) -> Result<AppendResponse> {
+        match self.call(&Request::RaftAppend(req))? {
+            Response::RaftAppend(resp) => Ok(resp),
+            other => Err(unexpected(ApiKey::RaftAppend, &other)),
+        }
+    }
+
+    /// Restart a partition follower's log at the leader's first retained offset.
+    pub fn raft_snapshot(&mut self, req: SnapshotRequest) -> Result<AppendResponse> {
+        match self.call(&Request::RaftSnapshot(req))? {
+            Response::RaftSnapshot(resp) => Ok(resp),
+            other => Err(unexpected(ApiKey::RaftSnapshot, &other)),
+        }
+    }
+}
+
+fn unexpected(expected: ApiKey, got: &Response) -> Error {
+    Error::UnexpectedResponse {
+        expected,
+        got: got.api_key(),
+    }
+}
@@ -0,0 +1,757 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::{SocketAddr, ToSocketAddrs},
+    thread,
+    time::Duration,
+};
+
+use crate::protocol::{
+    self, AckRequest, AddPartitionsToTxnRequest, EndTxnRequest, ErrorCode, FetchedPartition,
+    GroupAssignmentResponse, InitProducerResponse, JoinGroupRequest, LeaseRequest, LeasedMessage,
+    MetadataResponse, NackRequest, PartitionOffset, ProducedRecord, ReplayDeadLettersRequest,
+    ScheduleRequest, ScheduledMessage, ScheduledRecord,
+};
+use crate::record::{Compression, ProducerBatch, Record};
+use crate::selector::Selector;
+use crate::topic::{partition_for_key, IsolationLevel, Partitioning, TopicPartition};
+
+use super::client::Client;
+use super::error::{Error, Result};
+
+/// A blocking client for a cluster of riftd nodes, routing produce and fetch
+/// requests to the leader of each partition, and consumer group requests to
+/// the group's coordinator.
+///
+/// The cluster's metadata is fetched from the bootstrap addresses, or any node
+/// learned of since, and refreshed whenever a request reaches a node that no
+/// longer leads the partition or the leader can not be reached. Each group's
+/// coordinator is looked up the first time the group is used, and again once
+/// a request reaches a node that no longer coordinates it. Against a server
+/// that is not part of a cluster every request is sent to the server that
+/// answered the last metadata request.
+///
+/// Once made idempotent with [ClusterClient::idempotent], batches retried after
+/// a failure are written at most once, as each batch carries the producer's next
+/// sequence number for its partition until it succeeds.
+///
+/// Once made transactional with [ClusterClient::transactional], records may
+/// only be produced between [ClusterClient::begin_transaction] and committing
+/// or aborting the transaction. The transaction is coordinated by the node the
+/// producer was initialized against.
+pub struct ClusterClient {
+    bootstrap: Vec<SocketAddr>,
+    timeout: Duration,
+    retries: usize,
+    retry_backoff: Duration,
+    metadata: MetadataResponse,
+    source: Option<SocketAddr>,
+    clients: BTreeMap<SocketAddr, Client>,
+    round_robin: u32,
+    producer: Option<InitProducerResponse>,
+    sequences: BTreeMap<(String, u32), u32>,
+    transactional_id: Option<String>,
+    coordinator: Option<SocketAddr>,
+    transaction: Option<BTreeSet<TopicPartition>>,
+    groups: BTreeMap<String, SocketAddr>,
+    isolation: IsolationLevel,
+    selector: Option<Selector>,
+    compression: Compression,
+}
+
+impl ClusterClient {
+    /// Connect to the cluster reachable through the supplied bootstrap
+    /// addresses, failing any connect, read, or write that takes longer than
+    /// the supplied timeout.
+    pub fn connect(bootstrap: impl ToSocketAddrs, timeout: Duration) -> Result<ClusterClient> {
+        let bootstrap = bootstrap
+            .to_socket_addrs()
+            .map_err(protocol::Error::from)?
+            .collect();
+        let mut client = ClusterClient {
+            bootstrap,
+            timeout,
+            retries: 5,
+            retry_backoff: Duration::from_millis(100),
+            metadata: MetadataResponse {
+                nodes: Vec::new(),
+                topics: Vec::new(),
+            },
+            source: None,
+            clients: BTreeMap::new(),
+            round_robin: 0,
+            producer: None,
+            sequences: BTreeMap::new(),
+            transactional_id: None,
+            coordinator: None,
+            transaction: None,
+            groups: BTreeMap::new(),
+            isolation: IsolationLevel::ReadUncommitted,
+            selector: None,
+            compression: Compression::None,
+        };
+        client.refresh()?;
+        Ok(client)
+    }
+
+    /// Retry requests that fail because leadership moved or a node could not be
+    /// reached up to the supplied number of times, waiting the supplied backoff
+    /// before refreshing the cluster metadata and trying again.
+    pub fn with_retries(mut self, retries: usize, backoff: Duration) -> ClusterClient {
+        self.retries = retries;
+        self.retry_backoff = backoff;
+        self
+    }
+
+    /// Assign this client an idempotent producer id, which every subsequent
+    /// produce request is written with.
+    pub fn idempotent(mut self) -> Result<ClusterClient> {
+        let addr = self.source.ok_or(Error::NoNodes)?;
+        let producer = self.client(addr)?.init_producer()?;
+        self.producer = Some(producer);
+        self.sequences.clear();
+        Ok(self)
+    }
+
+    /// Initialize this client as the producer of the supplied transactional id,
+    /// fencing off any earlier instance of it. Transactions left ongoing for
+    /// longer than the supplied timeout are aborted by the coordinator.
+    pub fn transactional(
+        mut self,
+        transactional_id: &str,
+        timeout: Duration,
+    ) -> Result<ClusterClient> {
+        let addr = self.source.ok_or(Error::NoNodes)?;
+        let producer = self
+            .client(addr)?
+            .init_transactional_producer(transactional_id, timeout.as_millis() as u32)?;
+        self.producer = Some(producer);
+        self.sequences.clear();
+        self.transactional_id = Some(transactional_id.to_owned());
+        self.coordinator = Some(addr);
+        self.transaction = None;
+        Ok(self)
+    }
+
+    /// Read records at the supplied isolation level.
+    pub fn with_isolation(mut self, isolation: IsolationLevel) -> ClusterClient {
+        self.isolation = isolation;
+        self
+    }
+
+    /// Only read records matching the supplied selector, which the server
+    /// filters so records that do not match are never sent.
+    pub fn with_selector(mut self, selector: Selector) -> ClusterClient {
+        self.selector = Some(selector);
+        self
+    }
+
+    /// Ask the cluster to store produced records compressed with the supplied
+    /// codec, unless their topic forces its own.
+    pub fn with_compression(mut self, compression: Compression) -> ClusterClient {
+        self.compression = compression;
+        for client in self.clients.values_mut() {
+            client.compression = compression;
+        }
+        self
+    }
+
+    /// Begin a transaction, which every record produced until it is committed or
+    /// aborted is written as part of.
+    pub fn begin_transaction(&mut self) -> Result<()> {
+        if self.transactional_id.is_none() {
+            return Err(Error::NotTransactional);
+        }
+        if self.transaction.is_some() {
+            return Err(Error::TransactionInProgress);
+        }
+        self.transaction = Some(BTreeSet::new());
+        Ok(())
+    }
+
+    /// Commit the current transaction, making its records visible to read
+    /// committed consumers.
+    pub fn commit_transaction(&mut self) -> Result<()> {
+        self.end_transaction(true)
+    }
+
+    /// Abort the current transaction, hiding its records from read committed
+    /// consumers.
+    pub fn abort_transaction(&mut self) -> Result<()> {
+        self.end_transaction(false)
+    }
+
+    fn end_transaction(&mut self, commit: bool) -> Result<()> {
+        let (transactional_id, coordinator) = self.coordinator()?;
+        let producer = self.producer.ok_or(Error::NotTransactional)?;
+        let partitions = self.transaction.take().ok_or(Error::NoTransaction)?;
+        if partitions.is_empty() {
+            return Ok(());
+        }
+        self.client(coordinator)?.end_txn(EndTxnRequest {
+            transactional_id,
+            producer_id: producer.producer_id,
+            epoch: producer.epoch,
+            commit,
+        })
+    }
+
+    /// Add the supplied partition to the current transaction, if it is not already part of it.
+    fn add_to_transaction(&mut self, topic: &str, partition: u32) -> Result<()> {
+        let (transactional_id, coordinator) = self.coordinator()?;
+        let producer = self.producer.ok_or(Error::NotTransactional)?;
+        let partition = TopicPartition::new(topic, partition);
+        match &self.transaction {
+            Some(partitions) if partitions.contains(&partition) => return Ok(()),
+            Some(_) => (),
+            None => return Err(Error::NoTransaction),
+        }
+        self.client(coordinator)?
+            .add_partitions_to_txn(AddPartitionsToTxnRequest {
+                transactional_id,
+                producer_id: producer.producer_id,
+                epoch: producer.epoch,
+                partitions: vec![partition.clone()],
+            })?;
+        if let Some(partitions) = &mut self.transaction {
+            partitions.insert(partition);
+        }
+        Ok(())
+    }
+
+    fn coordinator(&self) -> Result<(String, SocketAddr)> {
+        match (&self.transactional_id, self.coordinator) {
+            (Some(transactional_id), Some(coordinator)) => {
+                Ok((transactional_id.clone(), coordinator))
+            }
+            _ => Err(Error::NotTransactional),
+        }
+    }
+
+    /// Returns the idempotent producer id assigned to this client, if any.
+    pub fn producer_id(&self) -> Option<u64> {
+        self.producer.map(|producer| producer.producer_id)
+    }
+
+    /// Returns the cluster metadata as of the last refresh.
+    pub fn metadata(&self) -> &MetadataResponse {
+        &self.metadata
+    }
+
+    /// Returns the id of the supplied partition's leader as of the last refresh.
+    pub fn leader(&self, topic: &str, partition: u32) -> Option<u32> {
+        self.metadata
+            .topics
+            .iter()
+            .find(|meta| meta.name == topic)
+            .and_then(|meta| meta.partitions.iter().find(|meta| meta.id == partition))
+            .and_then(|meta| meta.leader)
+    }
+
+    /// Fetch the cluster metadata from the first node that answers, trying the
+    /// nodes already known before the bootstrap addresses.
+    pub fn refresh(&mut self) -> Result<()> {
+        let mut addrs: Vec<SocketAddr> = self
+            .metadata
+            .nodes
+            .iter()
+            .filter_map(|node| node.address.parse().ok())
+            .collect();
+        addrs.extend(self.bootstrap.iter().copied());
+
+        let mut last = Error::NoNodes;
+        for addr in addrs {
+            match self
+                .client(addr)
+                .and_then(|client| client.metadata(Vec::new()))
+            {
+                Ok(metadata) => {
+                    self.metadata = metadata;
+                    self.source = Some(addr);
+                    return Ok(());
+                }
+                Err(err) => {
+                    self.clients.remove(&addr);
+                    last = err;
+                }
+            }
+        }
+        Err(last)
+    }
+
+    /// Append records to a topic, returning where each record was written.
+    ///
+    /// Records are assigned to partitions like the server would, then sent to
+    /// each partition's leader as a separate batch.
+    pub fn produce(
+        &mut self,
+        topic: &str,
+        partitioning: Partitioning,
+        records: Vec<Record>,
+    ) -> Result<Vec<ProducedRecord>> {
+        let count = self.partitions(topic)?;
+        let mut batches: Vec<(u32, Vec<usize>, Vec<Record>)> = Vec::new();
+        for (idx, record) in records.into_iter().enumerate() {
+            let partition = self.assign(partitioning, record.key.as_deref(), count);
+            match batches.iter_mut().find(|batch| batch.0 == partition) {
+                Some(batch) => {
+                    batch.1.push(idx);
+                    batch.2.push(record);
+                }
+                None => batches.push((partition, vec![idx], vec![record])),
+            }
+        }
+
+        let transactional = self.transactional_id.is_some();
+        if transactional && self.transaction.is_none() {
+            return Err(Error::NoTransaction);
+        }
+
+        let mut produced = Vec::new();
+        for (partition, indexes, records) in batches {
+            if transactional {
+                self.add_to_transaction(topic, partition)?;
+            }
+            let key = (topic.to_owned(), partition);
+            let producer = self.producer.map(|producer| ProducerBatch {
+                producer_id: producer.producer_id,
+                epoch: producer.epoch,
+                sequence: self.sequences.get(&key).copied().unwrap_or_default(),
+                transactional,
+            });
+            let count = records.len() as u32;
+            let written = self.with_leader(topic, partition, |client| match producer {
+                Some(producer) => {
+                    client.produce_idempotent(topic, partition, records.clone(), producer)
+                }
+                None => client.produce(topic, Partitioning::Explicit(partition), records.clone()),
+            })?;
+            if let Some(producer) = producer {
+                self.sequences
+                    .insert(key, producer.sequence.wrapping_add(count));
+            }
+            produced.extend(indexes.into_iter().zip(written));
+        }
+        produced.sort_unstable_by_key(|(idx, _)| *idx);
+        Ok(produced.into_iter().map(|(_, record)| record).collect())
+    }
+
+    /// Schedule records for delivery to a topic, returning the id and delivery
+    /// time assigned to each of them.
+    ///
+    /// Records are assigned to partitions like [ClusterClient::produce] does,
+    /// then sent to each partition's leader, which holds them until they are
+    /// due.
+    pub fn schedule(
+        &mut self,
+        topic: &str,
+        partitioning: Partitioning,
+        records: Vec<ScheduledRecord>,
+    ) -> Result<Vec<ScheduledMessage>> {
+        let count = self.partitions(topic)?;
+        let mut batches: Vec<(u32, Vec<usize>, Vec<ScheduledRecord>)> = Vec::new();
+        for (idx, record) in records.into_iter().enumerate() {
+            let partition = self.assign(partitioning, record.record.key.as_deref(), count);
+            match batches.iter_mut().find(|batch| batch.0 == partition) {
+                Some(batch) => {
+                    batch.1.push(idx);
+                    batch.2.push(record);
+                }
+                None => batches.push((partition, vec![idx], vec![record])),
+            }
+        }
+
+        let mut scheduled = Vec::new();
+        for (partition, indexes, records) in batches {
+            let req = ScheduleRequest {
+                topic: topic.to_owned(),
+                partition,
+                records,
+            };
+            let messages =
+                self.with_leader(topic, partition, |client| client.schedule(req.clone()))?;
+            scheduled.extend(indexes.into_iter().zip(messages));
+        }
+        scheduled.sort_unstable_by_key(|(idx, _)| *idx);
+        Ok(scheduled.into_iter().map(|(_, message)| message).collect())
+    }
+
+    /// Read records from a single partition's leader, at the isolation level
+    /// set with [ClusterClient::with_isolation] and filtered by the selector set
+    /// with [ClusterClient::with_selector].
+    pub fn fetch(
+        &mut self,
+        topic: &str,
+        partition: u32,
+        offset: u64,
+        max_bytes: u32,
+    ) -> Result<FetchedPartition> {
+        let isolation = self.isolation;
+        let selector = self.selector.clone();
+        self.with_leader(topic, partition, |client| match &selector {
+            Some(selector) => {
+                client.fetch_selected(topic, partition, offset, max_bytes, isolation, selector)
+            }
+            None => client.fetch_isolated(topic, partition, offset, max_bytes, isolation),
+        })
+    }
+
+    /// Returns the earliest offset of a single partition with a timestamp at or
+    /// after the supplied time, in milliseconds since the epoch, asking the
+    /// partition's leader.
+    pub fn offset_for_timestamp(
+        &mut self,
+        topic: &str,
+        partition: u32,
+        timestamp: i64,
+    ) -> Result<u64> {
+        self.with_leader(topic, partition, |client| {
+            client.offset_for_timestamp(topic, partition, timestamp)
+        })
+    }
+
+    /// Lease up to `max_messages` messages of a topic to a consumer of a queue,
+    /// asking the leader of each partition in turn until enough are leased.
+    /// Leases must be acknowledged, or released, through the same partition's
+    /// leader before the visibility timeout passes. Messages are redelivered
+    /// indefinitely unless the topic was created with a dead-letter topic.
+    pub fn lease(
+        &mut self,
+        queue: &str,
+        consumer_id: &str,
+        topic: &str,
+        max_messages: u32,
+        visibility_timeout: Duration,
+    ) -> Result<Vec<LeasedMessage>> {
+        let count = self.partitions(topic)?;
+        let mut leaders: BTreeMap<SocketAddr, Vec<u32>> = BTreeMap::new();
+        let mut stale = false;
+        for partition in 0..count {
+            match self.leader_addr(topic, partition) {
+                Ok(addr) => leaders.entry(addr).or_default().push(partition),
+                Err(_) => stale = true,
+            }
+        }
+        let leaders: Vec<(SocketAddr, Vec<u32>)> = leaders.into_iter().collect();
+        if leaders.is_empty() {
+            return Err(Error::NoLeader {
+                topic: topic.to_owned(),
+                partition: 0,
+            });
+        }
+
+        self.round_robin = self.round_robin.wrapping_add(1);
+        let start = self.round_robin as usize;
+        let mut messages = Vec::new();
+        let mut failed = None;
+        for idx in 0..leaders.len() {
+            let remaining = max_messages.saturating_sub(messages.len() as u32);
+            if remaining == 0 {
+                break;
+            }
+            let (addr, partitions) = &leaders[(start + idx) % leaders.len()];
+            let req = LeaseRequest {
+                queue: queue.to_owned(),
+                consumer_id: consumer_id.to_owned(),
+                topic: topic.to_owned(),
+                partitions: partitions.clone(),
+                max_messages: remaining,
+                visibility_timeout_ms: visibility_timeout.as_millis() as u32,
+            };
+            match self.client(*addr).and_then(|client| client.lease(req)) {
+                Ok(leased) => messages.extend(leased),
+                Err(err) if retriable(&err) => {
+                    if !matches!(err, Error::Response(_)) {
+                        self.clients.remove(addr);
+                    }
+                    stale = true;
+                    failed = Some(err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        // Leadership moved, so pick up the new leaders before the next lease.
+        if stale {
+            self.refresh()?;
+        }
+        match failed {
+            Some(err) if messages.is_empty() => Err(err),
+            _ => Ok(messages),
+        }
+    }
+
+    /// Acknowledge messages of a partition leased to a queue consumer so they
+    /// are never redelivered.
+    pub fn ack(
+        &mut self,
+        queue: &str,
+        consumer_id: &str,
+        topic: &str,
+        partition: u32,
+        offsets: &[u64],
+    ) -> Result<()> {
+        let req = ack_request(queue, consumer_id, topic, partition, offsets);
+        self.with_leader(topic, partition, |client| client.ack(req.clone()))
+    }
+
+    /// Release messages of a partition leased to a queue consumer that failed
+    /// to process them for immediate redelivery, recording why.
+    pub fn nack(
+        &mut self,
+        queue: &str,
+        consumer_id: &str,
+        topic: &str,
+        partition: u32,
+        offsets: &[u64],
+        error: &str,
+    ) -> Result<()> {
+        let req = NackRequest {
+            queue: queue.to_owned(),
+            consumer_id: consumer_id.to_owned(),
+            topic: topic.to_owned(),
+            partition,
+            offsets: offsets.to_vec(),
+            error: error.to_owned(),
+        };
+        self.with_leader(topic, partition, |client| client.nack(req.clone()))
+    }
+
+    /// Move up to `max_messages` messages of a dead-letter topic back to the
+    /// partitions they were originally written to, returning how many were
+    /// moved.
+    pub fn replay_dead_letters(&mut self, topic: &str, max_messages: u32) -> Result<u32> {
+        let mut replayed = 0;
+        for partition in 0..self.partitions(topic)? {
+            let remaining = max_messages.saturating_sub(replayed);
+            if remaining == 0 {
+                break;
+            }
+            let req = ReplayDeadLettersRequest {
+                topic: topic.to_owned(),
+                partition,
+                max_messages: remaining,
+            };
+            replayed += self.with_leader(topic, partition, |client| {
+                client.replay_dead_letters(req.clone())
+            })?;
+        }
+        Ok(replayed)
+    }
+
+    /// Join a consumer group through its coordinator, or rejoin it with a
+    /// previously assigned member id.
+    pub fn join_group(&mut self, req: JoinGroupRequest) -> Result<GroupAssignmentResponse> {
+        let group = req.group.clone();
+        self.with_coordinator(&group, |client| client.join_group(req.clone()))
+    }
+
+    /// Keep a consumer group membership alive, returning the member's current
+    /// assignment.
+    pub fn group_heartbeat(
+        &mut self,
+        group: &str,
+        member_id: &str,
+    ) -> Result<GroupAssignmentResponse> {
+        self.with_coordinator(group, |client| client.group_heartbeat(group, member_id))
+    }
+
+    /// Leave a consumer group, releasing the member's partitions to the rest of
+    /// the group.
+    pub fn leave_group(&mut self, group: &str, member_id: &str) -> Result<()> {
+        self.with_coordinator(group, |client| client.leave_group(group, member_id))
+    }
+
+    /// Commit consumed offsets on behalf of a group.
+    pub fn commit_offsets(&mut self, group: &str, offsets: Vec<PartitionOffset>) -> Result<()> {
+        self.with_coordinator(group, |client| {
+            client.commit_offsets(group, offsets.clone())
+        })
+    }
+
+    /// Returns the offsets committed by a group for the listed partitions, or
+    /// for every partition it has committed if none are listed.
+    pub fn fetch_offsets(
+        &mut self,
+        group: &str,
+        partitions: Vec<TopicPartition>,
+    ) -> Result<Vec<PartitionOffset>> {
+        self.with_coordinator(group, |client| {
+            client.fetch_offsets(group, partitions.clone())
+        })
+    }
+
+    /// Returns the partition a record with the supplied key is written to out
+    /// of `count` partitions.
+    fn assign(&mut self, partitioning: Partitioning, key: Option<&[u8]>, count: u32) -> u32 {
+        match (partitioning, key) {
+            (Partitioning::Explicit(partition), _) => partition,
+            (Partitioning::Key, Some(key)) => partition_for_key(key, count),
+            (Partitioning::Key, None) | (Partitioning::RoundRobin, _) => {
+                self.round_robin = self.round_robin.wrapping_add(1);
+                self.round_robin % count
+            }
+        }
+    }
+
+    fn partitions(&mut self, topic: &str) -> Result<u32> {
+        for refreshed in [false, true] {
+            if refreshed {
+                self.refresh()?;
+            }
+            let found = self
+                .metadata
+                .topics
+                .iter()
+                .find(|meta| meta.name == topic && meta.error_code == ErrorCode::None);
+            if let Some(meta) = found.filter(|meta| !meta.partitions.is_empty()) {
+                return Ok(meta.partitions.len() as u32);
+            }
+        }
+        Err(protocol::ResponseError::new(
+            ErrorCode::TopicNotFound,
+            format!("topic '{}' does not exist", topic),
+        )
+        .into())
+    }
+
+    /// Run the supplied request against the partition's leader, refreshing the
+    /// metadata and retrying while leadership is moving.
+    fn with_leader<T>(
+        &mut self,
+        topic: &str,
+        partition: u32,
+        mut f: impl FnMut(&mut Client) -> Result<T>,
+    ) -> Result<T> {
+        let mut attempt = 0;
+        loop {
+            let addr = self.leader_addr(topic, partition);
+            let result = addr.and_then(|addr| match self.client(addr).and_then(&mut f) {
+                Err(err) if !matches!(err, Error::Response(_)) => {
+                    // The connection may be left mid response, so start over.
+                    self.clients.remove(&addr);
+                    Err(err)
+                }
+                result => result,
+            });
+            match result {
+                Err(err) if attempt < self.retries && retriable(&err) => {
+                    attempt += 1;
+                    thread::sleep(self.retry_backoff);
+                    if let Err(err) = self.refresh() {
+                        if attempt >= self.retries {
+                            return Err(err);
+                        }
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Run the supplied request against the group's coordinator, looking the
+    /// coordinator up again and retrying while it can not be reached or no
+    /// longer coordinates the group.
+    fn with_coordinator<T>(
+        &mut self,
+        group: &str,
+        mut f: impl FnMut(&mut Client) -> Result<T>,
+    ) -> Result<T> {
+        let mut attempt = 0;
+        loop {
+            let addr = self.coordinator_addr(group);
+            let result = addr.and_then(|addr| match self.client(addr).and_then(&mut f) {
+                Err(err) if !matches!(err, Error::Response(_)) => {
+                    // The connection may be left mid response, so start over.
+                    self.clients.remove(&addr);
+                    Err(err)
+                }
+                result => result,
+            });
+            match result {
+                Err(err) if attempt < self.retries && retriable(&err) => {
+                    attempt += 1;
+                    self.groups.remove(group);
+                    thread::sleep(self.retry_backoff);
+                    if let Err(err) = self.refresh() {
+                        if attempt >= self.retries {
+                            return Err(err);
+                        }
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn coordinator_addr(&mut self, group: &str) -> Result<SocketAddr> {
+        if let Some(addr) = self.groups.get(group) {
+            return Ok(*addr);
+        }
+        let source = self.source.ok_or(Error::NoNodes)?;
+        let addr = match self.client(source)?.find_coordinator(group)? {
+            Some(node) => node.address.parse().map_err(|_| Error::NoCoordinator {
+                group: group.to_owned(),
+            })?,
+            None => source,
+        };
+        self.groups.insert(group.to_owned(), addr);
+        Ok(addr)
+    }
+
+    fn leader_addr(&self, topic: &str, partition: u32) -> Result<SocketAddr> {
+        if self.metadata.nodes.is_empty() {
+            return self.source.ok_or(Error::NoNodes);
+        }
+        self.leader(topic, partition)
+            .and_then(|leader| self.metadata.nodes.iter().find(|node| node.id == leader))
+            .and_then(|node| node.address.parse().ok())
+            .ok_or_else(|| Error::NoLeader {
+                topic: topic.to_owned(),
+                partition,
+            })
+    }
+
+    fn client(&mut self, addr: SocketAddr) -> Result<&mut Client> {
+        if !self.clients.contains_key(&addr) {
+            let client =
+                Client::connect_timeout(&addr, self.timeout)?.with_compression(self.compression);
+            self.clients.insert(addr, client);
+        }
+        Ok(self
+            .clients
+            .get_mut(&addr)
+            .expect("client was just connected"))
+    }
+}
+
+fn ack_request(
+    queue: &str,
+    consumer_id: &str,
+    topic: &str,
+    partition: u32,
+    offsets: &[u64],
+) -> AckRequest {
+    AckRequest {
+        queue: queue.to_owned(),
+        consumer_id: consumer_id.to_owned(),
+        topic: topic.to_owned(),
+        partition,
+        offsets: offsets.to_vec(),
+    }
+}
+
+fn retriable(err: &Error) -> bool {
+    match err {
+        Error::Response(err) => {
+            matches!(err.code, ErrorCode::NotLeader | ErrorCode::NotCoordinator)
+        }
+        Error::Protocol(_)
+        | Error::Closed
+        | Error::NoLeader { .. }
+        | Error::NoCoordinator { .. } => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,97 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{result, time::Duration};
+
+use thiserror::Error;
+
+use crate::protocol::{self, ApiKey, ResponseError};
+
+/// Custom Result wrapper to simplify usage.
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents errors communicating with a rift server.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// Handles connection and framing failures.
+    #[error(transparent)]
+    Protocol(#[from] protocol::Error),
+    /// Handles requests the server rejected.
+    #[error("request failed: {0}")]
+    Response(#[from] ResponseError),
+    /// Handles the server closing the connection while responses were outstanding.
+    #[error("connection closed by server")]
+    Closed,
+    /// Handles responses read while no request was outstanding.
+    #[error("received a response without an outstanding request")]
+    NoPendingRequest,
+    /// Handles responses that do not match the oldest outstanding request.
+    #[error("expected a response to correlation id {expected} but got {got}")]
+    CorrelationMismatch {
+        /// The correlation id of the oldest outstanding request.
+        expected: u32,
+        /// The correlation id received.
+        got: u32,
+    },
+    /// Handles partitions whose leader is not known to the cluster.
+    #[error("no leader is known for '{topic}' partition {partition}")]
+    NoLeader {
+        /// The name of the topic.
+        topic: String,
+        /// The partition within the topic.
+        partition: u32,
+    },
+    /// Handles consumer groups whose coordinator is not known to the cluster.
+    #[error("no coordinator is known for group '{group}'")]
+    NoCoordinator {
+        /// The id of the consumer group.
+        group: String,
+    },
+    /// Handles transactional operations on a client that has no transactional id.
+    #[error("the client is not a transactional producer")]
+    NotTransactional,
+    /// Handles transactional produce, commit, or abort requests made outside a transaction.
+    #[error("no transaction is in progress")]
+    NoTransaction,
+    /// Handles beginning a transaction while another is in progress.
+    #[error("a transaction is already in progress")]
+    TransactionInProgress,
+    /// Handles clusters none of whose nodes could be reached.
+    #[error("no cluster node could be reached")]
+    NoNodes,
+    /// Handles requests not replied to before the timeout passed.
+    #[error("no reply to request {correlation_id} within {timeout:?}")]
+    ReplyTimeout {
+        /// The correlation id of the request.
+        correlation_id: String,
+        /// How long the reply was awaited.
+        timeout: Duration,
+    },
+    /// Handles replying to records that do not say where replies go.
+    #[error("the request has no reply to header")]
+    NoReplyTo,
+    /// Handles responses for a different api than the request.
+    #[error("expected a response to {expected:?} but got {got:?}")]
+    UnexpectedResponse {
+        /// The api of the outstanding request.
+        expected: ApiKey,
+        /// The api of the received response.
+        got: ApiKey,
+    },
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+    use crate::protocol::ErrorCode;
+
+    #[test]
+    fn test_display() {
+        let err = Error::from(ResponseError::new(ErrorCode::TopicNotFound, "missing"));
+        assert_eq!(
+            "request failed: the topic does not exist (5): missing",
+            format!("{}", err)
+        );
+    }
+}
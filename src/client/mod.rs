@@ -0,0 +1,11 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+#[allow(clippy::module_inception)]
+mod client;
+mod cluster;
+mod error;
+
+pub use self::client::Client;
+pub use self::cluster::ClusterClient;
+pub use self::error::{Error, Result};
@@ -0,0 +1,185 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{fmt, net::SocketAddr, str::FromStr};
+
+use structopt::StructOpt;
+
+use crate::topic;
+
+use super::error::Error;
+
+/// A node of the cluster, given as `<id>=<host>:<port>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeAddr {
+    /// The id of the node.
+    pub id: u32,
+    /// The address the node serves the native protocol on.
+    pub addr: SocketAddr,
+}
+
+impl FromStr for NodeAddr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidConfig {
+            reason: format!("invalid node '{}', expected <id>=<host>:<port>", s),
+        };
+        let (id, addr) = s.split_once('=').ok_or_else(invalid)?;
+        Ok(NodeAddr {
+            id: id.trim().parse().map_err(|_| invalid())?,
+            addr: addr.trim().parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl fmt::Display for NodeAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.id, self.addr)
+    }
+}
+
+#[derive(Debug, Clone, StructOpt)]
+/// Rift cluster membership configuration.
+pub struct Config {
+    #[structopt(
+        long = "node-id",
+        env = "RIFT_NODE_ID",
+        help = "The id of this node within the cluster.",
+        long_help = "Sets the id identifying this node to the rest of the cluster, which must be unique within the cluster.",
+        default_value = "1",
+        takes_value = true
+    )]
+    /// Define the id of this node.
+    pub node_id: u32,
+
+    #[structopt(
+        long = "seeds",
+        env = "RIFT_SEEDS",
+        help = "The seed nodes of the cluster.",
+        long_help = "Sets the comma separated list of the cluster's seed nodes, each given as <id>=<host>:<port>. Seeds replicate the cluster metadata and are contacted by every other node to join the cluster, so every node must be configured with the same seeds. Clustering is disabled when no seeds are configured.",
+        use_delimiter = true,
+        takes_value = true
+    )]
+    /// Define the seed nodes of the cluster.
+    pub seeds: Vec<NodeAddr>,
+
+    #[structopt(
+        long = "advertised-address",
+        env = "RIFT_ADVERTISED_ADDRESS",
+        help = "The address other nodes and clients should use to reach this node.",
+        long_help = "Sets the address this node registers with the cluster, which other nodes and clients use to reach it. Defaults to the listen address.",
+        takes_value = true
+    )]
+    /// Define the address this node is reachable at.
+    pub advertised_address: Option<SocketAddr>,
+}
+
+impl Config {
+    /// Returns whether or not this node is part of a cluster, which requires
+    /// at least one seed.
+    pub fn enabled(&self) -> bool {
+        !self.seeds.is_empty()
+    }
+
+    /// Returns the address this node registers with the cluster, falling back
+    /// to the supplied listen address.
+    pub fn advertised_address(&self, listen_address: SocketAddr) -> SocketAddr {
+        self.advertised_address.unwrap_or(listen_address)
+    }
+
+    /// Returns whether or not this node is one of the seeds.
+    pub fn is_seed(&self) -> bool {
+        self.seeds.iter().any(|seed| seed.id == self.node_id)
+    }
+
+    /// Returns the seed coordinating the supplied consumer group, which keeps
+    /// the group's membership and committed offsets. Every node is configured
+    /// with the same seeds, so every node picks the same coordinator regardless
+    /// of the order the seeds are listed in.
+    pub fn coordinator(&self, group: &str) -> Option<NodeAddr> {
+        let mut seeds = self.seeds.clone();
+        seeds.sort_by_key(|seed| seed.id);
+        let index = topic::partition_for_key(group.as_bytes(), seeds.len().max(1) as u32);
+        seeds.get(index as usize).copied()
+    }
+
+    /// Validate that the configuration describes a usable cluster.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut ids = Vec::with_capacity(self.seeds.len());
+        for seed in &self.seeds {
+            if ids.contains(&seed.id) {
+                return Err(Error::InvalidConfig {
+                    reason: format!("seed id {} is used more than once", seed.id),
+                });
+            }
+            ids.push(seed.id);
+        }
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            node_id: 1,
+            seeds: Vec::new(),
+            advertised_address: None,
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_node_addr() {
+        let node: NodeAddr = "2=127.0.0.1:7072".parse().unwrap();
+        assert_eq!(2, node.id);
+        assert_eq!("127.0.0.1:7072", node.addr.to_string());
+        assert_eq!("2=127.0.0.1:7072", node.to_string());
+
+        assert!("127.0.0.1:7072".parse::<NodeAddr>().is_err());
+        assert!("two=127.0.0.1:7072".parse::<NodeAddr>().is_err());
+        assert!("2=localhost".parse::<NodeAddr>().is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let listen = "127.0.0.1:7071".parse().unwrap();
+        let mut cfg = Config::default();
+        assert!(!cfg.enabled());
+        assert!(cfg.validate().is_ok());
+        assert_eq!(listen, cfg.advertised_address(listen));
+
+        cfg.seeds = vec!["1=127.0.0.1:7071".parse().unwrap()];
+        assert!(cfg.enabled());
+        assert!(cfg.is_seed());
+        assert!(cfg.validate().is_ok());
+
+        cfg.seeds.push("1=127.0.0.1:7072".parse().unwrap());
+        assert!(cfg.validate().is_err());
+    }
+
+    #[test]
+    fn test_coordinator() {
+        let mut cfg = Config::default();
+        assert_eq!(None, cfg.coordinator("group"));
+
+        cfg.seeds = vec![
+            "2=127.0.0.1:7072".parse().unwrap(),
+            "1=127.0.0.1:7071".parse().unwrap(),
+            "3=127.0.0.1:7073".parse().unwrap(),
+        ];
+        let coordinator = cfg.coordinator("group").unwrap();
+        cfg.seeds.reverse();
+        assert_eq!(Some(coordinator), cfg.coordinator("group"));
+
+        let coordinators: std::collections::BTreeSet<u32> = (0..32)
+            .map(|group| cfg.coordinator(&group.to_string()).unwrap().id)
+            .collect();
+        assert_eq!(3, coordinators.len());
+    }
+}
@@ -0,0 +1,41 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::result;
+
+use thiserror::Error;
+
+/// Custom Result wrapper to simplify usage.
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents errors managing cluster membership and metadata.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// Handles configuration that does not describe a usable cluster.
+    #[error("invalid cluster configuration: {reason}")]
+    InvalidConfig {
+        /// Why the configuration was rejected.
+        reason: String,
+    },
+    /// Handles metadata records that could not be decoded.
+    #[error("failed to decode cluster metadata record at offset {offset}: {source}")]
+    Corrupt {
+        /// The offset of the record that failed to decode.
+        offset: u64,
+        /// The initial error cause.
+        source: serde_json::Error,
+    },
+    /// Handles metadata changes submitted by other nodes that could not be decoded.
+    #[error("invalid cluster metadata record: {source}")]
+    InvalidRecord {
+        /// The initial error cause.
+        source: serde_json::Error,
+    },
+    /// Handles topics that can not be placed because too few nodes have joined
+    /// the cluster.
+    #[error("no nodes have joined the cluster to place topic '{topic}' on")]
+    NoNodes {
+        /// The topic being placed.
+        topic: String,
+    },
+}
@@ -0,0 +1,311 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{collections::BTreeMap, net::SocketAddr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::record::{OffsetRecord, Record};
+use crate::topic::murmur2;
+
+use super::error::{Error, Result};
+
+/// The internal, compacted, topic the cluster metadata log is stored in.
+pub const METADATA_TOPIC: &str = "__cluster_metadata";
+
+/// A single change to the cluster metadata.
+///
+/// Records are keyed by the entity they describe so compaction only retains
+/// the latest state of each node, topic, and partition.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MetadataRecord {
+    /// A node joined the cluster, or changed its address.
+    RegisterNode {
+        /// The id of the node.
+        id: u32,
+        /// The address the node serves the native protocol on.
+        address: SocketAddr,
+    },
+    /// A topic was created with the supplied replica placement.
+    CreateTopic {
+        /// The name of the topic.
+        name: String,
+        /// The topic's configuration overrides.
+        config: Vec<(String, String)>,
+        /// The ids of the nodes holding a replica of each partition.
+        replicas: Vec<Vec<u32>>,
+    },
+    /// A topic was deleted.
+    DeleteTopic {
+        /// The name of the topic.
+        name: String,
+    },
+    /// A partition elected a leader, or its leader's set of in-sync replicas changed.
+    PartitionLeader {
+        /// The topic the partition belongs to.
+        topic: String,
+        /// The partition id.
+        partition: u32,
+        /// The id of the partition's leader.
+        leader: u32,
+        /// The term the leader was elected in.
+        term: u64,
+        /// The ids of the replicas caught up with the leader, the leader included.
+        isr: Vec<u32>,
+    },
+}
+
+impl MetadataRecord {
+    /// Returns the compaction key of this record.
+    pub fn key(&self) -> String {
+        match self {
+            MetadataRecord::RegisterNode { id, .. } => format!("node/{}", id),
+            MetadataRecord::CreateTopic { name, .. } | MetadataRecord::DeleteTopic { name } => {
+                format!("topic/{}", name)
+            }
+            MetadataRecord::PartitionLeader {
+                topic, partition, ..
+            } => format!("partition/{}/{}", topic, partition),
+        }
+    }
+
+    /// Encode this change as a record of the metadata topic.
+    pub fn to_record(&self) -> Record {
+        let value = serde_json::to_vec(self).expect("metadata records always serialize");
+        Record::new(value).with_key(self.key())
+    }
+
+    /// Decode a change submitted by another node.
+    pub fn decode(record: &Record) -> Result<MetadataRecord> {
+        let value = record.value.as_deref().unwrap_or_default();
+        serde_json::from_slice(value).map_err(|source| Error::InvalidRecord { source })
+    }
+
+    /// Decode a change read from the metadata topic.
+    pub fn from_record(record: &OffsetRecord) -> Result<MetadataRecord> {
+        let value = record.record.value.as_deref().unwrap_or_default();
+        serde_json::from_slice(value).map_err(|source| Error::Corrupt {
+            offset: record.offset,
+            source,
+        })
+    }
+}
+
+/// The replication state of a single partition.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PartitionState {
+    /// The ids of the nodes holding a replica of the partition.
+    pub replicas: Vec<u32>,
+    /// The id of the partition's leader, if one has been reported.
+    pub leader: Option<u32>,
+    /// The term the leader was elected in.
+    pub term: u64,
+    /// The ids of the replicas caught up with the leader.
+    pub isr: Vec<u32>,
+}
+
+/// The metadata of a single topic.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TopicState {
+    /// The topic's configuration overrides.
+    pub config: Vec<(String, String)>,
+    /// The topic's partitions, indexed by partition id.
+    pub partitions: Vec<PartitionState>,
+}
+
+/// The state of the cluster, built by applying the metadata log in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClusterMetadata {
+    nodes: BTreeMap<u32, SocketAddr>,
+    topics: BTreeMap<String, TopicState>,
+}
+
+impl ClusterMetadata {
+    /// Apply a single change to this state.
+    pub fn apply(&mut self, record: MetadataRecord) {
+        match record {
+            MetadataRecord::RegisterNode { id, address } => {
+                self.nodes.insert(id, address);
+            }
+            MetadataRecord::CreateTopic {
+                name,
+                config,
+                replicas,
+            } => {
+                let partitions = replicas
+                    .into_iter()
+                    .map(|replicas| PartitionState {
+                        replicas,
+                        ..PartitionState::default()
+                    })
+                    .collect();
+                self.topics.insert(name, TopicState { config, partitions });
+            }
+            MetadataRecord::DeleteTopic { name } => {
+                self.topics.remove(&name);
+            }
+            MetadataRecord::PartitionLeader {
+                topic,
+                partition,
+                leader,
+                term,
+                isr,
+            } => {
+                let state = match self
+                    .topics
+                    .get_mut(&topic)
+                    .and_then(|topic| topic.partitions.get_mut(partition as usize))
+                {
+                    Some(state) if state.term <= term => state,
+                    _ => return,
+                };
+                state.leader = Some(leader);
+                state.term = term;
+                state.isr = isr;
+            }
+        }
+    }
+
+    /// Returns the address of every node that has joined the cluster by id.
+    pub fn nodes(&self) -> &BTreeMap<u32, SocketAddr> {
+        &self.nodes
+    }
+
+    /// Returns every topic by name.
+    pub fn topics(&self) -> &BTreeMap<String, TopicState> {
+        &self.topics
+    }
+
+    /// Returns the named topic.
+    pub fn topic(&self, name: &str) -> Option<&TopicState> {
+        self.topics.get(name)
+    }
+
+    /// Returns the supplied partition of the named topic.
+    pub fn partition(&self, topic: &str, partition: u32) -> Option<&PartitionState> {
+        self.topic(topic)
+            .and_then(|topic| topic.partitions.get(partition as usize))
+    }
+
+    /// Place the replicas of a new topic's partitions on the nodes that have
+    /// joined the cluster.
+    ///
+    /// Each partition's replicas are placed on consecutive node ids, starting
+    /// from a node chosen by hashing the topic name and offset by the partition
+    /// id, which spreads leadership evenly across the cluster.
+    pub fn assign(
+        &self,
+        topic: &str,
+        partitions: u32,
+        replication_factor: u32,
+    ) -> Result<Vec<Vec<u32>>> {
+        let nodes: Vec<u32> = self.nodes.keys().copied().collect();
+        if nodes.is_empty() {
+            return Err(Error::NoNodes {
+                topic: topic.to_owned(),
+            });
+        }
+        let count = nodes.len();
+        let factor = (replication_factor as usize).clamp(1, count);
+        let start = murmur2(topic.as_bytes()) as usize;
+        Ok((0..partitions as usize)
+            .map(|partition| {
+                (0..factor)
+                    .map(|idx| nodes[(start + partition + idx) % count])
+                    .collect()
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    fn register(metadata: &mut ClusterMetadata, id: u32) {
+        metadata.apply(MetadataRecord::RegisterNode {
+            id,
+            address: format!("127.0.0.1:{}", 7070 + id).parse().unwrap(),
+        });
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let record = MetadataRecord::PartitionLeader {
+            topic: String::from("events"),
+            partition: 1,
+            leader: 2,
+            term: 3,
+            isr: vec![2, 3],
+        };
+        let encoded = record.to_record();
+        assert_eq!(Some(b"partition/events/1".to_vec()), encoded.key);
+        let read = OffsetRecord {
+            offset: 5,
+            timestamp: 0,
+            record: encoded,
+        };
+        assert_eq!(record, MetadataRecord::from_record(&read).unwrap());
+        assert_eq!(record, MetadataRecord::decode(&read.record).unwrap());
+
+        let corrupt = OffsetRecord {
+            record: Record::new("nope"),
+            ..read
+        };
+        assert!(matches!(
+            MetadataRecord::from_record(&corrupt),
+            Err(Error::Corrupt { offset: 5, .. })
+        ));
+        assert!(matches!(
+            MetadataRecord::decode(&corrupt.record),
+            Err(Error::InvalidRecord { .. })
+        ));
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut metadata = ClusterMetadata::default();
+        assert!(matches!(
+            metadata.assign("events", 1, 1),
+            Err(Error::NoNodes { .. })
+        ));
+        for id in 1..=3 {
+            register(&mut metadata, id);
+        }
+        assert_eq!(3, metadata.nodes().len());
+
+        let replicas = metadata.assign("events", 6, 2).unwrap();
+        for replicas in &replicas {
+            assert_eq!(2, replicas.len());
+            assert_eq!(replicas[1], replicas[0] % 3 + 1);
+        }
+        assert_eq!(3, metadata.assign("events", 1, 5).unwrap()[0].len());
+
+        metadata.apply(MetadataRecord::CreateTopic {
+            name: String::from("events"),
+            config: Vec::new(),
+            replicas: replicas.clone(),
+        });
+        let leader = |term| MetadataRecord::PartitionLeader {
+            topic: String::from("events"),
+            partition: 0,
+            leader: replicas[0][term as usize % 2],
+            term,
+            isr: replicas[0].clone(),
+        };
+        metadata.apply(leader(2));
+        metadata.apply(leader(1));
+        let state = metadata.partition("events", 0).unwrap();
+        assert_eq!(Some(replicas[0][0]), state.leader);
+        assert_eq!(2, state.term);
+
+        metadata.apply(MetadataRecord::DeleteTopic {
+            name: String::from("events"),
+        });
+        assert!(metadata.topic("events").is_none());
+        metadata.apply(leader(3));
+        assert!(metadata.topics().is_empty());
+    }
+}
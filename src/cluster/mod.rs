@@ -0,0 +1,12 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod config;
+mod error;
+mod metadata;
+
+pub use self::config::{Config, NodeAddr};
+pub use self::error::{Error, Result};
+pub use self::metadata::{
+    ClusterMetadata, MetadataRecord, PartitionState, TopicState, METADATA_TOPIC,
+};
@@ -0,0 +1,66 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::result;
+
+use thiserror::Error;
+
+/// Custom Result wrapper to simplify usage.
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents errors encountered while decoding binary data.
+#[derive(Error, Debug, PartialEq)]
+pub enum Error {
+    /// Handles buffers that end before a complete value could be read.
+    #[error("unexpected end of buffer: needed {needed} bytes but only {remaining} remain")]
+    UnexpectedEof {
+        /// The number of bytes required to decode the value.
+        needed: usize,
+        /// The number of bytes left in the buffer.
+        remaining: usize,
+    },
+    /// Handles strings that are not valid UTF-8.
+    #[error("invalid utf-8 string")]
+    InvalidUtf8,
+    /// Handles decoded values that are not valid for the field they represent.
+    #[error("invalid value for '{field}': {value}")]
+    InvalidValue {
+        /// The name of the field being decoded.
+        field: &'static str,
+        /// The offending value.
+        value: i64,
+    },
+    /// Handles compressed data that the codec it was compressed with cannot
+    /// decompress.
+    #[error("failed to decompress {codec} data: {reason}")]
+    Decompress {
+        /// The name of the codec the data was compressed with.
+        codec: &'static str,
+        /// Why the data could not be decompressed.
+        reason: String,
+    },
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let err = Error::UnexpectedEof {
+            needed: 4,
+            remaining: 1,
+        };
+        assert_eq!(
+            "unexpected end of buffer: needed 4 bytes but only 1 remain",
+            format!("{}", err)
+        );
+
+        let err = Error::InvalidValue {
+            field: "magic",
+            value: 7,
+        };
+        assert_eq!("invalid value for 'magic': 7", format!("{}", err));
+    }
+}
@@ -0,0 +1,10 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod error;
+mod reader;
+mod writer;
+
+pub use self::error::{Error, Result};
+pub use self::reader::Reader;
+pub use self::writer::Writer;
@@ -0,0 +1,168 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::convert::TryInto;
+
+use super::error::{Error, Result};
+
+/// A cursor over a byte buffer that decodes big endian values.
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+macro_rules! get_int {
+    ($name:ident, $ty:ty) => {
+        #[doc = concat!("Decode a big endian [", stringify!($ty), "].")]
+        pub fn $name(&mut self) -> Result<$ty> {
+            let raw = self.get_raw(std::mem::size_of::<$ty>())?;
+            Ok(<$ty>::from_be_bytes(raw.try_into().unwrap()))
+        }
+    };
+}
+
+impl<'a> Reader<'a> {
+    /// Create a new reader positioned at the start of the supplied buffer.
+    pub fn new(buf: &'a [u8]) -> Reader<'a> {
+        Reader { buf, pos: 0 }
+    }
+
+    /// Returns the number of bytes left to read.
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    /// Returns whether or not the entire buffer has been consumed.
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    get_int!(get_u8, u8);
+    get_int!(get_i8, i8);
+    get_int!(get_u16, u16);
+    get_int!(get_i16, i16);
+    get_int!(get_u32, u32);
+    get_int!(get_i32, i32);
+    get_int!(get_u64, u64);
+    get_int!(get_i64, i64);
+
+    /// Decode a single byte boolean.
+    pub fn get_bool(&mut self) -> Result<bool> {
+        Ok(self.get_u8()? != 0)
+    }
+
+    /// Consume exactly `len` raw bytes.
+    pub fn get_raw(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.remaining() < len {
+            return Err(Error::UnexpectedEof {
+                needed: len,
+                remaining: self.remaining(),
+            });
+        }
+        let raw = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(raw)
+    }
+
+    /// Decode a u32 length prefixed byte array.
+    pub fn get_bytes(&mut self) -> Result<Vec<u8>> {
+        let len = self.get_u32()? as usize;
+        Ok(self.get_raw(len)?.to_vec())
+    }
+
+    /// Decode an i32 length prefixed byte array, where a length of -1 represents [None].
+    pub fn get_optional_bytes(&mut self) -> Result<Option<Vec<u8>>> {
+        match self.get_i32()? {
+            -1 => Ok(None),
+            len if len < 0 => Err(Error::InvalidValue {
+                field: "length",
+                value: len as i64,
+            }),
+            len => Ok(Some(self.get_raw(len as usize)?.to_vec())),
+        }
+    }
+
+    /// Decode a u16 length prefixed UTF-8 string.
+    pub fn get_string(&mut self) -> Result<String> {
+        let len = self.get_u16()? as usize;
+        let raw = self.get_raw(len)?;
+        String::from_utf8(raw.to_vec()).map_err(|_| Error::InvalidUtf8)
+    }
+
+    /// Decode a u32 length prefixed array, using the supplied closure to decode each element.
+    pub fn get_array<T, F>(&mut self, mut f: F) -> Result<Vec<T>>
+    where
+        F: FnMut(&mut Reader<'a>) -> Result<T>,
+    {
+        let len = self.get_u32()? as usize;
+        // Guard against hostile lengths by never preallocating more than the
+        // buffer could possibly hold.
+        let mut items = Vec::with_capacity(len.min(self.remaining()));
+        for _ in 0..len {
+            items.push(f(self)?);
+        }
+        Ok(items)
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::super::Writer;
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut buf = Vec::new();
+        buf.put_u8(1);
+        buf.put_i16(-2);
+        buf.put_u32(3);
+        buf.put_i64(-4);
+        buf.put_bool(true);
+        buf.put_bytes(b"bytes");
+        buf.put_optional_bytes(None);
+        buf.put_optional_bytes(Some(b"some"));
+        buf.put_string("string");
+        buf.put_array(&[1u16, 2, 3], |buf, item| buf.put_u16(*item));
+
+        let mut reader = Reader::new(&buf);
+        assert_eq!(1, reader.get_u8().unwrap());
+        assert_eq!(-2, reader.get_i16().unwrap());
+        assert_eq!(3, reader.get_u32().unwrap());
+        assert_eq!(-4, reader.get_i64().unwrap());
+        assert!(reader.get_bool().unwrap());
+        assert_eq!(b"bytes".to_vec(), reader.get_bytes().unwrap());
+        assert_eq!(None, reader.get_optional_bytes().unwrap());
+        assert_eq!(Some(b"some".to_vec()), reader.get_optional_bytes().unwrap());
+        assert_eq!("string", reader.get_string().unwrap());
+        assert_eq!(
+            vec![1, 2, 3],
+            reader.get_array(|reader| reader.get_u16()).unwrap()
+        );
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_eof() {
+        let mut reader = Reader::new(&[0, 1]);
+        assert_eq!(
+            Err(Error::UnexpectedEof {
+                needed: 4,
+                remaining: 2
+            }),
+            reader.get_u32()
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut reader = Reader::new(&[0xff, 0xff, 0xff, 0xfe]);
+        assert!(matches!(
+            reader.get_optional_bytes(),
+            Err(Error::InvalidValue { value: -2, .. })
+        ));
+
+        let mut reader = Reader::new(&[0, 2, 0xff, 0xff]);
+        assert_eq!(Err(Error::InvalidUtf8), reader.get_string());
+    }
+}
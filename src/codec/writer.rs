@@ -0,0 +1,72 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+macro_rules! put_int {
+    ($name:ident, $ty:ty) => {
+        #[doc = concat!("Encode a big endian [", stringify!($ty), "].")]
+        fn $name(&mut self, value: $ty) {
+            self.put_raw(&value.to_be_bytes());
+        }
+    };
+}
+
+/// Encodes big endian values onto the end of a growable buffer.
+pub trait Writer {
+    /// Append the supplied bytes as is.
+    fn put_raw(&mut self, raw: &[u8]);
+
+    put_int!(put_u8, u8);
+    put_int!(put_i8, i8);
+    put_int!(put_u16, u16);
+    put_int!(put_i16, i16);
+    put_int!(put_u32, u32);
+    put_int!(put_i32, i32);
+    put_int!(put_u64, u64);
+    put_int!(put_i64, i64);
+
+    /// Encode a single byte boolean.
+    fn put_bool(&mut self, value: bool) {
+        self.put_u8(value as u8);
+    }
+
+    /// Encode a u32 length prefixed byte array.
+    fn put_bytes(&mut self, value: &[u8]) {
+        self.put_u32(value.len() as u32);
+        self.put_raw(value);
+    }
+
+    /// Encode an i32 length prefixed byte array, where [None] is encoded as a length of -1.
+    fn put_optional_bytes(&mut self, value: Option<&[u8]>) {
+        match value {
+            Some(value) => {
+                self.put_i32(value.len() as i32);
+                self.put_raw(value);
+            }
+            None => self.put_i32(-1),
+        }
+    }
+
+    /// Encode a u16 length prefixed UTF-8 string.
+    fn put_string(&mut self, value: &str) {
+        self.put_u16(value.len() as u16);
+        self.put_raw(value.as_bytes());
+    }
+
+    /// Encode a u32 length prefixed array, using the supplied closure to encode each element.
+    fn put_array<T, F>(&mut self, items: &[T], mut f: F)
+    where
+        Self: Sized,
+        F: FnMut(&mut Self, &T),
+    {
+        self.put_u32(items.len() as u32);
+        for item in items {
+            f(self, item);
+        }
+    }
+}
+
+impl Writer for Vec<u8> {
+    fn put_raw(&mut self, raw: &[u8]) {
+        self.extend_from_slice(raw);
+    }
+}
@@ -0,0 +1,54 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::result;
+
+use thiserror::Error;
+
+use crate::codec;
+use crate::topic;
+
+use super::exchange::ExchangeType;
+
+/// Custom Result wrapper to simplify usage.
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents errors declaring, binding, or routing through exchanges.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// Handles errors reading or writing the exchanges topic.
+    #[error(transparent)]
+    Topic(#[from] topic::Error),
+    /// Handles exchange records that could not be decoded.
+    #[error("failed to decode exchange record at offset {offset}: {source}")]
+    Corrupt {
+        /// The offset of the exchange record that failed to decode.
+        offset: u64,
+        /// The initial error cause.
+        source: codec::Error,
+    },
+    /// Handles exchange names that can not be used.
+    #[error("invalid exchange name '{name}': {reason}")]
+    InvalidName {
+        /// The offending exchange name.
+        name: String,
+        /// Why the exchange name was rejected.
+        reason: &'static str,
+    },
+    /// Handles references to exchanges that have not been declared.
+    #[error("exchange '{name}' does not exist")]
+    NotFound {
+        /// The name of the missing exchange.
+        name: String,
+    },
+    /// Handles redeclaring an exchange with a different type.
+    #[error("exchange '{name}' is already declared as a {existing} exchange, not {requested}")]
+    TypeMismatch {
+        /// The name of the exchange.
+        name: String,
+        /// The type the exchange was declared with.
+        existing: ExchangeType,
+        /// The type it was redeclared with.
+        requested: ExchangeType,
+    },
+}
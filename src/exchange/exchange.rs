@@ -0,0 +1,346 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fmt;
+
+use crate::codec;
+use crate::record::Header;
+
+use super::error::{Error, Result};
+
+/// The header holding the name of the exchange a record was published to.
+pub const EXCHANGE_HEADER: &str = "rift.exchange";
+/// The header holding the routing key a record was published with.
+pub const ROUTING_KEY_HEADER: &str = "rift.routing_key";
+/// The binding argument deciding whether a headers binding matches records
+/// with `all` of its arguments, the default, or `any` of them.
+pub const MATCH_ARGUMENT: &str = "x-match";
+
+/// The maximum length of an exchange name.
+const MAX_NAME_LEN: usize = 255;
+/// The prefix of binding arguments that configure a binding rather than being
+/// matched against record headers.
+const RESERVED_ARGUMENT_PREFIX: &str = "x-";
+
+/// Decides which of an exchange's bindings a published record is routed by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExchangeType {
+    /// Routes by bindings whose routing key equals the record's.
+    Direct,
+    /// Routes by every binding, ignoring routing keys.
+    Fanout,
+    /// Routes by bindings whose routing key is a pattern matching the record's.
+    /// Keys are split into words on `.`, and in patterns `*` matches exactly
+    /// one word while `#` matches zero or more.
+    Topic,
+    /// Routes by bindings whose arguments match the record's headers.
+    Headers,
+}
+
+impl ExchangeType {
+    /// Every exchange type, in order of id.
+    pub const ALL: [ExchangeType; 4] = [
+        ExchangeType::Direct,
+        ExchangeType::Fanout,
+        ExchangeType::Topic,
+        ExchangeType::Headers,
+    ];
+
+    /// Returns the id this type is identified by on the wire and on disk.
+    pub fn id(&self) -> u8 {
+        match self {
+            ExchangeType::Direct => 0,
+            ExchangeType::Fanout => 1,
+            ExchangeType::Topic => 2,
+            ExchangeType::Headers => 3,
+        }
+    }
+
+    /// Returns the exchange type identified by the supplied id.
+    pub fn from_id(id: u8) -> codec::Result<ExchangeType> {
+        ExchangeType::ALL
+            .get(id as usize)
+            .copied()
+            .ok_or(codec::Error::InvalidValue {
+                field: "exchange type",
+                value: id as i64,
+            })
+    }
+
+    /// Returns the name of this exchange type.
+    ///
+    /// ```
+    /// # use librift::exchange::ExchangeType;
+    /// assert_eq!("topic", ExchangeType::Topic.name());
+    /// assert_eq!(Some(ExchangeType::Topic), ExchangeType::from_name("topic"));
+    /// assert_eq!(None, ExchangeType::from_name("random"));
+    /// ```
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExchangeType::Direct => "direct",
+            ExchangeType::Fanout => "fanout",
+            ExchangeType::Topic => "topic",
+            ExchangeType::Headers => "headers",
+        }
+    }
+
+    /// Returns the exchange type with the supplied name, if any.
+    pub fn from_name(name: &str) -> Option<ExchangeType> {
+        ExchangeType::ALL
+            .into_iter()
+            .find(|kind| kind.name() == name)
+    }
+}
+
+impl fmt::Display for ExchangeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A named router of published records.
+pub struct Exchange {
+    /// The unique name of the exchange.
+    pub name: String,
+    /// How the exchange routes records.
+    pub kind: ExchangeType,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Routes records published to an exchange that match it to a destination
+/// topic, where queues and consumer groups read them.
+pub struct Binding {
+    /// The name of the exchange records are published to.
+    pub exchange: String,
+    /// The name of the topic matching records are appended to.
+    pub destination: String,
+    /// The routing key, or for topic exchanges the routing pattern, records
+    /// must match. Ignored by fanout and headers exchanges.
+    pub routing_key: String,
+    /// The headers records must carry to match a headers exchange binding,
+    /// along with the optional [MATCH_ARGUMENT]. Ignored by other exchanges.
+    pub arguments: Vec<Header>,
+}
+
+impl Binding {
+    /// Create a new binding of an exchange to a destination topic with no
+    /// arguments.
+    pub fn new(
+        exchange: impl Into<String>,
+        destination: impl Into<String>,
+        routing_key: impl Into<String>,
+    ) -> Binding {
+        Binding {
+            exchange: exchange.into(),
+            destination: destination.into(),
+            routing_key: routing_key.into(),
+            arguments: Vec::new(),
+        }
+    }
+
+    /// Append an argument to this binding.
+    pub fn with_argument(mut self, key: impl Into<String>, value: impl Into<Vec<u8>>) -> Binding {
+        self.arguments.push(Header {
+            key: key.into(),
+            value: value.into(),
+        });
+        self
+    }
+
+    /// Returns whether a record published with the supplied routing key and
+    /// headers to an exchange of the supplied type matches this binding.
+    ///
+    /// ```
+    /// # use librift::exchange::{Binding, ExchangeType};
+    /// let binding = Binding::new("events", "audit", "orders.*.created");
+    /// assert!(binding.matches(ExchangeType::Topic, "orders.eu.created", &[]));
+    /// assert!(!binding.matches(ExchangeType::Direct, "orders.eu.created", &[]));
+    /// assert!(binding.matches(ExchangeType::Fanout, "anything", &[]));
+    /// ```
+    pub fn matches(&self, kind: ExchangeType, routing_key: &str, headers: &[Header]) -> bool {
+        match kind {
+            ExchangeType::Direct => self.routing_key == routing_key,
+            ExchangeType::Fanout => true,
+            ExchangeType::Topic => topic_matches(&self.routing_key, routing_key),
+            ExchangeType::Headers => self.matches_headers(headers),
+        }
+    }
+
+    /// Returns whether the supplied headers match this binding's arguments.
+    /// Headers are matched by the last value of each key, as with
+    /// [Record::header](crate::record::Record::header).
+    fn matches_headers(&self, headers: &[Header]) -> bool {
+        let any = self
+            .arguments
+            .iter()
+            .rev()
+            .find(|argument| argument.key == MATCH_ARGUMENT)
+            .is_some_and(|argument| argument.value == b"any");
+        let mut arguments = self
+            .arguments
+            .iter()
+            .filter(|argument| !argument.key.starts_with(RESERVED_ARGUMENT_PREFIX));
+        let matched = |argument: &Header| {
+            headers
+                .iter()
+                .rev()
+                .find(|header| header.key == argument.key)
+                .is_some_and(|header| header.value == argument.value)
+        };
+        match any {
+            true => arguments.any(matched),
+            false => arguments.all(matched),
+        }
+    }
+}
+
+/// Returns whether a topic exchange routing pattern matches a routing key.
+/// Both are split into words on `.`, and in the pattern `*` matches exactly
+/// one word while `#` matches zero or more words.
+///
+/// ```
+/// # use librift::exchange::topic_matches;
+/// assert!(topic_matches("orders.*", "orders.created"));
+/// assert!(!topic_matches("orders.*", "orders.eu.created"));
+/// assert!(topic_matches("orders.#", "orders.eu.created"));
+/// assert!(topic_matches("orders.#", "orders"));
+/// assert!(topic_matches("#.created", "orders.eu.created"));
+/// ```
+pub fn topic_matches(pattern: &str, routing_key: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('.').collect();
+    let words: Vec<&str> = routing_key.split('.').collect();
+
+    // matched[j] holds whether the pattern so far matches the first j words.
+    let mut matched = vec![false; words.len() + 1];
+    matched[0] = true;
+    for part in pattern {
+        let mut next = vec![false; words.len() + 1];
+        for j in 0..=words.len() {
+            next[j] = match part {
+                "#" => matched[j] || (j > 0 && next[j - 1]),
+                "*" => j > 0 && matched[j - 1],
+                word => j > 0 && matched[j - 1] && words[j - 1] == word,
+            };
+        }
+        matched = next;
+    }
+    matched[words.len()]
+}
+
+/// Ensure the supplied exchange name may be declared.
+///
+/// ```
+/// # use librift::exchange::validate_name;
+/// assert!(validate_name("orders.v1:eu_west-1").is_ok());
+/// assert!(validate_name("").is_err());
+/// assert!(validate_name("a/b").is_err());
+/// ```
+pub fn validate_name(name: &str) -> Result<()> {
+    let reason = if name.is_empty() {
+        "must not be empty"
+    } else if name.len() > MAX_NAME_LEN {
+        "must be at most 255 characters"
+    } else if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | ':'))
+    {
+        "may only contain ASCII alphanumerics, '.', '_', '-', and ':'"
+    } else {
+        return Ok(());
+    };
+    Err(Error::InvalidName {
+        name: name.to_owned(),
+        reason,
+    })
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topic_matches() {
+        for (pattern, key) in [
+            ("a.b.c", "a.b.c"),
+            ("*.b.*", "a.b.c"),
+            ("#", ""),
+            ("#", "a.b.c"),
+            ("a.#", "a"),
+            ("a.#.c", "a.c"),
+            ("a.#.c", "a.b.b.c"),
+            ("#.#", "a"),
+            ("*.#", "a.b"),
+            ("a.*.#.d", "a.b.c.d"),
+            // Empty keys and words are words like any other.
+            ("*", ""),
+            ("a.*.c", "a..c"),
+        ] {
+            assert!(topic_matches(pattern, key), "{} ~ {}", pattern, key);
+        }
+        for (pattern, key) in [
+            ("a.b.c", "a.b"),
+            ("a.b", "a.b.c"),
+            ("*", "a.b"),
+            ("a.*", "a"),
+            ("a.#.c", "a.b.d"),
+            ("a*", "ab"),
+        ] {
+            assert!(!topic_matches(pattern, key), "{} !~ {}", pattern, key);
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let binding = Binding::new("events", "orders", "orders.created");
+        assert!(binding.matches(ExchangeType::Direct, "orders.created", &[]));
+        assert!(!binding.matches(ExchangeType::Direct, "orders.deleted", &[]));
+        assert!(binding.matches(ExchangeType::Fanout, "", &[]));
+
+        let header = |key: &str, value: &str| Header {
+            key: key.to_owned(),
+            value: value.as_bytes().to_vec(),
+        };
+        let all = Binding::new("events", "orders", "ignored")
+            .with_argument("region", "eu")
+            .with_argument("kind", "order");
+        let any = all.clone().with_argument(MATCH_ARGUMENT, "any");
+        let headers = [header("region", "eu"), header("kind", "refund")];
+        assert!(!all.matches(ExchangeType::Headers, "", &headers));
+        assert!(any.matches(ExchangeType::Headers, "", &headers));
+
+        let headers = [header("kind", "order"), header("region", "eu")];
+        assert!(all.matches(ExchangeType::Headers, "", &headers));
+
+        // The last value of a header is the one matched.
+        let headers = [header("kind", "order"), header("kind", "refund")];
+        assert!(!any.matches(ExchangeType::Headers, "", &headers));
+
+        // A binding without arguments matches every record unless any one
+        // argument is required.
+        let empty = Binding::new("events", "orders", "");
+        assert!(empty.matches(ExchangeType::Headers, "", &[]));
+        let empty = empty.with_argument(MATCH_ARGUMENT, "any");
+        assert!(!empty.matches(ExchangeType::Headers, "", &headers));
+    }
+
+    #[test]
+    fn test_exchange_type() {
+        for kind in ExchangeType::ALL {
+            assert_eq!(Ok(kind), ExchangeType::from_id(kind.id()));
+            assert_eq!(Some(kind), ExchangeType::from_name(&kind.to_string()));
+        }
+        assert!(ExchangeType::from_id(4).is_err());
+    }
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name(&"a".repeat(255)).is_ok());
+        assert!(matches!(
+            validate_name(&"a".repeat(256)),
+            Err(Error::InvalidName { .. })
+        ));
+        assert!(validate_name("a b").is_err());
+    }
+}
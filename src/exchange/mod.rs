@@ -0,0 +1,15 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod error;
+#[allow(clippy::module_inception)]
+mod exchange;
+mod registry;
+mod state;
+
+pub use self::error::{Error, Result};
+pub use self::exchange::{
+    topic_matches, validate_name, Binding, Exchange, ExchangeType, EXCHANGE_HEADER, MATCH_ARGUMENT,
+    ROUTING_KEY_HEADER,
+};
+pub use self::registry::{Registry, EXCHANGES_TOPIC};
@@ -0,0 +1,434 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::record::{Header, Record};
+use crate::topic::{self, Partition, Topic, TopicConfig, CLEANUP_POLICY};
+
+use super::error::{Error, Result};
+use super::exchange::{validate_name, Binding, Exchange, ExchangeType};
+use super::state::{self, Change};
+
+/// The internal topic declared exchanges and their bindings are stored in.
+pub const EXCHANGES_TOPIC: &str = "__exchanges";
+
+/// The number of bytes read at a time while replaying the exchanges topic.
+const REPLAY_BYTES: usize = 1024 * 1024;
+
+/// A declared exchange along with its bindings, in the order they were added.
+struct Declared {
+    kind: ExchangeType,
+    bindings: Vec<Binding>,
+}
+
+/// Holds the declared exchanges and routes records published to them to the
+/// destination topics of their matching bindings.
+///
+/// Every declaration and binding is appended to the exchanges topic and
+/// flushed before it takes effect, keyed by the exchange's name or everything
+/// identifying the binding. The exchanges are rebuilt by replaying the topic on
+/// open, so they survive restarts. Deleting an exchange or removing a binding
+/// writes tombstones, which compaction uses to drop them from the topic.
+pub struct Registry {
+    log: Arc<Topic>,
+    exchanges: Mutex<BTreeMap<String, Declared>>,
+}
+
+impl Registry {
+    /// Open the registry, creating the exchanges topic if it does not exist.
+    pub fn open(topics: Arc<topic::Manager>) -> Result<Registry> {
+        let log = match topics.get(EXCHANGES_TOPIC) {
+            Ok(log) => log,
+            Err(topic::Error::NotFound { .. }) => {
+                let mut config = TopicConfig::new();
+                config.set(CLEANUP_POLICY, "compact")?;
+                topics.create(EXCHANGES_TOPIC, 1, config)?
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let exchanges = replay(log.partition(0)?)?;
+        Ok(Registry {
+            log,
+            exchanges: Mutex::new(exchanges),
+        })
+    }
+
+    /// Declare an exchange of the supplied type, returning whether it was
+    /// created. Redeclaring an exchange with the type it already has does
+    /// nothing, while redeclaring it with another type is rejected.
+    pub fn declare(&self, name: &str, kind: ExchangeType) -> Result<bool> {
+        validate_name(name)?;
+        let mut exchanges = self.exchanges();
+        if let Some(declared) = exchanges.get(name) {
+            if declared.kind != kind {
+                return Err(Error::TypeMismatch {
+                    name: name.to_owned(),
+                    existing: declared.kind,
+                    requested: kind,
+                });
+            }
+            return Ok(false);
+        }
+
+        let exchange = Exchange {
+            name: name.to_owned(),
+            kind,
+        };
+        self.write(&[state::exchange_record(&exchange)])?;
+        exchanges.insert(
+            exchange.name,
+            Declared {
+                kind,
+                bindings: Vec::new(),
+            },
+        );
+        Ok(true)
+    }
+
+    /// Delete an exchange along with all of its bindings.
+    pub fn delete(&self, name: &str) -> Result<()> {
+        let mut exchanges = self.exchanges();
+        let declared = exchanges.get(name).ok_or_else(|| not_found(name))?;
+        let mut tombstones: Vec<Record> = declared
+            .bindings
+            .iter()
+            .map(state::binding_tombstone)
+            .collect();
+        tombstones.push(state::exchange_tombstone(name));
+        self.write(&tombstones)?;
+        exchanges.remove(name);
+        Ok(())
+    }
+
+    /// Returns the exchange with the supplied name.
+    pub fn exchange(&self, name: &str) -> Result<Exchange> {
+        let exchanges = self.exchanges();
+        let declared = exchanges.get(name).ok_or_else(|| not_found(name))?;
+        Ok(Exchange {
+            name: name.to_owned(),
+            kind: declared.kind,
+        })
+    }
+
+    /// Returns every exchange, ordered by name.
+    pub fn list(&self) -> Vec<Exchange> {
+        self.exchanges()
+            .iter()
+            .map(|(name, declared)| Exchange {
+                name: name.clone(),
+                kind: declared.kind,
+            })
+            .collect()
+    }
+
+    /// Returns the bindings of the supplied exchange, in the order they were added.
+    pub fn bindings(&self, name: &str) -> Result<Vec<Binding>> {
+        self.exchanges()
+            .get(name)
+            .map(|declared| declared.bindings.clone())
+            .ok_or_else(|| not_found(name))
+    }
+
+    /// Add a binding to its exchange, returning whether it was added rather
+    /// than already present.
+    pub fn bind(&self, binding: Binding) -> Result<bool> {
+        let mut exchanges = self.exchanges();
+        let declared = exchanges
+            .get_mut(&binding.exchange)
+            .ok_or_else(|| not_found(&binding.exchange))?;
+        if declared.bindings.contains(&binding) {
+            return Ok(false);
+        }
+        self.write(&[state::binding_record(&binding)])?;
+        declared.bindings.push(binding);
+        Ok(true)
+    }
+
+    /// Remove a binding from its exchange, returning whether it was present.
+    pub fn unbind(&self, binding: &Binding) -> Result<bool> {
+        let mut exchanges = self.exchanges();
+        let declared = exchanges
+            .get_mut(&binding.exchange)
+            .ok_or_else(|| not_found(&binding.exchange))?;
+        if !declared.bindings.contains(binding) {
+            return Ok(false);
+        }
+        self.write(&[state::binding_tombstone(binding)])?;
+        declared.bindings.retain(|existing| existing != binding);
+        Ok(true)
+    }
+
+    /// Remove every binding to the supplied destination topic, such as once
+    /// the topic is deleted, returning how many were removed.
+    pub fn unbind_destination(&self, destination: &str) -> Result<usize> {
+        let mut exchanges = self.exchanges();
+        let tombstones: Vec<Record> = exchanges
+            .values()
+            .flat_map(|declared| &declared.bindings)
+            .filter(|binding| binding.destination == destination)
+            .map(state::binding_tombstone)
+            .collect();
+        if tombstones.is_empty() {
+            return Ok(0);
+        }
+        self.write(&tombstones)?;
+        for declared in exchanges.values_mut() {
+            declared
+                .bindings
+                .retain(|binding| binding.destination != destination);
+        }
+        Ok(tombstones.len())
+    }
+
+    /// Returns the destination topics a record published to the supplied
+    /// exchange with the supplied routing key and headers is routed to, in the
+    /// order their first matching binding was added. A record is routed to
+    /// each destination once, however many of its bindings match.
+    pub fn route(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        headers: &[Header],
+    ) -> Result<Vec<String>> {
+        let exchanges = self.exchanges();
+        let declared = exchanges.get(exchange).ok_or_else(|| not_found(exchange))?;
+        let mut destinations: Vec<String> = Vec::new();
+        for binding in &declared.bindings {
+            if binding.matches(declared.kind, routing_key, headers)
+                && !destinations.contains(&binding.destination)
+            {
+                destinations.push(binding.destination.clone());
+            }
+        }
+        Ok(destinations)
+    }
+
+    fn write(&self, records: &[Record]) -> Result<()> {
+        let log = self.log.partition(0)?;
+        log.append(records)?;
+        log.flush()?;
+        Ok(())
+    }
+
+    fn exchanges(&self) -> MutexGuard<'_, BTreeMap<String, Declared>> {
+        self.exchanges
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn not_found(name: &str) -> Error {
+    Error::NotFound {
+        name: name.to_owned(),
+    }
+}
+
+fn replay(log: &Partition) -> Result<BTreeMap<String, Declared>> {
+    let mut exchanges: BTreeMap<String, Declared> = BTreeMap::new();
+    let mut offset = log.start_offset();
+    while offset < log.next_offset() {
+        let records = log.read(offset, REPLAY_BYTES)?;
+        for record in &records {
+            let change = state::from_record(&record.record).map_err(|source| Error::Corrupt {
+                offset: record.offset,
+                source,
+            })?;
+            match change {
+                Change::Declared(exchange) => {
+                    exchanges.insert(
+                        exchange.name,
+                        Declared {
+                            kind: exchange.kind,
+                            bindings: Vec::new(),
+                        },
+                    );
+                }
+                Change::Deleted(name) => {
+                    exchanges.remove(&name);
+                }
+                Change::Bound(binding) => {
+                    if let Some(declared) = exchanges.get_mut(&binding.exchange) {
+                        if !declared.bindings.contains(&binding) {
+                            declared.bindings.push(binding);
+                        }
+                    }
+                }
+                Change::Unbound(binding) => {
+                    if let Some(declared) = exchanges.get_mut(&binding.exchange) {
+                        declared.bindings.retain(|existing| *existing != binding);
+                    }
+                }
+            }
+        }
+        offset = match records.last() {
+            Some(record) => record.offset + 1,
+            None => break,
+        };
+    }
+    Ok(exchanges)
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+    use crate::storage::LogConfig;
+
+    fn open(dir: &std::path::Path) -> Registry {
+        let topics = Arc::new(topic::Manager::open(dir, LogConfig::default()).unwrap());
+        Registry::open(topics).unwrap()
+    }
+
+    fn header(key: &str, value: &str) -> Header {
+        Header {
+            key: key.to_owned(),
+            value: value.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_declare() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = open(dir.path());
+
+        assert!(registry.declare("events", ExchangeType::Topic).unwrap());
+        assert!(!registry.declare("events", ExchangeType::Topic).unwrap());
+        assert!(matches!(
+            registry.declare("events", ExchangeType::Direct),
+            Err(Error::TypeMismatch {
+                existing: ExchangeType::Topic,
+                requested: ExchangeType::Direct,
+                ..
+            })
+        ));
+        assert!(matches!(
+            registry.declare("", ExchangeType::Direct),
+            Err(Error::InvalidName { .. })
+        ));
+        assert_eq!(
+            ExchangeType::Topic,
+            registry.exchange("events").unwrap().kind
+        );
+
+        registry.delete("events").unwrap();
+        assert!(matches!(
+            registry.delete("events"),
+            Err(Error::NotFound { .. })
+        ));
+        assert!(registry.list().is_empty());
+        assert!(matches!(
+            registry.bind(Binding::new("events", "orders", "")),
+            Err(Error::NotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_route() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = open(dir.path());
+        registry.declare("direct", ExchangeType::Direct).unwrap();
+        registry.declare("fanout", ExchangeType::Fanout).unwrap();
+        registry.declare("topic", ExchangeType::Topic).unwrap();
+        registry.declare("headers", ExchangeType::Headers).unwrap();
+
+        assert!(registry
+            .bind(Binding::new("direct", "orders", "created"))
+            .unwrap());
+        assert!(!registry
+            .bind(Binding::new("direct", "orders", "created"))
+            .unwrap());
+        registry
+            .bind(Binding::new("direct", "audit", "created"))
+            .unwrap();
+        registry
+            .bind(Binding::new("direct", "refunds", "refunded"))
+            .unwrap();
+        assert_eq!(
+            vec!["orders", "audit"],
+            registry.route("direct", "created", &[]).unwrap()
+        );
+        assert!(registry.route("direct", "deleted", &[]).unwrap().is_empty());
+
+        registry.bind(Binding::new("fanout", "a", "x")).unwrap();
+        registry.bind(Binding::new("fanout", "b", "y")).unwrap();
+        assert_eq!(vec!["a", "b"], registry.route("fanout", "z", &[]).unwrap());
+
+        // A record is routed to each destination once, however many of its
+        // bindings match.
+        registry
+            .bind(Binding::new("topic", "eu", "*.eu.#"))
+            .unwrap();
+        registry
+            .bind(Binding::new("topic", "eu", "orders.eu.*"))
+            .unwrap();
+        registry.bind(Binding::new("topic", "all", "#")).unwrap();
+        assert_eq!(
+            vec!["eu", "all"],
+            registry.route("topic", "orders.eu.created", &[]).unwrap()
+        );
+        assert_eq!(
+            vec!["all"],
+            registry.route("topic", "orders.us", &[]).unwrap()
+        );
+
+        registry
+            .bind(Binding::new("headers", "eu", "").with_argument("region", "eu"))
+            .unwrap();
+        assert_eq!(
+            vec!["eu"],
+            registry
+                .route("headers", "", &[header("region", "eu")])
+                .unwrap()
+        );
+        assert!(registry.route("headers", "", &[]).unwrap().is_empty());
+
+        assert!(matches!(
+            registry.route("missing", "", &[]),
+            Err(Error::NotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_survives_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = open(dir.path());
+        registry.declare("events", ExchangeType::Topic).unwrap();
+        registry.declare("gone", ExchangeType::Fanout).unwrap();
+        registry.bind(Binding::new("gone", "orders", "")).unwrap();
+        registry.delete("gone").unwrap();
+        let bindings = [
+            Binding::new("events", "orders", "orders.#"),
+            Binding::new("events", "audit", "#"),
+            Binding::new("events", "refunds", "refunds.*"),
+        ];
+        for binding in &bindings {
+            registry.bind(binding.clone()).unwrap();
+        }
+        assert!(registry.unbind(&bindings[1]).unwrap());
+        assert!(!registry.unbind(&bindings[1]).unwrap());
+        assert_eq!(1, registry.unbind_destination("refunds").unwrap());
+        drop(registry);
+
+        let registry = open(dir.path());
+        assert_eq!(
+            vec![Exchange {
+                name: String::from("events"),
+                kind: ExchangeType::Topic
+            }],
+            registry.list()
+        );
+        assert_eq!(
+            vec![bindings[0].clone()],
+            registry.bindings("events").unwrap()
+        );
+
+        // A deleted exchange comes back without its old bindings.
+        registry.declare("gone", ExchangeType::Fanout).unwrap();
+        assert!(registry.bindings("gone").unwrap().is_empty());
+    }
+}
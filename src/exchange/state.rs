@@ -0,0 +1,189 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::codec::{self, Reader, Writer};
+use crate::record::{Header, Record};
+
+use super::exchange::{Binding, Exchange, ExchangeType};
+
+/// The current version of the exchange record key format.
+const KEY_VERSION: u16 = 0;
+/// The current version of the exchange record value format.
+const VALUE_VERSION: u16 = 0;
+
+/// Identifies a key describing an exchange.
+const EXCHANGE_KEY: u8 = 0;
+/// Identifies a key describing a binding.
+const BINDING_KEY: u8 = 1;
+
+/// A change to the declared exchanges, as stored in the exchanges topic.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Change {
+    /// An exchange was declared.
+    Declared(Exchange),
+    /// The named exchange was deleted.
+    Deleted(String),
+    /// A binding was added.
+    Bound(Binding),
+    /// A binding was removed.
+    Unbound(Binding),
+}
+
+/// Returns the record storing a declared exchange, keyed by its name.
+pub(super) fn exchange_record(exchange: &Exchange) -> Record {
+    let mut value = Vec::new();
+    value.put_u16(VALUE_VERSION);
+    value.put_u8(exchange.kind.id());
+    record(exchange_key(&exchange.name), Some(value))
+}
+
+/// Returns the tombstone marking the named exchange as deleted.
+pub(super) fn exchange_tombstone(name: &str) -> Record {
+    record(exchange_key(name), None)
+}
+
+/// Returns the record storing a binding, keyed by everything that identifies
+/// it, so that the tombstone written when it is removed compacts it away.
+pub(super) fn binding_record(binding: &Binding) -> Record {
+    let mut value = Vec::new();
+    value.put_u16(VALUE_VERSION);
+    record(binding_key(binding), Some(value))
+}
+
+/// Returns the tombstone marking a binding as removed.
+pub(super) fn binding_tombstone(binding: &Binding) -> Record {
+    record(binding_key(binding), None)
+}
+
+/// Decode a record of the exchanges topic into the change it describes.
+pub(super) fn from_record(record: &Record) -> codec::Result<Change> {
+    let key = record.key.as_deref().ok_or(codec::Error::InvalidValue {
+        field: "exchange key",
+        value: -1,
+    })?;
+    let mut reader = Reader::new(key);
+    check_version(reader.get_u16()?, KEY_VERSION, "exchange key version")?;
+    match reader.get_u8()? {
+        EXCHANGE_KEY => {
+            let name = reader.get_string()?;
+            let value = match record.value.as_deref() {
+                Some(value) => value,
+                None => return Ok(Change::Deleted(name)),
+            };
+            let mut reader = Reader::new(value);
+            check_version(reader.get_u16()?, VALUE_VERSION, "exchange value version")?;
+            Ok(Change::Declared(Exchange {
+                name,
+                kind: ExchangeType::from_id(reader.get_u8()?)?,
+            }))
+        }
+        BINDING_KEY => {
+            let binding = Binding {
+                exchange: reader.get_string()?,
+                destination: reader.get_string()?,
+                routing_key: reader.get_string()?,
+                arguments: reader.get_array(|reader| {
+                    Ok(Header {
+                        key: reader.get_string()?,
+                        value: reader.get_bytes()?,
+                    })
+                })?,
+            };
+            match record.value.as_deref() {
+                Some(value) => {
+                    let mut reader = Reader::new(value);
+                    check_version(reader.get_u16()?, VALUE_VERSION, "binding value version")?;
+                    Ok(Change::Bound(binding))
+                }
+                None => Ok(Change::Unbound(binding)),
+            }
+        }
+        kind => Err(codec::Error::InvalidValue {
+            field: "exchange key type",
+            value: kind as i64,
+        }),
+    }
+}
+
+fn record(key: Vec<u8>, value: Option<Vec<u8>>) -> Record {
+    Record {
+        key: Some(key),
+        value,
+        headers: Vec::new(),
+        timestamp: None,
+    }
+}
+
+fn exchange_key(name: &str) -> Vec<u8> {
+    let mut key = Vec::new();
+    key.put_u16(KEY_VERSION);
+    key.put_u8(EXCHANGE_KEY);
+    key.put_string(name);
+    key
+}
+
+fn binding_key(binding: &Binding) -> Vec<u8> {
+    let mut key = Vec::new();
+    key.put_u16(KEY_VERSION);
+    key.put_u8(BINDING_KEY);
+    key.put_string(&binding.exchange);
+    key.put_string(&binding.destination);
+    key.put_string(&binding.routing_key);
+    key.put_array(&binding.arguments, |buf, argument| {
+        buf.put_string(&argument.key);
+        buf.put_bytes(&argument.value);
+    });
+    key
+}
+
+fn check_version(version: u16, expected: u16, field: &'static str) -> codec::Result<()> {
+    if version != expected {
+        return Err(codec::Error::InvalidValue {
+            field,
+            value: version as i64,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let exchange = Exchange {
+            name: String::from("events"),
+            kind: ExchangeType::Headers,
+        };
+        let binding = Binding::new("events", "orders", "orders.#").with_argument("region", "eu");
+        for (record, change) in [
+            (
+                exchange_record(&exchange),
+                Change::Declared(exchange.clone()),
+            ),
+            (
+                exchange_tombstone("events"),
+                Change::Deleted(String::from("events")),
+            ),
+            (binding_record(&binding), Change::Bound(binding.clone())),
+            (
+                binding_tombstone(&binding),
+                Change::Unbound(binding.clone()),
+            ),
+        ] {
+            assert_eq!(change, from_record(&record).unwrap());
+        }
+
+        let mut record = exchange_record(&exchange);
+        record.key.as_mut().unwrap()[2] = 7;
+        assert!(matches!(
+            from_record(&record),
+            Err(codec::Error::InvalidValue {
+                field: "exchange key type",
+                value: 7
+            })
+        ));
+    }
+}
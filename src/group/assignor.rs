@@ -0,0 +1,345 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::topic::TopicPartition;
+
+/// The partitions assigned to each member of a group, keyed by member id.
+pub type Assignment = BTreeMap<String, Vec<TopicPartition>>;
+
+#[derive(Debug, Clone, PartialEq)]
+/// The topics a single group member has subscribed to.
+pub struct Subscription {
+    /// The id of the member.
+    pub member_id: String,
+    /// The topics the member consumes.
+    pub topics: BTreeSet<String>,
+}
+
+/// Distributes the partitions of a group's subscribed topics across its members.
+pub trait Assignor: Send + Sync {
+    /// Returns the name clients use to select this strategy.
+    fn name(&self) -> &'static str;
+
+    /// Compute a new assignment. Every member is present in the result, even if
+    /// it has been assigned nothing.
+    ///
+    /// `partitions` maps each subscribed topic to its partition count, while
+    /// `previous` holds the assignment of the prior generation.
+    fn assign(
+        &self,
+        members: &[Subscription],
+        partitions: &BTreeMap<String, u32>,
+        previous: &Assignment,
+    ) -> Assignment;
+}
+
+/// Returns the built in assignor with the supplied name.
+///
+/// ```
+/// # use librift::group::assignor;
+/// assert_eq!("sticky", assignor("sticky").unwrap().name());
+/// assert!(assignor("unknown").is_none());
+/// ```
+pub fn assignor(name: &str) -> Option<&'static dyn Assignor> {
+    match name {
+        RANGE => Some(&RangeAssignor),
+        ROUND_ROBIN => Some(&RoundRobinAssignor),
+        STICKY => Some(&StickyAssignor),
+        _ => None,
+    }
+}
+
+/// The name of the [RangeAssignor] strategy.
+pub const RANGE: &str = "range";
+/// The name of the [RoundRobinAssignor] strategy.
+pub const ROUND_ROBIN: &str = "roundrobin";
+/// The name of the [StickyAssignor] strategy.
+pub const STICKY: &str = "sticky";
+
+fn empty_assignment(members: &[Subscription]) -> Assignment {
+    members
+        .iter()
+        .map(|member| (member.member_id.clone(), Vec::new()))
+        .collect()
+}
+
+fn sorted_members(members: &[Subscription]) -> Vec<&Subscription> {
+    let mut sorted: Vec<&Subscription> = members.iter().collect();
+    sorted.sort_unstable_by(|a, b| a.member_id.cmp(&b.member_id));
+    sorted
+}
+
+/// Assigns each topic's partitions in contiguous ranges, handing any remainder
+/// to the lexicographically first members.
+pub struct RangeAssignor;
+
+impl Assignor for RangeAssignor {
+    fn name(&self) -> &'static str {
+        RANGE
+    }
+
+    fn assign(
+        &self,
+        members: &[Subscription],
+        partitions: &BTreeMap<String, u32>,
+        _: &Assignment,
+    ) -> Assignment {
+        let mut assignment = empty_assignment(members);
+        let members = sorted_members(members);
+        for (topic, count) in partitions {
+            let subscribed: Vec<&&Subscription> = members
+                .iter()
+                .filter(|member| member.topics.contains(topic))
+                .collect();
+            if subscribed.is_empty() {
+                continue;
+            }
+
+            let per_member = count / subscribed.len() as u32;
+            let extra = count % subscribed.len() as u32;
+            let mut start = 0;
+            for (idx, member) in subscribed.into_iter().enumerate() {
+                let len = per_member + if (idx as u32) < extra { 1 } else { 0 };
+                let owned = assignment.get_mut(&member.member_id).unwrap();
+                owned.extend((start..start + len).map(|p| TopicPartition::new(topic.clone(), p)));
+                start += len;
+            }
+        }
+        assignment
+    }
+}
+
+/// Deals every partition of every topic out to subscribed members one at a time.
+pub struct RoundRobinAssignor;
+
+impl Assignor for RoundRobinAssignor {
+    fn name(&self) -> &'static str {
+        ROUND_ROBIN
+    }
+
+    fn assign(
+        &self,
+        members: &[Subscription],
+        partitions: &BTreeMap<String, u32>,
+        _: &Assignment,
+    ) -> Assignment {
+        let mut assignment = empty_assignment(members);
+        let members = sorted_members(members);
+        if members.is_empty() {
+            return assignment;
+        }
+
+        let mut cursor = 0;
+        for (topic, count) in partitions {
+            for partition in 0..*count {
+                let owner = (0..members.len())
+                    .map(|step| (cursor + step) % members.len())
+                    .find(|idx| members[*idx].topics.contains(topic));
+                if let Some(idx) = owner {
+                    assignment
+                        .get_mut(&members[idx].member_id)
+                        .unwrap()
+                        .push(TopicPartition::new(topic.clone(), partition));
+                    cursor = idx + 1;
+                }
+            }
+        }
+        assignment
+    }
+}
+
+/// Produces a balanced assignment while moving as few partitions as possible
+/// from the previous generation's assignment.
+pub struct StickyAssignor;
+
+impl Assignor for StickyAssignor {
+    fn name(&self) -> &'static str {
+        STICKY
+    }
+
+    fn assign(
+        &self,
+        members: &[Subscription],
+        partitions: &BTreeMap<String, u32>,
+        previous: &Assignment,
+    ) -> Assignment {
+        let mut assignment = empty_assignment(members);
+        let members = sorted_members(members);
+
+        let mut unassigned: BTreeSet<TopicPartition> = partitions
+            .iter()
+            .filter(|(topic, _)| members.iter().any(|member| member.topics.contains(*topic)))
+            .flat_map(|(topic, count)| (0..*count).map(|p| TopicPartition::new(topic.clone(), p)))
+            .collect();
+
+        let consumers = members
+            .iter()
+            .filter(|member| !member.topics.is_empty())
+            .count();
+        if consumers == 0 {
+            return assignment;
+        }
+        let base = unassigned.len() / consumers;
+        let mut extra = unassigned.len() % consumers;
+
+        // Members that previously owned the most partitions keep their extras first,
+        // which minimises movement when the group shrinks or grows.
+        let mut keepers: Vec<(&Subscription, Vec<TopicPartition>)> = members
+            .iter()
+            .map(|member| {
+                let kept = previous
+                    .get(&member.member_id)
+                    .map(|owned| {
+                        owned
+                            .iter()
+                            .filter(|tp| member.topics.contains(&tp.topic))
+                            .filter(|tp| unassigned.contains(*tp))
+                            .cloned()
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                (*member, kept)
+            })
+            .collect();
+        keepers.sort_by(|(a, a_kept), (b, b_kept)| {
+            b_kept
+                .len()
+                .cmp(&a_kept.len())
+                .then_with(|| a.member_id.cmp(&b.member_id))
+        });
+
+        for (member, kept) in keepers {
+            let mut quota = base;
+            if extra > 0 && kept.len() > base {
+                quota += 1;
+                extra -= 1;
+            }
+            let owned = assignment.get_mut(&member.member_id).unwrap();
+            for tp in kept.into_iter().take(quota) {
+                unassigned.remove(&tp);
+                owned.push(tp);
+            }
+        }
+
+        for tp in unassigned {
+            let owner = members
+                .iter()
+                .filter(|member| member.topics.contains(&tp.topic))
+                .min_by_key(|member| (assignment[&member.member_id].len(), &member.member_id));
+            if let Some(member) = owner {
+                assignment.get_mut(&member.member_id).unwrap().push(tp);
+            }
+        }
+
+        for owned in assignment.values_mut() {
+            owned.sort_unstable();
+        }
+        assignment
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    fn member(id: &str, topics: &[&str]) -> Subscription {
+        Subscription {
+            member_id: id.to_owned(),
+            topics: topics.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+
+    fn tps(topic: &str, partitions: &[u32]) -> Vec<TopicPartition> {
+        partitions
+            .iter()
+            .map(|p| TopicPartition::new(topic, *p))
+            .collect()
+    }
+
+    fn counts(assignment: &Assignment) -> Vec<usize> {
+        assignment.values().map(|owned| owned.len()).collect()
+    }
+
+    #[test]
+    fn test_range() {
+        let members = vec![member("b", &["t"]), member("a", &["t"])];
+        let partitions = BTreeMap::from([(String::from("t"), 5)]);
+        let assignment = RangeAssignor.assign(&members, &partitions, &Assignment::new());
+        assert_eq!(tps("t", &[0, 1, 2]), assignment["a"]);
+        assert_eq!(tps("t", &[3, 4]), assignment["b"]);
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let members = vec![member("a", &["t", "u"]), member("b", &["t"])];
+        let partitions = BTreeMap::from([(String::from("t"), 3), (String::from("u"), 2)]);
+        let assignment = RoundRobinAssignor.assign(&members, &partitions, &Assignment::new());
+
+        let mut expected_a = tps("t", &[0, 2]);
+        expected_a.extend(tps("u", &[0, 1]));
+        assert_eq!(expected_a, assignment["a"]);
+        assert_eq!(tps("t", &[1]), assignment["b"]);
+    }
+
+    #[test]
+    fn test_unsubscribed_member() {
+        let members = vec![member("a", &["t"]), member("b", &[])];
+        let partitions = BTreeMap::from([(String::from("t"), 2)]);
+        for name in [RANGE, ROUND_ROBIN, STICKY] {
+            let assignment =
+                assignor(name)
+                    .unwrap()
+                    .assign(&members, &partitions, &Assignment::new());
+            assert_eq!(tps("t", &[0, 1]), assignment["a"], "{}", name);
+            assert!(assignment["b"].is_empty(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_sticky_balances() {
+        let partitions = BTreeMap::from([(String::from("t"), 7)]);
+        let members = vec![
+            member("a", &["t"]),
+            member("b", &["t"]),
+            member("c", &["t"]),
+        ];
+        let assignment = StickyAssignor.assign(&members, &partitions, &Assignment::new());
+        let mut sizes = counts(&assignment);
+        sizes.sort_unstable();
+        assert_eq!(vec![2, 2, 3], sizes);
+    }
+
+    #[test]
+    fn test_sticky_minimises_movement() {
+        let partitions = BTreeMap::from([(String::from("t"), 6)]);
+        let members = vec![
+            member("a", &["t"]),
+            member("b", &["t"]),
+            member("c", &["t"]),
+        ];
+        let first = StickyAssignor.assign(&members, &partitions, &Assignment::new());
+
+        // Removing a member only moves that member's partitions.
+        let remaining = vec![member("a", &["t"]), member("c", &["t"])];
+        let second = StickyAssignor.assign(&remaining, &partitions, &first);
+        for id in ["a", "c"] {
+            assert!(first[id].iter().all(|tp| second[id].contains(tp)));
+            assert_eq!(3, second[id].len());
+        }
+
+        // Adding a member only takes partitions away from existing members.
+        let grown = vec![
+            member("a", &["t"]),
+            member("c", &["t"]),
+            member("d", &["t"]),
+        ];
+        let third = StickyAssignor.assign(&grown, &partitions, &second);
+        for id in ["a", "c"] {
+            assert!(third[id].iter().all(|tp| second[id].contains(tp)));
+        }
+        assert_eq!(vec![2, 2, 2], counts(&third));
+    }
+}
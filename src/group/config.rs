@@ -0,0 +1,60 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::time::Duration;
+
+use structopt::StructOpt;
+
+#[derive(Debug, Clone, StructOpt)]
+/// Rift consumer group configuration.
+pub struct Config {
+    #[structopt(
+        long = "group-min-session-timeout-ms",
+        env = "RIFT_GROUP_MIN_SESSION_TIMEOUT_MS",
+        help = "The smallest session timeout a group member may request.",
+        long_help = "Sets the lower bound in milliseconds on the session timeout consumer group members may request when joining.",
+        default_value = "6000",
+        takes_value = true
+    )]
+    /// Define the minimum member session timeout in milliseconds.
+    pub min_session_timeout_ms: u64,
+
+    #[structopt(
+        long = "group-max-session-timeout-ms",
+        env = "RIFT_GROUP_MAX_SESSION_TIMEOUT_MS",
+        help = "The largest session timeout a group member may request.",
+        long_help = "Sets the upper bound in milliseconds on the session timeout consumer group members may request when joining.",
+        default_value = "300000",
+        takes_value = true
+    )]
+    /// Define the maximum member session timeout in milliseconds.
+    pub max_session_timeout_ms: u64,
+
+    #[structopt(
+        long = "group-expiry-interval-ms",
+        env = "RIFT_GROUP_EXPIRY_INTERVAL_MS",
+        help = "How often to check for expired group members.",
+        long_help = "Sets the interval in milliseconds at which consumer group members whose session has expired are evicted.",
+        default_value = "1000",
+        takes_value = true
+    )]
+    /// Define the member expiry check interval in milliseconds.
+    pub expiry_interval_ms: u64,
+}
+
+impl Config {
+    /// Returns the interval between member expiry checks.
+    pub fn expiry_interval(&self) -> Duration {
+        Duration::from_millis(self.expiry_interval_ms)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            min_session_timeout_ms: 6000,
+            max_session_timeout_ms: 300000,
+            expiry_interval_ms: 1000,
+        }
+    }
+}
@@ -0,0 +1,412 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::topic::{self, TopicPartition};
+
+use super::config::Config;
+use super::error::{Error, Result};
+use super::metrics::metrics;
+use super::state::{Group, GroupState, Member};
+
+#[derive(Debug, Clone, PartialEq)]
+/// A member's view of its group after joining or heartbeating.
+pub struct Membership {
+    /// The id assigned to the member by the coordinator.
+    pub member_id: String,
+    /// The generation of the group's current assignment.
+    pub generation: u32,
+    /// The assignment strategy in use by the group.
+    pub strategy: String,
+    /// The partitions assigned to this member.
+    pub assignment: Vec<TopicPartition>,
+}
+
+/// Tracks consumer group membership and distributes partitions between members.
+///
+/// Any change in membership, subscriptions, or the partition count of a
+/// subscribed topic starts a new generation with a freshly computed assignment,
+/// which members pick up on their next heartbeat.
+pub struct Coordinator {
+    logger: slog::Logger,
+    cfg: Config,
+    topics: Arc<topic::Manager>,
+    groups: Mutex<HashMap<String, Group>>,
+    next_member: AtomicU64,
+}
+
+impl Coordinator {
+    /// Create a new coordinator assigning partitions of the supplied topics.
+    pub fn new(logger: slog::Logger, cfg: Config, topics: Arc<topic::Manager>) -> Coordinator {
+        Coordinator {
+            logger,
+            cfg,
+            topics,
+            groups: Mutex::new(HashMap::new()),
+            next_member: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the configuration of this coordinator.
+    pub fn config(&self) -> &Config {
+        &self.cfg
+    }
+
+    /// Returns the state of the supplied group, or [None] if it has no members.
+    pub fn state(&self, group: &str) -> Option<GroupState> {
+        self.groups().get(group).map(Group::state)
+    }
+
+    /// Join a group, or rejoin it with updated subscriptions if `member_id` is
+    /// supplied. `strategies` lists the assignment strategies the member
+    /// supports, most preferred first.
+    pub fn join(
+        &self,
+        group: &str,
+        member_id: Option<&str>,
+        session_timeout_ms: u64,
+        topics: Vec<String>,
+        strategies: Vec<String>,
+    ) -> Result<Membership> {
+        validate_group(group)?;
+        if session_timeout_ms < self.cfg.min_session_timeout_ms
+            || session_timeout_ms > self.cfg.max_session_timeout_ms
+        {
+            return Err(Error::InvalidSessionTimeout {
+                timeout_ms: session_timeout_ms,
+                min_ms: self.cfg.min_session_timeout_ms,
+                max_ms: self.cfg.max_session_timeout_ms,
+            });
+        }
+
+        let mut groups = self.groups();
+        let state = groups.entry(group.to_owned()).or_default();
+        let member_id = match member_id {
+            Some(id) if state.members.contains_key(id) => id.to_owned(),
+            Some(id) => {
+                let err = Error::UnknownMember {
+                    group: group.to_owned(),
+                    member: id.to_owned(),
+                };
+                if state.members.is_empty() {
+                    groups.remove(group);
+                }
+                return Err(err);
+            }
+            None => self.member_id(),
+        };
+
+        let member = Member {
+            topics: topics.into_iter().collect(),
+            strategies,
+            session_timeout: Duration::from_millis(session_timeout_ms),
+            last_seen: Instant::now(),
+            generation: 0,
+        };
+        let previous = state.members.insert(member_id.clone(), member.clone());
+
+        let strategy = match state.select_strategy(&member.strategies) {
+            Some(strategy) => strategy,
+            None => {
+                match previous {
+                    Some(previous) => state.members.insert(member_id.clone(), previous),
+                    None => state.members.remove(&member_id),
+                };
+                if state.members.is_empty() {
+                    groups.remove(group);
+                }
+                return Err(Error::InconsistentStrategy {
+                    group: group.to_owned(),
+                    strategies: member.strategies,
+                });
+            }
+        };
+
+        let changed = match &previous {
+            Some(previous) => {
+                previous.topics != member.topics || state.strategy.as_ref() != Some(&strategy)
+            }
+            None => true,
+        };
+        if changed {
+            state.strategy = Some(strategy);
+            let partitions = self.partition_counts(&state.topics());
+            state.rebalance(partitions);
+            info!(self.logger, "Member joined group."; "group" => group, "member" => &member_id, "generation" => state.generation);
+        }
+
+        let membership = self.acknowledge(state, &member_id);
+        self.observe(group, state);
+        Ok(membership)
+    }
+
+    /// Record that a member is still alive and return its current assignment.
+    pub fn heartbeat(&self, group: &str, member_id: &str) -> Result<Membership> {
+        let mut groups = self.groups();
+        let state = groups
+            .get_mut(group)
+            .filter(|state| state.members.contains_key(member_id))
+            .ok_or_else(|| Error::UnknownMember {
+                group: group.to_owned(),
+                member: member_id.to_owned(),
+            })?;
+
+        // Topics may have gained partitions, or been created or deleted, since the
+        // last generation was computed.
+        let partitions = self.partition_counts(&state.topics());
+        if partitions != state.partitions {
+            state.rebalance(partitions);
+            info!(self.logger, "Subscribed topics changed, rebalancing group."; "group" => group, "generation" => state.generation);
+        }
+
+        let membership = self.acknowledge(state, member_id);
+        self.observe(group, state);
+        Ok(membership)
+    }
+
+    /// Remove a member from a group, redistributing its partitions.
+    pub fn leave(&self, group: &str, member_id: &str) -> Result<()> {
+        let mut groups = self.groups();
+        let state = groups
+            .get_mut(group)
+            .filter(|state| state.members.contains_key(member_id))
+            .ok_or_else(|| Error::UnknownMember {
+                group: group.to_owned(),
+                member: member_id.to_owned(),
+            })?;
+
+        state.members.remove(member_id);
+        info!(self.logger, "Member left group."; "group" => group, "member" => member_id);
+        self.rebalance_or_remove(&mut groups, group);
+        Ok(())
+    }
+
+    /// Evict every member whose session has expired as of `now`, returning the
+    /// number of members evicted.
+    pub fn expire(&self, now: Instant) -> usize {
+        let mut groups = self.groups();
+        let mut affected = Vec::new();
+        let mut evicted = 0;
+        for (group, state) in groups.iter_mut() {
+            let before = state.members.len();
+            state.members.retain(|member_id, member| {
+                let expired = member.is_expired(now);
+                if expired {
+                    info!(self.logger, "Evicted group member after session timeout."; "group" => group, "member" => member_id);
+                }
+                !expired
+            });
+            if state.members.len() != before {
+                evicted += before - state.members.len();
+                affected.push(group.clone());
+            }
+        }
+
+        for group in affected {
+            self.rebalance_or_remove(&mut groups, &group);
+        }
+        evicted
+    }
+
+    fn rebalance_or_remove(&self, groups: &mut HashMap<String, Group>, group: &str) {
+        let state = match groups.get_mut(group) {
+            Some(state) => state,
+            None => return,
+        };
+        if state.members.is_empty() {
+            groups.remove(group);
+            metrics().remove(group);
+            return;
+        }
+
+        let partitions = self.partition_counts(&state.topics());
+        state.rebalance(partitions);
+        self.observe(group, state);
+    }
+
+    fn acknowledge(&self, state: &mut Group, member_id: &str) -> Membership {
+        let generation = state.generation;
+        let member = state.members.get_mut(member_id).unwrap();
+        member.last_seen = Instant::now();
+        member.generation = generation;
+        Membership {
+            member_id: member_id.to_owned(),
+            generation,
+            strategy: state.strategy.clone().unwrap_or_default(),
+            assignment: state.assignment_for(member_id),
+        }
+    }
+
+    fn observe(&self, group: &str, state: &Group) {
+        metrics().observe(group, state.members.len(), state.generation, state.state());
+    }
+
+    fn partition_counts(&self, topics: &BTreeSet<String>) -> BTreeMap<String, u32> {
+        topics
+            .iter()
+            .filter_map(|name| {
+                let topic = self.topics.get(name).ok()?;
+                Some((name.clone(), topic.partitions().len() as u32))
+            })
+            .collect()
+    }
+
+    fn member_id(&self) -> String {
+        let seq = self.next_member.fetch_add(1, Ordering::Relaxed);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos())
+            .unwrap_or_default();
+        format!("member-{:x}-{}", nanos, seq)
+    }
+
+    fn groups(&self) -> MutexGuard<'_, HashMap<String, Group>> {
+        self.groups
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn validate_group(group: &str) -> Result<()> {
+    if group.is_empty() {
+        return Err(Error::InvalidGroup {
+            group: group.to_owned(),
+            reason: "group ids must not be empty",
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+    use crate::storage::LogConfig;
+    use crate::topic::TopicConfig;
+
+    fn coordinator(dir: &std::path::Path) -> Coordinator {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let topics = topic::Manager::open(dir, LogConfig::default()).unwrap();
+        topics.create("events", 4, TopicConfig::new()).unwrap();
+        let cfg = Config {
+            min_session_timeout_ms: 10,
+            max_session_timeout_ms: 1000,
+            expiry_interval_ms: 10,
+        };
+        Coordinator::new(logger, cfg, Arc::new(topics))
+    }
+
+    fn join(coordinator: &Coordinator, group: &str, strategies: &[&str]) -> Result<Membership> {
+        coordinator.join(
+            group,
+            None,
+            100,
+            vec![String::from("events")],
+            strategies.iter().map(|s| s.to_string()).collect(),
+        )
+    }
+
+    #[test]
+    fn test_join_rebalances() {
+        let dir = tempfile::tempdir().unwrap();
+        let coordinator = coordinator(dir.path());
+
+        let first = join(&coordinator, "group-a", &["range"]).unwrap();
+        assert_eq!(1, first.generation);
+        assert_eq!("range", first.strategy);
+        assert_eq!(4, first.assignment.len());
+        assert_eq!(Some(GroupState::Stable), coordinator.state("group-a"));
+
+        let second = join(&coordinator, "group-a", &["range"]).unwrap();
+        assert_eq!(2, second.generation);
+        assert_eq!(2, second.assignment.len());
+        assert_eq!(Some(GroupState::Rebalancing), coordinator.state("group-a"));
+
+        let first = coordinator.heartbeat("group-a", &first.member_id).unwrap();
+        assert_eq!(2, first.generation);
+        assert_eq!(2, first.assignment.len());
+        assert!(first
+            .assignment
+            .iter()
+            .all(|tp| !second.assignment.contains(tp)));
+        assert_eq!(Some(GroupState::Stable), coordinator.state("group-a"));
+
+        coordinator.leave("group-a", &second.member_id).unwrap();
+        let first = coordinator.heartbeat("group-a", &first.member_id).unwrap();
+        assert_eq!(3, first.generation);
+        assert_eq!(4, first.assignment.len());
+
+        coordinator.leave("group-a", &first.member_id).unwrap();
+        assert_eq!(None, coordinator.state("group-a"));
+    }
+
+    #[test]
+    fn test_strategy_selection() {
+        let dir = tempfile::tempdir().unwrap();
+        let coordinator = coordinator(dir.path());
+
+        join(&coordinator, "group-b", &["unknown", "sticky", "range"]).unwrap();
+        let second = join(&coordinator, "group-b", &["range", "sticky"]).unwrap();
+        assert_eq!("range", second.strategy);
+
+        let err = join(&coordinator, "group-b", &["roundrobin"]).unwrap_err();
+        assert!(matches!(err, Error::InconsistentStrategy { .. }));
+
+        let err = join(&coordinator, "group-c", &["unknown"]).unwrap_err();
+        assert!(matches!(err, Error::InconsistentStrategy { .. }));
+        assert_eq!(None, coordinator.state("group-c"));
+    }
+
+    #[test]
+    fn test_expire() {
+        let dir = tempfile::tempdir().unwrap();
+        let coordinator = coordinator(dir.path());
+
+        let member = join(&coordinator, "group-d", &["roundrobin"]).unwrap();
+        assert_eq!(0, coordinator.expire(Instant::now()));
+        assert_eq!(
+            1,
+            coordinator.expire(Instant::now() + Duration::from_millis(500))
+        );
+        assert_eq!(None, coordinator.state("group-d"));
+
+        let err = coordinator
+            .heartbeat("group-d", &member.member_id)
+            .unwrap_err();
+        assert!(matches!(err, Error::UnknownMember { .. }));
+    }
+
+    #[test]
+    fn test_validation() {
+        let dir = tempfile::tempdir().unwrap();
+        let coordinator = coordinator(dir.path());
+
+        let err = coordinator
+            .join("group-e", None, 5, Vec::new(), vec![String::from("range")])
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidSessionTimeout { .. }));
+
+        let err = join(&coordinator, "", &["range"]).unwrap_err();
+        assert!(matches!(err, Error::InvalidGroup { .. }));
+
+        let err = coordinator
+            .join(
+                "group-e",
+                Some("missing"),
+                100,
+                Vec::new(),
+                vec![String::from("range")],
+            )
+            .unwrap_err();
+        assert!(matches!(err, Error::UnknownMember { .. }));
+        assert_eq!(None, coordinator.state("group-e"));
+    }
+}
@@ -0,0 +1,51 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::result;
+
+use thiserror::Error;
+
+/// Custom Result wrapper to simplify usage.
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents errors coordinating consumer groups.
+#[derive(Error, Debug, PartialEq)]
+pub enum Error {
+    /// Handles group ids that can not be used.
+    #[error("invalid group id '{group}': {reason}")]
+    InvalidGroup {
+        /// The offending group id.
+        group: String,
+        /// Why the group id was rejected.
+        reason: &'static str,
+    },
+    /// Handles requests from members the coordinator does not know about, most
+    /// commonly because they were evicted after their session expired.
+    #[error("member '{member}' is not part of group '{group}'")]
+    UnknownMember {
+        /// The group the member claimed to belong to.
+        group: String,
+        /// The unknown member id.
+        member: String,
+    },
+    /// Handles members whose session timeout falls outside the configured bounds.
+    #[error("session timeout {timeout_ms}ms must be between {min_ms}ms and {max_ms}ms")]
+    InvalidSessionTimeout {
+        /// The requested session timeout.
+        timeout_ms: u64,
+        /// The smallest allowed session timeout.
+        min_ms: u64,
+        /// The largest allowed session timeout.
+        max_ms: u64,
+    },
+    /// Handles members that share no supported assignment strategy with the rest of the group.
+    #[error(
+        "no assignment strategy in {strategies:?} is supported by every member of group '{group}'"
+    )]
+    InconsistentStrategy {
+        /// The group being joined.
+        group: String,
+        /// The strategies offered by the joining member.
+        strategies: Vec<String>,
+    },
+}
@@ -0,0 +1,63 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::sync::OnceLock;
+
+use prometheus::IntGaugeVec;
+
+use crate::metrics::{register_int_gauge_vec, Opt};
+
+use super::state::GroupState;
+
+const SUBSYSTEM: &str = "group";
+const GROUP_LABEL: &str = "group";
+
+/// The per group series exported by the coordinator.
+pub(super) struct Metrics {
+    members: IntGaugeVec,
+    generation: IntGaugeVec,
+    state: IntGaugeVec,
+}
+
+impl Metrics {
+    /// Publish the current membership, generation, and state of a group.
+    pub(super) fn observe(&self, group: &str, members: usize, generation: u32, state: GroupState) {
+        self.members.with_label_values(&[group]).set(members as i64);
+        self.generation
+            .with_label_values(&[group])
+            .set(generation as i64);
+        self.state.with_label_values(&[group]).set(state as i64);
+    }
+
+    /// Stop exporting series for a group that no longer exists.
+    pub(super) fn remove(&self, group: &str) {
+        let _ = self.members.remove_label_values(&[group]);
+        let _ = self.generation.remove_label_values(&[group]);
+        let _ = self.state.remove_label_values(&[group]);
+    }
+}
+
+fn gauge(name: &str, help: &str) -> IntGaugeVec {
+    let opts = vec![
+        Opt::Namespace(String::from("rift")),
+        Opt::Subsystem(String::from(SUBSYSTEM)),
+        Opt::Label(String::from(GROUP_LABEL)),
+    ];
+    register_int_gauge_vec(name, help, Some(opts)).expect("group metrics registered twice")
+}
+
+/// Returns the process wide group metrics, registering them on first use.
+pub(super) fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(|| Metrics {
+        members: gauge("members", "The number of members in a consumer group."),
+        generation: gauge(
+            "generation",
+            "The generation of a consumer group's current assignment.",
+        ),
+        state: gauge(
+            "state",
+            "The state of a consumer group: 0 empty, 1 rebalancing, 2 stable.",
+        ),
+    })
+}
@@ -0,0 +1,18 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod assignor;
+mod config;
+mod coordinator;
+mod error;
+mod metrics;
+mod state;
+
+pub use self::assignor::{
+    assignor, Assignment, Assignor, RangeAssignor, RoundRobinAssignor, StickyAssignor,
+    Subscription, RANGE, ROUND_ROBIN, STICKY,
+};
+pub use self::config::Config;
+pub use self::coordinator::{Coordinator, Membership};
+pub use self::error::{Error, Result};
+pub use self::state::GroupState;
@@ -0,0 +1,124 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::topic::TopicPartition;
+
+use super::assignor::{assignor, Assignment, Subscription};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The lifecycle state of a consumer group.
+pub enum GroupState {
+    /// The group has no members.
+    Empty = 0,
+    /// The assignment changed and at least one member has not yet received it.
+    Rebalancing = 1,
+    /// Every member has received the current assignment.
+    Stable = 2,
+}
+
+impl fmt::Display for GroupState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            GroupState::Empty => "empty",
+            GroupState::Rebalancing => "rebalancing",
+            GroupState::Stable => "stable",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct Member {
+    pub(super) topics: BTreeSet<String>,
+    pub(super) strategies: Vec<String>,
+    pub(super) session_timeout: Duration,
+    pub(super) last_seen: Instant,
+    /// The last generation this member was handed an assignment for.
+    pub(super) generation: u32,
+}
+
+impl Member {
+    pub(super) fn is_expired(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.last_seen) > self.session_timeout
+    }
+}
+
+#[derive(Debug, Default)]
+pub(super) struct Group {
+    pub(super) generation: u32,
+    pub(super) strategy: Option<String>,
+    pub(super) members: BTreeMap<String, Member>,
+    pub(super) partitions: BTreeMap<String, u32>,
+    pub(super) assignment: Assignment,
+}
+
+impl Group {
+    pub(super) fn state(&self) -> GroupState {
+        if self.members.is_empty() {
+            GroupState::Empty
+        } else if self
+            .members
+            .values()
+            .any(|member| member.generation != self.generation)
+        {
+            GroupState::Rebalancing
+        } else {
+            GroupState::Stable
+        }
+    }
+
+    /// Returns every topic subscribed to by at least one member.
+    pub(super) fn topics(&self) -> BTreeSet<String> {
+        self.members
+            .values()
+            .flat_map(|member| member.topics.iter().cloned())
+            .collect()
+    }
+
+    /// Returns the first strategy, in the supplied order of preference, that every
+    /// current member supports and that names a known assignor.
+    pub(super) fn select_strategy(&self, preferences: &[String]) -> Option<String> {
+        preferences
+            .iter()
+            .filter(|strategy| assignor(strategy).is_some())
+            .find(|strategy| {
+                self.members
+                    .values()
+                    .all(|member| member.strategies.contains(strategy))
+            })
+            .cloned()
+    }
+
+    /// Bump the generation and recompute the assignment for the current members.
+    pub(super) fn rebalance(&mut self, partitions: BTreeMap<String, u32>) {
+        self.generation = self.generation.wrapping_add(1);
+        self.partitions = partitions;
+
+        let assignor = match self.strategy.as_deref().and_then(assignor) {
+            Some(assignor) => assignor,
+            None => {
+                self.assignment = Assignment::new();
+                return;
+            }
+        };
+        let subscriptions: Vec<Subscription> = self
+            .members
+            .iter()
+            .map(|(id, member)| Subscription {
+                member_id: id.clone(),
+                topics: member.topics.clone(),
+            })
+            .collect();
+        self.assignment = assignor.assign(&subscriptions, &self.partitions, &self.assignment);
+    }
+
+    pub(super) fn assignment_for(&self, member: &str) -> Vec<TopicPartition> {
+        self.assignment.get(member).cloned().unwrap_or_default()
+    }
+}
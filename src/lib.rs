@@ -8,9 +8,49 @@
 #[macro_use]
 extern crate slog;
 
+/// An AMQP 0-9-1 listener mapping AMQP queues and exchanges onto rift.
+pub mod amqp;
+/// Request dispatch across the subsystems that make up a rift server.
+pub mod broker;
+/// Background enforcement of topic retention limits.
+pub mod cleaner;
+/// A blocking client for the native binary protocol.
+pub mod client;
+/// Cluster membership and the replicated cluster metadata.
+pub mod cluster;
+/// Binary encoding and decoding primitives shared by the record format and wire protocol.
+pub mod codec;
+/// Exchanges routing published records to the topics bound to them.
+pub mod exchange;
+/// Consumer group membership and partition assignment.
+pub mod group;
 /// General logger implementation based on the slog ecosystem.
 pub mod log;
 /// General metrics collection/management based on the prometheus ecosystem.
 pub mod metrics;
+/// An MQTT 3.1.1 and 5.0 listener mapping MQTT topics onto rift topics.
+pub mod mqtt;
+/// Durable storage of the offsets committed by consumer groups.
+pub mod offset;
+/// Assignment of idempotent producer ids.
+pub mod producer;
+/// The native length prefixed binary request/response protocol.
+pub mod protocol;
+/// Work queues leasing individual messages to consumers until they are acknowledged.
+pub mod queue;
+/// Raft based replication of partitions across a cluster of nodes.
+pub mod raft;
+/// The record format stored in partition logs.
+pub mod record;
 /// The entrypoint, configuration, and logic for the `riftd` binary.
 pub mod riftd;
+/// Delayed delivery of messages scheduled for a future time.
+pub mod schedule;
+/// SQL-like selectors filtering the records returned to consumers.
+pub mod selector;
+/// Network listeners serving the native binary protocol.
+pub mod server;
+/// Durable append-only segmented log storage.
+pub mod storage;
+/// Named, partitioned topics backed by the storage engine.
+pub mod topic;
@@ -80,7 +80,7 @@ mod tests {
         assert_eq!(Level::Debug, Level::from_str("debug").unwrap());
 
         let res = Level::from_str("nope");
-        assert_eq!(true, res.is_err());
+        assert!(res.is_err());
         let err = res.unwrap_err();
 
         match err {
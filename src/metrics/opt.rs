@@ -206,8 +206,7 @@ mod tests {
         let const_val_2 = String::from("val2");
 
         let variable_key = String::from("variable");
-        let mut variable_lables = Vec::with_capacity(1);
-        variable_lables.push(variable_key.clone());
+        let variable_lables = vec![variable_key.clone()];
 
         let variable_key_2 = String::from("variable2");
 
@@ -276,8 +275,7 @@ mod tests {
         let const_val_2 = String::from("val2");
 
         let variable_key = String::from("variable");
-        let mut variable_lables = Vec::with_capacity(1);
-        variable_lables.push(variable_key.clone());
+        let variable_lables = vec![variable_key.clone()];
 
         let variable_key_2 = String::from("variable2");
 
@@ -0,0 +1,72 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::net::SocketAddr;
+
+use structopt::StructOpt;
+
+#[derive(Debug, Clone, StructOpt)]
+/// Rift MQTT listener configuration.
+pub struct Config {
+    #[structopt(
+        long = "mqtt-listen-address",
+        env = "RIFT_MQTT_LISTEN_ADDRESS",
+        help = "The address to listen for MQTT connections on.",
+        long_help = "Sets the address and port an MQTT 3.1.1 and 5.0 listener binds to. MQTT clients are only served when this is set.",
+        takes_value = true
+    )]
+    /// Define the address to listen for MQTT connections on, if any.
+    pub mqtt_listen_address: Option<SocketAddr>,
+
+    #[structopt(
+        long = "mqtt-max-packet-bytes",
+        env = "RIFT_MQTT_MAX_PACKET_BYTES",
+        help = "The maximum size of a single MQTT packet.",
+        long_help = "Sets the largest MQTT packet, in bytes, the listener will accept from clients before disconnecting them.",
+        default_value = "1048576",
+        takes_value = true
+    )]
+    /// Define the maximum MQTT packet size in bytes.
+    pub mqtt_max_packet_bytes: u32,
+
+    #[structopt(
+        long = "mqtt-partitions",
+        env = "RIFT_MQTT_PARTITIONS",
+        help = "The number of partitions of topics created by MQTT publishers.",
+        long_help = "Sets how many partitions the topic an MQTT message is published to is created with, should it not exist yet. MQTT only orders messages published to a single partition.",
+        default_value = "1",
+        takes_value = true
+    )]
+    /// Define the number of partitions of topics created for MQTT messages.
+    pub mqtt_partitions: u32,
+
+    #[structopt(
+        long = "mqtt-max-inflight",
+        env = "RIFT_MQTT_MAX_INFLIGHT",
+        help = "The most QoS 1 and 2 messages an MQTT client may have unacknowledged.",
+        long_help = "Sets how many QoS 1 and 2 messages are delivered to each MQTT client before it must acknowledge some of them, lowered for MQTT 5.0 clients with a smaller receive maximum.",
+        default_value = "32",
+        takes_value = true
+    )]
+    /// Define the maximum number of unacknowledged messages per MQTT client.
+    pub mqtt_max_inflight: u16,
+}
+
+impl Config {
+    /// Returns whether or not MQTT clients are served, which requires a
+    /// listen address.
+    pub fn enabled(&self) -> bool {
+        self.mqtt_listen_address.is_some()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            mqtt_listen_address: None,
+            mqtt_max_packet_bytes: 1048576,
+            mqtt_partitions: 1,
+            mqtt_max_inflight: 32,
+        }
+    }
+}
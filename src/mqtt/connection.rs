@@ -0,0 +1,834 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::BTreeMap,
+    io::{BufReader, BufWriter, Write},
+    net::{Shutdown, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::protocol::ErrorCode;
+use crate::record::{Header, Record};
+use crate::topic::TopicPartition;
+
+use super::error::{Error, Result};
+use super::filter;
+use super::gateway::{self, Gateway, CLIENT_ID_HEADER, RETAIN_HEADER};
+use super::packet::{
+    read_packet, reason, write_packet, Ack, ConnAck, Connect, Disconnect, Packet, Properties,
+    Publish, QoS, SubAck, Subscribe, Unsubscribe, Version, Will,
+};
+use super::session::{Outgoing, SessionState};
+use super::state::Retained;
+
+/// How long a client has to send CONNECT once it has connected.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often the topics a client subscribed to are checked for new messages.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// How often the topics matching a client's subscriptions are listed again,
+/// to pick up topics created since.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Why a connection stopped being served.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Ended {
+    /// The client sent DISCONNECT, which may ask for its will to be published.
+    Disconnected { publish_will: bool },
+    /// The connection closed without a DISCONNECT.
+    Closed,
+    /// Another connection claimed the client's session.
+    TakenOver,
+    /// The client sent nothing for one and a half times its keep alive.
+    KeepAliveExpired,
+}
+
+/// Serve a single MQTT client connection until it is closed.
+///
+/// The connection must start with CONNECT, after which packets are read on a
+/// separate thread so that messages can be delivered to the client while it
+/// is idle. Unless the client sends DISCONNECT, its will is published once
+/// the connection closes, however it closes.
+pub(super) fn serve(logger: slog::Logger, stream: TcpStream, gateway: Arc<Gateway>) -> Result<()> {
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream.try_clone()?);
+    let connect = match read_packet(&mut reader, Version::V5, gateway.max_packet_bytes) {
+        Ok(Some(Packet::Connect(connect))) => connect,
+        Ok(Some(_)) => {
+            return Err(Error::Protocol {
+                reason: "the first packet must be CONNECT",
+            })
+        }
+        Ok(None) => return Ok(()),
+        Err(err @ Error::UnsupportedVersion { .. }) => {
+            let connack = Packet::ConnAck(ConnAck {
+                session_present: false,
+                code: reason::V3_UNACCEPTABLE_VERSION,
+                properties: Properties::default(),
+            });
+            write_packet(&mut writer, &connack, Version::V311)?;
+            writer.flush()?;
+            return Err(err);
+        }
+        Err(err) => return Err(err),
+    };
+    stream.set_read_timeout(None)?;
+
+    let mut connection = match Connection::accept(logger, gateway.clone(), writer, *connect)? {
+        Some(connection) => connection,
+        None => return Ok(()),
+    };
+
+    let (sender, packets) = mpsc::channel();
+    let (version, max_packet_bytes) = (connection.version, gateway.max_packet_bytes);
+    thread::spawn(move || loop {
+        let packet = read_packet(&mut reader, version, max_packet_bytes);
+        let more = matches!(packet, Ok(Some(_)));
+        if sender.send(packet).is_err() || !more {
+            return;
+        }
+    });
+
+    let result = connection.run(&packets);
+    let (publish_will, disconnect) = match &result {
+        Ok(Ended::Disconnected { publish_will }) => (*publish_will, None),
+        Ok(Ended::Closed) => (true, None),
+        Ok(Ended::TakenOver) => (true, Some(reason::SESSION_TAKEN_OVER)),
+        Ok(Ended::KeepAliveExpired) => (true, Some(reason::KEEP_ALIVE_TIMEOUT)),
+        Err(err) => (true, disconnect_reason(err)),
+    };
+    if let Some(reason) = disconnect {
+        connection.disconnect(reason);
+    }
+    let _ = stream.shutdown(Shutdown::Both);
+    match result {
+        Ok(ended) => {
+            debug!(connection.logger, "MQTT client disconnected."; "reason" => format!("{:?}", ended));
+            connection.close(publish_will);
+            Ok(())
+        }
+        Err(err) => {
+            connection.close(publish_will);
+            Err(err)
+        }
+    }
+}
+
+/// Returns the reason code MQTT 5.0 clients are disconnected with after the
+/// supplied error, if the connection is still usable.
+fn disconnect_reason(err: &Error) -> Option<u8> {
+    match err {
+        Error::Codec(_) | Error::Malformed { .. } => Some(reason::MALFORMED_PACKET),
+        Error::Protocol { .. } => Some(reason::PROTOCOL_ERROR),
+        Error::PacketTooLarge { .. } => Some(reason::PACKET_TOO_LARGE),
+        Error::InvalidTopic { .. } => Some(reason::TOPIC_NAME_INVALID),
+        Error::Broker(_) | Error::Topic(_) | Error::Corrupt { .. } => {
+            Some(reason::UNSPECIFIED_ERROR)
+        }
+        Error::Io(_) | Error::Bind { .. } | Error::Accept(_) | Error::UnsupportedVersion { .. } => {
+            None
+        }
+    }
+}
+
+/// An established MQTT connection along with the session it holds.
+struct Connection {
+    logger: slog::Logger,
+    gateway: Arc<Gateway>,
+    writer: BufWriter<TcpStream>,
+    version: Version,
+    client_id: String,
+    state: SessionState,
+    will: Option<Will>,
+    taken_over: Arc<AtomicBool>,
+    keep_alive: Option<Duration>,
+    /// The most QoS 1 and 2 messages that may await acknowledgement.
+    max_inflight: usize,
+    /// The largest packet the client accepts.
+    max_packet_bytes: usize,
+    /// The partitions matching a subscription, along with the MQTT topic
+    /// their messages are delivered under.
+    partitions: BTreeMap<TopicPartition, String>,
+    refreshed_at: Instant,
+    delivered_at: Instant,
+    /// The positions last saved to the store.
+    saved: BTreeMap<TopicPartition, u64>,
+}
+
+impl Connection {
+    /// Answer the supplied CONNECT, returning the established connection or
+    /// [None] if the client was refused.
+    fn accept(
+        logger: slog::Logger,
+        gateway: Arc<Gateway>,
+        mut writer: BufWriter<TcpStream>,
+        connect: Connect,
+    ) -> Result<Option<Connection>> {
+        let version = connect.version;
+        let v5 = version == Version::V5;
+        let mut refuse = |v3_code: u8, v5_code: u8| -> Result<Option<Connection>> {
+            let connack = Packet::ConnAck(ConnAck {
+                session_present: false,
+                code: if v5 { v5_code } else { v3_code },
+                properties: Properties::default(),
+            });
+            write_packet(&mut writer, &connack, version)?;
+            writer.flush()?;
+            Ok(None)
+        };
+
+        if connect.properties.authentication_method.is_some() {
+            return refuse(
+                reason::V3_SERVER_UNAVAILABLE,
+                reason::BAD_AUTHENTICATION_METHOD,
+            );
+        }
+        if connect.properties.receive_maximum == Some(0) {
+            return Err(Error::Protocol {
+                reason: "the receive maximum must not be zero",
+            });
+        }
+        if let Some(will) = &connect.will {
+            if let Err(err) = filter::to_topic(&will.topic) {
+                // MQTT 3.1.1 servers must close the connection without a CONNACK.
+                return match v5 {
+                    true => refuse(reason::V3_SERVER_UNAVAILABLE, reason::TOPIC_NAME_INVALID),
+                    false => Err(err),
+                };
+            }
+        }
+
+        let mut properties = Properties::default();
+        let client_id = if connect.client_id.is_empty() {
+            if !v5 && !connect.clean_start {
+                return refuse(
+                    reason::V3_IDENTIFIER_REJECTED,
+                    reason::CLIENT_IDENTIFIER_INVALID,
+                );
+            }
+            let client_id = gateway.assign_client_id();
+            if v5 {
+                properties.assigned_client_id = Some(client_id.clone());
+            }
+            client_id
+        } else {
+            connect.client_id
+        };
+
+        let claim = match gateway.sessions.claim(&client_id, connect.clean_start) {
+            Some(claim) => claim,
+            None => {
+                warn!(logger, "MQTT session was not handed over in time."; "client_id" => &client_id);
+                return refuse(reason::V3_SERVER_UNAVAILABLE, reason::SERVER_UNAVAILABLE);
+            }
+        };
+        let logger = logger.new(o!("client_id" => client_id.clone()));
+        if let Some(discarded) = claim.discarded.filter(|discarded| discarded.persisted) {
+            if let Err(err) = gateway
+                .store
+                .delete_session(&client_id, discarded.positions.keys())
+            {
+                warn!(logger, "Failed to delete discarded MQTT session."; "error" => err.to_string());
+            }
+        }
+
+        let mut connection = Connection {
+            logger,
+            gateway: gateway.clone(),
+            writer,
+            version,
+            client_id,
+            state: claim.state,
+            will: connect.will,
+            taken_over: claim.taken_over,
+            keep_alive: match connect.keep_alive {
+                0 => None,
+                keep_alive => Some(Duration::from_millis(keep_alive as u64 * 1500)),
+            },
+            max_inflight: connect
+                .properties
+                .receive_maximum
+                .unwrap_or(u16::MAX)
+                .min(gateway.max_inflight) as usize,
+            max_packet_bytes: connect.properties.maximum_packet_size.unwrap_or(u32::MAX) as usize,
+            partitions: BTreeMap::new(),
+            refreshed_at: Instant::now(),
+            delivered_at: Instant::now(),
+            saved: BTreeMap::new(),
+        };
+        connection.state.expiry_interval = match version {
+            Version::V311 if connect.clean_start => 0,
+            Version::V311 => u32::MAX,
+            Version::V5 => connect.properties.session_expiry.unwrap_or(0),
+        };
+        if let Err(err) = connection.start(claim.present, properties) {
+            connection.close(false);
+            return Err(err);
+        }
+        Ok(Some(connection))
+    }
+
+    /// Acknowledge the connection, then resume delivering the messages the
+    /// client had yet to acknowledge when its session was last connected.
+    fn start(&mut self, present: bool, mut properties: Properties) -> Result<()> {
+        if self.state.is_persistent() {
+            self.save_session()?;
+        }
+        self.refresh(&[])?;
+
+        if self.version == Version::V5 {
+            properties.maximum_packet_size = Some(self.gateway.max_packet_bytes);
+            properties.subscription_identifiers_available = Some(0);
+            properties.shared_subscriptions_available = Some(0);
+        }
+        self.send(&Packet::ConnAck(ConnAck {
+            session_present: present,
+            code: reason::SUCCESS,
+            properties,
+        }))?;
+
+        let outgoing: Vec<(u16, Outgoing)> = self
+            .state
+            .outgoing
+            .iter()
+            .map(|(id, out)| (*id, out.clone()))
+            .collect();
+        for (packet_id, out) in outgoing {
+            if out.released {
+                self.send(&Packet::PubRel(Ack::new(packet_id)))?;
+            } else {
+                let publish = Publish {
+                    dup: true,
+                    ..out.publish
+                };
+                self.send(&Packet::Publish(publish))?;
+            }
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Serve packets and deliver messages until the connection ends.
+    fn run(&mut self, packets: &Receiver<Result<Option<Packet>>>) -> Result<Ended> {
+        let mut last_packet = Instant::now();
+        loop {
+            match packets.recv_timeout(POLL_INTERVAL) {
+                Ok(Ok(Some(packet))) => {
+                    last_packet = Instant::now();
+                    if let Some(ended) = self.handle(packet)? {
+                        return Ok(ended);
+                    }
+                }
+                Ok(Ok(None)) | Err(RecvTimeoutError::Disconnected) => return Ok(Ended::Closed),
+                Ok(Err(err)) => return Err(err),
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+            if self.taken_over.load(Ordering::SeqCst) {
+                return Ok(Ended::TakenOver);
+            }
+            if self
+                .keep_alive
+                .is_some_and(|keep_alive| last_packet.elapsed() > keep_alive)
+            {
+                return Ok(Ended::KeepAliveExpired);
+            }
+            if self.delivered_at.elapsed() >= POLL_INTERVAL {
+                self.deliver()?;
+                self.delivered_at = Instant::now();
+            }
+            if self.state.is_persistent() {
+                self.save_positions()?;
+            }
+            self.writer.flush()?;
+        }
+    }
+
+    /// Serve a single packet, returning why the connection ended if it did.
+    fn handle(&mut self, packet: Packet) -> Result<Option<Ended>> {
+        match packet {
+            Packet::Publish(publish) => self.receive(publish)?,
+            Packet::PubAck(ack) => {
+                if self
+                    .state
+                    .outgoing
+                    .get(&ack.packet_id)
+                    .is_some_and(|out| out.publish.qos == QoS::AtLeastOnce)
+                {
+                    self.state.outgoing.remove(&ack.packet_id);
+                }
+            }
+            Packet::PubRec(ack) => match self.state.outgoing.get_mut(&ack.packet_id) {
+                Some(out) if out.publish.qos == QoS::ExactlyOnce => {
+                    // MQTT 5.0 clients may refuse a message, which ends its delivery.
+                    if ack.reason >= reason::UNSPECIFIED_ERROR {
+                        self.state.outgoing.remove(&ack.packet_id);
+                    } else {
+                        out.released = true;
+                        self.send(&Packet::PubRel(Ack::new(ack.packet_id)))?;
+                    }
+                }
+                _ => self.send(&Packet::PubRel(Ack {
+                    packet_id: ack.packet_id,
+                    reason: reason::PACKET_IDENTIFIER_NOT_FOUND,
+                }))?,
+            },
+            Packet::PubRel(ack) => {
+                let reason = if self.state.incoming.remove(&ack.packet_id) {
+                    reason::SUCCESS
+                } else {
+                    reason::PACKET_IDENTIFIER_NOT_FOUND
+                };
+                self.send(&Packet::PubComp(Ack {
+                    packet_id: ack.packet_id,
+                    reason,
+                }))?;
+            }
+            Packet::PubComp(ack) => {
+                if self
+                    .state
+                    .outgoing
+                    .get(&ack.packet_id)
+                    .is_some_and(|out| out.released)
+                {
+                    self.state.outgoing.remove(&ack.packet_id);
+                }
+            }
+            Packet::Subscribe(subscribe) => self.subscribe(subscribe)?,
+            Packet::Unsubscribe(unsubscribe) => self.unsubscribe(unsubscribe)?,
+            Packet::PingReq => self.send(&Packet::PingResp)?,
+            Packet::Disconnect(disconnect) => {
+                if let Some(expiry_interval) = disconnect.properties.session_expiry {
+                    if !self.state.is_persistent() && expiry_interval > 0 {
+                        return Err(Error::Protocol {
+                            reason: "a session that ends with its connection can not be made to outlive it",
+                        });
+                    }
+                    self.state.expiry_interval = expiry_interval;
+                }
+                let publish_will = disconnect.reason == reason::DISCONNECT_WITH_WILL;
+                return Ok(Some(Ended::Disconnected { publish_will }));
+            }
+            Packet::Connect(_) => {
+                return Err(Error::Protocol {
+                    reason: "CONNECT may only be sent once",
+                })
+            }
+            Packet::ConnAck(_) | Packet::SubAck(_) | Packet::UnsubAck(_) | Packet::PingResp => {
+                return Err(Error::Protocol {
+                    reason: "clients may not send CONNACK, SUBACK, UNSUBACK, or PINGRESP",
+                })
+            }
+        }
+        Ok(None)
+    }
+
+    /// Publish a message sent by the client, then acknowledge it according
+    /// to its QoS. QoS 2 messages are only published the first time they are
+    /// received, however often the client retransmits them before PUBREL.
+    fn receive(&mut self, publish: Publish) -> Result<()> {
+        if publish.properties.topic_alias.is_some() {
+            return Err(Error::Protocol {
+                reason: "topic aliases are not supported",
+            });
+        }
+        let packet_id = publish.packet_id;
+        if publish.qos == QoS::ExactlyOnce && self.state.incoming.contains(&packet_id) {
+            return self.send(&Packet::PubRec(Ack::new(packet_id)));
+        }
+
+        let code = match self.gateway.publish(&self.client_id, &publish) {
+            Ok(()) => reason::SUCCESS,
+            Err(err @ Error::InvalidTopic { .. }) => return Err(err),
+            Err(err) => {
+                warn!(self.logger, "Failed to publish MQTT message."; "mqtt_topic" => &publish.topic, "error" => err.to_string());
+                // MQTT 3.1.1 can only report the failure by disconnecting.
+                if self.version == Version::V311 && publish.qos != QoS::AtMostOnce {
+                    return Err(err);
+                }
+                reason::UNSPECIFIED_ERROR
+            }
+        };
+        let ack = Ack {
+            packet_id,
+            reason: code,
+        };
+        match publish.qos {
+            QoS::AtMostOnce => Ok(()),
+            QoS::AtLeastOnce => self.send(&Packet::PubAck(ack)),
+            QoS::ExactlyOnce => {
+                if code == reason::SUCCESS {
+                    self.state.incoming.insert(packet_id);
+                }
+                self.send(&Packet::PubRec(ack))
+            }
+        }
+    }
+
+    fn subscribe(&mut self, subscribe: Subscribe) -> Result<()> {
+        let v5 = self.version == Version::V5;
+        let mut codes = Vec::with_capacity(subscribe.filters.len());
+        let mut added = Vec::new();
+        let mut retained = Vec::new();
+        for (topic_filter, options) in subscribe.filters {
+            if topic_filter.starts_with("$share/") {
+                codes.push(match v5 {
+                    true => reason::SHARED_SUBSCRIPTIONS_NOT_SUPPORTED,
+                    false => reason::V3_FAILURE,
+                });
+                continue;
+            }
+            if !filter::validate(&topic_filter) {
+                codes.push(match v5 {
+                    true => reason::TOPIC_FILTER_INVALID,
+                    false => reason::V3_FAILURE,
+                });
+                continue;
+            }
+            let existed = self
+                .state
+                .subscriptions
+                .insert(topic_filter.clone(), options)
+                .is_some();
+            codes.push(options.qos as u8);
+            if options.retain_handling == 0 || (options.retain_handling == 1 && !existed) {
+                retained.push((topic_filter.clone(), options.qos));
+            }
+            if !existed {
+                added.push(topic_filter);
+            }
+        }
+
+        // Subscribing only delivers messages published from now on.
+        self.refresh(&added)?;
+        if self.state.is_persistent() {
+            self.save_session()?;
+            self.save_positions()?;
+        }
+        self.send(&Packet::SubAck(SubAck {
+            packet_id: subscribe.packet_id,
+            codes,
+        }))?;
+
+        for (topic_filter, qos) in retained {
+            for (name, message) in self.gateway.store.retained(&topic_filter) {
+                self.deliver_retained(name, message, qos)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn unsubscribe(&mut self, unsubscribe: Unsubscribe) -> Result<()> {
+        let codes = unsubscribe
+            .filters
+            .iter()
+            .map(
+                |topic_filter| match self.state.subscriptions.remove(topic_filter) {
+                    Some(_) => reason::SUCCESS,
+                    None => reason::NO_SUBSCRIPTION_EXISTED,
+                },
+            )
+            .collect();
+        self.refresh(&[])?;
+        if self.state.is_persistent() {
+            self.save_session()?;
+        }
+        self.send(&Packet::UnsubAck(SubAck {
+            packet_id: unsubscribe.packet_id,
+            codes,
+        }))
+    }
+
+    /// List the partitions matching the client's subscriptions again. Delivery
+    /// of partitions that did not match before starts at their first message,
+    /// as they were created since, unless they only match the supplied newly
+    /// added subscriptions, in which case it starts at their next message.
+    fn refresh(&mut self, added: &[String]) -> Result<()> {
+        let mut partitions = BTreeMap::new();
+        for topic in self.gateway.topics()? {
+            let name = match filter::to_name(&topic.name) {
+                Some(name) => name,
+                None => continue,
+            };
+            let (mut existing, mut new) = (false, false);
+            for topic_filter in self.state.subscriptions.keys() {
+                if filter::matches(topic_filter, &name) {
+                    match added.contains(topic_filter) {
+                        true => new = true,
+                        false => existing = true,
+                    }
+                }
+            }
+            if !existing && !new {
+                continue;
+            }
+            for partition in topic.partitions {
+                let key = TopicPartition::new(topic.name.clone(), partition.id);
+                let start = match existing {
+                    true => partition.start_offset,
+                    false => partition.next_offset,
+                };
+                self.state.positions.entry(key.clone()).or_insert(start);
+                partitions.insert(key, name.clone());
+            }
+        }
+
+        let forgotten: Vec<TopicPartition> = self
+            .state
+            .positions
+            .keys()
+            .filter(|partition| !partitions.contains_key(*partition))
+            .cloned()
+            .collect();
+        for partition in &forgotten {
+            self.state.positions.remove(partition);
+            self.saved.remove(partition);
+        }
+        if self.state.persisted && !forgotten.is_empty() {
+            self.gateway
+                .store
+                .forget_positions(&self.client_id, &forgotten)?;
+        }
+        self.partitions = partitions;
+        self.refreshed_at = Instant::now();
+        Ok(())
+    }
+
+    /// Deliver the messages published to the partitions matching the client's
+    /// subscriptions since they were last delivered, until as many QoS 1 and
+    /// 2 messages await acknowledgement as the client allows.
+    fn deliver(&mut self) -> Result<()> {
+        if self.state.subscriptions.is_empty() {
+            return Ok(());
+        }
+        if self.refreshed_at.elapsed() >= REFRESH_INTERVAL {
+            self.refresh(&[])?;
+        }
+        if self.state.outgoing.len() >= self.max_inflight {
+            return Ok(());
+        }
+        let positions: Vec<(String, u32, u64)> = self
+            .partitions
+            .keys()
+            .filter_map(|partition| {
+                let offset = *self.state.positions.get(partition)?;
+                Some((partition.topic.clone(), partition.partition, offset))
+            })
+            .collect();
+        if positions.is_empty() {
+            return Ok(());
+        }
+
+        for fetched in self.gateway.fetch(positions)? {
+            let partition = TopicPartition::new(fetched.topic, fetched.partition);
+            match fetched.error_code {
+                ErrorCode::None => {}
+                // Retention removed the messages before they were delivered, or
+                // the topic was deleted, so start over from what remains.
+                ErrorCode::OffsetOutOfRange
+                | ErrorCode::TopicNotFound
+                | ErrorCode::PartitionNotFound => {
+                    self.state.positions.remove(&partition);
+                    self.refreshed_at -= REFRESH_INTERVAL;
+                    continue;
+                }
+                code => {
+                    debug!(self.logger, "Failed to fetch messages for MQTT delivery."; "partition" => partition.to_string(), "error" => code.to_string());
+                    continue;
+                }
+            }
+            let name = match self.partitions.get(&partition) {
+                Some(name) => name.clone(),
+                None => continue,
+            };
+            let mut next_offset = fetched.next_offset;
+            for fetched in fetched.records {
+                if let Some((qos, retain)) = self.delivery(&name, &fetched.record) {
+                    if qos != QoS::AtMostOnce && self.state.outgoing.len() >= self.max_inflight {
+                        next_offset = fetched.offset;
+                        break;
+                    }
+                    let publish = self.publish(name.clone(), qos, retain, fetched.record);
+                    self.send_publish(publish, Some((partition.clone(), fetched.offset)))?;
+                }
+            }
+            // Continue past records the fetch skipped, such as expired ones,
+            // unless delivery stopped early.
+            self.state.positions.insert(partition, next_offset);
+        }
+        Ok(())
+    }
+
+    /// Returns the QoS and retain flag a record is delivered with, the QoS
+    /// being the highest of the subscriptions matching it capped by the QoS it
+    /// was published with, or [None] if no subscription wants it.
+    fn delivery(&self, name: &str, record: &Record) -> Option<(QoS, bool)> {
+        let publisher = record.header(CLIENT_ID_HEADER);
+        let mut delivery: Option<(QoS, bool)> = None;
+        for (topic_filter, options) in &self.state.subscriptions {
+            if !filter::matches(topic_filter, name)
+                || (options.no_local && publisher == Some(self.client_id.as_bytes()))
+            {
+                continue;
+            }
+            let (qos, retain_as_published) = delivery.unwrap_or((QoS::AtMostOnce, false));
+            delivery = Some((
+                qos.max(options.qos),
+                retain_as_published || options.retain_as_published,
+            ));
+        }
+        let (qos, retain_as_published) = delivery?;
+        let retain = retain_as_published && record.header(RETAIN_HEADER) == Some(b"1");
+        Some((qos.min(gateway::record_qos(&record.headers)), retain))
+    }
+
+    fn deliver_retained(&mut self, name: String, retained: Retained, qos: QoS) -> Result<()> {
+        let record = Record {
+            key: None,
+            value: Some(retained.payload),
+            headers: retained.headers,
+            timestamp: None,
+        };
+        let publish = self.publish(name, qos.min(retained.qos), true, record);
+        self.send_publish(publish, None)
+    }
+
+    /// Returns the PUBLISH delivering a record, allocating it a packet id if
+    /// it is to be acknowledged.
+    fn publish(&mut self, name: String, qos: QoS, retain: bool, record: Record) -> Publish {
+        let packet_id = match qos {
+            QoS::AtMostOnce => 0,
+            _ => self.state.next_packet_id(),
+        };
+        Publish {
+            dup: false,
+            qos,
+            retain,
+            topic: name,
+            packet_id,
+            properties: self.properties(&record.headers),
+            payload: record.value.unwrap_or_default(),
+        }
+    }
+
+    fn properties(&self, headers: &[Header]) -> Properties {
+        match self.version {
+            Version::V5 => gateway::to_properties(headers),
+            Version::V311 => Properties::default(),
+        }
+    }
+
+    /// Send a message to the client, tracking it until it is acknowledged
+    /// unless it is QoS 0. Messages larger than the client accepts are
+    /// dropped.
+    fn send_publish(
+        &mut self,
+        publish: Publish,
+        source: Option<(TopicPartition, u64)>,
+    ) -> Result<()> {
+        let packet = Packet::Publish(publish);
+        let buf = packet.encode(self.version);
+        if buf.len() > self.max_packet_bytes {
+            debug!(self.logger, "Dropped MQTT message larger than the client accepts."; "size" => buf.len());
+            return Ok(());
+        }
+        self.writer.write_all(&buf)?;
+        if let Packet::Publish(publish) = packet {
+            if publish.qos != QoS::AtMostOnce {
+                let out = Outgoing {
+                    source,
+                    publish,
+                    released: false,
+                };
+                self.state.outgoing.insert(out.publish.packet_id, out);
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, packet: &Packet) -> Result<()> {
+        write_packet(&mut self.writer, packet, self.version)
+    }
+
+    /// Tell an MQTT 5.0 client why it is being disconnected, should the
+    /// connection still allow it.
+    fn disconnect(&mut self, code: u8) {
+        if self.version == Version::V5 {
+            let _ = self
+                .send(&Packet::Disconnect(Disconnect::new(code)))
+                .and_then(|_| Ok(self.writer.flush()?));
+        }
+    }
+
+    fn save_session(&mut self) -> Result<()> {
+        self.gateway
+            .store
+            .save_session(&self.client_id, &self.state.to_record())?;
+        self.state.persisted = true;
+        Ok(())
+    }
+
+    /// Save the positions of a persistent session that changed since they
+    /// were last saved.
+    fn save_positions(&mut self) -> Result<()> {
+        let changed: BTreeMap<TopicPartition, u64> = self
+            .state
+            .committed_positions()
+            .into_iter()
+            .filter(|(partition, offset)| self.saved.get(partition) != Some(offset))
+            .collect();
+        if changed.is_empty() {
+            return Ok(());
+        }
+        self.gateway
+            .store
+            .save_positions(&self.client_id, &changed)?;
+        self.saved.extend(changed);
+        Ok(())
+    }
+
+    /// Publish the client's will if asked to, then hand its session back to
+    /// the registry, saving it if it outlives the connection or deleting it
+    /// if it ended.
+    fn close(mut self, publish_will: bool) {
+        if let Some(will) = self.will.take().filter(|_| publish_will) {
+            let publish = Publish {
+                dup: false,
+                qos: will.qos,
+                retain: will.retain,
+                topic: will.topic,
+                packet_id: 0,
+                properties: will.properties,
+                payload: will.payload,
+            };
+            match self.gateway.publish(&self.client_id, &publish) {
+                Ok(()) => {
+                    debug!(self.logger, "Published MQTT will."; "mqtt_topic" => &publish.topic)
+                }
+                Err(err) => {
+                    warn!(self.logger, "Failed to publish MQTT will."; "mqtt_topic" => &publish.topic, "error" => err.to_string())
+                }
+            }
+        }
+
+        let saved = if self.state.is_persistent() {
+            self.save_session().and_then(|_| self.save_positions())
+        } else if self.state.persisted {
+            self.gateway
+                .store
+                .delete_session(&self.client_id, self.state.positions.keys())
+        } else {
+            Ok(())
+        };
+        if let Err(err) = saved {
+            warn!(self.logger, "Failed to save MQTT session."; "error" => err.to_string());
+        }
+        self.gateway.sessions.release(&self.client_id, self.state);
+    }
+}
@@ -0,0 +1,107 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{io, net::SocketAddr, result};
+
+use thiserror::Error;
+
+use crate::codec;
+use crate::protocol::ResponseError;
+use crate::topic;
+
+/// Custom Result wrapper to simplify usage.
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents errors serving MQTT clients.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// Handles failures binding the listening socket.
+    #[error("failed to bind MQTT listener to '{address}': {source}")]
+    Bind {
+        /// The address that could not be bound.
+        address: SocketAddr,
+        /// The initial error cause.
+        source: io::Error,
+    },
+    /// Handles failures accepting new connections.
+    #[error("failed to accept MQTT connection: {0}")]
+    Accept(#[source] io::Error),
+    /// Handles OS level errors on the underlying connection.
+    #[error("connection error: {0}")]
+    Io(#[from] io::Error),
+    /// Handles packets whose contents could not be decoded.
+    #[error("malformed packet: {0}")]
+    Codec(#[from] codec::Error),
+    /// Handles packets that violate the MQTT specification.
+    #[error("malformed packet: {reason}")]
+    Malformed {
+        /// Why the packet was rejected.
+        reason: &'static str,
+    },
+    /// Handles packets that are well formed but not allowed at that point of
+    /// the conversation, or use features that are not supported.
+    #[error("protocol error: {reason}")]
+    Protocol {
+        /// Why the packet was rejected.
+        reason: &'static str,
+    },
+    /// Handles clients speaking a version of MQTT other than 3.1.1 or 5.0.
+    #[error("unsupported MQTT protocol level {level}")]
+    UnsupportedVersion {
+        /// The protocol level the client requested.
+        level: u8,
+    },
+    /// Handles packets that exceed the configured maximum packet size.
+    #[error("packet of {size} bytes exceeds the maximum packet size of {max} bytes")]
+    PacketTooLarge {
+        /// The size of the rejected packet.
+        size: usize,
+        /// The configured maximum packet size.
+        max: u32,
+    },
+    /// Handles MQTT topic names that do not map onto a rift topic.
+    #[error("invalid MQTT topic '{topic}': {reason}")]
+    InvalidTopic {
+        /// The offending MQTT topic name.
+        topic: String,
+        /// Why the topic name was rejected.
+        reason: &'static str,
+    },
+    /// Handles failures serving the rift requests MQTT packets translate to.
+    #[error(transparent)]
+    Broker(#[from] ResponseError),
+    /// Handles errors reading or writing the MQTT state topic.
+    #[error(transparent)]
+    Topic(#[from] topic::Error),
+    /// Handles MQTT state records that could not be decoded.
+    #[error("failed to decode MQTT state record at offset {offset}: {source}")]
+    Corrupt {
+        /// The offset of the record that failed to decode.
+        offset: u64,
+        /// The initial error cause.
+        source: codec::Error,
+    },
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let err = Error::PacketTooLarge { size: 10, max: 4 };
+        assert_eq!(
+            "packet of 10 bytes exceeds the maximum packet size of 4 bytes",
+            err.to_string()
+        );
+        let err = Error::InvalidTopic {
+            topic: String::from("a/+"),
+            reason: "must not contain wildcards",
+        };
+        assert_eq!(
+            "invalid MQTT topic 'a/+': must not contain wildcards",
+            err.to_string()
+        );
+    }
+}
@@ -0,0 +1,181 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::topic;
+
+use super::error::{Error, Result};
+
+/// The separator between the levels of an MQTT topic.
+const MQTT_SEPARATOR: char = '/';
+/// The separator between the levels of the rift topic an MQTT topic maps onto.
+const TOPIC_SEPARATOR: char = '.';
+
+/// Returns the rift topic the supplied MQTT topic name maps onto, which joins
+/// its levels with '.' rather than '/', so that `sensors/kitchen/temp` maps
+/// onto `sensors.kitchen.temp`. Every level must be a non-empty run of ASCII
+/// alphanumerics, '_', and '-' so that the mapping can be reversed.
+///
+/// Names are also rejected if they contain wildcards, as only topic filters
+/// may, or map onto a topic reserved for internal use.
+pub(super) fn to_topic(name: &str) -> Result<String> {
+    let invalid = |reason| {
+        Err(Error::InvalidTopic {
+            topic: name.to_owned(),
+            reason,
+        })
+    };
+    if name.contains(['+', '#']) {
+        return invalid("must not contain wildcards");
+    }
+    let mut levels = Vec::new();
+    for level in name.split(MQTT_SEPARATOR) {
+        if level.is_empty() {
+            return invalid("levels must not be empty");
+        }
+        if !level
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return invalid("levels may only contain ASCII alphanumerics, '_', and '-'");
+        }
+        levels.push(level);
+    }
+    let topic = levels.join(&TOPIC_SEPARATOR.to_string());
+    if topic::is_internal(&topic) {
+        return invalid("is reserved for internal use");
+    }
+    if topic::validate_name(&topic).is_err() {
+        return invalid("is too long");
+    }
+    Ok(topic)
+}
+
+/// Returns the MQTT topic name the supplied rift topic is published under, or
+/// [None] for topics MQTT clients can not subscribe to.
+pub(super) fn to_name(topic: &str) -> Option<String> {
+    if topic::is_internal(topic) || topic.split(TOPIC_SEPARATOR).any(str::is_empty) {
+        return None;
+    }
+    Some(topic.replace(TOPIC_SEPARATOR, &MQTT_SEPARATOR.to_string()))
+}
+
+/// Ensure the supplied topic filter is well formed, in that '+' only ever
+/// makes up a whole level and '#' only the last one.
+pub(super) fn validate(filter: &str) -> bool {
+    if filter.is_empty() || filter.contains('\0') {
+        return false;
+    }
+    let levels: Vec<&str> = filter.split(MQTT_SEPARATOR).collect();
+    levels.iter().enumerate().all(|(idx, level)| match *level {
+        "#" => idx == levels.len() - 1,
+        "+" => true,
+        level => !level.contains(['+', '#']),
+    })
+}
+
+/// Returns whether the supplied topic filter matches a topic name. '+'
+/// matches any single level, and '#' the level before it along with any that
+/// follow. Wildcards at the start of a filter never match names starting with
+/// '$'.
+///
+/// The filter is assumed to have been validated.
+pub(super) fn matches(filter: &str, name: &str) -> bool {
+    if name.starts_with('$') && filter.starts_with(['+', '#']) {
+        return false;
+    }
+    let mut names = name.split(MQTT_SEPARATOR);
+    for level in filter.split(MQTT_SEPARATOR) {
+        if level == "#" {
+            return true;
+        }
+        match names.next() {
+            Some(name) if level == "+" || level == name => {}
+            _ => return false,
+        }
+    }
+    names.next().is_none()
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mapping() {
+        assert_eq!(
+            "sensors.kitchen.temp",
+            to_topic("sensors/kitchen/temp").unwrap()
+        );
+        assert_eq!("a_b-c", to_topic("a_b-c").unwrap());
+        for name in [
+            "",
+            "a//b",
+            "/a",
+            "a/",
+            "a/+",
+            "a/#",
+            "a.b",
+            "caf\u{e9}",
+            "$SYS/uptime",
+            "__consumer_offsets",
+        ] {
+            assert!(to_topic(name).is_err(), "{} should be rejected", name);
+        }
+        assert!(to_topic(&"a/".repeat(200)[..399]).is_err());
+
+        assert_eq!(
+            Some(String::from("sensors/kitchen/temp")),
+            to_name("sensors.kitchen.temp")
+        );
+        assert_eq!(Some(String::from("orders")), to_name("orders"));
+        assert_eq!(None, to_name("__consumer_offsets"));
+        assert_eq!(None, to_name("a..b"));
+        assert_eq!(None, to_name(".a"));
+    }
+
+    #[test]
+    fn test_validate() {
+        for filter in ["#", "+", "a/+/c", "a/#", "+/+", "/", "a//b", "$SYS/#"] {
+            assert!(validate(filter), "{} should be valid", filter);
+        }
+        for filter in ["", "a/#/c", "a#", "a/b+", "#/a", "a+/b"] {
+            assert!(!validate(filter), "{} should be invalid", filter);
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let matching = [
+            ("a/b/c", "a/b/c"),
+            ("a/+/c", "a/b/c"),
+            ("a/#", "a/b/c"),
+            // '#' also matches its parent level.
+            ("a/#", "a"),
+            ("#", "a/b"),
+            ("+/+", "a/b"),
+            ("+", "a"),
+            ("$SYS/#", "$SYS/uptime"),
+        ];
+        for (filter, name) in matching {
+            assert!(matches(filter, name), "{} should match {}", filter, name);
+        }
+        let mismatching = [
+            ("a/b", "a/b/c"),
+            ("a/b/c", "a/b"),
+            ("a/+", "a"),
+            ("+", "a/b"),
+            ("a/+/d", "a/b/c"),
+            ("#", "$SYS/uptime"),
+            ("+/uptime", "$SYS/uptime"),
+        ];
+        for (filter, name) in mismatching {
+            assert!(
+                !matches(filter, name),
+                "{} should not match {}",
+                filter,
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,285 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use crate::broker::Broker;
+use crate::protocol::{
+    CreateTopicRequest, ErrorCode, FetchPartition, FetchRequest, FetchedPartition, MetadataRequest,
+    ProduceRequest, Request, Response, ResponseError, TopicMetadata,
+};
+use crate::record::{self, Compression, Header, Record};
+use crate::topic::{IsolationLevel, Partitioning};
+
+use super::config::Config;
+use super::error::{Error, Result};
+use super::filter;
+use super::packet::{Properties, Publish, QoS};
+use super::session::Sessions;
+use super::state::Retained;
+use super::store::Store;
+
+/// The header holding the QoS an MQTT message was published with. Records
+/// without it, such as those produced by native clients, are delivered at the
+/// QoS of the subscription.
+pub const QOS_HEADER: &str = "mqtt.qos";
+/// The header set on MQTT messages published with the retain flag.
+pub const RETAIN_HEADER: &str = "mqtt.retain";
+/// The header holding the id of the MQTT client that published a message.
+pub const CLIENT_ID_HEADER: &str = "mqtt.client_id";
+
+/// The prefix of the headers carrying MQTT specific details of a message,
+/// which are not passed on to MQTT 5.0 subscribers as user properties.
+const HEADER_PREFIX: &str = "mqtt.";
+const PAYLOAD_FORMAT_HEADER: &str = "mqtt.payload_format";
+const CONTENT_TYPE_HEADER: &str = "mqtt.content_type";
+const RESPONSE_TOPIC_HEADER: &str = "mqtt.response_topic";
+const CORRELATION_DATA_HEADER: &str = "mqtt.correlation_data";
+
+/// The number of bytes fetched from each partition at a time for delivery.
+const FETCH_BYTES: u32 = 256 * 1024;
+
+/// The state shared by every MQTT connection, translating MQTT publishes and
+/// subscriptions into the rift requests served by the broker.
+pub(super) struct Gateway {
+    pub logger: slog::Logger,
+    pub store: Store,
+    pub sessions: Sessions,
+    pub max_packet_bytes: u32,
+    pub max_inflight: u16,
+    broker: Arc<Broker>,
+    partitions: u32,
+    client_ids: AtomicU64,
+}
+
+impl Gateway {
+    /// Open the gateway, recovering retained messages and persistent sessions.
+    pub(super) fn open(logger: slog::Logger, cfg: &Config, broker: Arc<Broker>) -> Result<Gateway> {
+        let (store, persisted) = Store::open(broker.topics())?;
+        Ok(Gateway {
+            logger,
+            store,
+            sessions: Sessions::new(persisted),
+            max_packet_bytes: cfg.mqtt_max_packet_bytes,
+            max_inflight: cfg.mqtt_max_inflight.max(1),
+            broker,
+            partitions: cfg.mqtt_partitions.max(1),
+            client_ids: AtomicU64::new(0),
+        })
+    }
+
+    /// Returns a unique client id for a client that connected without one.
+    pub(super) fn assign_client_id(&self) -> String {
+        format!(
+            "rift-{:x}-{}",
+            record::current_timestamp(),
+            self.client_ids.fetch_add(1, Ordering::Relaxed)
+        )
+    }
+
+    /// Append a message published by the supplied client, or on its behalf
+    /// as its will, to the rift topic its MQTT topic maps onto, creating the
+    /// topic if needed. Retained messages replace the message retained for
+    /// their topic first, or clear it if they are empty.
+    pub(super) fn publish(&self, client_id: &str, publish: &Publish) -> Result<()> {
+        let topic = filter::to_topic(&publish.topic)?;
+        let record = to_record(client_id, publish);
+        if publish.retain {
+            let retained = (!publish.payload.is_empty()).then(|| Retained {
+                qos: publish.qos,
+                payload: publish.payload.clone(),
+                headers: record.headers.clone(),
+            });
+            self.store.retain(&publish.topic, retained)?;
+        }
+
+        match self.produce(&topic, record.clone()) {
+            Err(Error::Broker(err)) if err.code == ErrorCode::TopicNotFound => {}
+            result => return result,
+        }
+        let create = Request::CreateTopic(CreateTopicRequest {
+            name: topic.clone(),
+            partitions: self.partitions,
+            config: Vec::new(),
+        });
+        match self.broker.handle(create) {
+            Ok(_) => {
+                info!(self.logger, "Created topic for MQTT messages."; "topic" => &topic, "mqtt_topic" => &publish.topic);
+            }
+            // Another client may have published to the topic first.
+            Err(err) if err.code == ErrorCode::TopicAlreadyExists => {}
+            Err(err) => return Err(err.into()),
+        }
+        self.produce(&topic, record)
+    }
+
+    /// Returns every topic MQTT clients can subscribe to.
+    pub(super) fn topics(&self) -> Result<Vec<TopicMetadata>> {
+        let request = Request::Metadata(MetadataRequest { topics: Vec::new() });
+        match self.broker.handle(request)? {
+            Response::Metadata(resp) => Ok(resp
+                .topics
+                .into_iter()
+                .filter(|topic| {
+                    topic.error_code == ErrorCode::None && filter::to_name(&topic.name).is_some()
+                })
+                .collect()),
+            _ => Err(unexpected("metadata")),
+        }
+    }
+
+    /// Read the committed records of each partition from the supplied offset.
+    pub(super) fn fetch(
+        &self,
+        positions: Vec<(String, u32, u64)>,
+    ) -> Result<Vec<FetchedPartition>> {
+        let partitions = positions
+            .into_iter()
+            .map(|(topic, partition, offset)| FetchPartition {
+                topic,
+                partition,
+                offset,
+                max_bytes: FETCH_BYTES,
+            })
+            .collect();
+        let request = Request::Fetch(FetchRequest {
+            partitions,
+            isolation: IsolationLevel::ReadCommitted,
+            selector: String::new(),
+        });
+        match self.broker.handle(request)? {
+            Response::Fetch(resp) => Ok(resp.partitions),
+            _ => Err(unexpected("fetch")),
+        }
+    }
+
+    fn produce(&self, topic: &str, record: Record) -> Result<()> {
+        let request = Request::Produce(ProduceRequest {
+            topic: topic.to_owned(),
+            partitioning: Partitioning::Key,
+            records: vec![record],
+            producer: None,
+            compression: Compression::None,
+        });
+        self.broker.handle(request)?;
+        Ok(())
+    }
+}
+
+fn unexpected(request: &str) -> Error {
+    Error::Broker(ResponseError::new(
+        ErrorCode::Unknown,
+        format!("unexpected response to a {} request", request),
+    ))
+}
+
+/// Returns the record an MQTT message is stored as, carrying its QoS, retain
+/// flag, and publisher in headers. The MQTT 5.0 properties of the message
+/// also become headers, with user properties kept under their own names.
+pub(super) fn to_record(client_id: &str, publish: &Publish) -> Record {
+    let mut record = Record::new(publish.payload.clone())
+        .with_header(QOS_HEADER, (publish.qos as u8).to_string())
+        .with_header(CLIENT_ID_HEADER, client_id);
+    if publish.retain {
+        record = record.with_header(RETAIN_HEADER, "1");
+    }
+    let properties = &publish.properties;
+    if let Some(format) = properties.payload_format {
+        record = record.with_header(PAYLOAD_FORMAT_HEADER, format.to_string());
+    }
+    if let Some(content_type) = &properties.content_type {
+        record = record.with_header(CONTENT_TYPE_HEADER, content_type.as_str());
+    }
+    if let Some(response_topic) = &properties.response_topic {
+        record = record.with_header(RESPONSE_TOPIC_HEADER, response_topic.as_str());
+    }
+    if let Some(correlation_data) = &properties.correlation_data {
+        record = record.with_header(CORRELATION_DATA_HEADER, correlation_data.clone());
+    }
+    for (key, value) in &properties.user {
+        record = record.with_header(key.as_str(), value.as_str());
+    }
+    record
+}
+
+/// Returns the QoS a record was published with, which for records produced
+/// by native clients is the highest there is.
+pub(super) fn record_qos(headers: &[Header]) -> QoS {
+    match header(headers, QOS_HEADER) {
+        Some(b"0") => QoS::AtMostOnce,
+        Some(b"1") => QoS::AtLeastOnce,
+        _ => QoS::ExactlyOnce,
+    }
+}
+
+/// Returns the MQTT 5.0 properties delivered along with a message stored with
+/// the supplied headers. Headers other than those describing the message in
+/// MQTT terms are passed on as user properties if their values are UTF-8.
+pub(super) fn to_properties(headers: &[Header]) -> Properties {
+    let string =
+        |key| header(headers, key).and_then(|value| String::from_utf8(value.to_vec()).ok());
+    Properties {
+        payload_format: string(PAYLOAD_FORMAT_HEADER).and_then(|format| format.parse().ok()),
+        content_type: string(CONTENT_TYPE_HEADER),
+        response_topic: string(RESPONSE_TOPIC_HEADER),
+        correlation_data: header(headers, CORRELATION_DATA_HEADER).map(<[u8]>::to_vec),
+        user: headers
+            .iter()
+            .filter(|header| !header.key.starts_with(HEADER_PREFIX))
+            .filter_map(|header| {
+                let value = String::from_utf8(header.value.clone()).ok()?;
+                Some((header.key.clone(), value))
+            })
+            .collect(),
+        ..Properties::default()
+    }
+}
+
+/// Returns the value of the last header with the supplied key.
+pub(super) fn header<'a>(headers: &'a [Header], key: &str) -> Option<&'a [u8]> {
+    headers
+        .iter()
+        .rev()
+        .find(|header| header.key == key)
+        .map(|header| header.value.as_slice())
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_mapping() {
+        let publish = Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            retain: true,
+            topic: String::from("sensors/kitchen"),
+            packet_id: 3,
+            properties: Properties {
+                content_type: Some(String::from("application/json")),
+                correlation_data: Some(vec![0xFF]),
+                user: vec![(String::from("unit"), String::from("celsius"))],
+                ..Properties::default()
+            },
+            payload: b"{}".to_vec(),
+        };
+        let record = to_record("sensor-1", &publish);
+        assert_eq!(Some(b"{}".to_vec()), record.value);
+        assert_eq!(Some(&b"1"[..]), record.header(QOS_HEADER));
+        assert_eq!(Some(&b"1"[..]), record.header(RETAIN_HEADER));
+        assert_eq!(Some(&b"sensor-1"[..]), record.header(CLIENT_ID_HEADER));
+        assert_eq!(Some(&b"celsius"[..]), record.header("unit"));
+        assert_eq!(QoS::AtLeastOnce, record_qos(&record.headers));
+        assert_eq!(publish.properties, to_properties(&record.headers));
+
+        // Records produced natively carry none of the MQTT headers.
+        let record = Record::new("value").with_header("trace", [0xFF]);
+        assert_eq!(QoS::ExactlyOnce, record_qos(&record.headers));
+        assert_eq!(Properties::default(), to_properties(&record.headers));
+    }
+}
@@ -0,0 +1,542 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    net::{SocketAddr, TcpListener},
+    sync::Arc,
+    thread,
+};
+
+use crate::broker::Broker;
+use crate::server::{self, OpenConnection};
+
+use super::config::Config;
+use super::connection;
+use super::error::{Error, Result};
+use super::gateway::Gateway;
+
+/// The port MQTT is served on when no listen address is configured.
+const DEFAULT_PORT: u16 = 1883;
+
+/// Accepts MQTT 3.1.1 and 5.0 client connections, serving each connection on
+/// its own thread.
+///
+/// MQTT topics are mapped onto rift topics by replacing their `/` separators
+/// with `.`, so that MQTT clients and native clients share messages. Retained
+/// messages and persistent sessions are kept in an internal compacted topic.
+pub struct Server {
+    logger: slog::Logger,
+    listener: TcpListener,
+    gateway: Arc<Gateway>,
+}
+
+impl Server {
+    /// Bind a new listener using the supplied configuration, recovering the
+    /// retained messages and persistent sessions of previous runs.
+    pub fn bind(logger: slog::Logger, cfg: &Config, broker: Arc<Broker>) -> Result<Server> {
+        let address = cfg
+            .mqtt_listen_address
+            .unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], DEFAULT_PORT)));
+        let listener =
+            TcpListener::bind(address).map_err(|source| Error::Bind { address, source })?;
+        let gateway = Gateway::open(logger.clone(), cfg, broker)?;
+        Ok(Server {
+            logger,
+            listener,
+            gateway: Arc::new(gateway),
+        })
+    }
+
+    /// Returns the address this listener is bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.listener.local_addr().map_err(Error::Accept)
+    }
+
+    /// Accept and serve connections until the process exits.
+    pub fn serve(self) -> ! {
+        loop {
+            let (stream, peer) = server::accept(&self.logger, &self.listener);
+            let logger = self.logger.new(o!("peer" => peer.to_string()));
+            let gateway = self.gateway.clone();
+
+            thread::spawn(move || {
+                let _open = OpenConnection::new("mqtt");
+                debug!(logger, "Accepted MQTT connection.");
+                if let Err(err) = stream.set_nodelay(true) {
+                    warn!(logger, "Failed to disable nagle's algorithm."; "error" => err.to_string());
+                }
+                match connection::serve(logger.clone(), stream, gateway) {
+                    Ok(()) => debug!(logger, "MQTT connection closed."),
+                    Err(err) => {
+                        warn!(logger, "MQTT connection failed."; "error" => err.to_string())
+                    }
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use std::{
+        io::{BufReader, Read, Write},
+        net::{Shutdown, TcpStream},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::mqtt::packet::{
+        read_packet, reason, write_packet, Ack, ConnAck, Connect, Disconnect, Packet, Properties,
+        Publish, QoS, Subscribe, SubscriptionOptions, Version, Will,
+    };
+    use crate::mqtt::{CLIENT_ID_HEADER, QOS_HEADER};
+    use crate::protocol::{FetchPartition, FetchRequest, ProduceRequest, Request, Response};
+    use crate::record::{Compression, Record};
+    use crate::storage::LogConfig;
+    use crate::topic::{self, IsolationLevel, Partitioning};
+    use crate::{group, producer, queue, schedule};
+
+    fn open_broker(dir: &std::path::Path) -> Arc<Broker> {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let topics = Arc::new(topic::Manager::open(dir, LogConfig::default()).unwrap());
+        Arc::new(
+            Broker::open(
+                logger,
+                topics,
+                group::Config::default(),
+                producer::Config::default(),
+                queue::Config::default(),
+                schedule::Config::default(),
+            )
+            .unwrap(),
+        )
+    }
+
+    fn start(broker: Arc<Broker>) -> SocketAddr {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let cfg = Config {
+            mqtt_listen_address: Some("127.0.0.1:0".parse().unwrap()),
+            ..Config::default()
+        };
+        let server = Server::bind(logger, &cfg, broker).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+        addr
+    }
+
+    fn connect_packet(version: Version, client_id: &str, clean_start: bool) -> Connect {
+        Connect {
+            version,
+            clean_start,
+            keep_alive: 60,
+            client_id: client_id.to_owned(),
+            will: None,
+            username: None,
+            password: None,
+            properties: Properties::default(),
+        }
+    }
+
+    struct TestClient {
+        stream: BufReader<TcpStream>,
+        version: Version,
+        packet_id: u16,
+    }
+
+    impl TestClient {
+        fn connect(addr: SocketAddr, connect: Connect) -> (TestClient, ConnAck) {
+            let stream = TcpStream::connect(addr).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(10)))
+                .unwrap();
+            let mut client = TestClient {
+                stream: BufReader::new(stream),
+                version: connect.version,
+                packet_id: 0,
+            };
+            client.send(&Packet::Connect(Box::new(connect)));
+            match client.recv() {
+                Packet::ConnAck(connack) => (client, connack),
+                packet => panic!("expected CONNACK, got {:?}", packet),
+            }
+        }
+
+        fn send(&mut self, packet: &Packet) {
+            write_packet(self.stream.get_mut(), packet, self.version).unwrap();
+        }
+
+        fn recv(&mut self) -> Packet {
+            read_packet(&mut self.stream, self.version, u32::MAX)
+                .unwrap()
+                .unwrap()
+        }
+
+        fn recv_publish(&mut self) -> Publish {
+            match self.recv() {
+                Packet::Publish(publish) => publish,
+                packet => panic!("expected PUBLISH, got {:?}", packet),
+            }
+        }
+
+        /// Asserts the server sends nothing for a little while.
+        fn assert_silent(&mut self) {
+            let stream = self.stream.get_ref();
+            stream
+                .set_read_timeout(Some(Duration::from_millis(300)))
+                .unwrap();
+            assert!(read_packet(&mut self.stream, self.version, u32::MAX).is_err());
+            self.stream
+                .get_ref()
+                .set_read_timeout(Some(Duration::from_secs(10)))
+                .unwrap();
+        }
+
+        fn subscribe(&mut self, filters: &[(&str, SubscriptionOptions)]) -> Vec<u8> {
+            self.packet_id += 1;
+            let subscribe = Subscribe {
+                packet_id: self.packet_id,
+                filters: filters
+                    .iter()
+                    .map(|(filter, options)| (filter.to_string(), *options))
+                    .collect(),
+            };
+            self.send(&Packet::Subscribe(subscribe));
+            match self.recv() {
+                Packet::SubAck(suback) => {
+                    assert_eq!(self.packet_id, suback.packet_id);
+                    suback.codes
+                }
+                packet => panic!("expected SUBACK, got {:?}", packet),
+            }
+        }
+
+        /// Publish a message, completing its acknowledgement.
+        fn publish(&mut self, topic: &str, payload: &str, qos: QoS, retain: bool) {
+            self.packet_id += 1;
+            let packet_id = match qos {
+                QoS::AtMostOnce => 0,
+                _ => self.packet_id,
+            };
+            let publish = Publish {
+                dup: false,
+                qos,
+                retain,
+                topic: topic.to_owned(),
+                packet_id,
+                properties: Properties::default(),
+                payload: payload.as_bytes().to_vec(),
+            };
+            self.send(&Packet::Publish(publish));
+            match qos {
+                QoS::AtMostOnce => {}
+                QoS::AtLeastOnce => assert_eq!(Packet::PubAck(Ack::new(packet_id)), self.recv()),
+                QoS::ExactlyOnce => {
+                    assert_eq!(Packet::PubRec(Ack::new(packet_id)), self.recv());
+                    self.send(&Packet::PubRel(Ack::new(packet_id)));
+                    assert_eq!(Packet::PubComp(Ack::new(packet_id)), self.recv());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_connect() {
+        let dir = tempfile::tempdir().unwrap();
+        let addr = start(open_broker(dir.path()));
+
+        // MQTT 3.1 is refused with the code 3.1.1 defines for it.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut connect = vec![0x10, 13, 0, 4];
+        connect.extend_from_slice(b"MQTT");
+        connect.extend_from_slice(&[3, 0x02, 0, 60, 0, 1, b'a']);
+        stream.write_all(&connect).unwrap();
+        let mut connack = Vec::new();
+        stream.read_to_end(&mut connack).unwrap();
+        assert_eq!(vec![0x20, 2, 0, reason::V3_UNACCEPTABLE_VERSION], connack);
+
+        // MQTT 3.1.1 clients without an id must start clean.
+        let connect = connect_packet(Version::V311, "", false);
+        let (_, connack) = TestClient::connect(addr, connect);
+        assert_eq!(reason::V3_IDENTIFIER_REJECTED, connack.code);
+        let connect = connect_packet(Version::V311, "", true);
+        let (_, connack) = TestClient::connect(addr, connect);
+        assert_eq!(reason::SUCCESS, connack.code);
+
+        // MQTT 5.0 clients without an id are told the one assigned to them.
+        let connect = connect_packet(Version::V5, "", false);
+        let (_, connack) = TestClient::connect(addr, connect);
+        assert_eq!(reason::SUCCESS, connack.code);
+        assert!(connack.properties.assigned_client_id.is_some());
+        assert_eq!(Some(0), connack.properties.shared_subscriptions_available);
+
+        let mut connect = connect_packet(Version::V5, "auth", true);
+        connect.properties.authentication_method = Some(String::from("SCRAM-SHA-1"));
+        let (_, connack) = TestClient::connect(addr, connect);
+        assert_eq!(reason::BAD_AUTHENTICATION_METHOD, connack.code);
+
+        let mut connect = connect_packet(Version::V5, "will", true);
+        connect.will = Some(Will {
+            topic: String::from("a/+"),
+            payload: Vec::new(),
+            qos: QoS::AtMostOnce,
+            retain: false,
+            properties: Properties::default(),
+        });
+        let (_, connack) = TestClient::connect(addr, connect);
+        assert_eq!(reason::TOPIC_NAME_INVALID, connack.code);
+
+        // Publishing to an invalid topic disconnects the client.
+        let (mut client, _) = TestClient::connect(addr, connect_packet(Version::V5, "bad", true));
+        client.publish("a//b", "", QoS::AtMostOnce, false);
+        assert_eq!(
+            Packet::Disconnect(Disconnect::new(reason::TOPIC_NAME_INVALID)),
+            client.recv()
+        );
+    }
+
+    #[test]
+    fn test_unknown_packet_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let addr = start(open_broker(dir.path()));
+
+        // MQTT 5.0 clients are told the packet identifier is not in use.
+        let (mut client, _) = TestClient::connect(addr, connect_packet(Version::V5, "v5", true));
+        let not_found = |packet_id| Ack {
+            packet_id,
+            reason: reason::PACKET_IDENTIFIER_NOT_FOUND,
+        };
+        client.send(&Packet::PubRec(Ack::new(7)));
+        assert_eq!(Packet::PubRel(not_found(7)), client.recv());
+        client.send(&Packet::PubRel(Ack::new(8)));
+        assert_eq!(Packet::PubComp(not_found(8)), client.recv());
+
+        // MQTT 3.1.1 has no reason codes, so the exchange simply completes.
+        let connect = connect_packet(Version::V311, "v311", true);
+        let (mut client, _) = TestClient::connect(addr, connect);
+        client.send(&Packet::PubRec(Ack::new(7)));
+        assert_eq!(Packet::PubRel(Ack::new(7)), client.recv());
+        client.send(&Packet::PubRel(Ack::new(8)));
+        assert_eq!(Packet::PubComp(Ack::new(8)), client.recv());
+    }
+
+    #[test]
+    fn test_publish_subscribe() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = open_broker(dir.path());
+        let addr = start(broker.clone());
+
+        let (mut subscriber, connack) =
+            TestClient::connect(addr, connect_packet(Version::V311, "subscriber", true));
+        assert_eq!(reason::SUCCESS, connack.code);
+        assert!(!connack.session_present);
+        let codes = subscriber.subscribe(&[
+            ("sensors/+/temp", SubscriptionOptions::new(QoS::ExactlyOnce)),
+            ("sensors/#/temp", SubscriptionOptions::new(QoS::AtMostOnce)),
+        ]);
+        assert_eq!(vec![2, reason::V3_FAILURE], codes);
+
+        let (mut publisher, _) =
+            TestClient::connect(addr, connect_packet(Version::V311, "publisher", true));
+        publisher.publish("sensors/kitchen/temp", "20", QoS::AtMostOnce, false);
+        publisher.publish("sensors/kitchen/temp", "21", QoS::AtLeastOnce, false);
+        publisher.publish("sensors/kitchen/temp", "22", QoS::ExactlyOnce, false);
+        publisher.publish("sensors/kitchen/humidity", "40", QoS::AtMostOnce, false);
+
+        // Messages are delivered at the QoS they were published with.
+        let publish = subscriber.recv_publish();
+        assert_eq!(
+            ("sensors/kitchen/temp", &b"20"[..]),
+            (publish.topic.as_str(), &publish.payload[..])
+        );
+        assert_eq!(QoS::AtMostOnce, publish.qos);
+        let publish = subscriber.recv_publish();
+        assert_eq!(b"21".to_vec(), publish.payload);
+        assert_eq!(QoS::AtLeastOnce, publish.qos);
+        subscriber.send(&Packet::PubAck(Ack::new(publish.packet_id)));
+        let publish = subscriber.recv_publish();
+        assert_eq!(b"22".to_vec(), publish.payload);
+        assert_eq!(QoS::ExactlyOnce, publish.qos);
+        subscriber.send(&Packet::PubRec(Ack::new(publish.packet_id)));
+        assert_eq!(
+            Packet::PubRel(Ack::new(publish.packet_id)),
+            subscriber.recv()
+        );
+        subscriber.send(&Packet::PubComp(Ack::new(publish.packet_id)));
+
+        // The messages are ordinary records of the mapped topic.
+        let fetch = Request::Fetch(FetchRequest {
+            partitions: vec![FetchPartition {
+                topic: String::from("sensors.kitchen.temp"),
+                partition: 0,
+                offset: 0,
+                max_bytes: 1024 * 1024,
+            }],
+            isolation: IsolationLevel::ReadCommitted,
+            selector: String::new(),
+        });
+        let records = match broker.handle(fetch).unwrap() {
+            Response::Fetch(resp) => resp.partitions[0].records.clone(),
+            _ => unimplemented!(),
+        };
+        assert_eq!(3, records.len());
+        assert_eq!(Some(&b"2"[..]), records[2].record.header(QOS_HEADER));
+        assert_eq!(
+            Some(&b"publisher"[..]),
+            records[2].record.header(CLIENT_ID_HEADER)
+        );
+
+        // Records produced by native clients reach MQTT subscribers too.
+        let produce = Request::Produce(ProduceRequest {
+            topic: String::from("sensors.kitchen.temp"),
+            partitioning: Partitioning::Key,
+            records: vec![Record::new("23")],
+            producer: None,
+            compression: Compression::None,
+        });
+        broker.handle(produce).unwrap();
+        let publish = subscriber.recv_publish();
+        assert_eq!(b"23".to_vec(), publish.payload);
+        assert_eq!(QoS::ExactlyOnce, publish.qos);
+        subscriber.send(&Packet::PubRec(Ack::new(publish.packet_id)));
+        assert_eq!(
+            Packet::PubRel(Ack::new(publish.packet_id)),
+            subscriber.recv()
+        );
+        subscriber.send(&Packet::PubComp(Ack::new(publish.packet_id)));
+
+        subscriber.send(&Packet::PingReq);
+        assert_eq!(Packet::PingResp, subscriber.recv());
+        subscriber.assert_silent();
+    }
+
+    #[test]
+    fn test_retained_and_will() {
+        let dir = tempfile::tempdir().unwrap();
+        let addr = start(open_broker(dir.path()));
+
+        let (mut publisher, _) =
+            TestClient::connect(addr, connect_packet(Version::V5, "publisher", true));
+        // Publishing the QoS 0 message first ensures both are retained once the
+        // QoS 1 message is acknowledged.
+        publisher.publish("lights/porch", "off", QoS::AtMostOnce, true);
+        publisher.publish("lights/hall", "on", QoS::AtLeastOnce, true);
+
+        // Retained messages are sent on subscribing, at the lower of the two QoS.
+        let (mut subscriber, _) =
+            TestClient::connect(addr, connect_packet(Version::V5, "subscriber", true));
+        let codes = subscriber.subscribe(&[
+            ("lights/#", SubscriptionOptions::new(QoS::AtLeastOnce)),
+            (
+                "$share/group/lights",
+                SubscriptionOptions::new(QoS::AtLeastOnce),
+            ),
+        ]);
+        assert_eq!(vec![1, reason::SHARED_SUBSCRIPTIONS_NOT_SUPPORTED], codes);
+        let hall = subscriber.recv_publish();
+        assert_eq!(
+            ("lights/hall", true, QoS::AtLeastOnce),
+            (hall.topic.as_str(), hall.retain, hall.qos)
+        );
+        subscriber.send(&Packet::PubAck(Ack::new(hall.packet_id)));
+        let porch = subscriber.recv_publish();
+        assert_eq!(
+            ("lights/porch", true, QoS::AtMostOnce),
+            (porch.topic.as_str(), porch.retain, porch.qos)
+        );
+        subscriber.subscribe(&[(
+            "clients/+/status",
+            SubscriptionOptions::new(QoS::AtMostOnce),
+        )]);
+
+        let will = |client_id: &str| {
+            let mut connect = connect_packet(Version::V5, client_id, true);
+            connect.will = Some(Will {
+                topic: format!("clients/{}/status", client_id),
+                payload: b"gone".to_vec(),
+                qos: QoS::AtMostOnce,
+                retain: false,
+                properties: Properties::default(),
+            });
+            connect
+        };
+
+        // A graceful disconnect discards the will, while a dropped connection
+        // publishes it.
+        let (mut graceful, _) = TestClient::connect(addr, will("graceful"));
+        graceful.send(&Packet::Disconnect(Disconnect::new(reason::SUCCESS)));
+        let (dropped, _) = TestClient::connect(addr, will("dropped"));
+        dropped.stream.get_ref().shutdown(Shutdown::Both).unwrap();
+
+        let publish = subscriber.recv_publish();
+        assert_eq!(
+            ("clients/dropped/status", &b"gone"[..]),
+            (publish.topic.as_str(), &publish.payload[..])
+        );
+        subscriber.assert_silent();
+    }
+
+    #[test]
+    fn test_persistent_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = open_broker(dir.path());
+        let addr = start(broker.clone());
+        let persistent = || {
+            let mut connect = connect_packet(Version::V5, "worker", false);
+            connect.properties.session_expiry = Some(3600);
+            connect
+        };
+
+        let (mut first, _) = TestClient::connect(addr, persistent());
+        first.subscribe(&[("jobs", SubscriptionOptions::new(QoS::AtLeastOnce))]);
+
+        // Connecting with the same id takes over the session.
+        let (mut second, connack) = TestClient::connect(addr, persistent());
+        assert!(connack.session_present);
+        assert_eq!(
+            Packet::Disconnect(Disconnect::new(reason::SESSION_TAKEN_OVER)),
+            first.recv()
+        );
+        second.send(&Packet::Disconnect(Disconnect::new(reason::SUCCESS)));
+
+        // Messages published while disconnected are delivered on reconnecting,
+        // and those left unacknowledged are delivered again.
+        let (mut publisher, _) =
+            TestClient::connect(addr, connect_packet(Version::V311, "publisher", true));
+        publisher.publish("jobs", "a", QoS::AtLeastOnce, false);
+        publisher.publish("jobs", "b", QoS::AtLeastOnce, false);
+        let (mut worker, connack) = TestClient::connect(addr, persistent());
+        assert!(connack.session_present);
+        let a = worker.recv_publish();
+        assert_eq!(b"a".to_vec(), a.payload);
+        worker.send(&Packet::PubAck(Ack::new(a.packet_id)));
+        let b = worker.recv_publish();
+        assert_eq!(b"b".to_vec(), b.payload);
+        worker.stream.get_ref().shutdown(Shutdown::Both).unwrap();
+
+        let (mut worker, _) = TestClient::connect(addr, persistent());
+        let resent = worker.recv_publish();
+        assert_eq!((b"b".to_vec(), true), (resent.payload, resent.dup));
+        worker.send(&Packet::PubAck(Ack::new(resent.packet_id)));
+        worker.assert_silent();
+        worker.send(&Packet::Disconnect(Disconnect::new(reason::SUCCESS)));
+
+        // The session is recovered from the store after a restart, delivering
+        // only what was published since.
+        publisher.publish("jobs", "c", QoS::AtLeastOnce, false);
+        let addr = start(broker);
+        let (mut worker, connack) = TestClient::connect(addr, persistent());
+        assert!(connack.session_present);
+        let c = worker.recv_publish();
+        assert_eq!(b"c".to_vec(), c.payload);
+        worker.send(&Packet::PubAck(Ack::new(c.packet_id)));
+        worker.assert_silent();
+
+        // Starting clean discards the session.
+        let mut connect = persistent();
+        connect.clean_start = true;
+        let (mut worker, connack) = TestClient::connect(addr, connect);
+        assert!(!connack.session_present);
+        worker.send(&Packet::Disconnect(Disconnect::new(reason::SUCCESS)));
+    }
+}
@@ -0,0 +1,19 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod config;
+mod connection;
+mod error;
+mod filter;
+mod gateway;
+mod listener;
+mod packet;
+mod session;
+mod state;
+mod store;
+
+pub use self::config::Config;
+pub use self::error::{Error, Result};
+pub use self::gateway::{CLIENT_ID_HEADER, QOS_HEADER, RETAIN_HEADER};
+pub use self::listener::Server;
+pub use self::store::MQTT_TOPIC;
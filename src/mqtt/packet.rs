@@ -0,0 +1,1116 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::io::{Read, Write};
+
+use crate::codec::{Reader, Writer};
+
+use super::error::{Error, Result};
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const PUBREC: u8 = 5;
+const PUBREL: u8 = 6;
+const PUBCOMP: u8 = 7;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const UNSUBSCRIBE: u8 = 10;
+const UNSUBACK: u8 = 11;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+const DISCONNECT: u8 = 14;
+const AUTH: u8 = 15;
+
+/// The protocol name every CONNECT packet starts with.
+const PROTOCOL_NAME: &str = "MQTT";
+
+/// The MQTT reason codes, which double as MQTT 3.1.1 return codes where the
+/// two overlap.
+pub(super) mod reason {
+    /// The operation succeeded, or a QoS 0 subscription was granted.
+    pub const SUCCESS: u8 = 0x00;
+    /// An unsubscribed topic filter had no subscription.
+    pub const NO_SUBSCRIPTION_EXISTED: u8 = 0x11;
+    /// The client disconnected and asked for its will to be published.
+    pub const DISCONNECT_WITH_WILL: u8 = 0x04;
+    /// MQTT 3.1.1's return code for an unsupported protocol level.
+    pub const V3_UNACCEPTABLE_VERSION: u8 = 0x01;
+    /// MQTT 3.1.1's return code for a rejected client identifier.
+    pub const V3_IDENTIFIER_REJECTED: u8 = 0x02;
+    /// MQTT 3.1.1's return code for an unavailable server.
+    pub const V3_SERVER_UNAVAILABLE: u8 = 0x03;
+    /// MQTT 3.1.1's return code for a failed subscription.
+    pub const V3_FAILURE: u8 = 0x80;
+    /// The operation failed for an unspecified reason.
+    pub const UNSPECIFIED_ERROR: u8 = 0x80;
+    /// The packet could not be decoded.
+    pub const MALFORMED_PACKET: u8 = 0x81;
+    /// The packet violated the protocol.
+    pub const PROTOCOL_ERROR: u8 = 0x82;
+    /// The client identifier is not valid.
+    pub const CLIENT_IDENTIFIER_INVALID: u8 = 0x85;
+    /// The server is unable to serve the client.
+    pub const SERVER_UNAVAILABLE: u8 = 0x88;
+    /// Another connection took over the client's session.
+    pub const SESSION_TAKEN_OVER: u8 = 0x8E;
+    /// The topic filter is not valid.
+    pub const TOPIC_FILTER_INVALID: u8 = 0x8F;
+    /// The topic name is not valid.
+    pub const TOPIC_NAME_INVALID: u8 = 0x90;
+    /// The acknowledged packet identifier is not in use.
+    pub const PACKET_IDENTIFIER_NOT_FOUND: u8 = 0x92;
+    /// The client did not send a packet within its keep alive.
+    pub const KEEP_ALIVE_TIMEOUT: u8 = 0x8D;
+    /// The packet exceeded the maximum packet size.
+    pub const PACKET_TOO_LARGE: u8 = 0x95;
+    /// The authentication method is not supported.
+    pub const BAD_AUTHENTICATION_METHOD: u8 = 0x8C;
+    /// Shared subscriptions are not supported.
+    pub const SHARED_SUBSCRIPTIONS_NOT_SUPPORTED: u8 = 0x9E;
+}
+
+/// The versions of MQTT served, identified by the protocol level of CONNECT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Version {
+    /// MQTT 3.1.1, protocol level 4.
+    V311,
+    /// MQTT 5.0, protocol level 5.
+    V5,
+}
+
+impl Version {
+    fn level(self) -> u8 {
+        match self {
+            Version::V311 => 4,
+            Version::V5 => 5,
+        }
+    }
+}
+
+/// The delivery guarantee of a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[allow(clippy::enum_variant_names)]
+pub(super) enum QoS {
+    /// Delivered at most once, without acknowledgement.
+    AtMostOnce = 0,
+    /// Delivered at least once, acknowledged with PUBACK.
+    AtLeastOnce = 1,
+    /// Delivered exactly once, through PUBREC, PUBREL, and PUBCOMP.
+    ExactlyOnce = 2,
+}
+
+impl QoS {
+    pub(super) fn from_u8(value: u8) -> Result<QoS> {
+        match value {
+            0 => Ok(QoS::AtMostOnce),
+            1 => Ok(QoS::AtLeastOnce),
+            2 => Ok(QoS::ExactlyOnce),
+            _ => Err(Error::Malformed {
+                reason: "QoS must be 0, 1, or 2",
+            }),
+        }
+    }
+}
+
+/// The MQTT 5.0 properties rift understands. Any others a client sends are
+/// skipped.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(super) struct Properties {
+    pub payload_format: Option<u8>,
+    pub content_type: Option<String>,
+    pub response_topic: Option<String>,
+    pub correlation_data: Option<Vec<u8>>,
+    pub session_expiry: Option<u32>,
+    pub assigned_client_id: Option<String>,
+    pub authentication_method: Option<String>,
+    pub reason_string: Option<String>,
+    pub receive_maximum: Option<u16>,
+    pub topic_alias: Option<u16>,
+    pub maximum_packet_size: Option<u32>,
+    pub subscription_identifiers_available: Option<u8>,
+    pub shared_subscriptions_available: Option<u8>,
+    pub user: Vec<(String, String)>,
+}
+
+impl Properties {
+    fn decode(reader: &mut Reader) -> Result<Properties> {
+        let len = get_varint(reader)? as usize;
+        let mut reader = Reader::new(reader.get_raw(len)?);
+        let mut properties = Properties::default();
+        while !reader.is_empty() {
+            match get_varint(&mut reader)? {
+                0x01 => properties.payload_format = Some(reader.get_u8()?),
+                0x03 => properties.content_type = Some(reader.get_string()?),
+                0x08 => properties.response_topic = Some(reader.get_string()?),
+                0x09 => properties.correlation_data = Some(get_binary(&mut reader)?),
+                0x11 => properties.session_expiry = Some(reader.get_u32()?),
+                0x12 => properties.assigned_client_id = Some(reader.get_string()?),
+                0x15 => properties.authentication_method = Some(reader.get_string()?),
+                0x1F => properties.reason_string = Some(reader.get_string()?),
+                0x21 => properties.receive_maximum = Some(reader.get_u16()?),
+                0x23 => properties.topic_alias = Some(reader.get_u16()?),
+                0x27 => properties.maximum_packet_size = Some(reader.get_u32()?),
+                0x29 => properties.subscription_identifiers_available = Some(reader.get_u8()?),
+                0x2A => properties.shared_subscriptions_available = Some(reader.get_u8()?),
+                0x26 => properties
+                    .user
+                    .push((reader.get_string()?, reader.get_string()?)),
+                // Properties that are understood well enough to skip.
+                0x17 | 0x19 | 0x24 | 0x25 | 0x28 => {
+                    reader.get_u8()?;
+                }
+                0x13 | 0x22 => {
+                    reader.get_u16()?;
+                }
+                0x02 | 0x18 => {
+                    reader.get_u32()?;
+                }
+                0x0B => {
+                    get_varint(&mut reader)?;
+                }
+                0x1A | 0x1C => {
+                    reader.get_string()?;
+                }
+                0x16 => {
+                    get_binary(&mut reader)?;
+                }
+                _ => {
+                    return Err(Error::Malformed {
+                        reason: "unknown property identifier",
+                    })
+                }
+            }
+        }
+        Ok(properties)
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let mut props = Vec::new();
+        if let Some(value) = self.payload_format {
+            props.put_u8(0x01);
+            props.put_u8(value);
+        }
+        if let Some(value) = &self.content_type {
+            props.put_u8(0x03);
+            props.put_string(value);
+        }
+        if let Some(value) = &self.response_topic {
+            props.put_u8(0x08);
+            props.put_string(value);
+        }
+        if let Some(value) = &self.correlation_data {
+            props.put_u8(0x09);
+            put_binary(&mut props, value);
+        }
+        if let Some(value) = self.session_expiry {
+            props.put_u8(0x11);
+            props.put_u32(value);
+        }
+        if let Some(value) = &self.assigned_client_id {
+            props.put_u8(0x12);
+            props.put_string(value);
+        }
+        if let Some(value) = &self.authentication_method {
+            props.put_u8(0x15);
+            props.put_string(value);
+        }
+        if let Some(value) = &self.reason_string {
+            props.put_u8(0x1F);
+            props.put_string(value);
+        }
+        if let Some(value) = self.receive_maximum {
+            props.put_u8(0x21);
+            props.put_u16(value);
+        }
+        if let Some(value) = self.topic_alias {
+            props.put_u8(0x23);
+            props.put_u16(value);
+        }
+        if let Some(value) = self.maximum_packet_size {
+            props.put_u8(0x27);
+            props.put_u32(value);
+        }
+        if let Some(value) = self.subscription_identifiers_available {
+            props.put_u8(0x29);
+            props.put_u8(value);
+        }
+        if let Some(value) = self.shared_subscriptions_available {
+            props.put_u8(0x2A);
+            props.put_u8(value);
+        }
+        for (key, value) in &self.user {
+            props.put_u8(0x26);
+            props.put_string(key);
+            props.put_string(value);
+        }
+        put_varint(buf, props.len() as u32);
+        buf.put_raw(&props);
+    }
+}
+
+/// The message a server publishes on a client's behalf should it disconnect
+/// without saying goodbye.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Will {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: QoS,
+    pub retain: bool,
+    pub properties: Properties,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Connect {
+    pub version: Version,
+    pub clean_start: bool,
+    pub keep_alive: u16,
+    pub client_id: String,
+    pub will: Option<Will>,
+    pub username: Option<String>,
+    pub password: Option<Vec<u8>>,
+    pub properties: Properties,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct ConnAck {
+    pub session_present: bool,
+    pub code: u8,
+    pub properties: Properties,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Publish {
+    pub dup: bool,
+    pub qos: QoS,
+    pub retain: bool,
+    pub topic: String,
+    /// The packet identifier, which is only sent for QoS 1 and 2 messages.
+    pub packet_id: u16,
+    pub properties: Properties,
+    pub payload: Vec<u8>,
+}
+
+/// The body shared by PUBACK, PUBREC, PUBREL, and PUBCOMP.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Ack {
+    pub packet_id: u16,
+    pub reason: u8,
+}
+
+impl Ack {
+    pub(super) fn new(packet_id: u16) -> Ack {
+        Ack {
+            packet_id,
+            reason: reason::SUCCESS,
+        }
+    }
+}
+
+/// How a subscription wants matching messages delivered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct SubscriptionOptions {
+    pub qos: QoS,
+    /// Skip messages the subscribing client published itself.
+    pub no_local: bool,
+    /// Keep the retain flag of messages delivered as they are published.
+    pub retain_as_published: bool,
+    /// When to send retained messages: 0 on every subscribe, 1 only for new
+    /// subscriptions, and 2 never.
+    pub retain_handling: u8,
+}
+
+impl SubscriptionOptions {
+    /// Returns options delivering messages at up to the supplied QoS, with the
+    /// defaults of MQTT 3.1.1.
+    pub(super) fn new(qos: QoS) -> SubscriptionOptions {
+        SubscriptionOptions {
+            qos,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: 0,
+        }
+    }
+
+    pub(super) fn from_u8(options: u8) -> Result<SubscriptionOptions> {
+        let retain_handling = (options >> 4) & 0x03;
+        if options & 0xC0 != 0 || retain_handling == 3 {
+            return Err(Error::Malformed {
+                reason: "invalid subscription options",
+            });
+        }
+        Ok(SubscriptionOptions {
+            qos: QoS::from_u8(options & 0x03)?,
+            no_local: options & 0x04 != 0,
+            retain_as_published: options & 0x08 != 0,
+            retain_handling,
+        })
+    }
+
+    pub(super) fn to_u8(self) -> u8 {
+        self.qos as u8
+            | (self.no_local as u8) << 2
+            | (self.retain_as_published as u8) << 3
+            | self.retain_handling << 4
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Subscribe {
+    pub packet_id: u16,
+    pub filters: Vec<(String, SubscriptionOptions)>,
+}
+
+/// The body shared by SUBACK and UNSUBACK, holding a reason code per topic
+/// filter. MQTT 3.1.1 UNSUBACKs carry no reason codes.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct SubAck {
+    pub packet_id: u16,
+    pub codes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Unsubscribe {
+    pub packet_id: u16,
+    pub filters: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Disconnect {
+    pub reason: u8,
+    pub properties: Properties,
+}
+
+impl Disconnect {
+    pub(super) fn new(reason: u8) -> Disconnect {
+        Disconnect {
+            reason,
+            properties: Properties::default(),
+        }
+    }
+}
+
+/// A single MQTT control packet.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Packet {
+    Connect(Box<Connect>),
+    ConnAck(ConnAck),
+    Publish(Publish),
+    PubAck(Ack),
+    PubRec(Ack),
+    PubRel(Ack),
+    PubComp(Ack),
+    Subscribe(Subscribe),
+    SubAck(SubAck),
+    Unsubscribe(Unsubscribe),
+    UnsubAck(SubAck),
+    PingReq,
+    PingResp,
+    Disconnect(Disconnect),
+}
+
+impl Packet {
+    /// Decode the body of a packet given the first byte of its fixed header.
+    /// CONNECT packets carry their own version, while every other packet is
+    /// decoded according to the version the connection was established with.
+    pub(super) fn decode(header: u8, body: &[u8], version: Version) -> Result<Packet> {
+        let (kind, flags) = (header >> 4, header & 0x0F);
+        let expected = match kind {
+            PUBLISH => flags,
+            PUBREL | SUBSCRIBE | UNSUBSCRIBE => 0x02,
+            _ => 0x00,
+        };
+        if flags != expected {
+            return Err(Error::Malformed {
+                reason: "invalid fixed header flags",
+            });
+        }
+
+        let v5 = version == Version::V5;
+        let mut reader = Reader::new(body);
+        let packet = match kind {
+            CONNECT => Packet::Connect(Box::new(decode_connect(&mut reader)?)),
+            CONNACK => {
+                let session_present = reader.get_u8()? & 0x01 != 0;
+                let code = reader.get_u8()?;
+                let properties = if v5 {
+                    Properties::decode(&mut reader)?
+                } else {
+                    Properties::default()
+                };
+                Packet::ConnAck(ConnAck {
+                    session_present,
+                    code,
+                    properties,
+                })
+            }
+            PUBLISH => {
+                let qos = QoS::from_u8((flags >> 1) & 0x03)?;
+                let topic = reader.get_string()?;
+                let packet_id = match qos {
+                    QoS::AtMostOnce => 0,
+                    _ => nonzero_packet_id(&mut reader)?,
+                };
+                let properties = if v5 {
+                    Properties::decode(&mut reader)?
+                } else {
+                    Properties::default()
+                };
+                let payload = reader.get_raw(reader.remaining())?.to_vec();
+                Packet::Publish(Publish {
+                    dup: flags & 0x08 != 0,
+                    qos,
+                    retain: flags & 0x01 != 0,
+                    topic,
+                    packet_id,
+                    properties,
+                    payload,
+                })
+            }
+            PUBACK | PUBREC | PUBREL | PUBCOMP => {
+                let packet_id = nonzero_packet_id(&mut reader)?;
+                let mut reason = reason::SUCCESS;
+                if v5 && !reader.is_empty() {
+                    reason = reader.get_u8()?;
+                    if !reader.is_empty() {
+                        Properties::decode(&mut reader)?;
+                    }
+                }
+                let ack = Ack { packet_id, reason };
+                match kind {
+                    PUBACK => Packet::PubAck(ack),
+                    PUBREC => Packet::PubRec(ack),
+                    PUBREL => Packet::PubRel(ack),
+                    _ => Packet::PubComp(ack),
+                }
+            }
+            SUBSCRIBE => {
+                let packet_id = nonzero_packet_id(&mut reader)?;
+                if v5 {
+                    Properties::decode(&mut reader)?;
+                }
+                let mut filters = Vec::new();
+                while !reader.is_empty() {
+                    let filter = reader.get_string()?;
+                    let options = match reader.get_u8()? {
+                        options if v5 => SubscriptionOptions::from_u8(options)?,
+                        options if options & 0xFC == 0 => {
+                            SubscriptionOptions::new(QoS::from_u8(options)?)
+                        }
+                        _ => {
+                            return Err(Error::Malformed {
+                                reason: "invalid subscription options",
+                            })
+                        }
+                    };
+                    filters.push((filter, options));
+                }
+                if filters.is_empty() {
+                    return Err(Error::Malformed {
+                        reason: "SUBSCRIBE must contain at least one topic filter",
+                    });
+                }
+                Packet::Subscribe(Subscribe { packet_id, filters })
+            }
+            SUBACK | UNSUBACK => {
+                let packet_id = reader.get_u16()?;
+                if v5 {
+                    Properties::decode(&mut reader)?;
+                }
+                let codes = reader.get_raw(reader.remaining())?.to_vec();
+                let ack = SubAck { packet_id, codes };
+                match kind {
+                    SUBACK => Packet::SubAck(ack),
+                    _ => Packet::UnsubAck(ack),
+                }
+            }
+            UNSUBSCRIBE => {
+                let packet_id = nonzero_packet_id(&mut reader)?;
+                if v5 {
+                    Properties::decode(&mut reader)?;
+                }
+                let mut filters = Vec::new();
+                while !reader.is_empty() {
+                    filters.push(reader.get_string()?);
+                }
+                if filters.is_empty() {
+                    return Err(Error::Malformed {
+                        reason: "UNSUBSCRIBE must contain at least one topic filter",
+                    });
+                }
+                Packet::Unsubscribe(Unsubscribe { packet_id, filters })
+            }
+            PINGREQ => Packet::PingReq,
+            PINGRESP => Packet::PingResp,
+            DISCONNECT => {
+                let mut disconnect = Disconnect::new(reason::SUCCESS);
+                if v5 && !reader.is_empty() {
+                    disconnect.reason = reader.get_u8()?;
+                    if !reader.is_empty() {
+                        disconnect.properties = Properties::decode(&mut reader)?;
+                    }
+                }
+                Packet::Disconnect(disconnect)
+            }
+            AUTH => {
+                return Err(Error::Protocol {
+                    reason: "enhanced authentication is not supported",
+                })
+            }
+            _ => {
+                return Err(Error::Malformed {
+                    reason: "unknown packet type",
+                })
+            }
+        };
+        if !reader.is_empty() {
+            return Err(Error::Malformed {
+                reason: "unexpected trailing bytes",
+            });
+        }
+        Ok(packet)
+    }
+
+    /// Encode the packet, including its fixed header, for a connection using
+    /// the supplied version.
+    pub(super) fn encode(&self, version: Version) -> Vec<u8> {
+        let v5 = version == Version::V5;
+        let mut body = Vec::new();
+        let header = match self {
+            Packet::Connect(connect) => {
+                encode_connect(connect, &mut body);
+                CONNECT << 4
+            }
+            Packet::ConnAck(connack) => {
+                body.put_u8(connack.session_present as u8);
+                body.put_u8(connack.code);
+                if v5 {
+                    connack.properties.encode(&mut body);
+                }
+                CONNACK << 4
+            }
+            Packet::Publish(publish) => {
+                body.put_string(&publish.topic);
+                if publish.qos != QoS::AtMostOnce {
+                    body.put_u16(publish.packet_id);
+                }
+                if v5 {
+                    publish.properties.encode(&mut body);
+                }
+                body.put_raw(&publish.payload);
+                PUBLISH << 4
+                    | (publish.dup as u8) << 3
+                    | (publish.qos as u8) << 1
+                    | publish.retain as u8
+            }
+            Packet::PubAck(ack)
+            | Packet::PubRec(ack)
+            | Packet::PubRel(ack)
+            | Packet::PubComp(ack) => {
+                body.put_u16(ack.packet_id);
+                // The reason code may be omitted when it is success.
+                if v5 && ack.reason != reason::SUCCESS {
+                    body.put_u8(ack.reason);
+                }
+                match self {
+                    Packet::PubAck(_) => PUBACK << 4,
+                    Packet::PubRec(_) => PUBREC << 4,
+                    Packet::PubRel(_) => PUBREL << 4 | 0x02,
+                    _ => PUBCOMP << 4,
+                }
+            }
+            Packet::Subscribe(subscribe) => {
+                body.put_u16(subscribe.packet_id);
+                if v5 {
+                    Properties::default().encode(&mut body);
+                }
+                for (filter, options) in &subscribe.filters {
+                    body.put_string(filter);
+                    body.put_u8(options.to_u8());
+                }
+                SUBSCRIBE << 4 | 0x02
+            }
+            Packet::SubAck(ack) | Packet::UnsubAck(ack) => {
+                body.put_u16(ack.packet_id);
+                if v5 {
+                    Properties::default().encode(&mut body);
+                }
+                let suback = matches!(self, Packet::SubAck(_));
+                if suback || v5 {
+                    body.put_raw(&ack.codes);
+                }
+                if suback {
+                    SUBACK << 4
+                } else {
+                    UNSUBACK << 4
+                }
+            }
+            Packet::Unsubscribe(unsubscribe) => {
+                body.put_u16(unsubscribe.packet_id);
+                if v5 {
+                    Properties::default().encode(&mut body);
+                }
+                for filter in &unsubscribe.filters {
+                    body.put_string(filter);
+                }
+                UNSUBSCRIBE << 4 | 0x02
+            }
+            Packet::PingReq => PINGREQ << 4,
+            Packet::PingResp => PINGRESP << 4,
+            Packet::Disconnect(disconnect) => {
+                if v5 {
+                    body.put_u8(disconnect.reason);
+                    disconnect.properties.encode(&mut body);
+                }
+                DISCONNECT << 4
+            }
+        };
+
+        let mut buf = Vec::with_capacity(body.len() + 5);
+        buf.put_u8(header);
+        put_varint(&mut buf, body.len() as u32);
+        buf.put_raw(&body);
+        buf
+    }
+}
+
+fn decode_connect(reader: &mut Reader) -> Result<Connect> {
+    if reader.get_string()? != PROTOCOL_NAME {
+        return Err(Error::Malformed {
+            reason: "unknown protocol name",
+        });
+    }
+    let version = match reader.get_u8()? {
+        4 => Version::V311,
+        5 => Version::V5,
+        level => return Err(Error::UnsupportedVersion { level }),
+    };
+    let flags = reader.get_u8()?;
+    if flags & 0x01 != 0 {
+        return Err(Error::Malformed {
+            reason: "reserved CONNECT flag is set",
+        });
+    }
+    let will_flag = flags & 0x04 != 0;
+    let will_qos = QoS::from_u8((flags >> 3) & 0x03)?;
+    let will_retain = flags & 0x20 != 0;
+    if !will_flag && (will_qos != QoS::AtMostOnce || will_retain) {
+        return Err(Error::Malformed {
+            reason: "will QoS and retain must be unset without a will",
+        });
+    }
+
+    let keep_alive = reader.get_u16()?;
+    let properties = match version {
+        Version::V5 => Properties::decode(reader)?,
+        Version::V311 => Properties::default(),
+    };
+    let client_id = reader.get_string()?;
+    let will = if will_flag {
+        let properties = match version {
+            Version::V5 => Properties::decode(reader)?,
+            Version::V311 => Properties::default(),
+        };
+        Some(Will {
+            topic: reader.get_string()?,
+            payload: get_binary(reader)?,
+            qos: will_qos,
+            retain: will_retain,
+            properties,
+        })
+    } else {
+        None
+    };
+    let username = match flags & 0x80 {
+        0 => None,
+        _ => Some(reader.get_string()?),
+    };
+    let password = match flags & 0x40 {
+        0 => None,
+        _ => Some(get_binary(reader)?),
+    };
+    Ok(Connect {
+        version,
+        clean_start: flags & 0x02 != 0,
+        keep_alive,
+        client_id,
+        will,
+        username,
+        password,
+        properties,
+    })
+}
+
+fn encode_connect(connect: &Connect, body: &mut Vec<u8>) {
+    let v5 = connect.version == Version::V5;
+    body.put_string(PROTOCOL_NAME);
+    body.put_u8(connect.version.level());
+    let mut flags = (connect.clean_start as u8) << 1;
+    if let Some(will) = &connect.will {
+        flags |= 0x04 | (will.qos as u8) << 3 | (will.retain as u8) << 5;
+    }
+    if connect.password.is_some() {
+        flags |= 0x40;
+    }
+    if connect.username.is_some() {
+        flags |= 0x80;
+    }
+    body.put_u8(flags);
+    body.put_u16(connect.keep_alive);
+    if v5 {
+        connect.properties.encode(body);
+    }
+    body.put_string(&connect.client_id);
+    if let Some(will) = &connect.will {
+        if v5 {
+            will.properties.encode(body);
+        }
+        body.put_string(&will.topic);
+        put_binary(body, &will.payload);
+    }
+    if let Some(username) = &connect.username {
+        body.put_string(username);
+    }
+    if let Some(password) = &connect.password {
+        put_binary(body, password);
+    }
+}
+
+/// Read a single packet, returning [None] if the connection was cleanly closed
+/// before any bytes of the packet were read. Packets larger than `max_size`
+/// are rejected without reading their body.
+pub(super) fn read_packet(
+    reader: &mut impl Read,
+    version: Version,
+    max_size: u32,
+) -> Result<Option<Packet>> {
+    let mut header = [0; 1];
+    if reader.read(&mut header)? == 0 {
+        return Ok(None);
+    }
+
+    let mut len = 0usize;
+    let mut prefix = 1;
+    loop {
+        let mut byte = [0; 1];
+        reader.read_exact(&mut byte)?;
+        len |= ((byte[0] & 0x7F) as usize) << (7 * (prefix - 1));
+        prefix += 1;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+        if prefix > 4 {
+            return Err(Error::Malformed {
+                reason: "remaining length longer than 4 bytes",
+            });
+        }
+    }
+    let size = 1 + prefix - 1 + len;
+    if size > max_size as usize {
+        return Err(Error::PacketTooLarge {
+            size,
+            max: max_size,
+        });
+    }
+
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body)?;
+    Packet::decode(header[0], &body, version).map(Some)
+}
+
+/// Write a single packet without flushing the writer.
+pub(super) fn write_packet(
+    writer: &mut impl Write,
+    packet: &Packet,
+    version: Version,
+) -> Result<()> {
+    writer.write_all(&packet.encode(version))?;
+    Ok(())
+}
+
+fn nonzero_packet_id(reader: &mut Reader) -> Result<u16> {
+    match reader.get_u16()? {
+        0 => Err(Error::Malformed {
+            reason: "packet identifiers must not be zero",
+        }),
+        packet_id => Ok(packet_id),
+    }
+}
+
+fn get_varint(reader: &mut Reader) -> Result<u32> {
+    let mut value = 0;
+    for shift in [0, 7, 14, 21] {
+        let byte = reader.get_u8()?;
+        value |= ((byte & 0x7F) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::Malformed {
+        reason: "variable byte integer longer than 4 bytes",
+    })
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let mut byte = (value % 128) as u8;
+        value /= 128;
+        if value > 0 {
+            byte |= 0x80;
+        }
+        buf.put_u8(byte);
+        if value == 0 {
+            return;
+        }
+    }
+}
+
+fn get_binary(reader: &mut Reader) -> Result<Vec<u8>> {
+    let len = reader.get_u16()? as usize;
+    Ok(reader.get_raw(len)?.to_vec())
+}
+
+fn put_binary(buf: &mut Vec<u8>, value: &[u8]) {
+    buf.put_u16(value.len() as u16);
+    buf.put_raw(value);
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn round_trip(packet: Packet, version: Version) {
+        let buf = packet.encode(version);
+        let mut cursor = Cursor::new(buf);
+        assert_eq!(
+            Some(packet),
+            read_packet(&mut cursor, version, u32::MAX).unwrap()
+        );
+        assert_eq!(None, read_packet(&mut cursor, version, u32::MAX).unwrap());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for version in [Version::V311, Version::V5] {
+            let v5 = version == Version::V5;
+            let properties = |properties: Properties| {
+                if v5 {
+                    properties
+                } else {
+                    Properties::default()
+                }
+            };
+            round_trip(
+                Packet::Connect(Box::new(Connect {
+                    version,
+                    clean_start: true,
+                    keep_alive: 30,
+                    client_id: String::from("sensor-1"),
+                    will: Some(Will {
+                        topic: String::from("sensors/1/status"),
+                        payload: b"offline".to_vec(),
+                        qos: QoS::AtLeastOnce,
+                        retain: true,
+                        properties: properties(Properties {
+                            content_type: Some(String::from("text/plain")),
+                            ..Properties::default()
+                        }),
+                    }),
+                    username: Some(String::from("user")),
+                    password: Some(b"secret".to_vec()),
+                    properties: properties(Properties {
+                        session_expiry: Some(60),
+                        receive_maximum: Some(10),
+                        ..Properties::default()
+                    }),
+                })),
+                version,
+            );
+            round_trip(
+                Packet::ConnAck(ConnAck {
+                    session_present: true,
+                    code: reason::SUCCESS,
+                    properties: properties(Properties {
+                        assigned_client_id: Some(String::from("rift-1")),
+                        ..Properties::default()
+                    }),
+                }),
+                version,
+            );
+            round_trip(
+                Packet::Publish(Publish {
+                    dup: true,
+                    qos: QoS::ExactlyOnce,
+                    retain: true,
+                    topic: String::from("sensors/1/temp"),
+                    packet_id: 7,
+                    properties: properties(Properties {
+                        response_topic: Some(String::from("replies/1")),
+                        correlation_data: Some(vec![1, 2]),
+                        user: vec![(String::from("unit"), String::from("celsius"))],
+                        ..Properties::default()
+                    }),
+                    payload: b"21.5".to_vec(),
+                }),
+                version,
+            );
+            round_trip(
+                Packet::Publish(Publish {
+                    dup: false,
+                    qos: QoS::AtMostOnce,
+                    retain: false,
+                    topic: String::from("a"),
+                    packet_id: 0,
+                    properties: Properties::default(),
+                    payload: Vec::new(),
+                }),
+                version,
+            );
+            round_trip(Packet::PubAck(Ack::new(1)), version);
+            round_trip(Packet::PubRec(Ack::new(2)), version);
+            round_trip(Packet::PubRel(Ack::new(3)), version);
+            round_trip(Packet::PubComp(Ack::new(4)), version);
+            round_trip(
+                Packet::Subscribe(Subscribe {
+                    packet_id: 5,
+                    filters: vec![
+                        (
+                            String::from("sensors/+/temp"),
+                            SubscriptionOptions::new(QoS::AtLeastOnce),
+                        ),
+                        (String::from("#"), SubscriptionOptions::new(QoS::AtMostOnce)),
+                    ],
+                }),
+                version,
+            );
+            round_trip(
+                Packet::SubAck(SubAck {
+                    packet_id: 5,
+                    codes: vec![1, reason::V3_FAILURE],
+                }),
+                version,
+            );
+            round_trip(
+                Packet::Unsubscribe(Unsubscribe {
+                    packet_id: 6,
+                    filters: vec![String::from("#")],
+                }),
+                version,
+            );
+            round_trip(
+                Packet::UnsubAck(SubAck {
+                    packet_id: 6,
+                    codes: if v5 {
+                        vec![reason::SUCCESS]
+                    } else {
+                        Vec::new()
+                    },
+                }),
+                version,
+            );
+            round_trip(Packet::PingReq, version);
+            round_trip(Packet::PingResp, version);
+            round_trip(
+                Packet::Disconnect(Disconnect::new(reason::SUCCESS)),
+                version,
+            );
+        }
+
+        round_trip(
+            Packet::PubAck(Ack {
+                packet_id: 9,
+                reason: reason::TOPIC_NAME_INVALID,
+            }),
+            Version::V5,
+        );
+        round_trip(
+            Packet::Subscribe(Subscribe {
+                packet_id: 1,
+                filters: vec![(
+                    String::from("a/#"),
+                    SubscriptionOptions {
+                        qos: QoS::ExactlyOnce,
+                        no_local: true,
+                        retain_as_published: true,
+                        retain_handling: 2,
+                    },
+                )],
+            }),
+            Version::V5,
+        );
+    }
+
+    #[test]
+    fn test_varint() {
+        for value in [
+            0,
+            127,
+            128,
+            16_383,
+            16_384,
+            2_097_151,
+            2_097_152,
+            268_435_455,
+        ] {
+            let mut buf = Vec::new();
+            put_varint(&mut buf, value);
+            assert_eq!(value, get_varint(&mut Reader::new(&buf)).unwrap());
+        }
+        let mut buf = Vec::new();
+        put_varint(&mut buf, 321);
+        assert_eq!(vec![0xC1, 0x02], buf);
+        assert!(get_varint(&mut Reader::new(&[0xFF, 0xFF, 0xFF, 0xFF, 0x01])).is_err());
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let publish = Packet::Publish(Publish {
+            dup: false,
+            qos: QoS::AtMostOnce,
+            retain: false,
+            topic: String::from("a/b"),
+            packet_id: 0,
+            properties: Properties::default(),
+            payload: vec![0; 100],
+        })
+        .encode(Version::V311);
+        assert!(matches!(
+            read_packet(&mut Cursor::new(publish), Version::V311, 64),
+            Err(Error::PacketTooLarge { size: 107, max: 64 })
+        ));
+        let ping = Packet::PingReq.encode(Version::V311);
+        assert!(read_packet(&mut Cursor::new(ping), Version::V311, 2).is_ok());
+
+        // SUBSCRIBE must set its reserved flags.
+        assert!(matches!(
+            Packet::decode(SUBSCRIBE << 4, &[0, 1, 0, 1, b'a', 0], Version::V311),
+            Err(Error::Malformed { .. })
+        ));
+        // QoS 3 does not exist.
+        assert!(matches!(
+            Packet::decode(PUBLISH << 4 | 0x06, &[0, 1, b'a', 0, 1], Version::V311),
+            Err(Error::Malformed { .. })
+        ));
+        // Packet identifiers must not be zero.
+        assert!(matches!(
+            Packet::decode(PUBACK << 4, &[0, 0], Version::V311),
+            Err(Error::Malformed { .. })
+        ));
+        assert!(matches!(
+            Packet::decode(AUTH << 4, &[], Version::V5),
+            Err(Error::Protocol { .. })
+        ));
+
+        let mut connect = Vec::new();
+        connect.put_string("MQTT");
+        connect.put_u8(3);
+        assert!(matches!(
+            Packet::decode(CONNECT << 4, &connect, Version::V311),
+            Err(Error::UnsupportedVersion { level: 3 })
+        ));
+    }
+}
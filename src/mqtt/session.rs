@@ -0,0 +1,340 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::topic::TopicPartition;
+
+use super::packet::{Publish, SubscriptionOptions};
+use super::state::SessionRecord;
+use super::store::Persisted;
+
+/// How long a client connecting with the id of a connected client waits for
+/// the existing connection to hand over its session.
+const TAKEOVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A QoS 1 or 2 message delivered to a client that it has yet to finish
+/// acknowledging.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Outgoing {
+    /// The partition and offset of the record delivered, or [None] for
+    /// retained messages.
+    pub source: Option<(TopicPartition, u64)>,
+    pub publish: Publish,
+    /// Whether the client has received the QoS 2 message, so only its PUBREL
+    /// remains to be completed.
+    pub released: bool,
+}
+
+/// Everything a server keeps for a client between packets, and between
+/// connections for persistent sessions.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(super) struct SessionState {
+    pub subscriptions: BTreeMap<String, SubscriptionOptions>,
+    /// The next offset of each partition to deliver to the client.
+    pub positions: BTreeMap<TopicPartition, u64>,
+    /// Messages delivered to the client awaiting acknowledgement, by packet id.
+    pub outgoing: BTreeMap<u16, Outgoing>,
+    /// The packet ids of QoS 2 messages received from the client awaiting
+    /// PUBREL.
+    pub incoming: BTreeSet<u16>,
+    /// How long the session outlives its connection, in seconds. Zero ends
+    /// the session along with the connection.
+    pub expiry_interval: u32,
+    /// Whether the session has been saved to the store.
+    pub persisted: bool,
+    next_packet_id: u16,
+    disconnected_at: Option<Instant>,
+}
+
+impl SessionState {
+    fn from_persisted(persisted: Persisted, now: Instant) -> SessionState {
+        SessionState {
+            subscriptions: persisted.session.subscriptions,
+            positions: persisted.positions,
+            expiry_interval: persisted.session.expiry_interval,
+            persisted: true,
+            disconnected_at: Some(now),
+            ..SessionState::default()
+        }
+    }
+
+    /// Returns whether the session outlives its connection.
+    pub(super) fn is_persistent(&self) -> bool {
+        self.expiry_interval > 0
+    }
+
+    /// Returns the subscriptions and expiry interval to save to the store.
+    pub(super) fn to_record(&self) -> SessionRecord {
+        SessionRecord {
+            expiry_interval: self.expiry_interval,
+            subscriptions: self.subscriptions.clone(),
+        }
+    }
+
+    /// Allocate a packet id not used by any message awaiting acknowledgement.
+    pub(super) fn next_packet_id(&mut self) -> u16 {
+        loop {
+            self.next_packet_id = self.next_packet_id.wrapping_add(1).max(1);
+            if !self.outgoing.contains_key(&self.next_packet_id) {
+                return self.next_packet_id;
+            }
+        }
+    }
+
+    /// Returns the offset of each partition from which delivery resumes
+    /// should the session be recovered from the store, which is that of the
+    /// earliest message still awaiting acknowledgement.
+    pub(super) fn committed_positions(&self) -> BTreeMap<TopicPartition, u64> {
+        let mut positions = self.positions.clone();
+        for (partition, offset) in self.outgoing.values().filter_map(|out| out.source.as_ref()) {
+            if let Some(position) = positions.get_mut(partition) {
+                *position = (*position).min(*offset);
+            }
+        }
+        positions
+    }
+
+    fn expired(&self, now: Instant) -> bool {
+        match self.disconnected_at {
+            Some(disconnected_at) => disconnected_at
+                .checked_add(Duration::from_secs(self.expiry_interval as u64))
+                .is_some_and(|expiry| expiry <= now),
+            None => false,
+        }
+    }
+}
+
+/// The session handed to a connection when its client connects.
+pub(super) struct Claim {
+    pub state: SessionState,
+    /// Whether an existing session was resumed.
+    pub present: bool,
+    /// An existing session that was discarded, as the client asked for a
+    /// clean start or it had expired.
+    pub discarded: Option<SessionState>,
+    /// Set once another connection claims the session.
+    pub taken_over: Arc<AtomicBool>,
+}
+
+struct Slot {
+    state: Option<SessionState>,
+    /// Present while a connection holds the session.
+    holder: Option<Arc<AtomicBool>>,
+}
+
+/// Tracks the session of every client that is connected, or disconnected
+/// with a session that has yet to expire, ensuring only one connection holds
+/// each session at a time.
+pub(super) struct Sessions {
+    slots: Mutex<HashMap<String, Slot>>,
+    released: Condvar,
+}
+
+impl Sessions {
+    /// Create a registry holding the supplied sessions recovered from the
+    /// store, which are treated as having disconnected just now.
+    pub(super) fn new(persisted: BTreeMap<String, Persisted>) -> Sessions {
+        let now = Instant::now();
+        let slots = persisted
+            .into_iter()
+            .map(|(client_id, persisted)| {
+                let slot = Slot {
+                    state: Some(SessionState::from_persisted(persisted, now)),
+                    holder: None,
+                };
+                (client_id, slot)
+            })
+            .collect();
+        Sessions {
+            slots: Mutex::new(slots),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Claim the session of the supplied client for a new connection, asking
+    /// any connection already holding it to hand it over. Returns [None] if
+    /// the existing connection did not do so in time.
+    pub(super) fn claim(&self, client_id: &str, clean_start: bool) -> Option<Claim> {
+        let deadline = Instant::now() + TAKEOVER_TIMEOUT;
+        let mut slots = self.slots();
+        while let Some(holder) = slots.get(client_id).and_then(|slot| slot.holder.clone()) {
+            holder.store(true, Ordering::SeqCst);
+            let timeout = deadline.checked_duration_since(Instant::now())?;
+            slots = self
+                .released
+                .wait_timeout(slots, timeout)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+
+        let taken_over = Arc::new(AtomicBool::new(false));
+        let slot = slots.entry(client_id.to_owned()).or_insert(Slot {
+            state: None,
+            holder: None,
+        });
+        slot.holder = Some(taken_over.clone());
+        let (state, present, discarded) = match slot.state.take() {
+            Some(state) if !clean_start && !state.expired(Instant::now()) => (state, true, None),
+            existing => (SessionState::default(), false, existing),
+        };
+        Some(Claim {
+            state: SessionState {
+                disconnected_at: None,
+                ..state
+            },
+            present,
+            discarded,
+            taken_over,
+        })
+    }
+
+    /// Hand back the session of a client whose connection closed, keeping it
+    /// for the client's next connection unless it ended with the connection.
+    pub(super) fn release(&self, client_id: &str, state: SessionState) {
+        let mut slots = self.slots();
+        if state.is_persistent() {
+            slots.insert(
+                client_id.to_owned(),
+                Slot {
+                    state: Some(SessionState {
+                        disconnected_at: Some(Instant::now()),
+                        ..state
+                    }),
+                    holder: None,
+                },
+            );
+        } else {
+            slots.remove(client_id);
+        }
+        self.released.notify_all();
+    }
+
+    fn slots(&self) -> MutexGuard<'_, HashMap<String, Slot>> {
+        self.slots
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::mqtt::packet::QoS;
+
+    fn persistent() -> SessionState {
+        let mut state = SessionState {
+            expiry_interval: u32::MAX,
+            ..SessionState::default()
+        };
+        state.subscriptions.insert(
+            String::from("a/#"),
+            SubscriptionOptions::new(QoS::AtLeastOnce),
+        );
+        state
+    }
+
+    #[test]
+    fn test_claim_and_release() {
+        let sessions = Sessions::new(BTreeMap::new());
+        let claim = sessions.claim("client", false).unwrap();
+        assert!(!claim.present);
+        assert!(claim.discarded.is_none());
+        sessions.release("client", persistent());
+
+        // A persistent session is resumed, unless the client starts clean.
+        let claim = sessions.claim("client", false).unwrap();
+        assert!(claim.present);
+        assert_eq!(persistent().subscriptions, claim.state.subscriptions);
+        sessions.release("client", claim.state);
+        let claim = sessions.claim("client", true).unwrap();
+        assert!(!claim.present);
+        assert!(claim.state.subscriptions.is_empty());
+        assert!(claim.discarded.is_some());
+
+        // Sessions that end with their connection are forgotten.
+        sessions.release("client", SessionState::default());
+        let claim = sessions.claim("client", false).unwrap();
+        assert!(!claim.present);
+        assert!(claim.discarded.is_none());
+    }
+
+    #[test]
+    fn test_expiry() {
+        let sessions = Sessions::new(BTreeMap::new());
+        sessions.claim("client", false).unwrap();
+        let mut state = persistent();
+        state.expiry_interval = 1;
+        sessions.release("client", state);
+        {
+            let mut slots = sessions.slots();
+            let state = slots.get_mut("client").unwrap().state.as_mut().unwrap();
+            state.disconnected_at = Some(Instant::now() - Duration::from_secs(2));
+        }
+        let claim = sessions.claim("client", false).unwrap();
+        assert!(!claim.present);
+        assert!(claim.discarded.is_some());
+    }
+
+    #[test]
+    fn test_takeover() {
+        let sessions = Arc::new(Sessions::new(BTreeMap::new()));
+        let first = sessions.claim("client", false).unwrap();
+
+        let holder = sessions.clone();
+        let handle = thread::spawn(move || {
+            while !first.taken_over.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            holder.release("client", persistent());
+        });
+        let second = sessions.claim("client", false).unwrap();
+        handle.join().unwrap();
+        assert!(second.present);
+        assert!(!second.taken_over.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_packet_ids() {
+        let mut state = SessionState::default();
+        assert_eq!(1, state.next_packet_id());
+        state.next_packet_id = u16::MAX - 1;
+        let outgoing = Outgoing {
+            source: Some((TopicPartition::new("a", 0), 5)),
+            publish: Publish {
+                dup: false,
+                qos: QoS::AtLeastOnce,
+                retain: false,
+                topic: String::from("a"),
+                packet_id: 1,
+                properties: Default::default(),
+                payload: Vec::new(),
+            },
+            released: false,
+        };
+        state.outgoing.insert(1, outgoing);
+        // Packet ids wrap around, skipping zero and those still in use.
+        assert_eq!(u16::MAX, state.next_packet_id());
+        assert_eq!(2, state.next_packet_id());
+
+        state.positions.insert(TopicPartition::new("a", 0), 9);
+        state.positions.insert(TopicPartition::new("b", 0), 3);
+        assert_eq!(
+            vec![5, 3],
+            state
+                .committed_positions()
+                .into_values()
+                .collect::<Vec<_>>()
+        );
+    }
+}
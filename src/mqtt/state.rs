@@ -0,0 +1,303 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::BTreeMap;
+
+use crate::codec::{self, Reader, Writer};
+use crate::record::{Header, Record};
+use crate::topic::TopicPartition;
+
+use super::packet::{QoS, SubscriptionOptions};
+
+/// The current version of the MQTT state record key format.
+const KEY_VERSION: u16 = 0;
+/// The current version of the MQTT state record value format.
+const VALUE_VERSION: u16 = 0;
+
+/// Identifies a key describing the message retained for an MQTT topic.
+const RETAINED_KEY: u8 = 0;
+/// Identifies a key describing a persistent session.
+const SESSION_KEY: u8 = 1;
+/// Identifies a key describing how far a persistent session has been
+/// delivered a partition.
+const POSITION_KEY: u8 = 2;
+
+/// A message retained for an MQTT topic, sent to clients as they subscribe
+/// to it.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Retained {
+    pub qos: QoS,
+    pub payload: Vec<u8>,
+    pub headers: Vec<Header>,
+}
+
+/// The subscriptions of a persistent session and how long it outlives its
+/// connection, in seconds.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct SessionRecord {
+    pub expiry_interval: u32,
+    pub subscriptions: BTreeMap<String, SubscriptionOptions>,
+}
+
+/// A change to the MQTT state, as stored in the MQTT state topic. Changes
+/// without a value are tombstones.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Change {
+    /// The message retained for the named MQTT topic was replaced or cleared.
+    Retained(String, Option<Retained>),
+    /// The identified client's persistent session was saved or ended.
+    Session(String, Option<SessionRecord>),
+    /// The next offset of a partition to deliver to the identified client
+    /// was saved or forgotten.
+    Position(String, TopicPartition, Option<u64>),
+}
+
+/// Returns the record storing the message retained for an MQTT topic, or the
+/// tombstone clearing it.
+pub(super) fn retained_record(name: &str, retained: Option<&Retained>) -> Record {
+    let mut key = key(RETAINED_KEY);
+    key.put_string(name);
+    let value = retained.map(|retained| {
+        let mut value = Vec::new();
+        value.put_u16(VALUE_VERSION);
+        value.put_u8(retained.qos as u8);
+        value.put_bytes(&retained.payload);
+        value.put_array(&retained.headers, |buf, header| {
+            buf.put_string(&header.key);
+            buf.put_bytes(&header.value);
+        });
+        value
+    });
+    record(key, value)
+}
+
+/// Returns the record storing a persistent session, or the tombstone ending it.
+pub(super) fn session_record(client_id: &str, session: Option<&SessionRecord>) -> Record {
+    let mut key = key(SESSION_KEY);
+    key.put_string(client_id);
+    let value = session.map(|session| {
+        let mut value = Vec::new();
+        value.put_u16(VALUE_VERSION);
+        value.put_u32(session.expiry_interval);
+        let subscriptions: Vec<_> = session.subscriptions.iter().collect();
+        value.put_array(&subscriptions, |buf, (filter, options)| {
+            buf.put_string(filter);
+            buf.put_u8(options.to_u8());
+        });
+        value
+    });
+    record(key, value)
+}
+
+/// Returns the record storing the next offset of a partition to deliver to a
+/// persistent session, or the tombstone forgetting it.
+pub(super) fn position_record(
+    client_id: &str,
+    partition: &TopicPartition,
+    offset: Option<u64>,
+) -> Record {
+    let mut key = key(POSITION_KEY);
+    key.put_string(client_id);
+    key.put_string(&partition.topic);
+    key.put_u32(partition.partition);
+    let value = offset.map(|offset| {
+        let mut value = Vec::new();
+        value.put_u16(VALUE_VERSION);
+        value.put_u64(offset);
+        value
+    });
+    record(key, value)
+}
+
+/// Decode a record of the MQTT state topic into the change it describes.
+pub(super) fn from_record(record: &Record) -> codec::Result<Change> {
+    let key = record.key.as_deref().ok_or(codec::Error::InvalidValue {
+        field: "MQTT state key",
+        value: -1,
+    })?;
+    let mut reader = Reader::new(key);
+    check_version(reader.get_u16()?, KEY_VERSION, "MQTT state key version")?;
+    let kind = reader.get_u8()?;
+    let mut value = match record.value.as_deref() {
+        Some(value) => {
+            let mut value = Reader::new(value);
+            check_version(value.get_u16()?, VALUE_VERSION, "MQTT state value version")?;
+            Some(value)
+        }
+        None => None,
+    };
+    match kind {
+        RETAINED_KEY => {
+            let name = reader.get_string()?;
+            let retained = match &mut value {
+                Some(value) => Some(Retained {
+                    qos: get_qos(value)?,
+                    payload: value.get_bytes()?,
+                    headers: value.get_array(|reader| {
+                        Ok(Header {
+                            key: reader.get_string()?,
+                            value: reader.get_bytes()?,
+                        })
+                    })?,
+                }),
+                None => None,
+            };
+            Ok(Change::Retained(name, retained))
+        }
+        SESSION_KEY => {
+            let client_id = reader.get_string()?;
+            let session = match &mut value {
+                Some(value) => {
+                    let expiry_interval = value.get_u32()?;
+                    let subscriptions = value.get_array(|reader| {
+                        let filter = reader.get_string()?;
+                        let options = reader.get_u8()?;
+                        let options = SubscriptionOptions::from_u8(options).map_err(|_| {
+                            codec::Error::InvalidValue {
+                                field: "subscription options",
+                                value: options as i64,
+                            }
+                        })?;
+                        Ok((filter, options))
+                    })?;
+                    Some(SessionRecord {
+                        expiry_interval,
+                        subscriptions: subscriptions.into_iter().collect(),
+                    })
+                }
+                None => None,
+            };
+            Ok(Change::Session(client_id, session))
+        }
+        POSITION_KEY => {
+            let client_id = reader.get_string()?;
+            let partition = TopicPartition::new(reader.get_string()?, reader.get_u32()?);
+            let offset = match &mut value {
+                Some(value) => Some(value.get_u64()?),
+                None => None,
+            };
+            Ok(Change::Position(client_id, partition, offset))
+        }
+        kind => Err(codec::Error::InvalidValue {
+            field: "MQTT state key type",
+            value: kind as i64,
+        }),
+    }
+}
+
+fn key(kind: u8) -> Vec<u8> {
+    let mut key = Vec::new();
+    key.put_u16(KEY_VERSION);
+    key.put_u8(kind);
+    key
+}
+
+fn record(key: Vec<u8>, value: Option<Vec<u8>>) -> Record {
+    Record {
+        key: Some(key),
+        value,
+        headers: Vec::new(),
+        timestamp: None,
+    }
+}
+
+fn get_qos(reader: &mut Reader) -> codec::Result<QoS> {
+    let qos = reader.get_u8()?;
+    QoS::from_u8(qos).map_err(|_| codec::Error::InvalidValue {
+        field: "QoS",
+        value: qos as i64,
+    })
+}
+
+fn check_version(version: u16, expected: u16, field: &'static str) -> codec::Result<()> {
+    if version != expected {
+        return Err(codec::Error::InvalidValue {
+            field,
+            value: version as i64,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let retained = Retained {
+            qos: QoS::AtLeastOnce,
+            payload: b"21.5".to_vec(),
+            headers: vec![Header {
+                key: String::from("unit"),
+                value: b"celsius".to_vec(),
+            }],
+        };
+        let session = SessionRecord {
+            expiry_interval: 60,
+            subscriptions: [
+                (
+                    String::from("sensors/#"),
+                    SubscriptionOptions::new(QoS::ExactlyOnce),
+                ),
+                (
+                    String::from("alerts"),
+                    SubscriptionOptions::new(QoS::AtMostOnce),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let partition = TopicPartition::new("sensors.kitchen", 1);
+
+        let changes = [
+            (
+                retained_record("sensors/kitchen", Some(&retained)),
+                Change::Retained(String::from("sensors/kitchen"), Some(retained.clone())),
+            ),
+            (
+                retained_record("sensors/kitchen", None),
+                Change::Retained(String::from("sensors/kitchen"), None),
+            ),
+            (
+                session_record("client", Some(&session)),
+                Change::Session(String::from("client"), Some(session.clone())),
+            ),
+            (
+                session_record("client", None),
+                Change::Session(String::from("client"), None),
+            ),
+            (
+                position_record("client", &partition, Some(7)),
+                Change::Position(String::from("client"), partition.clone(), Some(7)),
+            ),
+            (
+                position_record("client", &partition, None),
+                Change::Position(String::from("client"), partition.clone(), None),
+            ),
+        ];
+        for (record, change) in changes {
+            assert_eq!(change, from_record(&record).unwrap());
+        }
+
+        // Records of the same entity share a key, so compaction keeps the latest.
+        assert_eq!(
+            retained_record("a", Some(&retained)).key,
+            retained_record("a", None).key
+        );
+        assert_ne!(
+            session_record("a", None).key,
+            retained_record("a", None).key
+        );
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let mut record = session_record("client", None);
+        record.key = Some(vec![0, 0, 9]);
+        assert!(from_record(&record).is_err());
+        record.key = None;
+        assert!(from_record(&record).is_err());
+    }
+}
@@ -0,0 +1,298 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::record::Record;
+use crate::topic::{self, Partition, Topic, TopicConfig, TopicPartition, CLEANUP_POLICY};
+
+use super::error::{Error, Result};
+use super::filter;
+use super::state::{self, Change, Retained, SessionRecord};
+
+/// The internal topic retained messages and persistent sessions are stored in.
+pub const MQTT_TOPIC: &str = "__mqtt";
+
+/// The number of bytes read at a time while replaying the MQTT state topic.
+const REPLAY_BYTES: usize = 1024 * 1024;
+
+/// A persistent session as recovered from the MQTT state topic.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Persisted {
+    pub session: SessionRecord,
+    pub positions: BTreeMap<TopicPartition, u64>,
+}
+
+/// Durably stores retained messages along with the subscriptions of
+/// persistent sessions and how far each has been delivered its topics.
+///
+/// Every change is appended to the MQTT state topic and flushed before it
+/// takes effect, keyed so that compacting the topic keeps only the latest
+/// state of each retained message, session, and position. Retained messages
+/// are cached in memory, while sessions are handed to the session registry
+/// once when the store is opened.
+pub(super) struct Store {
+    log: Arc<Topic>,
+    retained: Mutex<BTreeMap<String, Retained>>,
+}
+
+impl Store {
+    /// Open the store, creating the MQTT state topic if it does not exist, and
+    /// return it along with the persistent sessions it holds.
+    pub(super) fn open(topics: &topic::Manager) -> Result<(Store, BTreeMap<String, Persisted>)> {
+        let log = match topics.get(MQTT_TOPIC) {
+            Ok(log) => log,
+            Err(topic::Error::NotFound { .. }) => {
+                let mut config = TopicConfig::new();
+                config.set(CLEANUP_POLICY, "compact")?;
+                topics.create(MQTT_TOPIC, 1, config)?
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let (retained, sessions) = replay(log.partition(0)?)?;
+        let store = Store {
+            log,
+            retained: Mutex::new(retained),
+        };
+        Ok((store, sessions))
+    }
+
+    /// Replace the message retained for the named MQTT topic, or clear it.
+    pub(super) fn retain(&self, name: &str, retained: Option<Retained>) -> Result<()> {
+        let mut cache = self.cache();
+        self.write(&[state::retained_record(name, retained.as_ref())])?;
+        match retained {
+            Some(retained) => cache.insert(name.to_owned(), retained),
+            None => cache.remove(name),
+        };
+        Ok(())
+    }
+
+    /// Returns the retained messages whose MQTT topic matches the supplied
+    /// filter, ordered by topic.
+    pub(super) fn retained(&self, topic_filter: &str) -> Vec<(String, Retained)> {
+        self.cache()
+            .iter()
+            .filter(|(name, _)| filter::matches(topic_filter, name))
+            .map(|(name, retained)| (name.clone(), retained.clone()))
+            .collect()
+    }
+
+    /// Save the subscriptions and expiry interval of a persistent session.
+    pub(super) fn save_session(&self, client_id: &str, session: &SessionRecord) -> Result<()> {
+        self.write(&[state::session_record(client_id, Some(session))])
+    }
+
+    /// Save the next offsets of the supplied partitions to deliver to a
+    /// persistent session.
+    pub(super) fn save_positions(
+        &self,
+        client_id: &str,
+        positions: &BTreeMap<TopicPartition, u64>,
+    ) -> Result<()> {
+        if positions.is_empty() {
+            return Ok(());
+        }
+        let records: Vec<Record> = positions
+            .iter()
+            .map(|(partition, offset)| state::position_record(client_id, partition, Some(*offset)))
+            .collect();
+        self.write(&records)
+    }
+
+    /// Forget the positions of a persistent session in partitions no longer
+    /// matching any of its subscriptions.
+    pub(super) fn forget_positions(
+        &self,
+        client_id: &str,
+        partitions: &[TopicPartition],
+    ) -> Result<()> {
+        let records: Vec<Record> = partitions
+            .iter()
+            .map(|partition| state::position_record(client_id, partition, None))
+            .collect();
+        self.write(&records)
+    }
+
+    /// End a persistent session, forgetting its subscriptions along with its
+    /// positions in the supplied partitions.
+    pub(super) fn delete_session<'a>(
+        &self,
+        client_id: &str,
+        partitions: impl IntoIterator<Item = &'a TopicPartition>,
+    ) -> Result<()> {
+        let mut records: Vec<Record> = partitions
+            .into_iter()
+            .map(|partition| state::position_record(client_id, partition, None))
+            .collect();
+        records.push(state::session_record(client_id, None));
+        self.write(&records)
+    }
+
+    fn write(&self, records: &[Record]) -> Result<()> {
+        let log = self.log.partition(0)?;
+        log.append(records)?;
+        log.flush()?;
+        Ok(())
+    }
+
+    fn cache(&self) -> MutexGuard<'_, BTreeMap<String, Retained>> {
+        self.retained
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+type Replayed = (BTreeMap<String, Retained>, BTreeMap<String, Persisted>);
+
+fn replay(log: &Partition) -> Result<Replayed> {
+    let mut retained = BTreeMap::new();
+    let mut sessions = BTreeMap::new();
+    let mut positions: BTreeMap<String, BTreeMap<TopicPartition, u64>> = BTreeMap::new();
+    let mut offset = log.start_offset();
+    while offset < log.next_offset() {
+        let records = log.read(offset, REPLAY_BYTES)?;
+        for record in &records {
+            let change = state::from_record(&record.record).map_err(|source| Error::Corrupt {
+                offset: record.offset,
+                source,
+            })?;
+            match change {
+                Change::Retained(name, Some(message)) => {
+                    retained.insert(name, message);
+                }
+                Change::Retained(name, None) => {
+                    retained.remove(&name);
+                }
+                Change::Session(client_id, Some(session)) => {
+                    sessions.insert(client_id, session);
+                }
+                Change::Session(client_id, None) => {
+                    sessions.remove(&client_id);
+                }
+                Change::Position(client_id, partition, Some(offset)) => {
+                    positions
+                        .entry(client_id)
+                        .or_default()
+                        .insert(partition, offset);
+                }
+                Change::Position(client_id, partition, None) => {
+                    if let Some(positions) = positions.get_mut(&client_id) {
+                        positions.remove(&partition);
+                    }
+                }
+            }
+        }
+        offset = match records.last() {
+            Some(record) => record.offset + 1,
+            None => break,
+        };
+    }
+
+    let sessions = sessions
+        .into_iter()
+        .map(|(client_id, session)| {
+            let positions = positions.remove(&client_id).unwrap_or_default();
+            (client_id, Persisted { session, positions })
+        })
+        .collect();
+    Ok((retained, sessions))
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+    use crate::mqtt::packet::{QoS, SubscriptionOptions};
+    use crate::storage::LogConfig;
+
+    fn open(dir: &std::path::Path) -> (Store, BTreeMap<String, Persisted>) {
+        let topics = topic::Manager::open(dir, LogConfig::default()).unwrap();
+        Store::open(&topics).unwrap()
+    }
+
+    fn retained(payload: &str) -> Retained {
+        Retained {
+            qos: QoS::AtLeastOnce,
+            payload: payload.as_bytes().to_vec(),
+            headers: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_retained() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store, _) = open(dir.path());
+        store
+            .retain("sensors/kitchen/temp", Some(retained("21")))
+            .unwrap();
+        store
+            .retain("sensors/hall/temp", Some(retained("19")))
+            .unwrap();
+        store
+            .retain("sensors/kitchen/temp", Some(retained("22")))
+            .unwrap();
+        store.retain("alerts", Some(retained("fire"))).unwrap();
+        store.retain("alerts", None).unwrap();
+
+        let names = |store: &Store, filter: &str| -> Vec<(String, Vec<u8>)> {
+            store
+                .retained(filter)
+                .into_iter()
+                .map(|(name, retained)| (name, retained.payload))
+                .collect()
+        };
+        let expected = vec![
+            (String::from("sensors/hall/temp"), b"19".to_vec()),
+            (String::from("sensors/kitchen/temp"), b"22".to_vec()),
+        ];
+        assert_eq!(expected, names(&store, "sensors/+/temp"));
+        assert!(names(&store, "alerts").is_empty());
+        drop(store);
+
+        let (store, sessions) = open(dir.path());
+        assert_eq!(expected, names(&store, "#"));
+        assert!(sessions.is_empty());
+    }
+
+    #[test]
+    fn test_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store, _) = open(dir.path());
+        let session = SessionRecord {
+            expiry_interval: u32::MAX,
+            subscriptions: [(
+                String::from("sensors/#"),
+                SubscriptionOptions::new(QoS::AtLeastOnce),
+            )]
+            .into_iter()
+            .collect(),
+        };
+        let p0 = TopicPartition::new("sensors.kitchen", 0);
+        let p1 = TopicPartition::new("sensors.hall", 0);
+        store.save_session("kept", &session).unwrap();
+        store.save_session("ended", &session).unwrap();
+        for client_id in ["kept", "ended"] {
+            let positions = [(p0.clone(), 3), (p1.clone(), 5)].into_iter().collect();
+            store.save_positions(client_id, &positions).unwrap();
+        }
+        store
+            .save_positions("kept", &[(p0.clone(), 4)].into_iter().collect())
+            .unwrap();
+        store.delete_session("ended", [&p0, &p1]).unwrap();
+        drop(store);
+
+        let (_, sessions) = open(dir.path());
+        assert_eq!(vec!["kept"], sessions.keys().collect::<Vec<_>>());
+        let kept = &sessions["kept"];
+        assert_eq!(session, kept.session);
+        assert_eq!(
+            vec![(&p1, &5), (&p0, &4)],
+            kept.positions.iter().collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,125 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::codec::{self, Reader, Writer};
+use crate::record::Record;
+use crate::topic::TopicPartition;
+
+/// The current version of the commit record key format.
+const KEY_VERSION: u16 = 0;
+/// The current version of the commit record value format.
+const VALUE_VERSION: u16 = 0;
+
+#[derive(Debug, Clone, PartialEq)]
+/// A single committed offset as stored in the offsets topic.
+///
+/// Commits are keyed by group and partition so that compacting the offsets
+/// topic retains only the latest commit for each. A commit without an offset
+/// is a tombstone removing the group's offset for the partition.
+pub(super) struct Commit {
+    pub(super) group: String,
+    pub(super) partition: TopicPartition,
+    pub(super) offset: Option<u64>,
+}
+
+impl Commit {
+    pub(super) fn to_record(&self) -> Record {
+        let mut key = Vec::new();
+        key.put_u16(KEY_VERSION);
+        key.put_string(&self.group);
+        key.put_string(&self.partition.topic);
+        key.put_u32(self.partition.partition);
+
+        let value = self.offset.map(|offset| {
+            let mut value = Vec::new();
+            value.put_u16(VALUE_VERSION);
+            value.put_u64(offset);
+            value
+        });
+        Record {
+            key: Some(key),
+            value,
+            headers: Vec::new(),
+            timestamp: None,
+        }
+    }
+
+    pub(super) fn from_record(record: &Record) -> codec::Result<Commit> {
+        let key = record.key.as_deref().ok_or(codec::Error::InvalidValue {
+            field: "offset commit key",
+            value: -1,
+        })?;
+        let mut reader = Reader::new(key);
+        check_version(reader.get_u16()?, KEY_VERSION, "offset commit key version")?;
+        let group = reader.get_string()?;
+        let partition = TopicPartition {
+            topic: reader.get_string()?,
+            partition: reader.get_u32()?,
+        };
+
+        let offset = match record.value.as_deref() {
+            Some(value) => {
+                let mut reader = Reader::new(value);
+                check_version(
+                    reader.get_u16()?,
+                    VALUE_VERSION,
+                    "offset commit value version",
+                )?;
+                Some(reader.get_u64()?)
+            }
+            None => None,
+        };
+        Ok(Commit {
+            group,
+            partition,
+            offset,
+        })
+    }
+}
+
+fn check_version(version: u16, expected: u16, field: &'static str) -> codec::Result<()> {
+    if version != expected {
+        return Err(codec::Error::InvalidValue {
+            field,
+            value: version as i64,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for offset in [Some(42), None] {
+            let commit = Commit {
+                group: String::from("group"),
+                partition: TopicPartition::new("events", 3),
+                offset,
+            };
+            let record = commit.to_record();
+            assert_eq!(offset.is_none(), record.value.is_none());
+            assert_eq!(commit, Commit::from_record(&record).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Commit::from_record(&Record::new("value")).is_err());
+
+        let mut record = Record::default().with_key(vec![0, 9]);
+        assert!(matches!(
+            Commit::from_record(&record),
+            Err(codec::Error::InvalidValue { value: 9, .. })
+        ));
+
+        record.key = Some(vec![0, 0]);
+        assert!(matches!(
+            Commit::from_record(&record),
+            Err(codec::Error::UnexpectedEof { .. })
+        ));
+    }
+}
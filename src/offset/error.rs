@@ -0,0 +1,36 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::result;
+
+use thiserror::Error;
+
+use crate::{codec, topic};
+
+/// Custom Result wrapper to simplify usage.
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents errors committing or loading consumer offsets.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// Handles errors reading or writing the offsets topic, or resolving the
+    /// partitions offsets are committed for.
+    #[error(transparent)]
+    Topic(#[from] topic::Error),
+    /// Handles commit records in the offsets topic that could not be decoded.
+    #[error("failed to decode offset commit at offset {offset}: {source}")]
+    Corrupt {
+        /// The offset of the commit record that failed to decode.
+        offset: u64,
+        /// The initial error cause.
+        source: codec::Error,
+    },
+    /// Handles group ids that can not be used.
+    #[error("invalid group id '{group}': {reason}")]
+    InvalidGroup {
+        /// The offending group id.
+        group: String,
+        /// Why the group id was rejected.
+        reason: &'static str,
+    },
+}
@@ -0,0 +1,9 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod commit;
+mod error;
+mod store;
+
+pub use self::error::{Error, Result};
+pub use self::store::{OffsetReset, Store, OFFSETS_TOPIC};
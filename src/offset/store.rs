@@ -0,0 +1,308 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::record::Record;
+use crate::topic::{self, Partition, Topic, TopicConfig, TopicPartition, CLEANUP_POLICY};
+
+use super::commit::Commit;
+use super::error::{Error, Result};
+
+/// The internal topic committed offsets are stored in.
+pub const OFFSETS_TOPIC: &str = "__consumer_offsets";
+
+/// The number of bytes read at a time while replaying the offsets topic.
+const REPLAY_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Where to move a group's committed offset when resetting it.
+pub enum OffsetReset {
+    /// The first offset retained by the partition.
+    Earliest,
+    /// The offset that will be assigned to the partition's next record.
+    Latest,
+    /// The first offset with a timestamp, in milliseconds since the epoch, at or
+    /// after the supplied timestamp.
+    Timestamp(i64),
+}
+
+type Committed = HashMap<String, BTreeMap<TopicPartition, u64>>;
+
+/// Durably stores the offsets consumer groups have committed.
+///
+/// Every commit request is appended to the offsets topic as a single batch and
+/// flushed before it is acknowledged, so a request is either recovered in full
+/// after a crash or not at all. The latest offsets are cached in memory and
+/// rebuilt by replaying the offsets topic on open.
+pub struct Store {
+    topics: Arc<topic::Manager>,
+    offsets: Arc<Topic>,
+    committed: Mutex<Committed>,
+}
+
+impl Store {
+    /// Open the offset store, creating the offsets topic if it does not exist.
+    pub fn open(topics: Arc<topic::Manager>) -> Result<Store> {
+        let offsets = match topics.get(OFFSETS_TOPIC) {
+            Ok(offsets) => offsets,
+            Err(topic::Error::NotFound { .. }) => {
+                // Only the latest commit for each group partition is needed, and
+                // commits must outlive any broker wide retention period.
+                let mut config = TopicConfig::new();
+                config.set(CLEANUP_POLICY, "compact")?;
+                topics.create(OFFSETS_TOPIC, 1, config)?
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let committed = replay(offsets.partition(0)?)?;
+        Ok(Store {
+            topics,
+            offsets,
+            committed: Mutex::new(committed),
+        })
+    }
+
+    /// Returns the offset last committed by the supplied group for a partition.
+    pub fn committed(&self, group: &str, partition: &TopicPartition) -> Option<u64> {
+        self.cache()
+            .get(group)
+            .and_then(|offsets| offsets.get(partition))
+            .copied()
+    }
+
+    /// Returns the offsets committed by the supplied group for the listed
+    /// partitions, or for every partition if none are listed. Partitions the
+    /// group has not committed an offset for are omitted.
+    pub fn fetch(&self, group: &str, partitions: &[TopicPartition]) -> Vec<(TopicPartition, u64)> {
+        let committed = self.cache();
+        let offsets = match committed.get(group) {
+            Some(offsets) => offsets,
+            None => return Vec::new(),
+        };
+        if partitions.is_empty() {
+            return offsets
+                .iter()
+                .map(|(partition, offset)| (partition.clone(), *offset))
+                .collect();
+        }
+        partitions
+            .iter()
+            .filter_map(|partition| {
+                offsets
+                    .get(partition)
+                    .map(|offset| (partition.clone(), *offset))
+            })
+            .collect()
+    }
+
+    /// Commit the supplied offsets on behalf of a group. Either every offset is
+    /// committed or, if any partition does not exist, none are.
+    pub fn commit(&self, group: &str, offsets: Vec<(TopicPartition, u64)>) -> Result<()> {
+        validate_group(group)?;
+        for (partition, _) in &offsets {
+            self.topics
+                .get(&partition.topic)?
+                .partition(partition.partition)?;
+        }
+        if offsets.is_empty() {
+            return Ok(());
+        }
+
+        let records: Vec<Record> = offsets
+            .iter()
+            .map(|(partition, offset)| {
+                Commit {
+                    group: group.to_owned(),
+                    partition: partition.clone(),
+                    offset: Some(*offset),
+                }
+                .to_record()
+            })
+            .collect();
+
+        // Hold the cache lock across the append so the cache and the offsets topic
+        // observe commits in the same order.
+        let mut committed = self.cache();
+        let log = self.offsets.partition(0)?;
+        log.append(&records)?;
+        log.flush()?;
+
+        committed
+            .entry(group.to_owned())
+            .or_default()
+            .extend(offsets);
+        Ok(())
+    }
+
+    /// Move the supplied group's offsets for the listed partitions, or for every
+    /// partition it has committed if none are listed, and return the new offsets.
+    pub fn reset(
+        &self,
+        group: &str,
+        partitions: &[TopicPartition],
+        reset: OffsetReset,
+    ) -> Result<Vec<(TopicPartition, u64)>> {
+        let partitions = if partitions.is_empty() {
+            self.fetch(group, &[])
+                .into_iter()
+                .map(|(partition, _)| partition)
+                .collect()
+        } else {
+            partitions.to_vec()
+        };
+
+        let offsets = partitions
+            .into_iter()
+            .map(|tp| {
+                let topic = self.topics.get(&tp.topic)?;
+                let partition = topic.partition(tp.partition)?;
+                let offset = match reset {
+                    OffsetReset::Earliest => partition.start_offset(),
+                    OffsetReset::Latest => partition.next_offset(),
+                    OffsetReset::Timestamp(timestamp) => {
+                        partition.offset_for_timestamp(timestamp)?
+                    }
+                };
+                Ok((tp, offset))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        self.commit(group, offsets.clone())?;
+        Ok(offsets)
+    }
+
+    fn cache(&self) -> MutexGuard<'_, Committed> {
+        self.committed
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn replay(log: &Partition) -> Result<Committed> {
+    let mut committed = Committed::new();
+    let mut offset = log.start_offset();
+    while offset < log.next_offset() {
+        let records = log.read(offset, REPLAY_BYTES)?;
+        for record in &records {
+            let commit = Commit::from_record(&record.record).map_err(|source| Error::Corrupt {
+                offset: record.offset,
+                source,
+            })?;
+            let offsets = committed.entry(commit.group).or_default();
+            match commit.offset {
+                Some(value) => offsets.insert(commit.partition, value),
+                None => offsets.remove(&commit.partition),
+            };
+        }
+        offset = match records.last() {
+            Some(record) => record.offset + 1,
+            None => break,
+        };
+    }
+    committed.retain(|_, offsets| !offsets.is_empty());
+    Ok(committed)
+}
+
+fn validate_group(group: &str) -> Result<()> {
+    if group.is_empty() {
+        return Err(Error::InvalidGroup {
+            group: group.to_owned(),
+            reason: "group ids must not be empty",
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+    use crate::storage::LogConfig;
+
+    fn open(dir: &std::path::Path) -> (Arc<topic::Manager>, Store) {
+        let topics = Arc::new(topic::Manager::open(dir, LogConfig::default()).unwrap());
+        if topics.get("events").is_err() {
+            topics.create("events", 2, TopicConfig::new()).unwrap();
+        }
+        let store = Store::open(topics.clone()).unwrap();
+        (topics, store)
+    }
+
+    #[test]
+    fn test_commit_survives_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, store) = open(dir.path());
+        let p0 = TopicPartition::new("events", 0);
+        let p1 = TopicPartition::new("events", 1);
+
+        store
+            .commit("group", vec![(p0.clone(), 5), (p1.clone(), 7)])
+            .unwrap();
+        store.commit("group", vec![(p0.clone(), 6)]).unwrap();
+        store.commit("other", vec![(p1.clone(), 1)]).unwrap();
+        drop(store);
+
+        let (_, store) = open(dir.path());
+        assert_eq!(Some(6), store.committed("group", &p0));
+        assert_eq!(vec![(p0, 6), (p1.clone(), 7)], store.fetch("group", &[]));
+        assert_eq!(vec![(p1.clone(), 1)], store.fetch("other", &[p1]));
+        assert!(store.fetch("missing", &[]).is_empty());
+    }
+
+    #[test]
+    fn test_commit_is_atomic() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, store) = open(dir.path());
+        let p0 = TopicPartition::new("events", 0);
+
+        let err = store
+            .commit(
+                "group",
+                vec![(p0.clone(), 5), (TopicPartition::new("events", 9), 1)],
+            )
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Topic(topic::Error::PartitionNotFound { .. })
+        ));
+        assert_eq!(None, store.committed("group", &p0));
+
+        let err = store.commit("", vec![(p0, 5)]).unwrap_err();
+        assert!(matches!(err, Error::InvalidGroup { .. }));
+    }
+
+    #[test]
+    fn test_reset() {
+        let dir = tempfile::tempdir().unwrap();
+        let (topics, store) = open(dir.path());
+        let partition = topics.get("events").unwrap();
+        let partition = partition.partition(0).unwrap();
+        partition
+            .append(&[Record::new("a"), Record::new("b")])
+            .unwrap();
+        let p0 = TopicPartition::new("events", 0);
+
+        let reset = store
+            .reset("group", std::slice::from_ref(&p0), OffsetReset::Latest)
+            .unwrap();
+        assert_eq!(vec![(p0.clone(), 2)], reset);
+        assert_eq!(Some(2), store.committed("group", &p0));
+
+        let reset = store.reset("group", &[], OffsetReset::Earliest).unwrap();
+        assert_eq!(vec![(p0.clone(), 0)], reset);
+
+        store
+            .reset("group", &[], OffsetReset::Timestamp(i64::MAX))
+            .unwrap();
+        assert_eq!(Some(2), store.committed("group", &p0));
+        store
+            .reset("group", &[], OffsetReset::Timestamp(0))
+            .unwrap();
+        assert_eq!(Some(0), store.committed("group", &p0));
+    }
+}
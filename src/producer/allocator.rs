@@ -0,0 +1,144 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::error::{Error, Result};
+
+/// The file, within the data directory, the allocated producer ids are persisted to.
+pub const PRODUCER_IDS_FILE: &str = "producer_ids.json";
+
+/// The number of ids reserved with each write to the allocation state.
+const BLOCK_SIZE: u64 = 1000;
+
+/// The persisted allocation state, every id below `next_block` may have been assigned.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Persisted {
+    next_block: u64,
+}
+
+#[derive(Debug, Default)]
+struct Block {
+    loaded: bool,
+    next: u64,
+    end: u64,
+}
+
+/// Assigns idempotent producer ids which are never reused, even across restarts.
+///
+/// Ids are reserved in blocks, persisting the end of each block before any id
+/// within it is assigned, so a restart skips whatever remained of the last
+/// block. The id of the assigning node makes up the upper 32 bits of each id,
+/// keeping ids unique across a cluster without any coordination between nodes.
+pub struct Allocator {
+    dir: PathBuf,
+    block: Mutex<Block>,
+}
+
+impl Allocator {
+    /// Create an allocator persisting its state in the supplied data directory.
+    /// The state is only loaded once the first id is assigned.
+    pub fn new(dir: impl Into<PathBuf>) -> Allocator {
+        Allocator {
+            dir: dir.into(),
+            block: Mutex::new(Block::default()),
+        }
+    }
+
+    /// Assign a new producer id on behalf of the supplied node.
+    ///
+    /// ```
+    /// # use librift::producer::Allocator;
+    /// let dir = tempfile::tempdir().unwrap();
+    /// let allocator = Allocator::new(dir.path());
+    /// assert_eq!(0, allocator.allocate(0).unwrap());
+    /// assert_eq!((2 << 32) + 1, allocator.allocate(2).unwrap());
+    /// ```
+    pub fn allocate(&self, node_id: u32) -> Result<u64> {
+        let mut block = self.block();
+        if !block.loaded {
+            let persisted = load(&self.dir)?;
+            block.next = persisted.next_block;
+            block.end = persisted.next_block;
+            block.loaded = true;
+        }
+        if block.next > u32::MAX as u64 {
+            return Err(Error::Exhausted { node_id });
+        }
+        if block.next == block.end {
+            let next_block = block.end + BLOCK_SIZE;
+            store(&self.dir, Persisted { next_block })?;
+            block.end = next_block;
+        }
+
+        let id = block.next;
+        block.next += 1;
+        Ok(((node_id as u64) << 32) | id)
+    }
+
+    fn block(&self) -> MutexGuard<'_, Block> {
+        self.block
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn load(dir: &Path) -> Result<Persisted> {
+    let path = dir.join(PRODUCER_IDS_FILE);
+    let raw = match fs::read(&path) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Persisted::default()),
+        Err(e) => return Err(Error::io(&path, e)),
+    };
+    serde_json::from_slice(&raw).map_err(|source| Error::State { path, source })
+}
+
+fn store(dir: &Path, persisted: Persisted) -> Result<()> {
+    let path = dir.join(PRODUCER_IDS_FILE);
+    let tmp = dir.join(format!("{}.tmp", PRODUCER_IDS_FILE));
+    let raw = serde_json::to_vec(&persisted).map_err(|source| Error::State {
+        path: path.clone(),
+        source,
+    })?;
+
+    let mut file = File::create(&tmp).map_err(|e| Error::io(&tmp, e))?;
+    file.write_all(&raw).map_err(|e| Error::io(&tmp, e))?;
+    file.sync_all().map_err(|e| Error::io(&tmp, e))?;
+    fs::rename(&tmp, &path).map_err(|e| Error::io(&path, e))
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allocate() {
+        let dir = tempfile::tempdir().unwrap();
+        let allocator = Allocator::new(dir.path());
+        assert_eq!(0, allocator.allocate(0).unwrap());
+        assert_eq!(1, allocator.allocate(0).unwrap());
+        assert_eq!((3 << 32) | 2, allocator.allocate(3).unwrap());
+
+        // A restart skips the remainder of the reserved block.
+        let allocator = Allocator::new(dir.path());
+        assert_eq!(BLOCK_SIZE, allocator.allocate(0).unwrap());
+        for _ in 1..BLOCK_SIZE {
+            allocator.allocate(0).unwrap();
+        }
+        assert_eq!(BLOCK_SIZE * 2, allocator.allocate(0).unwrap());
+        assert_eq!(
+            Persisted {
+                next_block: BLOCK_SIZE * 3
+            },
+            load(dir.path()).unwrap()
+        );
+    }
+}
@@ -0,0 +1,48 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::time::Duration;
+
+use structopt::StructOpt;
+
+#[derive(Debug, Clone, StructOpt)]
+/// Rift transaction coordinator configuration.
+pub struct Config {
+    #[structopt(
+        long = "transaction-max-timeout-ms",
+        env = "RIFT_TRANSACTION_MAX_TIMEOUT_MS",
+        help = "The largest transaction timeout a producer may request.",
+        long_help = "Sets the upper bound in milliseconds on the timeout transactional producers may request, after which an ongoing transaction is aborted.",
+        default_value = "900000",
+        takes_value = true
+    )]
+    /// Define the maximum transaction timeout in milliseconds.
+    pub max_timeout_ms: u64,
+
+    #[structopt(
+        long = "transaction-check-interval-ms",
+        env = "RIFT_TRANSACTION_CHECK_INTERVAL_MS",
+        help = "How often to check for timed out transactions.",
+        long_help = "Sets the interval in milliseconds at which timed out transactions are aborted, and markers that failed to be written are retried.",
+        default_value = "1000",
+        takes_value = true
+    )]
+    /// Define the transaction timeout check interval in milliseconds.
+    pub check_interval_ms: u64,
+}
+
+impl Config {
+    /// Returns the interval between transaction timeout checks.
+    pub fn check_interval(&self) -> Duration {
+        Duration::from_millis(self.check_interval_ms)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_timeout_ms: 900000,
+            check_interval_ms: 1000,
+        }
+    }
+}
@@ -0,0 +1,545 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::record::ControlMarker;
+use crate::topic::{self, Partition, Topic, TopicConfig, TopicPartition, CLEANUP_POLICY};
+
+use super::config::Config;
+use super::error::{Error, Result};
+use super::metrics::metrics;
+use super::state::{TxnMetadata, TxnState};
+
+/// The internal topic the state of every transactional id is stored in.
+pub const TRANSACTION_STATE_TOPIC: &str = "__transaction_state";
+
+/// The number of bytes read at a time while replaying the transaction state topic.
+const REPLAY_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The markers that must be written to complete a transaction.
+pub struct Markers {
+    /// The transactional id of the producer that wrote the transaction.
+    pub transactional_id: String,
+    /// The id of the producer that wrote the transaction.
+    pub producer_id: u64,
+    /// The epoch to write the markers with.
+    pub epoch: u16,
+    /// Whether the transaction is committed or aborted.
+    pub marker: ControlMarker,
+    /// The partitions the transaction wrote to.
+    pub partitions: Vec<TopicPartition>,
+    /// When the transaction was prepared, in milliseconds since the epoch,
+    /// identifying this attempt at completing it.
+    pub prepared_ms: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The result of initializing a transactional producer.
+pub struct Initialized {
+    /// The producer id assigned to the transactional id.
+    pub producer_id: u64,
+    /// The producer's new epoch, which fences off any earlier instance of it.
+    pub epoch: u16,
+    /// The markers aborting the transaction an earlier instance left ongoing.
+    pub aborted: Option<Markers>,
+}
+
+/// Tracks the transactions of every transactional producer.
+///
+/// Each state change is appended to the transaction state topic and flushed
+/// before it is acknowledged, and the latest state of every transactional id is
+/// cached in memory and rebuilt by replaying the topic on open. Writing the
+/// markers that complete a transaction is left to the caller, which reports
+/// back through [Coordinator::complete] once they are durable.
+pub struct Coordinator {
+    logger: slog::Logger,
+    cfg: Config,
+    log: Arc<Topic>,
+    txns: Mutex<BTreeMap<String, TxnMetadata>>,
+}
+
+impl Coordinator {
+    /// Open the coordinator, creating the transaction state topic if it does not exist.
+    pub fn open(
+        logger: slog::Logger,
+        cfg: Config,
+        topics: Arc<topic::Manager>,
+    ) -> Result<Coordinator> {
+        let log = match topics.get(TRANSACTION_STATE_TOPIC) {
+            Ok(log) => log,
+            Err(topic::Error::NotFound { .. }) => {
+                let mut config = TopicConfig::new();
+                config.set(CLEANUP_POLICY, "compact")?;
+                topics.create(TRANSACTION_STATE_TOPIC, 1, config)?
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let txns = replay(log.partition(0)?)?;
+        Ok(Coordinator {
+            logger,
+            cfg,
+            log,
+            txns: Mutex::new(txns),
+        })
+    }
+
+    /// Returns the configuration of this coordinator.
+    pub fn config(&self) -> &Config {
+        &self.cfg
+    }
+
+    /// Returns the state of the supplied transactional id, if it is known.
+    pub fn state(&self, transactional_id: &str) -> Option<TxnState> {
+        self.txns().get(transactional_id).map(|txn| txn.state)
+    }
+
+    /// Initialize the producer of a transactional id as of `now`, in milliseconds
+    /// since the epoch, bumping its epoch to fence off earlier instances. New
+    /// transactional ids, and those whose epoch is exhausted, are assigned a
+    /// producer id by `allocate`. A transaction left ongoing is aborted.
+    pub fn init<F>(
+        &self,
+        transactional_id: &str,
+        timeout_ms: u64,
+        now: i64,
+        allocate: F,
+    ) -> Result<Initialized>
+    where
+        F: FnOnce() -> Result<u64>,
+    {
+        validate_transactional_id(transactional_id)?;
+        if timeout_ms == 0 || timeout_ms > self.cfg.max_timeout_ms {
+            return Err(Error::InvalidTimeout {
+                timeout_ms,
+                max_ms: self.cfg.max_timeout_ms,
+            });
+        }
+
+        let mut txns = self.txns();
+        let mut aborted = None;
+        let mut txn = match txns.get(transactional_id).cloned() {
+            None => TxnMetadata {
+                transactional_id: transactional_id.to_owned(),
+                producer_id: allocate()?,
+                epoch: 0,
+                timeout_ms,
+                state: TxnState::Empty,
+                partitions: BTreeSet::new(),
+                updated_ms: now,
+            },
+            Some(TxnMetadata {
+                state: TxnState::PrepareCommit | TxnState::PrepareAbort,
+                ..
+            }) => {
+                return Err(Error::ConcurrentTransactions {
+                    transactional_id: transactional_id.to_owned(),
+                })
+            }
+            Some(mut txn) if txn.state == TxnState::Ongoing => {
+                txn.epoch = txn.epoch.saturating_add(1);
+                txn.state = TxnState::PrepareAbort;
+                txn.updated_ms = now;
+                aborted = Some(markers(&txn));
+                info!(self.logger, "Aborted ongoing transaction of reinitialized producer."; "transactional_id" => transactional_id, "producer_id" => txn.producer_id, "epoch" => txn.epoch);
+                metrics()
+                    .aborted_transactions
+                    .with_label_values(&["fenced"])
+                    .inc();
+                txn
+            }
+            Some(mut txn) => {
+                match txn.epoch.checked_add(1) {
+                    Some(epoch) => txn.epoch = epoch,
+                    None => {
+                        txn.producer_id = allocate()?;
+                        txn.epoch = 0;
+                    }
+                }
+                txn
+            }
+        };
+        txn.timeout_ms = timeout_ms;
+
+        self.persist(&mut txns, txn.clone())?;
+        Ok(Initialized {
+            producer_id: txn.producer_id,
+            epoch: txn.epoch,
+            aborted,
+        })
+    }
+
+    /// Add partitions to the producer's current transaction, beginning one as of
+    /// `now`, in milliseconds since the epoch, if none is in progress.
+    pub fn add_partitions(
+        &self,
+        transactional_id: &str,
+        producer_id: u64,
+        epoch: u16,
+        partitions: Vec<TopicPartition>,
+        now: i64,
+    ) -> Result<()> {
+        let mut txns = self.txns();
+        let mut txn = check_producer(&txns, transactional_id, producer_id, epoch)?.clone();
+        match txn.state {
+            TxnState::PrepareCommit | TxnState::PrepareAbort => {
+                return Err(Error::ConcurrentTransactions {
+                    transactional_id: transactional_id.to_owned(),
+                })
+            }
+            TxnState::Empty => {
+                txn.state = TxnState::Ongoing;
+                txn.updated_ms = now;
+            }
+            TxnState::Ongoing => {
+                if partitions.iter().all(|p| txn.partitions.contains(p)) {
+                    return Ok(());
+                }
+            }
+        }
+        txn.partitions.extend(partitions);
+        self.persist(&mut txns, txn)
+    }
+
+    /// End the producer's current transaction as of `now`, in milliseconds since
+    /// the epoch, returning the markers that must be written to complete it.
+    pub fn end(
+        &self,
+        transactional_id: &str,
+        producer_id: u64,
+        epoch: u16,
+        commit: bool,
+        now: i64,
+    ) -> Result<Markers> {
+        let mut txns = self.txns();
+        let mut txn = check_producer(&txns, transactional_id, producer_id, epoch)?.clone();
+        match (txn.state, commit) {
+            (TxnState::Ongoing, _) => (),
+            (TxnState::PrepareCommit, true) | (TxnState::PrepareAbort, false) => {
+                return Err(Error::ConcurrentTransactions {
+                    transactional_id: transactional_id.to_owned(),
+                })
+            }
+            (state, _) => {
+                return Err(Error::InvalidTxnState {
+                    transactional_id: transactional_id.to_owned(),
+                    operation: if commit { "commit" } else { "abort" },
+                    state,
+                })
+            }
+        }
+
+        txn.state = if commit {
+            TxnState::PrepareCommit
+        } else {
+            info!(self.logger, "Producer aborted transaction."; "transactional_id" => transactional_id, "producer_id" => producer_id, "epoch" => epoch);
+            metrics()
+                .aborted_transactions
+                .with_label_values(&["client"])
+                .inc();
+            TxnState::PrepareAbort
+        };
+        txn.updated_ms = now;
+        let markers = markers(&txn);
+        self.persist(&mut txns, txn)?;
+        Ok(markers)
+    }
+
+    /// Record that the supplied markers have been written, completing their
+    /// transaction as of `now`, in milliseconds since the epoch. Markers from
+    /// an earlier attempt at a transaction that has since completed are ignored.
+    pub fn complete(&self, markers: &Markers, now: i64) -> Result<()> {
+        let mut txns = self.txns();
+        let mut txn = match txns.get(&markers.transactional_id) {
+            Some(txn) if txn.updated_ms == markers.prepared_ms => txn.clone(),
+            _ => return Ok(()),
+        };
+        let prepared = match markers.marker {
+            ControlMarker::Commit => TxnState::PrepareCommit,
+            ControlMarker::Abort => TxnState::PrepareAbort,
+        };
+        if txn.state != prepared {
+            return Ok(());
+        }
+
+        txn.state = TxnState::Empty;
+        txn.partitions.clear();
+        txn.updated_ms = now;
+        self.persist(&mut txns, txn)
+    }
+
+    /// Abort every transaction that has outlived its timeout as of `now`, in
+    /// milliseconds since the epoch, bumping its producer's epoch so it can no
+    /// longer write to the transaction. Returns the markers of the aborted
+    /// transactions, along with those of transactions whose markers have not
+    /// been written within a check interval of them being prepared.
+    pub fn expire(&self, now: i64) -> Vec<Markers> {
+        let mut txns = self.txns();
+        let retry_before = now.saturating_sub(self.cfg.check_interval_ms as i64);
+        let expired: Vec<TxnMetadata> = txns
+            .values()
+            .filter(|txn| {
+                txn.state == TxnState::Ongoing
+                    && now.saturating_sub(txn.updated_ms) >= txn.timeout_ms as i64
+            })
+            .cloned()
+            .collect();
+        let mut pending: Vec<Markers> = txns
+            .values()
+            .filter(|txn| {
+                matches!(txn.state, TxnState::PrepareCommit | TxnState::PrepareAbort)
+                    && txn.updated_ms <= retry_before
+            })
+            .map(markers)
+            .collect();
+
+        for mut txn in expired {
+            txn.epoch = txn.epoch.saturating_add(1);
+            txn.state = TxnState::PrepareAbort;
+            txn.updated_ms = now;
+            let aborted = markers(&txn);
+            if let Err(err) = self.persist(&mut txns, txn) {
+                error!(self.logger, "Failed to abort timed out transaction."; "transactional_id" => &aborted.transactional_id, "error" => err.to_string());
+                continue;
+            }
+            warn!(self.logger, "Aborted transaction after timeout."; "transactional_id" => &aborted.transactional_id, "producer_id" => aborted.producer_id, "epoch" => aborted.epoch);
+            metrics()
+                .aborted_transactions
+                .with_label_values(&["timeout"])
+                .inc();
+            pending.push(aborted);
+        }
+        pending
+    }
+
+    /// Append the supplied state to the transaction state topic, then cache it.
+    fn persist(&self, txns: &mut BTreeMap<String, TxnMetadata>, txn: TxnMetadata) -> Result<()> {
+        let log = self.log.partition(0)?;
+        log.append(&[txn.to_record()])?;
+        log.flush()?;
+        txns.insert(txn.transactional_id.clone(), txn);
+        Ok(())
+    }
+
+    fn txns(&self) -> MutexGuard<'_, BTreeMap<String, TxnMetadata>> {
+        self.txns
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn markers(txn: &TxnMetadata) -> Markers {
+    Markers {
+        transactional_id: txn.transactional_id.clone(),
+        producer_id: txn.producer_id,
+        epoch: txn.epoch,
+        marker: match txn.state {
+            TxnState::PrepareCommit => ControlMarker::Commit,
+            _ => ControlMarker::Abort,
+        },
+        partitions: txn.partitions.iter().cloned().collect(),
+        prepared_ms: txn.updated_ms,
+    }
+}
+
+fn check_producer<'a>(
+    txns: &'a BTreeMap<String, TxnMetadata>,
+    transactional_id: &str,
+    producer_id: u64,
+    epoch: u16,
+) -> Result<&'a TxnMetadata> {
+    let txn = match txns.get(transactional_id) {
+        Some(txn) if txn.producer_id == producer_id => txn,
+        _ => {
+            return Err(Error::InvalidProducerIdMapping {
+                transactional_id: transactional_id.to_owned(),
+                producer_id,
+            })
+        }
+    };
+    if epoch != txn.epoch {
+        return Err(Error::ProducerFenced {
+            producer_id,
+            epoch,
+            current: txn.epoch,
+        });
+    }
+    Ok(txn)
+}
+
+fn validate_transactional_id(transactional_id: &str) -> Result<()> {
+    if transactional_id.is_empty() {
+        return Err(Error::InvalidTransactionalId {
+            transactional_id: transactional_id.to_owned(),
+            reason: "transactional ids must not be empty",
+        });
+    }
+    Ok(())
+}
+
+fn replay(log: &Partition) -> Result<BTreeMap<String, TxnMetadata>> {
+    let mut txns = BTreeMap::new();
+    let mut offset = log.start_offset();
+    while offset < log.next_offset() {
+        let records = log.read(offset, REPLAY_BYTES)?;
+        for record in &records {
+            let txn =
+                TxnMetadata::from_record(&record.record).map_err(|source| Error::Corrupt {
+                    offset: record.offset,
+                    source,
+                })?;
+            txns.insert(txn.transactional_id.clone(), txn);
+        }
+        match records.last() {
+            Some(last) => offset = last.offset + 1,
+            None => break,
+        }
+    }
+    Ok(txns)
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+    use crate::storage::LogConfig;
+
+    fn coordinator(topics: &Arc<topic::Manager>) -> Coordinator {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let cfg = Config {
+            max_timeout_ms: 1000,
+            check_interval_ms: 10,
+        };
+        Coordinator::open(logger, cfg, topics.clone()).unwrap()
+    }
+
+    fn partitions() -> Vec<TopicPartition> {
+        vec![
+            TopicPartition::new("events", 0),
+            TopicPartition::new("events", 1),
+        ]
+    }
+
+    #[test]
+    fn test_lifecycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let topics = Arc::new(topic::Manager::open(dir.path(), LogConfig::default()).unwrap());
+        let coordinator = coordinator(&topics);
+
+        assert!(matches!(
+            coordinator.init("txn", 5000, 0, || Ok(7)),
+            Err(Error::InvalidTimeout { .. })
+        ));
+        assert!(matches!(
+            coordinator.init("", 500, 0, || Ok(7)),
+            Err(Error::InvalidTransactionalId { .. })
+        ));
+        let init = coordinator.init("txn", 500, 0, || Ok(7)).unwrap();
+        assert_eq!((7, 0, None), (init.producer_id, init.epoch, init.aborted));
+        assert_eq!(Some(TxnState::Empty), coordinator.state("txn"));
+
+        assert!(matches!(
+            coordinator.add_partitions("txn", 8, 0, partitions(), 10),
+            Err(Error::InvalidProducerIdMapping { .. })
+        ));
+        assert!(matches!(
+            coordinator.end("txn", 7, 0, true, 10),
+            Err(Error::InvalidTxnState {
+                state: TxnState::Empty,
+                ..
+            })
+        ));
+        coordinator
+            .add_partitions("txn", 7, 0, partitions(), 10)
+            .unwrap();
+        assert_eq!(Some(TxnState::Ongoing), coordinator.state("txn"));
+
+        let markers = coordinator.end("txn", 7, 0, true, 20).unwrap();
+        assert_eq!(ControlMarker::Commit, markers.marker);
+        assert_eq!(partitions(), markers.partitions);
+        assert!(matches!(
+            coordinator.end("txn", 7, 0, true, 20),
+            Err(Error::ConcurrentTransactions { .. })
+        ));
+        assert!(matches!(
+            coordinator.end("txn", 7, 0, false, 20),
+            Err(Error::InvalidTxnState { .. })
+        ));
+        assert!(matches!(
+            coordinator.add_partitions("txn", 7, 0, partitions(), 20),
+            Err(Error::ConcurrentTransactions { .. })
+        ));
+
+        // Markers are retried until they are reported written.
+        assert!(coordinator.expire(25).is_empty());
+        assert_eq!(vec![markers.clone()], coordinator.expire(30));
+        coordinator.complete(&markers, 30).unwrap();
+        coordinator.complete(&markers, 40).unwrap();
+        assert_eq!(Some(TxnState::Empty), coordinator.state("txn"));
+
+        coordinator
+            .add_partitions("txn", 7, 0, partitions()[..1].to_vec(), 50)
+            .unwrap();
+        let markers = coordinator.end("txn", 7, 0, false, 60).unwrap();
+        assert_eq!(ControlMarker::Abort, markers.marker);
+        assert_eq!(1, markers.partitions.len());
+        coordinator.complete(&markers, 60).unwrap();
+
+        // The state is recovered on open.
+        drop(coordinator);
+        let coordinator = self::coordinator(&topics);
+        assert_eq!(Some(TxnState::Empty), coordinator.state("txn"));
+        let init = coordinator.init("txn", 500, 70, || unreachable!()).unwrap();
+        assert_eq!((7, 1), (init.producer_id, init.epoch));
+        assert!(matches!(
+            coordinator.add_partitions("txn", 7, 0, partitions(), 70),
+            Err(Error::ProducerFenced { current: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_fencing() {
+        let dir = tempfile::tempdir().unwrap();
+        let topics = Arc::new(topic::Manager::open(dir.path(), LogConfig::default()).unwrap());
+        let coordinator = coordinator(&topics);
+
+        coordinator.init("txn", 500, 0, || Ok(3)).unwrap();
+        coordinator
+            .add_partitions("txn", 3, 0, partitions(), 0)
+            .unwrap();
+
+        // Reinitializing aborts the ongoing transaction with the new epoch.
+        let init = coordinator.init("txn", 500, 10, || Ok(4)).unwrap();
+        let aborted = init.aborted.unwrap();
+        assert_eq!((3, 1), (init.producer_id, init.epoch));
+        assert_eq!((3, 1), (aborted.producer_id, aborted.epoch));
+        assert_eq!(ControlMarker::Abort, aborted.marker);
+        assert!(matches!(
+            coordinator.init("txn", 500, 10, || Ok(4)),
+            Err(Error::ConcurrentTransactions { .. })
+        ));
+        coordinator.complete(&aborted, 20).unwrap();
+
+        // Transactions that outlive their timeout are aborted, and their
+        // producer fenced.
+        coordinator
+            .add_partitions("txn", 3, 1, partitions(), 100)
+            .unwrap();
+        assert!(coordinator.expire(599).is_empty());
+        let expired = coordinator.expire(600);
+        assert_eq!(1, expired.len());
+        assert_eq!((3, 2), (expired[0].producer_id, expired[0].epoch));
+        assert_eq!(Some(TxnState::PrepareAbort), coordinator.state("txn"));
+        assert!(matches!(
+            coordinator.end("txn", 3, 1, true, 600),
+            Err(Error::ProducerFenced { current: 2, .. })
+        ));
+        coordinator.complete(&expired[0], 610).unwrap();
+        assert!(coordinator.expire(2000).is_empty());
+    }
+}
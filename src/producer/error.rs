@@ -0,0 +1,113 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{io, path::PathBuf, result};
+
+use thiserror::Error;
+
+use crate::{codec, topic};
+
+use super::state::TxnState;
+
+/// Custom Result wrapper to simplify usage.
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents errors assigning producer ids or coordinating transactions.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// Handles OS level errors while persisting allocated producer ids.
+    #[error("i/o error on '{path}': {source}")]
+    Io {
+        /// The file being operated on.
+        path: PathBuf,
+        /// The initial error cause.
+        source: io::Error,
+    },
+    /// Handles allocation state files that could not be serialized or deserialized.
+    #[error("invalid producer id state in '{path}': {source}")]
+    State {
+        /// The state file being operated on.
+        path: PathBuf,
+        /// The initial error cause.
+        source: serde_json::Error,
+    },
+    /// Handles nodes that have assigned every producer id available to them.
+    #[error("node {node_id} has no producer ids left to assign")]
+    Exhausted {
+        /// The id of the node.
+        node_id: u32,
+    },
+    /// Handles errors reading or writing the transaction state topic, or writing
+    /// transaction markers.
+    #[error(transparent)]
+    Topic(#[from] topic::Error),
+    /// Handles transaction state records that could not be decoded.
+    #[error("failed to decode transaction state at offset {offset}: {source}")]
+    Corrupt {
+        /// The offset of the record that failed to decode.
+        offset: u64,
+        /// The initial error cause.
+        source: codec::Error,
+    },
+    /// Handles transactional ids that can not be used.
+    #[error("invalid transactional id '{transactional_id}': {reason}")]
+    InvalidTransactionalId {
+        /// The offending transactional id.
+        transactional_id: String,
+        /// Why the transactional id was rejected.
+        reason: &'static str,
+    },
+    /// Handles transaction timeouts above the configured maximum.
+    #[error("transaction timeout {timeout_ms}ms must be between 1ms and {max_ms}ms")]
+    InvalidTimeout {
+        /// The requested transaction timeout.
+        timeout_ms: u64,
+        /// The largest allowed transaction timeout.
+        max_ms: u64,
+    },
+    /// Handles requests naming a producer id the transactional id is not mapped to.
+    #[error("producer {producer_id} is not mapped to transactional id '{transactional_id}'")]
+    InvalidProducerIdMapping {
+        /// The transactional id of the request.
+        transactional_id: String,
+        /// The producer id of the request.
+        producer_id: u64,
+    },
+    /// Handles requests from a producer whose epoch has since been bumped.
+    #[error("producer {producer_id} epoch {epoch} has been fenced by epoch {current}")]
+    ProducerFenced {
+        /// The id of the producer.
+        producer_id: u64,
+        /// The epoch of the request.
+        epoch: u16,
+        /// The producer's current epoch.
+        current: u16,
+    },
+    /// Handles requests that are not valid in the transaction's current state.
+    #[error("transaction '{transactional_id}' can not {operation} while {state}")]
+    InvalidTxnState {
+        /// The transactional id of the request.
+        transactional_id: String,
+        /// What the request attempted.
+        operation: &'static str,
+        /// The transaction's current state.
+        state: TxnState,
+    },
+    /// Handles requests made while the previous transaction's markers are still
+    /// being written.
+    #[error("transaction '{transactional_id}' is still completing its previous transaction")]
+    ConcurrentTransactions {
+        /// The transactional id of the request.
+        transactional_id: String,
+    },
+}
+
+impl Error {
+    /// Wraps the supplied [io::Error] with the path that was being operated on.
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Error {
+        Error::Io {
+            path: path.into(),
+            source,
+        }
+    }
+}
@@ -0,0 +1,34 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::sync::OnceLock;
+
+use prometheus::IntCounterVec;
+
+use crate::metrics::{register_int_counter_vec, Opt};
+
+/// The series exported by the transaction coordinator.
+pub(super) struct Metrics {
+    /// Transactions aborted, labelled by why they were aborted.
+    pub(super) aborted_transactions: IntCounterVec,
+}
+
+/// Returns the process wide transaction metrics, registering them on first use.
+pub(super) fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(|| {
+        let opts = vec![
+            Opt::Namespace(String::from("rift")),
+            Opt::Subsystem(String::from("transaction")),
+            Opt::Label(String::from("reason")),
+        ];
+        Metrics {
+            aborted_transactions: register_int_counter_vec(
+                "aborted_total",
+                "The number of transactions aborted by the coordinator or their producer.",
+                Some(opts),
+            )
+            .expect("transaction metrics registered twice"),
+        }
+    })
+}
@@ -0,0 +1,15 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod allocator;
+mod config;
+mod coordinator;
+mod error;
+mod metrics;
+mod state;
+
+pub use self::allocator::{Allocator, PRODUCER_IDS_FILE};
+pub use self::config::Config;
+pub use self::coordinator::{Coordinator, Initialized, Markers, TRANSACTION_STATE_TOPIC};
+pub use self::error::{Error, Result};
+pub use self::state::TxnState;
@@ -0,0 +1,200 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{collections::BTreeSet, fmt};
+
+use crate::codec::{self, Reader, Writer};
+use crate::record::Record;
+use crate::topic::TopicPartition;
+
+/// The current version of the transaction state record key format.
+const KEY_VERSION: u16 = 0;
+/// The current version of the transaction state record value format.
+const VALUE_VERSION: u16 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The states a transactional producer moves through.
+pub enum TxnState {
+    /// The producer has no transaction in progress.
+    Empty = 0,
+    /// The producer has added partitions to a transaction it has not ended.
+    Ongoing = 1,
+    /// The transaction is committing, and its markers are being written.
+    PrepareCommit = 2,
+    /// The transaction is aborting, and its markers are being written.
+    PrepareAbort = 3,
+}
+
+impl TxnState {
+    fn from_u8(value: u8) -> codec::Result<TxnState> {
+        match value {
+            0 => Ok(TxnState::Empty),
+            1 => Ok(TxnState::Ongoing),
+            2 => Ok(TxnState::PrepareCommit),
+            3 => Ok(TxnState::PrepareAbort),
+            _ => Err(codec::Error::InvalidValue {
+                field: "transaction state",
+                value: value as i64,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for TxnState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            TxnState::Empty => "empty",
+            TxnState::Ongoing => "ongoing",
+            TxnState::PrepareCommit => "preparing to commit",
+            TxnState::PrepareAbort => "preparing to abort",
+        };
+        f.write_str(state)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The state of a single transactional id as stored in the transaction state topic.
+///
+/// Records are keyed by transactional id so that compacting the topic retains
+/// only the latest state of each.
+pub(super) struct TxnMetadata {
+    pub(super) transactional_id: String,
+    pub(super) producer_id: u64,
+    pub(super) epoch: u16,
+    pub(super) timeout_ms: u64,
+    pub(super) state: TxnState,
+    pub(super) partitions: BTreeSet<TopicPartition>,
+    /// When the state last changed, in milliseconds since the epoch.
+    pub(super) updated_ms: i64,
+}
+
+impl TxnMetadata {
+    pub(super) fn to_record(&self) -> Record {
+        let mut key = Vec::new();
+        key.put_u16(KEY_VERSION);
+        key.put_string(&self.transactional_id);
+
+        let mut value = Vec::new();
+        value.put_u16(VALUE_VERSION);
+        value.put_u64(self.producer_id);
+        value.put_u16(self.epoch);
+        value.put_u64(self.timeout_ms);
+        value.put_u8(self.state as u8);
+        value.put_i64(self.updated_ms);
+        let partitions: Vec<&TopicPartition> = self.partitions.iter().collect();
+        value.put_array(&partitions, |buf, partition| {
+            buf.put_string(&partition.topic);
+            buf.put_u32(partition.partition);
+        });
+        Record {
+            key: Some(key),
+            value: Some(value),
+            headers: Vec::new(),
+            timestamp: None,
+        }
+    }
+
+    pub(super) fn from_record(record: &Record) -> codec::Result<TxnMetadata> {
+        let key = record.key.as_deref().ok_or(codec::Error::InvalidValue {
+            field: "transaction state key",
+            value: -1,
+        })?;
+        let mut reader = Reader::new(key);
+        check_version(
+            reader.get_u16()?,
+            KEY_VERSION,
+            "transaction state key version",
+        )?;
+        let transactional_id = reader.get_string()?;
+
+        let value = record.value.as_deref().ok_or(codec::Error::InvalidValue {
+            field: "transaction state value",
+            value: -1,
+        })?;
+        let mut reader = Reader::new(value);
+        check_version(
+            reader.get_u16()?,
+            VALUE_VERSION,
+            "transaction state value version",
+        )?;
+        Ok(TxnMetadata {
+            transactional_id,
+            producer_id: reader.get_u64()?,
+            epoch: reader.get_u16()?,
+            timeout_ms: reader.get_u64()?,
+            state: TxnState::from_u8(reader.get_u8()?)?,
+            updated_ms: reader.get_i64()?,
+            partitions: reader
+                .get_array(|reader| {
+                    Ok(TopicPartition {
+                        topic: reader.get_string()?,
+                        partition: reader.get_u32()?,
+                    })
+                })?
+                .into_iter()
+                .collect(),
+        })
+    }
+}
+
+fn check_version(version: u16, expected: u16, field: &'static str) -> codec::Result<()> {
+    if version != expected {
+        return Err(codec::Error::InvalidValue {
+            field,
+            value: version as i64,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let metadata = TxnMetadata {
+            transactional_id: String::from("txn"),
+            producer_id: 7,
+            epoch: 3,
+            timeout_ms: 60000,
+            state: TxnState::PrepareCommit,
+            partitions: [
+                TopicPartition::new("events", 0),
+                TopicPartition::new("events", 2),
+            ]
+            .into_iter()
+            .collect(),
+            updated_ms: 42,
+        };
+        assert_eq!(
+            metadata,
+            TxnMetadata::from_record(&metadata.to_record()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(TxnMetadata::from_record(&Record::new("value")).is_err());
+
+        let mut record = TxnMetadata {
+            transactional_id: String::from("txn"),
+            producer_id: 7,
+            epoch: 0,
+            timeout_ms: 60000,
+            state: TxnState::Empty,
+            partitions: BTreeSet::new(),
+            updated_ms: 0,
+        }
+        .to_record();
+        record.value.as_mut().unwrap()[20] = 9;
+        assert!(matches!(
+            TxnMetadata::from_record(&record),
+            Err(codec::Error::InvalidValue {
+                field: "transaction state",
+                value: 9
+            })
+        ));
+    }
+}
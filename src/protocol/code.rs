@@ -0,0 +1,154 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fmt;
+
+macro_rules! error_codes {
+    ($($(#[$doc:meta])* $name:ident = $value:expr, $desc:expr;)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        /// The error codes returned to clients in response headers and per
+        /// partition results.
+        pub enum ErrorCode {
+            $($(#[$doc])* $name,)*
+        }
+
+        impl ErrorCode {
+            /// Decode an error code from its wire representation, mapping unknown
+            /// values to [ErrorCode::Unknown].
+            pub fn from_i16(value: i16) -> ErrorCode {
+                match value {
+                    $($value => ErrorCode::$name,)*
+                    _ => ErrorCode::Unknown,
+                }
+            }
+
+            /// Returns the wire representation of this error code.
+            pub fn as_i16(self) -> i16 {
+                match self {
+                    $(ErrorCode::$name => $value,)*
+                }
+            }
+
+            /// Returns a human readable description of this error code.
+            pub fn description(self) -> &'static str {
+                match self {
+                    $(ErrorCode::$name => $desc,)*
+                }
+            }
+        }
+    };
+}
+
+error_codes! {
+    /// No error occurred.
+    None = 0, "success";
+    /// An unexpected server side error occurred.
+    Unknown = -1, "unknown server error";
+    /// The request frame could not be decoded.
+    CorruptFrame = 1, "the request frame could not be decoded";
+    /// The request frame exceeded the configured maximum frame size.
+    FrameTooLarge = 2, "the request frame exceeds the maximum frame size";
+    /// The request targeted an api key the server does not implement.
+    UnknownApi = 3, "the requested api is not supported";
+    /// The request used a version of the api the server does not implement.
+    UnsupportedVersion = 4, "the requested api version is not supported";
+    /// The referenced topic does not exist.
+    TopicNotFound = 5, "the topic does not exist";
+    /// The topic being created already exists.
+    TopicAlreadyExists = 6, "the topic already exists";
+    /// The topic name is invalid.
+    InvalidTopic = 7, "the topic name is invalid";
+    /// The requested partition count is invalid.
+    InvalidPartitions = 8, "the partition count is invalid";
+    /// The referenced partition does not exist.
+    PartitionNotFound = 9, "the partition does not exist";
+    /// The requested offset is not contained in the partition.
+    OffsetOutOfRange = 10, "the offset is out of range";
+    /// The supplied configuration is invalid.
+    InvalidConfig = 11, "the configuration is invalid";
+    /// The produced batch is larger than the partition allows.
+    MessageTooLarge = 12, "the message batch is too large";
+    /// Stored data failed validation.
+    CorruptMessage = 13, "stored data is corrupt";
+    /// The request was well formed but semantically invalid.
+    InvalidRequest = 14, "the request is invalid";
+    /// The member is not part of the consumer group.
+    UnknownMember = 15, "the member is not part of the group";
+    /// The member shares no assignment strategy with the rest of the group.
+    InconsistentStrategy = 16, "no assignment strategy is supported by every group member";
+    /// The requested session timeout is outside the allowed bounds.
+    InvalidSessionTimeout = 17, "the session timeout is invalid";
+    /// The consumer group id is invalid.
+    InvalidGroup = 18, "the group id is invalid";
+    /// The node is not the leader of, or does not host, the partition.
+    NotLeader = 19, "this node is not the leader of the partition";
+    /// The write was not replicated to a quorum of the partition's replicas in time.
+    ReplicationTimeout = 20, "the write was not replicated to a quorum in time";
+    /// Not enough nodes have joined the cluster to serve the request.
+    ClusterUnavailable = 21, "not enough nodes have joined the cluster";
+    /// The producer's id has been reassigned with a newer epoch.
+    ProducerFenced = 22, "the producer has been fenced by a newer epoch";
+    /// The batch repeats records the producer already wrote.
+    DuplicateSequence = 23, "the producer already wrote this sequence number";
+    /// The batch skips ahead of the producer's next sequence number.
+    OutOfOrderSequence = 24, "the producer's sequence number is out of order";
+    /// The request is not valid in the transaction's current state.
+    InvalidTxnState = 25, "the transaction is not in a valid state for the request";
+    /// The requested transaction timeout exceeds the configured maximum.
+    InvalidTransactionTimeout = 26, "the transaction timeout is invalid";
+    /// The producer id does not belong to the transactional id.
+    InvalidProducerIdMapping = 27, "the producer id is not mapped to the transactional id";
+    /// The transaction is still being completed; the request may be retried.
+    ConcurrentTransactions = 28, "the previous transaction is still being completed";
+    /// The queue name or consumer id is empty.
+    InvalidQueue = 29, "the queue name or consumer id is invalid";
+    /// The requested visibility timeout exceeds the configured maximum.
+    InvalidVisibilityTimeout = 30, "the visibility timeout is invalid";
+    /// The message is not leased to the consumer, or its lease has expired.
+    NotLeased = 31, "the message is not leased to the consumer";
+    /// The requested delivery time is further in the future than allowed.
+    InvalidDeliveryTime = 32, "the delivery time is invalid";
+    /// The requested exchange does not exist.
+    ExchangeNotFound = 33, "the exchange does not exist";
+    /// The exchange name is invalid, or it already exists with a different type.
+    InvalidExchange = 34, "the exchange is invalid";
+    /// The selector filtering fetched records could not be parsed.
+    InvalidSelector = 35, "the selector is invalid";
+    /// The node does not coordinate the consumer group; the request may be
+    /// retried against the group's coordinator.
+    NotCoordinator = 36, "this node is not the coordinator of the group";
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.description(), self.as_i16())
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for code in [
+            ErrorCode::None,
+            ErrorCode::Unknown,
+            ErrorCode::CorruptFrame,
+            ErrorCode::TopicNotFound,
+            ErrorCode::InvalidRequest,
+        ] {
+            assert_eq!(code, ErrorCode::from_i16(code.as_i16()));
+        }
+        assert_eq!(ErrorCode::Unknown, ErrorCode::from_i16(i16::MAX));
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            "the topic does not exist (5)",
+            format!("{}", ErrorCode::TopicNotFound)
+        );
+    }
+}
@@ -0,0 +1,90 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{io, result};
+
+use thiserror::Error;
+
+use crate::codec;
+
+use super::code::ErrorCode;
+
+/// Custom Result wrapper to simplify usage.
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents errors reading, writing, or decoding protocol frames.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// Handles OS level errors on the underlying connection.
+    #[error("connection error: {0}")]
+    Io(#[from] io::Error),
+    /// Handles frames whose contents could not be decoded.
+    #[error("malformed frame: {0}")]
+    Codec(#[from] codec::Error),
+    /// Handles frames with bytes left over after decoding.
+    #[error("malformed frame: {remaining} unexpected trailing bytes")]
+    TrailingBytes {
+        /// The number of bytes left undecoded.
+        remaining: usize,
+    },
+    /// Handles frames that exceed the configured maximum frame size.
+    #[error("frame of {size} bytes exceeds the maximum frame size of {max} bytes")]
+    FrameTooLarge {
+        /// The size of the rejected frame.
+        size: u32,
+        /// The configured maximum frame size.
+        max: u32,
+    },
+    /// Handles requests for api keys that are not implemented.
+    #[error("unknown api key {key}")]
+    UnknownApi {
+        /// The requested api key.
+        key: u16,
+    },
+    /// Handles requests for api versions that are not implemented.
+    #[error("unsupported version {version} of api {key}")]
+    UnsupportedVersion {
+        /// The requested api key.
+        key: u16,
+        /// The requested api version.
+        version: u16,
+    },
+}
+
+impl Error {
+    /// Returns the [ErrorCode] reported to clients for this error.
+    ///
+    /// ```
+    /// # use librift::protocol::{Error, ErrorCode};
+    /// let err = Error::UnknownApi { key: 999 };
+    /// assert_eq!(ErrorCode::UnknownApi, err.code());
+    /// ```
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::Io(_) => ErrorCode::Unknown,
+            Error::Codec(_) | Error::TrailingBytes { .. } => ErrorCode::CorruptFrame,
+            Error::FrameTooLarge { .. } => ErrorCode::FrameTooLarge,
+            Error::UnknownApi { .. } => ErrorCode::UnknownApi,
+            Error::UnsupportedVersion { .. } => ErrorCode::UnsupportedVersion,
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code() {
+        let err = Error::from(codec::Error::InvalidUtf8);
+        assert_eq!(ErrorCode::CorruptFrame, err.code());
+        assert_eq!("malformed frame: invalid utf-8 string", format!("{}", err));
+
+        let err = Error::FrameTooLarge { size: 10, max: 5 };
+        assert_eq!(ErrorCode::FrameTooLarge, err.code());
+
+        let err = Error::UnsupportedVersion { key: 1, version: 9 };
+        assert_eq!(ErrorCode::UnsupportedVersion, err.code());
+    }
+}
@@ -0,0 +1,113 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::io::{self, ErrorKind, Read, Write};
+
+use super::error::Result;
+
+/// The number of bytes of an oversized frame retained so the sender can still
+/// be told which request was rejected.
+pub const OVERSIZED_PREFIX: usize = 8;
+
+#[derive(Debug, PartialEq)]
+/// A single length prefixed frame read off of a connection.
+pub enum Frame {
+    /// A frame within the configured size limit.
+    Complete(Vec<u8>),
+    /// A frame that exceeded the configured size limit. Its contents have been
+    /// discarded, apart from a short prefix holding the request header.
+    Oversized {
+        /// The advertised size of the frame.
+        size: u32,
+        /// The first bytes of the frame.
+        prefix: Vec<u8>,
+    },
+}
+
+/// Read a single u32 length prefixed frame, returning [None] if the connection
+/// was cleanly closed before any bytes of the frame were read.
+pub fn read_frame(reader: &mut impl Read, max_size: u32) -> Result<Option<Frame>> {
+    let mut len = [0; 4];
+    match reader.read(&mut len[..1])? {
+        0 => return Ok(None),
+        _ => reader.read_exact(&mut len[1..])?,
+    }
+
+    let size = u32::from_be_bytes(len);
+    if size > max_size {
+        let mut prefix = vec![0; OVERSIZED_PREFIX.min(size as usize)];
+        reader.read_exact(&mut prefix)?;
+        let skip = size as u64 - prefix.len() as u64;
+        let skipped = io::copy(&mut reader.take(skip), &mut io::sink())?;
+        if skipped != skip {
+            return Err(io::Error::from(ErrorKind::UnexpectedEof).into());
+        }
+        return Ok(Some(Frame::Oversized { size, prefix }));
+    }
+
+    let mut buf = vec![0; size as usize];
+    reader.read_exact(&mut buf)?;
+    Ok(Some(Frame::Complete(buf)))
+}
+
+/// Write the supplied payload as a single u32 length prefixed frame.
+pub fn write_frame(writer: &mut impl Write, payload: &[u8]) -> Result<()> {
+    let mut buf = Vec::with_capacity(payload.len() + 4);
+    buf.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    buf.extend_from_slice(payload);
+    writer.write_all(&buf)?;
+    Ok(writer.flush()?)
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::protocol::Error;
+
+    #[test]
+    fn test_round_trip() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, b"hello").unwrap();
+        write_frame(&mut buf, b"").unwrap();
+
+        let mut cursor = Cursor::new(buf);
+        assert_eq!(
+            Some(Frame::Complete(b"hello".to_vec())),
+            read_frame(&mut cursor, 1024).unwrap()
+        );
+        assert_eq!(
+            Some(Frame::Complete(Vec::new())),
+            read_frame(&mut cursor, 1024).unwrap()
+        );
+        assert_eq!(None, read_frame(&mut cursor, 1024).unwrap());
+    }
+
+    #[test]
+    fn test_oversized() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, b"0123456789abcdef").unwrap();
+        write_frame(&mut buf, b"next").unwrap();
+
+        let mut cursor = Cursor::new(buf);
+        assert_eq!(
+            Some(Frame::Oversized {
+                size: 16,
+                prefix: b"01234567".to_vec()
+            }),
+            read_frame(&mut cursor, 8).unwrap()
+        );
+        assert_eq!(
+            Some(Frame::Complete(b"next".to_vec())),
+            read_frame(&mut cursor, 8).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut cursor = Cursor::new(vec![0, 0, 0, 10, 1, 2]);
+        assert!(matches!(read_frame(&mut cursor, 1024), Err(Error::Io(_))));
+    }
+}
@@ -0,0 +1,370 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::codec::{self, Reader, Writer};
+use crate::exchange::{Binding, ExchangeType};
+use crate::offset::OffsetReset;
+use crate::record::{ControlMarker, Header, OffsetRecord, ProducerBatch, Record};
+use crate::storage::Entry;
+use crate::topic::{IsolationLevel, Partitioning, TopicPartition};
+
+/// A value that can be written to and read from the wire.
+pub trait Message: Sized {
+    /// Encode this value onto the end of the supplied buffer.
+    fn encode(&self, buf: &mut Vec<u8>);
+    /// Decode a value from the supplied reader.
+    fn decode(reader: &mut Reader) -> codec::Result<Self>;
+}
+
+/// Encode a u32 length prefixed array of messages.
+pub fn put_messages<T: Message>(buf: &mut Vec<u8>, items: &[T]) {
+    buf.put_array(items, |buf, item| item.encode(buf));
+}
+
+/// Decode a u32 length prefixed array of messages.
+pub fn get_messages<T: Message>(reader: &mut Reader) -> codec::Result<Vec<T>> {
+    reader.get_array(T::decode)
+}
+
+impl Message for u32 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u32(*self);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        reader.get_u32()
+    }
+}
+
+impl Message for u64 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u64(*self);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        reader.get_u64()
+    }
+}
+
+impl Message for String {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(self);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        reader.get_string()
+    }
+}
+
+impl Message for (String, String) {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.0);
+        buf.put_string(&self.1);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok((reader.get_string()?, reader.get_string()?))
+    }
+}
+
+impl Message for ProducerBatch {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u64(self.producer_id);
+        buf.put_u16(self.epoch);
+        buf.put_u32(self.sequence);
+        buf.put_bool(self.transactional);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(ProducerBatch {
+            producer_id: reader.get_u64()?,
+            epoch: reader.get_u16()?,
+            sequence: reader.get_u32()?,
+            transactional: reader.get_bool()?,
+        })
+    }
+}
+
+impl Message for Partitioning {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let (kind, partition) = match self {
+            Partitioning::Key => (0, 0),
+            Partitioning::RoundRobin => (1, 0),
+            Partitioning::Explicit(partition) => (2, *partition),
+        };
+        buf.put_u8(kind);
+        buf.put_u32(partition);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        let kind = reader.get_u8()?;
+        let partition = reader.get_u32()?;
+        match kind {
+            0 => Ok(Partitioning::Key),
+            1 => Ok(Partitioning::RoundRobin),
+            2 => Ok(Partitioning::Explicit(partition)),
+            _ => Err(codec::Error::InvalidValue {
+                field: "partitioning",
+                value: kind as i64,
+            }),
+        }
+    }
+}
+
+impl Message for OffsetReset {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let (kind, timestamp) = match self {
+            OffsetReset::Earliest => (0, 0),
+            OffsetReset::Latest => (1, 0),
+            OffsetReset::Timestamp(timestamp) => (2, *timestamp),
+        };
+        buf.put_u8(kind);
+        buf.put_i64(timestamp);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        let kind = reader.get_u8()?;
+        let timestamp = reader.get_i64()?;
+        match kind {
+            0 => Ok(OffsetReset::Earliest),
+            1 => Ok(OffsetReset::Latest),
+            2 => Ok(OffsetReset::Timestamp(timestamp)),
+            _ => Err(codec::Error::InvalidValue {
+                field: "offset reset",
+                value: kind as i64,
+            }),
+        }
+    }
+}
+
+impl Message for IsolationLevel {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u8(match self {
+            IsolationLevel::ReadUncommitted => 0,
+            IsolationLevel::ReadCommitted => 1,
+        });
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        match reader.get_u8()? {
+            0 => Ok(IsolationLevel::ReadUncommitted),
+            1 => Ok(IsolationLevel::ReadCommitted),
+            kind => Err(codec::Error::InvalidValue {
+                field: "isolation level",
+                value: kind as i64,
+            }),
+        }
+    }
+}
+
+impl Message for ControlMarker {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_bool(*self == ControlMarker::Commit);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(match reader.get_bool()? {
+            true => ControlMarker::Commit,
+            false => ControlMarker::Abort,
+        })
+    }
+}
+
+impl Message for ExchangeType {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u8(self.id());
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        ExchangeType::from_id(reader.get_u8()?)
+    }
+}
+
+impl Message for Binding {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.exchange);
+        buf.put_string(&self.destination);
+        buf.put_string(&self.routing_key);
+        buf.put_array(&self.arguments, |buf, argument| {
+            buf.put_string(&argument.key);
+            buf.put_bytes(&argument.value);
+        });
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(Binding {
+            exchange: reader.get_string()?,
+            destination: reader.get_string()?,
+            routing_key: reader.get_string()?,
+            arguments: reader.get_array(|reader| {
+                Ok(Header {
+                    key: reader.get_string()?,
+                    value: reader.get_bytes()?,
+                })
+            })?,
+        })
+    }
+}
+
+impl Message for Record {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        Record::encode(self, buf)
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Record::decode(reader)
+    }
+}
+
+impl Message for OffsetRecord {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u64(self.offset);
+        buf.put_i64(self.timestamp);
+        self.record.encode(buf);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(OffsetRecord {
+            offset: reader.get_u64()?,
+            timestamp: reader.get_i64()?,
+            record: Record::decode(reader)?,
+        })
+    }
+}
+
+impl Message for TopicPartition {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.topic);
+        buf.put_u32(self.partition);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(TopicPartition {
+            topic: reader.get_string()?,
+            partition: reader.get_u32()?,
+        })
+    }
+}
+
+impl Message for Entry {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u64(self.base_offset);
+        buf.put_u32(self.record_count);
+        buf.put_i64(self.max_timestamp);
+        buf.put_bytes(&self.payload);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(Entry {
+            base_offset: reader.get_u64()?,
+            record_count: reader.get_u32()?,
+            max_timestamp: reader.get_i64()?,
+            payload: reader.get_bytes()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A log entry along with the raft term of the leader that appended it.
+pub struct ReplicatedEntry {
+    /// The term the entry was appended in.
+    pub term: u64,
+    /// The entry itself.
+    pub entry: Entry,
+}
+
+impl Message for ReplicatedEntry {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u64(self.term);
+        Message::encode(&self.entry, buf);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(ReplicatedEntry {
+            term: reader.get_u64()?,
+            entry: Entry::decode(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// An offset within a single topic partition.
+pub struct PartitionOffset {
+    /// The name of the topic.
+    pub topic: String,
+    /// The partition within the topic.
+    pub partition: u32,
+    /// The offset within the partition.
+    pub offset: u64,
+}
+
+impl Message for PartitionOffset {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.topic);
+        buf.put_u32(self.partition);
+        buf.put_u64(self.offset);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(PartitionOffset {
+            topic: reader.get_string()?,
+            partition: reader.get_u32()?,
+            offset: reader.get_u64()?,
+        })
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use std::fmt::Debug;
+
+    use super::*;
+
+    fn round_trip<T: Message + PartialEq + Debug>(value: T) {
+        let mut buf = Vec::new();
+        value.encode(&mut buf);
+        let mut reader = Reader::new(&buf);
+        assert_eq!(value, T::decode(&mut reader).unwrap());
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        round_trip(String::from("topic"));
+        round_trip((String::from("key"), String::from("value")));
+        round_trip(Partitioning::Key);
+        round_trip(Partitioning::RoundRobin);
+        round_trip(Partitioning::Explicit(7));
+        round_trip(OffsetReset::Earliest);
+        round_trip(OffsetReset::Latest);
+        round_trip(OffsetReset::Timestamp(-7));
+        round_trip(Record::new("value").with_key("key"));
+        round_trip(OffsetRecord {
+            offset: 1,
+            timestamp: 2,
+            record: Record::new("value"),
+        });
+        round_trip(TopicPartition::new("topic", 3));
+        let mut entry = Entry::new(2, 3, b"payload".to_vec());
+        entry.base_offset = 1;
+        round_trip(ReplicatedEntry { term: 4, entry });
+        round_trip(PartitionOffset {
+            topic: String::from("topic"),
+            partition: 1,
+            offset: 2,
+        });
+    }
+
+    #[test]
+    fn test_invalid_partitioning() {
+        let buf = vec![9, 0, 0, 0, 0];
+        assert!(matches!(
+            Partitioning::decode(&mut Reader::new(&buf)),
+            Err(codec::Error::InvalidValue {
+                field: "partitioning",
+                value: 9
+            })
+        ));
+    }
+}
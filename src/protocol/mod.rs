@@ -0,0 +1,31 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod code;
+mod error;
+mod frame;
+mod message;
+mod request;
+mod response;
+
+pub use self::code::ErrorCode;
+pub use self::error::{Error, Result};
+pub use self::frame::{read_frame, write_frame, Frame, OVERSIZED_PREFIX};
+pub use self::message::{get_messages, put_messages, Message, PartitionOffset, ReplicatedEntry};
+pub use self::request::{
+    AckRequest, AddPartitionsToTxnRequest, ApiKey, AppendRequest, BindRequest, CommitOffsetRequest,
+    CreateTopicRequest, DeclareExchangeRequest, DeleteExchangeRequest, DeleteTopicRequest,
+    EndTxnRequest, FetchOffsetsRequest, FetchPartition, FetchRequest, FindCoordinatorRequest,
+    GroupMemberRequest, InitProducerRequest, JoinGroupRequest, LeaseRequest, MetadataRequest,
+    MetadataWriteRequest, NackRequest, OffsetsForTimesRequest, PartitionTimestamp, ProduceRequest,
+    PublishRequest, ReplayDeadLettersRequest, Request, RequestHeader, ResetOffsetsRequest,
+    ScheduleRequest, ScheduledRecord, SnapshotRequest, VoteRequest, WriteTxnMarkersRequest,
+};
+pub use self::response::{
+    ApiVersion, ApiVersionsResponse, AppendResponse, CoordinatorResponse, FetchResponse,
+    FetchedPartition, GroupAssignmentResponse, InitProducerResponse, LeaseResponse, LeasedMessage,
+    MetadataResponse, NodeMetadata, OffsetsForTimesResponse, OffsetsResponse, PartitionMetadata,
+    ProduceResponse, ProducedRecord, PublishResponse, ReplayDeadLettersResponse, Response,
+    ResponseError, RoutedRecord, ScheduleResponse, ScheduledMessage, TemporaryQueueResponse,
+    TimestampOffset, TopicMetadata, VoteResponse,
+};
@@ -0,0 +1,1577 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::codec::{self, Reader, Writer};
+use crate::exchange::{Binding, ExchangeType};
+use crate::offset::OffsetReset;
+use crate::record::{Compression, ControlMarker, ProducerBatch, Record};
+use crate::topic::{IsolationLevel, Partitioning, TopicPartition};
+
+use super::error::{Error, Result};
+use super::message::{get_messages, put_messages, Message, PartitionOffset, ReplicatedEntry};
+
+macro_rules! api_keys {
+    ($($(#[$doc:meta])* $name:ident = $value:expr, $min:expr, $max:expr;)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        /// Identifies the api a request frame targets.
+        pub enum ApiKey {
+            $($(#[$doc])* $name,)*
+        }
+
+        impl ApiKey {
+            /// Every api implemented by this version of the protocol.
+            pub const ALL: &'static [ApiKey] = &[$(ApiKey::$name,)*];
+
+            /// Decode an api key from its wire representation.
+            pub fn from_u16(key: u16) -> Result<ApiKey> {
+                match key {
+                    $($value => Ok(ApiKey::$name),)*
+                    _ => Err(Error::UnknownApi { key }),
+                }
+            }
+
+            /// Returns the wire representation of this api key.
+            pub fn as_u16(self) -> u16 {
+                match self {
+                    $(ApiKey::$name => $value,)*
+                }
+            }
+
+            /// Returns the oldest supported version of this api.
+            pub fn min_version(self) -> u16 {
+                match self {
+                    $(ApiKey::$name => $min,)*
+                }
+            }
+
+            /// Returns the newest supported version of this api.
+            pub fn max_version(self) -> u16 {
+                match self {
+                    $(ApiKey::$name => $max,)*
+                }
+            }
+        }
+    };
+}
+
+api_keys! {
+    /// Lists the apis and versions supported by the server.
+    ApiVersions = 0, 0, 0;
+    /// Describes topics and their partitions.
+    Metadata = 1, 0, 0;
+    /// Creates a new topic.
+    CreateTopic = 2, 0, 0;
+    /// Deletes an existing topic.
+    DeleteTopic = 3, 0, 0;
+    /// Appends records to a topic.
+    Produce = 4, 0, 0;
+    /// Reads records from one or more topic partitions.
+    Fetch = 5, 0, 0;
+    /// Commits consumer offsets for a group.
+    CommitOffset = 6, 0, 0;
+    /// Keeps an otherwise idle connection alive.
+    Heartbeat = 7, 0, 0;
+    /// Joins a consumer group, or updates an existing member's subscriptions.
+    JoinGroup = 8, 0, 0;
+    /// Keeps a consumer group membership alive and returns its assignment.
+    GroupHeartbeat = 9, 0, 0;
+    /// Leaves a consumer group.
+    LeaveGroup = 10, 0, 0;
+    /// Returns the offsets committed by a consumer group.
+    FetchOffsets = 11, 0, 0;
+    /// Moves a consumer group's committed offsets to the start, end, or a point in time.
+    ResetOffsets = 12, 0, 0;
+    /// Requests a vote from a partition replica during a raft election.
+    RaftVote = 13, 0, 0;
+    /// Replicates log entries from a partition leader to a follower.
+    RaftAppend = 14, 0, 0;
+    /// Restarts a follower's log at the leader's first retained offset.
+    RaftSnapshot = 15, 0, 0;
+    /// Appends a change to the cluster metadata, sent by nodes to the metadata leader.
+    MetadataWrite = 16, 0, 0;
+    /// Assigns a new idempotent producer id, or initializes a transactional producer.
+    InitProducer = 17, 0, 0;
+    /// Adds partitions to a transactional producer's current transaction.
+    AddPartitionsToTxn = 18, 0, 0;
+    /// Commits or aborts a transactional producer's current transaction.
+    EndTxn = 19, 0, 0;
+    /// Writes transaction markers, sent by transaction coordinators to partition leaders.
+    WriteTxnMarkers = 20, 0, 0;
+    /// Leases messages of a topic to a queue consumer until they are acknowledged.
+    Lease = 21, 0, 0;
+    /// Acknowledges leased messages so they are never redelivered.
+    Ack = 22, 0, 0;
+    /// Releases leased messages for immediate redelivery.
+    Nack = 23, 0, 0;
+    /// Moves messages of a dead-letter topic back to the partitions they came from.
+    ReplayDeadLetters = 24, 0, 0;
+    /// Holds records back until their delivery time, then appends them to a partition.
+    Schedule = 25, 0, 0;
+    /// Returns the earliest offset of each partition with a timestamp at or after a given time.
+    OffsetsForTimes = 26, 0, 0;
+    /// Declares an exchange, which routes published records to the topics bound to it.
+    DeclareExchange = 27, 0, 0;
+    /// Deletes an exchange along with its bindings.
+    DeleteExchange = 28, 0, 0;
+    /// Binds an exchange to a destination topic.
+    Bind = 29, 0, 0;
+    /// Removes a binding of an exchange to a destination topic.
+    Unbind = 30, 0, 0;
+    /// Appends a record to every topic bound to an exchange that it matches.
+    Publish = 31, 0, 0;
+    /// Creates a single partition topic for replies to the connection's
+    /// requests, deleted once the connection closes.
+    CreateTemporaryQueue = 32, 0, 0;
+    /// Returns the node coordinating a consumer group's membership and offsets.
+    FindCoordinator = 33, 0, 0;
+}
+
+impl ApiKey {
+    /// Ensure the supplied version of this api is supported.
+    pub fn check_version(self, version: u16) -> Result<()> {
+        if version < self.min_version() || version > self.max_version() {
+            return Err(Error::UnsupportedVersion {
+                key: self.as_u16(),
+                version,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The header preceding every request body.
+pub struct RequestHeader {
+    /// The raw api key the request targets.
+    pub api_key: u16,
+    /// The version of the api the request body is encoded with.
+    pub api_version: u16,
+    /// A client chosen identifier echoed back in the matching response.
+    pub correlation_id: u32,
+}
+
+impl RequestHeader {
+    /// Create a header for the newest supported version of the supplied api.
+    pub fn new(api_key: ApiKey, correlation_id: u32) -> RequestHeader {
+        RequestHeader {
+            api_key: api_key.as_u16(),
+            api_version: api_key.max_version(),
+            correlation_id,
+        }
+    }
+
+    /// Encode this header onto the end of the supplied buffer.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u16(self.api_key);
+        buf.put_u16(self.api_version);
+        buf.put_u32(self.correlation_id);
+    }
+
+    /// Decode a header from the supplied reader.
+    pub fn decode(reader: &mut Reader) -> codec::Result<RequestHeader> {
+        Ok(RequestHeader {
+            api_key: reader.get_u16()?,
+            api_version: reader.get_u16()?,
+            correlation_id: reader.get_u32()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+/// Requests metadata for the listed topics, or every topic if empty.
+pub struct MetadataRequest {
+    /// The topics to describe.
+    pub topics: Vec<String>,
+}
+
+impl Message for MetadataRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_messages(buf, &self.topics);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(MetadataRequest {
+            topics: get_messages(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Requests the creation of a new topic.
+pub struct CreateTopicRequest {
+    /// The name of the topic.
+    pub name: String,
+    /// The number of partitions to create.
+    pub partitions: u32,
+    /// Topic configuration overrides.
+    pub config: Vec<(String, String)>,
+}
+
+impl Message for CreateTopicRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.name);
+        buf.put_u32(self.partitions);
+        put_messages(buf, &self.config);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(CreateTopicRequest {
+            name: reader.get_string()?,
+            partitions: reader.get_u32()?,
+            config: get_messages(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Requests the deletion of a topic.
+pub struct DeleteTopicRequest {
+    /// The name of the topic.
+    pub name: String,
+}
+
+impl Message for DeleteTopicRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.name);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(DeleteTopicRequest {
+            name: reader.get_string()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Requests that records be appended to a topic.
+pub struct ProduceRequest {
+    /// The name of the topic.
+    pub topic: String,
+    /// How records are distributed across the topic's partitions.
+    pub partitioning: Partitioning,
+    /// The records to append.
+    pub records: Vec<Record>,
+    /// The idempotent producer writing the records, which requires the records
+    /// be written to an explicit partition.
+    pub producer: Option<ProducerBatch>,
+    /// The codec the records are stored with, unless the topic forces its own.
+    pub compression: Compression,
+}
+
+impl Message for ProduceRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.topic);
+        self.partitioning.encode(buf);
+        put_messages(buf, &self.records);
+        buf.put_bool(self.producer.is_some());
+        if let Some(producer) = &self.producer {
+            producer.encode(buf);
+        }
+        buf.put_u8(self.compression.id());
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(ProduceRequest {
+            topic: reader.get_string()?,
+            partitioning: Partitioning::decode(reader)?,
+            records: get_messages(reader)?,
+            producer: match reader.get_bool()? {
+                true => Some(ProducerBatch::decode(reader)?),
+                false => None,
+            },
+            compression: Compression::from_id(reader.get_u8()?)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A single partition to read from as part of a [FetchRequest].
+pub struct FetchPartition {
+    /// The name of the topic.
+    pub topic: String,
+    /// The partition within the topic.
+    pub partition: u32,
+    /// The offset to start reading from.
+    pub offset: u64,
+    /// The maximum number of bytes of batches to return.
+    pub max_bytes: u32,
+}
+
+impl Message for FetchPartition {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.topic);
+        buf.put_u32(self.partition);
+        buf.put_u64(self.offset);
+        buf.put_u32(self.max_bytes);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(FetchPartition {
+            topic: reader.get_string()?,
+            partition: reader.get_u32()?,
+            offset: reader.get_u64()?,
+            max_bytes: reader.get_u32()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Requests records from one or more partitions.
+pub struct FetchRequest {
+    /// The partitions to read from.
+    pub partitions: Vec<FetchPartition>,
+    /// Which records of transactional producers to return.
+    pub isolation: IsolationLevel,
+    /// A [crate::selector::Selector] records must match to be returned, or
+    /// empty to return every record.
+    pub selector: String,
+}
+
+impl Message for FetchRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_messages(buf, &self.partitions);
+        self.isolation.encode(buf);
+        buf.put_string(&self.selector);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(FetchRequest {
+            partitions: get_messages(reader)?,
+            isolation: IsolationLevel::decode(reader)?,
+            selector: reader.get_string()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Requests that a group's consumed offsets be committed.
+pub struct CommitOffsetRequest {
+    /// The consumer group committing offsets.
+    pub group: String,
+    /// The offsets to commit.
+    pub offsets: Vec<PartitionOffset>,
+}
+
+impl Message for CommitOffsetRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.group);
+        put_messages(buf, &self.offsets);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(CommitOffsetRequest {
+            group: reader.get_string()?,
+            offsets: get_messages(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Requests membership of a consumer group.
+pub struct JoinGroupRequest {
+    /// The consumer group to join.
+    pub group: String,
+    /// The id of an existing member rejoining, or empty for a new member.
+    pub member_id: String,
+    /// How long the member may go without heartbeating before it is evicted.
+    pub session_timeout_ms: u32,
+    /// The topics the member consumes.
+    pub topics: Vec<String>,
+    /// The assignment strategies the member supports, most preferred first.
+    pub strategies: Vec<String>,
+}
+
+impl Message for JoinGroupRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.group);
+        buf.put_string(&self.member_id);
+        buf.put_u32(self.session_timeout_ms);
+        put_messages(buf, &self.topics);
+        put_messages(buf, &self.strategies);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(JoinGroupRequest {
+            group: reader.get_string()?,
+            member_id: reader.get_string()?,
+            session_timeout_ms: reader.get_u32()?,
+            topics: get_messages(reader)?,
+            strategies: get_messages(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Identifies a member of a consumer group, used by both [ApiKey::GroupHeartbeat]
+/// and [ApiKey::LeaveGroup].
+pub struct GroupMemberRequest {
+    /// The consumer group.
+    pub group: String,
+    /// The id of the member.
+    pub member_id: String,
+}
+
+impl Message for GroupMemberRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.group);
+        buf.put_string(&self.member_id);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(GroupMemberRequest {
+            group: reader.get_string()?,
+            member_id: reader.get_string()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Requests the offsets committed by a group.
+pub struct FetchOffsetsRequest {
+    /// The consumer group.
+    pub group: String,
+    /// The partitions to return offsets for, or every committed partition if empty.
+    pub partitions: Vec<TopicPartition>,
+}
+
+impl Message for FetchOffsetsRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.group);
+        put_messages(buf, &self.partitions);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(FetchOffsetsRequest {
+            group: reader.get_string()?,
+            partitions: get_messages(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Requests that a group's committed offsets be moved.
+pub struct ResetOffsetsRequest {
+    /// The consumer group.
+    pub group: String,
+    /// The partitions to reset, or every committed partition if empty.
+    pub partitions: Vec<TopicPartition>,
+    /// Where to move the offsets to.
+    pub reset: OffsetReset,
+}
+
+impl Message for ResetOffsetsRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.group);
+        put_messages(buf, &self.partitions);
+        self.reset.encode(buf);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(ResetOffsetsRequest {
+            group: reader.get_string()?,
+            partitions: get_messages(reader)?,
+            reset: OffsetReset::decode(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Requests a replica's vote for a candidate in a partition's raft election.
+pub struct VoteRequest {
+    /// The partition the election is for.
+    pub partition: TopicPartition,
+    /// The candidate's term.
+    pub term: u64,
+    /// The node id of the candidate.
+    pub candidate: u32,
+    /// The next offset of the candidate's log.
+    pub last_offset: u64,
+    /// The term of the last entry in the candidate's log.
+    pub last_term: u64,
+}
+
+impl Message for VoteRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.partition.encode(buf);
+        buf.put_u64(self.term);
+        buf.put_u32(self.candidate);
+        buf.put_u64(self.last_offset);
+        buf.put_u64(self.last_term);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(VoteRequest {
+            partition: TopicPartition::decode(reader)?,
+            term: reader.get_u64()?,
+            candidate: reader.get_u32()?,
+            last_offset: reader.get_u64()?,
+            last_term: reader.get_u64()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Replicates entries from a partition's leader to one of its followers, doubling
+/// as the leader's heartbeat when empty.
+pub struct AppendRequest {
+    /// The partition being replicated.
+    pub partition: TopicPartition,
+    /// The leader's term.
+    pub term: u64,
+    /// The node id of the leader.
+    pub leader: u32,
+    /// The offset the entries start at, which the follower's log must reach.
+    pub prev_offset: u64,
+    /// The term of the record preceding `prev_offset` in the leader's log.
+    pub prev_term: u64,
+    /// The leader's commit offset, below which every record is committed.
+    pub leader_commit: u64,
+    /// The entries to append.
+    pub entries: Vec<ReplicatedEntry>,
+}
+
+impl Message for AppendRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.partition.encode(buf);
+        buf.put_u64(self.term);
+        buf.put_u32(self.leader);
+        buf.put_u64(self.prev_offset);
+        buf.put_u64(self.prev_term);
+        buf.put_u64(self.leader_commit);
+        put_messages(buf, &self.entries);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(AppendRequest {
+            partition: TopicPartition::decode(reader)?,
+            term: reader.get_u64()?,
+            leader: reader.get_u32()?,
+            prev_offset: reader.get_u64()?,
+            prev_term: reader.get_u64()?,
+            leader_commit: reader.get_u64()?,
+            entries: get_messages(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Instructs a follower too far behind to catch up from the leader's log to
+/// discard its log and restart it at the leader's first retained offset.
+pub struct SnapshotRequest {
+    /// The partition being replicated.
+    pub partition: TopicPartition,
+    /// The leader's term.
+    pub term: u64,
+    /// The node id of the leader.
+    pub leader: u32,
+    /// The first offset retained by the leader.
+    pub start_offset: u64,
+    /// The term of the record at `start_offset` in the leader's log.
+    pub start_term: u64,
+}
+
+impl Message for SnapshotRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.partition.encode(buf);
+        buf.put_u64(self.term);
+        buf.put_u32(self.leader);
+        buf.put_u64(self.start_offset);
+        buf.put_u64(self.start_term);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(SnapshotRequest {
+            partition: TopicPartition::decode(reader)?,
+            term: reader.get_u64()?,
+            leader: reader.get_u32()?,
+            start_offset: reader.get_u64()?,
+            start_term: reader.get_u64()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Asks the leader of the cluster metadata to append a change on behalf of
+/// another node.
+pub struct MetadataWriteRequest {
+    /// The encoded metadata change.
+    pub record: Record,
+}
+
+impl Message for MetadataWriteRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.record.encode(buf);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(MetadataWriteRequest {
+            record: Record::decode(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Requests a producer id, which for transactional producers also bumps the
+/// epoch of the transactional id and aborts any transaction it left ongoing.
+pub struct InitProducerRequest {
+    /// The transactional id of the producer, if it is transactional.
+    pub transactional_id: Option<String>,
+    /// How long a transaction may remain ongoing before it is aborted.
+    pub transaction_timeout_ms: u32,
+}
+
+impl Message for InitProducerRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_bool(self.transactional_id.is_some());
+        if let Some(transactional_id) = &self.transactional_id {
+            buf.put_string(transactional_id);
+        }
+        buf.put_u32(self.transaction_timeout_ms);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(InitProducerRequest {
+            transactional_id: match reader.get_bool()? {
+                true => Some(reader.get_string()?),
+                false => None,
+            },
+            transaction_timeout_ms: reader.get_u32()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Adds partitions to a transactional producer's current transaction, beginning
+/// one if none is in progress.
+pub struct AddPartitionsToTxnRequest {
+    /// The transactional id of the producer.
+    pub transactional_id: String,
+    /// The id assigned to the producer.
+    pub producer_id: u64,
+    /// The producer's epoch.
+    pub epoch: u16,
+    /// The partitions the producer is about to write to.
+    pub partitions: Vec<TopicPartition>,
+}
+
+impl Message for AddPartitionsToTxnRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.transactional_id);
+        buf.put_u64(self.producer_id);
+        buf.put_u16(self.epoch);
+        put_messages(buf, &self.partitions);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(AddPartitionsToTxnRequest {
+            transactional_id: reader.get_string()?,
+            producer_id: reader.get_u64()?,
+            epoch: reader.get_u16()?,
+            partitions: get_messages(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Commits or aborts a transactional producer's current transaction.
+pub struct EndTxnRequest {
+    /// The transactional id of the producer.
+    pub transactional_id: String,
+    /// The id assigned to the producer.
+    pub producer_id: u64,
+    /// The producer's epoch.
+    pub epoch: u16,
+    /// Whether to commit, rather than abort, the transaction.
+    pub commit: bool,
+}
+
+impl Message for EndTxnRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.transactional_id);
+        buf.put_u64(self.producer_id);
+        buf.put_u16(self.epoch);
+        buf.put_bool(self.commit);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(EndTxnRequest {
+            transactional_id: reader.get_string()?,
+            producer_id: reader.get_u64()?,
+            epoch: reader.get_u16()?,
+            commit: reader.get_bool()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Asks the leader of each listed partition to append a marker completing a
+/// producer's transaction.
+pub struct WriteTxnMarkersRequest {
+    /// The id of the producer that wrote the transaction.
+    pub producer_id: u64,
+    /// The epoch to write the markers with.
+    pub epoch: u16,
+    /// Whether the transaction is committed or aborted.
+    pub marker: ControlMarker,
+    /// The partitions to write the marker to.
+    pub partitions: Vec<TopicPartition>,
+}
+
+impl Message for WriteTxnMarkersRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u64(self.producer_id);
+        buf.put_u16(self.epoch);
+        self.marker.encode(buf);
+        put_messages(buf, &self.partitions);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(WriteTxnMarkersRequest {
+            producer_id: reader.get_u64()?,
+            epoch: reader.get_u16()?,
+            marker: ControlMarker::decode(reader)?,
+            partitions: get_messages(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Leases messages of a topic to a consumer of a queue, redelivering messages
+/// whose lease expired or that were released before any new ones. Messages are
+/// moved to a dead-letter topic as the topic's configuration says.
+pub struct LeaseRequest {
+    /// The name of the queue.
+    pub queue: String,
+    /// A client chosen id identifying the consumer.
+    pub consumer_id: String,
+    /// The topic the queue consumes.
+    pub topic: String,
+    /// The partitions to lease messages from, or every partition the server
+    /// serves if empty.
+    pub partitions: Vec<u32>,
+    /// The most messages to lease.
+    pub max_messages: u32,
+    /// How long the consumer holds the messages before they are redelivered.
+    pub visibility_timeout_ms: u32,
+}
+
+impl Message for LeaseRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.queue);
+        buf.put_string(&self.consumer_id);
+        buf.put_string(&self.topic);
+        put_messages(buf, &self.partitions);
+        buf.put_u32(self.max_messages);
+        buf.put_u32(self.visibility_timeout_ms);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(LeaseRequest {
+            queue: reader.get_string()?,
+            consumer_id: reader.get_string()?,
+            topic: reader.get_string()?,
+            partitions: get_messages(reader)?,
+            max_messages: reader.get_u32()?,
+            visibility_timeout_ms: reader.get_u32()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Acknowledges messages of a single partition leased to a queue consumer.
+pub struct AckRequest {
+    /// The name of the queue.
+    pub queue: String,
+    /// The id of the consumer holding the leases.
+    pub consumer_id: String,
+    /// The topic the messages were leased from.
+    pub topic: String,
+    /// The partition the messages were leased from.
+    pub partition: u32,
+    /// The offsets of the messages.
+    pub offsets: Vec<u64>,
+}
+
+impl Message for AckRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.queue);
+        buf.put_string(&self.consumer_id);
+        buf.put_string(&self.topic);
+        buf.put_u32(self.partition);
+        put_messages(buf, &self.offsets);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(AckRequest {
+            queue: reader.get_string()?,
+            consumer_id: reader.get_string()?,
+            topic: reader.get_string()?,
+            partition: reader.get_u32()?,
+            offsets: get_messages(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Releases messages of a single partition leased to a queue consumer that
+/// failed to process them.
+pub struct NackRequest {
+    /// The name of the queue.
+    pub queue: String,
+    /// The id of the consumer holding the leases.
+    pub consumer_id: String,
+    /// The topic the messages were leased from.
+    pub topic: String,
+    /// The partition the messages were leased from.
+    pub partition: u32,
+    /// The offsets of the messages.
+    pub offsets: Vec<u64>,
+    /// Why the messages could not be processed, recorded on them should they
+    /// be moved to a dead-letter topic.
+    pub error: String,
+}
+
+impl Message for NackRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.queue);
+        buf.put_string(&self.consumer_id);
+        buf.put_string(&self.topic);
+        buf.put_u32(self.partition);
+        put_messages(buf, &self.offsets);
+        buf.put_string(&self.error);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(NackRequest {
+            queue: reader.get_string()?,
+            consumer_id: reader.get_string()?,
+            topic: reader.get_string()?,
+            partition: reader.get_u32()?,
+            offsets: get_messages(reader)?,
+            error: reader.get_string()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Moves messages of a dead-letter topic partition back to the partitions they
+/// were originally written to.
+pub struct ReplayDeadLettersRequest {
+    /// The dead-letter topic.
+    pub topic: String,
+    /// The partition of the dead-letter topic to replay.
+    pub partition: u32,
+    /// The most messages to replay.
+    pub max_messages: u32,
+}
+
+impl Message for ReplayDeadLettersRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.topic);
+        buf.put_u32(self.partition);
+        buf.put_u32(self.max_messages);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(ReplayDeadLettersRequest {
+            topic: reader.get_string()?,
+            partition: reader.get_u32()?,
+            max_messages: reader.get_u32()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A record to append to a partition once its delivery time has come.
+pub struct ScheduledRecord {
+    /// When to deliver the record, in milliseconds since the epoch.
+    pub deliver_at_ms: i64,
+    /// How long after the server receives the record to deliver it. The later
+    /// of the two delivery times applies.
+    pub delay_ms: u64,
+    /// The record itself.
+    pub record: Record,
+}
+
+impl ScheduledRecord {
+    /// Returns a record to deliver at the supplied time, in milliseconds since
+    /// the epoch.
+    pub fn at(deliver_at_ms: i64, record: Record) -> ScheduledRecord {
+        ScheduledRecord {
+            deliver_at_ms,
+            delay_ms: 0,
+            record,
+        }
+    }
+
+    /// Returns a record to deliver once the supplied delay, in milliseconds,
+    /// has passed on the server.
+    pub fn after(delay_ms: u64, record: Record) -> ScheduledRecord {
+        ScheduledRecord {
+            deliver_at_ms: 0,
+            delay_ms,
+            record,
+        }
+    }
+}
+
+impl Message for ScheduledRecord {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_i64(self.deliver_at_ms);
+        buf.put_u64(self.delay_ms);
+        self.record.encode(buf);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(ScheduledRecord {
+            deliver_at_ms: reader.get_i64()?,
+            delay_ms: reader.get_u64()?,
+            record: Record::decode(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Schedules records for delivery to a single partition at a future time.
+pub struct ScheduleRequest {
+    /// The topic to deliver the records to.
+    pub topic: String,
+    /// The partition to deliver the records to.
+    pub partition: u32,
+    /// The records and when to deliver each of them.
+    pub records: Vec<ScheduledRecord>,
+}
+
+impl Message for ScheduleRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.topic);
+        buf.put_u32(self.partition);
+        put_messages(buf, &self.records);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(ScheduleRequest {
+            topic: reader.get_string()?,
+            partition: reader.get_u32()?,
+            records: get_messages(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A single partition to look up as part of an [OffsetsForTimesRequest].
+pub struct PartitionTimestamp {
+    /// The name of the topic.
+    pub topic: String,
+    /// The partition within the topic.
+    pub partition: u32,
+    /// The time to look up, in milliseconds since the epoch.
+    pub timestamp: i64,
+}
+
+impl Message for PartitionTimestamp {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.topic);
+        buf.put_u32(self.partition);
+        buf.put_i64(self.timestamp);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(PartitionTimestamp {
+            topic: reader.get_string()?,
+            partition: reader.get_u32()?,
+            timestamp: reader.get_i64()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Requests the earliest offset of one or more partitions with a timestamp at
+/// or after a given time, judged by each topic's authoritative timestamp type.
+pub struct OffsetsForTimesRequest {
+    /// The partitions to look up.
+    pub partitions: Vec<PartitionTimestamp>,
+}
+
+impl Message for OffsetsForTimesRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_messages(buf, &self.partitions);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(OffsetsForTimesRequest {
+            partitions: get_messages(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Declares an exchange, succeeding without change if it already exists with
+/// the same type.
+pub struct DeclareExchangeRequest {
+    /// The name of the exchange.
+    pub name: String,
+    /// How the exchange routes records.
+    pub kind: ExchangeType,
+}
+
+impl Message for DeclareExchangeRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.name);
+        self.kind.encode(buf);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(DeclareExchangeRequest {
+            name: reader.get_string()?,
+            kind: ExchangeType::decode(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Deletes an exchange along with its bindings.
+pub struct DeleteExchangeRequest {
+    /// The name of the exchange.
+    pub name: String,
+}
+
+impl Message for DeleteExchangeRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.name);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(DeleteExchangeRequest {
+            name: reader.get_string()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Adds or removes a binding of an exchange to a destination topic, used by
+/// both [ApiKey::Bind] and [ApiKey::Unbind].
+pub struct BindRequest {
+    /// The binding to add or remove.
+    pub binding: Binding,
+}
+
+impl Message for BindRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.binding.encode(buf);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(BindRequest {
+            binding: Binding::decode(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Publishes a record to an exchange, which appends it to every destination
+/// topic of the exchange's matching bindings.
+pub struct PublishRequest {
+    /// The name of the exchange.
+    pub exchange: String,
+    /// The routing key bindings are matched against.
+    pub routing_key: String,
+    /// The record to route.
+    pub record: Record,
+}
+
+impl Message for PublishRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.exchange);
+        buf.put_string(&self.routing_key);
+        self.record.encode(buf);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(PublishRequest {
+            exchange: reader.get_string()?,
+            routing_key: reader.get_string()?,
+            record: Record::decode(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Looks up the node coordinating a consumer group.
+pub struct FindCoordinatorRequest {
+    /// The id of the consumer group.
+    pub group: String,
+}
+
+impl Message for FindCoordinatorRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.group);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(FindCoordinatorRequest {
+            group: reader.get_string()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Every request understood by the server.
+pub enum Request {
+    /// See [ApiKey::ApiVersions].
+    ApiVersions,
+    /// See [ApiKey::Metadata].
+    Metadata(MetadataRequest),
+    /// See [ApiKey::CreateTopic].
+    CreateTopic(CreateTopicRequest),
+    /// See [ApiKey::DeleteTopic].
+    DeleteTopic(DeleteTopicRequest),
+    /// See [ApiKey::Produce].
+    Produce(ProduceRequest),
+    /// See [ApiKey::Fetch].
+    Fetch(FetchRequest),
+    /// See [ApiKey::CommitOffset].
+    CommitOffset(CommitOffsetRequest),
+    /// See [ApiKey::Heartbeat].
+    Heartbeat,
+    /// See [ApiKey::JoinGroup].
+    JoinGroup(JoinGroupRequest),
+    /// See [ApiKey::GroupHeartbeat].
+    GroupHeartbeat(GroupMemberRequest),
+    /// See [ApiKey::LeaveGroup].
+    LeaveGroup(GroupMemberRequest),
+    /// See [ApiKey::FetchOffsets].
+    FetchOffsets(FetchOffsetsRequest),
+    /// See [ApiKey::ResetOffsets].
+    ResetOffsets(ResetOffsetsRequest),
+    /// See [ApiKey::RaftVote].
+    RaftVote(VoteRequest),
+    /// See [ApiKey::RaftAppend].
+    RaftAppend(AppendRequest),
+    /// See [ApiKey::RaftSnapshot].
+    RaftSnapshot(SnapshotRequest),
+    /// See [ApiKey::MetadataWrite].
+    MetadataWrite(MetadataWriteRequest),
+    /// See [ApiKey::InitProducer].
+    InitProducer(InitProducerRequest),
+    /// See [ApiKey::AddPartitionsToTxn].
+    AddPartitionsToTxn(AddPartitionsToTxnRequest),
+    /// See [ApiKey::EndTxn].
+    EndTxn(EndTxnRequest),
+    /// See [ApiKey::WriteTxnMarkers].
+    WriteTxnMarkers(WriteTxnMarkersRequest),
+    /// See [ApiKey::Lease].
+    Lease(LeaseRequest),
+    /// See [ApiKey::Ack].
+    Ack(AckRequest),
+    /// See [ApiKey::Nack].
+    Nack(NackRequest),
+    /// See [ApiKey::ReplayDeadLetters].
+    ReplayDeadLetters(ReplayDeadLettersRequest),
+    /// See [ApiKey::Schedule].
+    Schedule(ScheduleRequest),
+    /// See [ApiKey::OffsetsForTimes].
+    OffsetsForTimes(OffsetsForTimesRequest),
+    /// See [ApiKey::DeclareExchange].
+    DeclareExchange(DeclareExchangeRequest),
+    /// See [ApiKey::DeleteExchange].
+    DeleteExchange(DeleteExchangeRequest),
+    /// See [ApiKey::Bind].
+    Bind(BindRequest),
+    /// See [ApiKey::Unbind].
+    Unbind(BindRequest),
+    /// See [ApiKey::Publish].
+    Publish(PublishRequest),
+    /// See [ApiKey::CreateTemporaryQueue].
+    CreateTemporaryQueue,
+    /// See [ApiKey::FindCoordinator].
+    FindCoordinator(FindCoordinatorRequest),
+}
+
+impl Request {
+    /// Returns the api this request targets.
+    pub fn api_key(&self) -> ApiKey {
+        match self {
+            Request::ApiVersions => ApiKey::ApiVersions,
+            Request::Metadata(_) => ApiKey::Metadata,
+            Request::CreateTopic(_) => ApiKey::CreateTopic,
+            Request::DeleteTopic(_) => ApiKey::DeleteTopic,
+            Request::Produce(_) => ApiKey::Produce,
+            Request::Fetch(_) => ApiKey::Fetch,
+            Request::CommitOffset(_) => ApiKey::CommitOffset,
+            Request::Heartbeat => ApiKey::Heartbeat,
+            Request::JoinGroup(_) => ApiKey::JoinGroup,
+            Request::GroupHeartbeat(_) => ApiKey::GroupHeartbeat,
+            Request::LeaveGroup(_) => ApiKey::LeaveGroup,
+            Request::FetchOffsets(_) => ApiKey::FetchOffsets,
+            Request::ResetOffsets(_) => ApiKey::ResetOffsets,
+            Request::RaftVote(_) => ApiKey::RaftVote,
+            Request::RaftAppend(_) => ApiKey::RaftAppend,
+            Request::RaftSnapshot(_) => ApiKey::RaftSnapshot,
+            Request::MetadataWrite(_) => ApiKey::MetadataWrite,
+            Request::InitProducer(_) => ApiKey::InitProducer,
+            Request::AddPartitionsToTxn(_) => ApiKey::AddPartitionsToTxn,
+            Request::EndTxn(_) => ApiKey::EndTxn,
+            Request::WriteTxnMarkers(_) => ApiKey::WriteTxnMarkers,
+            Request::Lease(_) => ApiKey::Lease,
+            Request::Ack(_) => ApiKey::Ack,
+            Request::Nack(_) => ApiKey::Nack,
+            Request::ReplayDeadLetters(_) => ApiKey::ReplayDeadLetters,
+            Request::Schedule(_) => ApiKey::Schedule,
+            Request::OffsetsForTimes(_) => ApiKey::OffsetsForTimes,
+            Request::DeclareExchange(_) => ApiKey::DeclareExchange,
+            Request::DeleteExchange(_) => ApiKey::DeleteExchange,
+            Request::Bind(_) => ApiKey::Bind,
+            Request::Unbind(_) => ApiKey::Unbind,
+            Request::Publish(_) => ApiKey::Publish,
+            Request::CreateTemporaryQueue => ApiKey::CreateTemporaryQueue,
+            Request::FindCoordinator(_) => ApiKey::FindCoordinator,
+        }
+    }
+
+    /// Encode a complete request frame payload, header included.
+    pub fn encode(&self, correlation_id: u32) -> Vec<u8> {
+        let mut buf = Vec::new();
+        RequestHeader::new(self.api_key(), correlation_id).encode(&mut buf);
+        match self {
+            Request::ApiVersions | Request::Heartbeat | Request::CreateTemporaryQueue => {}
+            Request::Metadata(body) => body.encode(&mut buf),
+            Request::CreateTopic(body) => body.encode(&mut buf),
+            Request::DeleteTopic(body) => body.encode(&mut buf),
+            Request::Produce(body) => body.encode(&mut buf),
+            Request::Fetch(body) => body.encode(&mut buf),
+            Request::CommitOffset(body) => body.encode(&mut buf),
+            Request::JoinGroup(body) => body.encode(&mut buf),
+            Request::GroupHeartbeat(body) | Request::LeaveGroup(body) => body.encode(&mut buf),
+            Request::FetchOffsets(body) => body.encode(&mut buf),
+            Request::ResetOffsets(body) => body.encode(&mut buf),
+            Request::RaftVote(body) => body.encode(&mut buf),
+            Request::RaftAppend(body) => body.encode(&mut buf),
+            Request::RaftSnapshot(body) => body.encode(&mut buf),
+            Request::MetadataWrite(body) => body.encode(&mut buf),
+            Request::InitProducer(body) => body.encode(&mut buf),
+            Request::AddPartitionsToTxn(body) => body.encode(&mut buf),
+            Request::EndTxn(body) => body.encode(&mut buf),
+            Request::WriteTxnMarkers(body) => body.encode(&mut buf),
+            Request::Lease(body) => body.encode(&mut buf),
+            Request::Ack(body) => body.encode(&mut buf),
+            Request::Nack(body) => body.encode(&mut buf),
+            Request::ReplayDeadLetters(body) => body.encode(&mut buf),
+            Request::Schedule(body) => body.encode(&mut buf),
+            Request::OffsetsForTimes(body) => body.encode(&mut buf),
+            Request::DeclareExchange(body) => body.encode(&mut buf),
+            Request::DeleteExchange(body) => body.encode(&mut buf),
+            Request::Bind(body) | Request::Unbind(body) => body.encode(&mut buf),
+            Request::Publish(body) => body.encode(&mut buf),
+            Request::FindCoordinator(body) => body.encode(&mut buf),
+        }
+        buf
+    }
+
+    /// Decode the request body following the supplied header, ensuring the
+    /// targeted api and version are supported and no bytes are left over.
+    pub fn decode(header: &RequestHeader, reader: &mut Reader) -> Result<Request> {
+        let api_key = ApiKey::from_u16(header.api_key)?;
+        api_key.check_version(header.api_version)?;
+
+        let request = match api_key {
+            ApiKey::ApiVersions => Request::ApiVersions,
+            ApiKey::Metadata => Request::Metadata(Message::decode(reader)?),
+            ApiKey::CreateTopic => Request::CreateTopic(Message::decode(reader)?),
+            ApiKey::DeleteTopic => Request::DeleteTopic(Message::decode(reader)?),
+            ApiKey::Produce => Request::Produce(Message::decode(reader)?),
+            ApiKey::Fetch => Request::Fetch(Message::decode(reader)?),
+            ApiKey::CommitOffset => Request::CommitOffset(Message::decode(reader)?),
+            ApiKey::Heartbeat => Request::Heartbeat,
+            ApiKey::JoinGroup => Request::JoinGroup(Message::decode(reader)?),
+            ApiKey::GroupHeartbeat => Request::GroupHeartbeat(Message::decode(reader)?),
+            ApiKey::LeaveGroup => Request::LeaveGroup(Message::decode(reader)?),
+            ApiKey::FetchOffsets => Request::FetchOffsets(Message::decode(reader)?),
+            ApiKey::ResetOffsets => Request::ResetOffsets(Message::decode(reader)?),
+            ApiKey::RaftVote => Request::RaftVote(Message::decode(reader)?),
+            ApiKey::RaftAppend => Request::RaftAppend(Message::decode(reader)?),
+            ApiKey::RaftSnapshot => Request::RaftSnapshot(Message::decode(reader)?),
+            ApiKey::MetadataWrite => Request::MetadataWrite(Message::decode(reader)?),
+            ApiKey::InitProducer => Request::InitProducer(Message::decode(reader)?),
+            ApiKey::AddPartitionsToTxn => Request::AddPartitionsToTxn(Message::decode(reader)?),
+            ApiKey::EndTxn => Request::EndTxn(Message::decode(reader)?),
+            ApiKey::WriteTxnMarkers => Request::WriteTxnMarkers(Message::decode(reader)?),
+            ApiKey::Lease => Request::Lease(Message::decode(reader)?),
+            ApiKey::Ack => Request::Ack(Message::decode(reader)?),
+            ApiKey::Nack => Request::Nack(Message::decode(reader)?),
+            ApiKey::ReplayDeadLetters => Request::ReplayDeadLetters(Message::decode(reader)?),
+            ApiKey::Schedule => Request::Schedule(Message::decode(reader)?),
+            ApiKey::OffsetsForTimes => Request::OffsetsForTimes(Message::decode(reader)?),
+            ApiKey::DeclareExchange => Request::DeclareExchange(Message::decode(reader)?),
+            ApiKey::DeleteExchange => Request::DeleteExchange(Message::decode(reader)?),
+            ApiKey::Bind => Request::Bind(Message::decode(reader)?),
+            ApiKey::Unbind => Request::Unbind(Message::decode(reader)?),
+            ApiKey::Publish => Request::Publish(Message::decode(reader)?),
+            ApiKey::CreateTemporaryQueue => Request::CreateTemporaryQueue,
+            ApiKey::FindCoordinator => Request::FindCoordinator(Message::decode(reader)?),
+        };
+        if !reader.is_empty() {
+            return Err(Error::TrailingBytes {
+                remaining: reader.remaining(),
+            });
+        }
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    fn round_trip(request: Request) {
+        let buf = request.encode(42);
+        let mut reader = Reader::new(&buf);
+        let header = RequestHeader::decode(&mut reader).unwrap();
+        assert_eq!(42, header.correlation_id);
+        assert_eq!(request.api_key().as_u16(), header.api_key);
+        assert_eq!(request, Request::decode(&header, &mut reader).unwrap());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        round_trip(Request::ApiVersions);
+        round_trip(Request::Heartbeat);
+        round_trip(Request::CreateTemporaryQueue);
+        round_trip(Request::FindCoordinator(FindCoordinatorRequest {
+            group: String::from("group"),
+        }));
+        round_trip(Request::InitProducer(InitProducerRequest {
+            transactional_id: None,
+            transaction_timeout_ms: 0,
+        }));
+        round_trip(Request::InitProducer(InitProducerRequest {
+            transactional_id: Some(String::from("txn")),
+            transaction_timeout_ms: 60000,
+        }));
+        round_trip(Request::AddPartitionsToTxn(AddPartitionsToTxnRequest {
+            transactional_id: String::from("txn"),
+            producer_id: 7,
+            epoch: 2,
+            partitions: vec![TopicPartition::new("events", 1)],
+        }));
+        round_trip(Request::EndTxn(EndTxnRequest {
+            transactional_id: String::from("txn"),
+            producer_id: 7,
+            epoch: 2,
+            commit: true,
+        }));
+        round_trip(Request::WriteTxnMarkers(WriteTxnMarkersRequest {
+            producer_id: 7,
+            epoch: 2,
+            marker: ControlMarker::Abort,
+            partitions: vec![TopicPartition::new("events", 1)],
+        }));
+        round_trip(Request::Lease(LeaseRequest {
+            queue: String::from("jobs"),
+            consumer_id: String::from("worker"),
+            topic: String::from("events"),
+            partitions: vec![0, 2],
+            max_messages: 10,
+            visibility_timeout_ms: 30000,
+        }));
+        round_trip(Request::Ack(AckRequest {
+            queue: String::from("jobs"),
+            consumer_id: String::from("worker"),
+            topic: String::from("events"),
+            partition: 2,
+            offsets: vec![4, 9],
+        }));
+        round_trip(Request::Nack(NackRequest {
+            queue: String::from("jobs"),
+            consumer_id: String::from("worker"),
+            topic: String::from("events"),
+            partition: 2,
+            offsets: vec![4, 9],
+            error: String::from("timed out calling the payments service"),
+        }));
+        round_trip(Request::ReplayDeadLetters(ReplayDeadLettersRequest {
+            topic: String::from("events-dlq"),
+            partition: 1,
+            max_messages: 100,
+        }));
+        round_trip(Request::Schedule(ScheduleRequest {
+            topic: String::from("events"),
+            partition: 1,
+            records: vec![
+                ScheduledRecord::at(1234, Record::new("a").with_header("trace", "abc")),
+                ScheduledRecord::after(5000, Record::new("b")),
+            ],
+        }));
+        round_trip(Request::OffsetsForTimes(OffsetsForTimesRequest {
+            partitions: vec![PartitionTimestamp {
+                topic: String::from("events"),
+                partition: 3,
+                timestamp: 1_600_000_000_000,
+            }],
+        }));
+        round_trip(Request::DeclareExchange(DeclareExchangeRequest {
+            name: String::from("events"),
+            kind: ExchangeType::Headers,
+        }));
+        round_trip(Request::DeleteExchange(DeleteExchangeRequest {
+            name: String::from("events"),
+        }));
+        let binding = Binding::new("events", "orders", "orders.#")
+            .with_argument(crate::exchange::MATCH_ARGUMENT, "any")
+            .with_argument("region", "eu");
+        round_trip(Request::Bind(BindRequest {
+            binding: binding.clone(),
+        }));
+        round_trip(Request::Unbind(BindRequest { binding }));
+        round_trip(Request::Publish(PublishRequest {
+            exchange: String::from("events"),
+            routing_key: String::from("orders.eu.created"),
+            record: Record::new("a").with_header("region", "eu"),
+        }));
+        round_trip(Request::Metadata(MetadataRequest {
+            topics: vec![String::from("a"), String::from("b")],
+        }));
+        round_trip(Request::CreateTopic(CreateTopicRequest {
+            name: String::from("events"),
+            partitions: 3,
+            config: vec![(String::from("segment.bytes"), String::from("1024"))],
+        }));
+        round_trip(Request::DeleteTopic(DeleteTopicRequest {
+            name: String::from("events"),
+        }));
+        round_trip(Request::Produce(ProduceRequest {
+            topic: String::from("events"),
+            partitioning: Partitioning::Explicit(1),
+            records: vec![Record::new("a").with_key("k"), Record::default()],
+            producer: None,
+            compression: Compression::None,
+        }));
+        round_trip(Request::Produce(ProduceRequest {
+            topic: String::from("events"),
+            partitioning: Partitioning::Explicit(0),
+            records: vec![Record::new("a")],
+            producer: Some(ProducerBatch {
+                producer_id: 1 << 40,
+                epoch: 3,
+                sequence: 7,
+                transactional: false,
+            }),
+            compression: Compression::Snappy,
+        }));
+        round_trip(Request::Fetch(FetchRequest {
+            partitions: vec![FetchPartition {
+                topic: String::from("events"),
+                partition: 1,
+                offset: 10,
+                max_bytes: 1024,
+            }],
+            isolation: IsolationLevel::ReadCommitted,
+            selector: String::from("region = 'eu'"),
+        }));
+        round_trip(Request::CommitOffset(CommitOffsetRequest {
+            group: String::from("group"),
+            offsets: vec![PartitionOffset {
+                topic: String::from("events"),
+                partition: 1,
+                offset: 10,
+            }],
+        }));
+        round_trip(Request::JoinGroup(JoinGroupRequest {
+            group: String::from("group"),
+            member_id: String::new(),
+            session_timeout_ms: 10000,
+            topics: vec![String::from("events")],
+            strategies: vec![String::from("sticky"), String::from("range")],
+        }));
+        let member = GroupMemberRequest {
+            group: String::from("group"),
+            member_id: String::from("member"),
+        };
+        round_trip(Request::GroupHeartbeat(member.clone()));
+        round_trip(Request::LeaveGroup(member));
+        round_trip(Request::FetchOffsets(FetchOffsetsRequest {
+            group: String::from("group"),
+            partitions: vec![TopicPartition::new("events", 1)],
+        }));
+        round_trip(Request::ResetOffsets(ResetOffsetsRequest {
+            group: String::from("group"),
+            partitions: Vec::new(),
+            reset: OffsetReset::Timestamp(1000),
+        }));
+        round_trip(Request::RaftVote(VoteRequest {
+            partition: TopicPartition::new("events", 1),
+            term: 3,
+            candidate: 2,
+            last_offset: 10,
+            last_term: 2,
+        }));
+        let mut entry = crate::storage::Entry::new(1, 5, b"payload".to_vec());
+        entry.base_offset = 10;
+        round_trip(Request::RaftAppend(AppendRequest {
+            partition: TopicPartition::new("events", 1),
+            term: 3,
+            leader: 1,
+            prev_offset: 10,
+            prev_term: 2,
+            leader_commit: 8,
+            entries: vec![ReplicatedEntry { term: 3, entry }],
+        }));
+        round_trip(Request::RaftSnapshot(SnapshotRequest {
+            partition: TopicPartition::new("events", 1),
+            term: 3,
+            leader: 1,
+            start_offset: 100,
+            start_term: 2,
+        }));
+        round_trip(Request::MetadataWrite(MetadataWriteRequest {
+            record: Record::new("{}").with_key("node/1"),
+        }));
+    }
+
+    #[test]
+    fn test_api_key() {
+        for key in ApiKey::ALL {
+            assert_eq!(*key, ApiKey::from_u16(key.as_u16()).unwrap());
+        }
+        assert!(matches!(
+            ApiKey::from_u16(999),
+            Err(Error::UnknownApi { key: 999 })
+        ));
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let header = RequestHeader {
+            api_key: 999,
+            api_version: 0,
+            correlation_id: 1,
+        };
+        assert!(matches!(
+            Request::decode(&header, &mut Reader::new(&[])),
+            Err(Error::UnknownApi { key: 999 })
+        ));
+
+        let header = RequestHeader {
+            api_key: ApiKey::Heartbeat.as_u16(),
+            api_version: 9,
+            correlation_id: 1,
+        };
+        assert!(matches!(
+            Request::decode(&header, &mut Reader::new(&[])),
+            Err(Error::UnsupportedVersion { version: 9, .. })
+        ));
+
+        let header = RequestHeader::new(ApiKey::Heartbeat, 1);
+        assert!(matches!(
+            Request::decode(&header, &mut Reader::new(&[1])),
+            Err(Error::TrailingBytes { remaining: 1 })
+        ));
+
+        let header = RequestHeader::new(ApiKey::DeleteTopic, 1);
+        assert!(matches!(
+            Request::decode(&header, &mut Reader::new(&[0, 5, b'a'])),
+            Err(Error::Codec(codec::Error::UnexpectedEof { .. }))
+        ));
+    }
+}
@@ -0,0 +1,1181 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fmt;
+
+use crate::codec::{self, Reader, Writer};
+use crate::record::OffsetRecord;
+use crate::topic::TopicPartition;
+
+use super::code::ErrorCode;
+use super::error::{Error, Result};
+use super::message::{get_messages, put_messages, Message, PartitionOffset};
+use super::request::ApiKey;
+
+#[derive(Debug, Clone, PartialEq)]
+/// A request level failure reported in place of a response body.
+pub struct ResponseError {
+    /// The error code describing the failure.
+    pub code: ErrorCode,
+    /// A human readable explanation of the failure.
+    pub message: String,
+}
+
+impl ResponseError {
+    /// Create a new error response.
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> ResponseError {
+        ResponseError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ResponseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for ResponseError {}
+
+impl From<&Error> for ResponseError {
+    fn from(err: &Error) -> Self {
+        ResponseError::new(err.code(), err.to_string())
+    }
+}
+
+impl Message for ErrorCode {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_i16(self.as_i16());
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(ErrorCode::from_i16(reader.get_i16()?))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The range of versions supported for a single api.
+pub struct ApiVersion {
+    /// The raw api key.
+    pub api_key: u16,
+    /// The oldest supported version.
+    pub min_version: u16,
+    /// The newest supported version.
+    pub max_version: u16,
+}
+
+impl Message for ApiVersion {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u16(self.api_key);
+        buf.put_u16(self.min_version);
+        buf.put_u16(self.max_version);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(ApiVersion {
+            api_key: reader.get_u16()?,
+            min_version: reader.get_u16()?,
+            max_version: reader.get_u16()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Lists every api supported by the server.
+pub struct ApiVersionsResponse {
+    /// The supported apis.
+    pub apis: Vec<ApiVersion>,
+}
+
+impl ApiVersionsResponse {
+    /// Returns the apis supported by this build.
+    pub fn supported() -> ApiVersionsResponse {
+        ApiVersionsResponse {
+            apis: ApiKey::ALL
+                .iter()
+                .map(|key| ApiVersion {
+                    api_key: key.as_u16(),
+                    min_version: key.min_version(),
+                    max_version: key.max_version(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Message for ApiVersionsResponse {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_messages(buf, &self.apis);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(ApiVersionsResponse {
+            apis: get_messages(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Describes a single partition.
+pub struct PartitionMetadata {
+    /// The partition id.
+    pub id: u32,
+    /// The first offset retained by the partition.
+    pub start_offset: u64,
+    /// The offset that will be assigned to the next record.
+    pub next_offset: u64,
+    /// The id of the node leading the partition, if known. Always empty when
+    /// the server is not part of a cluster.
+    pub leader: Option<u32>,
+    /// The ids of the nodes holding a replica of the partition.
+    pub replicas: Vec<u32>,
+    /// The ids of the replicas caught up with the leader.
+    pub isr: Vec<u32>,
+}
+
+impl Message for PartitionMetadata {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u32(self.id);
+        buf.put_u64(self.start_offset);
+        buf.put_u64(self.next_offset);
+        buf.put_bool(self.leader.is_some());
+        buf.put_u32(self.leader.unwrap_or_default());
+        put_messages(buf, &self.replicas);
+        put_messages(buf, &self.isr);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(PartitionMetadata {
+            id: reader.get_u32()?,
+            start_offset: reader.get_u64()?,
+            next_offset: reader.get_u64()?,
+            leader: match (reader.get_bool()?, reader.get_u32()?) {
+                (true, leader) => Some(leader),
+                (false, _) => None,
+            },
+            replicas: get_messages(reader)?,
+            isr: get_messages(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Describes a single node of the cluster.
+pub struct NodeMetadata {
+    /// The id of the node.
+    pub id: u32,
+    /// The address the node serves the native protocol on.
+    pub address: String,
+}
+
+impl Message for NodeMetadata {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u32(self.id);
+        buf.put_string(&self.address);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(NodeMetadata {
+            id: reader.get_u32()?,
+            address: reader.get_string()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Describes a single topic.
+pub struct TopicMetadata {
+    /// The name of the topic.
+    pub name: String,
+    /// Whether or not the topic could be described.
+    pub error_code: ErrorCode,
+    /// The topic's partitions.
+    pub partitions: Vec<PartitionMetadata>,
+}
+
+impl Message for TopicMetadata {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.name);
+        self.error_code.encode(buf);
+        put_messages(buf, &self.partitions);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(TopicMetadata {
+            name: reader.get_string()?,
+            error_code: ErrorCode::decode(reader)?,
+            partitions: get_messages(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Describes the requested topics, and the nodes of the cluster serving them.
+pub struct MetadataResponse {
+    /// The nodes of the cluster, empty when the server is not part of a cluster.
+    pub nodes: Vec<NodeMetadata>,
+    /// The described topics.
+    pub topics: Vec<TopicMetadata>,
+}
+
+impl Message for MetadataResponse {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_messages(buf, &self.nodes);
+        put_messages(buf, &self.topics);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(MetadataResponse {
+            nodes: get_messages(reader)?,
+            topics: get_messages(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The location a produced record was written to.
+pub struct ProducedRecord {
+    /// The partition the record was written to.
+    pub partition: u32,
+    /// The offset assigned to the record.
+    pub offset: u64,
+}
+
+impl Message for ProducedRecord {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u32(self.partition);
+        buf.put_u64(self.offset);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(ProducedRecord {
+            partition: reader.get_u32()?,
+            offset: reader.get_u64()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Reports where each produced record was written, in request order.
+pub struct ProduceResponse {
+    /// The location of each record.
+    pub records: Vec<ProducedRecord>,
+}
+
+impl Message for ProduceResponse {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_messages(buf, &self.records);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(ProduceResponse {
+            records: get_messages(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The records read from a single partition.
+pub struct FetchedPartition {
+    /// The name of the topic.
+    pub topic: String,
+    /// The partition within the topic.
+    pub partition: u32,
+    /// Whether or not the partition could be read.
+    pub error_code: ErrorCode,
+    /// The offset that will be assigned to the partition's next record.
+    pub high_watermark: u64,
+    /// The offset below which every transaction written to the partition has completed.
+    pub last_stable_offset: u64,
+    /// The offset following the last one read, which the next fetch should
+    /// start from. Records that were read but not returned, such as those not
+    /// matching a selector, are behind it.
+    pub next_offset: u64,
+    /// The records read.
+    pub records: Vec<OffsetRecord>,
+}
+
+impl Message for FetchedPartition {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.topic);
+        buf.put_u32(self.partition);
+        self.error_code.encode(buf);
+        buf.put_u64(self.high_watermark);
+        buf.put_u64(self.last_stable_offset);
+        buf.put_u64(self.next_offset);
+        put_messages(buf, &self.records);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(FetchedPartition {
+            topic: reader.get_string()?,
+            partition: reader.get_u32()?,
+            error_code: ErrorCode::decode(reader)?,
+            high_watermark: reader.get_u64()?,
+            last_stable_offset: reader.get_u64()?,
+            next_offset: reader.get_u64()?,
+            records: get_messages(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The records read from each requested partition.
+pub struct FetchResponse {
+    /// The per partition results, in request order.
+    pub partitions: Vec<FetchedPartition>,
+}
+
+impl Message for FetchResponse {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_messages(buf, &self.partitions);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(FetchResponse {
+            partitions: get_messages(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A consumer group member's current assignment, returned when joining and heartbeating.
+pub struct GroupAssignmentResponse {
+    /// The id of the member.
+    pub member_id: String,
+    /// The generation of the group's current assignment.
+    pub generation: u32,
+    /// The assignment strategy in use by the group.
+    pub strategy: String,
+    /// The partitions assigned to the member.
+    pub assignment: Vec<TopicPartition>,
+}
+
+impl Message for GroupAssignmentResponse {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.member_id);
+        buf.put_u32(self.generation);
+        buf.put_string(&self.strategy);
+        put_messages(buf, &self.assignment);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(GroupAssignmentResponse {
+            member_id: reader.get_string()?,
+            generation: reader.get_u32()?,
+            strategy: reader.get_string()?,
+            assignment: get_messages(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The committed offsets of a consumer group, returned when fetching or resetting them.
+pub struct OffsetsResponse {
+    /// The committed offsets.
+    pub offsets: Vec<PartitionOffset>,
+}
+
+impl Message for OffsetsResponse {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_messages(buf, &self.offsets);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(OffsetsResponse {
+            offsets: get_messages(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// A replica's answer to a [crate::protocol::VoteRequest].
+pub struct VoteResponse {
+    /// The replica's current term, for the candidate to update itself.
+    pub term: u64,
+    /// Whether or not the replica voted for the candidate.
+    pub granted: bool,
+}
+
+impl Message for VoteResponse {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u64(self.term);
+        buf.put_bool(self.granted);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(VoteResponse {
+            term: reader.get_u64()?,
+            granted: reader.get_bool()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// A follower's answer to a [crate::protocol::AppendRequest] or
+/// [crate::protocol::SnapshotRequest].
+pub struct AppendResponse {
+    /// The follower's current term, for the leader to update itself.
+    pub term: u64,
+    /// Whether or not the follower's log now matches the leader's.
+    pub success: bool,
+    /// On success, the offset up to which the follower's log matches the
+    /// leader's. On failure, the offset the leader should retry from at most.
+    pub next_offset: u64,
+    /// On failure, the term of the follower's conflicting record, which the
+    /// leader uses to skip the rest of that term, or 0 if the follower's log is
+    /// simply too short.
+    pub conflict_term: u64,
+}
+
+impl Message for AppendResponse {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u64(self.term);
+        buf.put_bool(self.success);
+        buf.put_u64(self.next_offset);
+        buf.put_u64(self.conflict_term);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(AppendResponse {
+            term: reader.get_u64()?,
+            success: reader.get_bool()?,
+            next_offset: reader.get_u64()?,
+            conflict_term: reader.get_u64()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The id and epoch assigned to a new idempotent or transactional producer.
+pub struct InitProducerResponse {
+    /// The id assigned to the producer.
+    pub producer_id: u64,
+    /// The producer's epoch.
+    pub epoch: u16,
+}
+
+impl Message for InitProducerResponse {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u64(self.producer_id);
+        buf.put_u16(self.epoch);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(InitProducerResponse {
+            producer_id: reader.get_u64()?,
+            epoch: reader.get_u16()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A message leased to a queue consumer.
+pub struct LeasedMessage {
+    /// The partition the message was read from.
+    pub partition: u32,
+    /// How many times the message has been delivered, this delivery included.
+    pub delivery_count: u32,
+    /// The message and its offset.
+    pub record: OffsetRecord,
+}
+
+impl Message for LeasedMessage {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u32(self.partition);
+        buf.put_u32(self.delivery_count);
+        self.record.encode(buf);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(LeasedMessage {
+            partition: reader.get_u32()?,
+            delivery_count: reader.get_u32()?,
+            record: OffsetRecord::decode(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The messages leased to a queue consumer.
+pub struct LeaseResponse {
+    /// The leased messages, which may be fewer than requested.
+    pub messages: Vec<LeasedMessage>,
+}
+
+impl Message for LeaseResponse {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_messages(buf, &self.messages);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(LeaseResponse {
+            messages: get_messages(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The result of replaying a dead-letter topic partition.
+pub struct ReplayDeadLettersResponse {
+    /// How many messages were moved back to the partitions they came from.
+    pub replayed: u32,
+}
+
+impl Message for ReplayDeadLettersResponse {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u32(self.replayed);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(ReplayDeadLettersResponse {
+            replayed: reader.get_u32()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A record accepted for delivery at a future time.
+pub struct ScheduledMessage {
+    /// The id the server assigned the scheduled record.
+    pub id: u64,
+    /// When the record will be delivered, in milliseconds since the epoch.
+    pub deliver_at_ms: i64,
+}
+
+impl Message for ScheduledMessage {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u64(self.id);
+        buf.put_i64(self.deliver_at_ms);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(ScheduledMessage {
+            id: reader.get_u64()?,
+            deliver_at_ms: reader.get_i64()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The records accepted for delivery at a future time.
+pub struct ScheduleResponse {
+    /// Each scheduled record, in the order they were requested.
+    pub messages: Vec<ScheduledMessage>,
+}
+
+impl Message for ScheduleResponse {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_messages(buf, &self.messages);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(ScheduleResponse {
+            messages: get_messages(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The offset found for a single partition by an [crate::protocol::OffsetsForTimesRequest].
+pub struct TimestampOffset {
+    /// The name of the topic.
+    pub topic: String,
+    /// The partition within the topic.
+    pub partition: u32,
+    /// Whether or not the partition could be looked up.
+    pub error_code: ErrorCode,
+    /// The earliest offset with a timestamp at or after the requested time, or
+    /// the partition's high watermark if there is no such record.
+    pub offset: u64,
+}
+
+impl Message for TimestampOffset {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.topic);
+        buf.put_u32(self.partition);
+        self.error_code.encode(buf);
+        buf.put_u64(self.offset);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(TimestampOffset {
+            topic: reader.get_string()?,
+            partition: reader.get_u32()?,
+            error_code: ErrorCode::decode(reader)?,
+            offset: reader.get_u64()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The offsets found for each requested partition.
+pub struct OffsetsForTimesResponse {
+    /// The per partition results, in request order.
+    pub partitions: Vec<TimestampOffset>,
+}
+
+impl Message for OffsetsForTimesResponse {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_messages(buf, &self.partitions);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(OffsetsForTimesResponse {
+            partitions: get_messages(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Where a published record was appended.
+pub struct RoutedRecord {
+    /// The destination topic.
+    pub topic: String,
+    /// The partition the record was appended to.
+    pub partition: u32,
+    /// The offset the record was appended at.
+    pub offset: u64,
+}
+
+impl Message for RoutedRecord {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.topic);
+        buf.put_u32(self.partition);
+        buf.put_u64(self.offset);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(RoutedRecord {
+            topic: reader.get_string()?,
+            partition: reader.get_u32()?,
+            offset: reader.get_u64()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The topics a published record was routed to.
+pub struct PublishResponse {
+    /// One entry per destination topic, in binding order. Empty when no
+    /// binding matched.
+    pub records: Vec<RoutedRecord>,
+}
+
+impl Message for PublishResponse {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_messages(buf, &self.records);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(PublishResponse {
+            records: get_messages(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The temporary queue created for a connection's replies.
+pub struct TemporaryQueueResponse {
+    /// The name of the topic replies are produced to.
+    pub topic: String,
+}
+
+impl Message for TemporaryQueueResponse {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_string(&self.topic);
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        Ok(TemporaryQueueResponse {
+            topic: reader.get_string()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The node coordinating a consumer group.
+pub struct CoordinatorResponse {
+    /// The coordinating node, or none when the server is not part of a cluster
+    /// and so coordinates every group itself.
+    pub coordinator: Option<NodeMetadata>,
+}
+
+impl Message for CoordinatorResponse {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_bool(self.coordinator.is_some());
+        if let Some(coordinator) = &self.coordinator {
+            coordinator.encode(buf);
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> codec::Result<Self> {
+        let coordinator = match reader.get_bool()? {
+            true => Some(NodeMetadata::decode(reader)?),
+            false => None,
+        };
+        Ok(CoordinatorResponse { coordinator })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Every response returned by the server.
+pub enum Response {
+    /// See [ApiKey::ApiVersions].
+    ApiVersions(ApiVersionsResponse),
+    /// See [ApiKey::Metadata].
+    Metadata(MetadataResponse),
+    /// See [ApiKey::CreateTopic].
+    CreateTopic,
+    /// See [ApiKey::DeleteTopic].
+    DeleteTopic,
+    /// See [ApiKey::Produce].
+    Produce(ProduceResponse),
+    /// See [ApiKey::Fetch].
+    Fetch(FetchResponse),
+    /// See [ApiKey::CommitOffset].
+    CommitOffset,
+    /// See [ApiKey::Heartbeat].
+    Heartbeat,
+    /// See [ApiKey::JoinGroup].
+    JoinGroup(GroupAssignmentResponse),
+    /// See [ApiKey::GroupHeartbeat].
+    GroupHeartbeat(GroupAssignmentResponse),
+    /// See [ApiKey::LeaveGroup].
+    LeaveGroup,
+    /// See [ApiKey::FetchOffsets].
+    FetchOffsets(OffsetsResponse),
+    /// See [ApiKey::ResetOffsets].
+    ResetOffsets(OffsetsResponse),
+    /// See [ApiKey::RaftVote].
+    RaftVote(VoteResponse),
+    /// See [ApiKey::RaftAppend].
+    RaftAppend(AppendResponse),
+    /// See [ApiKey::RaftSnapshot].
+    RaftSnapshot(AppendResponse),
+    /// See [ApiKey::MetadataWrite].
+    MetadataWrite,
+    /// See [ApiKey::InitProducer].
+    InitProducer(InitProducerResponse),
+    /// See [ApiKey::AddPartitionsToTxn].
+    AddPartitionsToTxn,
+    /// See [ApiKey::EndTxn].
+    EndTxn,
+    /// See [ApiKey::WriteTxnMarkers].
+    WriteTxnMarkers,
+    /// See [ApiKey::Lease].
+    Lease(LeaseResponse),
+    /// See [ApiKey::Ack].
+    Ack,
+    /// See [ApiKey::Nack].
+    Nack,
+    /// See [ApiKey::ReplayDeadLetters].
+    ReplayDeadLetters(ReplayDeadLettersResponse),
+    /// See [ApiKey::Schedule].
+    Schedule(ScheduleResponse),
+    /// See [ApiKey::OffsetsForTimes].
+    OffsetsForTimes(OffsetsForTimesResponse),
+    /// See [ApiKey::DeclareExchange].
+    DeclareExchange,
+    /// See [ApiKey::DeleteExchange].
+    DeleteExchange,
+    /// See [ApiKey::Bind].
+    Bind,
+    /// See [ApiKey::Unbind].
+    Unbind,
+    /// See [ApiKey::Publish].
+    Publish(PublishResponse),
+    /// See [ApiKey::CreateTemporaryQueue].
+    CreateTemporaryQueue(TemporaryQueueResponse),
+    /// See [ApiKey::FindCoordinator].
+    FindCoordinator(CoordinatorResponse),
+}
+
+impl Response {
+    /// Returns the api this response answers.
+    pub fn api_key(&self) -> ApiKey {
+        match self {
+            Response::ApiVersions(_) => ApiKey::ApiVersions,
+            Response::Metadata(_) => ApiKey::Metadata,
+            Response::CreateTopic => ApiKey::CreateTopic,
+            Response::DeleteTopic => ApiKey::DeleteTopic,
+            Response::Produce(_) => ApiKey::Produce,
+            Response::Fetch(_) => ApiKey::Fetch,
+            Response::CommitOffset => ApiKey::CommitOffset,
+            Response::Heartbeat => ApiKey::Heartbeat,
+            Response::JoinGroup(_) => ApiKey::JoinGroup,
+            Response::GroupHeartbeat(_) => ApiKey::GroupHeartbeat,
+            Response::LeaveGroup => ApiKey::LeaveGroup,
+            Response::FetchOffsets(_) => ApiKey::FetchOffsets,
+            Response::ResetOffsets(_) => ApiKey::ResetOffsets,
+            Response::RaftVote(_) => ApiKey::RaftVote,
+            Response::RaftAppend(_) => ApiKey::RaftAppend,
+            Response::RaftSnapshot(_) => ApiKey::RaftSnapshot,
+            Response::MetadataWrite => ApiKey::MetadataWrite,
+            Response::InitProducer(_) => ApiKey::InitProducer,
+            Response::AddPartitionsToTxn => ApiKey::AddPartitionsToTxn,
+            Response::EndTxn => ApiKey::EndTxn,
+            Response::WriteTxnMarkers => ApiKey::WriteTxnMarkers,
+            Response::Lease(_) => ApiKey::Lease,
+            Response::Ack => ApiKey::Ack,
+            Response::Nack => ApiKey::Nack,
+            Response::ReplayDeadLetters(_) => ApiKey::ReplayDeadLetters,
+            Response::Schedule(_) => ApiKey::Schedule,
+            Response::OffsetsForTimes(_) => ApiKey::OffsetsForTimes,
+            Response::DeclareExchange => ApiKey::DeclareExchange,
+            Response::DeleteExchange => ApiKey::DeleteExchange,
+            Response::Bind => ApiKey::Bind,
+            Response::Unbind => ApiKey::Unbind,
+            Response::Publish(_) => ApiKey::Publish,
+            Response::CreateTemporaryQueue(_) => ApiKey::CreateTemporaryQueue,
+            Response::FindCoordinator(_) => ApiKey::FindCoordinator,
+        }
+    }
+
+    /// Encode a complete response frame payload for either a successful
+    /// response or a request level error.
+    pub fn encode(
+        correlation_id: u32,
+        response: &std::result::Result<Response, ResponseError>,
+    ) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.put_u32(correlation_id);
+        match response {
+            Err(err) => {
+                err.code.encode(&mut buf);
+                buf.put_string(&err.message);
+            }
+            Ok(response) => {
+                ErrorCode::None.encode(&mut buf);
+                match response {
+                    Response::CreateTopic
+                    | Response::DeleteTopic
+                    | Response::CommitOffset
+                    | Response::Heartbeat
+                    | Response::LeaveGroup
+                    | Response::MetadataWrite
+                    | Response::AddPartitionsToTxn
+                    | Response::EndTxn
+                    | Response::WriteTxnMarkers
+                    | Response::Ack
+                    | Response::Nack
+                    | Response::DeclareExchange
+                    | Response::DeleteExchange
+                    | Response::Bind
+                    | Response::Unbind => {}
+                    Response::ApiVersions(body) => body.encode(&mut buf),
+                    Response::Metadata(body) => body.encode(&mut buf),
+                    Response::Produce(body) => body.encode(&mut buf),
+                    Response::Fetch(body) => body.encode(&mut buf),
+                    Response::JoinGroup(body) | Response::GroupHeartbeat(body) => {
+                        body.encode(&mut buf)
+                    }
+                    Response::FetchOffsets(body) | Response::ResetOffsets(body) => {
+                        body.encode(&mut buf)
+                    }
+                    Response::RaftVote(body) => body.encode(&mut buf),
+                    Response::RaftAppend(body) | Response::RaftSnapshot(body) => {
+                        body.encode(&mut buf)
+                    }
+                    Response::InitProducer(body) => body.encode(&mut buf),
+                    Response::Lease(body) => body.encode(&mut buf),
+                    Response::ReplayDeadLetters(body) => body.encode(&mut buf),
+                    Response::Schedule(body) => body.encode(&mut buf),
+                    Response::OffsetsForTimes(body) => body.encode(&mut buf),
+                    Response::Publish(body) => body.encode(&mut buf),
+                    Response::CreateTemporaryQueue(body) => body.encode(&mut buf),
+                    Response::FindCoordinator(body) => body.encode(&mut buf),
+                }
+            }
+        }
+        buf
+    }
+
+    /// Decode the correlation id of a response frame, which callers use to
+    /// determine which api the remainder of the frame answers.
+    pub fn decode_correlation_id(reader: &mut Reader) -> Result<u32> {
+        Ok(reader.get_u32()?)
+    }
+
+    /// Decode the remainder of a response frame answering the supplied api.
+    pub fn decode(
+        api_key: ApiKey,
+        reader: &mut Reader,
+    ) -> Result<std::result::Result<Response, ResponseError>> {
+        let code = ErrorCode::decode(reader)?;
+        let response = if code != ErrorCode::None {
+            Err(ResponseError::new(code, reader.get_string()?))
+        } else {
+            Ok(match api_key {
+                ApiKey::ApiVersions => Response::ApiVersions(Message::decode(reader)?),
+                ApiKey::Metadata => Response::Metadata(Message::decode(reader)?),
+                ApiKey::CreateTopic => Response::CreateTopic,
+                ApiKey::DeleteTopic => Response::DeleteTopic,
+                ApiKey::Produce => Response::Produce(Message::decode(reader)?),
+                ApiKey::Fetch => Response::Fetch(Message::decode(reader)?),
+                ApiKey::CommitOffset => Response::CommitOffset,
+                ApiKey::Heartbeat => Response::Heartbeat,
+                ApiKey::JoinGroup => Response::JoinGroup(Message::decode(reader)?),
+                ApiKey::GroupHeartbeat => Response::GroupHeartbeat(Message::decode(reader)?),
+                ApiKey::LeaveGroup => Response::LeaveGroup,
+                ApiKey::FetchOffsets => Response::FetchOffsets(Message::decode(reader)?),
+                ApiKey::ResetOffsets => Response::ResetOffsets(Message::decode(reader)?),
+                ApiKey::RaftVote => Response::RaftVote(Message::decode(reader)?),
+                ApiKey::RaftAppend => Response::RaftAppend(Message::decode(reader)?),
+                ApiKey::RaftSnapshot => Response::RaftSnapshot(Message::decode(reader)?),
+                ApiKey::MetadataWrite => Response::MetadataWrite,
+                ApiKey::InitProducer => Response::InitProducer(Message::decode(reader)?),
+                ApiKey::AddPartitionsToTxn => Response::AddPartitionsToTxn,
+                ApiKey::EndTxn => Response::EndTxn,
+                ApiKey::WriteTxnMarkers => Response::WriteTxnMarkers,
+                ApiKey::Lease => Response::Lease(Message::decode(reader)?),
+                ApiKey::Ack => Response::Ack,
+                ApiKey::Nack => Response::Nack,
+                ApiKey::ReplayDeadLetters => Response::ReplayDeadLetters(Message::decode(reader)?),
+                ApiKey::Schedule => Response::Schedule(Message::decode(reader)?),
+                ApiKey::OffsetsForTimes => Response::OffsetsForTimes(Message::decode(reader)?),
+                ApiKey::DeclareExchange => Response::DeclareExchange,
+                ApiKey::DeleteExchange => Response::DeleteExchange,
+                ApiKey::Bind => Response::Bind,
+                ApiKey::Unbind => Response::Unbind,
+                ApiKey::Publish => Response::Publish(Message::decode(reader)?),
+                ApiKey::CreateTemporaryQueue => {
+                    Response::CreateTemporaryQueue(Message::decode(reader)?)
+                }
+                ApiKey::FindCoordinator => Response::FindCoordinator(Message::decode(reader)?),
+            })
+        };
+        if !reader.is_empty() {
+            return Err(Error::TrailingBytes {
+                remaining: reader.remaining(),
+            });
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+    use crate::record::Record;
+
+    fn round_trip(api_key: ApiKey, response: std::result::Result<Response, ResponseError>) {
+        let buf = Response::encode(7, &response);
+        let mut reader = Reader::new(&buf);
+        assert_eq!(7, Response::decode_correlation_id(&mut reader).unwrap());
+        assert_eq!(response, Response::decode(api_key, &mut reader).unwrap());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        round_trip(
+            ApiKey::ApiVersions,
+            Ok(Response::ApiVersions(ApiVersionsResponse::supported())),
+        );
+        round_trip(ApiKey::CreateTopic, Ok(Response::CreateTopic));
+        round_trip(ApiKey::DeleteTopic, Ok(Response::DeleteTopic));
+        round_trip(ApiKey::CommitOffset, Ok(Response::CommitOffset));
+        round_trip(ApiKey::Heartbeat, Ok(Response::Heartbeat));
+        round_trip(ApiKey::LeaveGroup, Ok(Response::LeaveGroup));
+        round_trip(ApiKey::MetadataWrite, Ok(Response::MetadataWrite));
+        round_trip(ApiKey::AddPartitionsToTxn, Ok(Response::AddPartitionsToTxn));
+        round_trip(ApiKey::EndTxn, Ok(Response::EndTxn));
+        round_trip(ApiKey::WriteTxnMarkers, Ok(Response::WriteTxnMarkers));
+        round_trip(ApiKey::Ack, Ok(Response::Ack));
+        round_trip(ApiKey::Nack, Ok(Response::Nack));
+        round_trip(ApiKey::DeclareExchange, Ok(Response::DeclareExchange));
+        round_trip(ApiKey::DeleteExchange, Ok(Response::DeleteExchange));
+        round_trip(ApiKey::Bind, Ok(Response::Bind));
+        round_trip(ApiKey::Unbind, Ok(Response::Unbind));
+        round_trip(
+            ApiKey::CreateTemporaryQueue,
+            Ok(Response::CreateTemporaryQueue(TemporaryQueueResponse {
+                topic: String::from("tmp.0.1"),
+            })),
+        );
+        round_trip(
+            ApiKey::FindCoordinator,
+            Ok(Response::FindCoordinator(CoordinatorResponse {
+                coordinator: Some(NodeMetadata {
+                    id: 2,
+                    address: String::from("127.0.0.1:7072"),
+                }),
+            })),
+        );
+        round_trip(
+            ApiKey::FindCoordinator,
+            Ok(Response::FindCoordinator(CoordinatorResponse {
+                coordinator: None,
+            })),
+        );
+        round_trip(
+            ApiKey::Publish,
+            Ok(Response::Publish(PublishResponse {
+                records: vec![RoutedRecord {
+                    topic: String::from("orders"),
+                    partition: 1,
+                    offset: 2,
+                }],
+            })),
+        );
+        round_trip(
+            ApiKey::ReplayDeadLetters,
+            Ok(Response::ReplayDeadLetters(ReplayDeadLettersResponse {
+                replayed: 3,
+            })),
+        );
+        round_trip(
+            ApiKey::Schedule,
+            Ok(Response::Schedule(ScheduleResponse {
+                messages: vec![ScheduledMessage {
+                    id: 4,
+                    deliver_at_ms: 1234,
+                }],
+            })),
+        );
+        round_trip(
+            ApiKey::OffsetsForTimes,
+            Ok(Response::OffsetsForTimes(OffsetsForTimesResponse {
+                partitions: vec![TimestampOffset {
+                    topic: String::from("events"),
+                    partition: 3,
+                    error_code: ErrorCode::TopicNotFound,
+                    offset: 0,
+                }],
+            })),
+        );
+        round_trip(
+            ApiKey::Lease,
+            Ok(Response::Lease(LeaseResponse {
+                messages: vec![LeasedMessage {
+                    partition: 1,
+                    delivery_count: 2,
+                    record: OffsetRecord {
+                        offset: 3,
+                        timestamp: 4,
+                        record: Record::new("value"),
+                    },
+                }],
+            })),
+        );
+        round_trip(
+            ApiKey::InitProducer,
+            Ok(Response::InitProducer(InitProducerResponse {
+                producer_id: 12,
+                epoch: 1,
+            })),
+        );
+        round_trip(
+            ApiKey::FetchOffsets,
+            Ok(Response::FetchOffsets(OffsetsResponse {
+                offsets: vec![PartitionOffset {
+                    topic: String::from("events"),
+                    partition: 0,
+                    offset: 5,
+                }],
+            })),
+        );
+        round_trip(
+            ApiKey::JoinGroup,
+            Ok(Response::JoinGroup(GroupAssignmentResponse {
+                member_id: String::from("member"),
+                generation: 3,
+                strategy: String::from("sticky"),
+                assignment: vec![TopicPartition::new("events", 1)],
+            })),
+        );
+        round_trip(
+            ApiKey::Metadata,
+            Ok(Response::Metadata(MetadataResponse {
+                nodes: vec![NodeMetadata {
+                    id: 1,
+                    address: String::from("127.0.0.1:7071"),
+                }],
+                topics: vec![TopicMetadata {
+                    name: String::from("events"),
+                    error_code: ErrorCode::None,
+                    partitions: vec![
+                        PartitionMetadata {
+                            id: 0,
+                            start_offset: 1,
+                            next_offset: 2,
+                            leader: Some(1),
+                            replicas: vec![1, 2],
+                            isr: vec![1],
+                        },
+                        PartitionMetadata {
+                            id: 1,
+                            start_offset: 0,
+                            next_offset: 0,
+                            leader: None,
+                            replicas: Vec::new(),
+                            isr: Vec::new(),
+                        },
+                    ],
+                }],
+            })),
+        );
+        round_trip(
+            ApiKey::Produce,
+            Ok(Response::Produce(ProduceResponse {
+                records: vec![ProducedRecord {
+                    partition: 1,
+                    offset: 2,
+                }],
+            })),
+        );
+        round_trip(
+            ApiKey::Fetch,
+            Ok(Response::Fetch(FetchResponse {
+                partitions: vec![FetchedPartition {
+                    topic: String::from("events"),
+                    partition: 0,
+                    error_code: ErrorCode::None,
+                    high_watermark: 2,
+                    last_stable_offset: 1,
+                    next_offset: 2,
+                    records: vec![OffsetRecord {
+                        offset: 1,
+                        timestamp: 0,
+                        record: Record::new("value"),
+                    }],
+                }],
+            })),
+        );
+        round_trip(
+            ApiKey::RaftVote,
+            Ok(Response::RaftVote(VoteResponse {
+                term: 3,
+                granted: true,
+            })),
+        );
+        round_trip(
+            ApiKey::RaftAppend,
+            Ok(Response::RaftAppend(AppendResponse {
+                term: 3,
+                success: false,
+                next_offset: 10,
+                conflict_term: 2,
+            })),
+        );
+        round_trip(
+            ApiKey::Fetch,
+            Err(ResponseError::new(ErrorCode::TopicNotFound, "missing")),
+        );
+    }
+
+    #[test]
+    fn test_trailing_bytes() {
+        let mut buf = Response::encode(1, &Ok(Response::Heartbeat));
+        buf.push(0);
+        let mut reader = Reader::new(&buf);
+        Response::decode_correlation_id(&mut reader).unwrap();
+        assert!(matches!(
+            Response::decode(ApiKey::Heartbeat, &mut reader),
+            Err(Error::TrailingBytes { remaining: 1 })
+        ));
+    }
+}
@@ -0,0 +1,63 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use structopt::StructOpt;
+
+#[derive(Debug, Clone, StructOpt)]
+/// Rift queue configuration.
+pub struct Config {
+    #[structopt(
+        long = "queue-max-visibility-timeout-ms",
+        env = "RIFT_QUEUE_MAX_VISIBILITY_TIMEOUT_MS",
+        help = "The largest visibility timeout a queue consumer may request.",
+        long_help = "Sets the upper bound in milliseconds on how long a queue consumer may lease messages for before they are redelivered to another consumer.",
+        default_value = "43200000",
+        takes_value = true
+    )]
+    /// Define the maximum visibility timeout in milliseconds.
+    pub max_visibility_timeout_ms: u64,
+
+    #[structopt(
+        long = "queue-max-unacked",
+        env = "RIFT_QUEUE_MAX_UNACKED",
+        help = "The most messages of a queue partition that may be awaiting acknowledgement.",
+        long_help = "Sets how many messages of each queue partition may be delivered without being acknowledged, after which only redeliveries are leased until consumers catch up.",
+        default_value = "10000",
+        takes_value = true
+    )]
+    /// Define the maximum number of unacknowledged messages per queue partition.
+    pub max_unacked: usize,
+
+    #[structopt(
+        long = "queue-priority-window",
+        env = "RIFT_QUEUE_PRIORITY_WINDOW",
+        help = "How many messages beyond those requested a queue reads ahead to order by priority.",
+        long_help = "Sets how many messages of each queue partition beyond those a lease requests are read ahead, so that urgent messages among them are delivered before bulk ones. Zero delivers messages in the order they were written.",
+        default_value = "100",
+        takes_value = true
+    )]
+    /// Define the number of messages read ahead to order by priority.
+    pub priority_window: usize,
+
+    #[structopt(
+        long = "queue-priority-weighting",
+        env = "RIFT_QUEUE_PRIORITY_WEIGHTING",
+        help = "How many times as often each priority level is served as the level below it.",
+        long_help = "Sets how many times as often a queue delivers messages of each priority level as messages of the level below it while both are waiting, so lower priorities are never starved entirely. Zero always delivers the highest priority messages first.",
+        default_value = "2",
+        takes_value = true
+    )]
+    /// Define the weight of each priority level relative to the one below it.
+    pub priority_weighting: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_visibility_timeout_ms: 43200000,
+            max_unacked: 10000,
+            priority_window: 100,
+            priority_weighting: 2,
+        }
+    }
+}
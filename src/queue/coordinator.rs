@@ -0,0 +1,1297 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::record::OffsetRecord;
+use crate::topic::{
+    self, IsolationLevel, Partition, Topic, TopicConfig, TopicPartition, CLEANUP_POLICY,
+};
+
+use super::config::Config;
+use super::dead_letter::DeadLetter;
+use super::error::{Error, Result};
+use super::metrics::metrics;
+use super::priority::Levels;
+use super::state::{Pending, QueueProgress};
+
+/// The internal topic the progress of every queue is stored in.
+pub const QUEUE_STATE_TOPIC: &str = "__queue_state";
+
+/// The number of bytes read at a time while replaying the queue state topic.
+const REPLAY_BYTES: usize = 1024 * 1024;
+/// The number of bytes read at a time while leasing new messages.
+const READ_BYTES: usize = 1024 * 1024;
+/// The consumer dead letters are leased to while they are written to their
+/// dead-letter topic.
+const DEAD_LETTER_CONSUMER: &str = "__dead_letter";
+/// How long a dead letter is held for before it is retried, should writing it
+/// to its dead-letter topic fail.
+const DEAD_LETTER_TIMEOUT_MS: i64 = 30_000;
+/// The last error recorded for messages whose visibility timeout passed.
+const TIMEOUT_ERROR: &str = "visibility timeout expired";
+/// The last error recorded for messages that outlived their time to live.
+const EXPIRED_ERROR: &str = "message expired";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// Limits on how a queue delivers the messages of a partition.
+pub struct DeliveryPolicy {
+    /// How many times a message is delivered before it is given up on and
+    /// handed out as a dead letter, or zero to redeliver it indefinitely.
+    pub max_deliveries: u32,
+    /// The time to live of messages without a TTL header, in milliseconds,
+    /// overriding the default of their topic.
+    pub message_ttl_ms: Option<u64>,
+    /// Whether messages that outlive their time to live are handed out as
+    /// dead letters rather than dropped.
+    pub dead_letter: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A message leased to a queue consumer.
+pub struct Delivery {
+    /// The message and where it was read from.
+    pub record: OffsetRecord,
+    /// How many times the message has been delivered, this delivery included.
+    pub delivery_count: u32,
+}
+
+/// A consumer's claim on a message until its visibility timeout passes.
+struct Lease {
+    consumer: String,
+    deadline_ms: i64,
+}
+
+/// A message that has been read but not acknowledged, which has not been
+/// delivered yet if it was only read ahead.
+struct Unacked {
+    deliveries: u32,
+    last_error: String,
+    lease: Option<Lease>,
+    /// Whether the message outlived its time to live and is only waiting to
+    /// be dead lettered. Not persisted, as it is found again on redelivery.
+    expired: bool,
+    priority: u8,
+}
+
+impl Unacked {
+    fn available(&self, now: i64) -> bool {
+        self.lease
+            .as_ref()
+            .is_none_or(|lease| lease.deadline_ms <= now)
+    }
+
+    /// Whether the message may be leased as of `now`.
+    fn waiting(&self, now: i64, max_deliveries: u32) -> bool {
+        self.available(now) && !self.expired && !self.exhausted(max_deliveries)
+    }
+
+    fn exhausted(&self, max_deliveries: u32) -> bool {
+        max_deliveries > 0 && self.deliveries >= max_deliveries
+    }
+
+    /// Drop the expired lease on an available message, if any, recording why
+    /// the message was released.
+    fn expire(&mut self) {
+        if let Some(lease) = self.lease.take() {
+            if lease.consumer != DEAD_LETTER_CONSUMER {
+                metrics().released.with_label_values(&["timeout"]).inc();
+                self.last_error = TIMEOUT_ERROR.to_owned();
+            }
+        }
+    }
+
+    fn leased_to(&self, consumer: &str, now: i64) -> bool {
+        self.lease
+            .as_ref()
+            .is_some_and(|lease| lease.consumer == consumer && lease.deadline_ms > now)
+    }
+}
+
+/// The progress of a queue through a single partition.
+struct QueuePartition {
+    next_offset: u64,
+    unacked: BTreeMap<u64, Unacked>,
+    levels: Levels,
+}
+
+type Queues = BTreeMap<(String, TopicPartition), QueuePartition>;
+
+/// Leases the messages of a partition to the consumers of a queue one at a time.
+///
+/// Consumers lease messages for a visibility timeout, then acknowledge them
+/// once processed or release them for immediate redelivery with a nack.
+/// Messages whose lease expires are redelivered to the next consumer to ask,
+/// and every delivery is counted. A queue starts at the partition's first
+/// retained offset.
+///
+/// Messages are delivered by priority, oldest first within each priority. A
+/// window of messages beyond those requested is read ahead so urgent messages
+/// can overtake bulk ones, and priority levels are weighted against each other
+/// so that lower priorities are never starved entirely.
+///
+/// Consumers may limit how many times a message is delivered, after which it
+/// is no longer leased to them but handed out as a dead letter for the broker
+/// to move to a dead-letter topic, along with why it was last released.
+/// Messages that outlive their time to live are never leased, and are either
+/// dropped or handed out as dead letters too.
+///
+/// The offset new messages are read from and the delivery count, last error,
+/// and priority of every message read but not acknowledged are appended to the
+/// queue state topic and flushed whenever they change, and rebuilt by
+/// replaying the topic on open. Leases are only held in memory.
+pub struct Coordinator {
+    cfg: Config,
+    topics: Arc<topic::Manager>,
+    log: Arc<Topic>,
+    queues: Mutex<Queues>,
+}
+
+impl Coordinator {
+    /// Open the coordinator, creating the queue state topic if it does not exist.
+    pub fn open(cfg: Config, topics: Arc<topic::Manager>) -> Result<Coordinator> {
+        let log = match topics.get(QUEUE_STATE_TOPIC) {
+            Ok(log) => log,
+            Err(topic::Error::NotFound { .. }) => {
+                let mut config = TopicConfig::new();
+                config.set(CLEANUP_POLICY, "compact")?;
+                topics.create(QUEUE_STATE_TOPIC, 1, config)?
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let queues = replay(log.partition(0)?)?;
+        Ok(Coordinator {
+            cfg,
+            topics,
+            log,
+            queues: Mutex::new(queues),
+        })
+    }
+
+    /// Returns the configuration of this coordinator.
+    pub fn config(&self) -> &Config {
+        &self.cfg
+    }
+
+    /// Returns the number of messages of a partition the supplied queue has
+    /// delivered but not had acknowledged.
+    pub fn unacked(&self, queue: &str, partition: &TopicPartition) -> usize {
+        self.queues()
+            .get(&(queue.to_owned(), partition.clone()))
+            .map_or(0, |state| {
+                state
+                    .unacked
+                    .values()
+                    .filter(|unacked| unacked.deliveries > 0)
+                    .count()
+            })
+    }
+
+    /// Returns the number of messages of a partition below `end_offset` waiting
+    /// to be delivered to the supplied queue as of `now`, both those not read
+    /// yet and those read but neither leased nor given up on. Messages not read
+    /// yet are counted by offset, so transaction markers and messages that will
+    /// turn out to be expired or aborted are included.
+    pub fn backlog(
+        &self,
+        queue: &str,
+        partition: &TopicPartition,
+        policy: &DeliveryPolicy,
+        end_offset: u64,
+        now: i64,
+    ) -> Result<u64> {
+        let start = self
+            .topics
+            .get(&partition.topic)?
+            .partition(partition.partition)?
+            .start_offset();
+        let queues = self.queues();
+        Ok(match queues.get(&(queue.to_owned(), partition.clone())) {
+            Some(state) => {
+                let waiting = state
+                    .unacked
+                    .range(start..)
+                    .filter(|(_, unacked)| unacked.waiting(now, policy.max_deliveries))
+                    .count() as u64;
+                waiting + end_offset.saturating_sub(state.next_offset.max(start))
+            }
+            None => end_offset.saturating_sub(start),
+        })
+    }
+
+    /// Lease up to `max_messages` messages of a partition below `end_offset` to
+    /// a consumer of the supplied queue as of `now`, in milliseconds since the
+    /// epoch, until the visibility timeout passes. Messages are leased by
+    /// priority and then offset, so those due for redelivery go ahead of new
+    /// ones of the same priority, unless they have already been delivered as
+    /// many times as the policy allows. Aborted transactional messages and
+    /// messages that have outlived their time to live are never leased.
+    #[allow(clippy::too_many_arguments)]
+    pub fn lease(
+        &self,
+        queue: &str,
+        consumer: &str,
+        partition: &TopicPartition,
+        max_messages: usize,
+        visibility_timeout_ms: u64,
+        policy: &DeliveryPolicy,
+        end_offset: u64,
+        now: i64,
+    ) -> Result<Vec<Delivery>> {
+        validate_queue(queue)?;
+        validate_consumer(consumer)?;
+        if visibility_timeout_ms == 0 || visibility_timeout_ms > self.cfg.max_visibility_timeout_ms
+        {
+            return Err(Error::InvalidVisibilityTimeout {
+                timeout_ms: visibility_timeout_ms,
+                max_ms: self.cfg.max_visibility_timeout_ms,
+            });
+        }
+        let topic = self.topics.get(&partition.topic)?;
+        let source = topic.partition(partition.partition)?;
+
+        let mut queues = self.queues();
+        let start = source.start_offset();
+        let state = queues
+            .entry((queue.to_owned(), partition.clone()))
+            .or_insert_with(|| QueuePartition {
+                next_offset: start,
+                unacked: BTreeMap::new(),
+                levels: Levels::default(),
+            });
+
+        // Messages removed by retention can no longer be delivered.
+        let retained = state.unacked.split_off(&start);
+        let mut changed = !state.unacked.is_empty() || state.next_offset < start;
+        state.unacked = retained;
+        state.next_offset = state.next_offset.max(start);
+
+        // Read ahead of the messages requested, so that urgent messages among
+        // them can be delivered before bulk ones.
+        let window = max_messages.saturating_add(self.cfg.priority_window);
+        let mut waiting = state
+            .unacked
+            .values()
+            .filter(|unacked| unacked.waiting(now, policy.max_deliveries))
+            .count();
+        let mut fresh = BTreeMap::new();
+        while waiting < window
+            && state.unacked.len() < self.cfg.max_unacked
+            && state.next_offset < end_offset
+        {
+            let records = source.read_isolated(
+                state.next_offset,
+                READ_BYTES,
+                IsolationLevel::ReadCommitted,
+            )?;
+            let mut read = false;
+            for record in records {
+                if record.offset >= end_offset
+                    || waiting >= window
+                    || state.unacked.len() >= self.cfg.max_unacked
+                {
+                    break;
+                }
+                state.next_offset = record.offset + 1;
+                read = true;
+                let expired = topic.expired(&record, policy.message_ttl_ms, now);
+                if expired && !policy.dead_letter {
+                    topic.count_expired(1);
+                    continue;
+                }
+                state.unacked.insert(
+                    record.offset,
+                    Unacked {
+                        deliveries: 0,
+                        last_error: if expired {
+                            EXPIRED_ERROR.to_owned()
+                        } else {
+                            String::new()
+                        },
+                        lease: None,
+                        expired,
+                        priority: record.record.priority(),
+                    },
+                );
+                if !expired {
+                    fresh.insert(record.offset, record);
+                    waiting += 1;
+                }
+            }
+            if !read {
+                break;
+            }
+            changed = true;
+        }
+
+        let mut by_priority: BTreeMap<u8, VecDeque<u64>> = BTreeMap::new();
+        for (offset, unacked) in &state.unacked {
+            if unacked.waiting(now, policy.max_deliveries) {
+                by_priority
+                    .entry(unacked.priority)
+                    .or_default()
+                    .push_back(*offset);
+            }
+        }
+        let deadline_ms = now.saturating_add(visibility_timeout_ms as i64);
+        let mut deliveries = Vec::new();
+        while deliveries.len() < max_messages {
+            let level = state.levels.next(
+                |level| {
+                    by_priority
+                        .get(&level)
+                        .is_some_and(|offsets| !offsets.is_empty())
+                },
+                self.cfg.priority_weighting,
+            );
+            let offset = match level.and_then(|level| by_priority.get_mut(&level)?.pop_front()) {
+                Some(offset) => offset,
+                None => break,
+            };
+            let record = match fresh.remove(&offset) {
+                Some(record) => Some(record),
+                None => read_one(source, offset)?,
+            };
+            let unacked = state.unacked.get_mut(&offset).expect("offset is unacked");
+            unacked.expire();
+            match record {
+                Some(record) if topic.expired(&record, policy.message_ttl_ms, now) => {
+                    if policy.dead_letter {
+                        unacked.expired = true;
+                        unacked.last_error = EXPIRED_ERROR.to_owned();
+                    } else {
+                        state.unacked.remove(&offset);
+                        topic.count_expired(1);
+                    }
+                }
+                Some(record) => {
+                    unacked.deliveries = unacked.deliveries.saturating_add(1);
+                    unacked.lease = Some(Lease {
+                        consumer: consumer.to_owned(),
+                        deadline_ms,
+                    });
+                    deliveries.push(Delivery {
+                        record,
+                        delivery_count: unacked.deliveries,
+                    });
+                }
+                // The message was compacted away since it was read.
+                None => {
+                    state.unacked.remove(&offset);
+                }
+            }
+            changed = true;
+        }
+
+        if changed {
+            self.persist(queue, partition, state)?;
+        }
+        Ok(deliveries)
+    }
+
+    /// Claim the messages of a partition that are due for redelivery as of
+    /// `now` but have already been delivered as many times as the policy
+    /// allows, or that were found to have outlived their time to live, so that
+    /// they can be moved to a dead-letter topic. Once written there they must
+    /// be settled with [Coordinator::dead_lettered], otherwise they are handed
+    /// out again after a while.
+    pub fn dead_letters(
+        &self,
+        queue: &str,
+        partition: &TopicPartition,
+        policy: &DeliveryPolicy,
+        now: i64,
+    ) -> Result<Vec<DeadLetter>> {
+        let mut queues = self.queues();
+        let state = match queues.get_mut(&(queue.to_owned(), partition.clone())) {
+            Some(state) => state,
+            None => return Ok(Vec::new()),
+        };
+        let topic = self.topics.get(&partition.topic)?;
+        let source = topic.partition(partition.partition)?;
+        // Messages removed by retention are dropped by the next lease instead.
+        let exhausted: Vec<u64> = state
+            .unacked
+            .range(source.start_offset()..)
+            .filter(|(_, unacked)| {
+                unacked.available(now)
+                    && (unacked.expired || unacked.exhausted(policy.max_deliveries))
+            })
+            .map(|(offset, _)| *offset)
+            .collect();
+        if exhausted.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut letters = Vec::new();
+        for offset in exhausted {
+            let record = read_one(source, offset)?;
+            let unacked = state.unacked.get_mut(&offset).expect("offset is unacked");
+            unacked.expire();
+            match record {
+                Some(record) => {
+                    unacked.lease = Some(Lease {
+                        consumer: DEAD_LETTER_CONSUMER.to_owned(),
+                        deadline_ms: now.saturating_add(DEAD_LETTER_TIMEOUT_MS),
+                    });
+                    letters.push(DeadLetter {
+                        queue: queue.to_owned(),
+                        partition: partition.clone(),
+                        record,
+                        delivery_count: unacked.deliveries,
+                        last_error: unacked.last_error.clone(),
+                    });
+                }
+                None => {
+                    state.unacked.remove(&offset);
+                }
+            }
+        }
+        self.persist(queue, partition, state)?;
+        Ok(letters)
+    }
+
+    /// Settle dead letters claimed as of `now` once they have been written to
+    /// their dead-letter topic, so they are never handed out again.
+    pub fn dead_lettered(
+        &self,
+        queue: &str,
+        partition: &TopicPartition,
+        offsets: &[u64],
+        now: i64,
+    ) -> Result<()> {
+        let expired = self
+            .queues()
+            .get(&(queue.to_owned(), partition.clone()))
+            .map_or(0, |state| {
+                offsets
+                    .iter()
+                    .filter(|offset| {
+                        state
+                            .unacked
+                            .get(offset)
+                            .is_some_and(|unacked| unacked.expired)
+                    })
+                    .count()
+            });
+        self.ack(queue, DEAD_LETTER_CONSUMER, partition, offsets, now)?;
+        if expired > 0 {
+            self.topics.get(&partition.topic)?.count_expired(expired);
+        }
+        metrics()
+            .dead_lettered
+            .with_label_values(&[queue])
+            .inc_by(offsets.len() as u64);
+        Ok(())
+    }
+
+    /// Acknowledge messages the consumer has processed, so they are never
+    /// redelivered. Either every message is acknowledged or, if any is not
+    /// leased to the consumer as of `now`, none are.
+    pub fn ack(
+        &self,
+        queue: &str,
+        consumer: &str,
+        partition: &TopicPartition,
+        offsets: &[u64],
+        now: i64,
+    ) -> Result<()> {
+        let mut queues = self.queues();
+        let state = check_leased(&mut queues, queue, consumer, partition, offsets, now)?;
+        for offset in offsets {
+            state.unacked.remove(offset);
+        }
+        self.persist(queue, partition, state)
+    }
+
+    /// Release messages the consumer failed to process with the supplied
+    /// error, so they are redelivered to the next consumer to ask. Either every
+    /// message is released or, if any is not leased to the consumer as of
+    /// `now`, none are.
+    pub fn nack(
+        &self,
+        queue: &str,
+        consumer: &str,
+        partition: &TopicPartition,
+        offsets: &[u64],
+        error: &str,
+        now: i64,
+    ) -> Result<()> {
+        let mut queues = self.queues();
+        let state = check_leased(&mut queues, queue, consumer, partition, offsets, now)?;
+        for offset in offsets {
+            if let Some(unacked) = state.unacked.get_mut(offset) {
+                unacked.lease = None;
+                unacked.last_error = error.to_owned();
+            }
+        }
+        metrics()
+            .released
+            .with_label_values(&["nack"])
+            .inc_by(offsets.len() as u64);
+        self.persist(queue, partition, state)
+    }
+
+    /// Give up on messages the consumer rejected with the supplied error,
+    /// claiming them as dead letters right away whatever their delivery count.
+    /// Once written to their dead-letter topic they must be settled with
+    /// [Coordinator::dead_lettered], otherwise they are redelivered after a
+    /// while. Either every message is claimed or, if any is not leased to the
+    /// consumer as of `now`, none are.
+    pub fn reject(
+        &self,
+        queue: &str,
+        consumer: &str,
+        partition: &TopicPartition,
+        offsets: &[u64],
+        error: &str,
+        now: i64,
+    ) -> Result<Vec<DeadLetter>> {
+        let topic = self.topics.get(&partition.topic)?;
+        let source = topic.partition(partition.partition)?;
+        let mut queues = self.queues();
+        let state = check_leased(&mut queues, queue, consumer, partition, offsets, now)?;
+        let mut letters = Vec::new();
+        for offset in offsets {
+            let record = read_one(source, *offset)?;
+            let unacked = state.unacked.get_mut(offset).expect("offset is leased");
+            unacked.last_error = error.to_owned();
+            match record {
+                Some(record) => {
+                    unacked.lease = Some(Lease {
+                        consumer: DEAD_LETTER_CONSUMER.to_owned(),
+                        deadline_ms: now.saturating_add(DEAD_LETTER_TIMEOUT_MS),
+                    });
+                    letters.push(DeadLetter {
+                        queue: queue.to_owned(),
+                        partition: partition.clone(),
+                        record,
+                        delivery_count: unacked.deliveries,
+                        last_error: unacked.last_error.clone(),
+                    });
+                }
+                None => {
+                    state.unacked.remove(offset);
+                }
+            }
+        }
+        self.persist(queue, partition, state)?;
+        Ok(letters)
+    }
+
+    fn persist(
+        &self,
+        queue: &str,
+        partition: &TopicPartition,
+        state: &QueuePartition,
+    ) -> Result<()> {
+        let record = QueueProgress {
+            queue: queue.to_owned(),
+            partition: partition.clone(),
+            next_offset: state.next_offset,
+            unacked: state
+                .unacked
+                .iter()
+                .map(|(offset, unacked)| {
+                    let pending = Pending {
+                        deliveries: unacked.deliveries,
+                        last_error: unacked.last_error.clone(),
+                        priority: unacked.priority,
+                    };
+                    (*offset, pending)
+                })
+                .collect(),
+        }
+        .to_record();
+        let log = self.log.partition(0)?;
+        log.append(&[record])?;
+        log.flush()?;
+        Ok(())
+    }
+
+    fn queues(&self) -> MutexGuard<'_, Queues> {
+        self.queues
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn check_leased<'a>(
+    queues: &'a mut Queues,
+    queue: &str,
+    consumer: &str,
+    partition: &TopicPartition,
+    offsets: &[u64],
+    now: i64,
+) -> Result<&'a mut QueuePartition> {
+    let state = queues.get_mut(&(queue.to_owned(), partition.clone()));
+    let unleased = offsets.iter().find(|offset| {
+        !state
+            .as_ref()
+            .and_then(|state| state.unacked.get(offset))
+            .is_some_and(|unacked| unacked.leased_to(consumer, now))
+    });
+    match (unleased, state) {
+        (None, Some(state)) => Ok(state),
+        (unleased, _) => Err(Error::NotLeased {
+            queue: queue.to_owned(),
+            partition: partition.clone(),
+            offset: unleased.copied().unwrap_or_default(),
+            consumer: consumer.to_owned(),
+        }),
+    }
+}
+
+/// Read the message at an offset, or `None` if it was compacted away or
+/// belongs to an aborted transaction.
+fn read_one(source: &Partition, offset: u64) -> Result<Option<OffsetRecord>> {
+    Ok(source
+        .read_isolated(offset, 1, IsolationLevel::ReadCommitted)?
+        .into_iter()
+        .next()
+        .filter(|record| record.offset == offset))
+}
+
+fn replay(log: &Partition) -> Result<Queues> {
+    let mut queues = Queues::new();
+    let mut offset = log.start_offset();
+    while offset < log.next_offset() {
+        let records = log.read(offset, REPLAY_BYTES)?;
+        for record in &records {
+            let progress =
+                QueueProgress::from_record(&record.record).map_err(|source| Error::Corrupt {
+                    offset: record.offset,
+                    source,
+                })?;
+            let unacked = progress
+                .unacked
+                .into_iter()
+                .map(|(offset, pending)| {
+                    let unacked = Unacked {
+                        deliveries: pending.deliveries,
+                        last_error: pending.last_error,
+                        lease: None,
+                        expired: false,
+                        priority: pending.priority,
+                    };
+                    (offset, unacked)
+                })
+                .collect();
+            queues.insert(
+                (progress.queue, progress.partition),
+                QueuePartition {
+                    next_offset: progress.next_offset,
+                    unacked,
+                    levels: Levels::default(),
+                },
+            );
+        }
+        offset = match records.last() {
+            Some(record) => record.offset + 1,
+            None => break,
+        };
+    }
+    Ok(queues)
+}
+
+fn validate_queue(queue: &str) -> Result<()> {
+    if queue.is_empty() {
+        return Err(Error::InvalidQueue {
+            queue: queue.to_owned(),
+            reason: "queue names must not be empty",
+        });
+    }
+    Ok(())
+}
+
+fn validate_consumer(consumer: &str) -> Result<()> {
+    if consumer.is_empty() {
+        return Err(Error::InvalidConsumer {
+            consumer: consumer.to_owned(),
+            reason: "consumer ids must not be empty",
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+    use crate::record::{ControlMarker, ProducerBatch, Record};
+    use crate::storage::LogConfig;
+
+    fn open(dir: &std::path::Path) -> (Arc<topic::Manager>, Coordinator) {
+        let topics = Arc::new(topic::Manager::open(dir, LogConfig::default()).unwrap());
+        if topics.get("events").is_err() {
+            topics.create("events", 1, TopicConfig::new()).unwrap();
+        }
+        let coordinator = Coordinator::open(Config::default(), topics.clone()).unwrap();
+        (topics, coordinator)
+    }
+
+    fn limit(max_deliveries: u32) -> DeliveryPolicy {
+        DeliveryPolicy {
+            max_deliveries,
+            ..DeliveryPolicy::default()
+        }
+    }
+
+    fn offsets(deliveries: &[Delivery]) -> Vec<(u64, u32)> {
+        deliveries
+            .iter()
+            .map(|delivery| (delivery.record.offset, delivery.delivery_count))
+            .collect()
+    }
+
+    #[test]
+    fn test_lease_ack() {
+        let dir = tempfile::tempdir().unwrap();
+        let (topics, queues) = open(dir.path());
+        let events = topics.get("events").unwrap();
+        let source = events.partition(0).unwrap();
+        source
+            .append(&[Record::new("a"), Record::new("b"), Record::new("c")])
+            .unwrap();
+        let tp = TopicPartition::new("events", 0);
+
+        let first = queues
+            .lease(
+                "jobs",
+                "one",
+                &tp,
+                2,
+                1000,
+                &DeliveryPolicy::default(),
+                3,
+                0,
+            )
+            .unwrap();
+        assert_eq!(vec![(0, 1), (1, 1)], offsets(&first));
+        let second = queues
+            .lease(
+                "jobs",
+                "two",
+                &tp,
+                5,
+                1000,
+                &DeliveryPolicy::default(),
+                3,
+                0,
+            )
+            .unwrap();
+        assert_eq!(vec![(2, 1)], offsets(&second));
+        assert!(queues
+            .lease(
+                "jobs",
+                "two",
+                &tp,
+                5,
+                1000,
+                &DeliveryPolicy::default(),
+                3,
+                0
+            )
+            .unwrap()
+            .is_empty());
+
+        // Other queues consume the partition independently.
+        let other = queues
+            .lease(
+                "audit",
+                "one",
+                &tp,
+                5,
+                1000,
+                &DeliveryPolicy::default(),
+                3,
+                0,
+            )
+            .unwrap();
+        assert_eq!(3, other.len());
+
+        queues.ack("jobs", "one", &tp, &[0], 10).unwrap();
+        let err = queues.ack("jobs", "two", &tp, &[1], 10).unwrap_err();
+        assert!(matches!(err, Error::NotLeased { offset: 1, .. }));
+        queues.nack("jobs", "one", &tp, &[1], "failed", 10).unwrap();
+        assert_eq!(2, queues.unacked("jobs", &tp));
+
+        // Released messages are redelivered first, then those whose lease expired.
+        let redelivered = queues
+            .lease(
+                "jobs",
+                "two",
+                &tp,
+                1,
+                1000,
+                &DeliveryPolicy::default(),
+                3,
+                20,
+            )
+            .unwrap();
+        assert_eq!(vec![(1, 2)], offsets(&redelivered));
+        let err = queues.ack("jobs", "two", &tp, &[2], 1000).unwrap_err();
+        assert!(matches!(err, Error::NotLeased { offset: 2, .. }));
+        let redelivered = queues
+            .lease(
+                "jobs",
+                "one",
+                &tp,
+                5,
+                1000,
+                &DeliveryPolicy::default(),
+                3,
+                1000,
+            )
+            .unwrap();
+        assert_eq!(vec![(2, 2)], offsets(&redelivered));
+        queues.ack("jobs", "one", &tp, &[2], 1000).unwrap();
+        queues.ack("jobs", "two", &tp, &[1], 1000).unwrap();
+        assert_eq!(0, queues.unacked("jobs", &tp));
+
+        source.append(&[Record::new("d")]).unwrap();
+        assert!(queues
+            .lease(
+                "jobs",
+                "one",
+                &tp,
+                5,
+                1000,
+                &DeliveryPolicy::default(),
+                3,
+                1000
+            )
+            .unwrap()
+            .is_empty());
+        let leased = queues
+            .lease(
+                "jobs",
+                "one",
+                &tp,
+                5,
+                1000,
+                &DeliveryPolicy::default(),
+                4,
+                1000,
+            )
+            .unwrap();
+        assert_eq!(vec![(3, 1)], offsets(&leased));
+    }
+
+    #[test]
+    fn test_survives_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let (topics, queues) = open(dir.path());
+        let events = topics.get("events").unwrap();
+        events
+            .partition(0)
+            .unwrap()
+            .append(&[Record::new("a"), Record::new("b"), Record::new("c")])
+            .unwrap();
+        let tp = TopicPartition::new("events", 0);
+
+        queues
+            .lease(
+                "jobs",
+                "one",
+                &tp,
+                2,
+                1000,
+                &DeliveryPolicy::default(),
+                3,
+                0,
+            )
+            .unwrap();
+        queues.ack("jobs", "one", &tp, &[1], 0).unwrap();
+        drop(queues);
+        drop(events);
+        drop(topics);
+
+        // Leases are lost, so unacknowledged messages are redelivered at once.
+        let (_, queues) = open(dir.path());
+        let leased = queues
+            .lease(
+                "jobs",
+                "two",
+                &tp,
+                5,
+                1000,
+                &DeliveryPolicy::default(),
+                3,
+                0,
+            )
+            .unwrap();
+        assert_eq!(vec![(0, 2), (2, 1)], offsets(&leased));
+    }
+
+    #[test]
+    fn test_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let topics = Arc::new(topic::Manager::open(dir.path(), LogConfig::default()).unwrap());
+        let events = topics.create("events", 1, TopicConfig::new()).unwrap();
+        let source = events.partition(0).unwrap();
+        source
+            .append(&[Record::new("a"), Record::new("b"), Record::new("c")])
+            .unwrap();
+        let cfg = Config {
+            max_visibility_timeout_ms: 1000,
+            max_unacked: 2,
+            ..Config::default()
+        };
+        let queues = Coordinator::open(cfg, topics.clone()).unwrap();
+        let tp = TopicPartition::new("events", 0);
+
+        let err = queues
+            .lease(
+                "jobs",
+                "one",
+                &tp,
+                5,
+                1001,
+                &DeliveryPolicy::default(),
+                3,
+                0,
+            )
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidVisibilityTimeout { .. }));
+        let err = queues
+            .lease("", "one", &tp, 5, 1000, &DeliveryPolicy::default(), 3, 0)
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidQueue { .. }));
+        let err = queues
+            .lease("jobs", "", &tp, 5, 1000, &DeliveryPolicy::default(), 3, 0)
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidConsumer { .. }));
+
+        let leased = queues
+            .lease(
+                "jobs",
+                "one",
+                &tp,
+                5,
+                1000,
+                &DeliveryPolicy::default(),
+                3,
+                0,
+            )
+            .unwrap();
+        assert_eq!(vec![(0, 1), (1, 1)], offsets(&leased));
+        assert!(queues
+            .lease(
+                "jobs",
+                "one",
+                &tp,
+                5,
+                1000,
+                &DeliveryPolicy::default(),
+                3,
+                0
+            )
+            .unwrap()
+            .is_empty());
+        queues.ack("jobs", "one", &tp, &[0], 0).unwrap();
+        let leased = queues
+            .lease(
+                "jobs",
+                "one",
+                &tp,
+                5,
+                1000,
+                &DeliveryPolicy::default(),
+                3,
+                0,
+            )
+            .unwrap();
+        assert_eq!(vec![(2, 1)], offsets(&leased));
+    }
+
+    #[test]
+    fn test_priorities() {
+        let dir = tempfile::tempdir().unwrap();
+        let topics = Arc::new(topic::Manager::open(dir.path(), LogConfig::default()).unwrap());
+        let events = topics.create("events", 1, TopicConfig::new()).unwrap();
+        events
+            .partition(0)
+            .unwrap()
+            .append(&[
+                Record::new("a"),
+                Record::new("b"),
+                Record::new("c").with_priority(9),
+                Record::new("d").with_priority(5),
+                Record::new("e").with_priority(9),
+            ])
+            .unwrap();
+        let tp = TopicPartition::new("events", 0);
+        let open = |cfg: Config| Coordinator::open(cfg, topics.clone()).unwrap();
+        let lease = |queues: &Coordinator, queue: &str, max_messages| {
+            queues
+                .lease(
+                    queue,
+                    "one",
+                    &tp,
+                    max_messages,
+                    1000,
+                    &DeliveryPolicy::default(),
+                    5,
+                    0,
+                )
+                .unwrap()
+                .iter()
+                .map(|delivery| delivery.record.offset)
+                .collect::<Vec<_>>()
+        };
+
+        // Urgent messages go first, oldest first within a priority.
+        let strict = open(Config {
+            priority_weighting: 0,
+            ..Config::default()
+        });
+        assert_eq!(vec![2, 4, 3, 0, 1], lease(&strict, "strict", 5));
+
+        // Equally weighted priorities take turns.
+        let equal = open(Config {
+            priority_weighting: 1,
+            ..Config::default()
+        });
+        assert_eq!(vec![2, 3, 0, 4, 1], lease(&equal, "equal", 5));
+
+        // Without a window messages are delivered in the order they were written.
+        let fifo = open(Config {
+            priority_window: 0,
+            ..Config::default()
+        });
+        assert_eq!(vec![0], lease(&fifo, "fifo", 1));
+        assert_eq!(vec![1], lease(&fifo, "fifo", 1));
+        assert_eq!(vec![2, 3], lease(&fifo, "fifo", 2));
+
+        // Messages read ahead keep their priority across restarts, after
+        // which unacknowledged messages are redelivered.
+        let queues = open(Config::default());
+        assert_eq!(vec![2], lease(&queues, "jobs", 1));
+        assert_eq!(1, queues.unacked("jobs", &tp));
+        drop(queues);
+        let queues = open(Config::default());
+        assert_eq!(vec![2, 4, 3, 0, 1], lease(&queues, "jobs", 5));
+    }
+
+    #[test]
+    fn test_skips_aborted() {
+        let dir = tempfile::tempdir().unwrap();
+        let (topics, queues) = open(dir.path());
+        let events = topics.get("events").unwrap();
+        let source = events.partition(0).unwrap();
+        let producer = ProducerBatch {
+            producer_id: 1,
+            epoch: 0,
+            sequence: 0,
+            transactional: true,
+        };
+        source
+            .append_batch(&[Record::new("a")], Some(producer))
+            .unwrap();
+        source.append_marker(1, 0, ControlMarker::Abort).unwrap();
+        source.append(&[Record::new("b")]).unwrap();
+        let tp = TopicPartition::new("events", 0);
+
+        let leased = queues
+            .lease(
+                "jobs",
+                "one",
+                &tp,
+                5,
+                1000,
+                &DeliveryPolicy::default(),
+                3,
+                0,
+            )
+            .unwrap();
+        assert_eq!(vec![(2, 1)], offsets(&leased));
+    }
+
+    #[test]
+    fn test_dead_letters() {
+        let dir = tempfile::tempdir().unwrap();
+        let (topics, queues) = open(dir.path());
+        let events = topics.get("events").unwrap();
+        events
+            .partition(0)
+            .unwrap()
+            .append(&[Record::new("a"), Record::new("b")])
+            .unwrap();
+        let tp = TopicPartition::new("events", 0);
+        let last_errors = |letters: &[DeadLetter]| {
+            letters
+                .iter()
+                .map(|letter| (letter.record.offset, letter.last_error.clone()))
+                .collect::<Vec<_>>()
+        };
+
+        queues
+            .lease("jobs", "one", &tp, 2, 1000, &limit(2), 2, 0)
+            .unwrap();
+        queues.nack("jobs", "one", &tp, &[0], "boom", 0).unwrap();
+        let leased = queues
+            .lease("jobs", "one", &tp, 2, 1000, &limit(2), 2, 0)
+            .unwrap();
+        assert_eq!(vec![(0, 2)], offsets(&leased));
+        queues.nack("jobs", "one", &tp, &[0], "bang", 0).unwrap();
+
+        // Exhausted messages are withheld from consumers until dead lettered.
+        assert!(queues
+            .lease("jobs", "one", &tp, 2, 1000, &limit(2), 2, 0)
+            .unwrap()
+            .is_empty());
+        let letters = queues.dead_letters("jobs", &tp, &limit(2), 0).unwrap();
+        assert_eq!(vec![(0, String::from("bang"))], last_errors(&letters));
+        assert_eq!(2, letters[0].delivery_count);
+        assert!(queues
+            .dead_letters("jobs", &tp, &limit(2), 0)
+            .unwrap()
+            .is_empty());
+
+        let leased = queues
+            .lease("jobs", "one", &tp, 2, 1000, &limit(2), 2, 1000)
+            .unwrap();
+        assert_eq!(vec![(1, 2)], offsets(&leased));
+        let letters = queues.dead_letters("jobs", &tp, &limit(2), 2000).unwrap();
+        assert_eq!(
+            vec![(1, String::from(TIMEOUT_ERROR))],
+            last_errors(&letters)
+        );
+        queues.dead_lettered("jobs", &tp, &[1], 2000).unwrap();
+
+        // Unsettled dead letters are handed out again, here after a restart.
+        drop(queues);
+        drop(events);
+        drop(topics);
+        let (_, queues) = open(dir.path());
+        let letters = queues.dead_letters("jobs", &tp, &limit(2), 2000).unwrap();
+        assert_eq!(vec![(0, String::from("bang"))], last_errors(&letters));
+        queues.dead_lettered("jobs", &tp, &[0], 2000).unwrap();
+        assert_eq!(0, queues.unacked("jobs", &tp));
+    }
+
+    #[test]
+    fn test_reject() {
+        let dir = tempfile::tempdir().unwrap();
+        let (topics, queues) = open(dir.path());
+        let events = topics.get("events").unwrap();
+        events
+            .partition(0)
+            .unwrap()
+            .append(&[Record::new("a"), Record::new("b"), Record::new("c")])
+            .unwrap();
+        let tp = TopicPartition::new("events", 0);
+        let policy = DeliveryPolicy::default();
+        assert_eq!(3, queues.backlog("jobs", &tp, &policy, 3, 0).unwrap());
+
+        let leased = queues
+            .lease("jobs", "one", &tp, 2, 1000, &policy, 3, 0)
+            .unwrap();
+        assert_eq!(vec![(0, 1), (1, 1)], offsets(&leased));
+        assert_eq!(1, queues.backlog("jobs", &tp, &policy, 3, 0).unwrap());
+        assert!(queues.reject("jobs", "two", &tp, &[0], "nope", 0).is_err());
+
+        // Rejected messages are dead letters right away, whatever the policy.
+        let letters = queues.reject("jobs", "one", &tp, &[0], "nope", 0).unwrap();
+        assert_eq!(1, letters.len());
+        assert_eq!(
+            (0, 1),
+            (letters[0].record.offset, letters[0].delivery_count)
+        );
+        assert_eq!("nope", letters[0].last_error);
+        assert_eq!(1, queues.backlog("jobs", &tp, &policy, 3, 0).unwrap());
+        queues.dead_lettered("jobs", &tp, &[0], 0).unwrap();
+        assert_eq!(1, queues.unacked("jobs", &tp));
+
+        queues.nack("jobs", "one", &tp, &[1], "boom", 0).unwrap();
+        assert_eq!(2, queues.backlog("jobs", &tp, &policy, 3, 0).unwrap());
+    }
+
+    #[test]
+    fn test_expired() {
+        let dir = tempfile::tempdir().unwrap();
+        let (topics, queues) = open(dir.path());
+        let source = topics.get("events").unwrap();
+        let source = source.partition(0).unwrap();
+        source
+            .append(&[
+                Record::new("a").with_ttl(100),
+                Record::new("b"),
+                Record::new("c").with_ttl(100),
+            ])
+            .unwrap();
+        let appended = source.read(0, 1024).unwrap()[0].timestamp;
+        let tp = TopicPartition::new("events", 0);
+        let expiring = DeliveryPolicy {
+            message_ttl_ms: Some(500),
+            dead_letter: true,
+            ..DeliveryPolicy::default()
+        };
+        let expired = |letters: &[DeadLetter]| {
+            letters
+                .iter()
+                .map(|letter| {
+                    assert_eq!(EXPIRED_ERROR, letter.last_error);
+                    (letter.record.offset, letter.delivery_count)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Without a dead-letter topic expired messages are dropped.
+        let leased = queues
+            .lease(
+                "jobs",
+                "one",
+                &tp,
+                5,
+                1000,
+                &DeliveryPolicy::default(),
+                3,
+                appended + 100,
+            )
+            .unwrap();
+        assert_eq!(vec![(1, 1)], offsets(&leased));
+        assert_eq!(1, queues.unacked("jobs", &tp));
+
+        // Messages expiring while awaiting redelivery are withheld until dead
+        // lettered, along with how many times they were delivered.
+        let leased = queues
+            .lease("audit", "one", &tp, 5, 1000, &expiring, 3, appended)
+            .unwrap();
+        assert_eq!(3, leased.len());
+        queues
+            .nack("audit", "one", &tp, &[0, 1, 2], "boom", appended)
+            .unwrap();
+        let leased = queues
+            .lease("audit", "one", &tp, 5, 1000, &expiring, 3, appended + 200)
+            .unwrap();
+        assert_eq!(vec![(1, 2)], offsets(&leased));
+        assert!(queues
+            .lease("audit", "two", &tp, 5, 1000, &expiring, 3, appended + 200)
+            .unwrap()
+            .is_empty());
+        let letters = queues
+            .dead_letters("audit", &tp, &expiring, appended + 200)
+            .unwrap();
+        assert_eq!(vec![(0, 1), (2, 1)], expired(&letters));
+        queues
+            .dead_lettered("audit", &tp, &[0, 2], appended + 200)
+            .unwrap();
+        assert_eq!(1, queues.unacked("audit", &tp));
+
+        // Messages that expired before ever being delivered are never leased.
+        assert!(queues
+            .lease("late", "one", &tp, 5, 1000, &expiring, 3, appended + 500)
+            .unwrap()
+            .is_empty());
+        let letters = queues
+            .dead_letters("late", &tp, &expiring, appended + 500)
+            .unwrap();
+        assert_eq!(vec![(0, 0), (1, 0), (2, 0)], expired(&letters));
+    }
+}
@@ -0,0 +1,138 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::record::{OffsetRecord, Record, TTL_HEADER};
+use crate::topic::TopicPartition;
+
+/// The prefix of every header describing where a dead letter came from.
+const HEADER_PREFIX: &str = "rift.dead_letter.";
+/// The header holding the queue a dead letter was moved out of.
+pub const QUEUE_HEADER: &str = "rift.dead_letter.queue";
+/// The header holding the topic a dead letter was originally written to.
+pub const TOPIC_HEADER: &str = "rift.dead_letter.topic";
+/// The header holding the partition a dead letter was originally written to.
+pub const PARTITION_HEADER: &str = "rift.dead_letter.partition";
+/// The header holding the offset a dead letter was originally written at.
+pub const OFFSET_HEADER: &str = "rift.dead_letter.offset";
+/// The header holding how many times a dead letter was delivered.
+pub const DELIVERY_COUNT_HEADER: &str = "rift.dead_letter.delivery_count";
+/// The header holding why a dead letter was last released.
+pub const ERROR_HEADER: &str = "rift.dead_letter.error";
+/// The header holding the time to live a dead letter was written with, which
+/// no longer applies in the dead-letter topic.
+pub const TTL_MS_HEADER: &str = "rift.dead_letter.ttl_ms";
+
+#[derive(Debug, Clone, PartialEq)]
+/// A message that exhausted its delivery attempts and is due to be moved to a
+/// dead-letter topic.
+pub struct DeadLetter {
+    /// The queue the message was leased through.
+    pub queue: String,
+    /// The partition the message was read from.
+    pub partition: TopicPartition,
+    /// The message and where it was read from.
+    pub record: OffsetRecord,
+    /// How many times the message was delivered.
+    pub delivery_count: u32,
+    /// Why the message was last released, empty if no reason was given.
+    pub last_error: String,
+}
+
+impl DeadLetter {
+    /// Returns the record to write to the dead-letter topic, which keeps the
+    /// key, value, and headers of the message and adds headers describing
+    /// where it came from and why it was given up on. All of them are UTF-8
+    /// text, numbers in decimal. The message's TTL header is moved aside, so
+    /// dead letters do not expire.
+    pub fn to_record(&self) -> Record {
+        let mut record = self.record.record.clone();
+        if let Some(ttl_ms) = record.header(TTL_HEADER).map(<[u8]>::to_vec) {
+            record.headers.retain(|header| header.key != TTL_HEADER);
+            record = record.with_header(TTL_MS_HEADER, ttl_ms);
+        }
+        record
+            .with_header(QUEUE_HEADER, self.queue.as_bytes())
+            .with_header(TOPIC_HEADER, self.partition.topic.as_bytes())
+            .with_header(PARTITION_HEADER, self.partition.partition.to_string())
+            .with_header(OFFSET_HEADER, self.record.offset.to_string())
+            .with_header(DELIVERY_COUNT_HEADER, self.delivery_count.to_string())
+            .with_header(ERROR_HEADER, self.last_error.as_bytes())
+    }
+}
+
+/// Returns the partition a dead-letter record was moved out of along with the
+/// record as it was originally written, including its time to live, or `None`
+/// if the record was not written to the dead-letter topic by a queue.
+///
+/// ```
+/// # use librift::queue::{replay_target, PARTITION_HEADER, TOPIC_HEADER};
+/// # use librift::record::Record;
+/// let record = Record::new("payload")
+///     .with_header("trace", "abc")
+///     .with_header(TOPIC_HEADER, "orders")
+///     .with_header(PARTITION_HEADER, "3");
+/// let (partition, original) = replay_target(&record).unwrap();
+/// assert_eq!(("orders", 3), (partition.topic.as_str(), partition.partition));
+/// assert_eq!(Record::new("payload").with_header("trace", "abc"), original);
+/// ```
+pub fn replay_target(record: &Record) -> Option<(TopicPartition, Record)> {
+    let topic = std::str::from_utf8(record.header(TOPIC_HEADER)?).ok()?;
+    let partition = std::str::from_utf8(record.header(PARTITION_HEADER)?)
+        .ok()?
+        .parse()
+        .ok()?;
+    let mut original = record.clone();
+    original
+        .headers
+        .retain(|header| !header.key.starts_with(HEADER_PREFIX));
+    if let Some(ttl_ms) = record.header(TTL_MS_HEADER) {
+        original = original.with_header(TTL_HEADER, ttl_ms);
+    }
+    Some((TopicPartition::new(topic, partition), original))
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let original = Record::new("payload")
+            .with_key("key")
+            .with_header("trace", "abc")
+            .with_ttl(5000);
+        let letter = DeadLetter {
+            queue: String::from("jobs"),
+            partition: TopicPartition::new("events", 2),
+            record: OffsetRecord {
+                offset: 17,
+                timestamp: 0,
+                record: original.clone(),
+            },
+            delivery_count: 5,
+            last_error: String::from("boom"),
+        };
+
+        let record = letter.to_record();
+        assert_eq!(Some(&b"jobs"[..]), record.header(QUEUE_HEADER));
+        assert_eq!(Some(&b"17"[..]), record.header(OFFSET_HEADER));
+        assert_eq!(Some(&b"5"[..]), record.header(DELIVERY_COUNT_HEADER));
+        assert_eq!(Some(&b"boom"[..]), record.header(ERROR_HEADER));
+        assert_eq!(None, record.ttl_ms());
+        assert_eq!(Some(&b"5000"[..]), record.header(TTL_MS_HEADER));
+        assert_eq!(
+            Some((TopicPartition::new("events", 2), original)),
+            replay_target(&record)
+        );
+    }
+
+    #[test]
+    fn test_not_dead_letter() {
+        assert_eq!(None, replay_target(&Record::new("payload")));
+        let record = Record::new("payload")
+            .with_header(TOPIC_HEADER, "events")
+            .with_header(PARTITION_HEADER, "two");
+        assert_eq!(None, replay_target(&record));
+    }
+}
@@ -0,0 +1,67 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::result;
+
+use thiserror::Error;
+
+use crate::codec;
+use crate::topic::{self, TopicPartition};
+
+/// Custom Result wrapper to simplify usage.
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents errors leasing or acknowledging queued messages.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// Handles errors reading or writing the queue state topic, or reading the
+    /// partitions messages are leased from.
+    #[error(transparent)]
+    Topic(#[from] topic::Error),
+    /// Handles queue state records that could not be decoded.
+    #[error("failed to decode queue state at offset {offset}: {source}")]
+    Corrupt {
+        /// The offset of the queue state record that failed to decode.
+        offset: u64,
+        /// The initial error cause.
+        source: codec::Error,
+    },
+    /// Handles queue names that can not be used.
+    #[error("invalid queue '{queue}': {reason}")]
+    InvalidQueue {
+        /// The offending queue name.
+        queue: String,
+        /// Why the queue name was rejected.
+        reason: &'static str,
+    },
+    /// Handles consumer ids that can not be used.
+    #[error("invalid consumer id '{consumer}': {reason}")]
+    InvalidConsumer {
+        /// The offending consumer id.
+        consumer: String,
+        /// Why the consumer id was rejected.
+        reason: &'static str,
+    },
+    /// Handles visibility timeouts outside of the configured bounds.
+    #[error("visibility timeout {timeout_ms}ms must be between 1ms and {max_ms}ms")]
+    InvalidVisibilityTimeout {
+        /// The requested visibility timeout.
+        timeout_ms: u64,
+        /// The largest visibility timeout allowed.
+        max_ms: u64,
+    },
+    /// Handles acknowledging messages the consumer does not currently hold a lease on.
+    #[error(
+        "offset {offset} of {partition} is not leased to consumer '{consumer}' of queue '{queue}'"
+    )]
+    NotLeased {
+        /// The name of the queue.
+        queue: String,
+        /// The partition the message was read from.
+        partition: TopicPartition,
+        /// The offset of the message.
+        offset: u64,
+        /// The consumer acknowledging the message.
+        consumer: String,
+    },
+}
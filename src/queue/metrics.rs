@@ -0,0 +1,45 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::sync::OnceLock;
+
+use prometheus::IntCounterVec;
+
+use crate::metrics::{register_int_counter_vec, Opt};
+
+/// The series exported by the queue coordinator.
+pub(super) struct Metrics {
+    /// Messages made available for redelivery, labelled by why they were released.
+    pub(super) released: IntCounterVec,
+    /// Messages given up on after exhausting their delivery attempts or
+    /// outliving their time to live, labelled by queue.
+    pub(super) dead_lettered: IntCounterVec,
+}
+
+/// Returns the process wide queue metrics, registering them on first use.
+pub(super) fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(|| {
+        let opts = |label: &str| {
+            vec![
+                Opt::Namespace(String::from("rift")),
+                Opt::Subsystem(String::from("queue")),
+                Opt::Label(label.to_owned()),
+            ]
+        };
+        Metrics {
+            released: register_int_counter_vec(
+                "released_total",
+                "The number of leased messages released for redelivery, by a nack or their visibility timeout.",
+                Some(opts("reason")),
+            )
+            .expect("queue metrics registered twice"),
+            dead_lettered: register_int_counter_vec(
+                "dead_lettered_total",
+                "The number of messages moved to a dead-letter topic after exhausting their delivery attempts or expiring.",
+                Some(opts("queue")),
+            )
+            .expect("queue metrics registered twice"),
+        }
+    })
+}
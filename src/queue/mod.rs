@@ -0,0 +1,18 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod config;
+mod coordinator;
+mod dead_letter;
+mod error;
+mod metrics;
+mod priority;
+mod state;
+
+pub use self::config::Config;
+pub use self::coordinator::{Coordinator, Delivery, DeliveryPolicy, QUEUE_STATE_TOPIC};
+pub use self::dead_letter::{
+    replay_target, DeadLetter, DELIVERY_COUNT_HEADER, ERROR_HEADER, OFFSET_HEADER,
+    PARTITION_HEADER, QUEUE_HEADER, TOPIC_HEADER, TTL_MS_HEADER,
+};
+pub use self::error::{Error, Result};
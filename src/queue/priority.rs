@@ -0,0 +1,107 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::record::MAX_PRIORITY;
+
+/// The number of priority levels messages are delivered at.
+const LEVELS: usize = MAX_PRIORITY as usize + 1;
+
+/// Chooses which priority level of a queue partition the next message is
+/// delivered from.
+///
+/// Levels are served by stride scheduling: every level with messages waiting
+/// is owed a turn once its virtual time comes up, and each turn advances it by
+/// a stride inversely proportional to the level's weight. With a weighting of
+/// `w` a level weighs `w` times as much as the one below it, so while both
+/// have messages waiting it is served `w` times as often, and lower levels are
+/// never starved entirely. Levels that were idle resume from the current
+/// virtual time, rather than catching up on the turns they missed.
+#[derive(Debug, Default)]
+pub(super) struct Levels {
+    passes: [f64; LEVELS],
+    waiting: [bool; LEVELS],
+    now: f64,
+}
+
+impl Levels {
+    /// Returns the level to deliver the next message from out of those with
+    /// messages waiting, if any, charging it for the turn. A weighting of zero
+    /// always serves the highest level with messages waiting.
+    pub(super) fn next(&mut self, waiting: impl Fn(u8) -> bool, weighting: u32) -> Option<u8> {
+        let mut chosen: Option<(u8, f64)> = None;
+        for level in (0..=MAX_PRIORITY).rev() {
+            let idx = level as usize;
+            let was_waiting = std::mem::replace(&mut self.waiting[idx], waiting(level));
+            if !self.waiting[idx] {
+                continue;
+            }
+            if !was_waiting {
+                self.passes[idx] = self.passes[idx].max(self.now);
+            }
+            if weighting == 0 {
+                chosen = chosen.or(Some((level, 0.0)));
+                continue;
+            }
+            let finish = self.passes[idx] + 1.0 / (weighting as f64).powi(level as i32);
+            // Ties go to the higher level, which is visited first.
+            if chosen.is_none_or(|(_, best)| finish < best) {
+                chosen = Some((level, finish));
+            }
+        }
+        let (level, finish) = chosen?;
+        if weighting > 0 {
+            self.passes[level as usize] = finish;
+            self.now = self.now.max(finish);
+        }
+        Some(level)
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    fn serve(levels: &mut Levels, waiting: &[u8], weighting: u32, turns: usize) -> Vec<u8> {
+        (0..turns)
+            .map(|_| {
+                levels
+                    .next(|level| waiting.contains(&level), weighting)
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_strict() {
+        let mut levels = Levels::default();
+        assert_eq!(None, levels.next(|_| false, 0));
+        assert_eq!(vec![9, 9, 9], serve(&mut levels, &[0, 9], 0, 3));
+        assert_eq!(vec![4], serve(&mut levels, &[0, 4], 0, 1));
+    }
+
+    #[test]
+    fn test_weighted() {
+        let mut levels = Levels::default();
+        assert_eq!(None, levels.next(|_| false, 2));
+
+        // Higher levels go first and are served more often, without starving
+        // the lower ones.
+        let served = serve(&mut levels, &[1, 3], 2, 10);
+        assert_eq!(vec![3, 3, 3, 3, 1, 3, 3, 3, 3, 1], served);
+
+        // Equal weights take turns.
+        let mut levels = Levels::default();
+        assert_eq!(vec![5, 2, 5, 2], serve(&mut levels, &[2, 5], 1, 4));
+    }
+
+    #[test]
+    fn test_idle_levels() {
+        let mut levels = Levels::default();
+        serve(&mut levels, &[0], 2, 100);
+
+        // A level that was idle does not make up for the turns it missed.
+        let served = serve(&mut levels, &[0, 1], 2, 6);
+        assert_eq!(vec![1, 1, 0, 1, 1, 0], served);
+    }
+}
@@ -0,0 +1,226 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::BTreeMap;
+
+use crate::codec::{self, Reader, Writer};
+use crate::record::Record;
+use crate::topic::TopicPartition;
+
+/// The current version of the queue state record key format.
+const KEY_VERSION: u16 = 0;
+/// The current version of the queue state record value format.
+const VALUE_VERSION: u16 = 2;
+/// The value format that predates storing the priority of each message.
+const VALUE_VERSION_V1: u16 = 1;
+/// The value format that predates storing the last error of each message.
+const VALUE_VERSION_V0: u16 = 0;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The progress of a queue through a single partition as stored in the queue
+/// state topic.
+///
+/// Records are keyed by queue and partition so that compacting the topic
+/// retains only the latest progress of each. Leases are not stored, so every
+/// unacknowledged message is redelivered after a restart.
+pub(super) struct QueueProgress {
+    pub(super) queue: String,
+    pub(super) partition: TopicPartition,
+    /// The offset new messages are read from.
+    pub(super) next_offset: u64,
+    /// Every message read but not acknowledged by offset, including those
+    /// read ahead that have not been delivered yet.
+    pub(super) unacked: BTreeMap<u64, Pending>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// An unacknowledged message as stored in the queue state topic.
+pub(super) struct Pending {
+    /// How many times the message has been delivered.
+    pub(super) deliveries: u32,
+    /// Why the message was last released, empty if it never was.
+    pub(super) last_error: String,
+    /// The priority the message is delivered at.
+    pub(super) priority: u8,
+}
+
+impl QueueProgress {
+    pub(super) fn to_record(&self) -> Record {
+        let mut key = Vec::new();
+        key.put_u16(KEY_VERSION);
+        key.put_string(&self.queue);
+        key.put_string(&self.partition.topic);
+        key.put_u32(self.partition.partition);
+
+        let mut value = Vec::new();
+        value.put_u16(VALUE_VERSION);
+        value.put_u64(self.next_offset);
+        let unacked: Vec<(&u64, &Pending)> = self.unacked.iter().collect();
+        value.put_array(&unacked, |buf, (offset, pending)| {
+            buf.put_u64(**offset);
+            buf.put_u32(pending.deliveries);
+            buf.put_string(&pending.last_error);
+            buf.put_u8(pending.priority);
+        });
+        Record {
+            key: Some(key),
+            value: Some(value),
+            headers: Vec::new(),
+            timestamp: None,
+        }
+    }
+
+    pub(super) fn from_record(record: &Record) -> codec::Result<QueueProgress> {
+        let key = record.key.as_deref().ok_or(codec::Error::InvalidValue {
+            field: "queue state key",
+            value: -1,
+        })?;
+        let mut reader = Reader::new(key);
+        check_version(reader.get_u16()?, KEY_VERSION, "queue state key version")?;
+        let queue = reader.get_string()?;
+        let partition = TopicPartition {
+            topic: reader.get_string()?,
+            partition: reader.get_u32()?,
+        };
+
+        let value = record.value.as_deref().ok_or(codec::Error::InvalidValue {
+            field: "queue state value",
+            value: -1,
+        })?;
+        let mut reader = Reader::new(value);
+        let version = reader.get_u16()?;
+        if version != VALUE_VERSION_V0 && version != VALUE_VERSION_V1 {
+            check_version(version, VALUE_VERSION, "queue state value version")?;
+        }
+        Ok(QueueProgress {
+            queue,
+            partition,
+            next_offset: reader.get_u64()?,
+            unacked: reader
+                .get_array(|reader| {
+                    let offset = reader.get_u64()?;
+                    let pending = Pending {
+                        deliveries: reader.get_u32()?,
+                        last_error: match version {
+                            VALUE_VERSION_V0 => String::new(),
+                            _ => reader.get_string()?,
+                        },
+                        priority: match version {
+                            VALUE_VERSION_V0 | VALUE_VERSION_V1 => 0,
+                            _ => reader.get_u8()?,
+                        },
+                    };
+                    Ok((offset, pending))
+                })?
+                .into_iter()
+                .collect(),
+        })
+    }
+}
+
+fn check_version(version: u16, expected: u16, field: &'static str) -> codec::Result<()> {
+    if version != expected {
+        return Err(codec::Error::InvalidValue {
+            field,
+            value: version as i64,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let progress = QueueProgress {
+            queue: String::from("jobs"),
+            partition: TopicPartition::new("events", 1),
+            next_offset: 12,
+            unacked: [
+                (3, pending(2, "timeout", 0)),
+                (10, pending(1, "", 9)),
+                (11, pending(0, "", 4)),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        assert_eq!(
+            progress,
+            QueueProgress::from_record(&progress.to_record()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_v0_value() {
+        let mut value = Vec::new();
+        value.put_u16(VALUE_VERSION_V0);
+        value.put_u64(12);
+        value.put_array(&[(3u64, 2u32)], |buf, (offset, deliveries)| {
+            buf.put_u64(*offset);
+            buf.put_u32(*deliveries);
+        });
+        let mut record = QueueProgress {
+            queue: String::from("jobs"),
+            partition: TopicPartition::new("events", 1),
+            next_offset: 0,
+            unacked: BTreeMap::new(),
+        }
+        .to_record();
+        record.value = Some(value);
+
+        let progress = QueueProgress::from_record(&record).unwrap();
+        assert_eq!(12, progress.next_offset);
+        assert_eq!(
+            vec![(3, pending(2, "", 0))],
+            progress.unacked.into_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_v1_value() {
+        let mut value = Vec::new();
+        value.put_u16(VALUE_VERSION_V1);
+        value.put_u64(12);
+        value.put_array(&[(3u64, 2u32)], |buf, (offset, deliveries)| {
+            buf.put_u64(*offset);
+            buf.put_u32(*deliveries);
+            buf.put_string("boom");
+        });
+        let mut record = QueueProgress {
+            queue: String::from("jobs"),
+            partition: TopicPartition::new("events", 1),
+            next_offset: 0,
+            unacked: BTreeMap::new(),
+        }
+        .to_record();
+        record.value = Some(value);
+
+        let progress = QueueProgress::from_record(&record).unwrap();
+        assert_eq!(
+            vec![(3, pending(2, "boom", 0))],
+            progress.unacked.into_iter().collect::<Vec<_>>()
+        );
+    }
+
+    fn pending(deliveries: u32, last_error: &str, priority: u8) -> Pending {
+        Pending {
+            deliveries,
+            last_error: last_error.to_owned(),
+            priority,
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(QueueProgress::from_record(&Record::new("value")).is_err());
+
+        let record = Record::default().with_key(vec![0, 7]);
+        assert!(matches!(
+            QueueProgress::from_record(&record),
+            Err(codec::Error::InvalidValue { value: 7, .. })
+        ));
+    }
+}
@@ -0,0 +1,182 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{fmt, str::FromStr, time::Duration};
+
+use structopt::StructOpt;
+
+use super::error::Error;
+
+/// How many replicas must have a produced record before it is acknowledged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Acks {
+    /// Acknowledge records once the partition leader has appended them.
+    Leader,
+    /// Acknowledge records once a majority of the partition's replicas have them.
+    Quorum,
+}
+
+impl FromStr for Acks {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "leader" => Ok(Acks::Leader),
+            "quorum" => Ok(Acks::Quorum),
+            _ => Err(Error::InvalidConfig {
+                reason: format!("unknown acks '{}', expected one of leader or quorum", s),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Acks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Acks::Leader => write!(f, "leader"),
+            Acks::Quorum => write!(f, "quorum"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, StructOpt)]
+/// Rift partition replication configuration.
+pub struct Config {
+    #[structopt(
+        long = "replication-factor",
+        env = "RIFT_REPLICATION_FACTOR",
+        help = "How many nodes hold a copy of each partition.",
+        long_help = "Sets the number of nodes each partition is replicated to, capped at the size of the cluster.",
+        default_value = "3",
+        takes_value = true
+    )]
+    /// Define the number of replicas of each partition.
+    pub replication_factor: u32,
+
+    #[structopt(
+        long = "acks",
+        env = "RIFT_ACKS",
+        help = "When produced records are acknowledged.",
+        long_help = "Sets whether produced records are acknowledged once the partition leader has appended them (leader), or once a majority of the partition's replicas have them (quorum).",
+        default_value = "quorum",
+        takes_value = true
+    )]
+    /// Define when produced records are acknowledged.
+    pub acks: Acks,
+
+    #[structopt(
+        long = "raft-election-timeout-ms",
+        env = "RIFT_RAFT_ELECTION_TIMEOUT_MS",
+        help = "How long followers wait to hear from a leader before electing a new one.",
+        long_help = "Sets the minimum time in milliseconds a follower waits without hearing from its leader before starting an election, randomized up to twice this value. Also bounds how long requests to peers may take.",
+        default_value = "1000",
+        takes_value = true
+    )]
+    /// Define the minimum election timeout in milliseconds.
+    pub election_timeout_ms: u64,
+
+    #[structopt(
+        long = "raft-heartbeat-interval-ms",
+        env = "RIFT_RAFT_HEARTBEAT_INTERVAL_MS",
+        help = "How often leaders contact idle followers.",
+        long_help = "Sets the interval in milliseconds at which partition leaders contact followers they have nothing new to send to.",
+        default_value = "100",
+        takes_value = true
+    )]
+    /// Define the heartbeat interval in milliseconds.
+    pub heartbeat_interval_ms: u64,
+
+    #[structopt(
+        long = "replication-timeout-ms",
+        env = "RIFT_REPLICATION_TIMEOUT_MS",
+        help = "How long produce requests wait for records to replicate.",
+        long_help = "Sets the time in milliseconds produce requests wait for a quorum of replicas to have their records before failing.",
+        default_value = "5000",
+        takes_value = true
+    )]
+    /// Define the replication timeout in milliseconds.
+    pub replication_timeout_ms: u64,
+
+    #[structopt(
+        long = "replica-lag-time-ms",
+        env = "RIFT_REPLICA_LAG_TIME_MS",
+        help = "How long a follower may lag behind its leader and stay in sync.",
+        long_help = "Sets the time in milliseconds a follower may go without catching up to its partition leader before it is dropped from the partition's in-sync replicas.",
+        default_value = "10000",
+        takes_value = true
+    )]
+    /// Define the maximum in-sync replica lag in milliseconds.
+    pub replica_lag_time_ms: u64,
+}
+
+impl Config {
+    /// Returns the minimum election timeout.
+    pub fn election_timeout(&self) -> Duration {
+        Duration::from_millis(self.election_timeout_ms)
+    }
+
+    /// Returns the interval between heartbeats to idle followers.
+    pub fn heartbeat_interval(&self) -> Duration {
+        Duration::from_millis(self.heartbeat_interval_ms)
+    }
+
+    /// Returns how long produce requests wait for records to replicate.
+    pub fn replication_timeout(&self) -> Duration {
+        Duration::from_millis(self.replication_timeout_ms)
+    }
+
+    /// Returns how long a follower may lag behind its leader and stay in sync.
+    pub fn replica_lag_time(&self) -> Duration {
+        Duration::from_millis(self.replica_lag_time_ms)
+    }
+
+    /// Validate that the configuration describes usable replication settings.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.replication_factor == 0 {
+            return Err(Error::InvalidConfig {
+                reason: String::from("the replication factor must be at least 1"),
+            });
+        }
+        if self.election_timeout_ms == 0 || self.heartbeat_interval_ms == 0 {
+            return Err(Error::InvalidConfig {
+                reason: String::from("raft timeouts must be greater than 0"),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            replication_factor: 3,
+            acks: Acks::Quorum,
+            election_timeout_ms: 1000,
+            heartbeat_interval_ms: 100,
+            replication_timeout_ms: 5000,
+            replica_lag_time_ms: 10000,
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let mut cfg = Config::default();
+        assert!(cfg.validate().is_ok());
+        assert_eq!(Duration::from_secs(10), cfg.replica_lag_time());
+
+        cfg.heartbeat_interval_ms = 0;
+        assert!(cfg.validate().is_err());
+
+        cfg.heartbeat_interval_ms = 100;
+        cfg.replication_factor = 0;
+        assert!(cfg.validate().is_err());
+        assert_eq!(Acks::Leader, "leader".parse().unwrap());
+        assert!("all".parse::<Acks>().is_err());
+    }
+}
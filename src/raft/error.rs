@@ -0,0 +1,102 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{io, path::PathBuf, result};
+
+use thiserror::Error;
+
+use crate::cluster;
+use crate::topic::{self, TopicPartition};
+
+/// Custom Result wrapper to simplify usage.
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents errors replicating partitions.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// Handles errors raised by the replicated topics.
+    #[error(transparent)]
+    Topic(#[from] topic::Error),
+    /// Handles errors raised managing cluster membership and metadata.
+    #[error(transparent)]
+    Cluster(#[from] cluster::Error),
+    /// Handles OS level errors while persisting replica state.
+    #[error("i/o error on '{path}': {source}")]
+    Io {
+        /// The file being operated on.
+        path: PathBuf,
+        /// The initial error cause.
+        source: io::Error,
+    },
+    /// Handles replica state files that could not be serialized or deserialized.
+    #[error("invalid replica state in '{path}': {source}")]
+    State {
+        /// The state file being operated on.
+        path: PathBuf,
+        /// The initial error cause.
+        source: serde_json::Error,
+    },
+    /// Handles configuration that does not describe a usable cluster.
+    #[error("invalid replication configuration: {reason}")]
+    InvalidConfig {
+        /// Why the configuration was rejected.
+        reason: String,
+    },
+    /// Handles requests for partitions this node holds no replica of.
+    #[error("this node is not a replica of '{}' partition {}", .partition.topic, .partition.partition)]
+    NotReplica {
+        /// The requested partition.
+        partition: TopicPartition,
+    },
+    /// Handles writes to a partition replica that is not the leader.
+    #[error("this node is not the leader of '{}' partition {}{}", .partition.topic, .partition.partition, leader_hint(.leader))]
+    NotLeader {
+        /// The requested partition.
+        partition: TopicPartition,
+        /// The node id of the current leader, if known.
+        leader: Option<u32>,
+    },
+    /// Handles records that were not replicated to a quorum in time.
+    #[error("records at offset {offset} of '{}' partition {} were not replicated in time", .partition.topic, .partition.partition)]
+    ReplicationTimeout {
+        /// The partition the records were appended to.
+        partition: TopicPartition,
+        /// The offset of the first record appended.
+        offset: u64,
+    },
+}
+
+impl Error {
+    /// Wrap an i/o error with the path it occurred on.
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Error {
+        Error::Io {
+            path: path.into(),
+            source,
+        }
+    }
+}
+
+fn leader_hint(leader: &Option<u32>) -> String {
+    match leader {
+        Some(leader) => format!(", node {} is", leader),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let err = Error::NotLeader {
+            partition: TopicPartition::new("events", 1),
+            leader: Some(2),
+        };
+        assert_eq!(
+            "this node is not the leader of 'events' partition 1, node 2 is",
+            err.to_string()
+        );
+    }
+}
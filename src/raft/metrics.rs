@@ -0,0 +1,56 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::sync::OnceLock;
+
+use prometheus::IntGaugeVec;
+
+use crate::metrics::{register_int_gauge_vec, Opt};
+use crate::topic::TopicPartition;
+
+const SUBSYSTEM: &str = "raft";
+
+/// The per replica series exported by partition leaders.
+pub(super) struct Metrics {
+    replication_lag: IntGaugeVec,
+}
+
+impl Metrics {
+    /// Publish how many records a follower is behind its leader.
+    pub(super) fn observe_lag(&self, partition: &TopicPartition, replica: u32, lag: u64) {
+        let (id, replica) = (partition.partition.to_string(), replica.to_string());
+        self.replication_lag
+            .with_label_values(&[&partition.topic, &id, &replica])
+            .set(lag as i64);
+    }
+
+    /// Stop exporting lag for a follower once this node stops leading its partition.
+    pub(super) fn remove_lag(&self, partition: &TopicPartition, replica: u32) {
+        let (id, replica) = (partition.partition.to_string(), replica.to_string());
+        let _ = self
+            .replication_lag
+            .remove_label_values(&[&partition.topic, &id, &replica]);
+    }
+}
+
+/// Returns the process wide raft metrics, registering them on first use.
+pub(super) fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(|| {
+        let opts = vec![
+            Opt::Namespace(String::from("rift")),
+            Opt::Subsystem(String::from(SUBSYSTEM)),
+            Opt::Label(String::from("topic")),
+            Opt::Label(String::from("partition")),
+            Opt::Label(String::from("replica")),
+        ];
+        Metrics {
+            replication_lag: register_int_gauge_vec(
+                "replication_lag",
+                "The number of records a follower replica is behind its partition leader.",
+                Some(opts),
+            )
+            .expect("raft metrics registered twice"),
+        }
+    })
+}
@@ -0,0 +1,14 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod config;
+mod error;
+mod metrics;
+mod node;
+mod peer;
+mod replica;
+mod state;
+
+pub use self::config::{Acks, Config};
+pub use self::error::{Error, Result};
+pub use self::node::Node;
@@ -0,0 +1,1231 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    net::SocketAddr,
+    sync::{Arc, Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::client::Client;
+use crate::cluster::{self, ClusterMetadata, MetadataRecord, METADATA_TOPIC};
+use crate::protocol::{
+    AppendRequest, AppendResponse, MetadataWriteRequest, Request, Response, SnapshotRequest,
+    VoteRequest, VoteResponse,
+};
+use crate::record::{Compression, ControlMarker, ProducerBatch, Record};
+use crate::topic::{self, Partitioning, Topic, TopicConfig, TopicPartition, CLEANUP_POLICY};
+
+use super::config::{Acks, Config};
+use super::error::{Error, Result};
+use super::peer::Peer;
+use super::replica::Replica;
+
+/// The most entry bytes shipped to a follower in a single append request.
+const MAX_APPEND_BYTES: usize = 1024 * 1024;
+
+/// Wakes replication threads whenever a leader appends new records.
+struct Signal {
+    generation: Mutex<u64>,
+    cond: Condvar,
+}
+
+impl Signal {
+    fn generation(&self) -> u64 {
+        *self
+            .generation
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn notify(&self) {
+        let mut generation = self
+            .generation
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        *generation = generation.wrapping_add(1);
+        self.cond.notify_all();
+    }
+
+    /// Wait until notified after the supplied generation was observed, or the
+    /// timeout elapses.
+    fn wait(&self, seen: u64, timeout: Duration) {
+        let generation = self
+            .generation
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let _ = self
+            .cond
+            .wait_timeout_while(generation, timeout, |generation| *generation == seen);
+    }
+}
+
+/// This node's membership in a cluster of riftd nodes, replicating every
+/// external topic's partitions to a subset of the cluster with raft.
+///
+/// The cluster's membership, topics, and partition leadership are kept in the
+/// compacted `__cluster_metadata` topic, itself replicated with raft among the
+/// seed nodes and copied to every other node as a learner. Changes are made by
+/// appending records to it through its leader, and every node applies them
+/// once committed, creating and deleting its local topics to match.
+///
+/// Every partition is replicated independently, with its own leader elected
+/// among the nodes it was placed on. Only the leader accepts produced records,
+/// and consumers only see records once a quorum of the partition's replicas
+/// has them.
+pub struct Node {
+    logger: slog::Logger,
+    cluster: cluster::Config,
+    address: SocketAddr,
+    cfg: Config,
+    topics: Arc<topic::Manager>,
+    peers: RwLock<BTreeMap<u32, Arc<Peer>>>,
+    replicators: Mutex<BTreeSet<u32>>,
+    replicas: RwLock<HashMap<TopicPartition, Arc<Replica>>>,
+    metadata: RwLock<ClusterMetadata>,
+    applied: Mutex<u64>,
+    signal: Signal,
+}
+
+impl Node {
+    /// Join the cluster described by the supplied configuration, registering
+    /// this node at the supplied address and replicating the supplied topics.
+    /// Any metadata already committed locally is applied before returning.
+    pub fn new(
+        logger: slog::Logger,
+        cluster: cluster::Config,
+        address: SocketAddr,
+        cfg: Config,
+        topics: Arc<topic::Manager>,
+    ) -> Result<Node> {
+        cluster.validate()?;
+        cfg.validate()?;
+        if let Err(topic::Error::NotFound { .. }) = topics.get(METADATA_TOPIC) {
+            // Only the latest state of each node, topic, and partition is needed.
+            let mut config = TopicConfig::new();
+            config.set(CLEANUP_POLICY, "compact")?;
+            topics.create(METADATA_TOPIC, 1, config)?;
+        }
+
+        let peers = cluster
+            .seeds
+            .iter()
+            .filter(|seed| seed.id != cluster.node_id)
+            .map(|seed| {
+                let peer = Peer::new(seed.id, seed.addr, cfg.election_timeout());
+                (seed.id, Arc::new(peer))
+            })
+            .collect();
+        let node = Node {
+            logger,
+            cluster,
+            address,
+            cfg,
+            topics,
+            peers: RwLock::new(peers),
+            replicators: Mutex::new(BTreeSet::new()),
+            replicas: RwLock::new(HashMap::new()),
+            metadata: RwLock::new(ClusterMetadata::default()),
+            applied: Mutex::new(0),
+            signal: Signal {
+                generation: Mutex::new(0),
+                cond: Condvar::new(),
+            },
+        };
+        node.sync()?;
+        node.apply()?;
+        Ok(node)
+    }
+
+    /// Returns the id of this node.
+    pub fn id(&self) -> u32 {
+        self.cluster.node_id
+    }
+
+    /// Returns the address this node registers with the cluster.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Returns the replication configuration of this node.
+    pub fn config(&self) -> &Config {
+        &self.cfg
+    }
+
+    /// Returns a copy of the cluster metadata this node has applied so far.
+    pub fn cluster(&self) -> ClusterMetadata {
+        self.metadata().clone()
+    }
+
+    /// Returns the id of the seed coordinating the supplied consumer group, see
+    /// [cluster::Config::coordinator].
+    pub fn coordinator(&self, group: &str) -> u32 {
+        self.cluster
+            .coordinator(group)
+            .map_or(self.id(), |seed| seed.id)
+    }
+
+    /// Returns the id of the supplied partition's leader as known to this node,
+    /// which must hold a replica of the partition.
+    pub fn leader(&self, partition: &TopicPartition) -> Option<u32> {
+        self.replica(partition)
+            .ok()
+            .and_then(|replica| replica.leader())
+    }
+
+    /// Returns the offset below which records of the supplied partition are
+    /// committed, and so may be served to consumers.
+    pub fn high_watermark(&self, partition: &TopicPartition) -> Result<u64> {
+        Ok(self.replica(partition)?.commit_offset())
+    }
+
+    /// Create a topic across the cluster, placing its partitions' replicas on
+    /// the nodes that have joined it, and wait for the topic to exist locally.
+    pub fn create_topic(
+        &self,
+        name: &str,
+        partitions: u32,
+        config: TopicConfig,
+    ) -> Result<Arc<Topic>> {
+        topic::validate_name(name)?;
+        if partitions == 0 {
+            return Err(topic::Error::InvalidPartitions { partitions }.into());
+        }
+        config.validate()?;
+
+        self.apply()?;
+        let replicas = {
+            let metadata = self.metadata();
+            if metadata.topic(name).is_some() {
+                return Err(topic::Error::AlreadyExists {
+                    name: name.to_owned(),
+                }
+                .into());
+            }
+            metadata.assign(name, partitions, self.cfg.replication_factor)?
+        };
+        self.submit(&MetadataRecord::CreateTopic {
+            name: name.to_owned(),
+            config: config
+                .iter()
+                .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                .collect(),
+            replicas,
+        })?;
+        self.await_applied(|| self.topics.get(name).ok())
+    }
+
+    /// Delete a topic across the cluster, and wait for it to be removed locally.
+    pub fn delete_topic(&self, name: &str) -> Result<()> {
+        self.apply()?;
+        if self.metadata().topic(name).is_none() {
+            return Err(topic::Error::NotFound {
+                name: name.to_owned(),
+            }
+            .into());
+        }
+        self.submit(&MetadataRecord::DeleteTopic {
+            name: name.to_owned(),
+        })?;
+        self.await_applied(|| self.topics.get(name).err().map(|_| ()))
+    }
+
+    /// Write records to a replicated topic like [Topic::produce], failing for
+    /// any partition this node does not lead. Records are acknowledged as
+    /// configured by [Config::acks].
+    ///
+    /// Records written by an idempotent producer must all be destined for the
+    /// same partition, as the producer's sequence numbers are per partition.
+    pub fn produce(
+        &self,
+        topic: &Topic,
+        partitioning: Partitioning,
+        records: Vec<Record>,
+        producer: Option<ProducerBatch>,
+        compression: Compression,
+    ) -> Result<Vec<(u32, u64)>> {
+        topic.produce_with(partitioning, records, |partition, records| {
+            let replica = self.replica(&TopicPartition::new(topic.name(), partition.id()))?;
+            let appended = replica.append_batch(records, producer, compression)?;
+            self.signal.notify();
+            if self.cfg.acks == Acks::Quorum {
+                replica.await_commit(appended, self.cfg.replication_timeout())?;
+            }
+            Ok(appended.base_offset)
+        })
+    }
+
+    /// Write a marker completing the supplied producer's transaction to a
+    /// partition this node leads, returning the marker's offset.
+    pub fn write_marker(
+        &self,
+        partition: &TopicPartition,
+        producer_id: u64,
+        epoch: u16,
+        marker: ControlMarker,
+    ) -> Result<u64> {
+        let replica = self.replica(partition)?;
+        let appended = replica.append_marker(producer_id, epoch, marker)?;
+        self.signal.notify();
+        if self.cfg.acks == Acks::Quorum {
+            replica.await_commit(appended, self.cfg.replication_timeout())?;
+        }
+        Ok(appended.base_offset)
+    }
+
+    /// Answer a candidate's request for this node's vote.
+    pub fn handle_vote(&self, req: VoteRequest) -> Result<VoteResponse> {
+        self.replica(&req.partition)?
+            .handle_vote(&req, Instant::now())
+    }
+
+    /// Append a leader's entries to this node's replica of a partition.
+    pub fn handle_append(&self, req: AppendRequest) -> Result<AppendResponse> {
+        self.replica(&req.partition)?
+            .handle_append(req, Instant::now())
+    }
+
+    /// Restart this node's replica of a partition at the leader's log start.
+    pub fn handle_snapshot(&self, req: SnapshotRequest) -> Result<AppendResponse> {
+        self.replica(&req.partition)?
+            .handle_snapshot(&req, Instant::now())
+    }
+
+    /// Append a metadata change submitted by another node, which requires this
+    /// node to lead the metadata topic.
+    pub fn handle_metadata_write(&self, req: MetadataWriteRequest) -> Result<()> {
+        let record = MetadataRecord::decode(&req.record)?;
+        let replica = self.replica(&metadata_partition())?;
+        let appended = replica.append(&[record.to_record()])?;
+        self.signal.notify();
+        replica.await_commit(appended, self.cfg.replication_timeout())
+    }
+
+    /// Spawn the background threads driving elections, replication, and
+    /// reporting to the cluster. The threads exit once the node is dropped.
+    pub fn start(self: &Arc<Self>) {
+        let node = Arc::downgrade(self);
+        let interval = self.cfg.heartbeat_interval();
+        thread::spawn(move || {
+            while let Some(node) = node.upgrade() {
+                node.tick(Instant::now());
+                node.spawn_replicators();
+                drop(node);
+                thread::sleep(interval);
+            }
+        });
+
+        // Reports wait on the metadata leader, so they must not hold up elections.
+        let node = Arc::downgrade(self);
+        thread::spawn(move || {
+            while let Some(node) = node.upgrade() {
+                node.report(Instant::now());
+                drop(node);
+                thread::sleep(interval);
+            }
+        });
+    }
+
+    /// Apply newly committed metadata, start elections for partitions whose
+    /// leader has gone quiet, and checkpoint commit offsets.
+    pub fn tick(&self, now: Instant) {
+        if let Err(err) = self.apply() {
+            error!(self.logger, "Failed to apply cluster metadata."; "error" => err.to_string());
+        }
+        for replica in self.list() {
+            if let Err(err) = self.elect(&replica, now) {
+                error!(self.logger, "Failed to run partition leader election."; "topic" => &replica.partition().topic, "partition" => replica.partition().partition, "error" => err.to_string());
+            }
+            if let Err(err) = replica.checkpoint() {
+                error!(self.logger, "Failed to checkpoint partition commit offset."; "topic" => &replica.partition().topic, "partition" => replica.partition().partition, "error" => err.to_string());
+            }
+        }
+    }
+
+    /// Register this node with the cluster if its address is not yet known,
+    /// and report the leader and in-sync replicas of every partition this node
+    /// leads whose metadata is out of date.
+    pub fn report(&self, now: Instant) {
+        if let Err(err) = self.apply() {
+            error!(self.logger, "Failed to apply cluster metadata."; "error" => err.to_string());
+        }
+        let registered = self.metadata().nodes().get(&self.id()).copied();
+        if registered != Some(self.address) {
+            let record = MetadataRecord::RegisterNode {
+                id: self.id(),
+                address: self.address,
+            };
+            match self.submit(&record) {
+                Ok(()) => {
+                    info!(self.logger, "Registered with the cluster."; "node" => self.id(), "address" => self.address.to_string())
+                }
+                Err(err) => {
+                    debug!(self.logger, "Failed to register with the cluster."; "error" => err.to_string());
+                    return;
+                }
+            }
+        }
+
+        for replica in self.list() {
+            let partition = replica.partition();
+            let (term, isr) = match replica.leadership(now, self.cfg.replica_lag_time()) {
+                Some(leadership) if partition.topic != METADATA_TOPIC => leadership,
+                _ => continue,
+            };
+            let current = self
+                .metadata()
+                .partition(&partition.topic, partition.partition)
+                .cloned();
+            match current {
+                Some(state)
+                    if state.leader != Some(self.id())
+                        || state.term != term
+                        || state.isr != isr => {}
+                _ => continue,
+            }
+            let record = MetadataRecord::PartitionLeader {
+                topic: partition.topic.clone(),
+                partition: partition.partition,
+                leader: self.id(),
+                term,
+                isr,
+            };
+            if let Err(err) = self.submit(&record) {
+                debug!(self.logger, "Failed to report partition leadership."; "topic" => &partition.topic, "partition" => partition.partition, "error" => err.to_string());
+            }
+        }
+    }
+
+    /// Send the next batch of entries, or a heartbeat, to the supplied peer for
+    /// every partition this node leads that it also replicates, returning
+    /// whether or not the peer is still behind on any of them.
+    pub fn replicate(&self, id: u32) -> bool {
+        let peer = match self.peer(id) {
+            Some(peer) => peer,
+            None => return false,
+        };
+
+        let mut behind = false;
+        for replica in self.list() {
+            let request = match replica.prepare(id, MAX_APPEND_BYTES) {
+                Ok(Some(request)) => request,
+                Ok(None) => continue,
+                Err(err) => {
+                    error!(self.logger, "Failed to read entries to replicate."; "topic" => &replica.partition().topic, "partition" => replica.partition().partition, "error" => err.to_string());
+                    continue;
+                }
+            };
+            let term = match &request {
+                Request::RaftAppend(req) => req.term,
+                Request::RaftSnapshot(req) => req.term,
+                _ => continue,
+            };
+            let resp = match peer.call(&request) {
+                Ok(Response::RaftAppend(resp)) | Ok(Response::RaftSnapshot(resp)) => resp,
+                Ok(_) => continue,
+                Err(err) => {
+                    debug!(self.logger, "Failed to replicate to peer."; "topic" => &replica.partition().topic, "partition" => replica.partition().partition, "peer" => peer.id(), "error" => err.to_string());
+                    continue;
+                }
+            };
+            match replica.handle_progress(id, term, &resp, Instant::now()) {
+                Ok(more) => behind |= more,
+                Err(err) => {
+                    error!(self.logger, "Failed to record replication progress."; "topic" => &replica.partition().topic, "partition" => replica.partition().partition, "error" => err.to_string());
+                }
+            }
+        }
+        behind
+    }
+
+    /// Spawn a replication thread for every peer that does not have one yet.
+    fn spawn_replicators(self: &Arc<Self>) {
+        let ids: Vec<u32> = self.peers().keys().copied().collect();
+        let mut spawned = self
+            .replicators
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let interval = self.cfg.heartbeat_interval();
+        for id in ids {
+            if !spawned.insert(id) {
+                continue;
+            }
+            let node = Arc::downgrade(self);
+            thread::spawn(move || {
+                while let Some(node) = node.upgrade() {
+                    let seen = node.signal.generation();
+                    if !node.replicate(id) {
+                        node.signal.wait(seen, interval);
+                    }
+                }
+            });
+        }
+    }
+
+    fn elect(&self, replica: &Replica, now: Instant) -> Result<()> {
+        let request = match replica.start_election(now)? {
+            Some(request) => request,
+            None => return Ok(()),
+        };
+
+        let mut votes = 1;
+        for id in replica.peers() {
+            let peer = match self.peer(id) {
+                Some(peer) => peer,
+                None => continue,
+            };
+            match peer.call(&Request::RaftVote(request.clone())) {
+                Ok(Response::RaftVote(resp)) => {
+                    if replica.observe_term(resp.term)? {
+                        return Ok(());
+                    }
+                    if resp.granted {
+                        votes += 1;
+                    }
+                }
+                Ok(_) => continue,
+                Err(err) => {
+                    debug!(self.logger, "Failed to request vote from peer."; "topic" => &request.partition.topic, "partition" => request.partition.partition, "peer" => id, "error" => err.to_string());
+                }
+            }
+        }
+        if replica.finish_election(request.term, votes)? {
+            // Let followers know about the new leader right away.
+            self.signal.notify();
+        }
+        Ok(())
+    }
+
+    /// Append a change to the cluster metadata and wait for it to commit,
+    /// forwarding it to the metadata leader if this node is not leading.
+    fn submit(&self, record: &MetadataRecord) -> Result<()> {
+        let replica = self.replica(&metadata_partition())?;
+        match replica.append(&[record.to_record()]) {
+            Ok(appended) => {
+                self.signal.notify();
+                replica.await_commit(appended, self.cfg.replication_timeout())?;
+                self.apply()
+            }
+            Err(Error::NotLeader { leader, .. }) => self.forward(record, leader),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Send a metadata change to the supplied leader, falling back to every
+    /// seed in turn while no leader is known or it has since stepped down.
+    fn forward(&self, record: &MetadataRecord, leader: Option<u32>) -> Result<()> {
+        let mut targets: Vec<SocketAddr> = leader
+            .and_then(|id| self.peer(id))
+            .map(|peer| peer.addr())
+            .into_iter()
+            .collect();
+        targets.extend(
+            self.cluster
+                .seeds
+                .iter()
+                .filter(|seed| seed.id != self.id() && Some(seed.id) != leader)
+                .map(|seed| seed.addr),
+        );
+
+        let request = Request::MetadataWrite(MetadataWriteRequest {
+            record: record.to_record(),
+        });
+        for addr in targets {
+            let result = Client::connect_timeout(&addr, self.cfg.replication_timeout())
+                .and_then(|mut client| client.call(&request));
+            match result {
+                Ok(Response::MetadataWrite) => return Ok(()),
+                Ok(_) => continue,
+                Err(err) => {
+                    debug!(self.logger, "Failed to forward cluster metadata change."; "address" => addr.to_string(), "error" => err.to_string());
+                }
+            }
+        }
+        Err(Error::NotLeader {
+            partition: metadata_partition(),
+            leader,
+        })
+    }
+
+    /// Poll the committed metadata until the supplied condition holds, or the
+    /// replication timeout elapses.
+    fn await_applied<T>(&self, mut done: impl FnMut() -> Option<T>) -> Result<T> {
+        let deadline = Instant::now() + self.cfg.replication_timeout();
+        loop {
+            self.apply()?;
+            if let Some(value) = done() {
+                return Ok(value);
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::ReplicationTimeout {
+                    partition: metadata_partition(),
+                    offset: *self.applied(),
+                });
+            }
+            thread::sleep(self.cfg.heartbeat_interval());
+        }
+    }
+
+    /// Apply every newly committed metadata change, then bring this node's
+    /// peers, topics, and replicas in line with the resulting metadata.
+    fn apply(&self) -> Result<()> {
+        let mut applied = self.applied();
+        let replica = self.replica(&metadata_partition())?;
+        let mut records = Vec::new();
+        loop {
+            let batch = replica.read_committed(*applied, MAX_APPEND_BYTES)?;
+            match batch.last() {
+                Some(last) => *applied = last.offset + 1,
+                None => break,
+            }
+            records.extend(batch);
+        }
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        let mut deleted = Vec::new();
+        let metadata = {
+            let mut metadata = self.metadata_mut();
+            for record in &records {
+                match MetadataRecord::from_record(record) {
+                    Ok(MetadataRecord::DeleteTopic { name }) => {
+                        deleted.push(name.clone());
+                        metadata.apply(MetadataRecord::DeleteTopic { name });
+                    }
+                    Ok(change) => metadata.apply(change),
+                    Err(err) => {
+                        error!(self.logger, "Skipping unreadable cluster metadata."; "error" => err.to_string())
+                    }
+                }
+            }
+            metadata.clone()
+        };
+
+        {
+            let mut peers = self.peers_mut();
+            for (id, addr) in metadata.nodes() {
+                if *id == self.id() || peers.get(id).is_some_and(|peer| peer.addr() == *addr) {
+                    continue;
+                }
+                let peer = Peer::new(*id, *addr, self.cfg.election_timeout());
+                peers.insert(*id, Arc::new(peer));
+            }
+        }
+
+        // Only delete topics once the whole batch is applied, so replaying a
+        // delete followed by a re-create leaves the current topic in place.
+        for name in deleted {
+            if metadata.topic(&name).is_some() {
+                continue;
+            }
+            match self.topics.delete(&name) {
+                Ok(()) => info!(self.logger, "Deleted topic."; "topic" => &name),
+                Err(topic::Error::NotFound { .. }) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        for (name, state) in metadata.topics() {
+            if self.topics.get(name).is_ok() {
+                continue;
+            }
+            let mut config = TopicConfig::new();
+            for (key, value) in &state.config {
+                config.set(key, value)?;
+            }
+            self.topics
+                .create(name, state.partitions.len() as u32, config)?;
+            info!(self.logger, "Created topic."; "topic" => name, "partitions" => state.partitions.len());
+        }
+        self.sync()
+    }
+
+    /// Open replicas for any partition placed on this node, and drop replicas of
+    /// topics that no longer exist. The metadata topic is replicated by the
+    /// seeds, with every other node that joined the cluster as a learner.
+    fn sync(&self) -> Result<()> {
+        let metadata = self.metadata();
+        let seeds: Vec<u32> = self.cluster.seeds.iter().map(|seed| seed.id).collect();
+        let topics = self.topics.list();
+        let mut replicas = self.write();
+        replicas.retain(|partition, replica| {
+            topics
+                .iter()
+                .any(|topic| topic.name() == partition.topic && replica.serves(topic))
+        });
+
+        for topic in topics {
+            let placement: Vec<Vec<u32>> = if topic.name() == METADATA_TOPIC {
+                vec![seeds.clone()]
+            } else if topic::is_internal(topic.name()) {
+                continue;
+            } else {
+                match metadata.topic(topic.name()) {
+                    Some(state) => state
+                        .partitions
+                        .iter()
+                        .map(|partition| partition.replicas.clone())
+                        .collect(),
+                    None => continue,
+                }
+            };
+
+            for partition in topic.partitions() {
+                let id = TopicPartition::new(topic.name(), partition.id());
+                if replicas.contains_key(&id) {
+                    continue;
+                }
+                let voters = match placement.get(partition.id() as usize) {
+                    Some(voters) => voters.clone(),
+                    None => continue,
+                };
+                if topic.name() != METADATA_TOPIC && !voters.contains(&self.id()) {
+                    continue;
+                }
+                let replica = Replica::open(
+                    self.logger.clone(),
+                    topic.clone(),
+                    partition.id(),
+                    self.id(),
+                    voters,
+                    self.cfg.election_timeout(),
+                )?;
+                replicas.insert(id, Arc::new(replica));
+            }
+        }
+
+        if let Some(replica) = replicas.get(&metadata_partition()) {
+            let learners: Vec<u32> = metadata.nodes().keys().copied().collect();
+            replica.set_learners(&learners)?;
+        }
+        Ok(())
+    }
+
+    fn replica(&self, partition: &TopicPartition) -> Result<Arc<Replica>> {
+        if let Some(replica) = self.read().get(partition) {
+            return Ok(replica.clone());
+        }
+        // The topic may have been created since the last tick.
+        self.sync()?;
+        self.read()
+            .get(partition)
+            .cloned()
+            .ok_or_else(|| Error::NotReplica {
+                partition: partition.clone(),
+            })
+    }
+
+    fn peer(&self, id: u32) -> Option<Arc<Peer>> {
+        self.peers().get(&id).cloned()
+    }
+
+    fn list(&self) -> Vec<Arc<Replica>> {
+        self.read().values().cloned().collect()
+    }
+
+    fn applied(&self) -> MutexGuard<'_, u64> {
+        self.applied
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn metadata(&self) -> RwLockReadGuard<'_, ClusterMetadata> {
+        self.metadata
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn metadata_mut(&self) -> RwLockWriteGuard<'_, ClusterMetadata> {
+        self.metadata
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn peers(&self) -> RwLockReadGuard<'_, BTreeMap<u32, Arc<Peer>>> {
+        self.peers
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn peers_mut(&self) -> RwLockWriteGuard<'_, BTreeMap<u32, Arc<Peer>>> {
+        self.peers
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, HashMap<TopicPartition, Arc<Replica>>> {
+        self.replicas
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, HashMap<TopicPartition, Arc<Replica>>> {
+        self.replicas
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn metadata_partition() -> TopicPartition {
+    TopicPartition::new(METADATA_TOPIC, 0)
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::broker::Broker;
+    use crate::client::{self, Client, ClusterClient};
+    use crate::cluster::NodeAddr;
+    use crate::protocol::{ErrorCode, JoinGroupRequest, PartitionOffset, ScheduledRecord};
+    use crate::server::{self, Server};
+    use crate::storage::LogConfig;
+    use crate::topic::{IsolationLevel, Manager};
+    use crate::{group, producer, queue, record, schedule};
+
+    fn logger() -> slog::Logger {
+        slog::Logger::root(slog::Discard, o!())
+    }
+
+    fn cluster_config(node_id: u32, seeds: &[(u32, SocketAddr)]) -> cluster::Config {
+        cluster::Config {
+            node_id,
+            seeds: seeds
+                .iter()
+                .map(|(id, addr)| NodeAddr {
+                    id: *id,
+                    addr: *addr,
+                })
+                .collect(),
+            advertised_address: None,
+        }
+    }
+
+    fn config() -> Config {
+        Config {
+            election_timeout_ms: 200,
+            heartbeat_interval_ms: 20,
+            ..Config::default()
+        }
+    }
+
+    fn wait_for<T>(mut f: impl FnMut() -> Option<T>) -> T {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline {
+            if let Some(value) = f() {
+                return value;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        panic!("timed out waiting for the cluster");
+    }
+
+    /// Start a node serving the native protocol on the supplied address.
+    fn serve(
+        dir: &std::path::Path,
+        id: u32,
+        addr: SocketAddr,
+        seeds: &[(u32, SocketAddr)],
+    ) -> Arc<Node> {
+        let topics = Arc::new(Manager::open(dir, LogConfig::default()).unwrap());
+        let cfg = cluster_config(id, seeds);
+        let node = Arc::new(Node::new(logger(), cfg, addr, config(), topics.clone()).unwrap());
+        let broker = Broker::open(
+            logger(),
+            topics,
+            group::Config::default(),
+            producer::Config::default(),
+            queue::Config::default(),
+            schedule::Config::default(),
+        )
+        .unwrap()
+        .replicated(node.clone());
+        let cfg = server::Config {
+            listen_address: addr,
+            max_frame_bytes: 1024 * 1024,
+        };
+        let server = Server::bind(logger(), &cfg, Arc::new(broker)).unwrap();
+        thread::spawn(move || server.serve());
+        node.start();
+        node
+    }
+
+    #[test]
+    fn test_single_node() {
+        let dir = tempfile::tempdir().unwrap();
+        let topics = Arc::new(Manager::open(dir.path(), LogConfig::default()).unwrap());
+        let addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let seeds = [(1, addr)];
+        let mut cfg = config();
+        cfg.replication_factor = 1;
+        let node = Node::new(
+            logger(),
+            cluster_config(1, &seeds),
+            addr,
+            cfg.clone(),
+            topics.clone(),
+        )
+        .unwrap();
+
+        // Nothing can change until the node leads the metadata and has joined.
+        node.report(Instant::now());
+        assert!(node.cluster().nodes().is_empty());
+        assert!(matches!(
+            node.create_topic("events", 4, TopicConfig::new()),
+            Err(Error::Cluster(cluster::Error::NoNodes { .. }))
+        ));
+        node.tick(Instant::now() + Duration::from_secs(1));
+        node.report(Instant::now());
+        assert_eq!(Some(&addr), node.cluster().nodes().get(&1));
+
+        let topic = node.create_topic("events", 4, TopicConfig::new()).unwrap();
+        assert!(matches!(
+            node.create_topic("events", 1, TopicConfig::new()),
+            Err(Error::Topic(topic::Error::AlreadyExists { .. }))
+        ));
+        node.tick(Instant::now() + Duration::from_secs(1));
+        for partition in 0..4 {
+            let id = TopicPartition::new("events", partition);
+            let produced = node.produce(
+                &topic,
+                Partitioning::Explicit(partition),
+                vec![Record::new("a")],
+                None,
+                Compression::None,
+            );
+            assert_eq!(vec![(partition, 0)], produced.unwrap());
+            assert_eq!(Some(1), node.leader(&id));
+            assert_eq!(1, node.high_watermark(&id).unwrap());
+        }
+
+        node.report(Instant::now());
+        let cluster = node.cluster();
+        let state = cluster.partition("events", 3).unwrap();
+        assert_eq!(
+            (Some(1), vec![1], vec![1]),
+            (state.leader, state.replicas.clone(), state.isr.clone())
+        );
+
+        node.delete_topic("events").unwrap();
+        assert!(topics.get("events").is_err());
+        assert!(node.cluster().topic("events").is_none());
+        assert!(matches!(
+            node.delete_topic("events"),
+            Err(Error::Topic(topic::Error::NotFound { .. }))
+        ));
+
+        // Committed metadata is replayed on restart.
+        node.create_topic("orders", 2, TopicConfig::new()).unwrap();
+        node.tick(Instant::now());
+        drop((node, topics));
+        let topics = Arc::new(Manager::open(dir.path(), LogConfig::default()).unwrap());
+        let node = Node::new(
+            logger(),
+            cluster_config(1, &seeds),
+            addr,
+            cfg,
+            topics.clone(),
+        )
+        .unwrap();
+        assert_eq!(2, node.cluster().topic("orders").unwrap().partitions.len());
+        assert!(node.cluster().topic("events").is_none());
+        assert!(topics.get("orders").is_ok());
+    }
+
+    #[test]
+    fn test_cluster() {
+        let listeners: Vec<TcpListener> = (0..4)
+            .map(|_| TcpListener::bind("127.0.0.1:0").unwrap())
+            .collect();
+        let addrs: Vec<(u32, SocketAddr)> = listeners
+            .iter()
+            .enumerate()
+            .map(|(idx, listener)| (idx as u32 + 1, listener.local_addr().unwrap()))
+            .collect();
+        drop(listeners);
+        let seeds = &addrs[..3];
+
+        let dirs: Vec<_> = (0..4).map(|_| tempfile::tempdir().unwrap()).collect();
+        let mut nodes: Vec<Arc<Node>> = dirs
+            .iter()
+            .zip(seeds)
+            .map(|(dir, (id, addr))| serve(dir.path(), *id, *addr, seeds))
+            .collect();
+        wait_for(|| {
+            nodes
+                .iter()
+                .all(|node| node.cluster().nodes().len() == 3)
+                .then_some(())
+        });
+
+        let mut client = Client::connect(addrs[0].1).unwrap();
+        client.create_topic("events-dlq", 1, Vec::new()).unwrap();
+        let config = vec![
+            (topic::QUEUE_MAX_DELIVERIES.to_owned(), String::from("1")),
+            (
+                topic::QUEUE_DEAD_LETTER_TOPIC.to_owned(),
+                String::from("events-dlq"),
+            ),
+        ];
+        client.create_topic("events", 1, config).unwrap();
+        let err = client.create_topic("events", 1, Vec::new()).unwrap_err();
+        assert!(
+            matches!(err, client::Error::Response(err) if err.code == ErrorCode::TopicAlreadyExists)
+        );
+        for node in &nodes {
+            wait_for(|| node.topics.get("events").ok());
+        }
+
+        // A node outside the seeds learns the metadata as a learner.
+        let (id, addr) = addrs[3];
+        nodes.push(serve(dirs[3].path(), id, addr, seeds));
+        wait_for(|| nodes[3].topics.get("events").ok());
+        wait_for(|| {
+            nodes
+                .iter()
+                .all(|node| node.cluster().nodes().len() == 4)
+                .then_some(())
+        });
+
+        let mut cluster = ClusterClient::connect(addr, Duration::from_secs(10))
+            .unwrap()
+            .with_retries(50, Duration::from_millis(50));
+        let records = vec![Record::new("a"), Record::new("b"), Record::new("c")];
+        let produced = cluster
+            .produce("events", Partitioning::Explicit(0), records)
+            .unwrap();
+        assert_eq!(3, produced.len());
+        let leader = cluster.leader("events", 0).unwrap();
+        assert_eq!(4, cluster.metadata().nodes.len());
+        let fetched = cluster.fetch("events", 0, 0, 1024 * 1024).unwrap();
+        assert_eq!(3, fetched.high_watermark);
+        assert_eq!(Some(b"c".to_vec()), fetched.records[2].record.value);
+
+        let partition = TopicPartition::new("events", 0);
+        for (id, addr) in seeds {
+            let node = &nodes[*id as usize - 1];
+            wait_for(|| (node.high_watermark(&partition).unwrap() == 3).then_some(()));
+            if *id != leader {
+                let err = Client::connect(addr)
+                    .unwrap()
+                    .produce("events", Partitioning::Explicit(0), vec![Record::new("d")])
+                    .unwrap_err();
+                match err {
+                    client::Error::Response(err) => assert_eq!(ErrorCode::NotLeader, err.code),
+                    err => panic!("unexpected error: {}", err),
+                }
+            }
+        }
+
+        let metadata = client.metadata(Vec::new()).unwrap();
+        let described = &metadata.topics[0].partitions[0];
+        assert_eq!(3, described.replicas.len());
+        assert_eq!(Some(leader), described.leader);
+
+        // Producer ids carry the id of the node that assigned them, and a retried
+        // batch is recognised by the partition's leader.
+        let mut cluster = cluster.idempotent().unwrap();
+        let producer_id = cluster.producer_id().unwrap();
+        assert!(cluster
+            .metadata()
+            .nodes
+            .iter()
+            .any(|node| node.id as u64 == producer_id >> 32));
+        let producer = ProducerBatch {
+            producer_id,
+            epoch: 0,
+            sequence: 0,
+            transactional: false,
+        };
+        let produced = cluster
+            .produce("events", Partitioning::Explicit(0), vec![Record::new("d")])
+            .unwrap();
+        let leader_addr = seeds[leader as usize - 1].1;
+        let retried = Client::connect(leader_addr)
+            .unwrap()
+            .produce_idempotent("events", 0, vec![Record::new("d")], producer)
+            .unwrap();
+        assert_eq!(produced, retried);
+        assert_eq!(
+            4,
+            nodes[leader as usize - 1]
+                .topics
+                .get("events")
+                .unwrap()
+                .partitions()[0]
+                .next_offset()
+        );
+
+        // Transactions are coordinated by the node the producer was initialized
+        // against, which forwards markers to each partition's leader.
+        let coordinator = seeds.iter().find(|(id, _)| *id != leader).unwrap().1;
+        let mut txn = ClusterClient::connect(coordinator, Duration::from_secs(10))
+            .unwrap()
+            .with_retries(50, Duration::from_millis(50))
+            .transactional("txn", Duration::from_secs(60))
+            .unwrap();
+        let mut committed = ClusterClient::connect(addr, Duration::from_secs(10))
+            .unwrap()
+            .with_retries(50, Duration::from_millis(50))
+            .with_isolation(IsolationLevel::ReadCommitted);
+        assert!(matches!(
+            txn.produce("events", Partitioning::Explicit(0), vec![Record::new("e")]),
+            Err(client::Error::NoTransaction)
+        ));
+        txn.begin_transaction().unwrap();
+        let produced = txn
+            .produce("events", Partitioning::Explicit(0), vec![Record::new("e")])
+            .unwrap();
+        let fetched = committed.fetch("events", 0, 4, 1024 * 1024).unwrap();
+        assert!(fetched.records.is_empty());
+        assert_eq!(4, fetched.last_stable_offset);
+
+        txn.commit_transaction().unwrap();
+        let fetched = wait_for(|| {
+            committed
+                .fetch("events", 0, 4, 1024 * 1024)
+                .ok()
+                .filter(|fetched| !fetched.records.is_empty())
+        });
+        assert_eq!(produced[0].offset, fetched.records[0].offset);
+
+        // Queue consumers lease from, and acknowledge through, each partition's leader.
+        let mut worker = ClusterClient::connect(coordinator, Duration::from_secs(10))
+            .unwrap()
+            .with_retries(50, Duration::from_millis(50));
+        let leased = worker
+            .lease("jobs", "worker", "events", 100, Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(
+            Some(produced[0].offset),
+            leased.last().map(|msg| msg.record.offset)
+        );
+        assert!(leased.iter().all(|msg| msg.delivery_count == 1));
+        let offsets: Vec<u64> = leased.iter().map(|msg| msg.record.offset).collect();
+        worker.ack("jobs", "worker", "events", 0, &offsets).unwrap();
+        assert!(worker
+            .lease("jobs", "worker", "events", 100, Duration::from_secs(60))
+            .unwrap()
+            .is_empty());
+
+        // Messages delivered too many times move to the dead-letter topic, which
+        // may be led by another node, and are replayed from there.
+        let dead_letters = TopicPartition::new("events-dlq", 0);
+        wait_for(|| nodes.iter().find_map(|node| node.leader(&dead_letters)));
+        let poison = cluster
+            .produce(
+                "events",
+                Partitioning::Explicit(0),
+                vec![Record::new("poison")],
+            )
+            .unwrap();
+        let lease = |worker: &mut ClusterClient| {
+            worker
+                .lease("jobs", "worker", "events", 100, Duration::from_secs(60))
+                .unwrap()
+        };
+        let leased = lease(&mut worker);
+        assert_eq!(1, leased.len());
+        assert_eq!(poison[0].offset, leased[0].record.offset);
+        worker
+            .nack("jobs", "worker", "events", 0, &[poison[0].offset], "boom")
+            .unwrap();
+        assert!(lease(&mut worker).is_empty());
+        let dead = wait_for(|| {
+            cluster
+                .fetch("events-dlq", 0, 0, 1024 * 1024)
+                .ok()
+                .filter(|fetched| !fetched.records.is_empty())
+        });
+        assert_eq!(
+            Some(&b"boom"[..]),
+            dead.records[0].record.header(queue::ERROR_HEADER)
+        );
+        assert_eq!(1, worker.replay_dead_letters("events-dlq", 100).unwrap());
+        let replayed = lease(&mut worker);
+        assert_eq!(1, replayed.len());
+        assert_eq!(Record::new("poison"), replayed[0].record.record);
+        assert_eq!(1, replayed[0].delivery_count);
+
+        // Scheduled records are held by each partition's leader until due.
+        let before = record::current_timestamp();
+        let scheduled = cluster
+            .schedule(
+                "events",
+                Partitioning::RoundRobin,
+                vec![
+                    ScheduledRecord::after(60000, Record::new("a")),
+                    ScheduledRecord::after(60000, Record::new("b")),
+                ],
+            )
+            .unwrap();
+        assert_eq!(2, scheduled.len());
+        assert!(scheduled
+            .iter()
+            .all(|message| message.deliver_at_ms >= before + 60000));
+
+        // Committed offsets are kept by the group's coordinator, so they are the
+        // same whichever node they are committed through or fetched from.
+        let committed = |offset| {
+            vec![PartitionOffset {
+                topic: String::from("events"),
+                partition: 0,
+                offset,
+            }]
+        };
+        for (idx, (_, addr)) in addrs.iter().enumerate() {
+            let mut client = Client::connect(addr).unwrap();
+            client
+                .commit_offsets("group", committed(idx as u64))
+                .unwrap();
+            for (_, addr) in &addrs {
+                let fetched = Client::connect(addr)
+                    .unwrap()
+                    .fetch_offsets("group", Vec::new())
+                    .unwrap();
+                assert_eq!(committed(idx as u64), fetched);
+            }
+        }
+
+        // Group membership is only tracked by the group's coordinator, which
+        // cluster clients look up whichever node they were bootstrapped from.
+        let coordinator = Client::connect(addr)
+            .unwrap()
+            .find_coordinator("workers")
+            .unwrap()
+            .unwrap();
+        let (_, other) = seeds.iter().find(|(id, _)| *id != coordinator.id).unwrap();
+        let join = JoinGroupRequest {
+            group: String::from("workers"),
+            member_id: String::new(),
+            session_timeout_ms: 10000,
+            topics: vec![String::from("events")],
+            strategies: vec![String::from("sticky")],
+        };
+        let err = Client::connect(other)
+            .unwrap()
+            .join_group(join.clone())
+            .unwrap_err();
+        match err {
+            client::Error::Response(err) => assert_eq!(ErrorCode::NotCoordinator, err.code),
+            err => panic!("unexpected error: {}", err),
+        }
+        let mut first = ClusterClient::connect(other, Duration::from_secs(10)).unwrap();
+        let mut second = ClusterClient::connect(addr, Duration::from_secs(10)).unwrap();
+        let a = first.join_group(join.clone()).unwrap();
+        assert_eq!(1, a.assignment.len());
+        let b = second.join_group(join).unwrap();
+        let a = first.group_heartbeat("workers", &a.member_id).unwrap();
+        assert_eq!(b.generation, a.generation);
+        second.leave_group("workers", &b.member_id).unwrap();
+        first.commit_offsets("workers", committed(9)).unwrap();
+        assert_eq!(
+            committed(9),
+            second.fetch_offsets("workers", Vec::new()).unwrap()
+        );
+    }
+}
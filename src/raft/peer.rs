@@ -0,0 +1,86 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    net::SocketAddr,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::client::{self, Client};
+use crate::protocol::{Request, Response};
+
+struct Connection {
+    client: Option<Client>,
+    retry_at: Option<Instant>,
+}
+
+/// A lazily established connection to another node of the cluster.
+///
+/// Failed connections are dropped and re-established on the next call, though
+/// no sooner than one timeout after the last failed attempt, so an unreachable
+/// peer does not hold up every partition it replicates.
+pub(super) struct Peer {
+    id: u32,
+    addr: SocketAddr,
+    timeout: Duration,
+    connection: Mutex<Connection>,
+}
+
+impl Peer {
+    pub(super) fn new(id: u32, addr: SocketAddr, timeout: Duration) -> Peer {
+        Peer {
+            id,
+            addr,
+            timeout,
+            connection: Mutex::new(Connection {
+                client: None,
+                retry_at: None,
+            }),
+        }
+    }
+
+    /// Returns the node id of this peer.
+    pub(super) fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Returns the address of this peer.
+    pub(super) fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Send a request to this peer and wait for its response.
+    pub(super) fn call(&self, request: &Request) -> client::Result<Response> {
+        let mut connection = self.connection();
+        let mut client = match connection.client.take() {
+            Some(client) => client,
+            None => {
+                if connection.retry_at.is_some_and(|at| Instant::now() < at) {
+                    return Err(client::Error::Closed);
+                }
+                match Client::connect_timeout(&self.addr, self.timeout) {
+                    Ok(client) => client,
+                    Err(err) => {
+                        connection.retry_at = Some(Instant::now() + self.timeout);
+                        return Err(err);
+                    }
+                }
+            }
+        };
+
+        // Keep the connection unless it may be left with a half read response.
+        let result = client.call(request);
+        if matches!(result, Ok(_) | Err(client::Error::Response(_))) {
+            connection.client = Some(client);
+            connection.retry_at = None;
+        }
+        result
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.connection
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
@@ -0,0 +1,933 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::{hash_map::RandomState, BTreeMap},
+    hash::{BuildHasher, Hasher},
+    path::PathBuf,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::protocol::{
+    AppendRequest, AppendResponse, ReplicatedEntry, Request, SnapshotRequest, VoteRequest,
+    VoteResponse,
+};
+use crate::record::{Compression, ControlMarker, OffsetRecord, ProducerBatch, Record};
+use crate::topic::{Partition, Topic, TopicPartition};
+
+use super::error::{Error, Result};
+use super::metrics::metrics;
+use super::state::HardState;
+
+/// The role a replica plays in its partition's current term.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// Where records appended by a leader landed, and the term they were written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Appended {
+    pub(super) term: u64,
+    pub(super) base_offset: u64,
+    pub(super) next_offset: u64,
+}
+
+/// What a leader knows about how much of its log a follower has.
+#[derive(Debug, Clone, Copy)]
+struct Progress {
+    next_offset: u64,
+    match_offset: u64,
+    caught_up_at: Instant,
+}
+
+impl Progress {
+    fn new(next_offset: u64, now: Instant) -> Progress {
+        Progress {
+            next_offset,
+            match_offset: 0,
+            caught_up_at: now,
+        }
+    }
+}
+
+struct State {
+    hard: HardState,
+    role: Role,
+    leader: Option<u32>,
+    commit_offset: u64,
+    election_deadline: Instant,
+    learners: Vec<u32>,
+    progress: BTreeMap<u32, Progress>,
+}
+
+/// This node's replica of a single partition, running the raft protocol with
+/// the partition's other replicas.
+///
+/// Only the voting replicas the partition was placed on take part in elections
+/// and count towards its quorum. Learners are replicated to like any follower,
+/// but never vote or stand for election themselves.
+///
+/// The replica only tracks state and answers requests, the [super::Node]
+/// owning it is responsible for driving elections and shipping requests to
+/// peers. Whenever both are held the replica's state lock is taken before the
+/// partition's log lock.
+pub(super) struct Replica {
+    logger: slog::Logger,
+    partition: TopicPartition,
+    topic: Arc<Topic>,
+    dir: PathBuf,
+    node_id: u32,
+    replicas: Vec<u32>,
+    election_timeout: Duration,
+    state: Mutex<State>,
+    committed: Condvar,
+}
+
+impl Replica {
+    /// Open this node's replica of a partition, restoring its persisted state.
+    pub(super) fn open(
+        logger: slog::Logger,
+        topic: Arc<Topic>,
+        partition: u32,
+        node_id: u32,
+        replicas: Vec<u32>,
+        election_timeout: Duration,
+    ) -> Result<Replica> {
+        let log = topic.partition(partition)?;
+        let dir = log.dir();
+        let hard = HardState::load(&dir)?;
+        let commit_offset = hard
+            .commit_offset
+            .clamp(log.start_offset(), log.next_offset());
+
+        // A lone replica has no one to wait for, so let it elect itself right away.
+        let now = Instant::now();
+        let election_deadline = match replicas.len() {
+            1 => now,
+            _ => election_deadline(now, election_timeout),
+        };
+        Ok(Replica {
+            logger,
+            partition: TopicPartition::new(topic.name(), partition),
+            topic,
+            dir,
+            node_id,
+            replicas,
+            election_timeout,
+            state: Mutex::new(State {
+                hard,
+                role: Role::Follower,
+                leader: None,
+                commit_offset,
+                election_deadline,
+                learners: Vec::new(),
+                progress: BTreeMap::new(),
+            }),
+            committed: Condvar::new(),
+        })
+    }
+
+    /// Returns the partition this is a replica of.
+    pub(super) fn partition(&self) -> &TopicPartition {
+        &self.partition
+    }
+
+    /// Returns whether or not this replica belongs to the supplied topic, rather
+    /// than a deleted topic of the same name.
+    pub(super) fn serves(&self, topic: &Arc<Topic>) -> bool {
+        Arc::ptr_eq(&self.topic, topic)
+    }
+
+    /// Returns the node ids of the partition's other voting replicas.
+    pub(super) fn peers(&self) -> impl Iterator<Item = u32> + '_ {
+        self.replicas
+            .iter()
+            .copied()
+            .filter(move |id| *id != self.node_id)
+    }
+
+    /// Replace the non-voting replicas the partition is replicated to.
+    pub(super) fn set_learners(&self, learners: &[u32]) -> Result<()> {
+        let mut state = self.state();
+        let learners: Vec<u32> = learners
+            .iter()
+            .copied()
+            .filter(|id| *id != self.node_id && !self.replicas.contains(id))
+            .collect();
+        if state.learners == learners {
+            return Ok(());
+        }
+        if state.role == Role::Leader {
+            let next_offset = self.log()?.next_offset();
+            let now = Instant::now();
+            state
+                .progress
+                .retain(|id, _| self.replicas.contains(id) || learners.contains(id));
+            for id in &learners {
+                state
+                    .progress
+                    .entry(*id)
+                    .or_insert_with(|| Progress::new(next_offset, now));
+            }
+        }
+        state.learners = learners;
+        Ok(())
+    }
+
+    /// Returns the node id of the partition's current leader, if known.
+    pub(super) fn leader(&self) -> Option<u32> {
+        self.state().leader
+    }
+
+    /// Returns the offset below which every record is committed, and so visible
+    /// to consumers.
+    pub(super) fn commit_offset(&self) -> u64 {
+        self.state().commit_offset
+    }
+
+    /// Returns the current term and in-sync replicas of a leader, being the
+    /// leader itself and every voting follower that has caught up with it
+    /// within the supplied lag, or nothing if this replica is not leading.
+    pub(super) fn leadership(&self, now: Instant, lag: Duration) -> Option<(u64, Vec<u32>)> {
+        let state = self.state();
+        if state.role != Role::Leader {
+            return None;
+        }
+        let mut isr: Vec<u32> = state
+            .progress
+            .iter()
+            .filter(|(id, progress)| {
+                self.replicas.contains(id) && progress.caught_up_at + lag >= now
+            })
+            .map(|(id, _)| *id)
+            .chain(std::iter::once(self.node_id))
+            .collect();
+        isr.sort_unstable();
+        Some((state.hard.term, isr))
+    }
+
+    /// Read committed records from the supplied offset, or the start of the log
+    /// if it has since been removed.
+    pub(super) fn read_committed(
+        &self,
+        offset: u64,
+        max_bytes: usize,
+    ) -> Result<Vec<OffsetRecord>> {
+        let commit_offset = self.commit_offset();
+        let log = self.log()?;
+        let offset = offset.max(log.start_offset());
+        if offset >= commit_offset {
+            return Ok(Vec::new());
+        }
+        let mut records = log.read(offset, max_bytes)?;
+        records.retain(|record| record.offset < commit_offset);
+        Ok(records)
+    }
+
+    /// Start an election if this replica has not heard from a leader in time,
+    /// returning the vote request to send to its peers. A replica that is its
+    /// own quorum becomes leader immediately instead, and learners never stand
+    /// for election.
+    pub(super) fn start_election(&self, now: Instant) -> Result<Option<VoteRequest>> {
+        let mut state = self.state();
+        if state.role == Role::Leader
+            || now < state.election_deadline
+            || !self.replicas.contains(&self.node_id)
+        {
+            return Ok(None);
+        }
+
+        let next_offset = self.log()?.next_offset();
+        state.hard.term += 1;
+        state.hard.voted_for = Some(self.node_id);
+        state.role = Role::Candidate;
+        state.leader = None;
+        state.election_deadline = election_deadline(now, self.election_timeout);
+        self.persist(&mut state)?;
+        debug!(self.logger, "Starting partition leader election."; "topic" => &self.partition.topic, "partition" => self.partition.partition, "term" => state.hard.term);
+
+        if self.quorum() == 1 {
+            self.become_leader(&mut state)?;
+            return Ok(None);
+        }
+        Ok(Some(VoteRequest {
+            partition: self.partition.clone(),
+            term: state.hard.term,
+            candidate: self.node_id,
+            last_offset: next_offset,
+            last_term: state.hard.last_term(next_offset),
+        }))
+    }
+
+    /// Conclude an election, becoming leader if the candidacy for the supplied
+    /// term is still live and won the supplied number of votes.
+    pub(super) fn finish_election(&self, term: u64, votes: usize) -> Result<bool> {
+        let mut state = self.state();
+        if state.role != Role::Candidate || state.hard.term != term || votes < self.quorum() {
+            return Ok(false);
+        }
+        self.become_leader(&mut state)?;
+        Ok(true)
+    }
+
+    /// Step down to follower if a peer reports a newer term, returning whether
+    /// or not it did.
+    pub(super) fn observe_term(&self, term: u64) -> Result<bool> {
+        let mut state = self.state();
+        if term <= state.hard.term {
+            return Ok(false);
+        }
+        self.follow(&mut state, term, None)?;
+        Ok(true)
+    }
+
+    /// Answer a candidate's request for this replica's vote.
+    pub(super) fn handle_vote(&self, req: &VoteRequest, now: Instant) -> Result<VoteResponse> {
+        let mut state = self.state();
+        if req.term > state.hard.term {
+            self.follow(&mut state, req.term, None)?;
+        }
+
+        let next_offset = self.log()?.next_offset();
+        let last_term = state.hard.last_term(next_offset);
+        let up_to_date = req.last_term > last_term
+            || (req.last_term == last_term && req.last_offset >= next_offset);
+        let granted = req.term == state.hard.term
+            && state.hard.voted_for.unwrap_or(req.candidate) == req.candidate
+            && up_to_date;
+        if granted {
+            if state.hard.voted_for.is_none() {
+                state.hard.voted_for = Some(req.candidate);
+                self.persist(&mut state)?;
+            }
+            state.election_deadline = election_deadline(now, self.election_timeout);
+        }
+        Ok(VoteResponse {
+            term: state.hard.term,
+            granted,
+        })
+    }
+
+    /// Append the leader's entries to this replica's log, discarding any of its
+    /// own entries that conflict with them.
+    pub(super) fn handle_append(&self, req: AppendRequest, now: Instant) -> Result<AppendResponse> {
+        let mut state = self.state();
+        let log = self.log()?;
+        if req.term < state.hard.term {
+            return Ok(reject(state.hard.term, log.next_offset(), 0));
+        }
+        self.follow(&mut state, req.term, Some(req.leader))?;
+        state.election_deadline = election_deadline(now, self.election_timeout);
+
+        // The entries only apply if this log matches the leader's up to them.
+        let next_offset = log.next_offset();
+        if next_offset < req.prev_offset {
+            return Ok(reject(state.hard.term, next_offset, 0));
+        }
+        if req.prev_offset > log.start_offset() {
+            let term = state.hard.term_at(req.prev_offset - 1);
+            if term != req.prev_term {
+                return Ok(reject(state.hard.term, req.prev_offset - 1, term));
+            }
+        }
+
+        let mut matched = req.prev_offset;
+        for ReplicatedEntry { term, entry } in req.entries {
+            let entry_next = entry.next_offset();
+            if entry.base_offset < log.start_offset()
+                || (entry_next <= log.next_offset() && state.hard.term_at(entry_next - 1) == term)
+            {
+                matched = entry_next;
+                continue;
+            }
+            if entry.base_offset < log.next_offset() {
+                warn!(self.logger, "Discarding records that conflict with the partition leader."; "topic" => &self.partition.topic, "partition" => self.partition.partition, "offset" => entry.base_offset);
+                log.truncate_to(entry.base_offset)?;
+                state.hard.truncate(entry.base_offset);
+                self.persist(&mut state)?;
+            }
+            if state.hard.begin_epoch(term, entry.base_offset) {
+                self.persist(&mut state)?;
+            }
+            log.append_entry(entry)?;
+            matched = entry_next;
+        }
+
+        let commit_offset = req.leader_commit.min(matched);
+        if commit_offset > state.commit_offset {
+            state.commit_offset = commit_offset;
+            self.committed.notify_all();
+        }
+        Ok(AppendResponse {
+            term: state.hard.term,
+            success: true,
+            next_offset: matched,
+            conflict_term: 0,
+        })
+    }
+
+    /// Discard this replica's log and restart it at the leader's first retained
+    /// offset.
+    pub(super) fn handle_snapshot(
+        &self,
+        req: &SnapshotRequest,
+        now: Instant,
+    ) -> Result<AppendResponse> {
+        let mut state = self.state();
+        let log = self.log()?;
+        if req.term < state.hard.term {
+            return Ok(reject(state.hard.term, log.next_offset(), 0));
+        }
+        self.follow(&mut state, req.term, Some(req.leader))?;
+        state.election_deadline = election_deadline(now, self.election_timeout);
+
+        info!(self.logger, "Restarting partition replica from the leader's log start."; "topic" => &self.partition.topic, "partition" => self.partition.partition, "offset" => req.start_offset);
+        log.reset(req.start_offset)?;
+        state.hard.reset(req.start_term, req.start_offset);
+        state.commit_offset = req.start_offset;
+        self.persist(&mut state)?;
+        Ok(AppendResponse {
+            term: state.hard.term,
+            success: true,
+            next_offset: req.start_offset,
+            conflict_term: 0,
+        })
+    }
+
+    /// Build the next request a leader should send to the supplied follower, or
+    /// nothing if this replica is not leading the partition.
+    pub(super) fn prepare(&self, follower: u32, max_bytes: usize) -> Result<Option<Request>> {
+        let state = self.state();
+        let progress = match (state.role, state.progress.get(&follower)) {
+            (Role::Leader, Some(progress)) => *progress,
+            _ => return Ok(None),
+        };
+
+        let log = self.log()?;
+        let start_offset = log.start_offset();
+        if progress.next_offset < start_offset {
+            return Ok(Some(Request::RaftSnapshot(SnapshotRequest {
+                partition: self.partition.clone(),
+                term: state.hard.term,
+                leader: self.node_id,
+                start_offset,
+                start_term: state.hard.term_at(start_offset),
+            })));
+        }
+
+        let entries = match progress.next_offset < log.next_offset() {
+            true => log.read_entries(progress.next_offset, max_bytes)?,
+            false => Vec::new(),
+        };
+        let prev_offset = entries
+            .first()
+            .map(|entry| entry.base_offset.min(progress.next_offset))
+            .unwrap_or(progress.next_offset);
+        Ok(Some(Request::RaftAppend(AppendRequest {
+            partition: self.partition.clone(),
+            term: state.hard.term,
+            leader: self.node_id,
+            prev_offset,
+            prev_term: state.hard.last_term(prev_offset),
+            leader_commit: state.commit_offset,
+            entries: entries
+                .into_iter()
+                .map(|entry| ReplicatedEntry {
+                    term: state.hard.term_at(entry.base_offset),
+                    entry,
+                })
+                .collect(),
+        })))
+    }
+
+    /// Record a follower's answer to a request built by [Replica::prepare] in
+    /// the supplied term, returning whether or not the follower still has
+    /// records to catch up on.
+    pub(super) fn handle_progress(
+        &self,
+        follower: u32,
+        term: u64,
+        resp: &AppendResponse,
+        now: Instant,
+    ) -> Result<bool> {
+        let mut state = self.state();
+        if resp.term > state.hard.term {
+            self.follow(&mut state, resp.term, None)?;
+            return Ok(false);
+        }
+        if state.role != Role::Leader || state.hard.term != term {
+            return Ok(false);
+        }
+
+        let next_offset = self.log()?.next_offset();
+        let end_offset = match resp.conflict_term {
+            0 => next_offset,
+            conflict_term => state.hard.end_offset_for(conflict_term, next_offset),
+        };
+        let progress = match state.progress.get_mut(&follower) {
+            Some(progress) => progress,
+            None => return Ok(false),
+        };
+        if resp.success {
+            progress.match_offset = progress.match_offset.max(resp.next_offset);
+            progress.next_offset = resp.next_offset;
+            if progress.match_offset >= next_offset {
+                progress.caught_up_at = now;
+            }
+        } else {
+            progress.next_offset = resp.next_offset.min(end_offset);
+        }
+        let behind = progress.next_offset < next_offset;
+        metrics().observe_lag(
+            &self.partition,
+            follower,
+            next_offset.saturating_sub(progress.match_offset),
+        );
+
+        self.advance_commit(&mut state, next_offset);
+        Ok(behind)
+    }
+
+    /// Append records as the partition's leader.
+    pub(super) fn append(&self, records: &[Record]) -> Result<Appended> {
+        self.append_batch(records, None, Compression::None)
+    }
+
+    /// Append records written by the supplied idempotent producer, if any, as
+    /// the partition's leader, compressed with the supplied codec unless the
+    /// topic forces its own. A retry of the producer's last batch returns
+    /// where the batch was originally written, which may not be committed yet.
+    pub(super) fn append_batch(
+        &self,
+        records: &[Record],
+        producer: Option<ProducerBatch>,
+        compression: Compression,
+    ) -> Result<Appended> {
+        let mut state = self.state();
+        if state.role != Role::Leader {
+            return Err(Error::NotLeader {
+                partition: self.partition.clone(),
+                leader: state.leader,
+            });
+        }
+
+        let log = self.log()?;
+        let base_offset = log.append_compressed(records, producer, compression)?;
+        let next_offset = base_offset + records.len() as u64;
+        self.advance_commit(&mut state, log.next_offset());
+        Ok(Appended {
+            term: state.hard.term,
+            base_offset,
+            next_offset,
+        })
+    }
+
+    /// Append a marker completing the supplied producer's transaction as the
+    /// partition's leader.
+    pub(super) fn append_marker(
+        &self,
+        producer_id: u64,
+        epoch: u16,
+        marker: ControlMarker,
+    ) -> Result<Appended> {
+        let mut state = self.state();
+        if state.role != Role::Leader {
+            return Err(Error::NotLeader {
+                partition: self.partition.clone(),
+                leader: state.leader,
+            });
+        }
+
+        let log = self.log()?;
+        let base_offset = log.append_marker(producer_id, epoch, marker)?;
+        self.advance_commit(&mut state, log.next_offset());
+        Ok(Appended {
+            term: state.hard.term,
+            base_offset,
+            next_offset: base_offset + 1,
+        })
+    }
+
+    /// Wait for appended records to be committed by a quorum of replicas.
+    pub(super) fn await_commit(&self, appended: Appended, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state();
+        while state.commit_offset < appended.next_offset {
+            if state.role != Role::Leader || state.hard.term != appended.term {
+                return Err(Error::NotLeader {
+                    partition: self.partition.clone(),
+                    leader: state.leader,
+                });
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::ReplicationTimeout {
+                    partition: self.partition.clone(),
+                    offset: appended.base_offset,
+                });
+            }
+            state = self
+                .committed
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+        Ok(())
+    }
+
+    /// Persist the commit offset if it moved since it was last persisted.
+    pub(super) fn checkpoint(&self) -> Result<()> {
+        let mut state = self.state();
+        if state.hard.commit_offset == state.commit_offset {
+            return Ok(());
+        }
+        self.persist(&mut state)
+    }
+
+    fn quorum(&self) -> usize {
+        self.replicas.len() / 2 + 1
+    }
+
+    fn become_leader(&self, state: &mut State) -> Result<()> {
+        let next_offset = self.log()?.next_offset();
+        state.role = Role::Leader;
+        state.leader = Some(self.node_id);
+        state.hard.begin_epoch(state.hard.term, next_offset);
+        self.persist(state)?;
+        let now = Instant::now();
+        state.progress = self
+            .peers()
+            .chain(state.learners.iter().copied())
+            .map(|id| (id, Progress::new(next_offset, now)))
+            .collect();
+        info!(self.logger, "Elected partition leader."; "topic" => &self.partition.topic, "partition" => self.partition.partition, "term" => state.hard.term);
+        self.advance_commit(state, next_offset);
+        Ok(())
+    }
+
+    /// Become a follower, adopting the supplied term if it is newer and the
+    /// supplied leader if known.
+    fn follow(&self, state: &mut State, term: u64, leader: Option<u32>) -> Result<()> {
+        if term > state.hard.term {
+            state.hard.term = term;
+            state.hard.voted_for = None;
+            state.leader = None;
+            self.persist(state)?;
+        }
+        if state.role == Role::Leader {
+            info!(self.logger, "Stepped down as partition leader."; "topic" => &self.partition.topic, "partition" => self.partition.partition, "term" => state.hard.term);
+            for id in state.progress.keys() {
+                metrics().remove_lag(&self.partition, *id);
+            }
+            state.progress.clear();
+            self.committed.notify_all();
+        }
+        state.role = Role::Follower;
+        if leader.is_some() && state.leader != leader {
+            state.leader = leader;
+            info!(self.logger, "Following partition leader."; "topic" => &self.partition.topic, "partition" => self.partition.partition, "term" => state.hard.term, "leader" => leader);
+        }
+        Ok(())
+    }
+
+    /// Move the commit offset up to the highest offset a quorum of voting
+    /// replicas has reached, which raft only allows once a record from the
+    /// current term is among them.
+    fn advance_commit(&self, state: &mut State, next_offset: u64) {
+        let mut offsets: Vec<u64> = state
+            .progress
+            .iter()
+            .filter(|(id, _)| self.replicas.contains(id))
+            .map(|(_, progress)| progress.match_offset)
+            .chain(std::iter::once(next_offset))
+            .collect();
+        offsets.sort_unstable_by(|a, b| b.cmp(a));
+        let commit_offset = offsets[self.quorum() - 1];
+        if commit_offset > state.commit_offset
+            && state.hard.last_term(commit_offset) == state.hard.term
+        {
+            state.commit_offset = commit_offset;
+            self.committed.notify_all();
+        }
+    }
+
+    fn persist(&self, state: &mut State) -> Result<()> {
+        state.hard.commit_offset = state.commit_offset;
+        state.hard.store(&self.dir)
+    }
+
+    fn log(&self) -> Result<&Partition> {
+        Ok(self.topic.partition(self.partition.partition)?)
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn reject(term: u64, next_offset: u64, conflict_term: u64) -> AppendResponse {
+    AppendResponse {
+        term,
+        success: false,
+        next_offset,
+        conflict_term,
+    }
+}
+
+/// Returns when a follower that last heard from its leader at `now` should
+/// start an election, randomized over `[timeout, 2 * timeout)` so replicas
+/// rarely time out together.
+fn election_deadline(now: Instant, timeout: Duration) -> Instant {
+    let millis = timeout.as_millis().max(1) as u64;
+    let jitter = RandomState::new().build_hasher().finish() % millis;
+    now + timeout + Duration::from_millis(jitter)
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::storage::LogConfig;
+    use crate::topic::{self, Manager, TopicConfig};
+
+    fn replica(dir: &Path, node_id: u32) -> Replica {
+        voter(dir, node_id, vec![1, 2])
+    }
+
+    fn voter(dir: &Path, node_id: u32, replicas: Vec<u32>) -> Replica {
+        let topics = Manager::open(dir, LogConfig::default()).unwrap();
+        let topic = match topics.get("events") {
+            Ok(topic) => topic,
+            Err(_) => topics.create("events", 1, TopicConfig::new()).unwrap(),
+        };
+        let logger = slog::Logger::root(slog::Discard, o!());
+        Replica::open(logger, topic, 0, node_id, replicas, Duration::from_secs(1)).unwrap()
+    }
+
+    fn later() -> Instant {
+        Instant::now() + Duration::from_secs(10)
+    }
+
+    /// Elect the candidate, counting the supplied number of votes rather than
+    /// asking the voter so tests can force elections the voter would refuse.
+    fn elect(candidate: &Replica, voter: &Replica, votes: Option<usize>) {
+        let deadline = candidate.state().election_deadline;
+        let req = candidate.start_election(deadline).unwrap().unwrap();
+        let resp = voter.handle_vote(&req, Instant::now()).unwrap();
+        let votes = votes.unwrap_or(1 + resp.granted as usize);
+        assert!(candidate.finish_election(req.term, votes).unwrap());
+    }
+
+    /// Ship requests from the leader to the follower until it has caught up.
+    fn sync(leader: &Replica, follower: &Replica) {
+        let id = follower.node_id;
+        for _ in 0..10 {
+            let (term, resp) = match leader.prepare(id, 1024).unwrap().unwrap() {
+                Request::RaftAppend(req) => (req.term, follower.handle_append(req, Instant::now())),
+                Request::RaftSnapshot(req) => {
+                    (req.term, follower.handle_snapshot(&req, Instant::now()))
+                }
+                _ => unreachable!(),
+            };
+            let behind = leader
+                .handle_progress(id, term, &resp.unwrap(), Instant::now())
+                .unwrap();
+            if !behind && follower.commit_offset() == leader.commit_offset() {
+                return;
+            }
+        }
+        panic!("follower did not catch up");
+    }
+
+    fn values(replica: &Replica, offset: u64) -> Vec<Vec<u8>> {
+        replica
+            .log()
+            .unwrap()
+            .read(offset, 1024 * 1024)
+            .unwrap()
+            .into_iter()
+            .map(|record| record.record.value.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_replicate() {
+        let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let (a, b) = (replica(dir_a.path(), 1), replica(dir_b.path(), 2));
+        assert!(a.start_election(Instant::now()).unwrap().is_none());
+
+        elect(&a, &b, None);
+        assert_eq!(Some(1), a.leader());
+        assert_eq!(None, b.leader());
+
+        let records = vec![Record::new("a"), Record::new("b"), Record::new("c")];
+        let appended = a.append(&records).unwrap();
+        assert_eq!((0, 3), (appended.base_offset, appended.next_offset));
+        assert_eq!(0, a.commit_offset());
+        assert!(matches!(
+            a.await_commit(appended, Duration::from_millis(10)),
+            Err(Error::ReplicationTimeout { offset: 0, .. })
+        ));
+
+        sync(&a, &b);
+        assert_eq!(3, a.commit_offset());
+        assert_eq!(3, b.commit_offset());
+        assert_eq!(Some(1), b.leader());
+        assert!(a.await_commit(appended, Duration::from_millis(10)).is_ok());
+        assert_eq!(values(&a, 0), values(&b, 0));
+        assert!(matches!(
+            b.append(&records),
+            Err(Error::NotLeader {
+                leader: Some(1),
+                ..
+            })
+        ));
+
+        b.checkpoint().unwrap();
+        drop(b);
+        let b = replica(dir_b.path(), 2);
+        assert_eq!(3, b.commit_offset());
+        let req = a.start_election(later());
+        assert!(req.unwrap().is_none());
+        let stale = VoteRequest {
+            partition: b.partition().clone(),
+            term: 1,
+            candidate: 3,
+            last_offset: 10,
+            last_term: 1,
+        };
+        assert!(!b.handle_vote(&stale, Instant::now()).unwrap().granted);
+    }
+
+    #[test]
+    fn test_conflict() {
+        let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let (a, b) = (replica(dir_a.path(), 2), replica(dir_b.path(), 1));
+
+        // Node 1 leads term 1 and writes records that never reach node 2.
+        elect(&b, &a, None);
+        b.append(&[Record::new("a"), Record::new("b")]).unwrap();
+        sync(&b, &a);
+        b.append(&[Record::new("x"), Record::new("y"), Record::new("z")])
+            .unwrap();
+
+        // Node 2 takes over twice, the second time believing node 1 has its log.
+        elect(&a, &b, Some(2));
+        a.append(&[Record::new("c"), Record::new("d")]).unwrap();
+        let term = a.state().hard.term;
+        assert!(a.observe_term(term + 1).unwrap());
+        elect(&a, &b, Some(2));
+        a.append(&[Record::new("e")]).unwrap();
+
+        sync(&a, &b);
+        assert_eq!(5, b.log().unwrap().next_offset());
+        assert_eq!(5, b.commit_offset());
+        assert_eq!(
+            vec![b"a", b"b", b"c", b"d", b"e"]
+                .into_iter()
+                .map(|value| value.to_vec())
+                .collect::<Vec<_>>(),
+            values(&b, 0)
+        );
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let (a, b) = (replica(dir_a.path(), 1), replica(dir_b.path(), 2));
+        elect(&a, &b, None);
+        a.append(&[Record::new("a"), Record::new("b"), Record::new("c")])
+            .unwrap();
+
+        // Simulate retention removing everything the follower has yet to copy.
+        a.log().unwrap().reset(3).unwrap();
+        a.append(&[Record::new("d"), Record::new("e")]).unwrap();
+
+        sync(&a, &b);
+        assert_eq!(3, b.log().unwrap().start_offset());
+        assert_eq!(5, b.commit_offset());
+        assert_eq!(vec![b"d".to_vec(), b"e".to_vec()], values(&b, 3));
+    }
+
+    #[test]
+    fn test_producer_failover() {
+        let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let (a, b) = (replica(dir_a.path(), 1), replica(dir_b.path(), 2));
+        elect(&a, &b, None);
+
+        let producer = ProducerBatch {
+            producer_id: 7,
+            epoch: 0,
+            sequence: 0,
+            transactional: false,
+        };
+        let records = vec![Record::new("a"), Record::new("b")];
+        let appended = a
+            .append_batch(&records, Some(producer), Compression::None)
+            .unwrap();
+        let retried = a
+            .append_batch(&records, Some(producer), Compression::None)
+            .unwrap();
+        assert_eq!(appended, retried);
+        sync(&a, &b);
+
+        // The new leader recognises the retry from the replicated batch.
+        let term = a.state().hard.term;
+        assert!(b.observe_term(term + 1).unwrap());
+        elect(&b, &a, Some(2));
+        let retried = b
+            .append_batch(&records, Some(producer), Compression::None)
+            .unwrap();
+        assert_eq!((0, 2), (retried.base_offset, retried.next_offset));
+        assert_eq!(2, b.log().unwrap().next_offset());
+        assert!(matches!(
+            b.append_batch(
+                &records[..1],
+                Some(ProducerBatch {
+                    sequence: 3,
+                    transactional: false,
+                    ..producer
+                }),
+                Compression::None,
+            ),
+            Err(Error::Topic(topic::Error::OutOfOrderSequence {
+                expected: 2,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_learner() {
+        let dirs: Vec<_> = (0..3).map(|_| tempfile::tempdir().unwrap()).collect();
+        let (a, b) = (replica(dirs[0].path(), 1), replica(dirs[1].path(), 2));
+        let c = voter(dirs[2].path(), 3, vec![1, 2]);
+        assert!(c.start_election(later()).unwrap().is_none());
+
+        elect(&a, &b, None);
+        a.set_learners(&[3]).unwrap();
+        a.append(&[Record::new("a"), Record::new("b")]).unwrap();
+        sync(&a, &c);
+        assert_eq!(vec![b"a".to_vec(), b"b".to_vec()], values(&c, 0));
+        assert_eq!(Some(1), c.leader());
+
+        // The learner's copy does not count towards the quorum.
+        assert_eq!(0, a.commit_offset());
+        sync(&a, &b);
+        assert_eq!(2, a.commit_offset());
+
+        let lag = Duration::from_secs(1);
+        let term = a.state().hard.term;
+        assert_eq!(Some((term, vec![1, 2])), a.leadership(Instant::now(), lag));
+        assert_eq!(Some((term, vec![1])), a.leadership(later(), lag));
+        assert_eq!(None, b.leadership(Instant::now(), lag));
+    }
+}
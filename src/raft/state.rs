@@ -0,0 +1,169 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::error::{Error, Result};
+
+/// The file, within a partition's log directory, replica state is persisted to.
+pub(super) const STATE_FILE: &str = "raft.json";
+
+/// The first offset written by a leader elected in a given term.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct Epoch {
+    pub(super) term: u64,
+    pub(super) start_offset: u64,
+}
+
+/// The replica state which must survive restarts for elections to stay safe.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(super) struct HardState {
+    /// The latest term this replica has seen.
+    pub(super) term: u64,
+    /// The candidate this replica voted for in the current term.
+    pub(super) voted_for: Option<u32>,
+    /// The terms of the replica's log, in offset order.
+    pub(super) epochs: Vec<Epoch>,
+    /// A checkpoint of the replica's commit offset, restored on open so fetches
+    /// do not stall until the first heartbeat from the leader.
+    pub(super) commit_offset: u64,
+}
+
+impl HardState {
+    /// Load the state persisted in the supplied partition directory, or the
+    /// initial state if none has been persisted yet.
+    pub(super) fn load(dir: &Path) -> Result<HardState> {
+        let path = dir.join(STATE_FILE);
+        let raw = match fs::read(&path) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(HardState::default()),
+            Err(e) => return Err(Error::io(&path, e)),
+        };
+        serde_json::from_slice(&raw).map_err(|source| Error::State { path, source })
+    }
+
+    /// Atomically persist this state into the supplied partition directory.
+    pub(super) fn store(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(STATE_FILE);
+        let tmp = dir.join(format!("{}.tmp", STATE_FILE));
+        let raw = serde_json::to_vec(self).map_err(|source| Error::State {
+            path: path.clone(),
+            source,
+        })?;
+
+        let mut file = File::create(&tmp).map_err(|e| Error::io(&tmp, e))?;
+        file.write_all(&raw).map_err(|e| Error::io(&tmp, e))?;
+        file.sync_all().map_err(|e| Error::io(&tmp, e))?;
+        fs::rename(&tmp, &path).map_err(|e| Error::io(&path, e))?;
+        File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .map_err(|e| Error::io(dir, e))
+    }
+
+    /// Returns the term of the record at the supplied offset, records written
+    /// before the first known term belong to term 0.
+    pub(super) fn term_at(&self, offset: u64) -> u64 {
+        self.epochs
+            .iter()
+            .rev()
+            .find(|epoch| epoch.start_offset <= offset)
+            .map(|epoch| epoch.term)
+            .unwrap_or(0)
+    }
+
+    /// Returns the term of the last record of a log ending at `next_offset`.
+    pub(super) fn last_term(&self, next_offset: u64) -> u64 {
+        match next_offset {
+            0 => 0,
+            next_offset => self.term_at(next_offset - 1),
+        }
+    }
+
+    /// Returns the offset the log moves past the supplied term at, or
+    /// `next_offset` if it never does.
+    pub(super) fn end_offset_for(&self, term: u64, next_offset: u64) -> u64 {
+        self.epochs
+            .iter()
+            .find(|epoch| epoch.term > term)
+            .map(|epoch| epoch.start_offset)
+            .unwrap_or(next_offset)
+    }
+
+    /// Record that records from `start_offset` on belong to the supplied term,
+    /// returning whether or not the state changed.
+    pub(super) fn begin_epoch(&mut self, term: u64, start_offset: u64) -> bool {
+        match self.epochs.last_mut() {
+            Some(last) if last.term >= term => return false,
+            Some(last) if last.start_offset >= start_offset => *last = Epoch { term, start_offset },
+            _ => self.epochs.push(Epoch { term, start_offset }),
+        }
+        true
+    }
+
+    /// Forget the terms of every record from `offset` on.
+    pub(super) fn truncate(&mut self, offset: u64) {
+        self.epochs.retain(|epoch| epoch.start_offset < offset);
+    }
+
+    /// Forget every term, restarting the log at `start_offset` in the supplied term.
+    pub(super) fn reset(&mut self, term: u64, start_offset: u64) {
+        self.epochs = vec![Epoch { term, start_offset }];
+        self.commit_offset = start_offset;
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_load() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(HardState::default(), HardState::load(dir.path()).unwrap());
+
+        let mut state = HardState {
+            term: 3,
+            voted_for: Some(2),
+            ..HardState::default()
+        };
+        state.begin_epoch(1, 0);
+        state.begin_epoch(3, 10);
+        state.store(dir.path()).unwrap();
+        assert_eq!(state, HardState::load(dir.path()).unwrap());
+    }
+
+    #[test]
+    fn test_epochs() {
+        let mut state = HardState::default();
+        assert_eq!(0, state.term_at(5));
+        assert!(state.begin_epoch(1, 0));
+        assert!(state.begin_epoch(2, 10));
+        assert!(!state.begin_epoch(2, 15));
+        assert!(state.begin_epoch(4, 10));
+        assert!(state.begin_epoch(5, 20));
+
+        assert_eq!(1, state.term_at(9));
+        assert_eq!(4, state.term_at(10));
+        assert_eq!(5, state.term_at(25));
+        assert_eq!(4, state.last_term(20));
+        assert_eq!(0, state.last_term(0));
+
+        assert_eq!(10, state.end_offset_for(1, 30));
+        assert_eq!(10, state.end_offset_for(3, 30));
+        assert_eq!(30, state.end_offset_for(5, 30));
+
+        state.truncate(15);
+        assert_eq!(4, state.term_at(25));
+        state.reset(4, 100);
+        assert_eq!(0, state.term_at(99));
+        assert_eq!(4, state.term_at(100));
+        assert_eq!(100, state.commit_offset);
+    }
+}
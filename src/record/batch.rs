@@ -0,0 +1,515 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::codec::{self, Reader, Writer};
+use crate::storage::Entry;
+
+use super::compression::Compression;
+use super::record::{OffsetRecord, Record, TimestampType};
+
+/// The current version of the record batch format.
+pub const MAGIC: u8 = 4;
+
+/// The original version of the record batch format, which predates idempotent
+/// producers and is still readable.
+const MAGIC_V1: u8 = 1;
+/// The version of the record batch format that predates record headers, which
+/// is still readable.
+const MAGIC_V2: u8 = 2;
+/// The version of the record batch format that predates create timestamps, and
+/// so stamps each record only with the entry's timestamp, which is still readable.
+const MAGIC_V3: u8 = 3;
+
+/// Set in a batch's attributes when it was written by an idempotent producer.
+const PRODUCER_ATTRIBUTE: u8 = 1;
+/// Set in a batch's attributes when it was written as part of a transaction.
+const TRANSACTIONAL_ATTRIBUTE: u8 = 1 << 1;
+/// Set in a batch's attributes when it holds a transaction marker rather than records.
+const CONTROL_ATTRIBUTE: u8 = 1 << 2;
+/// Set in a batch's attributes when the entry is stamped with the records'
+/// create time rather than the time they were appended.
+const CREATE_TIME_ATTRIBUTE: u8 = 1 << 3;
+/// The position of the id of the codec a batch's records are compressed with
+/// within its attributes. Batches written before compression was supported
+/// leave these bits unset, and so read as uncompressed.
+const COMPRESSION_SHIFT: u8 = 4;
+/// The bits of a batch's attributes holding its compression codec's id.
+const COMPRESSION_MASK: u8 = 0b111 << COMPRESSION_SHIFT;
+
+/// Identifies the idempotent producer that wrote a batch, and where the batch
+/// falls in that producer's sequence of writes to the partition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProducerBatch {
+    /// The id assigned to the producer.
+    pub producer_id: u64,
+    /// The producer's epoch, bumped whenever its id is reassigned.
+    pub epoch: u16,
+    /// The sequence number of the first record in the batch.
+    pub sequence: u32,
+    /// Whether the batch was written as part of a transaction, and so is only
+    /// visible to read committed consumers once the transaction commits.
+    pub transactional: bool,
+}
+
+impl ProducerBatch {
+    /// Returns the sequence number of the last record in a batch of `count` records.
+    ///
+    /// ```
+    /// # use librift::record::ProducerBatch;
+    /// let batch = ProducerBatch { producer_id: 1, epoch: 0, sequence: 5, transactional: false };
+    /// assert_eq!(7, batch.last_sequence(3));
+    /// ```
+    pub fn last_sequence(&self, count: u32) -> u32 {
+        self.sequence.wrapping_add(count.saturating_sub(1))
+    }
+}
+
+/// The outcome of a transaction, written to each partition it touched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlMarker {
+    /// The transaction's records are visible to read committed consumers.
+    Commit,
+    /// The transaction's records are hidden from read committed consumers.
+    Abort,
+}
+
+/// The header of a single batch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchHeader {
+    /// The idempotent producer that wrote the batch, if any.
+    pub producer: Option<ProducerBatch>,
+    /// The transaction marker held by the batch, if it is a control batch.
+    pub control: Option<ControlMarker>,
+    /// Which of its records' timestamps the entry holding the batch is stamped
+    /// with.
+    pub timestamp_type: TimestampType,
+    /// The codec the batch's records are compressed with.
+    pub compression: Compression,
+}
+
+/// Returns the current time in milliseconds since the epoch.
+pub fn current_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+/// Encode the supplied records into a single log entry appended at, and stamped
+/// with, the supplied timestamp.
+///
+/// ```
+/// # use librift::record::{self, Record};
+/// let entry = record::encode(&[Record::new("a"), Record::new("b")], 100);
+/// assert_eq!(2, entry.record_count);
+///
+/// let records = record::decode(&entry).unwrap();
+/// assert_eq!(1, records[1].offset);
+/// assert_eq!(Some(b"b".to_vec()), records[1].record.value);
+/// ```
+pub fn encode(records: &[Record], timestamp: i64) -> Entry {
+    encode_with_producer(records, timestamp, None, TimestampType::LogAppendTime)
+}
+
+/// Encode the supplied records into a single log entry like [encode], tagging the
+/// batch with the idempotent producer that wrote it. The entry is stamped with
+/// the largest of the records' timestamps of the supplied type, so that
+/// retention and time based lookups honour it.
+///
+/// ```
+/// # use librift::record::{self, ProducerBatch, Record, TimestampType};
+/// let producer = ProducerBatch { producer_id: 7, epoch: 1, sequence: 0, transactional: true };
+/// let records = [Record::new("a").with_timestamp(50)];
+/// let entry = record::encode_with_producer(&records, 100, Some(producer), TimestampType::CreateTime);
+/// assert_eq!(Some(producer), record::header(&entry).unwrap().producer);
+/// assert_eq!(50, entry.max_timestamp);
+///
+/// let decoded = record::decode(&entry).unwrap();
+/// assert_eq!(100, decoded[0].timestamp);
+/// assert_eq!(Some(50), decoded[0].record.timestamp);
+/// ```
+pub fn encode_with_producer(
+    records: &[Record],
+    timestamp: i64,
+    producer: Option<ProducerBatch>,
+    timestamp_type: TimestampType,
+) -> Entry {
+    encode_batch(
+        records,
+        timestamp,
+        &BatchHeader {
+            producer,
+            control: None,
+            timestamp_type,
+            compression: Compression::None,
+        },
+    )
+}
+
+/// Encode the supplied records into a single log entry like
+/// [encode_with_producer], described by the supplied header. The records are
+/// compressed as a whole with the header's codec, while the header itself is
+/// always left uncompressed so it can be read cheaply.
+///
+/// ```
+/// # use librift::record::{self, BatchHeader, Compression, Record};
+/// let header = BatchHeader { compression: Compression::Zstd, ..BatchHeader::default() };
+/// let entry = record::encode_batch(&[Record::new("a")], 100, &header);
+/// assert_eq!(Compression::Zstd, record::header(&entry).unwrap().compression);
+/// assert_eq!(Some(b"a".to_vec()), record::decode(&entry).unwrap()[0].record.value);
+/// ```
+pub fn encode_batch(records: &[Record], timestamp: i64, header: &BatchHeader) -> Entry {
+    let mut payload = Vec::new();
+    put_header(&mut payload, header, timestamp);
+    let mut encoded = Vec::new();
+    for record in records {
+        record.encode(&mut encoded);
+    }
+    match header.compression {
+        Compression::None => payload.extend_from_slice(&encoded),
+        compression => payload.extend_from_slice(&compression.compress(&encoded)),
+    }
+    let max_timestamp = match header.timestamp_type {
+        TimestampType::CreateTime => records
+            .iter()
+            .map(|record| record.timestamp.unwrap_or(timestamp))
+            .max()
+            .unwrap_or(timestamp),
+        TimestampType::LogAppendTime => timestamp,
+    };
+    Entry::new(records.len() as u32, max_timestamp, payload)
+}
+
+/// Encode a transaction marker written on behalf of the supplied producer as a
+/// control batch. The batch holds a single empty record, so the marker occupies
+/// an offset of its own, but is never returned to consumers.
+///
+/// ```
+/// # use librift::record::{self, ControlMarker};
+/// let entry = record::encode_marker(7, 1, ControlMarker::Abort, 100);
+/// let header = record::header(&entry).unwrap();
+/// assert_eq!(Some(ControlMarker::Abort), header.control);
+/// assert_eq!(7, header.producer.unwrap().producer_id);
+/// ```
+pub fn encode_marker(producer_id: u64, epoch: u16, marker: ControlMarker, timestamp: i64) -> Entry {
+    let mut payload = Vec::new();
+    let producer = ProducerBatch {
+        producer_id,
+        epoch,
+        sequence: 0,
+        transactional: true,
+    };
+    put_header(
+        &mut payload,
+        &BatchHeader {
+            producer: Some(producer),
+            control: Some(marker),
+            timestamp_type: TimestampType::LogAppendTime,
+            compression: Compression::None,
+        },
+        timestamp,
+    );
+    Record::default().encode(&mut payload);
+    Entry::new(1, timestamp, payload)
+}
+
+/// Returns the header of the supplied log entry.
+pub fn header(entry: &Entry) -> codec::Result<BatchHeader> {
+    read_header(&mut Reader::new(&entry.payload)).map(|(_, header, _)| header)
+}
+
+/// Decode every record held in the supplied log entry. Batches written before
+/// the time they were appended was recorded separately report the entry's
+/// timestamp instead.
+pub fn decode(entry: &Entry) -> codec::Result<Vec<OffsetRecord>> {
+    let mut reader = Reader::new(&entry.payload);
+    let (magic, header, appended) = read_header(&mut reader)?;
+    let timestamp = appended.unwrap_or(entry.max_timestamp);
+
+    let decompressed;
+    if header.compression != Compression::None {
+        let compressed = reader.get_raw(reader.remaining())?;
+        decompressed = header.compression.decompress(compressed)?;
+        reader = Reader::new(&decompressed);
+    }
+
+    (0..entry.record_count as u64)
+        .map(|delta| {
+            let record = match magic {
+                MAGIC => Record::decode(&mut reader)?,
+                MAGIC_V3 => Record::decode_without_timestamp(&mut reader)?,
+                _ => Record::decode_without_headers(&mut reader)?,
+            };
+            Ok(OffsetRecord {
+                offset: entry.base_offset + delta,
+                timestamp,
+                record,
+            })
+        })
+        .collect()
+}
+
+/// Write the batch's version and header, followed by the time it was appended.
+fn put_header(buf: &mut Vec<u8>, header: &BatchHeader, timestamp: i64) {
+    let mut attributes = 0;
+    if let Some(producer) = &header.producer {
+        attributes |= PRODUCER_ATTRIBUTE;
+        if producer.transactional {
+            attributes |= TRANSACTIONAL_ATTRIBUTE;
+        }
+    }
+    if header.control.is_some() {
+        attributes |= CONTROL_ATTRIBUTE;
+    }
+    if header.timestamp_type == TimestampType::CreateTime {
+        attributes |= CREATE_TIME_ATTRIBUTE;
+    }
+    attributes |= header.compression.id() << COMPRESSION_SHIFT;
+
+    buf.put_u8(MAGIC);
+    buf.put_u8(attributes);
+    if let Some(producer) = &header.producer {
+        buf.put_u64(producer.producer_id);
+        buf.put_u16(producer.epoch);
+        buf.put_u32(producer.sequence);
+    }
+    if let Some(marker) = header.control {
+        buf.put_bool(marker == ControlMarker::Commit);
+    }
+    buf.put_i64(timestamp);
+}
+
+/// Read the batch's version and header, along with the time it was appended if
+/// the batch records it, leaving the reader positioned at the first record.
+fn read_header(reader: &mut Reader) -> codec::Result<(u8, BatchHeader, Option<i64>)> {
+    let magic = reader.get_u8()?;
+    let attributes = match magic {
+        MAGIC_V1 => return Ok((magic, BatchHeader::default(), None)),
+        MAGIC_V2 | MAGIC_V3 | MAGIC => reader.get_u8()?,
+        magic => {
+            return Err(codec::Error::InvalidValue {
+                field: "magic",
+                value: magic as i64,
+            })
+        }
+    };
+
+    let mut header = BatchHeader::default();
+    if attributes & PRODUCER_ATTRIBUTE != 0 {
+        header.producer = Some(ProducerBatch {
+            producer_id: reader.get_u64()?,
+            epoch: reader.get_u16()?,
+            sequence: reader.get_u32()?,
+            transactional: attributes & TRANSACTIONAL_ATTRIBUTE != 0,
+        });
+    }
+    if attributes & CONTROL_ATTRIBUTE != 0 {
+        header.control = Some(match reader.get_bool()? {
+            true => ControlMarker::Commit,
+            false => ControlMarker::Abort,
+        });
+    }
+    if attributes & CREATE_TIME_ATTRIBUTE != 0 {
+        header.timestamp_type = TimestampType::CreateTime;
+    }
+    if magic == MAGIC {
+        header.compression =
+            Compression::from_id((attributes & COMPRESSION_MASK) >> COMPRESSION_SHIFT)?;
+    }
+    let appended = match magic {
+        MAGIC => Some(reader.get_i64()?),
+        _ => None,
+    };
+    Ok((magic, header, appended))
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let records = vec![
+            Record::new("a").with_key("k").with_header("h", "v"),
+            Record::default(),
+        ];
+        let mut entry = encode(&records, 42);
+        entry.base_offset = 10;
+
+        let decoded = decode(&entry).unwrap();
+        assert_eq!(2, decoded.len());
+        assert_eq!(10, decoded[0].offset);
+        assert_eq!(11, decoded[1].offset);
+        assert_eq!(42, decoded[1].timestamp);
+        assert_eq!(records[0], decoded[0].record);
+        assert_eq!(records[1], decoded[1].record);
+    }
+
+    #[test]
+    fn test_header() {
+        let producer = ProducerBatch {
+            producer_id: 3,
+            epoch: 2,
+            sequence: 10,
+            transactional: true,
+        };
+        let records = [Record::new("a"), Record::new("b")];
+        let entry = encode_with_producer(&records, 0, Some(producer), TimestampType::CreateTime);
+        assert_eq!(
+            BatchHeader {
+                producer: Some(producer),
+                control: None,
+                timestamp_type: TimestampType::CreateTime,
+                compression: Compression::None,
+            },
+            header(&entry).unwrap()
+        );
+        assert_eq!(11, producer.last_sequence(entry.record_count));
+        assert_eq!(2, decode(&entry).unwrap().len());
+        assert_eq!(
+            BatchHeader::default(),
+            header(&encode(&[Record::new("a")], 0)).unwrap()
+        );
+
+        let entry = encode_marker(3, 2, ControlMarker::Abort, 0);
+        let decoded = header(&entry).unwrap();
+        assert_eq!(Some(ControlMarker::Abort), decoded.control);
+        assert_eq!(Some(2), decoded.producer.map(|p| p.epoch));
+        assert_eq!(1, decode(&entry).unwrap().len());
+
+        // Batches written before producers were tracked, or before records
+        // carried headers, are still readable.
+        let mut v2 = vec![MAGIC_V2, PRODUCER_ATTRIBUTE];
+        v2.put_u64(9);
+        v2.put_u16(1);
+        v2.put_u32(2);
+        for mut payload in [vec![MAGIC_V1], v2] {
+            payload.put_optional_bytes(None);
+            payload.put_optional_bytes(Some(b"a"));
+            let entry = Entry::new(1, 0, payload);
+            assert!(header(&entry).unwrap().control.is_none());
+            let decoded = decode(&entry).unwrap();
+            assert_eq!(Some(b"a".to_vec()), decoded[0].record.value);
+            assert!(decoded[0].record.headers.is_empty());
+        }
+
+        // As are batches written before records carried a create timestamp.
+        let mut payload = vec![MAGIC_V3, 0];
+        payload.put_optional_bytes(None);
+        payload.put_optional_bytes(Some(b"a"));
+        payload.put_array(&[("h", "v")], |buf, (key, value)| {
+            buf.put_string(key);
+            buf.put_bytes(value.as_bytes());
+        });
+        let decoded = decode(&Entry::new(1, 7, payload)).unwrap();
+        assert_eq!(Record::new("a").with_header("h", "v"), decoded[0].record);
+        assert_eq!(7, decoded[0].timestamp);
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let records = [
+            Record::new("a").with_timestamp(30),
+            Record::new("b").with_timestamp(10),
+            Record::new("c"),
+        ];
+
+        // Entries are stamped with the time they were appended unless the
+        // records' create time is authoritative, in which case records without
+        // one count as created when they were appended.
+        let entry = encode_with_producer(&records, 20, None, TimestampType::LogAppendTime);
+        assert_eq!(20, entry.max_timestamp);
+        let entry = encode_with_producer(&records, 20, None, TimestampType::CreateTime);
+        assert_eq!(30, entry.max_timestamp);
+        let entry = encode_with_producer(&records[1..], 20, None, TimestampType::CreateTime);
+        assert_eq!(20, entry.max_timestamp);
+
+        // Either way records keep both timestamps.
+        let decoded = decode(&entry).unwrap();
+        assert_eq!(
+            vec![20, 20],
+            decoded.iter().map(|r| r.timestamp).collect::<Vec<_>>()
+        );
+        assert_eq!(Some(10), decoded[0].record.timestamp);
+        assert_eq!(None, decoded[1].record.timestamp);
+        assert_eq!(10, decoded[0].timestamp_of(TimestampType::CreateTime));
+        assert_eq!(20, decoded[1].timestamp_of(TimestampType::CreateTime));
+    }
+
+    #[test]
+    fn test_compression() {
+        let records: Vec<Record> = (0..10)
+            .map(|i| {
+                Record::new(format!("value-{}", i).repeat(10))
+                    .with_key("key")
+                    .with_header("h", "v")
+                    .with_timestamp(i)
+            })
+            .collect();
+        let uncompressed = encode(&records, 7);
+        for compression in Compression::ALL {
+            let header = BatchHeader {
+                compression,
+                ..BatchHeader::default()
+            };
+            let entry = encode_batch(&records, 7, &header);
+            assert_eq!(header, super::header(&entry).unwrap());
+            if compression != Compression::None {
+                assert!(entry.payload.len() < uncompressed.payload.len());
+            }
+
+            let decoded = decode(&entry).unwrap();
+            assert_eq!(
+                records,
+                decoded.into_iter().map(|r| r.record).collect::<Vec<_>>()
+            );
+        }
+
+        // Corrupt data and unknown codecs are reported rather than misread.
+        let mut entry = encode_batch(
+            &records,
+            7,
+            &BatchHeader {
+                compression: Compression::Gzip,
+                ..BatchHeader::default()
+            },
+        );
+        let len = entry.payload.len();
+        entry.payload[len - 20..].fill(0);
+        assert!(matches!(
+            decode(&entry),
+            Err(codec::Error::Decompress { codec: "gzip", .. })
+        ));
+        entry.payload[1] |= COMPRESSION_MASK;
+        assert!(matches!(
+            decode(&entry),
+            Err(codec::Error::InvalidValue {
+                field: "compression",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_bad_magic() {
+        let mut entry = encode(&[Record::new("a")], 0);
+        entry.payload[0] = 0;
+        assert!(matches!(
+            decode(&entry),
+            Err(codec::Error::InvalidValue { field: "magic", .. })
+        ));
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut entry = encode(&[Record::new("a")], 0);
+        entry.payload.truncate(4);
+        assert!(matches!(
+            decode(&entry),
+            Err(codec::Error::UnexpectedEof { .. })
+        ));
+    }
+}
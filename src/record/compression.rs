@@ -0,0 +1,206 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fmt,
+    io::{Read, Write},
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder};
+
+use crate::codec;
+
+use super::metrics::metrics;
+
+/// The zstd level batches are compressed at, which favours speed.
+const ZSTD_LEVEL: i32 = 3;
+
+/// The codec the records of a batch are compressed with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Compression {
+    /// Records are stored as is.
+    #[default]
+    None,
+    /// Records are compressed with gzip.
+    Gzip,
+    /// Records are compressed with zstd.
+    Zstd,
+    /// Records are compressed with the lz4 block format.
+    Lz4,
+    /// Records are compressed with the raw snappy format.
+    Snappy,
+}
+
+impl Compression {
+    /// Every codec, in order of id.
+    pub const ALL: [Compression; 5] = [
+        Compression::None,
+        Compression::Gzip,
+        Compression::Zstd,
+        Compression::Lz4,
+        Compression::Snappy,
+    ];
+
+    /// Returns the id this codec is identified by on the wire and on disk.
+    pub fn id(&self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Gzip => 1,
+            Compression::Zstd => 2,
+            Compression::Lz4 => 3,
+            Compression::Snappy => 4,
+        }
+    }
+
+    /// Returns the codec identified by the supplied id.
+    pub fn from_id(id: u8) -> codec::Result<Compression> {
+        Compression::ALL
+            .get(id as usize)
+            .copied()
+            .ok_or(codec::Error::InvalidValue {
+                field: "compression",
+                value: id as i64,
+            })
+    }
+
+    /// Returns the name of this codec.
+    ///
+    /// ```
+    /// # use librift::record::Compression;
+    /// assert_eq!("zstd", Compression::Zstd.name());
+    /// assert_eq!(Some(Compression::Zstd), Compression::from_name("zstd"));
+    /// assert_eq!(None, Compression::from_name("brotli"));
+    /// ```
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::Lz4 => "lz4",
+            Compression::Snappy => "snappy",
+        }
+    }
+
+    /// Returns the codec with the supplied name, if any.
+    pub fn from_name(name: &str) -> Option<Compression> {
+        Compression::ALL
+            .into_iter()
+            .find(|compression| compression.name() == name)
+    }
+
+    /// Compress the supplied data, counting the bytes in and out against this
+    /// codec.
+    pub(super) fn compress(&self, data: &[u8]) -> Vec<u8> {
+        let compressed = match self {
+            Compression::None => return data.to_vec(),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder
+                    .write_all(data)
+                    .and_then(|_| encoder.finish())
+                    .expect("writes to a vec never fail")
+            }
+            Compression::Zstd => {
+                zstd::bulk::compress(data, ZSTD_LEVEL).expect("writes to a vec never fail")
+            }
+            Compression::Lz4 => lz4_flex::compress_prepend_size(data),
+            Compression::Snappy => snap::raw::Encoder::new()
+                .compress_vec(data)
+                .expect("snappy input is within its size limit"),
+        };
+
+        let metrics = metrics();
+        metrics
+            .uncompressed_bytes
+            .with_label_values(&[self.name()])
+            .inc_by(data.len() as u64);
+        metrics
+            .compressed_bytes
+            .with_label_values(&[self.name()])
+            .inc_by(compressed.len() as u64);
+        compressed
+    }
+
+    /// Decompress data compressed with this codec.
+    pub(super) fn decompress(&self, data: &[u8]) -> codec::Result<Vec<u8>> {
+        let decompressed = match self {
+            Compression::None => return Ok(data.to_vec()),
+            Compression::Gzip => {
+                let mut buf = Vec::new();
+                GzDecoder::new(data)
+                    .read_to_end(&mut buf)
+                    .map(|_| buf)
+                    .map_err(|e| e.to_string())
+            }
+            Compression::Zstd => zstd::stream::decode_all(data).map_err(|e| e.to_string()),
+            Compression::Lz4 => {
+                lz4_flex::decompress_size_prepended(data).map_err(|e| e.to_string())
+            }
+            Compression::Snappy => snap::raw::Decoder::new()
+                .decompress_vec(data)
+                .map_err(|e| e.to_string()),
+        };
+        decompressed.map_err(|reason| codec::Error::Decompress {
+            codec: self.name(),
+            reason,
+        })
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let data = b"hello hello hello hello hello hello".repeat(16);
+        for compression in Compression::ALL {
+            assert_eq!(Ok(compression), Compression::from_id(compression.id()));
+            assert_eq!(
+                Some(compression),
+                Compression::from_name(compression.name())
+            );
+
+            let compressed = compression.compress(&data);
+            if compression != Compression::None {
+                assert!(compressed.len() < data.len(), "{}", compression);
+            }
+            assert_eq!(data, compression.decompress(&compressed).unwrap());
+            assert_eq!(
+                Vec::<u8>::new(),
+                compression.decompress(&compression.compress(&[])).unwrap()
+            );
+        }
+        assert!(Compression::from_id(5).is_err());
+    }
+
+    #[test]
+    fn test_corrupt() {
+        for compression in &Compression::ALL[1..] {
+            assert!(matches!(
+                compression.decompress(&[0xff; 16]),
+                Err(codec::Error::Decompress { codec, .. }) if codec == compression.name()
+            ));
+        }
+    }
+
+    #[test]
+    fn test_metrics() {
+        let data = vec![0; 4096];
+        let metrics = metrics();
+        let uncompressed = metrics.uncompressed_bytes.with_label_values(&["lz4"]);
+        let compressed = metrics.compressed_bytes.with_label_values(&["lz4"]);
+        let (before_in, before_out) = (uncompressed.get(), compressed.get());
+
+        let out = Compression::Lz4.compress(&data);
+        assert!(uncompressed.get() - before_in >= 4096);
+        assert!(compressed.get() - before_out >= out.len() as u64);
+    }
+}
@@ -0,0 +1,44 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::sync::OnceLock;
+
+use prometheus::IntCounterVec;
+
+use crate::metrics::{register_int_counter_vec, Opt};
+
+/// The series exported for record batches.
+pub(super) struct Metrics {
+    /// Bytes of records handed to a codec to compress, labelled by codec.
+    pub(super) uncompressed_bytes: IntCounterVec,
+    /// Bytes of records produced by a codec, labelled by codec.
+    pub(super) compressed_bytes: IntCounterVec,
+}
+
+/// Returns the process wide record batch metrics, registering them on first use.
+pub(super) fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(|| {
+        let opts = || {
+            vec![
+                Opt::Namespace(String::from("rift")),
+                Opt::Subsystem(String::from("compression")),
+                Opt::Label(String::from("codec")),
+            ]
+        };
+        Metrics {
+            uncompressed_bytes: register_int_counter_vec(
+                "uncompressed_bytes_total",
+                "The number of bytes of records compressed, before compression.",
+                Some(opts()),
+            )
+            .expect("record metrics registered twice"),
+            compressed_bytes: register_int_counter_vec(
+                "compressed_bytes_total",
+                "The number of bytes of records compressed, after compression.",
+                Some(opts()),
+            )
+            .expect("record metrics registered twice"),
+        }
+    })
+}
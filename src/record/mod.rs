@@ -0,0 +1,18 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod batch;
+mod compression;
+mod metrics;
+#[allow(clippy::module_inception)]
+mod record;
+
+pub use self::batch::{
+    current_timestamp, decode, encode, encode_batch, encode_marker, encode_with_producer, header,
+    BatchHeader, ControlMarker, ProducerBatch, MAGIC,
+};
+pub use self::compression::Compression;
+pub use self::record::{
+    Header, OffsetRecord, Record, TimestampType, CORRELATION_ID_HEADER, MAX_PRIORITY,
+    PRIORITY_HEADER, REPLY_TO_HEADER, TTL_HEADER,
+};
@@ -0,0 +1,355 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::codec::{self, Reader, Writer};
+
+/// The header holding how long in milliseconds after it is appended a record
+/// may be delivered, as UTF-8 decimal text.
+pub const TTL_HEADER: &str = "rift.ttl_ms";
+/// The header holding the priority queues deliver a record at, as UTF-8
+/// decimal text from 0 to [MAX_PRIORITY].
+pub const PRIORITY_HEADER: &str = "rift.priority";
+/// The highest priority a record may be delivered at.
+pub const MAX_PRIORITY: u8 = 9;
+/// The header holding the topic replies to a request record are produced to.
+pub const REPLY_TO_HEADER: &str = "rift.reply_to";
+/// The header holding the id matching a reply up with the request it answers.
+pub const CORRELATION_ID_HEADER: &str = "rift.correlation_id";
+
+/// Which of a record's timestamps is authoritative for a topic, and so is used
+/// for time based retention and lookups.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimestampType {
+    /// The time the producer created the record, falling back to the time it
+    /// was appended for records created without one.
+    CreateTime,
+    /// The time the broker appended the record to the log.
+    #[default]
+    LogAppendTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Application defined metadata attached to a record.
+pub struct Header {
+    /// The name of the header, which need not be unique within a record.
+    pub key: String,
+    /// The header's value.
+    pub value: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+/// A single message as produced by a client.
+pub struct Record {
+    /// The optional key used for partitioning.
+    pub key: Option<Vec<u8>>,
+    /// The optional message payload.
+    pub value: Option<Vec<u8>>,
+    /// The record's headers, in the order they were added.
+    pub headers: Vec<Header>,
+    /// The time, in milliseconds since the epoch, the producer created the
+    /// record, if it was given one.
+    pub timestamp: Option<i64>,
+}
+
+impl Record {
+    /// Create a new unkeyed record holding the supplied value.
+    ///
+    /// ```
+    /// # use librift::record::Record;
+    /// let record = Record::new("hello").with_key("world");
+    /// assert_eq!(Some(b"hello".to_vec()), record.value);
+    /// assert_eq!(Some(b"world".to_vec()), record.key);
+    /// ```
+    pub fn new(value: impl Into<Vec<u8>>) -> Record {
+        Record {
+            key: None,
+            value: Some(value.into()),
+            headers: Vec::new(),
+            timestamp: None,
+        }
+    }
+
+    /// Set the key of this record.
+    pub fn with_key(mut self, key: impl Into<Vec<u8>>) -> Record {
+        self.key = Some(key.into());
+        self
+    }
+
+    /// Set the time, in milliseconds since the epoch, this record was created.
+    ///
+    /// ```
+    /// # use librift::record::Record;
+    /// let record = Record::new("hello").with_timestamp(1000);
+    /// assert_eq!(Some(1000), record.timestamp);
+    /// ```
+    pub fn with_timestamp(mut self, timestamp: i64) -> Record {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Append a header to this record.
+    ///
+    /// ```
+    /// # use librift::record::Record;
+    /// let record = Record::new("hello").with_header("region", "eu");
+    /// assert_eq!(Some(&b"eu"[..]), record.header("region"));
+    /// assert_eq!(None, record.header("missing"));
+    /// ```
+    pub fn with_header(mut self, key: impl Into<String>, value: impl Into<Vec<u8>>) -> Record {
+        self.headers.push(Header {
+            key: key.into(),
+            value: value.into(),
+        });
+        self
+    }
+
+    /// Returns the value of the last header with the supplied key, if any.
+    pub fn header(&self, key: &str) -> Option<&[u8]> {
+        self.headers
+            .iter()
+            .rev()
+            .find(|header| header.key == key)
+            .map(|header| header.value.as_slice())
+    }
+
+    /// Set how long in milliseconds after it is appended this record may be
+    /// delivered, replacing any time to live it already had.
+    ///
+    /// ```
+    /// # use librift::record::Record;
+    /// let record = Record::new("hello").with_ttl(5000);
+    /// assert_eq!(Some(5000), record.ttl_ms());
+    /// assert_eq!(None, Record::new("hello").ttl_ms());
+    /// ```
+    pub fn with_ttl(mut self, ttl_ms: u64) -> Record {
+        self.headers.retain(|header| header.key != TTL_HEADER);
+        self.with_header(TTL_HEADER, ttl_ms.to_string())
+    }
+
+    /// Returns the time to live of this record in milliseconds, if it has a
+    /// valid TTL header.
+    pub fn ttl_ms(&self) -> Option<u64> {
+        std::str::from_utf8(self.header(TTL_HEADER)?)
+            .ok()?
+            .parse()
+            .ok()
+    }
+
+    /// Set the priority queues deliver this record at, from 0 to
+    /// [MAX_PRIORITY], replacing any priority it already had.
+    ///
+    /// ```
+    /// # use librift::record::Record;
+    /// assert_eq!(7, Record::new("hello").with_priority(7).priority());
+    /// assert_eq!(9, Record::new("hello").with_priority(200).priority());
+    /// assert_eq!(0, Record::new("hello").priority());
+    /// ```
+    pub fn with_priority(mut self, priority: u8) -> Record {
+        self.headers.retain(|header| header.key != PRIORITY_HEADER);
+        self.with_header(PRIORITY_HEADER, priority.min(MAX_PRIORITY).to_string())
+    }
+
+    /// Returns the priority queues deliver this record at, which is zero
+    /// unless it has a valid priority header. Priorities above
+    /// [MAX_PRIORITY] are lowered to it.
+    pub fn priority(&self) -> u8 {
+        self.header(PRIORITY_HEADER)
+            .and_then(|value| std::str::from_utf8(value).ok())
+            .and_then(|value| value.parse::<u64>().ok())
+            .map_or(0, |priority| priority.min(MAX_PRIORITY as u64) as u8)
+    }
+
+    /// Set the topic replies to this record should be produced to, replacing
+    /// any it already had.
+    ///
+    /// ```
+    /// # use librift::record::Record;
+    /// let record = Record::new("ping").with_reply_to("tmp.replies");
+    /// assert_eq!(Some("tmp.replies"), record.reply_to());
+    /// assert_eq!(None, Record::new("ping").reply_to());
+    /// ```
+    pub fn with_reply_to(mut self, topic: &str) -> Record {
+        self.headers.retain(|header| header.key != REPLY_TO_HEADER);
+        self.with_header(REPLY_TO_HEADER, topic)
+    }
+
+    /// Returns the topic replies to this record should be produced to, if it
+    /// has a UTF-8 reply to header.
+    pub fn reply_to(&self) -> Option<&str> {
+        std::str::from_utf8(self.header(REPLY_TO_HEADER)?).ok()
+    }
+
+    /// Set the id matching this record up with its request or replies,
+    /// replacing any it already had.
+    ///
+    /// ```
+    /// # use librift::record::Record;
+    /// let record = Record::new("ping").with_correlation_id("42");
+    /// assert_eq!(Some("42"), record.correlation_id());
+    /// assert_eq!(None, Record::new("ping").correlation_id());
+    /// ```
+    pub fn with_correlation_id(mut self, id: &str) -> Record {
+        self.headers
+            .retain(|header| header.key != CORRELATION_ID_HEADER);
+        self.with_header(CORRELATION_ID_HEADER, id)
+    }
+
+    /// Returns the id matching this record up with its request or replies, if
+    /// it has a UTF-8 correlation id header.
+    pub fn correlation_id(&self) -> Option<&str> {
+        std::str::from_utf8(self.header(CORRELATION_ID_HEADER)?).ok()
+    }
+
+    /// Encode this record onto the end of the supplied buffer.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_optional_bytes(self.key.as_deref());
+        buf.put_optional_bytes(self.value.as_deref());
+        buf.put_array(&self.headers, |buf, header| {
+            buf.put_string(&header.key);
+            buf.put_bytes(&header.value);
+        });
+        match self.timestamp {
+            Some(timestamp) => {
+                buf.put_bool(true);
+                buf.put_i64(timestamp);
+            }
+            None => buf.put_bool(false),
+        }
+    }
+
+    /// Decode a single record from the supplied reader.
+    pub fn decode(reader: &mut Reader) -> codec::Result<Record> {
+        let mut record = Record::decode_without_timestamp(reader)?;
+        if reader.get_bool()? {
+            record.timestamp = Some(reader.get_i64()?);
+        }
+        Ok(record)
+    }
+
+    /// Decode a single record written before records carried a create
+    /// timestamp.
+    pub(crate) fn decode_without_timestamp(reader: &mut Reader) -> codec::Result<Record> {
+        let mut record = Record::decode_without_headers(reader)?;
+        record.headers = reader.get_array(|reader| {
+            Ok(Header {
+                key: reader.get_string()?,
+                value: reader.get_bytes()?,
+            })
+        })?;
+        Ok(record)
+    }
+
+    /// Decode a single record written by a batch format that predates headers.
+    pub(super) fn decode_without_headers(reader: &mut Reader) -> codec::Result<Record> {
+        Ok(Record {
+            key: reader.get_optional_bytes()?,
+            value: reader.get_optional_bytes()?,
+            headers: Vec::new(),
+            timestamp: None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A record that has been appended to a partition log.
+pub struct OffsetRecord {
+    /// The offset assigned to the record.
+    pub offset: u64,
+    /// The time, in milliseconds since the epoch, the broker appended the
+    /// record to the log.
+    pub timestamp: i64,
+    /// The record itself.
+    pub record: Record,
+}
+
+impl OffsetRecord {
+    /// Returns this record's timestamp of the supplied type, in milliseconds
+    /// since the epoch.
+    ///
+    /// ```
+    /// # use librift::record::{OffsetRecord, Record, TimestampType};
+    /// let record = OffsetRecord { offset: 0, timestamp: 20, record: Record::new("a") };
+    /// assert_eq!(20, record.timestamp_of(TimestampType::CreateTime));
+    ///
+    /// let record = OffsetRecord { record: Record::new("a").with_timestamp(10), ..record };
+    /// assert_eq!(10, record.timestamp_of(TimestampType::CreateTime));
+    /// assert_eq!(20, record.timestamp_of(TimestampType::LogAppendTime));
+    /// ```
+    pub fn timestamp_of(&self, timestamp_type: TimestampType) -> i64 {
+        match timestamp_type {
+            TimestampType::CreateTime => self.record.timestamp.unwrap_or(self.timestamp),
+            TimestampType::LogAppendTime => self.timestamp,
+        }
+    }
+
+    /// Returns when this record expires, in milliseconds since the epoch, if
+    /// ever. The record's TTL header takes precedence over the supplied
+    /// default time to live.
+    pub fn expires_at(&self, default_ttl_ms: Option<u64>) -> Option<i64> {
+        let ttl_ms = self.record.ttl_ms().or(default_ttl_ms)?;
+        Some(
+            self.timestamp
+                .saturating_add(ttl_ms.min(i64::MAX as u64) as i64),
+        )
+    }
+
+    /// Returns whether this record has expired as of `now`, in milliseconds
+    /// since the epoch, and so must no longer be delivered.
+    pub fn is_expired(&self, default_ttl_ms: Option<u64>, now: i64) -> bool {
+        self.expires_at(default_ttl_ms)
+            .is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let records = vec![
+            Record::new("value"),
+            Record::new("value").with_key("key"),
+            Record::new("value")
+                .with_header("a", "1")
+                .with_header("a", "")
+                .with_header("b", vec![0, 1]),
+            Record::new("value").with_timestamp(-5),
+            Record::default(),
+        ];
+        for record in records {
+            let mut buf = Vec::new();
+            record.encode(&mut buf);
+            let mut reader = Reader::new(&buf);
+            assert_eq!(record, Record::decode(&mut reader).unwrap());
+            assert!(reader.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_expiry() {
+        let record = |record: Record| OffsetRecord {
+            offset: 0,
+            timestamp: 1000,
+            record,
+        };
+
+        let never = record(Record::new("value"));
+        assert_eq!(None, never.expires_at(None));
+        assert!(!never.is_expired(None, i64::MAX));
+        assert_eq!(Some(1500), never.expires_at(Some(500)));
+
+        // The header takes precedence over the default.
+        let short = record(Record::new("value").with_ttl(100).with_ttl(200));
+        assert_eq!(Some(200), short.record.ttl_ms());
+        assert_eq!(Some(1200), short.expires_at(Some(500)));
+        assert!(!short.is_expired(None, 1199));
+        assert!(short.is_expired(None, 1200));
+
+        let invalid = record(Record::new("value").with_header(TTL_HEADER, "soon"));
+        assert_eq!(None, invalid.expires_at(None));
+        let forever = record(Record::new("value").with_ttl(u64::MAX));
+        assert_eq!(Some(i64::MAX), forever.expires_at(None));
+    }
+}
@@ -1,13 +1,18 @@
 // (c) Copyright 2022 Christian Saide
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use std::{sync::Arc, thread, time::Instant};
+
 use exitcode::ExitCode;
 use structopt::{
     clap::{self, crate_version, ErrorKind},
     StructOpt,
 };
 
-use super::log;
+use super::{
+    amqp, broker::Broker, cleaner, cluster, group, log, mqtt, producer, queue, raft, record,
+    schedule, server, storage, topic,
+};
 
 const RIFTD: &str = "riftd";
 
@@ -22,6 +27,28 @@ const RIFTD: &str = "riftd";
 struct Config {
     #[structopt(flatten)]
     log_config: log::Config,
+    #[structopt(flatten)]
+    storage_config: storage::Config,
+    #[structopt(flatten)]
+    server_config: server::Config,
+    #[structopt(flatten)]
+    mqtt_config: mqtt::Config,
+    #[structopt(flatten)]
+    amqp_config: amqp::Config,
+    #[structopt(flatten)]
+    group_config: group::Config,
+    #[structopt(flatten)]
+    producer_config: producer::Config,
+    #[structopt(flatten)]
+    queue_config: queue::Config,
+    #[structopt(flatten)]
+    schedule_config: schedule::Config,
+    #[structopt(flatten)]
+    cleaner_config: cleaner::Config,
+    #[structopt(flatten)]
+    cluster_config: cluster::Config,
+    #[structopt(flatten)]
+    raft_config: raft::Config,
 }
 
 /// The primary entrypoint function for the `riftd` binary.
@@ -41,7 +68,144 @@ pub fn run() -> ExitCode {
     };
 
     let logger = log::new(&cfg.log_config, RIFTD);
-    info!(logger, "Hello World!");
 
-    exitcode::OK
+    if let Err(err) = cfg.storage_config.log_config().validate() {
+        crit!(logger, "Invalid storage configuration."; "error" => err.to_string());
+        return exitcode::CONFIG;
+    }
+
+    let data_dir = &cfg.storage_config.data_dir;
+    if let Err(err) = std::fs::create_dir_all(data_dir) {
+        crit!(logger, "Failed to create data directory."; "path" => data_dir.display().to_string(), "error" => err.to_string());
+        return exitcode::CANTCREAT;
+    }
+
+    let topics = match topic::Manager::open(data_dir, cfg.storage_config.log_config()) {
+        Ok(topics) => Arc::new(topics),
+        Err(err) => {
+            crit!(logger, "Failed to load topics."; "path" => data_dir.display().to_string(), "error" => err.to_string());
+            return exitcode::IOERR;
+        }
+    };
+    for topic in topics.list() {
+        info!(logger, "Loaded topic."; "topic" => topic.name(), "partitions" => topic.partitions().len());
+    }
+
+    let flushed = topics.clone();
+    let interval = cfg.storage_config.flush_check_interval();
+    let flush_logger = logger.clone();
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        for topic in flushed.list() {
+            for partition in topic.partitions() {
+                if let Err(err) = partition.flush_if_due(Instant::now()) {
+                    error!(flush_logger, "Failed to flush partition."; "topic" => partition.topic(), "partition" => partition.id(), "error" => err.to_string());
+                }
+            }
+        }
+    });
+
+    let cleaner = cleaner::Cleaner::new(logger.clone(), topics.clone());
+    let interval = cfg.cleaner_config.interval();
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        cleaner.clean(record::current_timestamp());
+    });
+
+    let mut broker = match Broker::open(
+        logger.clone(),
+        topics.clone(),
+        cfg.group_config.clone(),
+        cfg.producer_config.clone(),
+        cfg.queue_config.clone(),
+        cfg.schedule_config.clone(),
+    ) {
+        Ok(broker) => broker,
+        Err(err) => {
+            crit!(logger, "Failed to load committed offsets, transactions, queues, and scheduled messages."; "error" => err.to_string());
+            return exitcode::IOERR;
+        }
+    };
+    if cfg.cluster_config.enabled() {
+        let address = cfg
+            .cluster_config
+            .advertised_address(cfg.server_config.listen_address);
+        let node = match raft::Node::new(
+            logger.clone(),
+            cfg.cluster_config.clone(),
+            address,
+            cfg.raft_config.clone(),
+            topics,
+        ) {
+            Ok(node) => Arc::new(node),
+            Err(raft::Error::InvalidConfig { reason })
+            | Err(raft::Error::Cluster(cluster::Error::InvalidConfig { reason })) => {
+                crit!(logger, "Invalid cluster configuration."; "error" => reason);
+                return exitcode::CONFIG;
+            }
+            Err(err) => {
+                crit!(logger, "Failed to load partition replicas."; "error" => err.to_string());
+                return exitcode::IOERR;
+            }
+        };
+        node.start();
+        info!(logger, "Replicating partitions."; "node" => node.id(), "address" => address.to_string(), "seeds" => cfg.cluster_config.seeds.len(), "replication_factor" => cfg.raft_config.replication_factor, "acks" => cfg.raft_config.acks.to_string());
+        broker = broker.replicated(node);
+    } else {
+        let deleted = broker.delete_temporary_queues();
+        if deleted > 0 {
+            info!(logger, "Deleted temporary queues left by a previous run."; "topics" => deleted);
+        }
+    }
+    let broker = Arc::new(broker);
+    let expiry = broker.clone();
+    thread::spawn(move || loop {
+        thread::sleep(expiry.groups().config().expiry_interval());
+        expiry.groups().expire(Instant::now());
+    });
+    let transactions = broker.clone();
+    thread::spawn(move || loop {
+        thread::sleep(transactions.transactions().config().check_interval());
+        transactions.expire_transactions(record::current_timestamp());
+    });
+    let scheduled = broker.clone();
+    thread::spawn(move || loop {
+        thread::sleep(scheduled.scheduler().config().check_interval());
+        scheduled.release_scheduled(record::current_timestamp());
+    });
+
+    if cfg.mqtt_config.enabled() {
+        let mqtt = match mqtt::Server::bind(logger.clone(), &cfg.mqtt_config, broker.clone()) {
+            Ok(mqtt) => mqtt,
+            Err(err) => {
+                crit!(logger, "Failed to start MQTT listener."; "error" => err.to_string());
+                return exitcode::UNAVAILABLE;
+            }
+        };
+        info!(logger, "Listening for MQTT connections."; "address" => cfg.mqtt_config.mqtt_listen_address.map(|address| address.to_string()));
+        thread::spawn(move || mqtt.serve());
+    }
+
+    if cfg.amqp_config.enabled() {
+        let amqp = match amqp::Server::bind(logger.clone(), &cfg.amqp_config, broker.clone()) {
+            Ok(amqp) => amqp,
+            Err(err) => {
+                crit!(logger, "Failed to start AMQP listener."; "error" => err.to_string());
+                return exitcode::UNAVAILABLE;
+            }
+        };
+        info!(logger, "Listening for AMQP connections."; "address" => cfg.amqp_config.amqp_listen_address.map(|address| address.to_string()));
+        thread::spawn(move || amqp.serve());
+    }
+
+    let server = match server::Server::bind(logger.clone(), &cfg.server_config, broker) {
+        Ok(server) => server,
+        Err(err) => {
+            crit!(logger, "Failed to start listener."; "error" => err.to_string());
+            return exitcode::UNAVAILABLE;
+        }
+    };
+    info!(logger, "Listening for connections."; "address" => cfg.server_config.listen_address.to_string());
+
+    server.serve()
 }
@@ -0,0 +1,48 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::time::Duration;
+
+use structopt::StructOpt;
+
+#[derive(Debug, Clone, StructOpt)]
+/// Rift scheduled message configuration.
+pub struct Config {
+    #[structopt(
+        long = "schedule-max-delay-ms",
+        env = "RIFT_SCHEDULE_MAX_DELAY_MS",
+        help = "The furthest into the future a message may be scheduled.",
+        long_help = "Sets the upper bound in milliseconds on how far past the time it is received a message may be scheduled for delivery.",
+        default_value = "604800000",
+        takes_value = true
+    )]
+    /// Define the maximum scheduling delay in milliseconds.
+    pub max_delay_ms: u64,
+
+    #[structopt(
+        long = "schedule-check-interval-ms",
+        env = "RIFT_SCHEDULE_CHECK_INTERVAL_MS",
+        help = "How often to release scheduled messages that are due.",
+        long_help = "Sets the interval in milliseconds at which scheduled messages that are due are appended to their partitions, which bounds how late they are delivered.",
+        default_value = "100",
+        takes_value = true
+    )]
+    /// Define the scheduled message check interval in milliseconds.
+    pub check_interval_ms: u64,
+}
+
+impl Config {
+    /// Returns the interval between checks for scheduled messages that are due.
+    pub fn check_interval(&self) -> Duration {
+        Duration::from_millis(self.check_interval_ms)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_delay_ms: 604800000,
+            check_interval_ms: 100,
+        }
+    }
+}
@@ -0,0 +1,36 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::result;
+
+use thiserror::Error;
+
+use crate::codec;
+use crate::topic;
+
+/// Custom Result wrapper to simplify usage.
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents errors scheduling or releasing delayed messages.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// Handles errors reading or writing the scheduled messages topic.
+    #[error(transparent)]
+    Topic(#[from] topic::Error),
+    /// Handles scheduled message records that could not be decoded.
+    #[error("failed to decode scheduled message at offset {offset}: {source}")]
+    Corrupt {
+        /// The offset of the scheduled message record that failed to decode.
+        offset: u64,
+        /// The initial error cause.
+        source: codec::Error,
+    },
+    /// Handles messages scheduled further into the future than allowed.
+    #[error("delivery time {deliver_at_ms} is more than {max_delay_ms}ms in the future")]
+    InvalidDeliveryTime {
+        /// The requested delivery time, in milliseconds since the epoch.
+        deliver_at_ms: i64,
+        /// The furthest into the future a message may be scheduled.
+        max_delay_ms: u64,
+    },
+}
@@ -0,0 +1,11 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod config;
+mod error;
+mod scheduler;
+mod state;
+
+pub use self::config::Config;
+pub use self::error::{Error, Result};
+pub use self::scheduler::{Scheduled, Scheduler, SCHEDULED_MESSAGES_TOPIC};
@@ -0,0 +1,332 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::record::Record;
+use crate::topic::{self, Partition, Topic, TopicConfig, TopicPartition, CLEANUP_POLICY};
+
+use super::config::Config;
+use super::error::{Error, Result};
+use super::state;
+
+/// The internal topic scheduled messages are stored in until they are released.
+pub const SCHEDULED_MESSAGES_TOPIC: &str = "__scheduled_messages";
+
+/// The number of bytes read at a time while replaying the scheduled messages topic.
+const REPLAY_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+/// A message waiting to be appended to a partition at its delivery time.
+pub struct Scheduled {
+    /// The id the message was assigned when it was scheduled.
+    pub id: u64,
+    /// When the message is delivered, in milliseconds since the epoch.
+    pub deliver_at_ms: i64,
+    /// The partition the message is appended to.
+    pub partition: TopicPartition,
+    /// The message itself.
+    pub record: Record,
+}
+
+/// Scheduled messages ordered by delivery time, then by the order they were
+/// scheduled in.
+struct Timers {
+    next_id: u64,
+    due: BTreeMap<(i64, u64), Scheduled>,
+    deliver_at: BTreeMap<u64, i64>,
+}
+
+impl Timers {
+    fn insert(&mut self, scheduled: Scheduled) {
+        self.next_id = self.next_id.max(scheduled.id + 1);
+        self.deliver_at
+            .insert(scheduled.id, scheduled.deliver_at_ms);
+        self.due
+            .insert((scheduled.deliver_at_ms, scheduled.id), scheduled);
+    }
+
+    fn remove(&mut self, id: u64) {
+        self.next_id = self.next_id.max(id + 1);
+        if let Some(deliver_at_ms) = self.deliver_at.remove(&id) {
+            self.due.remove(&(deliver_at_ms, id));
+        }
+    }
+}
+
+/// Holds messages back until their delivery time, so they only become visible
+/// to consumers once they are due.
+///
+/// Every scheduled message is appended to the scheduled messages topic and
+/// flushed before it is accepted, keyed by an id assigned in the order messages
+/// are scheduled. The timer index of pending messages, ordered by delivery time
+/// and then id, is rebuilt by replaying the topic on open, so messages survive
+/// restarts and are released in order. Once a released message has been
+/// appended to its partition a tombstone is written for it, which compaction
+/// uses to drop the message from the topic.
+pub struct Scheduler {
+    cfg: Config,
+    log: Arc<Topic>,
+    timers: Mutex<Timers>,
+}
+
+impl Scheduler {
+    /// Open the scheduler, creating the scheduled messages topic if it does not exist.
+    pub fn open(cfg: Config, topics: Arc<topic::Manager>) -> Result<Scheduler> {
+        let log = match topics.get(SCHEDULED_MESSAGES_TOPIC) {
+            Ok(log) => log,
+            Err(topic::Error::NotFound { .. }) => {
+                let mut config = TopicConfig::new();
+                config.set(CLEANUP_POLICY, "compact")?;
+                topics.create(SCHEDULED_MESSAGES_TOPIC, 1, config)?
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let timers = replay(log.partition(0)?)?;
+        Ok(Scheduler {
+            cfg,
+            log,
+            timers: Mutex::new(timers),
+        })
+    }
+
+    /// Returns the configuration of this scheduler.
+    pub fn config(&self) -> &Config {
+        &self.cfg
+    }
+
+    /// Returns the number of messages that have not been released yet.
+    pub fn pending(&self) -> usize {
+        self.timers().due.len()
+    }
+
+    /// Schedule messages for delivery to a partition at the paired time, in
+    /// milliseconds since the epoch, returning the scheduled messages. Either
+    /// every message is scheduled or, if any is due more than the configured
+    /// maximum delay after `now`, none are.
+    pub fn schedule(
+        &self,
+        partition: &TopicPartition,
+        messages: Vec<(i64, Record)>,
+        now: i64,
+    ) -> Result<Vec<Scheduled>> {
+        let latest_ms = now.saturating_add(self.cfg.max_delay_ms as i64);
+        if let Some((deliver_at_ms, _)) = messages.iter().find(|(at, _)| *at > latest_ms) {
+            return Err(Error::InvalidDeliveryTime {
+                deliver_at_ms: *deliver_at_ms,
+                max_delay_ms: self.cfg.max_delay_ms,
+            });
+        }
+
+        let mut timers = self.timers();
+        let scheduled: Vec<Scheduled> = messages
+            .into_iter()
+            .enumerate()
+            .map(|(idx, (deliver_at_ms, record))| Scheduled {
+                id: timers.next_id + idx as u64,
+                deliver_at_ms,
+                partition: partition.clone(),
+                record,
+            })
+            .collect();
+        if scheduled.is_empty() {
+            return Ok(scheduled);
+        }
+        let records: Vec<Record> = scheduled.iter().map(state::to_record).collect();
+        let log = self.log.partition(0)?;
+        log.append(&records)?;
+        log.flush()?;
+        for message in &scheduled {
+            timers.insert(message.clone());
+        }
+        Ok(scheduled)
+    }
+
+    /// Returns up to `max_messages` messages due for delivery as of `now`, in
+    /// the order they are to be delivered. They remain pending until marked
+    /// as released.
+    pub fn due(&self, now: i64, max_messages: usize) -> Vec<Scheduled> {
+        self.timers()
+            .due
+            .values()
+            .take_while(|scheduled| scheduled.deliver_at_ms <= now)
+            .take(max_messages)
+            .cloned()
+            .collect()
+    }
+
+    /// Mark messages as released once they have been appended to their
+    /// partition, so they are never delivered again.
+    pub fn released(&self, ids: &[u64]) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let mut timers = self.timers();
+        let tombstones: Vec<Record> = ids.iter().map(|id| state::tombstone(*id)).collect();
+        let log = self.log.partition(0)?;
+        log.append(&tombstones)?;
+        log.flush()?;
+        for id in ids {
+            timers.remove(*id);
+        }
+        Ok(())
+    }
+
+    fn timers(&self) -> MutexGuard<'_, Timers> {
+        self.timers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn replay(log: &Partition) -> Result<Timers> {
+    let mut timers = Timers {
+        next_id: 0,
+        due: BTreeMap::new(),
+        deliver_at: BTreeMap::new(),
+    };
+    let mut offset = log.start_offset();
+    while offset < log.next_offset() {
+        let records = log.read(offset, REPLAY_BYTES)?;
+        for record in &records {
+            let (id, scheduled) =
+                state::from_record(&record.record).map_err(|source| Error::Corrupt {
+                    offset: record.offset,
+                    source,
+                })?;
+            match scheduled {
+                Some(scheduled) => timers.insert(scheduled),
+                None => timers.remove(id),
+            }
+        }
+        offset = match records.last() {
+            Some(record) => record.offset + 1,
+            None => break,
+        };
+    }
+    Ok(timers)
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+    use crate::storage::LogConfig;
+
+    fn open(dir: &std::path::Path) -> Scheduler {
+        let topics = Arc::new(topic::Manager::open(dir, LogConfig::default()).unwrap());
+        Scheduler::open(Config::default(), topics).unwrap()
+    }
+
+    fn values(scheduled: &[Scheduled]) -> Vec<(i64, Vec<u8>)> {
+        scheduled
+            .iter()
+            .map(|s| (s.deliver_at_ms, s.record.value.clone().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_schedule_release() {
+        let dir = tempfile::tempdir().unwrap();
+        let scheduler = open(dir.path());
+        let tp = TopicPartition::new("events", 0);
+
+        let messages = vec![
+            (300, Record::new("c")),
+            (100, Record::new("a")),
+            (300, Record::new("d")),
+        ];
+        let scheduled = scheduler.schedule(&tp, messages, 0).unwrap();
+        assert_eq!(
+            vec![0, 1, 2],
+            scheduled.iter().map(|s| s.id).collect::<Vec<_>>()
+        );
+        scheduler
+            .schedule(&tp, vec![(200, Record::new("b"))], 0)
+            .unwrap();
+        assert_eq!(4, scheduler.pending());
+
+        // Messages are due in delivery time order, then in the order scheduled.
+        assert!(scheduler.due(99, 10).is_empty());
+        assert_eq!(vec![(100, b"a".to_vec())], values(&scheduler.due(100, 10)));
+        let due = scheduler.due(300, 10);
+        assert_eq!(
+            vec![
+                (100, b"a".to_vec()),
+                (200, b"b".to_vec()),
+                (300, b"c".to_vec()),
+                (300, b"d".to_vec())
+            ],
+            values(&due)
+        );
+        assert_eq!(2, scheduler.due(300, 2).len());
+
+        scheduler.released(&[due[0].id, due[1].id]).unwrap();
+        assert_eq!(
+            vec![(300, b"c".to_vec()), (300, b"d".to_vec())],
+            values(&scheduler.due(300, 10))
+        );
+    }
+
+    #[test]
+    fn test_survives_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let scheduler = open(dir.path());
+        let tp = TopicPartition::new("events", 1);
+        let scheduled = scheduler
+            .schedule(
+                &tp,
+                vec![(100, Record::new("a")), (200, Record::new("b"))],
+                0,
+            )
+            .unwrap();
+        scheduler.released(&[scheduled[1].id]).unwrap();
+        drop(scheduler);
+
+        let scheduler = open(dir.path());
+        assert_eq!(1, scheduler.pending());
+        let due = scheduler.due(1000, 10);
+        assert_eq!(vec![scheduled[0].clone()], due);
+
+        // Ids are never reused, even for released messages.
+        let next = scheduler
+            .schedule(&tp, vec![(300, Record::new("c"))], 0)
+            .unwrap();
+        assert_eq!(2, next[0].id);
+    }
+
+    #[test]
+    fn test_max_delay() {
+        let dir = tempfile::tempdir().unwrap();
+        let topics = Arc::new(topic::Manager::open(dir.path(), LogConfig::default()).unwrap());
+        let cfg = Config {
+            max_delay_ms: 1000,
+            check_interval_ms: 100,
+        };
+        let scheduler = Scheduler::open(cfg, topics).unwrap();
+        let tp = TopicPartition::new("events", 0);
+
+        let err = scheduler
+            .schedule(
+                &tp,
+                vec![(1500, Record::new("a")), (2001, Record::new("b"))],
+                1000,
+            )
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::InvalidDeliveryTime {
+                deliver_at_ms: 2001,
+                max_delay_ms: 1000
+            }
+        ));
+        assert_eq!(0, scheduler.pending());
+        scheduler
+            .schedule(&tp, vec![(2000, Record::new("a"))], 1000)
+            .unwrap();
+    }
+}
@@ -0,0 +1,158 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::codec::{self, Reader, Writer};
+use crate::record::Record;
+use crate::topic::TopicPartition;
+
+use super::scheduler::Scheduled;
+
+/// The current version of the scheduled message record key format.
+const KEY_VERSION: u16 = 0;
+/// The current version of the scheduled message record value format.
+const VALUE_VERSION: u16 = 1;
+/// The value format that predates records carrying a create timestamp.
+const VALUE_VERSION_V0: u16 = 0;
+
+/// Returns the record storing a scheduled message in the scheduled messages
+/// topic, keyed by its id so that the tombstone written once it is released
+/// compacts it away.
+pub(super) fn to_record(scheduled: &Scheduled) -> Record {
+    let mut value = Vec::new();
+    value.put_u16(VALUE_VERSION);
+    value.put_i64(scheduled.deliver_at_ms);
+    value.put_string(&scheduled.partition.topic);
+    value.put_u32(scheduled.partition.partition);
+    scheduled.record.encode(&mut value);
+    Record {
+        key: Some(key(scheduled.id)),
+        value: Some(value),
+        headers: Vec::new(),
+        timestamp: None,
+    }
+}
+
+/// Returns the tombstone marking the scheduled message with the supplied id as
+/// released.
+pub(super) fn tombstone(id: u64) -> Record {
+    Record {
+        key: Some(key(id)),
+        value: None,
+        headers: Vec::new(),
+        timestamp: None,
+    }
+}
+
+/// Decode a record of the scheduled messages topic into the id of the message
+/// it describes and, unless it is a tombstone, the message itself.
+pub(super) fn from_record(record: &Record) -> codec::Result<(u64, Option<Scheduled>)> {
+    let key = record.key.as_deref().ok_or(codec::Error::InvalidValue {
+        field: "scheduled message key",
+        value: -1,
+    })?;
+    let mut reader = Reader::new(key);
+    check_version(
+        reader.get_u16()?,
+        KEY_VERSION,
+        "scheduled message key version",
+    )?;
+    let id = reader.get_u64()?;
+
+    let value = match record.value.as_deref() {
+        Some(value) => value,
+        None => return Ok((id, None)),
+    };
+    let mut reader = Reader::new(value);
+    let version = reader.get_u16()?;
+    if version != VALUE_VERSION_V0 {
+        check_version(version, VALUE_VERSION, "scheduled message value version")?;
+    }
+    let scheduled = Scheduled {
+        id,
+        deliver_at_ms: reader.get_i64()?,
+        partition: TopicPartition {
+            topic: reader.get_string()?,
+            partition: reader.get_u32()?,
+        },
+        record: match version {
+            VALUE_VERSION_V0 => Record::decode_without_timestamp(&mut reader)?,
+            _ => Record::decode(&mut reader)?,
+        },
+    };
+    Ok((id, Some(scheduled)))
+}
+
+fn key(id: u64) -> Vec<u8> {
+    let mut key = Vec::new();
+    key.put_u16(KEY_VERSION);
+    key.put_u64(id);
+    key
+}
+
+fn check_version(version: u16, expected: u16, field: &'static str) -> codec::Result<()> {
+    if version != expected {
+        return Err(codec::Error::InvalidValue {
+            field,
+            value: version as i64,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let scheduled = Scheduled {
+            id: 7,
+            deliver_at_ms: 1234,
+            partition: TopicPartition::new("events", 2),
+            record: Record::new("payload")
+                .with_key("key")
+                .with_header("trace", "abc")
+                .with_timestamp(1000),
+        };
+        assert_eq!(
+            (7, Some(scheduled.clone())),
+            from_record(&to_record(&scheduled)).unwrap()
+        );
+        assert_eq!((7, None), from_record(&tombstone(7)).unwrap());
+    }
+
+    #[test]
+    fn test_v0_value() {
+        let mut value = Vec::new();
+        value.put_u16(VALUE_VERSION_V0);
+        value.put_i64(1234);
+        value.put_string("events");
+        value.put_u32(2);
+        value.put_optional_bytes(None);
+        value.put_optional_bytes(Some(b"payload"));
+        value.put_array(&[("trace", "abc")], |buf, (key, value)| {
+            buf.put_string(key);
+            buf.put_bytes(value.as_bytes());
+        });
+        let mut record = tombstone(7);
+        record.value = Some(value);
+
+        let (_, scheduled) = from_record(&record).unwrap();
+        assert_eq!(
+            Record::new("payload").with_header("trace", "abc"),
+            scheduled.unwrap().record
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(from_record(&Record::new("value")).is_err());
+
+        let record = Record::default().with_key(vec![0, 7]);
+        assert!(matches!(
+            from_record(&record),
+            Err(codec::Error::InvalidValue { value: 7, .. })
+        ));
+    }
+}
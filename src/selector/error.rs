@@ -0,0 +1,71 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::result;
+
+use thiserror::Error;
+
+/// Custom Result wrapper to simplify usage.
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents errors parsing a selector. Positions are the 1-based column,
+/// in characters, of the offending part of the selector.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum Error {
+    /// Handles characters that do not start any token.
+    #[error("unexpected character '{character}' at column {position}")]
+    UnexpectedCharacter {
+        /// The column of the character.
+        position: usize,
+        /// The offending character.
+        character: char,
+    },
+    /// Handles string literals or quoted identifiers missing their closing quote.
+    #[error("unterminated {what} starting at column {position}")]
+    Unterminated {
+        /// The column of the opening quote.
+        position: usize,
+        /// What was left unterminated.
+        what: &'static str,
+    },
+    /// Handles numeric literals that could not be parsed.
+    #[error("invalid number '{literal}' at column {position}")]
+    InvalidNumber {
+        /// The column the number starts at.
+        position: usize,
+        /// The offending literal.
+        literal: String,
+    },
+    /// Handles tokens that are not valid where they appear.
+    #[error("expected {expected} at column {position}, found {found}")]
+    UnexpectedToken {
+        /// The column of the token.
+        position: usize,
+        /// What was expected instead.
+        expected: &'static str,
+        /// A description of the token found.
+        found: String,
+    },
+    /// Handles expressions whose operands can never have the right type.
+    #[error("invalid expression at column {position}: {reason}")]
+    InvalidExpression {
+        /// The column the expression starts at.
+        position: usize,
+        /// Why the expression was rejected.
+        reason: String,
+    },
+    /// Handles selectors longer than allowed.
+    #[error("selector is {length} characters long, more than the maximum of {max_length}")]
+    TooLong {
+        /// The length of the selector in characters.
+        length: usize,
+        /// The longest selector allowed.
+        max_length: usize,
+    },
+    /// Handles selectors nesting expressions deeper than allowed.
+    #[error("selector nests expressions more than {max_depth} levels deep")]
+    TooDeep {
+        /// The deepest nesting allowed.
+        max_depth: usize,
+    },
+}
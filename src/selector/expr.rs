@@ -0,0 +1,382 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::cmp::Ordering;
+
+use crate::record::Record;
+
+/// A value a selector expression evaluates to. Header values and keys are
+/// strings, coerced to numbers or booleans when compared with one.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+}
+
+impl Value {
+    fn from_bytes(bytes: Option<&[u8]>) -> Value {
+        match bytes.map(std::str::from_utf8) {
+            Some(Ok(value)) => Value::String(value.to_owned()),
+            _ => Value::Null,
+        }
+    }
+
+    fn from_truth(truth: Option<bool>) -> Value {
+        truth.map_or(Value::Null, Value::Bool)
+    }
+
+    fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(value) => Some(*value),
+            Value::String(value) => value
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|value| value.is_finite()),
+            Value::Null | Value::Bool(_) => None,
+        }
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(value) => Some(*value),
+            Value::String(value) if value.eq_ignore_ascii_case("true") => Some(true),
+            Value::String(value) if value.eq_ignore_ascii_case("false") => Some(false),
+            _ => None,
+        }
+    }
+}
+
+/// A comparison between two values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    pub(super) fn is_ordering(&self) -> bool {
+        !matches!(self, Comparison::Eq | Comparison::Ne)
+    }
+
+    /// Compare two values, returning `None` when either is null or they can
+    /// not be compared. Booleans may only be tested for equality.
+    fn apply(&self, left: &Value, right: &Value) -> Option<bool> {
+        if matches!(left, Value::Bool(_)) || matches!(right, Value::Bool(_)) {
+            let equal = left.as_bool()? == right.as_bool()?;
+            return match self {
+                Comparison::Eq => Some(equal),
+                Comparison::Ne => Some(!equal),
+                _ => None,
+            };
+        }
+        let ordering = match (left, right) {
+            (Value::Null, _) | (_, Value::Null) => return None,
+            (Value::String(left), Value::String(right)) => left.cmp(right),
+            _ => left.as_number()?.partial_cmp(&right.as_number()?)?,
+        };
+        Some(match self {
+            Comparison::Eq => ordering == Ordering::Equal,
+            Comparison::Ne => ordering != Ordering::Equal,
+            Comparison::Lt => ordering == Ordering::Less,
+            Comparison::Le => ordering != Ordering::Greater,
+            Comparison::Gt => ordering == Ordering::Greater,
+            Comparison::Ge => ordering != Ordering::Less,
+        })
+    }
+}
+
+/// An arithmetic operator.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+/// A part of a `LIKE` pattern.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum PatternPart {
+    /// Matches exactly the character.
+    Char(char),
+    /// Matches any single character, written `_`.
+    One,
+    /// Matches any run of characters, including none, written `%`.
+    Many,
+}
+
+/// A parsed `LIKE` pattern.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Pattern(pub Vec<PatternPart>);
+
+impl Pattern {
+    fn matches(&self, text: &str) -> bool {
+        let parts = &self.0;
+        let text: Vec<char> = text.chars().collect();
+        let (mut part, mut idx) = (0, 0);
+        // Where matching resumes should the characters after the last `%` not match.
+        let mut backtrack = None;
+        while idx < text.len() {
+            match parts.get(part) {
+                Some(PatternPart::Many) => {
+                    backtrack = Some((part, idx));
+                    part += 1;
+                    continue;
+                }
+                Some(PatternPart::One) => {
+                    part += 1;
+                    idx += 1;
+                    continue;
+                }
+                Some(PatternPart::Char(c)) if *c == text[idx] => {
+                    part += 1;
+                    idx += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            match backtrack {
+                Some((many, start)) => {
+                    part = many + 1;
+                    idx = start + 1;
+                    backtrack = Some((many, start + 1));
+                }
+                None => return false,
+            }
+        }
+        parts[part..].iter().all(|part| *part == PatternPart::Many)
+    }
+}
+
+/// A parsed selector expression.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Expr {
+    Literal(Value),
+    Key,
+    Header(String),
+    Not(Box<Expr>),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+    Compare(Comparison, Box<Expr>, Box<Expr>),
+    Arithmetic(Operator, Box<Expr>, Box<Expr>),
+    Negate(Box<Expr>),
+    Between {
+        expr: Box<Expr>,
+        low: Box<Expr>,
+        high: Box<Expr>,
+        negated: bool,
+    },
+    In {
+        expr: Box<Expr>,
+        values: Vec<Value>,
+        negated: bool,
+    },
+    Like {
+        expr: Box<Expr>,
+        pattern: Pattern,
+        negated: bool,
+    },
+    IsNull {
+        expr: Box<Expr>,
+        negated: bool,
+    },
+}
+
+impl Expr {
+    /// Returns whether the expression holds for the supplied record, which is
+    /// only the case if it evaluates to true rather than false or unknown.
+    pub(super) fn test(&self, record: &Record) -> bool {
+        self.eval(record).as_bool() == Some(true)
+    }
+
+    /// Evaluate the expression against a record using SQL's three valued
+    /// logic, in which conditions over null or mismatched values are unknown,
+    /// represented by [Value::Null].
+    fn eval(&self, record: &Record) -> Value {
+        match self {
+            Expr::Literal(value) => value.clone(),
+            Expr::Key => Value::from_bytes(record.key.as_deref()),
+            Expr::Header(name) => Value::from_bytes(record.header(name)),
+            Expr::Not(expr) => Value::from_truth(expr.eval(record).as_bool().map(|truth| !truth)),
+            Expr::And(exprs) => {
+                let mut unknown = false;
+                for expr in exprs {
+                    match expr.eval(record).as_bool() {
+                        Some(false) => return Value::Bool(false),
+                        Some(true) => {}
+                        None => unknown = true,
+                    }
+                }
+                Value::from_truth((!unknown).then_some(true))
+            }
+            Expr::Or(exprs) => {
+                let mut unknown = false;
+                for expr in exprs {
+                    match expr.eval(record).as_bool() {
+                        Some(true) => return Value::Bool(true),
+                        Some(false) => {}
+                        None => unknown = true,
+                    }
+                }
+                Value::from_truth((!unknown).then_some(false))
+            }
+            Expr::Compare(comparison, left, right) => {
+                Value::from_truth(comparison.apply(&left.eval(record), &right.eval(record)))
+            }
+            Expr::Arithmetic(operator, left, right) => {
+                let (left, right) = match (
+                    left.eval(record).as_number(),
+                    right.eval(record).as_number(),
+                ) {
+                    (Some(left), Some(right)) => (left, right),
+                    _ => return Value::Null,
+                };
+                let value = match operator {
+                    Operator::Add => left + right,
+                    Operator::Subtract => left - right,
+                    Operator::Multiply => left * right,
+                    Operator::Divide => left / right,
+                };
+                if value.is_finite() {
+                    Value::Number(value)
+                } else {
+                    Value::Null
+                }
+            }
+            Expr::Negate(expr) => expr
+                .eval(record)
+                .as_number()
+                .map_or(Value::Null, |value| Value::Number(-value)),
+            Expr::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => {
+                let between = (|| {
+                    let value = expr.eval(record).as_number()?;
+                    let low = low.eval(record).as_number()?;
+                    let high = high.eval(record).as_number()?;
+                    Some(low <= value && value <= high)
+                })();
+                Value::from_truth(between.map(|between| between != *negated))
+            }
+            Expr::In {
+                expr,
+                values,
+                negated,
+            } => {
+                let value = expr.eval(record);
+                let mut found = Some(false);
+                for candidate in values {
+                    match Comparison::Eq.apply(&value, candidate) {
+                        Some(true) => {
+                            found = Some(true);
+                            break;
+                        }
+                        Some(false) => {}
+                        None => found = None,
+                    }
+                }
+                Value::from_truth(found.map(|found| found != *negated))
+            }
+            Expr::Like {
+                expr,
+                pattern,
+                negated,
+            } => match expr.eval(record) {
+                Value::String(value) => Value::Bool(pattern.matches(&value) != *negated),
+                _ => Value::Null,
+            },
+            Expr::IsNull { expr, negated } => {
+                Value::Bool((expr.eval(record) == Value::Null) != *negated)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    fn pattern(source: &str) -> Pattern {
+        Pattern(
+            source
+                .chars()
+                .map(|c| match c {
+                    '%' => PatternPart::Many,
+                    '_' => PatternPart::One,
+                    c => PatternPart::Char(c),
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_like() {
+        let matching = [
+            ("", ""),
+            ("%", ""),
+            ("%", "anything"),
+            ("eu-%", "eu-west"),
+            ("%west", "eu-west"),
+            ("e_-%t", "eu-west"),
+            ("%-%-%", "a-b-c"),
+            ("%aab", "aaab"),
+        ];
+        for (source, text) in matching {
+            assert!(pattern(source).matches(text), "{} LIKE {}", text, source);
+        }
+        let mismatching = [
+            ("", "a"),
+            ("_", ""),
+            ("eu-%", "us-west"),
+            ("%west", "eu-westish"),
+            ("e_", "eu-west"),
+            ("%-%-%", "a-b"),
+        ];
+        for (source, text) in mismatching {
+            assert!(
+                !pattern(source).matches(text),
+                "{} NOT LIKE {}",
+                text,
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        let string = |value: &str| Value::String(value.to_owned());
+        assert_eq!(
+            Some(true),
+            Comparison::Gt.apply(&string("10"), &Value::Number(9.0))
+        );
+        // Two strings are compared as strings.
+        assert_eq!(
+            Some(false),
+            Comparison::Gt.apply(&string("10"), &string("9"))
+        );
+        assert_eq!(
+            Some(true),
+            Comparison::Eq.apply(&string("TRUE"), &Value::Bool(true))
+        );
+        assert_eq!(
+            None,
+            Comparison::Lt.apply(&Value::Bool(false), &Value::Bool(true))
+        );
+        assert_eq!(
+            None,
+            Comparison::Eq.apply(&string("eu"), &Value::Number(1.0))
+        );
+        assert_eq!(None, Comparison::Eq.apply(&Value::Null, &Value::Null));
+    }
+}
@@ -0,0 +1,331 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fmt;
+
+use super::error::{Error, Result};
+
+/// A single lexical element of a selector.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Token {
+    Identifier(String),
+    String(String),
+    Number(f64),
+    And,
+    Or,
+    Not,
+    Between,
+    In,
+    Like,
+    Escape,
+    Is,
+    Null,
+    True,
+    False,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    LeftParen,
+    RightParen,
+    Comma,
+    End,
+}
+
+impl Token {
+    fn keyword(word: &str) -> Option<Token> {
+        let token = match word.to_ascii_uppercase().as_str() {
+            "AND" => Token::And,
+            "OR" => Token::Or,
+            "NOT" => Token::Not,
+            "BETWEEN" => Token::Between,
+            "IN" => Token::In,
+            "LIKE" => Token::Like,
+            "ESCAPE" => Token::Escape,
+            "IS" => Token::Is,
+            "NULL" => Token::Null,
+            "TRUE" => Token::True,
+            "FALSE" => Token::False,
+            _ => return None,
+        };
+        Some(token)
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            Token::Identifier(name) => return write!(f, "identifier '{}'", name),
+            Token::String(value) => return write!(f, "string '{}'", value),
+            Token::Number(value) => return write!(f, "number {}", value),
+            Token::End => return write!(f, "the end of the selector"),
+            Token::And => "AND",
+            Token::Or => "OR",
+            Token::Not => "NOT",
+            Token::Between => "BETWEEN",
+            Token::In => "IN",
+            Token::Like => "LIKE",
+            Token::Escape => "ESCAPE",
+            Token::Is => "IS",
+            Token::Null => "NULL",
+            Token::True => "TRUE",
+            Token::False => "FALSE",
+            Token::Eq => "=",
+            Token::Ne => "<>",
+            Token::Lt => "<",
+            Token::Le => "<=",
+            Token::Gt => ">",
+            Token::Ge => ">=",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Star => "*",
+            Token::Slash => "/",
+            Token::LeftParen => "(",
+            Token::RightParen => ")",
+            Token::Comma => ",",
+        };
+        write!(f, "'{}'", symbol)
+    }
+}
+
+/// A token along with the 1-based column it starts at.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Spanned {
+    pub token: Token,
+    pub position: usize,
+}
+
+/// Split a selector into tokens, always ending with [Token::End].
+pub(super) fn tokenize(source: &str) -> Result<Vec<Spanned>> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut idx = 0;
+    while idx < chars.len() {
+        let start = idx;
+        let position = start + 1;
+        let c = chars[idx];
+        let token = match c {
+            c if c.is_whitespace() => {
+                idx += 1;
+                continue;
+            }
+            '\'' => {
+                let (value, end) = quoted(&chars, start, '\'', "string")?;
+                idx = end;
+                Token::String(value)
+            }
+            '"' => {
+                let (name, end) = quoted(&chars, start, '"', "quoted identifier")?;
+                idx = end;
+                Token::Identifier(name)
+            }
+            c if c.is_ascii_digit() => {
+                idx = number_end(&chars, start);
+                while idx < chars.len() && is_identifier_char(chars[idx]) {
+                    idx += 1;
+                }
+                let literal: String = chars[start..idx].iter().collect();
+                match literal.parse::<f64>() {
+                    Ok(value) if value.is_finite() => Token::Number(value),
+                    _ => return Err(Error::InvalidNumber { position, literal }),
+                }
+            }
+            c if is_identifier_start(c) => {
+                while idx < chars.len() && is_identifier_char(chars[idx]) {
+                    idx += 1;
+                }
+                let word: String = chars[start..idx].iter().collect();
+                Token::keyword(&word).unwrap_or(Token::Identifier(word))
+            }
+            _ => {
+                let next = chars.get(idx + 1).copied();
+                let (token, len) = match (c, next) {
+                    ('<', Some('>')) | ('!', Some('=')) => (Token::Ne, 2),
+                    ('<', Some('=')) => (Token::Le, 2),
+                    ('>', Some('=')) => (Token::Ge, 2),
+                    ('=', _) => (Token::Eq, 1),
+                    ('<', _) => (Token::Lt, 1),
+                    ('>', _) => (Token::Gt, 1),
+                    ('+', _) => (Token::Plus, 1),
+                    ('-', _) => (Token::Minus, 1),
+                    ('*', _) => (Token::Star, 1),
+                    ('/', _) => (Token::Slash, 1),
+                    ('(', _) => (Token::LeftParen, 1),
+                    (')', _) => (Token::RightParen, 1),
+                    (',', _) => (Token::Comma, 1),
+                    _ => {
+                        return Err(Error::UnexpectedCharacter {
+                            position,
+                            character: c,
+                        })
+                    }
+                };
+                idx += len;
+                token
+            }
+        };
+        tokens.push(Spanned { token, position });
+    }
+    tokens.push(Spanned {
+        token: Token::End,
+        position: chars.len() + 1,
+    });
+    Ok(tokens)
+}
+
+/// Read a literal enclosed in the supplied quote, in which a doubled quote
+/// stands for the quote itself, returning it and the index following it.
+fn quoted(
+    chars: &[char],
+    start: usize,
+    quote: char,
+    what: &'static str,
+) -> Result<(String, usize)> {
+    let mut value = String::new();
+    let mut idx = start + 1;
+    loop {
+        match chars.get(idx) {
+            None => {
+                return Err(Error::Unterminated {
+                    position: start + 1,
+                    what,
+                })
+            }
+            Some(&c) if c == quote => {
+                if chars.get(idx + 1) == Some(&quote) {
+                    value.push(quote);
+                    idx += 2;
+                } else {
+                    return Ok((value, idx + 1));
+                }
+            }
+            Some(&c) => {
+                value.push(c);
+                idx += 1;
+            }
+        }
+    }
+}
+
+/// Returns the index following the digits, fraction, and exponent of the
+/// number starting at the supplied index.
+fn number_end(chars: &[char], start: usize) -> usize {
+    let digits = |mut idx: usize| {
+        while idx < chars.len() && chars[idx].is_ascii_digit() {
+            idx += 1;
+        }
+        idx
+    };
+    let is_digit = |idx: usize| chars.get(idx).is_some_and(|c| c.is_ascii_digit());
+
+    let mut idx = digits(start);
+    if chars.get(idx) == Some(&'.') && is_digit(idx + 1) {
+        idx = digits(idx + 1);
+    }
+    if matches!(chars.get(idx), Some('e' | 'E')) {
+        let sign = usize::from(matches!(chars.get(idx + 1), Some('+' | '-')));
+        if is_digit(idx + 1 + sign) {
+            idx = digits(idx + 1 + sign);
+        }
+    }
+    idx
+}
+
+fn is_identifier_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_' || c == '$'
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '$' | '.')
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<Token> {
+        tokenize(source)
+            .unwrap()
+            .into_iter()
+            .map(|spanned| spanned.token)
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            vec![
+                Token::Identifier(String::from("region")),
+                Token::Eq,
+                Token::String(String::from("it's")),
+                Token::And,
+                Token::Identifier(String::from("rift.priority")),
+                Token::Ge,
+                Token::Number(3.5),
+                Token::Or,
+                Token::Not,
+                Token::Identifier(String::from("x-match")),
+                Token::Ne,
+                Token::Number(1e3),
+                Token::End,
+            ],
+            tokens("region = 'it''s' and rift.priority>=3.5 OR NOT \"x-match\" != 1e3")
+        );
+        assert_eq!(
+            vec![
+                Token::LeftParen,
+                Token::Identifier(String::from("$key")),
+                Token::Minus,
+                Token::Number(1.0),
+                Token::RightParen,
+                Token::Ne,
+                Token::Number(2.0),
+                Token::End
+            ],
+            tokens("($key-1)<>2")
+        );
+
+        let spanned = tokenize("a  = 'b'").unwrap();
+        let positions: Vec<usize> = spanned.iter().map(|spanned| spanned.position).collect();
+        assert_eq!(vec![1, 4, 6, 9], positions);
+    }
+
+    #[test]
+    fn test_tokenize_errors() {
+        assert_eq!(
+            Err(Error::Unterminated {
+                position: 10,
+                what: "string"
+            }),
+            tokenize("region = 'eu")
+        );
+        assert_eq!(
+            Err(Error::Unterminated {
+                position: 1,
+                what: "quoted identifier"
+            }),
+            tokenize("\"region = 'eu'")
+        );
+        assert_eq!(
+            Err(Error::InvalidNumber {
+                position: 12,
+                literal: String::from("3abc"),
+            }),
+            tokenize("priority > 3abc")
+        );
+        assert_eq!(
+            Err(Error::UnexpectedCharacter {
+                position: 8,
+                character: ';',
+            }),
+            tokenize("a = 'b';")
+        );
+    }
+}
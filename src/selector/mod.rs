@@ -0,0 +1,12 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod error;
+mod expr;
+mod lexer;
+mod parser;
+#[allow(clippy::module_inception)]
+mod selector;
+
+pub use self::error::{Error, Result};
+pub use self::selector::{Selector, KEY_IDENTIFIER, MAX_DEPTH, MAX_LENGTH};
@@ -0,0 +1,628 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use super::error::{Error, Result};
+use super::expr::{Comparison, Expr, Operator, Pattern, PatternPart, Value};
+use super::lexer::{self, Spanned, Token};
+use super::selector::{KEY_IDENTIFIER, MAX_DEPTH, MAX_LENGTH};
+
+/// The type an expression is known to have before it is evaluated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Bool,
+    Number,
+    String,
+    /// Keys, headers, and nulls, whose type is only known once evaluated.
+    Any,
+}
+
+impl Kind {
+    fn name(&self) -> &'static str {
+        match self {
+            Kind::Bool => "condition",
+            Kind::Number => "number",
+            Kind::String => "string",
+            Kind::Any => "value",
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!("a {}", self.name())
+    }
+}
+
+/// An expression along with its type and the column it starts at.
+struct Typed {
+    expr: Expr,
+    kind: Kind,
+    position: usize,
+}
+
+impl Typed {
+    fn new(expr: Expr, kind: Kind, position: usize) -> Typed {
+        Typed {
+            expr,
+            kind,
+            position,
+        }
+    }
+
+    /// Ensure the expression can be used where a condition is expected.
+    fn condition(self) -> Result<Expr> {
+        match self.kind {
+            Kind::Bool | Kind::Any => Ok(self.expr),
+            kind => Err(invalid(
+                self.position,
+                format!("expected a condition, found {}", kind.describe()),
+            )),
+        }
+    }
+
+    /// Ensure the expression can be used as an operand of the supplied
+    /// numeric operator.
+    fn number(self, operator: &Token) -> Result<Expr> {
+        match self.kind {
+            Kind::Number | Kind::Any => Ok(self.expr),
+            kind => Err(invalid(
+                self.position,
+                format!("{} requires numbers, found {}", operator, kind.describe()),
+            )),
+        }
+    }
+}
+
+/// Parse a selector into the expression it evaluates, rejecting expressions
+/// that could never hold because their operands have the wrong type.
+pub(super) fn parse(source: &str) -> Result<Expr> {
+    let length = source.chars().count();
+    if length > MAX_LENGTH {
+        return Err(Error::TooLong {
+            length,
+            max_length: MAX_LENGTH,
+        });
+    }
+    let mut parser = Parser {
+        tokens: lexer::tokenize(source)?,
+        next: 0,
+        depth: 0,
+    };
+    let expr = parser.or()?.condition()?;
+    parser.expect(Token::End, "an operator or the end of the selector")?;
+    Ok(expr)
+}
+
+struct Parser {
+    tokens: Vec<Spanned>,
+    next: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.next].token
+    }
+
+    fn position(&self) -> usize {
+        self.tokens[self.next].position
+    }
+
+    fn advance(&mut self) -> Spanned {
+        let spanned = self.tokens[self.next].clone();
+        if spanned.token != Token::End {
+            self.next += 1;
+        }
+        spanned
+    }
+
+    fn consume(&mut self, token: &Token) -> bool {
+        if self.peek() == token {
+            self.advance();
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, token: Token, expected: &'static str) -> Result<()> {
+        if self.consume(&token) {
+            return Ok(());
+        }
+        Err(self.unexpected(expected))
+    }
+
+    fn unexpected(&self, expected: &'static str) -> Error {
+        Error::UnexpectedToken {
+            position: self.position(),
+            expected,
+            found: self.peek().to_string(),
+        }
+    }
+
+    /// Track entering a nested expression, bounding how deep the parser and
+    /// evaluation recurse.
+    fn enter(&mut self) -> Result<()> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(Error::TooDeep {
+                max_depth: MAX_DEPTH,
+            });
+        }
+        Ok(())
+    }
+
+    fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    fn or(&mut self) -> Result<Typed> {
+        let first = self.and()?;
+        if self.peek() != &Token::Or {
+            return Ok(first);
+        }
+        let position = first.position;
+        let mut exprs = vec![first.condition()?];
+        while self.consume(&Token::Or) {
+            exprs.push(self.and()?.condition()?);
+        }
+        Ok(Typed::new(Expr::Or(exprs), Kind::Bool, position))
+    }
+
+    fn and(&mut self) -> Result<Typed> {
+        let first = self.not()?;
+        if self.peek() != &Token::And {
+            return Ok(first);
+        }
+        let position = first.position;
+        let mut exprs = vec![first.condition()?];
+        while self.consume(&Token::And) {
+            exprs.push(self.not()?.condition()?);
+        }
+        Ok(Typed::new(Expr::And(exprs), Kind::Bool, position))
+    }
+
+    fn not(&mut self) -> Result<Typed> {
+        let position = self.position();
+        if !self.consume(&Token::Not) {
+            return self.comparison();
+        }
+        self.enter()?;
+        let expr = self.not()?.condition()?;
+        self.leave();
+        Ok(Typed::new(Expr::Not(Box::new(expr)), Kind::Bool, position))
+    }
+
+    fn comparison(&mut self) -> Result<Typed> {
+        let left = self.additive()?;
+        let position = left.position;
+        let comparison = match self.peek() {
+            Token::Eq => Comparison::Eq,
+            Token::Ne => Comparison::Ne,
+            Token::Lt => Comparison::Lt,
+            Token::Le => Comparison::Le,
+            Token::Gt => Comparison::Gt,
+            Token::Ge => Comparison::Ge,
+            Token::Not | Token::Between | Token::In | Token::Like | Token::Is => {
+                return self.predicate(left)
+            }
+            _ => return Ok(left),
+        };
+        let operator = self.advance().token;
+        let right = self.additive()?;
+        check_comparable(&operator, comparison, &left, &right)?;
+        let expr = Expr::Compare(comparison, Box::new(left.expr), Box::new(right.expr));
+        Ok(Typed::new(expr, Kind::Bool, position))
+    }
+
+    /// Parse the `BETWEEN`, `IN`, `LIKE`, or `IS NULL` predicate following the
+    /// supplied operand.
+    fn predicate(&mut self, left: Typed) -> Result<Typed> {
+        let position = left.position;
+        if self.consume(&Token::Is) {
+            let negated = self.consume(&Token::Not);
+            self.expect(Token::Null, "NULL")?;
+            let expr = Expr::IsNull {
+                expr: Box::new(left.expr),
+                negated,
+            };
+            return Ok(Typed::new(expr, Kind::Bool, position));
+        }
+
+        let negated = self.consume(&Token::Not);
+        if !matches!(self.peek(), Token::Between | Token::In | Token::Like) {
+            return Err(self.unexpected("BETWEEN, IN, or LIKE"));
+        }
+        let operator = self.advance();
+        let expr = match operator.token {
+            Token::Between => {
+                let expr = left.number(&operator.token)?;
+                let low = self.additive()?.number(&operator.token)?;
+                self.expect(Token::And, "AND")?;
+                let high = self.additive()?.number(&operator.token)?;
+                Expr::Between {
+                    expr: Box::new(expr),
+                    low: Box::new(low),
+                    high: Box::new(high),
+                    negated,
+                }
+            }
+            Token::In => {
+                let values = self.values(&left)?;
+                Expr::In {
+                    expr: Box::new(left.expr),
+                    values,
+                    negated,
+                }
+            }
+            Token::Like => {
+                if !matches!(left.kind, Kind::String | Kind::Any) {
+                    return Err(invalid(
+                        position,
+                        format!("LIKE requires a string, found {}", left.kind.describe()),
+                    ));
+                }
+                let pattern = self.pattern()?;
+                Expr::Like {
+                    expr: Box::new(left.expr),
+                    pattern,
+                    negated,
+                }
+            }
+            _ => unreachable!("checked above"),
+        };
+        Ok(Typed::new(expr, Kind::Bool, position))
+    }
+
+    /// Parse the parenthesized list of literals of an `IN` predicate, which
+    /// must all be strings or all be numbers comparable with the operand.
+    fn values(&mut self, left: &Typed) -> Result<Vec<Value>> {
+        self.expect(Token::LeftParen, "'('")?;
+        let mut values = Vec::new();
+        let mut kind = None;
+        loop {
+            let position = self.position();
+            let negative = self.consume(&Token::Minus);
+            let (value, value_kind) = match (self.peek().clone(), negative) {
+                (Token::String(value), false) => (Value::String(value), Kind::String),
+                (Token::Number(value), negative) => {
+                    let value = if negative { -value } else { value };
+                    (Value::Number(value), Kind::Number)
+                }
+                _ => return Err(self.unexpected("a string or number literal")),
+            };
+            self.advance();
+            if let Some(kind) = kind.filter(|kind: &Kind| *kind != value_kind) {
+                return Err(invalid(
+                    position,
+                    format!(
+                        "IN lists must not mix strings and numbers, found {} after {}",
+                        value_kind.describe(),
+                        kind.describe()
+                    ),
+                ));
+            }
+            kind = Some(value_kind);
+            values.push(value);
+            if !self.consume(&Token::Comma) {
+                break;
+            }
+        }
+        self.expect(Token::RightParen, "',' or ')'")?;
+        if !matches!(left.kind, Kind::Any) && Some(left.kind) != kind {
+            return Err(invalid(
+                left.position,
+                format!(
+                    "can not compare {} with a list of {}s",
+                    left.kind.describe(),
+                    kind.map_or("value", |kind| kind.name())
+                ),
+            ));
+        }
+        Ok(values)
+    }
+
+    /// Parse the string literal pattern of a `LIKE` predicate, along with its
+    /// optional `ESCAPE` character.
+    fn pattern(&mut self) -> Result<Pattern> {
+        let position = self.position();
+        let source = match self.peek().clone() {
+            Token::String(source) => source,
+            _ => return Err(self.unexpected("a string pattern")),
+        };
+        self.advance();
+
+        let mut escape = None;
+        if self.consume(&Token::Escape) {
+            let escape_position = self.position();
+            match self.peek().clone() {
+                Token::String(value) if value.chars().count() == 1 => {
+                    escape = value.chars().next();
+                    self.advance();
+                }
+                Token::String(_) => {
+                    return Err(invalid(
+                        escape_position,
+                        String::from("the ESCAPE character must be a single character"),
+                    ))
+                }
+                _ => return Err(self.unexpected("a string escape character")),
+            }
+        }
+
+        let mut parts = Vec::new();
+        let mut chars = source.chars();
+        while let Some(c) = chars.next() {
+            let part = match c {
+                c if Some(c) == escape => match chars.next() {
+                    Some(next) if next == '%' || next == '_' || Some(next) == escape => {
+                        PatternPart::Char(next)
+                    }
+                    _ => {
+                        return Err(invalid(
+                            position,
+                            String::from(
+                                "the ESCAPE character must be followed by '%', '_', or itself",
+                            ),
+                        ))
+                    }
+                },
+                '%' => PatternPart::Many,
+                '_' => PatternPart::One,
+                c => PatternPart::Char(c),
+            };
+            parts.push(part);
+        }
+        Ok(Pattern(parts))
+    }
+
+    fn additive(&mut self) -> Result<Typed> {
+        let left = self.multiplicative()?;
+        self.arithmetic(left, Parser::multiplicative, |token| match token {
+            Token::Plus => Some(Operator::Add),
+            Token::Minus => Some(Operator::Subtract),
+            _ => None,
+        })
+    }
+
+    fn multiplicative(&mut self) -> Result<Typed> {
+        let left = self.unary()?;
+        self.arithmetic(left, Parser::unary, |token| match token {
+            Token::Star => Some(Operator::Multiply),
+            Token::Slash => Some(Operator::Divide),
+            _ => None,
+        })
+    }
+
+    /// Parse a chain of the left associative arithmetic operators recognized by
+    /// `operator`, reading each right operand with `operand`. Every operator
+    /// nests the expression one level deeper.
+    fn arithmetic(
+        &mut self,
+        mut left: Typed,
+        operand: fn(&mut Parser) -> Result<Typed>,
+        operator: fn(&Token) -> Option<Operator>,
+    ) -> Result<Typed> {
+        let depth = self.depth;
+        while let Some(operator) = operator(self.peek()) {
+            let token = self.advance().token;
+            self.enter()?;
+            let right = operand(self)?;
+            let position = left.position;
+            left = Typed::new(
+                Expr::Arithmetic(
+                    operator,
+                    Box::new(left.number(&token)?),
+                    Box::new(right.number(&token)?),
+                ),
+                Kind::Number,
+                position,
+            );
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Typed> {
+        let position = self.position();
+        let negate = match self.peek() {
+            Token::Minus => true,
+            Token::Plus => false,
+            _ => return self.primary(),
+        };
+        let token = self.advance().token;
+        self.enter()?;
+        let operand = self.unary()?.number(&token)?;
+        self.leave();
+        let expr = match (negate, operand) {
+            (true, Expr::Literal(Value::Number(value))) => Expr::Literal(Value::Number(-value)),
+            (true, operand) => Expr::Negate(Box::new(operand)),
+            (false, operand) => operand,
+        };
+        Ok(Typed::new(expr, Kind::Number, position))
+    }
+
+    fn primary(&mut self) -> Result<Typed> {
+        let position = self.position();
+        let (expr, kind) = match self.peek().clone() {
+            Token::Number(value) => (Expr::Literal(Value::Number(value)), Kind::Number),
+            Token::String(value) => (Expr::Literal(Value::String(value)), Kind::String),
+            Token::True => (Expr::Literal(Value::Bool(true)), Kind::Bool),
+            Token::False => (Expr::Literal(Value::Bool(false)), Kind::Bool),
+            Token::Null => (Expr::Literal(Value::Null), Kind::Any),
+            Token::Identifier(name) if name == KEY_IDENTIFIER => (Expr::Key, Kind::Any),
+            Token::Identifier(name) => (Expr::Header(name), Kind::Any),
+            Token::LeftParen => {
+                self.advance();
+                self.enter()?;
+                let inner = self.or()?;
+                self.leave();
+                self.expect(Token::RightParen, "an operator or ')'")?;
+                return Ok(Typed::new(inner.expr, inner.kind, position));
+            }
+            _ => return Err(self.unexpected("a value")),
+        };
+        self.advance();
+        Ok(Typed::new(expr, kind, position))
+    }
+}
+
+/// Ensure two operands can be compared with the supplied operator.
+fn check_comparable(
+    operator: &Token,
+    comparison: Comparison,
+    left: &Typed,
+    right: &Typed,
+) -> Result<()> {
+    for operand in [left, right] {
+        if comparison.is_ordering() && operand.kind == Kind::Bool {
+            return Err(invalid(
+                operand.position,
+                format!("{} can not order conditions", operator),
+            ));
+        }
+    }
+    if left.kind != Kind::Any && right.kind != Kind::Any && left.kind != right.kind {
+        return Err(invalid(
+            left.position,
+            format!(
+                "can not compare {} with {}",
+                left.kind.describe(),
+                right.kind.describe()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn invalid(position: usize, reason: String) -> Error {
+    Error::InvalidExpression { position, reason }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    fn message(source: &str) -> String {
+        parse(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Expr::Or(vec![
+                Expr::And(vec![
+                    Expr::Compare(
+                        Comparison::Eq,
+                        Box::new(Expr::Header(String::from("region"))),
+                        Box::new(Expr::Literal(Value::String(String::from("eu")))),
+                    ),
+                    Expr::Compare(
+                        Comparison::Gt,
+                        Box::new(Expr::Header(String::from("priority"))),
+                        Box::new(Expr::Literal(Value::Number(3.0))),
+                    ),
+                ]),
+                Expr::Not(Box::new(Expr::IsNull {
+                    expr: Box::new(Expr::Key),
+                    negated: true,
+                })),
+            ]),
+            parse("region = 'eu' AND priority > 3 OR NOT $key IS NOT NULL").unwrap()
+        );
+        assert_eq!(
+            Expr::In {
+                expr: Box::new(Expr::Header(String::from("n"))),
+                values: vec![Value::Number(-1.0), Value::Number(2.0)],
+                negated: true,
+            },
+            parse("n NOT IN (-1, 2)").unwrap()
+        );
+        assert_eq!(
+            Expr::Like {
+                expr: Box::new(Expr::Header(String::from("path"))),
+                pattern: Pattern(vec![
+                    PatternPart::Char('%'),
+                    PatternPart::Many,
+                    PatternPart::One,
+                ]),
+                negated: false,
+            },
+            parse("path LIKE '!%%_' ESCAPE '!'").unwrap()
+        );
+        parse("(a + 1) * -b BETWEEN 1 AND 10 AND flag").unwrap();
+        parse("flag = TRUE OR x <> NULL").unwrap();
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            "expected a value at column 10, found the end of the selector",
+            message("region = ")
+        );
+        assert_eq!(
+            "expected an operator or the end of the selector at column 15, found identifier 'priority'",
+            message("region = 'eu' priority > 3")
+        );
+        assert_eq!(
+            "expected an operator or ')' at column 12, found the end of the selector",
+            message("(region = 1")
+        );
+        assert_eq!(
+            "invalid expression at column 1: can not compare a string with a number",
+            message("'eu' = 1")
+        );
+        assert_eq!(
+            "invalid expression at column 1: expected a condition, found a number",
+            message("1 + priority")
+        );
+        assert_eq!(
+            "invalid expression at column 11: expected a condition, found a string",
+            message("a = 1 AND (('b'))")
+        );
+        assert_eq!(
+            "invalid expression at column 5: '+' requires numbers, found a string",
+            message("a = 'x' + 1")
+        );
+        assert_eq!(
+            "invalid expression at column 1: '<' can not order conditions",
+            message("TRUE < a")
+        );
+        assert_eq!(
+            "invalid expression at column 1: can not compare a number with a list of strings",
+            message("1 IN ('a')")
+        );
+        assert_eq!(
+            "invalid expression at column 18: IN lists must not mix strings and numbers, found a number after a string",
+            message("region IN ('eu', 1)")
+        );
+        assert_eq!(
+            "expected a string pattern at column 13, found identifier 'region'",
+            message("region LIKE region")
+        );
+        assert_eq!(
+            "invalid expression at column 13: the ESCAPE character must be followed by '%', '_', or itself",
+            message("region LIKE 'e!u' ESCAPE '!'")
+        );
+        assert_eq!(
+            "expected BETWEEN, IN, or LIKE at column 12, found 'NULL'",
+            message("region NOT NULL")
+        );
+        assert_eq!(
+            "expected NULL at column 11, found number 1",
+            message("region IS 1")
+        );
+
+        let nested = format!(
+            "{}a{}",
+            "(".repeat(MAX_DEPTH + 1),
+            ")".repeat(MAX_DEPTH + 1)
+        );
+        assert_eq!(
+            Err(Error::TooDeep {
+                max_depth: MAX_DEPTH
+            }),
+            parse(&nested)
+        );
+        let long = format!("a = '{}'", "x".repeat(MAX_LENGTH));
+        assert!(matches!(parse(&long), Err(Error::TooLong { .. })));
+    }
+}
@@ -0,0 +1,160 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::record::Record;
+
+use super::error::{Error, Result};
+use super::expr::Expr;
+use super::parser;
+
+/// The identifier selectors use to refer to the record key. Every other
+/// identifier refers to the record header of the same name.
+pub const KEY_IDENTIFIER: &str = "$key";
+/// The longest selector accepted, in characters.
+pub const MAX_LENGTH: usize = 4096;
+/// The deepest selectors may nest parentheses, `NOT`s, and arithmetic.
+pub const MAX_DEPTH: usize = 64;
+
+/// A filter over records written in a subset of the SQL-92 conditional
+/// expression syntax, such as `region = 'eu' AND priority > 3`.
+///
+/// Identifiers refer to record headers, or to the record key when written
+/// [KEY_IDENTIFIER], and may be double quoted to include other characters,
+/// as in `"x-match" = 'all'`. Headers missing from a record, or whose values
+/// are not UTF-8, are null. Header values are strings, converted to numbers or
+/// booleans when compared with one. Selectors support `AND`, `OR`, `NOT`,
+/// comparisons, `+ - * /`, `BETWEEN`, `IN`, `LIKE` with an optional `ESCAPE`,
+/// and `IS NULL`, with keywords matched case insensitively. Conditions over
+/// null or unconvertible values are unknown, and a record only matches if the
+/// selector is true for it.
+///
+/// ```
+/// # use librift::record::Record;
+/// # use librift::selector::Selector;
+/// let selector = Selector::parse("region = 'eu' AND priority > 3").unwrap();
+/// let record = Record::new("value").with_header("region", "eu");
+/// assert!(selector.matches(&record.clone().with_header("priority", "5")));
+/// assert!(!selector.matches(&record));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Selector {
+    source: String,
+    expr: Expr,
+}
+
+impl Selector {
+    /// Parse a selector, rejecting selectors that are malformed or compare
+    /// values that can never be compared.
+    pub fn parse(source: &str) -> Result<Selector> {
+        Ok(Selector {
+            source: source.to_owned(),
+            expr: parser::parse(source)?,
+        })
+    }
+
+    /// Returns whether the supplied record matches the selector.
+    pub fn matches(&self, record: &Record) -> bool {
+        self.expr.test(record)
+    }
+
+    /// Returns the selector as it was written.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+}
+
+impl PartialEq for Selector {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl FromStr for Selector {
+    type Err = Error;
+
+    fn from_str(source: &str) -> Result<Selector> {
+        Selector::parse(source)
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        let record = Record::new("value")
+            .with_key("order_17")
+            .with_header("region", "eu-west")
+            .with_header("priority", "5")
+            .with_header("urgent", "true")
+            .with_header("x-match", "all");
+
+        let matching = [
+            "region = 'eu-west' AND priority > 3",
+            "region LIKE 'eu-%' and priority between 1 and 5",
+            "priority IN (1, 5) OR missing = 1",
+            "urgent",
+            "urgent = TRUE AND NOT (priority <> 5)",
+            "priority * 2 - 1 = 9 AND -priority < 0",
+            "$key LIKE 'order\\_%' ESCAPE '\\'",
+            "\"x-match\" = 'all'",
+            "missing IS NULL AND region IS NOT NULL",
+            "region NOT IN ('us', 'ap')",
+            "priority / 0 IS NULL",
+        ];
+        for source in matching {
+            let selector = Selector::parse(source).unwrap();
+            assert!(selector.matches(&record), "{} should match", source);
+        }
+
+        let mismatching = [
+            "region = 'us'",
+            "priority > 5",
+            "region LIKE 'us-%'",
+            // Conditions over missing headers are unknown, as are their negations.
+            "missing = 1",
+            "NOT (missing = 1)",
+            "missing NOT IN ('a')",
+            // As are conditions over values that can not be converted.
+            "region > 1",
+            "NOT region",
+            "missing = 1 OR region = 1",
+        ];
+        for source in mismatching {
+            let selector = Selector::parse(source).unwrap();
+            assert!(!selector.matches(&record), "{} should not match", source);
+        }
+
+        // Unknown only propagates while it can still change the result.
+        assert!(Selector::parse("missing = 1 OR region <> 'us'")
+            .unwrap()
+            .matches(&record));
+        assert!(Selector::parse("NOT (missing = 1 AND region = 'us')")
+            .unwrap()
+            .matches(&record));
+    }
+
+    #[test]
+    fn test_from_str() {
+        let selector: Selector = "region = 'eu'".parse().unwrap();
+        assert_eq!("region = 'eu'", selector.to_string());
+        assert_eq!(selector, Selector::parse("region = 'eu'").unwrap());
+
+        let err = "region = ".parse::<Selector>().unwrap_err();
+        assert_eq!(
+            "expected a value at column 10, found the end of the selector",
+            err.to_string()
+        );
+    }
+}
@@ -0,0 +1,33 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::net::SocketAddr;
+
+use structopt::StructOpt;
+
+#[derive(Debug, Clone, StructOpt)]
+/// Rift network listener configuration.
+pub struct Config {
+    #[structopt(
+        long = "listen-address",
+        short = "a",
+        env = "RIFT_LISTEN_ADDRESS",
+        help = "The address to listen for client connections on.",
+        long_help = "Sets the address and port the native binary protocol listener binds to.",
+        default_value = "0.0.0.0:7070",
+        takes_value = true
+    )]
+    /// Define the address to listen on.
+    pub listen_address: SocketAddr,
+
+    #[structopt(
+        long = "max-frame-bytes",
+        env = "RIFT_MAX_FRAME_BYTES",
+        help = "The maximum size of a single request frame.",
+        long_help = "Sets the largest request frame, in bytes, the listener will accept before rejecting it.",
+        default_value = "16777216",
+        takes_value = true
+    )]
+    /// Define the maximum request frame size in bytes.
+    pub max_frame_bytes: u32,
+}
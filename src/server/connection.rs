@@ -0,0 +1,77 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    io::{BufReader, BufWriter},
+    net::TcpStream,
+    sync::Arc,
+};
+
+use crate::broker::{Broker, Session};
+use crate::codec::Reader;
+use crate::protocol::{
+    self, read_frame, write_frame, Frame, Request, RequestHeader, Response, ResponseError,
+};
+
+/// Serve requests from a single client connection until it is closed.
+///
+/// Requests are served strictly in the order they are received so clients may
+/// pipeline requests and match responses up using their correlation ids.
+/// Frames that cannot be decoded are answered with an error response rather
+/// than tearing down the connection, as framing is still intact. Temporary
+/// queues created through the connection are deleted once it closes, however
+/// it closes.
+pub(super) fn serve(
+    logger: slog::Logger,
+    stream: TcpStream,
+    broker: Arc<Broker>,
+    max_frame_bytes: u32,
+) -> protocol::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    let mut session = Session::new(broker);
+
+    while let Some(frame) = read_frame(&mut reader, max_frame_bytes)? {
+        let (correlation_id, response) = match frame {
+            Frame::Complete(buf) => handle(&logger, &mut session, &buf),
+            Frame::Oversized { size, prefix } => {
+                let correlation_id = RequestHeader::decode(&mut Reader::new(&prefix))
+                    .map(|header| header.correlation_id)
+                    .unwrap_or_default();
+                let err = protocol::Error::FrameTooLarge {
+                    size,
+                    max: max_frame_bytes,
+                };
+                warn!(logger, "Rejected oversized frame."; "correlation_id" => correlation_id, "size" => size);
+                (correlation_id, Err(ResponseError::from(&err)))
+            }
+        };
+        write_frame(&mut writer, &Response::encode(correlation_id, &response))?;
+    }
+    Ok(())
+}
+
+fn handle(
+    logger: &slog::Logger,
+    session: &mut Session,
+    buf: &[u8],
+) -> (u32, Result<Response, ResponseError>) {
+    let mut reader = Reader::new(buf);
+    let header = match RequestHeader::decode(&mut reader) {
+        Ok(header) => header,
+        Err(err) => {
+            let err = protocol::Error::from(err);
+            warn!(logger, "Rejected malformed request header."; "error" => err.to_string());
+            return (0, Err(ResponseError::from(&err)));
+        }
+    };
+
+    let response = match Request::decode(&header, &mut reader) {
+        Ok(request) => session.handle(request),
+        Err(err) => {
+            warn!(logger, "Rejected malformed request."; "correlation_id" => header.correlation_id, "api_key" => header.api_key, "error" => err.to_string());
+            Err(ResponseError::from(&err))
+        }
+    };
+    (header.correlation_id, response)
+}
@@ -0,0 +1,40 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{io, net::SocketAddr, result};
+
+use thiserror::Error;
+
+/// Custom Result wrapper to simplify usage.
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents errors running a network listener.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// Handles failures binding the listening socket.
+    #[error("failed to bind listener to '{address}': {source}")]
+    Bind {
+        /// The address that could not be bound.
+        address: SocketAddr,
+        /// The initial error cause.
+        source: io::Error,
+    },
+    /// Handles failures accepting new connections.
+    #[error("failed to accept connection: {0}")]
+    Accept(#[source] io::Error),
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let err = Error::Bind {
+            address: "127.0.0.1:1".parse().unwrap(),
+            source: io::ErrorKind::AddrInUse.into(),
+        };
+        assert!(format!("{}", err).starts_with("failed to bind listener to '127.0.0.1:1'"));
+    }
+}
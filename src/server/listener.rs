@@ -0,0 +1,374 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    io::ErrorKind,
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use crate::broker::Broker;
+
+use super::config::Config;
+use super::connection;
+use super::error::{Error, Result};
+use super::metrics::OpenConnection;
+
+/// How long to pause for after failing to accept a connection, so that running
+/// out of file descriptors does not spin the listener.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Accepts client connections for the native binary protocol, serving each
+/// connection on its own thread.
+pub struct Server {
+    logger: slog::Logger,
+    listener: TcpListener,
+    broker: Arc<Broker>,
+    max_frame_bytes: u32,
+}
+
+impl Server {
+    /// Bind a new listener using the supplied configuration.
+    pub fn bind(logger: slog::Logger, cfg: &Config, broker: Arc<Broker>) -> Result<Server> {
+        let listener = TcpListener::bind(cfg.listen_address).map_err(|source| Error::Bind {
+            address: cfg.listen_address,
+            source,
+        })?;
+        Ok(Server {
+            logger,
+            listener,
+            broker,
+            max_frame_bytes: cfg.max_frame_bytes,
+        })
+    }
+
+    /// Returns the address this listener is bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.listener.local_addr().map_err(Error::Accept)
+    }
+
+    /// Accept and serve connections until the process exits.
+    pub fn serve(self) -> ! {
+        loop {
+            let (stream, peer) = accept(&self.logger, &self.listener);
+            let logger = self.logger.new(o!("peer" => peer.to_string()));
+            let broker = self.broker.clone();
+            let max_frame_bytes = self.max_frame_bytes;
+
+            thread::spawn(move || {
+                let _open = OpenConnection::new("rift");
+                debug!(logger, "Accepted connection.");
+                if let Err(err) = stream.set_nodelay(true) {
+                    warn!(logger, "Failed to disable nagle's algorithm."; "error" => err.to_string());
+                }
+                match connection::serve(logger.clone(), stream, broker, max_frame_bytes) {
+                    Ok(()) => debug!(logger, "Connection closed."),
+                    Err(err) => warn!(logger, "Connection failed."; "error" => err.to_string()),
+                }
+            });
+        }
+    }
+}
+
+/// Accept the next connection on a listener. Failing to accept one, as when
+/// the process runs out of file descriptors or the peer gives up first, is
+/// logged and retried rather than stopping the listener.
+pub(crate) fn accept(logger: &slog::Logger, listener: &TcpListener) -> (TcpStream, SocketAddr) {
+    loop {
+        match listener.accept() {
+            Ok(accepted) => return accepted,
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => {
+                warn!(logger, "Failed to accept connection, will retry."; "error" => err.to_string());
+                thread::sleep(ACCEPT_BACKOFF);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use std::{
+        io::BufReader,
+        net::TcpStream,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+    use crate::broker::TEMPORARY_QUEUE_PREFIX;
+    use crate::client::{self, Client};
+    use crate::codec::{Reader, Writer};
+    use crate::protocol::{
+        read_frame, write_frame, ApiKey, ErrorCode, Frame, JoinGroupRequest, ProduceRequest,
+        Request, Response,
+    };
+    use crate::record::{Compression, Record};
+    use crate::storage::LogConfig;
+    use crate::topic::{self, Partitioning};
+    use crate::{group, producer, queue, schedule};
+
+    fn start(dir: &std::path::Path) -> SocketAddr {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let topics = Arc::new(topic::Manager::open(dir, LogConfig::default()).unwrap());
+        let broker = Arc::new(
+            Broker::open(
+                logger.clone(),
+                topics,
+                group::Config::default(),
+                producer::Config::default(),
+                queue::Config::default(),
+                schedule::Config::default(),
+            )
+            .unwrap(),
+        );
+        let cfg = Config {
+            listen_address: "127.0.0.1:0".parse().unwrap(),
+            max_frame_bytes: 1024,
+        };
+        let server = Server::bind(logger, &cfg, broker).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+        addr
+    }
+
+    fn raw_response(stream: &mut BufReader<TcpStream>, api_key: ApiKey) -> (u32, ErrorCode) {
+        let buf = match read_frame(stream, u32::MAX).unwrap() {
+            Some(Frame::Complete(buf)) => buf,
+            _ => unimplemented!(),
+        };
+        let mut reader = Reader::new(&buf);
+        let correlation_id = Response::decode_correlation_id(&mut reader).unwrap();
+        let code = match Response::decode(api_key, &mut reader).unwrap() {
+            Ok(_) => ErrorCode::None,
+            Err(err) => err.code,
+        };
+        (correlation_id, code)
+    }
+
+    #[test]
+    fn test_request_reply() {
+        let dir = tempfile::tempdir().unwrap();
+        let addr = start(dir.path());
+        let mut admin = Client::connect(addr).unwrap();
+        admin.create_topic("rpc", 1, Vec::new()).unwrap();
+        let temporary_queues = |admin: &mut Client| {
+            admin
+                .metadata(Vec::new())
+                .unwrap()
+                .topics
+                .into_iter()
+                .filter(|topic| topic.name.starts_with(TEMPORARY_QUEUE_PREFIX))
+                .count()
+        };
+
+        // Answer every request but "slow", preceding each reply with one that
+        // does not match so the requester has to skip it.
+        let responder = thread::spawn(move || {
+            let mut client = Client::connect(addr).unwrap();
+            let mut offset = 0;
+            loop {
+                for request in client.fetch("rpc", 0, offset, 1024).unwrap().records {
+                    offset = request.offset + 1;
+                    let mut value = request.record.value.clone().unwrap();
+                    if value == b"slow" {
+                        continue;
+                    }
+                    let stale = Record::new("stale").with_correlation_id("stale");
+                    let topic = request.record.reply_to().unwrap();
+                    client
+                        .produce(topic, Partitioning::Key, vec![stale])
+                        .unwrap();
+                    value.extend_from_slice(b"-pong");
+                    client.reply(&request.record, Record::new(value)).unwrap();
+                    return;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+
+        let mut requester = Client::connect(addr).unwrap();
+        let err = requester
+            .request("rpc", Record::new("slow"), Duration::from_millis(50))
+            .unwrap_err();
+        assert!(matches!(err, client::Error::ReplyTimeout { .. }));
+        let reply = requester
+            .request("rpc", Record::new("ping"), Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(Some(b"ping-pong".to_vec()), reply.value);
+        assert_eq!(Some("1"), reply.correlation_id());
+        responder.join().unwrap();
+
+        let err = requester.reply(&reply, Record::new("a")).unwrap_err();
+        assert!(matches!(err, client::Error::NoReplyTo));
+
+        // The reply queue is deleted once the requester disconnects.
+        assert_eq!(1, temporary_queues(&mut admin));
+        drop(requester);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while temporary_queues(&mut admin) > 0 {
+            assert!(Instant::now() < deadline, "temporary queue was not deleted");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let addr = start(dir.path());
+
+        let mut client = Client::connect(addr).unwrap();
+        client.heartbeat().unwrap();
+        client.create_topic("events", 2, Vec::new()).unwrap();
+
+        let produced = client
+            .produce("events", Partitioning::Explicit(1), vec![Record::new("a")])
+            .unwrap();
+        assert_eq!(1, produced[0].partition);
+        assert_eq!(0, produced[0].offset);
+
+        let fetched = client.fetch("events", 1, 0, 1024).unwrap();
+        assert_eq!(Some(b"a".to_vec()), fetched.records[0].record.value);
+        assert_eq!(0, client.offset_for_timestamp("events", 1, 0).unwrap());
+        assert_eq!(
+            1,
+            client.offset_for_timestamp("events", 1, i64::MAX).unwrap()
+        );
+
+        let err = client.fetch("missing", 0, 0, 1024).unwrap_err();
+        assert!(format!("{}", err).contains("does not exist"));
+        let err = client.offset_for_timestamp("missing", 0, 0).unwrap_err();
+        assert!(format!("{}", err).contains("does not exist"));
+    }
+
+    #[test]
+    fn test_consumer_group() {
+        let dir = tempfile::tempdir().unwrap();
+        let addr = start(dir.path());
+
+        let mut first = Client::connect(addr).unwrap();
+        let mut second = Client::connect(addr).unwrap();
+        first.create_topic("events", 4, Vec::new()).unwrap();
+
+        let join = JoinGroupRequest {
+            group: String::from("workers"),
+            member_id: String::new(),
+            session_timeout_ms: 10000,
+            topics: vec![String::from("events")],
+            strategies: vec![String::from("sticky")],
+        };
+        let a = first.join_group(join.clone()).unwrap();
+        assert_eq!(4, a.assignment.len());
+
+        let b = second.join_group(join).unwrap();
+        let a = first.group_heartbeat("workers", &a.member_id).unwrap();
+        assert_eq!(b.generation, a.generation);
+        assert_eq!(2, a.assignment.len());
+        assert_eq!(2, b.assignment.len());
+
+        second.leave_group("workers", &b.member_id).unwrap();
+        let a = first.group_heartbeat("workers", &a.member_id).unwrap();
+        assert_eq!(4, a.assignment.len());
+    }
+
+    #[test]
+    fn test_pipelining() {
+        let dir = tempfile::tempdir().unwrap();
+        let addr = start(dir.path());
+
+        let mut client = Client::connect(addr).unwrap();
+        client.create_topic("events", 1, Vec::new()).unwrap();
+
+        let mut ids = Vec::new();
+        for idx in 0..10 {
+            let req = Request::Produce(ProduceRequest {
+                topic: String::from("events"),
+                partitioning: Partitioning::Key,
+                records: vec![Record::new(idx.to_string())],
+                producer: None,
+                compression: Compression::None,
+            });
+            ids.push(client.send(&req).unwrap());
+        }
+        for (idx, id) in ids.into_iter().enumerate() {
+            let (correlation_id, resp) = client.receive().unwrap();
+            assert_eq!(id, correlation_id);
+            match resp.unwrap() {
+                Response::Produce(resp) => assert_eq!(idx as u64, resp.records[0].offset),
+                _ => unimplemented!(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_malformed_frames() {
+        let dir = tempfile::tempdir().unwrap();
+        let addr = start(dir.path());
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+
+        // A header too short to decode is reported against correlation id zero.
+        write_frame(&mut writer, &[0, 1]).unwrap();
+        assert_eq!(
+            (0, ErrorCode::CorruptFrame),
+            raw_response(&mut reader, ApiKey::Heartbeat)
+        );
+
+        // Unknown apis are reported against the request's correlation id.
+        let mut buf = Vec::new();
+        buf.put_u16(999);
+        buf.put_u16(0);
+        buf.put_u32(5);
+        write_frame(&mut writer, &buf).unwrap();
+        assert_eq!(
+            (5, ErrorCode::UnknownApi),
+            raw_response(&mut reader, ApiKey::Heartbeat)
+        );
+
+        // Truncated bodies are reported as corrupt frames.
+        let mut buf = Vec::new();
+        buf.put_u16(ApiKey::DeleteTopic.as_u16());
+        buf.put_u16(0);
+        buf.put_u32(6);
+        buf.put_u16(10);
+        write_frame(&mut writer, &buf).unwrap();
+        assert_eq!(
+            (6, ErrorCode::CorruptFrame),
+            raw_response(&mut reader, ApiKey::DeleteTopic)
+        );
+
+        // Unsupported versions are rejected.
+        let mut buf = Vec::new();
+        buf.put_u16(ApiKey::Heartbeat.as_u16());
+        buf.put_u16(99);
+        buf.put_u32(7);
+        write_frame(&mut writer, &buf).unwrap();
+        assert_eq!(
+            (7, ErrorCode::UnsupportedVersion),
+            raw_response(&mut reader, ApiKey::Heartbeat)
+        );
+
+        // Oversized frames are drained and rejected without losing framing.
+        let mut buf = Vec::new();
+        buf.put_u16(ApiKey::Heartbeat.as_u16());
+        buf.put_u16(0);
+        buf.put_u32(8);
+        buf.extend_from_slice(&[0; 2048]);
+        write_frame(&mut writer, &buf).unwrap();
+        assert_eq!(
+            (8, ErrorCode::FrameTooLarge),
+            raw_response(&mut reader, ApiKey::Heartbeat)
+        );
+
+        // The connection remains usable afterwards.
+        write_frame(&mut writer, &Request::Heartbeat.encode(9)).unwrap();
+        assert_eq!(
+            (9, ErrorCode::None),
+            raw_response(&mut reader, ApiKey::Heartbeat)
+        );
+    }
+}
@@ -0,0 +1,74 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::sync::OnceLock;
+
+use prometheus::IntGaugeVec;
+
+use crate::metrics::{register_int_gauge_vec, Opt};
+
+/// The series exported by the listeners.
+struct Metrics {
+    /// Open client connections, labelled by the protocol they speak.
+    connections: IntGaugeVec,
+}
+
+/// Returns the process wide listener metrics, registering them on first use.
+fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(|| {
+        let opts = vec![
+            Opt::Namespace(String::from("rift")),
+            Opt::Subsystem(String::from("server")),
+            Opt::Labels(vec![String::from("protocol")]),
+        ];
+        Metrics {
+            connections: register_int_gauge_vec(
+                "connections",
+                "The number of open client connections.",
+                Some(opts),
+            )
+            .expect("server metrics registered twice"),
+        }
+    })
+}
+
+/// Counts a client connection as open for as long as it is held.
+pub(crate) struct OpenConnection {
+    protocol: &'static str,
+}
+
+impl OpenConnection {
+    /// Count a newly accepted connection speaking the supplied protocol.
+    pub(crate) fn new(protocol: &'static str) -> OpenConnection {
+        metrics().connections.with_label_values(&[protocol]).inc();
+        OpenConnection { protocol }
+    }
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        metrics()
+            .connections
+            .with_label_values(&[self.protocol])
+            .dec();
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_connection() {
+        let open = || metrics().connections.with_label_values(&["test"]).get();
+        let first = OpenConnection::new("test");
+        let second = OpenConnection::new("test");
+        assert_eq!(2, open());
+        drop(first);
+        assert_eq!(1, open());
+        drop(second);
+        assert_eq!(0, open());
+    }
+}
@@ -0,0 +1,14 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod config;
+mod connection;
+mod error;
+mod listener;
+mod metrics;
+
+pub use self::config::Config;
+pub use self::error::{Error, Result};
+pub(crate) use self::listener::accept;
+pub use self::listener::Server;
+pub(crate) use self::metrics::OpenConnection;
@@ -0,0 +1,251 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fs::{self, File},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use super::config::LogConfig;
+use super::entry::Entry;
+use super::error::{Error, Result};
+use super::segment::{Segment, INDEX_EXT, LOG_EXT, TIME_INDEX_EXT};
+
+/// The directory, within a log's directory, compacted segments are written to
+/// before being swapped into place.
+pub(super) const CLEANING_DIR: &str = ".cleaning";
+/// The file marking a compaction as committed, listing the offset compaction ran
+/// up to followed by the base offsets of the replacement segments.
+const SWAP_MARKER: &str = "swap";
+/// The file, within a log's directory, recording the offset the log's last
+/// compaction ran up to.
+const COMPACTED_MARKER: &str = ".compacted";
+
+/// Rewritten copies of a log's closed segments.
+///
+/// A compaction is started with [crate::storage::Log::begin_compaction], filled
+/// with the entries to retain while the log continues to accept appends, and
+/// swapped into place with [crate::storage::Log::finish_compaction].
+pub struct Compaction {
+    dir: PathBuf,
+    config: LogConfig,
+    start_offset: u64,
+    end_offset: u64,
+    replaced: Vec<u64>,
+    replaced_bytes: u64,
+    segments: Vec<Segment>,
+}
+
+impl Compaction {
+    pub(super) fn new(
+        log_dir: &Path,
+        config: LogConfig,
+        replaced: &[Segment],
+        end_offset: u64,
+    ) -> Result<Compaction> {
+        let dir = log_dir.join(CLEANING_DIR);
+        remove_dir(&dir)?;
+        fs::create_dir(&dir).map_err(|e| Error::io(&dir, e))?;
+        Ok(Compaction {
+            dir,
+            config,
+            start_offset: replaced[0].base_offset(),
+            end_offset,
+            replaced: replaced.iter().map(Segment::base_offset).collect(),
+            replaced_bytes: replaced.iter().map(Segment::size).sum(),
+            segments: Vec::new(),
+        })
+    }
+
+    /// Returns the first offset covered by this compaction.
+    pub fn start_offset(&self) -> u64 {
+        self.start_offset
+    }
+
+    /// Returns the offset this compaction runs up to, which is the base offset of
+    /// the log's active segment when the compaction began.
+    pub fn end_offset(&self) -> u64 {
+        self.end_offset
+    }
+
+    /// Append an entry to retain. Entries must be appended in offset order and lie
+    /// within this compaction's offset range, though gaps between them are allowed.
+    pub fn append(&mut self, entry: &Entry) -> Result<()> {
+        let next = self
+            .segments
+            .last()
+            .map(Segment::next_offset)
+            .unwrap_or(self.start_offset);
+        if entry.base_offset < next || entry.next_offset() > self.end_offset {
+            return Err(Error::OffsetOutOfRange {
+                offset: entry.base_offset,
+                start: next,
+                end: self.end_offset,
+            });
+        }
+
+        let roll = match self.segments.last() {
+            Some(segment) => {
+                segment.is_full(entry.size() as u64, &self.config)
+                    || entry.next_offset() - segment.base_offset() > u32::MAX as u64
+            }
+            None => true,
+        };
+        if roll {
+            if let Some(segment) = self.segments.last() {
+                segment.flush()?;
+            }
+            let base_offset = if self.segments.is_empty() {
+                self.start_offset
+            } else {
+                entry.base_offset
+            };
+            let segment = Segment::open(&self.dir, base_offset, false, &self.config)?;
+            self.segments.push(segment);
+        }
+
+        let config = self.config.clone();
+        self.segments.last_mut().unwrap().append(entry, &config)
+    }
+
+    /// Returns the base offsets of the segments being replaced.
+    pub(super) fn replaced(&self) -> &[u64] {
+        &self.replaced
+    }
+
+    /// Flush the compacted segments and durably mark the compaction as committed,
+    /// returning the base offsets of the replacement segments and the number of
+    /// bytes reclaimed.
+    pub(super) fn commit(mut self) -> Result<(Vec<u64>, u64)> {
+        if self.segments.is_empty() {
+            let segment = Segment::open(&self.dir, self.start_offset, false, &self.config)?;
+            self.segments.push(segment);
+        }
+
+        let mut bases = Vec::with_capacity(self.segments.len());
+        let mut size = 0;
+        for segment in self.segments.drain(..) {
+            segment.flush()?;
+            bases.push(segment.base_offset());
+            size += segment.size();
+        }
+
+        let mut marker = self.end_offset.to_string();
+        for base in &bases {
+            marker.push(' ');
+            marker.push_str(&base.to_string());
+        }
+        let tmp = self.dir.join(format!("{}.tmp", SWAP_MARKER));
+        let path = self.dir.join(SWAP_MARKER);
+        fs::write(&tmp, marker).map_err(|e| Error::io(&tmp, e))?;
+        sync_file(&tmp)?;
+        fs::rename(&tmp, &path).map_err(|e| Error::io(&path, e))?;
+        sync_file(&self.dir)?;
+
+        Ok((bases, self.replaced_bytes.saturating_sub(size)))
+    }
+
+    /// Discard this compaction without touching the log.
+    pub fn abort(self) -> Result<()> {
+        remove_dir(&self.dir)
+    }
+}
+
+/// Complete or discard any compaction interrupted by a crash. A compaction that
+/// reached its swap marker is rolled forward, anything else is discarded.
+pub(super) fn recover(log_dir: &Path) -> Result<()> {
+    let dir = log_dir.join(CLEANING_DIR);
+    let marker = match fs::read_to_string(dir.join(SWAP_MARKER)) {
+        Ok(marker) => marker,
+        Err(e) if e.kind() == ErrorKind::NotFound => return remove_dir(&dir),
+        Err(e) => return Err(Error::io(dir.join(SWAP_MARKER), e)),
+    };
+
+    let mut values = marker.split_whitespace().map(|value| value.parse::<u64>());
+    let end_offset = match values.next() {
+        Some(Ok(end_offset)) => end_offset,
+        _ => return remove_dir(&dir),
+    };
+    let bases = match values.collect::<std::result::Result<Vec<u64>, _>>() {
+        Ok(bases) => bases,
+        Err(_) => return remove_dir(&dir),
+    };
+    swap(log_dir, end_offset, &bases)
+}
+
+/// Move the compacted segments into the log directory, replacing every segment
+/// below `end_offset` that is not one of the supplied replacement segments.
+pub(super) fn swap(log_dir: &Path, end_offset: u64, bases: &[u64]) -> Result<()> {
+    let dir = log_dir.join(CLEANING_DIR);
+    for dirent in fs::read_dir(&dir).map_err(|e| Error::io(&dir, e))? {
+        let path = dirent.map_err(|e| Error::io(&dir, e))?.path();
+        if segment_file(&path).is_none() {
+            continue;
+        }
+        let target = log_dir.join(path.file_name().unwrap());
+        fs::rename(&path, &target).map_err(|e| Error::io(&target, e))?;
+    }
+
+    for dirent in fs::read_dir(log_dir).map_err(|e| Error::io(log_dir, e))? {
+        let path = dirent.map_err(|e| Error::io(log_dir, e))?.path();
+        match segment_file(&path) {
+            Some(base) if base < end_offset && !bases.contains(&base) => {
+                fs::remove_file(&path).map_err(|e| Error::io(&path, e))?
+            }
+            _ => continue,
+        }
+    }
+
+    let tmp = log_dir.join(format!("{}.tmp", COMPACTED_MARKER));
+    let path = log_dir.join(COMPACTED_MARKER);
+    fs::write(&tmp, end_offset.to_string()).map_err(|e| Error::io(&tmp, e))?;
+    sync_file(&tmp)?;
+    fs::rename(&tmp, &path).map_err(|e| Error::io(&path, e))?;
+
+    sync_file(log_dir)?;
+    remove_dir(&dir)
+}
+
+/// Returns the offset the log's last compaction ran up to, or zero if it has
+/// never been compacted.
+pub(super) fn compacted_offset(log_dir: &Path) -> Result<u64> {
+    let path = log_dir.join(COMPACTED_MARKER);
+    match fs::read_to_string(&path) {
+        Ok(marker) => Ok(marker.trim().parse().unwrap_or(0)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(Error::io(path, e)),
+    }
+}
+
+/// Forget the offset the log's last compaction ran up to.
+pub(super) fn clear_compacted_offset(log_dir: &Path) -> Result<()> {
+    let path = log_dir.join(COMPACTED_MARKER);
+    match fs::remove_file(&path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(Error::io(path, e)),
+        _ => Ok(()),
+    }
+}
+
+fn segment_file(path: &Path) -> Option<u64> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(LOG_EXT) | Some(INDEX_EXT) | Some(TIME_INDEX_EXT) => path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse().ok()),
+        _ => None,
+    }
+}
+
+fn sync_file(path: &Path) -> Result<()> {
+    File::open(path)
+        .and_then(|file| file.sync_all())
+        .map_err(|e| Error::io(path, e))
+}
+
+fn remove_dir(dir: &Path) -> Result<()> {
+    match fs::remove_dir_all(dir) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(Error::io(dir, e)),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,404 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{fmt, path::PathBuf, str::FromStr, time::Duration};
+
+use structopt::StructOpt;
+
+use super::error::{Error, Result};
+
+/// The size in bytes of a single sparse offset index entry.
+pub const INDEX_ENTRY_SIZE: u64 = 8;
+/// The size in bytes of a single sparse time index entry.
+pub const TIME_INDEX_ENTRY_SIZE: u64 = 12;
+
+#[derive(Debug, Clone, StructOpt)]
+/// Rift storage configuration.
+pub struct Config {
+    #[structopt(
+        long = "data-dir",
+        short = "d",
+        env = "RIFT_DATA_DIR",
+        help = "The directory to store data in.",
+        long_help = "Sets the root directory that all topic, partition, and segment data is stored under.",
+        default_value = "/var/lib/rift",
+        takes_value = true
+    )]
+    /// Define the root data directory.
+    pub data_dir: PathBuf,
+
+    #[structopt(
+        long = "segment-bytes",
+        env = "RIFT_SEGMENT_BYTES",
+        help = "The maximum size of a single log segment.",
+        long_help = "Sets the size in bytes a log segment may grow to before a new segment is rolled.",
+        default_value = "1073741824",
+        takes_value = true
+    )]
+    /// Define the maximum segment size in bytes.
+    pub segment_bytes: u64,
+
+    #[structopt(
+        long = "index-bytes",
+        env = "RIFT_INDEX_BYTES",
+        help = "The maximum size of a single segment index.",
+        long_help = "Sets the size in bytes a segment's offset index may grow to before a new segment is rolled.",
+        default_value = "10485760",
+        takes_value = true
+    )]
+    /// Define the maximum offset index size in bytes.
+    pub index_bytes: u64,
+
+    #[structopt(
+        long = "index-interval-bytes",
+        env = "RIFT_INDEX_INTERVAL_BYTES",
+        help = "The number of bytes between offset index entries.",
+        long_help = "Sets how many bytes of log data are appended between entries in the sparse offset index.",
+        default_value = "4096",
+        takes_value = true
+    )]
+    /// Define the number of bytes between index entries.
+    pub index_interval_bytes: u64,
+
+    #[structopt(
+        long = "retention-ms",
+        env = "RIFT_RETENTION_MS",
+        help = "How long to retain log segments, or -1 to retain them forever.",
+        long_help = "Sets how long in milliseconds a closed log segment is retained, measured from its newest record, before it is deleted. Use -1 to disable time based retention.",
+        default_value = "604800000",
+        allow_hyphen_values = true,
+        takes_value = true
+    )]
+    /// Define the default time based retention in milliseconds.
+    pub retention_ms: i64,
+
+    #[structopt(
+        long = "retention-bytes",
+        env = "RIFT_RETENTION_BYTES",
+        help = "The maximum size of a partition's log, or -1 for no limit.",
+        long_help = "Sets the size in bytes a partition's log may grow to before its oldest closed segments are deleted. Use -1 to disable size based retention.",
+        default_value = "-1",
+        allow_hyphen_values = true,
+        takes_value = true
+    )]
+    /// Define the default size based retention in bytes.
+    pub retention_bytes: i64,
+
+    #[structopt(
+        long = "cleanup-policy",
+        env = "RIFT_CLEANUP_POLICY",
+        help = "How old log data is cleaned up: delete, compact, or compact,delete.",
+        long_help = "Sets whether closed log segments are deleted once outside their retention limits, compacted to retain only the latest record for each key, or both.",
+        default_value = "delete",
+        takes_value = true
+    )]
+    /// Define the default cleanup policy.
+    pub cleanup_policy: CleanupPolicy,
+
+    #[structopt(
+        long = "delete-retention-ms",
+        env = "RIFT_DELETE_RETENTION_MS",
+        help = "How long compaction retains tombstones.",
+        long_help = "Sets how long in milliseconds a tombstone, a keyed record with no value, is retained by compaction so that consumers have a chance to observe the deletion.",
+        default_value = "86400000",
+        takes_value = true
+    )]
+    /// Define the default tombstone retention in milliseconds.
+    pub delete_retention_ms: u64,
+
+    #[structopt(
+        long = "flush-policy",
+        env = "RIFT_FLUSH_POLICY",
+        help = "When appended data is fsynced: always, messages:<count>, ms:<millis>, or os.",
+        long_help = "Sets when appended log data is flushed to durable storage: after every write, once the given number of messages are unflushed, once the given number of milliseconds have passed since the last flush, or only when the operating system decides to. Produce requests are acknowledged once the policy is satisfied.",
+        default_value = "os",
+        takes_value = true
+    )]
+    /// Define the default flush policy.
+    pub flush_policy: FlushPolicy,
+
+    #[structopt(
+        long = "flush-check-interval-ms",
+        env = "RIFT_FLUSH_CHECK_INTERVAL_MS",
+        help = "How often to check for logs due a time based flush.",
+        long_help = "Sets the interval in milliseconds at which logs with a time based flush policy are checked, so that data is flushed even when no further writes arrive.",
+        default_value = "100",
+        takes_value = true
+    )]
+    /// Define the interval between time based flush checks in milliseconds.
+    pub flush_check_interval_ms: u64,
+}
+
+impl Config {
+    /// Returns the [LogConfig] described by this configuration.
+    pub fn log_config(&self) -> LogConfig {
+        LogConfig {
+            segment_bytes: self.segment_bytes,
+            index_bytes: self.index_bytes,
+            index_interval_bytes: self.index_interval_bytes,
+            retention_ms: retention_limit(self.retention_ms),
+            retention_bytes: retention_limit(self.retention_bytes),
+            cleanup_policy: self.cleanup_policy,
+            delete_retention_ms: self.delete_retention_ms,
+            flush_policy: self.flush_policy,
+        }
+    }
+
+    /// Returns the interval between checks for logs due a time based flush.
+    pub fn flush_check_interval(&self) -> Duration {
+        Duration::from_millis(self.flush_check_interval_ms)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Determines how old data is removed from a log.
+pub struct CleanupPolicy {
+    /// Delete closed segments once they fall outside the log's retention limits.
+    pub delete: bool,
+    /// Compact closed segments so only the latest record for each key remains.
+    pub compact: bool,
+}
+
+impl CleanupPolicy {
+    /// Delete segments outside the retention limits, without compacting.
+    pub const DELETE: CleanupPolicy = CleanupPolicy {
+        delete: true,
+        compact: false,
+    };
+    /// Compact segments, without enforcing retention limits.
+    pub const COMPACT: CleanupPolicy = CleanupPolicy {
+        delete: false,
+        compact: true,
+    };
+}
+
+impl FromStr for CleanupPolicy {
+    type Err = Error;
+
+    /// Parse a comma separated list of policies.
+    ///
+    /// ```
+    /// # use librift::storage::CleanupPolicy;
+    /// let policy: CleanupPolicy = "compact,delete".parse().unwrap();
+    /// assert!(policy.compact && policy.delete);
+    /// assert_eq!("compact", "compact".parse::<CleanupPolicy>().unwrap().to_string());
+    /// assert!("archive".parse::<CleanupPolicy>().is_err());
+    /// ```
+    fn from_str(s: &str) -> Result<Self> {
+        let mut policy = CleanupPolicy {
+            delete: false,
+            compact: false,
+        };
+        for part in s.split(',').map(str::trim) {
+            match part {
+                "delete" => policy.delete = true,
+                "compact" => policy.compact = true,
+                _ => {
+                    return Err(Error::InvalidConfig {
+                        reason: format!(
+                            "cleanup policy must be a list of 'delete' and 'compact', got '{}'",
+                            s
+                        ),
+                    })
+                }
+            }
+        }
+        Ok(policy)
+    }
+}
+
+impl fmt::Display for CleanupPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.compact, self.delete) {
+            (true, true) => f.write_str("compact,delete"),
+            (true, false) => f.write_str("compact"),
+            _ => f.write_str("delete"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Determines when data appended to a log is flushed to durable storage.
+pub enum FlushPolicy {
+    /// Flush after every append.
+    Always,
+    /// Flush once the supplied number of messages have been appended since the
+    /// last flush.
+    Messages(u64),
+    /// Flush once the supplied number of milliseconds have passed since the last
+    /// flush.
+    Interval(u64),
+    /// Leave flushing to the operating system.
+    Os,
+}
+
+impl FromStr for FlushPolicy {
+    type Err = Error;
+
+    /// Parse a flush policy.
+    ///
+    /// ```
+    /// # use librift::storage::FlushPolicy;
+    /// assert_eq!(FlushPolicy::Always, "always".parse().unwrap());
+    /// assert_eq!(FlushPolicy::Messages(10), "messages:10".parse().unwrap());
+    /// assert_eq!(FlushPolicy::Interval(500), "ms:500".parse().unwrap());
+    /// assert_eq!("os", "os".parse::<FlushPolicy>().unwrap().to_string());
+    /// assert!("messages:0".parse::<FlushPolicy>().is_err());
+    /// ```
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || {
+            Error::InvalidConfig {
+            reason: format!(
+                "flush policy must be 'always', 'messages:<count>', 'ms:<millis>', or 'os', got '{}'",
+                s
+            ),
+        }
+        };
+        let limit = |value: &str| match value.parse::<u64>() {
+            Ok(limit) if limit > 0 => Ok(limit),
+            _ => Err(invalid()),
+        };
+
+        match s.trim().split_once(':') {
+            Some(("messages", count)) => limit(count).map(FlushPolicy::Messages),
+            Some(("ms", millis)) => limit(millis).map(FlushPolicy::Interval),
+            Some(_) => Err(invalid()),
+            None => match s.trim() {
+                "always" => Ok(FlushPolicy::Always),
+                "os" => Ok(FlushPolicy::Os),
+                _ => Err(invalid()),
+            },
+        }
+    }
+}
+
+impl fmt::Display for FlushPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlushPolicy::Always => f.write_str("always"),
+            FlushPolicy::Messages(count) => write!(f, "messages:{}", count),
+            FlushPolicy::Interval(millis) => write!(f, "ms:{}", millis),
+            FlushPolicy::Os => f.write_str("os"),
+        }
+    }
+}
+
+/// Converts a retention setting, where any negative value means unlimited, into
+/// an optional limit.
+///
+/// ```
+/// # use librift::storage::retention_limit;
+/// assert_eq!(None, retention_limit(-1));
+/// assert_eq!(Some(1000), retention_limit(1000));
+/// ```
+pub fn retention_limit(value: i64) -> Option<u64> {
+    u64::try_from(value).ok()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The settings that govern the layout and retention of a single [crate::storage::Log].
+pub struct LogConfig {
+    /// The size in bytes a segment may grow to before rolling.
+    pub segment_bytes: u64,
+    /// The size in bytes a segment's offset index may grow to before rolling.
+    pub index_bytes: u64,
+    /// The number of bytes appended between offset index entries.
+    pub index_interval_bytes: u64,
+    /// How long in milliseconds closed segments are retained, if limited.
+    pub retention_ms: Option<u64>,
+    /// The size in bytes the log may grow to before closed segments are deleted, if limited.
+    pub retention_bytes: Option<u64>,
+    /// How old data is removed from the log.
+    pub cleanup_policy: CleanupPolicy,
+    /// How long in milliseconds compaction retains tombstones.
+    pub delete_retention_ms: u64,
+    /// When appended data is flushed to durable storage.
+    pub flush_policy: FlushPolicy,
+}
+
+impl LogConfig {
+    /// Ensures the configured values describe a usable log layout.
+    ///
+    /// ```
+    /// # use librift::storage::LogConfig;
+    /// let cfg = LogConfig {
+    ///     segment_bytes: 1024,
+    ///     index_bytes: 4,
+    ///     index_interval_bytes: 128,
+    ///     ..Default::default()
+    /// };
+    /// assert!(cfg.validate().is_err());
+    /// ```
+    pub fn validate(&self) -> Result<()> {
+        if self.segment_bytes == 0 || self.segment_bytes > u32::MAX as u64 {
+            return Err(Error::InvalidConfig {
+                reason: format!(
+                    "segment bytes must be between 1 and {}, got {}",
+                    u32::MAX,
+                    self.segment_bytes
+                ),
+            });
+        }
+        if self.index_bytes < INDEX_ENTRY_SIZE {
+            return Err(Error::InvalidConfig {
+                reason: format!(
+                    "index bytes must be at least {}, got {}",
+                    INDEX_ENTRY_SIZE, self.index_bytes
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns the maximum number of entries a single segment index may hold.
+    pub fn max_index_entries(&self) -> usize {
+        (self.index_bytes / INDEX_ENTRY_SIZE) as usize
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            segment_bytes: 1073741824,
+            index_bytes: 10485760,
+            index_interval_bytes: 4096,
+            retention_ms: Some(604800000),
+            retention_bytes: None,
+            cleanup_policy: CleanupPolicy::DELETE,
+            delete_retention_ms: 86400000,
+            flush_policy: FlushPolicy::Os,
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert!(LogConfig::default().validate().is_ok());
+
+        let cfg = LogConfig {
+            segment_bytes: 0,
+            ..Default::default()
+        };
+        assert!(matches!(cfg.validate(), Err(Error::InvalidConfig { .. })));
+
+        let cfg = LogConfig {
+            index_bytes: 7,
+            ..Default::default()
+        };
+        assert!(matches!(cfg.validate(), Err(Error::InvalidConfig { .. })));
+    }
+
+    #[test]
+    fn test_max_index_entries() {
+        let cfg = LogConfig {
+            index_bytes: 80,
+            ..Default::default()
+        };
+        assert_eq!(10, cfg.max_index_entries());
+    }
+}
@@ -0,0 +1,201 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::convert::TryInto;
+
+/// The size in bytes of the framing header written in front of every entry payload.
+///
+/// The header is laid out as follows, with all integers big endian:
+///
+/// | field         | type | description                                          |
+/// |---------------|------|------------------------------------------------------|
+/// | base_offset   | u64  | the offset of the first record in the entry          |
+/// | length        | u32  | the number of bytes following this field             |
+/// | crc           | u32  | CRC32 of every byte following this field             |
+/// | record_count  | u32  | the number of records contained in the payload       |
+/// | max_timestamp | i64  | the largest timestamp of any record in the payload   |
+pub const HEADER_SIZE: usize = 28;
+
+/// The number of bytes of the header that precede, and are therefore not
+/// included in, the length field.
+const LENGTH_OFFSET: usize = 12;
+
+/// The number of bytes of the header that precede, and are therefore not
+/// covered by, the crc field.
+const CRC_OFFSET: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+/// A single framed unit of data in the log, holding one or more records that
+/// are assigned contiguous offsets starting at the base offset.
+pub struct Entry {
+    /// The offset assigned to the first record in this entry.
+    pub base_offset: u64,
+    /// The number of records, and therefore offsets, held in the payload.
+    pub record_count: u32,
+    /// The largest timestamp, in milliseconds since the epoch, of any record in the payload.
+    pub max_timestamp: i64,
+    /// The opaque encoded records.
+    pub payload: Vec<u8>,
+}
+
+impl Entry {
+    /// Create a new entry that has not yet been assigned an offset.
+    pub fn new(record_count: u32, max_timestamp: i64, payload: Vec<u8>) -> Entry {
+        Entry {
+            base_offset: 0,
+            record_count,
+            max_timestamp,
+            payload,
+        }
+    }
+
+    /// Returns the offset of the last record contained in this entry.
+    ///
+    /// ```
+    /// # use librift::storage::Entry;
+    /// let mut entry = Entry::new(3, 0, Vec::new());
+    /// entry.base_offset = 10;
+    /// assert_eq!(12, entry.last_offset());
+    /// assert_eq!(13, entry.next_offset());
+    /// ```
+    pub fn last_offset(&self) -> u64 {
+        self.next_offset().saturating_sub(1)
+    }
+
+    /// Returns the offset that will be assigned to the entry following this one.
+    pub fn next_offset(&self) -> u64 {
+        self.base_offset + self.record_count as u64
+    }
+
+    /// Returns the number of bytes this entry occupies on disk, including framing.
+    pub fn size(&self) -> usize {
+        HEADER_SIZE + self.payload.len()
+    }
+
+    /// Encode this entry, including its framing header, into a new buffer.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.size());
+        buf.extend_from_slice(&self.base_offset.to_be_bytes());
+        buf.extend_from_slice(&((self.size() - LENGTH_OFFSET) as u32).to_be_bytes());
+        buf.extend_from_slice(&[0; 4]);
+        buf.extend_from_slice(&self.record_count.to_be_bytes());
+        buf.extend_from_slice(&self.max_timestamp.to_be_bytes());
+        buf.extend_from_slice(&self.payload);
+
+        let crc = crc32fast::hash(&buf[CRC_OFFSET..]);
+        buf[LENGTH_OFFSET..CRC_OFFSET].copy_from_slice(&crc.to_be_bytes());
+        buf
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The decoded framing header of an on disk entry.
+pub(super) struct Header {
+    pub base_offset: u64,
+    pub length: u32,
+    pub crc: u32,
+    pub record_count: u32,
+    pub max_timestamp: i64,
+}
+
+impl Header {
+    /// Decode a header from the supplied buffer.
+    pub fn decode(buf: &[u8; HEADER_SIZE]) -> Header {
+        Header {
+            base_offset: u64::from_be_bytes(buf[0..8].try_into().unwrap()),
+            length: u32::from_be_bytes(buf[8..12].try_into().unwrap()),
+            crc: u32::from_be_bytes(buf[12..16].try_into().unwrap()),
+            record_count: u32::from_be_bytes(buf[16..20].try_into().unwrap()),
+            max_timestamp: i64::from_be_bytes(buf[20..28].try_into().unwrap()),
+        }
+    }
+
+    /// Returns whether or not the length field describes a well formed entry.
+    pub fn is_sane(&self) -> bool {
+        self.length as usize >= HEADER_SIZE - LENGTH_OFFSET && self.record_count > 0
+    }
+
+    /// Returns the total on disk size of the entry this header describes.
+    pub fn entry_size(&self) -> u64 {
+        LENGTH_OFFSET as u64 + self.length as u64
+    }
+
+    /// Returns the length of the payload following this header.
+    pub fn payload_size(&self) -> usize {
+        self.length as usize - (HEADER_SIZE - LENGTH_OFFSET)
+    }
+
+    /// Returns the offset that will be assigned to the entry following this one.
+    pub fn next_offset(&self) -> u64 {
+        self.base_offset + self.record_count as u64
+    }
+
+    /// Validates the supplied payload against this header's crc, returning the
+    /// resulting entry on success.
+    pub fn verify(&self, payload: Vec<u8>) -> Option<Entry> {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&self.record_count.to_be_bytes());
+        hasher.update(&self.max_timestamp.to_be_bytes());
+        hasher.update(&payload);
+        if hasher.finalize() != self.crc {
+            return None;
+        }
+        Some(self.into_entry(payload))
+    }
+
+    /// Combine this header with the supplied payload without validation.
+    pub fn into_entry(self, payload: Vec<u8>) -> Entry {
+        Entry {
+            base_offset: self.base_offset,
+            record_count: self.record_count,
+            max_timestamp: self.max_timestamp,
+            payload,
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let mut entry = Entry::new(2, 1234, b"hello".to_vec());
+        entry.base_offset = 42;
+
+        let buf = entry.encode();
+        assert_eq!(entry.size(), buf.len());
+
+        let header = Header::decode(buf[..HEADER_SIZE].try_into().unwrap());
+        assert!(header.is_sane());
+        assert_eq!(42, header.base_offset);
+        assert_eq!(2, header.record_count);
+        assert_eq!(1234, header.max_timestamp);
+        assert_eq!(buf.len() as u64, header.entry_size());
+        assert_eq!(5, header.payload_size());
+        assert_eq!(44, header.next_offset());
+
+        let decoded = header
+            .verify(buf[HEADER_SIZE..].to_vec())
+            .expect("crc should match");
+        assert_eq!(entry, decoded);
+    }
+
+    #[test]
+    fn test_verify_corrupt() {
+        let entry = Entry::new(1, 0, b"hello".to_vec());
+        let mut buf = entry.encode();
+        let last = buf.len() - 1;
+        buf[last] ^= 0xff;
+
+        let header = Header::decode(buf[..HEADER_SIZE].try_into().unwrap());
+        assert!(header.verify(buf[HEADER_SIZE..].to_vec()).is_none());
+    }
+
+    #[test]
+    fn test_insane_header() {
+        let header = Header::decode(&[0; HEADER_SIZE]);
+        assert!(!header.is_sane());
+    }
+}
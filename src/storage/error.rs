@@ -0,0 +1,92 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{io, path::PathBuf, result};
+
+use thiserror::Error;
+
+/// Custom Result wrapper to simplify usage.
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents storage errors, covering both OS level failures and invalid
+/// requests against the on disk log.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// Handles OS level errors while interacting with segment or index files.
+    #[error("i/o error on '{path}': {source}")]
+    Io {
+        /// The file or directory being operated on.
+        path: PathBuf,
+        /// The initial error cause.
+        source: io::Error,
+    },
+    /// Handles reads targeting an offset that is not contained in the log.
+    #[error("offset {offset} is out of range, log spans [{start}, {end})")]
+    OffsetOutOfRange {
+        /// The offset that was requested.
+        offset: u64,
+        /// The first offset contained in the log.
+        start: u64,
+        /// The next offset to be written to the log.
+        end: u64,
+    },
+    /// Handles entries that are larger than the configured segment size.
+    #[error("entry of {size} bytes exceeds the maximum segment size of {max} bytes")]
+    EntryTooLarge {
+        /// The size of the rejected entry.
+        size: usize,
+        /// The configured maximum segment size.
+        max: u64,
+    },
+    /// Handles entries that do not contain any records.
+    #[error("entries must contain at least one record")]
+    EmptyEntry,
+    /// Handles invalid log configuration values.
+    #[error("invalid storage configuration: {reason}")]
+    InvalidConfig {
+        /// Why the configuration was rejected.
+        reason: String,
+    },
+}
+
+impl Error {
+    /// Wraps the supplied [io::Error] with the path that was being operated on.
+    ///
+    /// ```
+    /// # use librift::storage;
+    /// let err = storage::Error::io("/tmp/rift", std::io::ErrorKind::NotFound.into());
+    /// assert!(matches!(err, storage::Error::Io { .. }));
+    /// ```
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Error {
+        Error::Io {
+            path: path.into(),
+            source,
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io() {
+        let err = Error::io("/tmp/rift", io::ErrorKind::NotFound.into());
+        assert!(matches!(err, Error::Io { ref path, .. } if path == &PathBuf::from("/tmp/rift")));
+        assert!(format!("{}", err).starts_with("i/o error on '/tmp/rift'"));
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let err = Error::OffsetOutOfRange {
+            offset: 10,
+            start: 0,
+            end: 5,
+        };
+        assert_eq!(
+            "offset 10 is out of range, log spans [0, 5)",
+            format!("{}", err)
+        );
+    }
+}
@@ -0,0 +1,174 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    convert::TryInto,
+    fs::{File, OpenOptions},
+    io::Read,
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+};
+
+use super::config::INDEX_ENTRY_SIZE;
+use super::error::{Error, Result};
+
+/// A sparse mapping of offsets, relative to a segment's base offset, to the
+/// byte position of the entry containing that offset within the segment file.
+///
+/// Entries are persisted as pairs of big endian u32 values and mirrored in
+/// memory so lookups never touch the disk.
+pub(super) struct Index {
+    path: PathBuf,
+    file: File,
+    entries: Vec<(u32, u32)>,
+}
+
+impl Index {
+    /// Open, or create if missing, the index file at the supplied path. Any
+    /// trailing partial entry is discarded.
+    pub fn open(path: &Path) -> Result<Index> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| Error::io(path, e))?;
+
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf).map_err(|e| Error::io(path, e))?;
+
+        let whole = buf.len() - buf.len() % INDEX_ENTRY_SIZE as usize;
+        if whole != buf.len() {
+            file.set_len(whole as u64).map_err(|e| Error::io(path, e))?;
+        }
+
+        let entries = buf[..whole]
+            .as_chunks::<{ INDEX_ENTRY_SIZE as usize }>()
+            .0
+            .iter()
+            .map(|chunk| {
+                (
+                    u32::from_be_bytes(chunk[0..4].try_into().unwrap()),
+                    u32::from_be_bytes(chunk[4..8].try_into().unwrap()),
+                )
+            })
+            .collect();
+
+        Ok(Index {
+            path: path.to_owned(),
+            file,
+            entries,
+        })
+    }
+
+    /// Returns the number of entries held in this index.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns the last entry held in this index, if any.
+    pub fn last(&self) -> Option<(u32, u32)> {
+        self.entries.last().copied()
+    }
+
+    /// Append a new entry mapping the relative offset to the byte position.
+    pub fn append(&mut self, relative_offset: u32, position: u32) -> Result<()> {
+        let mut buf = [0; INDEX_ENTRY_SIZE as usize];
+        buf[0..4].copy_from_slice(&relative_offset.to_be_bytes());
+        buf[4..8].copy_from_slice(&position.to_be_bytes());
+
+        let at = self.entries.len() as u64 * INDEX_ENTRY_SIZE;
+        self.file
+            .write_all_at(&buf, at)
+            .map_err(|e| Error::io(&self.path, e))?;
+        self.entries.push((relative_offset, position));
+        Ok(())
+    }
+
+    /// Returns the entry with the largest relative offset less than or equal to
+    /// the supplied relative offset, or the start of the segment if there is none.
+    pub fn lookup(&self, relative_offset: u32) -> (u32, u32) {
+        let idx = self
+            .entries
+            .partition_point(|(offset, _)| *offset <= relative_offset);
+        if idx == 0 {
+            (0, 0)
+        } else {
+            self.entries[idx - 1]
+        }
+    }
+
+    /// Discard all but the first `len` entries.
+    pub fn truncate(&mut self, len: usize) -> Result<()> {
+        if len >= self.entries.len() {
+            return Ok(());
+        }
+        self.entries.truncate(len);
+        self.file
+            .set_len(len as u64 * INDEX_ENTRY_SIZE)
+            .map_err(|e| Error::io(&self.path, e))
+    }
+
+    /// Flush the index file to durable storage.
+    pub fn sync(&self) -> Result<()> {
+        self.file.sync_data().map_err(|e| Error::io(&self.path, e))
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_lookup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("0.index");
+
+        let mut index = Index::open(&path).unwrap();
+        assert_eq!((0, 0), index.lookup(5));
+
+        index.append(10, 100).unwrap();
+        index.append(20, 200).unwrap();
+        assert_eq!(2, index.len());
+        assert_eq!((0, 0), index.lookup(5));
+        assert_eq!((10, 100), index.lookup(10));
+        assert_eq!((10, 100), index.lookup(19));
+        assert_eq!((20, 200), index.lookup(1000));
+
+        let index = Index::open(&path).unwrap();
+        assert_eq!(2, index.len());
+        assert_eq!(Some((20, 200)), index.last());
+    }
+
+    #[test]
+    fn test_partial_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("0.index");
+
+        let mut index = Index::open(&path).unwrap();
+        index.append(1, 1).unwrap();
+        index.file.write_all_at(&[1, 2, 3], 8).unwrap();
+        drop(index);
+
+        let index = Index::open(&path).unwrap();
+        assert_eq!(1, index.len());
+        assert_eq!(8, std::fs::metadata(&path).unwrap().len());
+    }
+
+    #[test]
+    fn test_truncate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("0.index");
+
+        let mut index = Index::open(&path).unwrap();
+        index.append(1, 10).unwrap();
+        index.append(2, 20).unwrap();
+        index.append(3, 30).unwrap();
+
+        index.truncate(1).unwrap();
+        assert_eq!(1, index.len());
+        assert_eq!(8, std::fs::metadata(&path).unwrap().len());
+    }
+}
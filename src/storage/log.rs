@@ -0,0 +1,837 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use super::compaction::{self, Compaction};
+use super::config::{FlushPolicy, LogConfig};
+use super::entry::Entry;
+use super::error::{Error, Result};
+use super::segment::{segment_path, Segment, INDEX_EXT, LOG_EXT, TIME_INDEX_EXT};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The retention limit that caused a segment to be deleted.
+pub enum RetentionLimit {
+    /// The segment's newest record was older than the log's retention period.
+    Time,
+    /// The log exceeded its maximum size.
+    Size,
+}
+
+impl RetentionLimit {
+    /// Returns a short name for this limit, suitable for logs and metric labels.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RetentionLimit::Time => "time",
+            RetentionLimit::Size => "size",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Describes a segment removed from the log by [Log::apply_retention].
+pub struct DeletedSegment {
+    /// The offset of the first record in the segment.
+    pub base_offset: u64,
+    /// The size in bytes of the segment's data file.
+    pub size: u64,
+    /// The limit that caused the segment to be deleted.
+    pub limit: RetentionLimit,
+}
+
+/// An append-only log of entries split across fixed size segment files.
+///
+/// Every record appended to the log is assigned a monotonically increasing
+/// offset. Only the last, or active, segment is ever written to; once it fills
+/// up it is flushed and a new segment is rolled starting at the next offset.
+pub struct Log {
+    dir: PathBuf,
+    config: LogConfig,
+    segments: Vec<Segment>,
+    compacted_offset: u64,
+    unflushed: u64,
+    last_flush: Instant,
+}
+
+impl Log {
+    /// Open, or create if missing, the log stored in the supplied directory.
+    ///
+    /// The active segment is always recovered on open, which validates every
+    /// entry and truncates any torn or corrupt writes left behind by a crash.
+    ///
+    /// ```
+    /// # use librift::storage::{Entry, Log, LogConfig};
+    /// # let dir = tempfile::tempdir().unwrap();
+    /// let mut log = Log::open(dir.path(), LogConfig::default()).unwrap();
+    /// let offset = log.append(Entry::new(1, 0, b"hello".to_vec())).unwrap();
+    /// assert_eq!(0, offset);
+    ///
+    /// let entries = log.read(offset, 1024).unwrap();
+    /// assert_eq!(b"hello".to_vec(), entries[0].payload);
+    /// ```
+    pub fn open(dir: impl Into<PathBuf>, config: LogConfig) -> Result<Log> {
+        config.validate()?;
+        let dir = dir.into();
+        fs::create_dir_all(&dir).map_err(|e| Error::io(&dir, e))?;
+        compaction::recover(&dir)?;
+        let compacted_offset = compaction::compacted_offset(&dir)?;
+
+        let mut log_offsets = Vec::new();
+        let mut index_offsets = Vec::new();
+        for dirent in fs::read_dir(&dir).map_err(|e| Error::io(&dir, e))? {
+            let path = dirent.map_err(|e| Error::io(&dir, e))?.path();
+            let base_offset = match path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<u64>().ok())
+            {
+                Some(base_offset) => base_offset,
+                None => continue,
+            };
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some(LOG_EXT) => log_offsets.push(base_offset),
+                Some(INDEX_EXT) => index_offsets.push((base_offset, INDEX_EXT)),
+                Some(TIME_INDEX_EXT) => index_offsets.push((base_offset, TIME_INDEX_EXT)),
+                _ => continue,
+            }
+        }
+
+        for (orphan, ext) in index_offsets
+            .into_iter()
+            .filter(|(offset, _)| !log_offsets.contains(offset))
+        {
+            let path = segment_path(&dir, orphan, ext);
+            fs::remove_file(&path).map_err(|e| Error::io(path, e))?;
+        }
+
+        log_offsets.sort_unstable();
+        if log_offsets.is_empty() {
+            log_offsets.push(0);
+        }
+
+        let last = log_offsets.len() - 1;
+        let segments = log_offsets
+            .into_iter()
+            .enumerate()
+            .map(|(idx, base_offset)| Segment::open(&dir, base_offset, idx == last, &config))
+            .collect::<Result<Vec<Segment>>>()?;
+
+        Ok(Log {
+            dir,
+            config,
+            segments,
+            compacted_offset,
+            unflushed: 0,
+            last_flush: Instant::now(),
+        })
+    }
+
+    /// Returns the directory this log is stored in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the configuration this log was opened with.
+    pub fn config(&self) -> &LogConfig {
+        &self.config
+    }
+
+    /// Returns the offset of the first record retained by this log.
+    pub fn start_offset(&self) -> u64 {
+        self.segments[0].base_offset()
+    }
+
+    /// Returns the offset that will be assigned to the next appended record.
+    pub fn next_offset(&self) -> u64 {
+        self.active().next_offset()
+    }
+
+    /// Returns the total size in bytes of all segments in this log.
+    pub fn size(&self) -> u64 {
+        self.segments.iter().map(|segment| segment.size()).sum()
+    }
+
+    /// Returns the number of segments that make up this log.
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Append the supplied entry to the log, rolling a new segment if the active
+    /// segment is full, and return the base offset assigned to the entry. The
+    /// log is flushed before returning if its [FlushPolicy] requires it.
+    pub fn append(&mut self, mut entry: Entry) -> Result<u64> {
+        entry.base_offset = self.next_offset();
+        self.append_at(entry)
+    }
+
+    /// Append an entry that has already been assigned its base offset, such as
+    /// one copied from another replica's log. The base offset may skip ahead of
+    /// the log's next offset, as compaction leaves gaps, but must not precede it.
+    pub fn append_at(&mut self, entry: Entry) -> Result<u64> {
+        if entry.record_count == 0 {
+            return Err(Error::EmptyEntry);
+        }
+        let size = entry.size() as u64;
+        if size > self.config.segment_bytes {
+            return Err(Error::EntryTooLarge {
+                size: entry.size(),
+                max: self.config.segment_bytes,
+            });
+        }
+        if entry.base_offset < self.next_offset() {
+            return Err(Error::OffsetOutOfRange {
+                offset: entry.base_offset,
+                start: self.start_offset(),
+                end: self.next_offset(),
+            });
+        }
+
+        if self.active().is_full(size, &self.config)
+            || entry.next_offset() - self.active().base_offset() > u32::MAX as u64
+        {
+            self.roll()?;
+        }
+
+        let config = self.config.clone();
+        self.active_mut().append(&entry, &config)?;
+        self.unflushed += entry.record_count as u64;
+        self.flush_if_due(Instant::now())?;
+        Ok(entry.base_offset)
+    }
+
+    /// Read entries starting with the one containing the supplied offset until
+    /// `max_bytes` is reached or the log is exhausted. At least one entry is
+    /// always returned when the offset is not the log's next offset.
+    pub fn read(&self, offset: u64, max_bytes: usize) -> Result<Vec<Entry>> {
+        let (start, end) = (self.start_offset(), self.next_offset());
+        if offset == end {
+            return Ok(Vec::new());
+        }
+        if offset < start || offset > end {
+            return Err(Error::OffsetOutOfRange { offset, start, end });
+        }
+
+        let first = self
+            .segments
+            .partition_point(|segment| segment.base_offset() <= offset)
+            - 1;
+
+        let mut entries: Vec<Entry> = Vec::new();
+        let mut remaining = max_bytes;
+        let mut offset = offset;
+        for segment in &self.segments[first..] {
+            // Compaction may leave gaps between segments.
+            let read = segment.read(offset.max(segment.base_offset()), remaining)?;
+            if read.is_empty() {
+                continue;
+            }
+            let size: usize = read.iter().map(|entry| entry.size()).sum();
+            offset = read.last().map(|entry| entry.next_offset()).unwrap();
+            entries.extend(read);
+            if size >= remaining || offset < segment.next_offset() {
+                break;
+            }
+            remaining -= size;
+        }
+        Ok(entries)
+    }
+
+    /// Delete the oldest closed segments that fall outside this log's retention
+    /// limits as of `now`, in milliseconds since the epoch. The active segment is
+    /// never deleted, and segments are only ever removed from the start of the log
+    /// so the retained offsets stay contiguous.
+    pub fn apply_retention(&mut self, now: i64) -> Result<Vec<DeletedSegment>> {
+        let mut deleted = Vec::new();
+        while self.config.cleanup_policy.delete && self.segments.len() > 1 {
+            let oldest = &self.segments[0];
+            let expired = self.config.retention_ms.is_some_and(|retention_ms| {
+                oldest.max_timestamp() < now.saturating_sub(retention_ms as i64)
+            });
+            let oversized = self
+                .config
+                .retention_bytes
+                .is_some_and(|retention_bytes| self.size() - oldest.size() >= retention_bytes);
+            let limit = if expired {
+                RetentionLimit::Time
+            } else if oversized {
+                RetentionLimit::Size
+            } else {
+                break;
+            };
+
+            let segment = self.segments.remove(0);
+            deleted.push(DeletedSegment {
+                base_offset: segment.base_offset(),
+                size: segment.size(),
+                limit,
+            });
+            segment.delete(&self.dir)?;
+        }
+        Ok(deleted)
+    }
+
+    /// Start compacting every closed segment, returning [None] if the log is not
+    /// compacted or no segment has been closed since the last compaction.
+    /// Appends may continue while the returned [Compaction] is filled.
+    pub fn begin_compaction(&self) -> Result<Option<Compaction>> {
+        let end_offset = self.active().base_offset();
+        let closed = &self.segments[..self.segments.len() - 1];
+        if !self.config.cleanup_policy.compact
+            || closed.is_empty()
+            || end_offset <= self.compacted_offset
+        {
+            return Ok(None);
+        }
+        Compaction::new(&self.dir, self.config.clone(), closed, end_offset).map(Some)
+    }
+
+    /// Swap the compacted segments into place and return the number of bytes
+    /// reclaimed. Returns [None], discarding the compaction, if the segments it
+    /// replaces were removed from the log while it was being filled.
+    pub fn finish_compaction(&mut self, compaction: Compaction) -> Result<Option<u64>> {
+        let replaced = compaction.replaced().len();
+        let unchanged = self.segments.len() > replaced
+            && self
+                .segments
+                .iter()
+                .map(Segment::base_offset)
+                .take(replaced)
+                .eq(compaction.replaced().iter().copied());
+        if !unchanged {
+            compaction.abort()?;
+            return Ok(None);
+        }
+
+        let end_offset = compaction.end_offset();
+        let (bases, reclaimed) = compaction.commit()?;
+        compaction::swap(&self.dir, end_offset, &bases)?;
+        self.segments.drain(..replaced);
+
+        let segments = bases
+            .into_iter()
+            .map(|base_offset| Segment::open(&self.dir, base_offset, false, &self.config))
+            .collect::<Result<Vec<Segment>>>()?;
+        self.segments.splice(..0, segments);
+        self.compacted_offset = end_offset;
+        Ok(Some(reclaimed))
+    }
+
+    /// Returns the first entry with a timestamp at or after the supplied
+    /// timestamp, or [None] if there is no such entry. An entry's timestamp is
+    /// the largest of its records, so records of the entry may be older.
+    /// Segments are skipped wholesale using their largest timestamp, and
+    /// searched from the position their time index points to.
+    pub fn entry_for_timestamp(&self, timestamp: i64) -> Result<Option<Entry>> {
+        for segment in &self.segments {
+            if let Some(entry) = segment.entry_for_timestamp(timestamp)? {
+                return Ok(Some(entry));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Flush the active segment to durable storage. Closed segments are flushed
+    /// as part of rolling.
+    pub fn flush(&mut self) -> Result<()> {
+        self.active().flush()?;
+        self.unflushed = 0;
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+
+    /// Flush the log if its [FlushPolicy] requires it as of `now`, returning
+    /// whether a flush took place. Time based policies rely on this being called
+    /// periodically to flush data even when no further appends arrive.
+    pub fn flush_if_due(&mut self, now: Instant) -> Result<bool> {
+        let due = self.unflushed > 0
+            && match self.config.flush_policy {
+                FlushPolicy::Always => true,
+                FlushPolicy::Messages(count) => self.unflushed >= count,
+                FlushPolicy::Interval(millis) => {
+                    now.saturating_duration_since(self.last_flush) >= Duration::from_millis(millis)
+                }
+                FlushPolicy::Os => false,
+            };
+        if due {
+            self.flush()?;
+        }
+        Ok(due)
+    }
+
+    /// Close the active segment and start a new one at the next offset.
+    pub fn roll(&mut self) -> Result<()> {
+        let next_offset = self.next_offset();
+        if self.active().is_empty() {
+            return Ok(());
+        }
+        self.flush()?;
+
+        let segment = Segment::open(&self.dir, next_offset, true, &self.config)?;
+        self.segments.push(segment);
+        Ok(())
+    }
+
+    /// Discard every entry with a base offset at or beyond the supplied offset.
+    pub fn truncate_to(&mut self, offset: u64) -> Result<()> {
+        while self.segments.len() > 1 && self.active().base_offset() >= offset {
+            let segment = self.segments.pop().unwrap();
+            segment.delete(&self.dir)?;
+        }
+        let config = self.config.clone();
+        self.active_mut().truncate_to(offset, &config)
+    }
+
+    /// Discard every entry and restart the log empty at the supplied offset.
+    pub fn reset(&mut self, start_offset: u64) -> Result<()> {
+        while let Some(segment) = self.segments.pop() {
+            segment.delete(&self.dir)?;
+        }
+        let segment = Segment::open(&self.dir, start_offset, true, &self.config)?;
+        self.segments.push(segment);
+        compaction::clear_compacted_offset(&self.dir)?;
+        self.compacted_offset = 0;
+        self.unflushed = 0;
+        Ok(())
+    }
+
+    fn active(&self) -> &Segment {
+        self.segments.last().unwrap()
+    }
+
+    fn active_mut(&mut self) -> &mut Segment {
+        self.segments.last_mut().unwrap()
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use std::{fs::OpenOptions, io::Write};
+
+    use crate::storage::CleanupPolicy;
+
+    use super::*;
+
+    fn small_config() -> LogConfig {
+        LogConfig {
+            segment_bytes: 256,
+            index_bytes: 1024,
+            index_interval_bytes: 64,
+            ..Default::default()
+        }
+    }
+
+    fn compacted_config() -> LogConfig {
+        LogConfig {
+            cleanup_policy: CleanupPolicy::COMPACT,
+            ..small_config()
+        }
+    }
+
+    fn payload(idx: usize) -> Vec<u8> {
+        format!("message-{:04}", idx).into_bytes()
+    }
+
+    #[test]
+    fn test_append_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = Log::open(dir.path(), small_config()).unwrap();
+        assert_eq!(0, log.start_offset());
+        assert_eq!(0, log.next_offset());
+        assert!(log.read(0, 1024).unwrap().is_empty());
+
+        for idx in 0..50 {
+            let offset = log.append(Entry::new(1, idx as i64, payload(idx))).unwrap();
+            assert_eq!(idx as u64, offset);
+        }
+        assert_eq!(50, log.next_offset());
+        assert!(log.segment_count() > 1);
+
+        for idx in 0..50 {
+            let entries = log.read(idx as u64, 1).unwrap();
+            assert_eq!(1, entries.len());
+            assert_eq!(idx as u64, entries[0].base_offset);
+            assert_eq!(payload(idx), entries[0].payload);
+        }
+
+        let entries = log.read(0, usize::MAX).unwrap();
+        assert_eq!(50, entries.len());
+
+        assert!(matches!(
+            log.read(51, 1024),
+            Err(Error::OffsetOutOfRange {
+                offset: 51,
+                start: 0,
+                end: 50
+            })
+        ));
+    }
+
+    #[test]
+    fn test_entry_for_timestamp() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = Log::open(dir.path(), small_config()).unwrap();
+        let offset = |log: &Log, timestamp| {
+            log.entry_for_timestamp(timestamp)
+                .unwrap()
+                .map_or(log.next_offset(), |entry| entry.base_offset)
+        };
+        assert_eq!(0, offset(&log, 0));
+
+        for idx in 0..50 {
+            log.append(Entry::new(2, idx as i64 * 10, payload(idx)))
+                .unwrap();
+        }
+        assert!(log.segment_count() > 1);
+
+        assert_eq!(0, offset(&log, -5));
+        assert_eq!(0, offset(&log, 0));
+        assert_eq!(62, offset(&log, 305));
+        assert_eq!(62, offset(&log, 310));
+        assert_eq!(100, offset(&log, 1000));
+
+        // Timestamps need not increase, the first entry at or after the
+        // timestamp is found either way.
+        for idx in 0..50 {
+            let timestamp = if idx == 30 { 2000 } else { 1000 + idx as i64 };
+            log.append(Entry::new(1, timestamp, payload(idx))).unwrap();
+        }
+        assert_eq!(100, offset(&log, 1000));
+        assert_eq!(110, offset(&log, 1010));
+        assert_eq!(130, offset(&log, 1031));
+        assert_eq!(130, offset(&log, 2000));
+        assert_eq!(150, offset(&log, 2001));
+
+        // Time indexes are persisted, and rebuilt if missing.
+        drop(log);
+        let log = Log::open(dir.path(), small_config()).unwrap();
+        assert_eq!(110, offset(&log, 1010));
+        let path = segment_path(dir.path(), 0, TIME_INDEX_EXT);
+        fs::remove_file(&path).unwrap();
+        let log = Log::open(dir.path(), small_config()).unwrap();
+        assert!(path.exists());
+        assert_eq!(62, offset(&log, 305));
+        assert_eq!(130, offset(&log, 1031));
+    }
+
+    #[test]
+    fn test_retention_by_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = LogConfig {
+            retention_ms: Some(100),
+            ..small_config()
+        };
+        let mut log = Log::open(dir.path(), cfg).unwrap();
+        for idx in 0..50 {
+            log.append(Entry::new(1, idx as i64 * 10, payload(idx)))
+                .unwrap();
+        }
+        let segments = log.segment_count();
+        assert!(log.apply_retention(0).unwrap().is_empty());
+
+        let deleted = log.apply_retention(400).unwrap();
+        assert!(!deleted.is_empty());
+        assert!(deleted.iter().all(|d| d.limit == RetentionLimit::Time));
+        assert_eq!(segments - deleted.len(), log.segment_count());
+        assert_eq!(
+            log.start_offset(),
+            log.read(log.start_offset(), 1).unwrap()[0].base_offset
+        );
+        assert!(log.start_offset() <= 30);
+        assert!(log.read(0, 1).is_err());
+
+        // The active segment is always retained.
+        log.apply_retention(i64::MAX).unwrap();
+        assert_eq!(1, log.segment_count());
+        assert_eq!(50, log.next_offset());
+        drop(log);
+
+        let log = Log::open(dir.path(), small_config()).unwrap();
+        assert_eq!(1, log.segment_count());
+        assert_eq!(50, log.next_offset());
+    }
+
+    #[test]
+    fn test_retention_by_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = LogConfig {
+            retention_ms: None,
+            retention_bytes: Some(512),
+            ..small_config()
+        };
+        let mut log = Log::open(dir.path(), cfg).unwrap();
+        for idx in 0..50 {
+            log.append(Entry::new(1, 0, payload(idx))).unwrap();
+        }
+
+        let deleted = log.apply_retention(i64::MAX).unwrap();
+        assert!(!deleted.is_empty());
+        assert!(deleted.iter().all(|d| d.limit == RetentionLimit::Size));
+        assert!(log.size() >= 512);
+        assert!(log.size() - log.segments[0].size() < 512);
+        assert!(log.apply_retention(i64::MAX).unwrap().is_empty());
+    }
+
+    fn compact_even(log: &Log) -> Compaction {
+        let mut compaction = log.begin_compaction().unwrap().unwrap();
+        let mut offset = compaction.start_offset();
+        while offset < compaction.end_offset() {
+            for entry in log.read(offset, 1).unwrap() {
+                if entry.base_offset >= compaction.end_offset() {
+                    break;
+                }
+                if entry.base_offset % 2 == 0 {
+                    compaction.append(&entry).unwrap();
+                }
+                offset = entry.next_offset();
+            }
+        }
+        compaction
+    }
+
+    #[test]
+    fn test_compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = Log::open(dir.path(), small_config()).unwrap();
+        for idx in 0..50 {
+            log.append(Entry::new(1, idx as i64, payload(idx))).unwrap();
+        }
+        assert!(log.begin_compaction().unwrap().is_none());
+        drop(log);
+
+        let mut log = Log::open(dir.path(), compacted_config()).unwrap();
+        let size = log.size();
+
+        let compaction = compact_even(&log);
+        let end_offset = compaction.end_offset();
+        log.append(Entry::new(1, 50, payload(50))).unwrap();
+
+        let reclaimed = log.finish_compaction(compaction).unwrap().unwrap();
+        assert!(reclaimed > 0);
+        assert!(log.size() < size);
+        assert_eq!(0, log.start_offset());
+        assert_eq!(51, log.next_offset());
+        assert!(log.begin_compaction().unwrap().is_none());
+        assert!(!dir.path().join(compaction::CLEANING_DIR).exists());
+
+        let check = |log: &Log| {
+            let entries = log.read(0, usize::MAX).unwrap();
+            let offsets: Vec<u64> = entries.iter().map(|entry| entry.base_offset).collect();
+            let expected: Vec<u64> = (0..end_offset)
+                .filter(|offset| offset % 2 == 0)
+                .chain(end_offset..51)
+                .collect();
+            assert_eq!(expected, offsets);
+            assert_eq!(payload(2), log.read(1, 1).unwrap()[0].payload);
+        };
+        check(&log);
+        drop(log);
+
+        // Segments compacted before a restart are not compacted again.
+        let log = Log::open(dir.path(), compacted_config()).unwrap();
+        check(&log);
+        assert!(log.begin_compaction().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_compaction_recovery() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = Log::open(dir.path(), compacted_config()).unwrap();
+        for idx in 0..50 {
+            log.append(Entry::new(1, idx as i64, payload(idx))).unwrap();
+        }
+
+        // A compaction abandoned before its swap marker is written is discarded.
+        drop(compact_even(&log));
+        let log = Log::open(dir.path(), compacted_config()).unwrap();
+        assert_eq!(50, log.read(0, usize::MAX).unwrap().len());
+        assert!(!dir.path().join(compaction::CLEANING_DIR).exists());
+
+        // A compaction that crashed after its swap marker is rolled forward.
+        let compaction = compact_even(&log);
+        let end_offset = compaction.end_offset();
+        compaction.commit().unwrap();
+        drop(log);
+        assert!(dir.path().join(compaction::CLEANING_DIR).exists());
+        let log = Log::open(dir.path(), compacted_config()).unwrap();
+        let entries = log.read(0, usize::MAX).unwrap();
+        assert!(entries.len() < 50);
+        assert!(entries
+            .iter()
+            .all(|entry| entry.base_offset % 2 == 0 || entry.base_offset >= end_offset));
+        assert_eq!(50, log.next_offset());
+    }
+
+    #[test]
+    fn test_multi_record_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = Log::open(dir.path(), compacted_config()).unwrap();
+
+        assert_eq!(0, log.append(Entry::new(5, 0, payload(0))).unwrap());
+        assert_eq!(5, log.append(Entry::new(3, 0, payload(1))).unwrap());
+        assert_eq!(8, log.next_offset());
+
+        let entries = log.read(6, 1).unwrap();
+        assert_eq!(5, entries[0].base_offset);
+        assert_eq!(7, entries[0].last_offset());
+    }
+
+    #[test]
+    fn test_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = Log::open(dir.path(), compacted_config()).unwrap();
+        for idx in 0..50 {
+            log.append(Entry::new(1, idx as i64, payload(idx))).unwrap();
+        }
+        let segments = log.segment_count();
+        drop(log);
+
+        let mut log = Log::open(dir.path(), compacted_config()).unwrap();
+        assert_eq!(segments, log.segment_count());
+        assert_eq!(50, log.next_offset());
+        assert_eq!(50, log.append(Entry::new(1, 0, payload(50))).unwrap());
+        let entries = log.read(25, 1).unwrap();
+        assert_eq!(payload(25), entries[0].payload);
+    }
+
+    #[test]
+    fn test_recover_torn_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = Log::open(dir.path(), LogConfig::default()).unwrap();
+        for idx in 0..10 {
+            log.append(Entry::new(1, 0, payload(idx))).unwrap();
+        }
+        let size = log.size();
+        drop(log);
+
+        let mut partial = Entry::new(1, 0, payload(10));
+        partial.base_offset = 10;
+        let buf = partial.encode();
+        let path = segment_path(dir.path(), 0, LOG_EXT);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&buf[..buf.len() / 2]).unwrap();
+        drop(file);
+
+        let log = Log::open(dir.path(), LogConfig::default()).unwrap();
+        assert_eq!(10, log.next_offset());
+        assert_eq!(size, log.size());
+        assert_eq!(size, fs::metadata(&path).unwrap().len());
+    }
+
+    #[test]
+    fn test_recover_corrupt_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = Log::open(dir.path(), LogConfig::default()).unwrap();
+        let mut positions = Vec::new();
+        for idx in 0..10 {
+            positions.push(log.size());
+            log.append(Entry::new(1, 0, payload(idx))).unwrap();
+        }
+        drop(log);
+
+        let path = segment_path(dir.path(), 0, LOG_EXT);
+        let mut data = fs::read(&path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        fs::write(&path, data).unwrap();
+
+        let log = Log::open(dir.path(), LogConfig::default()).unwrap();
+        assert_eq!(9, log.next_offset());
+        assert_eq!(positions[9], log.size());
+    }
+
+    #[test]
+    fn test_flush_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = |flush_policy| LogConfig {
+            flush_policy,
+            ..small_config()
+        };
+
+        let mut log = Log::open(dir.path().join("always"), config(FlushPolicy::Always)).unwrap();
+        log.append(Entry::new(1, 0, payload(0))).unwrap();
+        assert_eq!(0, log.unflushed);
+
+        let mut log = Log::open(
+            dir.path().join("messages"),
+            config(FlushPolicy::Messages(3)),
+        )
+        .unwrap();
+        log.append(Entry::new(2, 0, payload(0))).unwrap();
+        assert_eq!(2, log.unflushed);
+        log.append(Entry::new(1, 0, payload(1))).unwrap();
+        assert_eq!(0, log.unflushed);
+
+        let mut log = Log::open(
+            dir.path().join("interval"),
+            config(FlushPolicy::Interval(1000)),
+        )
+        .unwrap();
+        log.append(Entry::new(1, 0, payload(0))).unwrap();
+        let now = Instant::now();
+        assert!(!log.flush_if_due(now).unwrap());
+        assert!(log.flush_if_due(now + Duration::from_secs(2)).unwrap());
+        assert!(!log.flush_if_due(now + Duration::from_secs(4)).unwrap());
+
+        let mut log = Log::open(dir.path().join("os"), config(FlushPolicy::Os)).unwrap();
+        log.append(Entry::new(1, 0, payload(0))).unwrap();
+        assert!(!log.flush_if_due(now + Duration::from_secs(4)).unwrap());
+        assert_eq!(1, log.unflushed);
+    }
+
+    #[test]
+    fn test_truncate_to() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = Log::open(dir.path(), small_config()).unwrap();
+        for idx in 0..50 {
+            log.append(Entry::new(1, 0, payload(idx))).unwrap();
+        }
+
+        log.truncate_to(5).unwrap();
+        assert_eq!(5, log.next_offset());
+        assert_eq!(5, log.append(Entry::new(1, 0, payload(99))).unwrap());
+        assert_eq!(payload(99), log.read(5, 1).unwrap()[0].payload);
+        assert_eq!(payload(4), log.read(4, 1).unwrap()[0].payload);
+    }
+
+    #[test]
+    fn test_append_at_reset() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = Log::open(dir.path(), small_config()).unwrap();
+        log.append(Entry::new(2, 0, payload(0))).unwrap();
+
+        let mut entry = Entry::new(1, 0, payload(1));
+        entry.base_offset = 1;
+        assert!(matches!(
+            log.append_at(entry.clone()),
+            Err(Error::OffsetOutOfRange { offset: 1, .. })
+        ));
+        entry.base_offset = 5;
+        assert_eq!(5, log.append_at(entry).unwrap());
+        assert_eq!(6, log.next_offset());
+        assert_eq!(payload(1), log.read(2, 1).unwrap()[0].payload);
+
+        log.reset(100).unwrap();
+        assert_eq!(100, log.start_offset());
+        assert_eq!(100, log.next_offset());
+        assert_eq!(1, log.segment_count());
+        assert_eq!(100, log.append(Entry::new(1, 0, payload(2))).unwrap());
+        drop(log);
+
+        let log = Log::open(dir.path(), small_config()).unwrap();
+        assert_eq!(100, log.start_offset());
+        assert_eq!(101, log.next_offset());
+    }
+
+    #[test]
+    fn test_entry_too_large() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = Log::open(dir.path(), small_config()).unwrap();
+        let res = log.append(Entry::new(1, 0, vec![0; 512]));
+        assert!(matches!(res, Err(Error::EntryTooLarge { .. })));
+    }
+}
@@ -0,0 +1,34 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::sync::OnceLock;
+
+use prometheus::Histogram;
+
+use crate::metrics::{register_histogram, Opt};
+
+/// The series exported by the storage layer.
+pub(super) struct Metrics {
+    /// The time taken to flush a segment to durable storage.
+    pub(super) fsync_seconds: Histogram,
+}
+
+/// Returns the process wide storage metrics, registering them on first use.
+pub(super) fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(|| {
+        let opts = vec![
+            Opt::Namespace(String::from("rift")),
+            Opt::Subsystem(String::from("storage")),
+            Opt::exponential_buckets(0.0001, 2.0, 16),
+        ];
+        Metrics {
+            fsync_seconds: register_histogram(
+                "fsync_seconds",
+                "The time taken to flush a log segment to durable storage.",
+                Some(opts),
+            )
+            .expect("storage metrics registered twice"),
+        }
+    })
+}
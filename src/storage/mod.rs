@@ -0,0 +1,18 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod compaction;
+mod config;
+mod entry;
+mod error;
+mod index;
+mod log;
+mod metrics;
+mod segment;
+mod time_index;
+
+pub use self::compaction::Compaction;
+pub use self::config::{retention_limit, CleanupPolicy, Config, FlushPolicy, LogConfig};
+pub use self::entry::{Entry, HEADER_SIZE};
+pub use self::error::{Error, Result};
+pub use self::log::{DeletedSegment, Log, RetentionLimit};
@@ -0,0 +1,351 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::ErrorKind,
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+};
+
+use super::config::LogConfig;
+use super::entry::{Entry, Header, HEADER_SIZE};
+use super::error::{Error, Result};
+use super::index::Index;
+use super::metrics::metrics;
+use super::time_index::TimeIndex;
+
+/// The file extension used for segment data files.
+pub(super) const LOG_EXT: &str = "log";
+/// The file extension used for segment offset index files.
+pub(super) const INDEX_EXT: &str = "index";
+/// The file extension used for segment time index files.
+pub(super) const TIME_INDEX_EXT: &str = "timeindex";
+
+/// Returns the path of the file with the given extension for the segment
+/// starting at the supplied base offset.
+pub(super) fn segment_path(dir: &Path, base_offset: u64, ext: &str) -> PathBuf {
+    dir.join(format!("{:020}.{}", base_offset, ext))
+}
+
+/// A single contiguous slice of the log, backed by a data file, a sparse
+/// offset index, and a sparse time index.
+pub(super) struct Segment {
+    base_offset: u64,
+    next_offset: u64,
+    max_timestamp: i64,
+    size: u64,
+    bytes_since_index: u64,
+    log_path: PathBuf,
+    file: File,
+    index: Index,
+    time_index: TimeIndex,
+}
+
+impl Segment {
+    /// Open, or create if missing, the segment starting at the supplied base offset.
+    ///
+    /// When `recover` is set every entry is validated against its crc and the
+    /// segment is truncated at the first torn or corrupt entry, with the indexes
+    /// rebuilt from scratch, as they also are if either is missing. Otherwise
+    /// only the entry headers are scanned to recover the segment's bookkeeping.
+    pub fn open(dir: &Path, base_offset: u64, recover: bool, cfg: &LogConfig) -> Result<Segment> {
+        let log_path = segment_path(dir, base_offset, LOG_EXT);
+        let index_path = segment_path(dir, base_offset, INDEX_EXT);
+        let time_index_path = segment_path(dir, base_offset, TIME_INDEX_EXT);
+        let rebuild = recover || !index_path.exists() || !time_index_path.exists();
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&log_path)
+            .map_err(|e| Error::io(&log_path, e))?;
+        let size = file.metadata().map_err(|e| Error::io(&log_path, e))?.len();
+
+        let index = Index::open(&index_path)?;
+        let time_index = TimeIndex::open(&time_index_path)?;
+        let mut segment = Segment {
+            base_offset,
+            next_offset: base_offset,
+            max_timestamp: -1,
+            size,
+            bytes_since_index: 0,
+            log_path,
+            file,
+            index,
+            time_index,
+        };
+
+        if rebuild {
+            segment.recover(cfg)?;
+        } else {
+            segment.scan()?;
+        }
+        Ok(segment)
+    }
+
+    /// Returns the offset of the first record in this segment.
+    pub fn base_offset(&self) -> u64 {
+        self.base_offset
+    }
+
+    /// Returns the offset that will be assigned to the next appended record.
+    pub fn next_offset(&self) -> u64 {
+        self.next_offset
+    }
+
+    /// Returns the size in bytes of this segment's data file.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the largest timestamp of any entry in this segment, or -1 if empty.
+    pub fn max_timestamp(&self) -> i64 {
+        self.max_timestamp
+    }
+
+    /// Returns whether or not this segment holds any entries.
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Returns whether or not appending an entry of the given size would overflow
+    /// this segment's configured limits.
+    pub fn is_full(&self, entry_size: u64, cfg: &LogConfig) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        self.size + entry_size > cfg.segment_bytes
+            || self.index.len() >= cfg.max_index_entries()
+            || self.next_offset - self.base_offset > u32::MAX as u64
+    }
+
+    /// Append the supplied entry, which must already have its base offset assigned.
+    pub fn append(&mut self, entry: &Entry, cfg: &LogConfig) -> Result<()> {
+        let buf = entry.encode();
+        self.file
+            .write_all_at(&buf, self.size)
+            .map_err(|e| Error::io(&self.log_path, e))?;
+
+        self.track(
+            entry.base_offset,
+            entry.next_offset(),
+            entry.max_timestamp,
+            buf.len() as u64,
+            cfg,
+        )
+    }
+
+    /// Read entries starting with the one containing the supplied offset, until
+    /// either `max_bytes` is reached or the segment is exhausted. The first
+    /// matching entry is always returned regardless of `max_bytes`.
+    pub fn read(&self, offset: u64, max_bytes: usize) -> Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        if offset < self.base_offset || offset >= self.next_offset {
+            return Ok(entries);
+        }
+
+        let (_, position) = self.index.lookup((offset - self.base_offset) as u32);
+        let mut position = position as u64;
+        let mut read = 0;
+        while position < self.size {
+            let header = self.read_header(position)?;
+            let entry_size = header.entry_size();
+            if header.next_offset() <= offset {
+                position += entry_size;
+                continue;
+            }
+            if !entries.is_empty() && read + entry_size as usize > max_bytes {
+                break;
+            }
+
+            let payload = self.read_payload(position, &header)?;
+            entries.push(header.into_entry(payload));
+            read += entry_size as usize;
+            position += entry_size;
+        }
+        Ok(entries)
+    }
+
+    /// Returns the first entry with a timestamp at or after the supplied
+    /// timestamp, or [None] if this segment holds no such entry. Only entry
+    /// headers are read, starting from the position the time index points to,
+    /// until the matching entry is found.
+    pub fn entry_for_timestamp(&self, timestamp: i64) -> Result<Option<Entry>> {
+        if self.max_timestamp < timestamp {
+            return Ok(None);
+        }
+
+        let (_, position) = self.index.lookup(self.time_index.lookup(timestamp));
+        let mut position = position as u64;
+        while position < self.size {
+            let header = self.read_header(position)?;
+            if header.max_timestamp >= timestamp {
+                let payload = self.read_payload(position, &header)?;
+                return Ok(Some(header.into_entry(payload)));
+            }
+            position += header.entry_size();
+        }
+        Ok(None)
+    }
+
+    /// Discard every entry with a base offset at or beyond the supplied offset.
+    pub fn truncate_to(&mut self, offset: u64, cfg: &LogConfig) -> Result<()> {
+        if offset >= self.next_offset {
+            return Ok(());
+        }
+
+        let (_, position) = self
+            .index
+            .lookup(offset.saturating_sub(self.base_offset) as u32);
+        let mut position = position as u64;
+        while position < self.size {
+            let header = self.read_header(position)?;
+            if header.base_offset >= offset {
+                break;
+            }
+            position += header.entry_size();
+        }
+
+        self.file
+            .set_len(position)
+            .map_err(|e| Error::io(&self.log_path, e))?;
+        self.size = position;
+        self.recover(cfg)
+    }
+
+    /// Flush the segment and its indexes to durable storage.
+    pub fn flush(&self) -> Result<()> {
+        let _timer = metrics().fsync_seconds.start_timer();
+        self.file
+            .sync_data()
+            .map_err(|e| Error::io(&self.log_path, e))?;
+        self.index.sync()?;
+        self.time_index.sync()
+    }
+
+    /// Remove this segment's files from disk.
+    pub fn delete(self, dir: &Path) -> Result<()> {
+        for ext in [LOG_EXT, INDEX_EXT, TIME_INDEX_EXT] {
+            let path = segment_path(dir, self.base_offset, ext);
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(Error::io(path, e)),
+                _ => continue,
+            }
+        }
+        Ok(())
+    }
+
+    fn track(
+        &mut self,
+        base_offset: u64,
+        next_offset: u64,
+        max_timestamp: i64,
+        entry_size: u64,
+        cfg: &LogConfig,
+    ) -> Result<()> {
+        if self.bytes_since_index >= cfg.index_interval_bytes {
+            let relative_offset = (base_offset - self.base_offset) as u32;
+            self.index.append(relative_offset, self.size as u32)?;
+            if self
+                .time_index
+                .last()
+                .is_none_or(|(indexed, _)| self.max_timestamp > indexed)
+            {
+                self.time_index
+                    .append(self.max_timestamp, relative_offset)?;
+            }
+            self.bytes_since_index = 0;
+        }
+        self.bytes_since_index += entry_size;
+        self.size += entry_size;
+        self.next_offset = next_offset;
+        self.max_timestamp = self.max_timestamp.max(max_timestamp);
+        Ok(())
+    }
+
+    /// Validate every entry in the segment, truncating at the first torn or
+    /// corrupt entry, and rebuild the indexes.
+    fn recover(&mut self, cfg: &LogConfig) -> Result<()> {
+        let end = self.size;
+        self.index.truncate(0)?;
+        self.time_index.truncate(0)?;
+        self.size = 0;
+        self.bytes_since_index = 0;
+        self.next_offset = self.base_offset;
+        self.max_timestamp = -1;
+
+        let mut position = 0;
+        while position < end {
+            let header = match self.valid_entry_at(position, end)? {
+                Some(header) => header,
+                None => break,
+            };
+            self.track(
+                header.base_offset,
+                header.next_offset(),
+                header.max_timestamp,
+                header.entry_size(),
+                cfg,
+            )?;
+            position += header.entry_size();
+        }
+
+        if position < end {
+            self.file
+                .set_len(position)
+                .map_err(|e| Error::io(&self.log_path, e))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the header of the entry at the supplied position if, and only if,
+    /// the entry is complete, correctly sequenced, and passes its crc check.
+    /// Offsets must increase but need not be contiguous, as compaction leaves gaps.
+    fn valid_entry_at(&self, position: u64, end: u64) -> Result<Option<Header>> {
+        if position + HEADER_SIZE as u64 > end {
+            return Ok(None);
+        }
+        let header = self.read_header(position)?;
+        if !header.is_sane()
+            || position + header.entry_size() > end
+            || header.base_offset < self.next_offset
+        {
+            return Ok(None);
+        }
+        let payload = self.read_payload(position, &header)?;
+        Ok(header.verify(payload).map(|_| header))
+    }
+
+    /// Recover the segment's bookkeeping from the persisted index and entry headers.
+    fn scan(&mut self) -> Result<()> {
+        let mut position = 0;
+        while position < self.size {
+            let header = self.read_header(position)?;
+            self.next_offset = header.next_offset();
+            self.max_timestamp = self.max_timestamp.max(header.max_timestamp);
+            position += header.entry_size();
+        }
+        let last_indexed = self.index.last().map(|(_, pos)| pos as u64).unwrap_or(0);
+        self.bytes_since_index = self.size - last_indexed;
+        Ok(())
+    }
+
+    fn read_header(&self, position: u64) -> Result<Header> {
+        let mut buf = [0; HEADER_SIZE];
+        self.file
+            .read_exact_at(&mut buf, position)
+            .map_err(|e| Error::io(&self.log_path, e))?;
+        Ok(Header::decode(&buf))
+    }
+
+    fn read_payload(&self, position: u64, header: &Header) -> Result<Vec<u8>> {
+        let mut payload = vec![0; header.payload_size()];
+        self.file
+            .read_exact_at(&mut payload, position + HEADER_SIZE as u64)
+            .map_err(|e| Error::io(&self.log_path, e))?;
+        Ok(payload)
+    }
+}
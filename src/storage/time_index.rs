@@ -0,0 +1,165 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    convert::TryInto,
+    fs::{File, OpenOptions},
+    io::Read,
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+};
+
+use super::config::TIME_INDEX_ENTRY_SIZE;
+use super::error::{Error, Result};
+
+/// A sparse mapping of timestamps to offsets, relative to a segment's base
+/// offset, used to find where in a segment to start searching by time.
+///
+/// Each entry records that every entry of the segment before the relative offset
+/// has a timestamp no later than the entry's timestamp. Entries are only added
+/// alongside offset index entries, and only when the segment's largest timestamp
+/// has grown, so their timestamps strictly increase and every relative offset
+/// can be resolved to a byte position exactly by the offset index.
+///
+/// Entries are persisted as a big endian i64 followed by a big endian u32 and
+/// mirrored in memory so lookups never touch the disk.
+pub(super) struct TimeIndex {
+    path: PathBuf,
+    file: File,
+    entries: Vec<(i64, u32)>,
+}
+
+impl TimeIndex {
+    /// Open, or create if missing, the time index file at the supplied path. Any
+    /// trailing partial entry is discarded.
+    pub fn open(path: &Path) -> Result<TimeIndex> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| Error::io(path, e))?;
+
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf).map_err(|e| Error::io(path, e))?;
+
+        let whole = buf.len() - buf.len() % TIME_INDEX_ENTRY_SIZE as usize;
+        if whole != buf.len() {
+            file.set_len(whole as u64).map_err(|e| Error::io(path, e))?;
+        }
+
+        let entries = buf[..whole]
+            .as_chunks::<{ TIME_INDEX_ENTRY_SIZE as usize }>()
+            .0
+            .iter()
+            .map(|chunk| {
+                (
+                    i64::from_be_bytes(chunk[0..8].try_into().unwrap()),
+                    u32::from_be_bytes(chunk[8..12].try_into().unwrap()),
+                )
+            })
+            .collect();
+
+        Ok(TimeIndex {
+            path: path.to_owned(),
+            file,
+            entries,
+        })
+    }
+
+    /// Returns the last entry held in this index, if any.
+    pub fn last(&self) -> Option<(i64, u32)> {
+        self.entries.last().copied()
+    }
+
+    /// Append a new entry recording that every entry before the relative offset
+    /// has a timestamp no later than the supplied timestamp.
+    pub fn append(&mut self, timestamp: i64, relative_offset: u32) -> Result<()> {
+        let mut buf = [0; TIME_INDEX_ENTRY_SIZE as usize];
+        buf[0..8].copy_from_slice(&timestamp.to_be_bytes());
+        buf[8..12].copy_from_slice(&relative_offset.to_be_bytes());
+
+        let at = self.entries.len() as u64 * TIME_INDEX_ENTRY_SIZE;
+        self.file
+            .write_all_at(&buf, at)
+            .map_err(|e| Error::io(&self.path, e))?;
+        self.entries.push((timestamp, relative_offset));
+        Ok(())
+    }
+
+    /// Returns the largest relative offset before which every entry has a
+    /// timestamp earlier than the supplied timestamp, or the start of the
+    /// segment if there is none.
+    pub fn lookup(&self, timestamp: i64) -> u32 {
+        let idx = self.entries.partition_point(|(ts, _)| *ts < timestamp);
+        if idx == 0 {
+            0
+        } else {
+            self.entries[idx - 1].1
+        }
+    }
+
+    /// Discard all but the first `len` entries.
+    pub fn truncate(&mut self, len: usize) -> Result<()> {
+        if len >= self.entries.len() {
+            return Ok(());
+        }
+        self.entries.truncate(len);
+        self.file
+            .set_len(len as u64 * TIME_INDEX_ENTRY_SIZE)
+            .map_err(|e| Error::io(&self.path, e))
+    }
+
+    /// Flush the index file to durable storage.
+    pub fn sync(&self) -> Result<()> {
+        self.file.sync_data().map_err(|e| Error::io(&self.path, e))
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_lookup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("0.timeindex");
+
+        let mut index = TimeIndex::open(&path).unwrap();
+        assert_eq!(0, index.lookup(100));
+
+        index.append(100, 10).unwrap();
+        index.append(200, 20).unwrap();
+        assert_eq!(0, index.lookup(50));
+        assert_eq!(0, index.lookup(100));
+        assert_eq!(10, index.lookup(101));
+        assert_eq!(10, index.lookup(200));
+        assert_eq!(20, index.lookup(i64::MAX));
+
+        let index = TimeIndex::open(&path).unwrap();
+        assert_eq!(Some((200, 20)), index.last());
+        assert_eq!(10, index.lookup(150));
+    }
+
+    #[test]
+    fn test_partial_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("0.timeindex");
+
+        let mut index = TimeIndex::open(&path).unwrap();
+        index.append(1, 1).unwrap();
+        index.file.write_all_at(&[1, 2, 3], 12).unwrap();
+        drop(index);
+
+        let mut index = TimeIndex::open(&path).unwrap();
+        assert_eq!(Some((1, 1)), index.last());
+        assert_eq!(12, std::fs::metadata(&path).unwrap().len());
+
+        index.append(2, 2).unwrap();
+        index.truncate(1).unwrap();
+        assert_eq!(Some((1, 1)), index.last());
+        assert_eq!(12, std::fs::metadata(&path).unwrap().len());
+    }
+}
@@ -0,0 +1,434 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{collections::BTreeMap, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::record::{Compression, TimestampType};
+use crate::storage::{self, retention_limit, CleanupPolicy, FlushPolicy, LogConfig};
+
+use super::error::{Error, Result};
+use super::manager::{is_internal, validate_name};
+
+/// Overrides the maximum segment size for a topic's partitions.
+pub const SEGMENT_BYTES: &str = "segment.bytes";
+/// Overrides the maximum offset index size for a topic's partitions.
+pub const INDEX_BYTES: &str = "index.bytes";
+/// Overrides the number of bytes between offset index entries for a topic's partitions.
+pub const INDEX_INTERVAL_BYTES: &str = "index.interval.bytes";
+/// Overrides how long in milliseconds closed segments are retained, or -1 for forever.
+pub const RETENTION_MS: &str = "retention.ms";
+/// Overrides the size in bytes each partition may grow to before closed segments are
+/// deleted, or -1 for no limit.
+pub const RETENTION_BYTES: &str = "retention.bytes";
+/// Overrides how old data is cleaned up: `delete`, `compact`, or `compact,delete`.
+pub const CLEANUP_POLICY: &str = "cleanup.policy";
+/// Overrides how long in milliseconds compaction retains tombstones.
+pub const DELETE_RETENTION_MS: &str = "delete.retention.ms";
+/// Overrides when appended data is flushed: `always`, `messages:<count>`, `ms:<millis>`, or `os`.
+pub const FLUSH_POLICY: &str = "flush.policy";
+/// Sets how long in milliseconds after they are appended messages without a TTL
+/// header may be delivered. Messages never expire if unset.
+pub const MESSAGE_TTL_MS: &str = "message.ttl.ms";
+/// Sets how long in milliseconds after they are appended messages without a TTL
+/// header may be leased by queues consuming a topic, overriding
+/// `message.ttl.ms`. Fetches are unaffected.
+pub const QUEUE_MESSAGE_TTL_MS: &str = "queue.message.ttl.ms";
+/// Sets which message timestamp retention and time based lookups honour:
+/// `CreateTime` or `LogAppendTime`, the default.
+pub const MESSAGE_TIMESTAMP_TYPE: &str = "message.timestamp.type";
+/// Sets how many times queues consuming a topic deliver a message before moving
+/// it to the dead-letter topic instead, or 0, the default, to redeliver it
+/// indefinitely. Requires `queue.dead.letter.topic`.
+pub const QUEUE_MAX_DELIVERIES: &str = "queue.max.deliveries";
+/// Sets the topic queues consuming a topic move messages to once they exhaust
+/// their delivery attempts or expire. Expired messages are dropped if unset.
+pub const QUEUE_DEAD_LETTER_TOPIC: &str = "queue.dead.letter.topic";
+/// Sets the codec batches are stored with: `producer`, the default, to keep the
+/// codec chosen by each producer, or `none`, `gzip`, `zstd`, `lz4` or `snappy`
+/// to recompress every batch with that codec.
+pub const COMPRESSION_TYPE: &str = "compression.type";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+/// Per topic overrides of broker wide defaults.
+pub struct TopicConfig {
+    overrides: BTreeMap<String, String>,
+}
+
+impl TopicConfig {
+    /// Create a new configuration with no overrides.
+    pub fn new() -> TopicConfig {
+        TopicConfig::default()
+    }
+
+    /// Validate and set an override.
+    ///
+    /// ```
+    /// # use librift::topic::TopicConfig;
+    /// let mut cfg = TopicConfig::new();
+    /// assert!(cfg.set("segment.bytes", "1024").is_ok());
+    /// assert!(cfg.set("segment.bytes", "huge").is_err());
+    /// assert!(cfg.set("unknown", "1").is_err());
+    /// assert_eq!(Some("1024"), cfg.get("segment.bytes"));
+    /// ```
+    pub fn set(&mut self, key: impl Into<String>, value: impl Into<String>) -> Result<()> {
+        let (key, value) = (key.into(), value.into());
+        validate(&key, &value)?;
+        self.overrides.insert(key, value);
+        Ok(())
+    }
+
+    /// Returns the override for the supplied key, if any.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.overrides.get(key).map(|value| value.as_str())
+    }
+
+    /// Returns an iterator over every override.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.overrides
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// Ensure every override is a known key with a valid value, and that
+    /// overrides depending on each other are set together.
+    pub fn validate(&self) -> Result<()> {
+        self.overrides
+            .iter()
+            .try_for_each(|(key, value)| validate(key, value))?;
+        if self.max_deliveries() > 0 && self.dead_letter_topic().is_none() {
+            return Err(Error::InvalidConfig {
+                key: QUEUE_MAX_DELIVERIES.to_owned(),
+                value: self.max_deliveries().to_string(),
+                reason: format!("requires {} to be set", QUEUE_DEAD_LETTER_TOPIC),
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns the time to live of this topic's messages in milliseconds, if
+    /// they expire.
+    pub fn message_ttl_ms(&self) -> Option<u64> {
+        self.get(MESSAGE_TTL_MS)
+            .and_then(|value| parse_u64(MESSAGE_TTL_MS, value).ok())
+    }
+
+    /// Returns the time to live in milliseconds queues consuming this topic
+    /// apply to messages without a TTL header, if it overrides the topic's.
+    pub fn queue_message_ttl_ms(&self) -> Option<u64> {
+        self.get(QUEUE_MESSAGE_TTL_MS)
+            .and_then(|value| parse_u64(QUEUE_MESSAGE_TTL_MS, value).ok())
+    }
+
+    /// Returns how many times queues consuming this topic deliver a message
+    /// before moving it to the dead-letter topic, or zero to redeliver it
+    /// indefinitely.
+    pub fn max_deliveries(&self) -> u32 {
+        self.get(QUEUE_MAX_DELIVERIES)
+            .and_then(|value| parse_u32(QUEUE_MAX_DELIVERIES, value).ok())
+            .unwrap_or_default()
+    }
+
+    /// Returns the topic queues consuming this topic move messages to once they
+    /// exhaust their delivery attempts or expire, if any.
+    ///
+    /// ```
+    /// # use librift::topic::TopicConfig;
+    /// let mut cfg = TopicConfig::new();
+    /// assert_eq!(None, cfg.dead_letter_topic());
+    /// cfg.set("queue.dead.letter.topic", "orders-dlq").unwrap();
+    /// assert_eq!(Some("orders-dlq"), cfg.dead_letter_topic());
+    /// ```
+    pub fn dead_letter_topic(&self) -> Option<&str> {
+        self.get(QUEUE_DEAD_LETTER_TOPIC)
+    }
+
+    /// Returns which message timestamp is authoritative for this topic.
+    ///
+    /// ```
+    /// # use librift::record::TimestampType;
+    /// # use librift::topic::TopicConfig;
+    /// let mut cfg = TopicConfig::new();
+    /// assert_eq!(TimestampType::LogAppendTime, cfg.timestamp_type());
+    /// cfg.set("message.timestamp.type", "CreateTime").unwrap();
+    /// assert_eq!(TimestampType::CreateTime, cfg.timestamp_type());
+    /// ```
+    pub fn timestamp_type(&self) -> TimestampType {
+        self.get(MESSAGE_TIMESTAMP_TYPE)
+            .and_then(|value| parse_timestamp_type(MESSAGE_TIMESTAMP_TYPE, value).ok())
+            .unwrap_or_default()
+    }
+
+    /// Returns the codec this topic forces its batches to be stored with, if
+    /// it does not keep the codec chosen by each producer.
+    ///
+    /// ```
+    /// # use librift::record::Compression;
+    /// # use librift::topic::TopicConfig;
+    /// let mut cfg = TopicConfig::new();
+    /// assert_eq!(None, cfg.compression());
+    /// cfg.set("compression.type", "lz4").unwrap();
+    /// assert_eq!(Some(Compression::Lz4), cfg.compression());
+    /// ```
+    pub fn compression(&self) -> Option<Compression> {
+        self.get(COMPRESSION_TYPE)
+            .and_then(|value| parse_compression(COMPRESSION_TYPE, value).ok())
+            .flatten()
+    }
+
+    /// Returns the supplied log defaults with this topic's overrides applied.
+    pub fn log_config(&self, defaults: &LogConfig) -> Result<LogConfig> {
+        let mut cfg = defaults.clone();
+        if let Some(value) = self.get(SEGMENT_BYTES) {
+            cfg.segment_bytes = parse_u64(SEGMENT_BYTES, value)?;
+        }
+        if let Some(value) = self.get(INDEX_BYTES) {
+            cfg.index_bytes = parse_u64(INDEX_BYTES, value)?;
+        }
+        if let Some(value) = self.get(INDEX_INTERVAL_BYTES) {
+            cfg.index_interval_bytes = parse_u64(INDEX_INTERVAL_BYTES, value)?;
+        }
+        if let Some(value) = self.get(RETENTION_MS) {
+            cfg.retention_ms = retention_limit(parse_retention(RETENTION_MS, value)?);
+        }
+        if let Some(value) = self.get(RETENTION_BYTES) {
+            cfg.retention_bytes = retention_limit(parse_retention(RETENTION_BYTES, value)?);
+        }
+        if let Some(value) = self.get(CLEANUP_POLICY) {
+            cfg.cleanup_policy = parse_policy(CLEANUP_POLICY, value)?;
+        }
+        if let Some(value) = self.get(DELETE_RETENTION_MS) {
+            cfg.delete_retention_ms = parse_u64(DELETE_RETENTION_MS, value)?;
+        }
+        if let Some(value) = self.get(FLUSH_POLICY) {
+            cfg.flush_policy = parse_policy(FLUSH_POLICY, value)?;
+        }
+        cfg.validate().map_err(|e| Error::InvalidConfig {
+            key: String::from("log"),
+            value: String::new(),
+            reason: e.to_string(),
+        })?;
+        Ok(cfg)
+    }
+}
+
+fn validate(key: &str, value: &str) -> Result<()> {
+    match key {
+        SEGMENT_BYTES | INDEX_BYTES | INDEX_INTERVAL_BYTES | DELETE_RETENTION_MS
+        | MESSAGE_TTL_MS | QUEUE_MESSAGE_TTL_MS => parse_u64(key, value).map(|_| ()),
+        RETENTION_MS | RETENTION_BYTES => parse_retention(key, value).map(|_| ()),
+        CLEANUP_POLICY => parse_policy::<CleanupPolicy>(key, value).map(|_| ()),
+        FLUSH_POLICY => parse_policy::<FlushPolicy>(key, value).map(|_| ()),
+        MESSAGE_TIMESTAMP_TYPE => parse_timestamp_type(key, value).map(|_| ()),
+        COMPRESSION_TYPE => parse_compression(key, value).map(|_| ()),
+        QUEUE_MAX_DELIVERIES => parse_u32(key, value).map(|_| ()),
+        QUEUE_DEAD_LETTER_TOPIC => parse_topic(key, value),
+        _ => Err(Error::InvalidConfig {
+            key: key.to_owned(),
+            value: value.to_owned(),
+            reason: String::from("unknown configuration key"),
+        }),
+    }
+}
+
+fn parse_u64(key: &str, value: &str) -> Result<u64> {
+    value.parse().map_err(|_| Error::InvalidConfig {
+        key: key.to_owned(),
+        value: value.to_owned(),
+        reason: String::from("expected a non-negative integer"),
+    })
+}
+
+fn parse_u32(key: &str, value: &str) -> Result<u32> {
+    value.parse().map_err(|_| Error::InvalidConfig {
+        key: key.to_owned(),
+        value: value.to_owned(),
+        reason: String::from("expected a non-negative 32 bit integer"),
+    })
+}
+
+fn parse_topic(key: &str, value: &str) -> Result<()> {
+    let reason = match validate_name(value) {
+        Err(Error::InvalidName { reason, .. }) => reason.to_owned(),
+        Err(err) => err.to_string(),
+        Ok(()) if is_internal(value) => String::from("must not be reserved for internal use"),
+        Ok(()) => return Ok(()),
+    };
+    Err(Error::InvalidConfig {
+        key: key.to_owned(),
+        value: value.to_owned(),
+        reason,
+    })
+}
+
+fn parse_policy<T>(key: &str, value: &str) -> Result<T>
+where
+    T: FromStr<Err = storage::Error>,
+{
+    value
+        .parse()
+        .map_err(|e: storage::Error| Error::InvalidConfig {
+            key: key.to_owned(),
+            value: value.to_owned(),
+            reason: e.to_string(),
+        })
+}
+
+fn parse_timestamp_type(key: &str, value: &str) -> Result<TimestampType> {
+    match value {
+        "CreateTime" => Ok(TimestampType::CreateTime),
+        "LogAppendTime" => Ok(TimestampType::LogAppendTime),
+        _ => Err(Error::InvalidConfig {
+            key: key.to_owned(),
+            value: value.to_owned(),
+            reason: String::from("expected CreateTime or LogAppendTime"),
+        }),
+    }
+}
+
+fn parse_compression(key: &str, value: &str) -> Result<Option<Compression>> {
+    match value {
+        "producer" => Ok(None),
+        _ => Compression::from_name(value)
+            .map(Some)
+            .ok_or_else(|| Error::InvalidConfig {
+                key: key.to_owned(),
+                value: value.to_owned(),
+                reason: String::from("expected producer, none, gzip, zstd, lz4 or snappy"),
+            }),
+    }
+}
+
+fn parse_retention(key: &str, value: &str) -> Result<i64> {
+    match value.parse() {
+        Ok(limit) if limit >= -1 => Ok(limit),
+        _ => Err(Error::InvalidConfig {
+            key: key.to_owned(),
+            value: value.to_owned(),
+            reason: String::from("expected a non-negative integer or -1"),
+        }),
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_config() {
+        let mut cfg = TopicConfig::new();
+        let defaults = LogConfig::default();
+        assert_eq!(defaults, cfg.log_config(&defaults).unwrap());
+
+        cfg.set(SEGMENT_BYTES, "1024").unwrap();
+        cfg.set(INDEX_BYTES, "64").unwrap();
+        cfg.set(INDEX_INTERVAL_BYTES, "16").unwrap();
+        let actual = cfg.log_config(&defaults).unwrap();
+        assert_eq!(1024, actual.segment_bytes);
+        assert_eq!(64, actual.index_bytes);
+        assert_eq!(16, actual.index_interval_bytes);
+
+        cfg.set(RETENTION_MS, "-1").unwrap();
+        cfg.set(RETENTION_BYTES, "4096").unwrap();
+        let actual = cfg.log_config(&defaults).unwrap();
+        assert_eq!(None, actual.retention_ms);
+        assert_eq!(Some(4096), actual.retention_bytes);
+        assert!(cfg.set(RETENTION_MS, "-2").is_err());
+
+        cfg.set(CLEANUP_POLICY, "compact").unwrap();
+        cfg.set(DELETE_RETENTION_MS, "10").unwrap();
+        let actual = cfg.log_config(&defaults).unwrap();
+        assert_eq!(CleanupPolicy::COMPACT, actual.cleanup_policy);
+        assert_eq!(10, actual.delete_retention_ms);
+        assert!(cfg.set(CLEANUP_POLICY, "archive").is_err());
+
+        cfg.set(FLUSH_POLICY, "messages:100").unwrap();
+        let actual = cfg.log_config(&defaults).unwrap();
+        assert_eq!(FlushPolicy::Messages(100), actual.flush_policy);
+        assert!(cfg.set(FLUSH_POLICY, "sometimes").is_err());
+
+        cfg.set(SEGMENT_BYTES, "0").unwrap();
+        assert!(matches!(
+            cfg.log_config(&defaults),
+            Err(Error::InvalidConfig { .. })
+        ));
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut cfg = TopicConfig::new();
+        assert!(matches!(
+            cfg.set("retention", "1"),
+            Err(Error::InvalidConfig { ref key, .. }) if key == "retention"
+        ));
+        assert!(cfg.get("retention").is_none());
+
+        cfg.set(SEGMENT_BYTES, "1").unwrap();
+        assert!(cfg.validate().is_ok());
+        assert_eq!(vec![(SEGMENT_BYTES, "1")], cfg.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_message_ttl() {
+        let mut cfg = TopicConfig::new();
+        assert_eq!(None, cfg.message_ttl_ms());
+        cfg.set(MESSAGE_TTL_MS, "5000").unwrap();
+        assert_eq!(Some(5000), cfg.message_ttl_ms());
+        assert!(cfg.set(MESSAGE_TTL_MS, "-1").is_err());
+        assert_eq!(Some(5000), cfg.message_ttl_ms());
+
+        assert_eq!(None, cfg.queue_message_ttl_ms());
+        cfg.set(QUEUE_MESSAGE_TTL_MS, "100").unwrap();
+        assert_eq!(Some(100), cfg.queue_message_ttl_ms());
+        assert!(cfg.set(QUEUE_MESSAGE_TTL_MS, "soon").is_err());
+    }
+
+    #[test]
+    fn test_dead_letter() {
+        let mut cfg = TopicConfig::new();
+        assert_eq!(0, cfg.max_deliveries());
+        assert_eq!(None, cfg.dead_letter_topic());
+        cfg.set(QUEUE_MAX_DELIVERIES, "3").unwrap();
+        assert!(matches!(
+            cfg.validate(),
+            Err(Error::InvalidConfig { ref key, .. }) if key == QUEUE_MAX_DELIVERIES
+        ));
+        cfg.set(QUEUE_DEAD_LETTER_TOPIC, "events-dlq").unwrap();
+        assert!(cfg.validate().is_ok());
+        assert_eq!(3, cfg.max_deliveries());
+        assert_eq!(Some("events-dlq"), cfg.dead_letter_topic());
+
+        assert!(cfg.set(QUEUE_MAX_DELIVERIES, "-1").is_err());
+        assert!(cfg.set(QUEUE_MAX_DELIVERIES, "4294967296").is_err());
+        assert!(cfg.set(QUEUE_DEAD_LETTER_TOPIC, "").is_err());
+        assert!(cfg.set(QUEUE_DEAD_LETTER_TOPIC, "a/b").is_err());
+        assert!(cfg.set(QUEUE_DEAD_LETTER_TOPIC, "__queue_state").is_err());
+    }
+
+    #[test]
+    fn test_timestamp_type() {
+        let mut cfg = TopicConfig::new();
+        assert_eq!(TimestampType::LogAppendTime, cfg.timestamp_type());
+        cfg.set(MESSAGE_TIMESTAMP_TYPE, "CreateTime").unwrap();
+        assert_eq!(TimestampType::CreateTime, cfg.timestamp_type());
+        cfg.set(MESSAGE_TIMESTAMP_TYPE, "LogAppendTime").unwrap();
+        assert_eq!(TimestampType::LogAppendTime, cfg.timestamp_type());
+        assert!(cfg.set(MESSAGE_TIMESTAMP_TYPE, "createtime").is_err());
+    }
+
+    #[test]
+    fn test_compression() {
+        let mut cfg = TopicConfig::new();
+        assert_eq!(None, cfg.compression());
+        cfg.set(COMPRESSION_TYPE, "gzip").unwrap();
+        assert_eq!(Some(Compression::Gzip), cfg.compression());
+        cfg.set(COMPRESSION_TYPE, "none").unwrap();
+        assert_eq!(Some(Compression::None), cfg.compression());
+        cfg.set(COMPRESSION_TYPE, "producer").unwrap();
+        assert_eq!(None, cfg.compression());
+        assert!(cfg.set(COMPRESSION_TYPE, "brotli").is_err());
+    }
+}
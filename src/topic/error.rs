@@ -0,0 +1,156 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{io, path::PathBuf, result};
+
+use thiserror::Error;
+
+use crate::{codec, storage};
+
+/// Custom Result wrapper to simplify usage.
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents errors managing topics and their partitions.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// Handles errors raised by the underlying partition logs.
+    #[error(transparent)]
+    Storage(#[from] storage::Error),
+    /// Handles record batches that could not be decoded.
+    #[error("failed to decode record batch at offset {offset}: {source}")]
+    Corrupt {
+        /// The base offset of the batch that failed to decode.
+        offset: u64,
+        /// The initial error cause.
+        source: codec::Error,
+    },
+    /// Handles OS level errors while managing topic directories and metadata.
+    #[error("i/o error on '{path}': {source}")]
+    Io {
+        /// The file or directory being operated on.
+        path: PathBuf,
+        /// The initial error cause.
+        source: io::Error,
+    },
+    /// Handles topic metadata files that could not be serialized or deserialized.
+    #[error("invalid topic metadata in '{path}': {source}")]
+    Metadata {
+        /// The metadata file being operated on.
+        path: PathBuf,
+        /// The initial error cause.
+        source: serde_json::Error,
+    },
+    /// Handles attempts to create a topic that already exists.
+    #[error("topic '{name}' already exists")]
+    AlreadyExists {
+        /// The name of the topic.
+        name: String,
+    },
+    /// Handles references to topics that do not exist.
+    #[error("topic '{name}' does not exist")]
+    NotFound {
+        /// The name of the topic.
+        name: String,
+    },
+    /// Handles topic names that are not usable.
+    #[error("invalid topic name '{name}': {reason}")]
+    InvalidName {
+        /// The rejected topic name.
+        name: String,
+        /// Why the name was rejected.
+        reason: &'static str,
+    },
+    /// Handles topics created without any partitions.
+    #[error("topics must have at least one partition, got {partitions}")]
+    InvalidPartitions {
+        /// The requested partition count.
+        partitions: u32,
+    },
+    /// Handles references to partitions that do not exist.
+    #[error("partition {partition} does not exist for topic '{topic}'")]
+    PartitionNotFound {
+        /// The name of the topic.
+        topic: String,
+        /// The requested partition.
+        partition: u32,
+    },
+    /// Handles invalid topic configuration overrides.
+    #[error("invalid value '{value}' for topic config '{key}': {reason}")]
+    InvalidConfig {
+        /// The configuration key.
+        key: String,
+        /// The rejected value.
+        value: String,
+        /// Why the value was rejected.
+        reason: String,
+    },
+    /// Handles batches from a producer whose id has since been reassigned with a
+    /// newer epoch.
+    #[error("producer {producer_id} epoch {epoch} has been fenced by epoch {current}")]
+    ProducerFenced {
+        /// The id of the producer.
+        producer_id: u64,
+        /// The epoch the batch was written with.
+        epoch: u16,
+        /// The producer's current epoch.
+        current: u16,
+    },
+    /// Handles batches that repeat records already written by a producer, other
+    /// than a retry of its last batch.
+    #[error("producer {producer_id} already wrote sequence {sequence}")]
+    DuplicateSequence {
+        /// The id of the producer.
+        producer_id: u64,
+        /// The sequence number of the batch's first record.
+        sequence: u32,
+    },
+    /// Handles batches that skip ahead of the next sequence number expected
+    /// from a producer.
+    #[error("producer {producer_id} expected sequence {expected}, got {received}")]
+    OutOfOrderSequence {
+        /// The id of the producer.
+        producer_id: u64,
+        /// The next sequence number expected from the producer.
+        expected: u32,
+        /// The sequence number of the batch's first record.
+        received: u32,
+    },
+}
+
+impl Error {
+    /// Wraps the supplied [io::Error] with the path that was being operated on.
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Error {
+        Error::Io {
+            path: path.into(),
+            source,
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_storage() {
+        let err = Error::from(storage::Error::EmptyEntry);
+        assert!(matches!(err, Error::Storage(storage::Error::EmptyEntry)));
+        assert_eq!(
+            "entries must contain at least one record",
+            format!("{}", err)
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let err = Error::PartitionNotFound {
+            topic: String::from("events"),
+            partition: 3,
+        };
+        assert_eq!(
+            "partition 3 does not exist for topic 'events'",
+            format!("{}", err)
+        );
+    }
+}
@@ -0,0 +1,321 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use crate::record;
+use crate::storage::LogConfig;
+
+use super::config::TopicConfig;
+use super::error::{Error, Result};
+use super::metadata::{Metadata, METADATA_FILE};
+use super::topic::Topic;
+
+/// The maximum length of a topic name.
+pub const MAX_NAME_LEN: usize = 249;
+
+/// The prefix reserved for topics managed by riftd itself.
+pub const INTERNAL_PREFIX: &str = "__";
+
+/// Returns whether or not the supplied topic name is reserved for internal use,
+/// in which case clients may not create, delete, or produce to it directly.
+///
+/// ```
+/// # use librift::topic::is_internal;
+/// assert!(is_internal("__consumer_offsets"));
+/// assert!(!is_internal("orders"));
+/// ```
+pub fn is_internal(name: &str) -> bool {
+    name.starts_with(INTERNAL_PREFIX)
+}
+
+/// Ensure the supplied topic name is usable as both an identifier and a directory name.
+///
+/// ```
+/// # use librift::topic::validate_name;
+/// assert!(validate_name("orders.v1-eu_west").is_ok());
+/// assert!(validate_name("").is_err());
+/// assert!(validate_name("..").is_err());
+/// assert!(validate_name("a/b").is_err());
+/// ```
+pub fn validate_name(name: &str) -> Result<()> {
+    let reason = if name.is_empty() {
+        "must not be empty"
+    } else if name.len() > MAX_NAME_LEN {
+        "must be at most 249 characters"
+    } else if name == "." || name == ".." {
+        "must not be '.' or '..'"
+    } else if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-')
+    {
+        "may only contain ASCII alphanumerics, '.', '_', and '-'"
+    } else {
+        return Ok(());
+    };
+    Err(Error::InvalidName {
+        name: name.to_owned(),
+        reason,
+    })
+}
+
+/// Owns every topic stored under the data directory.
+pub struct Manager {
+    dir: PathBuf,
+    defaults: LogConfig,
+    topics: RwLock<HashMap<String, Arc<Topic>>>,
+}
+
+impl Manager {
+    /// Load every topic persisted under the supplied data directory, creating it
+    /// if missing. Directories without committed topic metadata are ignored.
+    pub fn open(dir: impl Into<PathBuf>, defaults: LogConfig) -> Result<Manager> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).map_err(|e| Error::io(&dir, e))?;
+
+        let mut topics = HashMap::new();
+        for dirent in fs::read_dir(&dir).map_err(|e| Error::io(&dir, e))? {
+            let path = dirent.map_err(|e| Error::io(&dir, e))?.path();
+            if !path.join(METADATA_FILE).is_file() {
+                continue;
+            }
+            let metadata = Metadata::load(&path)?;
+            let topic = Topic::open(&path, metadata, &defaults)?;
+            topics.insert(topic.name().to_owned(), Arc::new(topic));
+        }
+
+        Ok(Manager {
+            dir,
+            defaults,
+            topics: RwLock::new(topics),
+        })
+    }
+
+    /// Returns the data directory topics are stored under.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Create and persist a new topic.
+    pub fn create(&self, name: &str, partitions: u32, config: TopicConfig) -> Result<Arc<Topic>> {
+        validate_name(name)?;
+        if partitions == 0 {
+            return Err(Error::InvalidPartitions { partitions });
+        }
+        config.validate()?;
+        config.log_config(&self.defaults)?;
+
+        let mut topics = self.write();
+        if topics.contains_key(name) {
+            return Err(Error::AlreadyExists {
+                name: name.to_owned(),
+            });
+        }
+
+        // Any directory left behind by an interrupted create or delete holds no
+        // committed metadata and must not leak stale partition data into the new topic.
+        let path = self.dir.join(name);
+        if path.exists() {
+            fs::remove_dir_all(&path).map_err(|e| Error::io(&path, e))?;
+        }
+        fs::create_dir_all(&path).map_err(|e| Error::io(&path, e))?;
+
+        let metadata = Metadata {
+            name: name.to_owned(),
+            partitions,
+            created_at: record::current_timestamp(),
+            config,
+        };
+        let topic = Arc::new(Topic::open(&path, metadata, &self.defaults)?);
+        topic.metadata().store(&path)?;
+
+        topics.insert(name.to_owned(), topic.clone());
+        Ok(topic)
+    }
+
+    /// Delete a topic and all of its data.
+    pub fn delete(&self, name: &str) -> Result<()> {
+        let topic = self.write().remove(name).ok_or_else(|| Error::NotFound {
+            name: name.to_owned(),
+        })?;
+
+        // Removing the metadata first uncommits the topic, so a crash part way
+        // through removing its data leaves nothing behind that would be reloaded.
+        let metadata = topic.dir().join(METADATA_FILE);
+        fs::remove_file(&metadata).map_err(|e| Error::io(&metadata, e))?;
+        fs::remove_dir_all(topic.dir()).map_err(|e| Error::io(topic.dir(), e))
+    }
+
+    /// Returns the topic with the supplied name.
+    pub fn get(&self, name: &str) -> Result<Arc<Topic>> {
+        self.read()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::NotFound {
+                name: name.to_owned(),
+            })
+    }
+
+    /// Returns every topic, ordered by name.
+    pub fn list(&self) -> Vec<Arc<Topic>> {
+        let mut topics: Vec<Arc<Topic>> = self.read().values().cloned().collect();
+        topics.sort_unstable_by(|a, b| a.name().cmp(b.name()));
+        topics
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, HashMap<String, Arc<Topic>>> {
+        self.topics
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, HashMap<String, Arc<Topic>>> {
+        self.topics
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+    use crate::record::Record;
+    use crate::topic::Partitioning;
+
+    #[test]
+    fn test_create_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = Manager::open(dir.path(), LogConfig::default()).unwrap();
+
+        let mut config = TopicConfig::new();
+        config.set("segment.bytes", "4096").unwrap();
+        let topic = manager.create("events", 3, config.clone()).unwrap();
+        topic
+            .produce(Partitioning::Explicit(1), vec![Record::new("hello")])
+            .unwrap();
+        let created_at = topic.metadata().created_at;
+        drop(topic);
+        drop(manager);
+
+        let manager = Manager::open(dir.path(), LogConfig::default()).unwrap();
+        let topic = manager.get("events").unwrap();
+        assert_eq!(3, topic.partitions().len());
+        assert_eq!(created_at, topic.metadata().created_at);
+        assert_eq!(config, topic.metadata().config);
+
+        let records = topic.partition(1).unwrap().read(0, 1024).unwrap();
+        assert_eq!(Some(b"hello".to_vec()), records[0].record.value);
+    }
+
+    #[test]
+    fn test_create_invalid() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = Manager::open(dir.path(), LogConfig::default()).unwrap();
+
+        assert!(matches!(
+            manager.create("a/b", 1, TopicConfig::new()),
+            Err(Error::InvalidName { .. })
+        ));
+        assert!(matches!(
+            manager.create("events", 0, TopicConfig::new()),
+            Err(Error::InvalidPartitions { partitions: 0 })
+        ));
+
+        manager.create("events", 1, TopicConfig::new()).unwrap();
+        assert!(matches!(
+            manager.create("events", 1, TopicConfig::new()),
+            Err(Error::AlreadyExists { .. })
+        ));
+    }
+
+    #[test]
+    fn test_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = Manager::open(dir.path(), LogConfig::default()).unwrap();
+
+        let topic = manager.create("events", 2, TopicConfig::new()).unwrap();
+        topic
+            .produce(Partitioning::Explicit(0), vec![Record::new("stale")])
+            .unwrap();
+        drop(topic);
+
+        manager.delete("events").unwrap();
+        assert!(matches!(manager.get("events"), Err(Error::NotFound { .. })));
+        assert!(!dir.path().join("events").exists());
+        assert!(matches!(
+            manager.delete("events"),
+            Err(Error::NotFound { .. })
+        ));
+
+        let topic = manager.create("events", 2, TopicConfig::new()).unwrap();
+        assert_eq!(0, topic.partition(0).unwrap().next_offset());
+    }
+
+    #[test]
+    fn test_ignore_uncommitted() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("partial").join("0")).unwrap();
+
+        let manager = Manager::open(dir.path(), LogConfig::default()).unwrap();
+        assert!(manager.list().is_empty());
+
+        let topic = manager.create("partial", 1, TopicConfig::new()).unwrap();
+        assert_eq!("partial", topic.name());
+    }
+
+    #[test]
+    fn test_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = Manager::open(dir.path(), LogConfig::default()).unwrap();
+        manager.create("b", 1, TopicConfig::new()).unwrap();
+        manager.create("a", 1, TopicConfig::new()).unwrap();
+
+        let names: Vec<String> = manager
+            .list()
+            .iter()
+            .map(|topic| topic.name().to_owned())
+            .collect();
+        assert_eq!(vec!["a", "b"], names);
+    }
+
+    #[test]
+    fn test_produce_partitioning() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = Manager::open(dir.path(), LogConfig::default()).unwrap();
+        let topic = manager.create("events", 4, TopicConfig::new()).unwrap();
+
+        let keyed = topic
+            .produce(
+                Partitioning::Key,
+                vec![
+                    Record::new("1").with_key("user-1"),
+                    Record::new("2").with_key("user-2"),
+                    Record::new("3").with_key("user-1"),
+                ],
+            )
+            .unwrap();
+        assert_eq!(keyed[0].0, keyed[2].0);
+        assert!(keyed[0].1 < keyed[2].1);
+
+        let round_robin = topic
+            .produce(
+                Partitioning::RoundRobin,
+                (0..4).map(|i| Record::new(i.to_string())).collect(),
+            )
+            .unwrap();
+        let mut partitions: Vec<u32> = round_robin.iter().map(|(p, _)| *p).collect();
+        partitions.sort_unstable();
+        assert_eq!(vec![0, 1, 2, 3], partitions);
+
+        assert!(matches!(
+            topic.produce(Partitioning::Explicit(4), vec![Record::new("x")]),
+            Err(Error::PartitionNotFound { partition: 4, .. })
+        ));
+    }
+}
@@ -0,0 +1,89 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::config::TopicConfig;
+use super::error::{Error, Result};
+
+/// The name of the file topic metadata is persisted to within a topic's directory.
+pub(super) const METADATA_FILE: &str = "topic.json";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// The persisted description of a topic.
+pub struct Metadata {
+    /// The unique name of the topic.
+    pub name: String,
+    /// The number of partitions the topic is split into.
+    pub partitions: u32,
+    /// The time, in milliseconds since the epoch, the topic was created.
+    pub created_at: i64,
+    /// The topic's configuration overrides.
+    pub config: TopicConfig,
+}
+
+impl Metadata {
+    /// Load the metadata persisted in the supplied topic directory.
+    pub(super) fn load(dir: &Path) -> Result<Metadata> {
+        let path = dir.join(METADATA_FILE);
+        let raw = fs::read(&path).map_err(|e| Error::io(&path, e))?;
+        serde_json::from_slice(&raw).map_err(|source| Error::Metadata { path, source })
+    }
+
+    /// Atomically persist this metadata into the supplied topic directory.
+    pub(super) fn store(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(METADATA_FILE);
+        let tmp = dir.join(format!("{}.tmp", METADATA_FILE));
+        let raw = serde_json::to_vec_pretty(self).map_err(|source| Error::Metadata {
+            path: path.clone(),
+            source,
+        })?;
+
+        let mut file = File::create(&tmp).map_err(|e| Error::io(&tmp, e))?;
+        file.write_all(&raw).map_err(|e| Error::io(&tmp, e))?;
+        file.sync_all().map_err(|e| Error::io(&tmp, e))?;
+        fs::rename(&tmp, &path).map_err(|e| Error::io(&path, e))?;
+        File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .map_err(|e| Error::io(dir, e))
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = TopicConfig::new();
+        config.set("segment.bytes", "1024").unwrap();
+        let metadata = Metadata {
+            name: String::from("events"),
+            partitions: 3,
+            created_at: 1234,
+            config,
+        };
+
+        metadata.store(dir.path()).unwrap();
+        assert!(!dir.path().join("topic.json.tmp").exists());
+        assert_eq!(metadata, Metadata::load(dir.path()).unwrap());
+    }
+
+    #[test]
+    fn test_load_invalid() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(METADATA_FILE), b"{").unwrap();
+        assert!(matches!(
+            Metadata::load(dir.path()),
+            Err(Error::Metadata { .. })
+        ));
+    }
+}
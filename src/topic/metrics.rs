@@ -0,0 +1,34 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::sync::OnceLock;
+
+use prometheus::IntCounterVec;
+
+use crate::metrics::{register_int_counter_vec, Opt};
+
+/// The series exported for topics.
+pub(super) struct Metrics {
+    /// Messages dropped or dead lettered by queues after outliving their time
+    /// to live, labelled by topic.
+    pub(super) expired: IntCounterVec,
+}
+
+/// Returns the process wide topic metrics, registering them on first use.
+pub(super) fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(|| {
+        let opts = vec![
+            Opt::Namespace(String::from("rift")),
+            Opt::Label(String::from("topic")),
+        ];
+        Metrics {
+            expired: register_int_counter_vec(
+                "expired_messages_total",
+                "The number of messages dropped or dead lettered by a queue because they outlived their time to live.",
+                Some(opts),
+            )
+            .expect("topic metrics registered twice"),
+        }
+    })
+}
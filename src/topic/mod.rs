@@ -0,0 +1,25 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod config;
+mod error;
+mod manager;
+mod metadata;
+mod metrics;
+mod partition;
+mod partitioner;
+mod producer;
+#[allow(clippy::module_inception)]
+mod topic;
+
+pub use self::config::{
+    TopicConfig, CLEANUP_POLICY, COMPRESSION_TYPE, DELETE_RETENTION_MS, FLUSH_POLICY, INDEX_BYTES,
+    INDEX_INTERVAL_BYTES, MESSAGE_TIMESTAMP_TYPE, MESSAGE_TTL_MS, QUEUE_DEAD_LETTER_TOPIC,
+    QUEUE_MAX_DELIVERIES, QUEUE_MESSAGE_TTL_MS, RETENTION_BYTES, RETENTION_MS, SEGMENT_BYTES,
+};
+pub use self::error::{Error, Result};
+pub use self::manager::{is_internal, validate_name, Manager, INTERNAL_PREFIX, MAX_NAME_LEN};
+pub use self::metadata::Metadata;
+pub use self::partition::{IsolationLevel, Partition, TopicPartition};
+pub use self::partitioner::{murmur2, partition_for_key, Partitioning};
+pub use self::topic::Topic;
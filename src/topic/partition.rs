@@ -0,0 +1,936 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+    time::Instant,
+};
+
+use crate::record::{
+    self, BatchHeader, Compression, ControlMarker, OffsetRecord, ProducerBatch, Record,
+    TimestampType,
+};
+use crate::storage::{Compaction, DeletedSegment, Entry, Log, LogConfig};
+
+use super::error::{Error, Result};
+use super::producer::{AbortedTxn, Producers};
+
+/// The number of bytes read from the log at a time while compacting, bounding how
+/// long each read holds the partition lock.
+const COMPACTION_READ_BYTES: usize = 1024 * 1024;
+
+/// The number of bytes read from the log at a time while rebuilding producer state.
+const REPLAY_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+/// Identifies a single partition of a topic.
+pub struct TopicPartition {
+    /// The name of the topic.
+    pub topic: String,
+    /// The partition within the topic.
+    pub partition: u32,
+}
+
+impl TopicPartition {
+    /// Create a new topic partition identifier.
+    ///
+    /// ```
+    /// # use librift::topic::TopicPartition;
+    /// let tp = TopicPartition::new("events", 3);
+    /// assert_eq!("events-3", tp.to_string());
+    /// ```
+    pub fn new(topic: impl Into<String>, partition: u32) -> TopicPartition {
+        TopicPartition {
+            topic: topic.into(),
+            partition,
+        }
+    }
+}
+
+impl fmt::Display for TopicPartition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.topic, self.partition)
+    }
+}
+
+/// A decoded batch visited while scanning a partition for compaction.
+struct ScannedBatch {
+    base_offset: u64,
+    max_timestamp: i64,
+    header: BatchHeader,
+    records: Vec<OffsetRecord>,
+}
+
+/// Which records a read returns, relative to the transactions that wrote them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IsolationLevel {
+    /// Every record, including those of transactions still in progress or aborted.
+    #[default]
+    ReadUncommitted,
+    /// Only records below the last stable offset, excluding those of aborted
+    /// transactions.
+    ReadCommitted,
+}
+
+/// A single independently ordered slice of a topic, backed by its own log.
+///
+/// Alongside its log each partition tracks the last batch written by every
+/// idempotent producer, so retried batches are not written twice, along with
+/// the transactions in progress and the aborted transactions still in the log.
+/// The producer state is snapshot whenever the log is flushed, and rebuilt on
+/// open from the snapshot and the entries written after it. Whenever both are held the log
+/// lock is taken before the producer state lock.
+///
+/// Batches are stamped with the timestamp type of the partition's topic, which
+/// decides which of their records' timestamps retention and time based lookups
+/// honour. They are compressed with the codec their producer chose, unless the
+/// topic forces a codec of its own.
+pub struct Partition {
+    topic: String,
+    id: u32,
+    timestamp_type: TimestampType,
+    compression: Option<Compression>,
+    log: Mutex<Log>,
+    producers: Mutex<Producers>,
+}
+
+impl Partition {
+    /// Open, or create if missing, the partition stored in the supplied directory.
+    pub(super) fn open(
+        dir: &Path,
+        topic: &str,
+        id: u32,
+        cfg: LogConfig,
+        timestamp_type: TimestampType,
+        compression: Option<Compression>,
+    ) -> Result<Partition> {
+        let log = Log::open(dir.join(id.to_string()), cfg)?;
+        let mut producers = Producers::load(log.dir())?;
+        if producers.offset() > log.next_offset() {
+            producers.reset(log.start_offset());
+        }
+        replay(&log, &mut producers)?;
+        Ok(Partition {
+            topic: topic.to_owned(),
+            id,
+            timestamp_type,
+            compression,
+            log: Mutex::new(log),
+            producers: Mutex::new(producers),
+        })
+    }
+
+    /// Returns the name of the topic this partition belongs to.
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Returns this partition's id within its topic.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Returns the directory this partition's log is stored in.
+    pub fn dir(&self) -> PathBuf {
+        self.log().dir().to_owned()
+    }
+
+    /// Returns the offset of the first record retained by this partition.
+    pub fn start_offset(&self) -> u64 {
+        self.log().start_offset()
+    }
+
+    /// Returns the offset that will be assigned to the next appended record.
+    pub fn next_offset(&self) -> u64 {
+        self.log().next_offset()
+    }
+
+    /// Append the supplied records as a single batch and return the offset
+    /// assigned to the first record, once the partition's flush policy has been
+    /// satisfied.
+    pub fn append(&self, records: &[Record]) -> Result<u64> {
+        self.append_batch(records, None)
+    }
+
+    /// Append the supplied records as a single batch like [Partition::append],
+    /// written by the supplied idempotent producer if any.
+    ///
+    /// A retry of the producer's last batch is not written again, and returns
+    /// the offset the batch was originally written at. Any other batch out of
+    /// sequence with the producer's earlier writes is rejected.
+    pub fn append_batch(&self, records: &[Record], producer: Option<ProducerBatch>) -> Result<u64> {
+        self.append_compressed(records, producer, Compression::None)
+    }
+
+    /// Append the supplied records as a single batch like
+    /// [Partition::append_batch], compressed with the supplied codec unless
+    /// the partition's topic forces a codec of its own.
+    pub fn append_compressed(
+        &self,
+        records: &[Record],
+        producer: Option<ProducerBatch>,
+        compression: Compression,
+    ) -> Result<u64> {
+        let mut log = self.log();
+        let mut producers = self.producers();
+        let count = records.len() as u32;
+        if let Some(producer) = &producer {
+            if let Some(base_offset) = producers.check(producer, count)? {
+                return Ok(base_offset);
+            }
+        }
+
+        let header = BatchHeader {
+            producer,
+            control: None,
+            timestamp_type: self.timestamp_type,
+            compression: self.compression.unwrap_or(compression),
+        };
+        let entry = record::encode_batch(records, record::current_timestamp(), &header);
+        let base_offset = log.append(entry)?;
+        producers.record(&header, base_offset, count);
+        Ok(base_offset)
+    }
+
+    /// Append a marker completing the supplied producer's transaction, returning
+    /// the offset assigned to the marker. Markers written with an epoch older
+    /// than the producer's current one are rejected.
+    pub fn append_marker(
+        &self,
+        producer_id: u64,
+        epoch: u16,
+        marker: ControlMarker,
+    ) -> Result<u64> {
+        let mut log = self.log();
+        let mut producers = self.producers();
+        producers.check_epoch(producer_id, epoch)?;
+
+        let entry = record::encode_marker(producer_id, epoch, marker, record::current_timestamp());
+        let header = record::header(&entry).expect("markers always decode");
+        let base_offset = log.append(entry)?;
+        producers.record(&header, base_offset, 1);
+        Ok(base_offset)
+    }
+
+    /// Returns the offset below which every transaction written to this
+    /// partition has completed.
+    pub fn last_stable_offset(&self) -> u64 {
+        self.producers().stable_offset()
+    }
+
+    /// Read records starting at the supplied offset, until `max_bytes` worth of
+    /// batches have been read or the partition is exhausted.
+    pub fn read(&self, offset: u64, max_bytes: usize) -> Result<Vec<OffsetRecord>> {
+        self.read_isolated(offset, max_bytes, IsolationLevel::ReadUncommitted)
+    }
+
+    /// Read records like [Partition::read] at the supplied isolation level.
+    /// Transaction markers are never returned, though they occupy offsets.
+    pub fn read_isolated(
+        &self,
+        offset: u64,
+        max_bytes: usize,
+        isolation: IsolationLevel,
+    ) -> Result<Vec<OffsetRecord>> {
+        let mut position = offset;
+        loop {
+            let (entries, end, aborted) = {
+                let log = self.log();
+                let entries = log.read(position, max_bytes)?;
+                match isolation {
+                    IsolationLevel::ReadUncommitted => (entries, u64::MAX, Vec::new()),
+                    IsolationLevel::ReadCommitted => {
+                        let producers = self.producers();
+                        let end = producers.stable_offset();
+                        (entries, end, producers.aborted(position, end))
+                    }
+                }
+            };
+
+            let mut records = Vec::new();
+            let mut next = position;
+            for entry in entries
+                .into_iter()
+                .take_while(|entry| entry.base_offset < end)
+            {
+                next = entry.next_offset();
+                let header = record::header(&entry).map_err(|source| Error::Corrupt {
+                    offset: entry.base_offset,
+                    source,
+                })?;
+                if header.control.is_some() || is_aborted(&header, entry.base_offset, &aborted) {
+                    continue;
+                }
+                let decoded = record::decode(&entry).map_err(|source| Error::Corrupt {
+                    offset: entry.base_offset,
+                    source,
+                })?;
+                records.extend(decoded.into_iter().filter(|r| r.offset >= offset));
+            }
+
+            // Keep reading past batches that were entirely hidden, so readers are
+            // never stuck behind a run of markers or aborted records.
+            if !records.is_empty() || next == position {
+                return Ok(records);
+            }
+            position = next;
+        }
+    }
+
+    /// Read raw log entries starting with the one containing the supplied offset,
+    /// until `max_bytes` worth of entries have been read or the partition is
+    /// exhausted.
+    pub fn read_entries(&self, offset: u64, max_bytes: usize) -> Result<Vec<Entry>> {
+        Ok(self.log().read(offset, max_bytes)?)
+    }
+
+    /// Append a raw log entry that has already been assigned its base offset, as
+    /// when copying entries from another replica.
+    pub fn append_entry(&self, entry: Entry) -> Result<u64> {
+        let header = record::header(&entry).map_err(|source| Error::Corrupt {
+            offset: entry.base_offset,
+            source,
+        })?;
+        let count = entry.record_count;
+        let mut log = self.log();
+        let base_offset = log.append_at(entry)?;
+        self.producers().record(&header, base_offset, count);
+        Ok(base_offset)
+    }
+
+    /// Discard every record at or beyond the supplied offset.
+    pub fn truncate_to(&self, offset: u64) -> Result<()> {
+        let mut log = self.log();
+        log.truncate_to(offset)?;
+
+        // The discarded batches may have replaced a producer's earlier batch, so
+        // the producer state is rebuilt from what remains of the log.
+        let mut producers = self.producers();
+        if producers.offset() > log.next_offset() {
+            producers.reset(log.start_offset());
+            replay(&log, &mut producers)?;
+        }
+        Ok(())
+    }
+
+    /// Discard every record and restart this partition empty at the supplied offset.
+    pub fn reset(&self, start_offset: u64) -> Result<()> {
+        let mut log = self.log();
+        log.reset(start_offset)?;
+        self.producers().reset(start_offset);
+        Ok(())
+    }
+
+    /// Returns the offset of the first record with a timestamp at or after the
+    /// supplied timestamp, or the next offset if there is no such record.
+    ///
+    /// The log finds the first batch stamped at or after the timestamp, which
+    /// is then decoded, as records earlier in the batch may have been created
+    /// before it.
+    pub fn offset_for_timestamp(&self, timestamp: i64) -> Result<u64> {
+        let entry = {
+            let log = self.log();
+            match log.entry_for_timestamp(timestamp)? {
+                Some(entry) => entry,
+                None => return Ok(log.next_offset()),
+            }
+        };
+        let corrupt = |source| Error::Corrupt {
+            offset: entry.base_offset,
+            source,
+        };
+        let header = record::header(&entry).map_err(corrupt)?;
+        if header.control.is_some() {
+            return Ok(entry.base_offset);
+        }
+        Ok(record::decode(&entry)
+            .map_err(corrupt)?
+            .into_iter()
+            .find(|record| record.timestamp_of(header.timestamp_type) >= timestamp)
+            .map_or(entry.next_offset(), |record| record.offset))
+    }
+
+    /// Delete the closed segments that fall outside this partition's retention
+    /// limits as of `now`, in milliseconds since the epoch.
+    pub fn apply_retention(&self, now: i64) -> Result<Vec<DeletedSegment>> {
+        let mut log = self.log();
+        let deleted = log.apply_retention(now)?;
+        self.producers().prune(log.start_offset());
+        Ok(deleted)
+    }
+
+    /// Compact this partition's closed segments, keeping only the latest record for
+    /// each key, and return the number of bytes reclaimed. Tombstones older than
+    /// the log's delete retention as of `now`, in milliseconds since the epoch,
+    /// are dropped entirely. Returns [None] if there was nothing to compact.
+    ///
+    /// The partition is only locked briefly while reading, so producers are not
+    /// blocked for the duration of the compaction.
+    pub fn compact(&self, now: i64) -> Result<Option<u64>> {
+        let (mut compaction, delete_retention_ms) = {
+            let log = self.log();
+            match log.begin_compaction()? {
+                Some(compaction) => (compaction, log.config().delete_retention_ms),
+                None => return Ok(None),
+            }
+        };
+
+        let tombstone_horizon = now.saturating_sub(delete_retention_ms as i64);
+        if let Err(err) = self.fill(&mut compaction, tombstone_horizon) {
+            compaction.abort()?;
+            return Err(err);
+        }
+        Ok(self.log().finish_compaction(compaction)?)
+    }
+
+    /// Flush this partition's log to durable storage.
+    pub fn flush(&self) -> Result<()> {
+        let mut log = self.log();
+        log.flush()?;
+        self.producers().store(log.dir())
+    }
+
+    /// Flush this partition's log if its flush policy requires it as of `now`,
+    /// returning whether a flush took place.
+    pub fn flush_if_due(&self, now: Instant) -> Result<bool> {
+        let mut log = self.log();
+        if !log.flush_if_due(now)? {
+            return Ok(false);
+        }
+        self.producers().store(log.dir())?;
+        Ok(true)
+    }
+
+    /// Fill the compaction in two passes over its offset range: the first finds
+    /// the latest offset of every key, the second appends the records to retain.
+    /// Records of aborted transactions never shadow earlier records, and every
+    /// retained batch keeps the producer header it was written with.
+    fn fill(&self, compaction: &mut Compaction, tombstone_horizon: i64) -> Result<()> {
+        let (start, end) = (compaction.start_offset(), compaction.end_offset());
+        let aborted = self.producers().aborted(start, end);
+
+        let mut latest = HashMap::new();
+        self.scan(start, end, |batch| {
+            if batch.header.control.is_some()
+                || is_aborted(&batch.header, batch.base_offset, &aborted)
+            {
+                return Ok(());
+            }
+            for record in batch.records {
+                if let Some(key) = record.record.key {
+                    latest.insert(key, record.offset);
+                }
+            }
+            Ok(())
+        })?;
+
+        self.scan(start, end, |mut batch| {
+            if let (Some(marker), Some(producer)) = (batch.header.control, batch.header.producer) {
+                let mut entry = record::encode_marker(
+                    producer.producer_id,
+                    producer.epoch,
+                    marker,
+                    batch.max_timestamp,
+                );
+                entry.base_offset = batch.base_offset;
+                compaction.append(&entry)?;
+                return Ok(());
+            }
+
+            // Retained records are re-encoded in contiguous runs, as offsets within
+            // an entry must be contiguous.
+            let mut run = Vec::new();
+            for record in std::mem::take(&mut batch.records) {
+                let keep = match &record.record.key {
+                    Some(key) => {
+                        latest.get(key) == Some(&record.offset)
+                            && (record.record.value.is_some()
+                                || record.timestamp >= tombstone_horizon)
+                    }
+                    None => true,
+                };
+                if keep {
+                    run.push(record);
+                } else if !run.is_empty() {
+                    compaction.append(&reencode(&run, &batch))?;
+                    run.clear();
+                }
+            }
+            if !run.is_empty() {
+                compaction.append(&reencode(&run, &batch))?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Call `f` with every decoded batch between `start` and `end`, locking the
+    /// log only for each read.
+    fn scan<F>(&self, start: u64, end: u64, mut f: F) -> Result<()>
+    where
+        F: FnMut(ScannedBatch) -> Result<()>,
+    {
+        let mut offset = start;
+        while offset < end {
+            let entries = self.log().read(offset, COMPACTION_READ_BYTES)?;
+            if entries.is_empty() {
+                break;
+            }
+            for entry in entries {
+                if entry.base_offset >= end {
+                    return Ok(());
+                }
+                offset = entry.next_offset();
+                let corrupt = |source| Error::Corrupt {
+                    offset: entry.base_offset,
+                    source,
+                };
+                f(ScannedBatch {
+                    base_offset: entry.base_offset,
+                    max_timestamp: entry.max_timestamp,
+                    header: record::header(&entry).map_err(corrupt)?,
+                    records: record::decode(&entry).map_err(corrupt)?,
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    fn log(&self) -> MutexGuard<'_, Log> {
+        self.log
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn producers(&self) -> MutexGuard<'_, Producers> {
+        self.producers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Bring the supplied producer state up to date with the entries written to the
+/// log after it.
+fn replay(log: &Log, producers: &mut Producers) -> Result<()> {
+    let mut offset = producers.offset().max(log.start_offset());
+    while offset < log.next_offset() {
+        let entries = log.read(offset, REPLAY_BYTES)?;
+        if entries.is_empty() {
+            break;
+        }
+        for entry in entries {
+            let header = record::header(&entry).map_err(|source| Error::Corrupt {
+                offset: entry.base_offset,
+                source,
+            })?;
+            producers.record(&header, entry.base_offset, entry.record_count);
+            offset = entry.next_offset();
+        }
+    }
+    Ok(())
+}
+
+/// Encode a run of records with contiguous offsets back into a single entry.
+fn reencode(run: &[OffsetRecord], batch: &ScannedBatch) -> Entry {
+    let records: Vec<Record> = run.iter().map(|r| r.record.clone()).collect();
+    // Sequences follow offsets within a batch, so a run keeps the sequences its
+    // records were originally written with.
+    let producer = batch.header.producer.map(|producer| ProducerBatch {
+        sequence: producer
+            .sequence
+            .wrapping_add((run[0].offset - batch.base_offset) as u32),
+        ..producer
+    });
+    let mut entry = record::encode_batch(
+        &records,
+        run[0].timestamp,
+        &BatchHeader {
+            producer,
+            ..batch.header
+        },
+    );
+    entry.base_offset = run[0].offset;
+    entry
+}
+
+/// Returns whether a batch with the supplied header, written at `base_offset`,
+/// belongs to one of the supplied aborted transactions.
+fn is_aborted(header: &BatchHeader, base_offset: u64, aborted: &[AbortedTxn]) -> bool {
+    header.producer.is_some_and(|producer| {
+        producer.transactional
+            && aborted.iter().any(|txn| {
+                txn.producer_id == producer.producer_id
+                    && (txn.first_offset..=txn.last_offset).contains(&base_offset)
+            })
+    })
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let partition = Partition::open(
+            dir.path(),
+            "events",
+            0,
+            LogConfig::default(),
+            TimestampType::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!("events", partition.topic());
+        assert_eq!(0, partition.id());
+
+        let first = partition
+            .append(&[Record::new("a"), Record::new("b")])
+            .unwrap();
+        let second = partition.append(&[Record::new("c")]).unwrap();
+        assert_eq!(0, first);
+        assert_eq!(2, second);
+        assert_eq!(0, partition.start_offset());
+        assert_eq!(3, partition.next_offset());
+
+        let records = partition.read(1, 1024).unwrap();
+        assert_eq!(2, records.len());
+        assert_eq!(1, records[0].offset);
+        assert_eq!(Some(b"b".to_vec()), records[0].record.value);
+        assert_eq!(Some(b"c".to_vec()), records[1].record.value);
+        assert!(partition.read(3, 1024).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_timestamp_type() {
+        let dir = tempfile::tempdir().unwrap();
+        let open = |id, timestamp_type| {
+            Partition::open(
+                dir.path(),
+                "events",
+                id,
+                LogConfig::default(),
+                timestamp_type,
+                None,
+            )
+            .unwrap()
+        };
+        let records = [100, 300, 200].map(|ts| Record::new("a").with_timestamp(ts));
+
+        // Time based lookups honour the create time of the records, where the
+        // topic chooses it.
+        let partition = open(0, TimestampType::CreateTime);
+        for record in &records {
+            partition.append(std::slice::from_ref(record)).unwrap();
+        }
+        assert_eq!(0, partition.offset_for_timestamp(50).unwrap());
+        assert_eq!(1, partition.offset_for_timestamp(150).unwrap());
+        assert_eq!(1, partition.offset_for_timestamp(250).unwrap());
+        assert_eq!(3, partition.offset_for_timestamp(350).unwrap());
+
+        // Lookups land on the first record at or after the timestamp, even when
+        // earlier records of its batch were created before it.
+        partition
+            .append(&[150, 400, 500, 350].map(|ts| Record::new("b").with_timestamp(ts)))
+            .unwrap();
+        assert_eq!(1, partition.offset_for_timestamp(300).unwrap());
+        assert_eq!(4, partition.offset_for_timestamp(301).unwrap());
+        assert_eq!(4, partition.offset_for_timestamp(400).unwrap());
+        assert_eq!(5, partition.offset_for_timestamp(450).unwrap());
+        assert_eq!(7, partition.offset_for_timestamp(501).unwrap());
+
+        let partition = open(1, TimestampType::LogAppendTime);
+        let now = record::current_timestamp();
+        for record in &records {
+            partition.append(std::slice::from_ref(record)).unwrap();
+        }
+        assert_eq!(0, partition.offset_for_timestamp(150).unwrap());
+
+        // Either way reads return both timestamps.
+        let read = partition.read(0, 1024).unwrap();
+        assert_eq!(Some(300), read[1].record.timestamp);
+        assert!(read.iter().all(|record| record.timestamp >= now));
+    }
+
+    #[test]
+    fn test_compression() {
+        let dir = tempfile::tempdir().unwrap();
+        let open = |id, compression| {
+            Partition::open(
+                dir.path(),
+                "events",
+                id,
+                LogConfig::default(),
+                TimestampType::default(),
+                compression,
+            )
+            .unwrap()
+        };
+        let records = [Record::new("a".repeat(100)), Record::new("b".repeat(100))];
+        let codecs = |partition: &Partition| {
+            partition
+                .log()
+                .read(0, 1024 * 1024)
+                .unwrap()
+                .iter()
+                .map(|entry| record::header(entry).unwrap().compression)
+                .collect::<Vec<_>>()
+        };
+
+        // Batches keep the codec their producer chose by default.
+        let partition = open(0, None);
+        partition
+            .append_compressed(&records, None, Compression::Gzip)
+            .unwrap();
+        partition.append(&records).unwrap();
+        assert_eq!(
+            vec![Compression::Gzip, Compression::None],
+            codecs(&partition)
+        );
+
+        // Unless the topic forces a codec of its own.
+        let partition = open(1, Some(Compression::Lz4));
+        partition
+            .append_compressed(&records, None, Compression::Gzip)
+            .unwrap();
+        partition.append(&records).unwrap();
+        assert_eq!(vec![Compression::Lz4, Compression::Lz4], codecs(&partition));
+
+        let read = partition.read(0, 1024 * 1024).unwrap();
+        assert_eq!(4, read.len());
+        assert_eq!(records[1], read[3].record);
+    }
+
+    #[test]
+    fn test_producer_recovery() {
+        let dir = tempfile::tempdir().unwrap();
+        let producer = |sequence| {
+            Some(ProducerBatch {
+                producer_id: 9,
+                epoch: 0,
+                sequence,
+                transactional: false,
+            })
+        };
+        let records = [Record::new("a"), Record::new("b")];
+        let partition = Partition::open(
+            dir.path(),
+            "events",
+            0,
+            LogConfig::default(),
+            TimestampType::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(0, partition.append_batch(&records, producer(0)).unwrap());
+        assert_eq!(0, partition.append_batch(&records, producer(0)).unwrap());
+        partition.flush().unwrap();
+        assert_eq!(2, partition.append_batch(&records, producer(2)).unwrap());
+        partition.append(&records).unwrap();
+
+        // The snapshot is brought up to date from the batches written after it.
+        drop(partition);
+        let partition = Partition::open(
+            dir.path(),
+            "events",
+            0,
+            LogConfig::default(),
+            TimestampType::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(2, partition.append_batch(&records, producer(2)).unwrap());
+        assert!(matches!(
+            partition.append_batch(&records, producer(0)),
+            Err(Error::DuplicateSequence { .. })
+        ));
+        assert_eq!(6, partition.next_offset());
+
+        // Truncating away the producer's last batch restores its earlier one.
+        partition.truncate_to(2).unwrap();
+        assert_eq!(0, partition.append_batch(&records, producer(0)).unwrap());
+        assert_eq!(2, partition.append_batch(&records, producer(2)).unwrap());
+
+        partition.reset(10).unwrap();
+        assert_eq!(10, partition.append_batch(&records, producer(4)).unwrap());
+    }
+
+    #[test]
+    fn test_transactions() {
+        let dir = tempfile::tempdir().unwrap();
+        let producer = |producer_id, sequence| {
+            Some(ProducerBatch {
+                producer_id,
+                epoch: 0,
+                sequence,
+                transactional: true,
+            })
+        };
+        let values = |records: Vec<OffsetRecord>| {
+            records
+                .into_iter()
+                .map(|r| (r.offset, r.record.value.unwrap()))
+                .collect::<Vec<_>>()
+        };
+        let committed = IsolationLevel::ReadCommitted;
+
+        let partition = Partition::open(
+            dir.path(),
+            "events",
+            0,
+            LogConfig::default(),
+            TimestampType::default(),
+            None,
+        )
+        .unwrap();
+        partition.append(&[Record::new("a")]).unwrap();
+        partition
+            .append_batch(&[Record::new("b")], producer(1, 0))
+            .unwrap();
+        partition
+            .append_batch(&[Record::new("c")], producer(2, 0))
+            .unwrap();
+        assert_eq!(1, partition.last_stable_offset());
+        assert_eq!(3, partition.read(0, 1024).unwrap().len());
+        assert_eq!(
+            vec![(0, "a".into())],
+            values(partition.read_isolated(0, 1024, committed).unwrap())
+        );
+
+        assert_eq!(
+            3,
+            partition.append_marker(2, 0, ControlMarker::Abort).unwrap()
+        );
+        assert_eq!(
+            4,
+            partition
+                .append_marker(1, 0, ControlMarker::Commit)
+                .unwrap()
+        );
+        assert_eq!(5, partition.last_stable_offset());
+        assert_eq!(
+            5,
+            partition
+                .append_marker(1, 0, ControlMarker::Commit)
+                .unwrap()
+        );
+        assert_eq!(
+            6,
+            partition
+                .append_batch(&[Record::new("d")], producer(1, 1))
+                .unwrap()
+        );
+
+        // Markers and aborted records are hidden, and recovered on restart.
+        drop(partition);
+        let partition = Partition::open(
+            dir.path(),
+            "events",
+            0,
+            LogConfig::default(),
+            TimestampType::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![(0, "a".into()), (1, "b".into())],
+            values(partition.read_isolated(0, 1024, committed).unwrap())
+        );
+        assert_eq!(
+            vec![(1, "b".into()), (2, "c".into()), (6, "d".into())],
+            values(partition.read(1, 1024).unwrap())
+        );
+        assert!(partition
+            .read_isolated(2, 1024, committed)
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(
+            7,
+            partition.append_marker(1, 1, ControlMarker::Abort).unwrap()
+        );
+        assert!(matches!(
+            partition.append_marker(1, 0, ControlMarker::Abort),
+            Err(Error::ProducerFenced { current: 1, .. })
+        ));
+        assert_eq!(
+            vec![(0, "a".into()), (1, "b".into())],
+            values(partition.read_isolated(0, 1024, committed).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_compact() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = LogConfig {
+            segment_bytes: 256,
+            cleanup_policy: crate::storage::CleanupPolicy::COMPACT,
+            delete_retention_ms: 1000,
+            ..Default::default()
+        };
+        let partition =
+            Partition::open(dir.path(), "events", 0, cfg, TimestampType::default(), None).unwrap();
+        assert_eq!(None, partition.compact(0).unwrap());
+
+        for idx in 0..30 {
+            let key = format!("key-{}", idx % 3);
+            partition
+                .append(&[
+                    Record::new(format!("value-{}", idx)).with_key(key),
+                    Record::new("unkeyed"),
+                ])
+                .unwrap();
+        }
+        partition
+            .append(&[Record {
+                key: Some(b"key-0".to_vec()),
+                value: None,
+                headers: Vec::new(),
+                timestamp: None,
+            }])
+            .unwrap();
+        for idx in 0..10 {
+            partition
+                .append(&[Record::new(format!("filler-{}", idx)).with_key("filler")])
+                .unwrap();
+        }
+        let next_offset = partition.next_offset();
+
+        let now = record::current_timestamp();
+        let reclaimed = partition.compact(now).unwrap().unwrap();
+        assert!(reclaimed > 0);
+        assert_eq!(next_offset, partition.next_offset());
+
+        let records = partition.read(0, usize::MAX).unwrap();
+        let keyed = |key: &str| {
+            records
+                .iter()
+                .filter(|r| r.record.key.as_deref() == Some(key.as_bytes()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            30,
+            records.iter().filter(|r| r.record.key.is_none()).count()
+        );
+        assert_eq!(1, keyed("key-1").len());
+        assert_eq!(Some(b"value-28".to_vec()), keyed("key-1")[0].record.value);
+        assert_eq!(1, keyed("key-0").len());
+        assert_eq!(None, keyed("key-0")[0].record.value);
+        assert!(records.windows(2).all(|w| w[0].offset < w[1].offset));
+
+        // Nothing new was closed, so there is nothing further to compact.
+        assert_eq!(None, partition.compact(now).unwrap());
+
+        // Once the tombstone ages out it is removed along with its key.
+        partition.log().roll().unwrap();
+        partition.compact(now + 2000).unwrap().unwrap();
+        let records = partition.read(0, usize::MAX).unwrap();
+        assert!(records
+            .iter()
+            .all(|r| r.record.key.as_deref() != Some(b"key-0".as_slice())));
+        assert_eq!(next_offset, partition.next_offset());
+    }
+}
@@ -0,0 +1,99 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+/// Selects how produced records are distributed across a topic's partitions.
+pub enum Partitioning {
+    /// Hash the record key to a stable partition, falling back to round robin for
+    /// records without a key.
+    #[default]
+    Key,
+    /// Distribute records evenly across all partitions.
+    RoundRobin,
+    /// Write every record to the supplied partition.
+    Explicit(u32),
+}
+
+/// Returns the stable partition for the supplied key given a partition count.
+///
+/// ```
+/// # use librift::topic::partition_for_key;
+/// let partition = partition_for_key(b"user-1234", 8);
+/// assert!(partition < 8);
+/// assert_eq!(partition, partition_for_key(b"user-1234", 8));
+/// ```
+pub fn partition_for_key(key: &[u8], partitions: u32) -> u32 {
+    (murmur2(key) & 0x7fffffff) % partitions
+}
+
+/// An implementation of the 32 bit murmur2 hash, with the same seed Kafka uses
+/// so keyed data lands on the same partition numbers.
+pub fn murmur2(data: &[u8]) -> u32 {
+    const SEED: u32 = 0x9747b28c;
+    const M: u32 = 0x5bd1e995;
+    const R: u32 = 24;
+
+    let len = data.len();
+    let mut h = SEED ^ len as u32;
+
+    let (chunks, tail) = data.as_chunks::<4>();
+    for chunk in chunks {
+        let mut k = u32::from_le_bytes(*chunk);
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M);
+        h ^= k;
+    }
+
+    if tail.len() >= 3 {
+        h ^= (tail[2] as u32) << 16;
+    }
+    if tail.len() >= 2 {
+        h ^= (tail[1] as u32) << 8;
+    }
+    if !tail.is_empty() {
+        h ^= tail[0] as u32;
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^= h >> 15;
+    h
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_murmur2() {
+        // Reference values taken from Kafka's own murmur2 test cases.
+        let cases: &[(&[u8], i32)] = &[
+            (b"21", -973932308),
+            (b"foobar", -790332482),
+            (b"a-little-bit-long-string", -985981536),
+            (b"a-little-bit-longer-string", -1486304829),
+            (
+                b"lkjh234lh9fiuh90y23oiuhsafujhadof229phr9h19h89h8",
+                -58897971,
+            ),
+            (b"abc", 479470107),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(*expected, murmur2(input) as i32);
+        }
+    }
+
+    #[test]
+    fn test_partition_for_key() {
+        for partitions in 1..16 {
+            for key in ["a", "b", "user-1", "user-2", ""] {
+                let partition = partition_for_key(key.as_bytes(), partitions);
+                assert!(partition < partitions);
+            }
+        }
+    }
+}
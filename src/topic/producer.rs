@@ -0,0 +1,394 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::record::{BatchHeader, ControlMarker, ProducerBatch};
+
+use super::error::{Error, Result};
+
+/// The file, within a partition's log directory, producer state is snapshot to.
+pub(super) const PRODUCERS_FILE: &str = "producers.json";
+
+/// The last batch a producer wrote to a partition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct LastBatch {
+    first_sequence: u32,
+    last_sequence: u32,
+    base_offset: u64,
+}
+
+/// What a partition knows about a single producer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct ProducerEntry {
+    epoch: u16,
+    last_batch: Option<LastBatch>,
+    /// The offset of the producer's latest batch or control marker.
+    #[serde(default)]
+    last_offset: u64,
+    /// The offset of the producer's first record in its ongoing transaction.
+    txn_start: Option<u64>,
+}
+
+/// The offsets spanned by an aborted transaction, from its first record to its
+/// abort marker inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct AbortedTxn {
+    pub(super) producer_id: u64,
+    pub(super) first_offset: u64,
+    pub(super) last_offset: u64,
+}
+
+/// The idempotent producers that have written to a partition, the transactions
+/// they have in progress or aborted, and the offset of the log the state reflects.
+///
+/// Only the last batch of each producer is tracked, so a producer may only have
+/// a single batch in flight per partition at a time. Producers are forgotten
+/// once retention has removed everything they wrote.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct Producers {
+    offset: u64,
+    producers: BTreeMap<u64, ProducerEntry>,
+    aborted: Vec<AbortedTxn>,
+}
+
+impl Producers {
+    /// Load the snapshot persisted in the supplied partition directory, or an
+    /// empty state if none has been persisted yet.
+    pub(super) fn load(dir: &Path) -> Result<Producers> {
+        let path = dir.join(PRODUCERS_FILE);
+        let raw = match fs::read(&path) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Producers::default()),
+            Err(e) => return Err(Error::io(&path, e)),
+        };
+        serde_json::from_slice(&raw).map_err(|source| Error::Metadata { path, source })
+    }
+
+    /// Atomically persist a snapshot of this state into the supplied partition directory.
+    pub(super) fn store(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(PRODUCERS_FILE);
+        let tmp = dir.join(format!("{}.tmp", PRODUCERS_FILE));
+        let raw = serde_json::to_vec(self).map_err(|source| Error::Metadata {
+            path: path.clone(),
+            source,
+        })?;
+
+        let mut file = File::create(&tmp).map_err(|e| Error::io(&tmp, e))?;
+        file.write_all(&raw).map_err(|e| Error::io(&tmp, e))?;
+        file.sync_all().map_err(|e| Error::io(&tmp, e))?;
+        fs::rename(&tmp, &path).map_err(|e| Error::io(&path, e))
+    }
+
+    /// Returns the offset of the first entry not yet reflected in this state.
+    pub(super) fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the offset below which every transaction has completed, which is
+    /// the first offset of the earliest transaction still in progress.
+    pub(super) fn stable_offset(&self) -> u64 {
+        self.producers
+            .values()
+            .filter_map(|entry| entry.txn_start)
+            .min()
+            .unwrap_or(self.offset)
+    }
+
+    /// Returns the aborted transactions overlapping the supplied offsets.
+    pub(super) fn aborted(&self, start: u64, end: u64) -> Vec<AbortedTxn> {
+        self.aborted
+            .iter()
+            .filter(|txn| txn.last_offset >= start && txn.first_offset < end)
+            .copied()
+            .collect()
+    }
+
+    /// Check a batch of `count` records from the supplied producer against the
+    /// last batch it wrote, returning the base offset the batch was originally
+    /// written at if it is a retry of that batch.
+    pub(super) fn check(&self, producer: &ProducerBatch, count: u32) -> Result<Option<u64>> {
+        let entry = match self.producers.get(&producer.producer_id) {
+            Some(entry) => entry,
+            // The producer's earlier batches may have aged out of the log.
+            None => return Ok(None),
+        };
+        self.check_epoch(producer.producer_id, producer.epoch)?;
+
+        let last = entry.last_batch.filter(|_| producer.epoch == entry.epoch);
+        let expected = last.map_or(0, |last| last.last_sequence.wrapping_add(1));
+        if producer.sequence == expected {
+            return Ok(None);
+        }
+        if let Some(last) = last {
+            if producer.sequence == last.first_sequence
+                && producer.last_sequence(count) == last.last_sequence
+            {
+                return Ok(Some(last.base_offset));
+            }
+            if producer.sequence < expected {
+                return Err(Error::DuplicateSequence {
+                    producer_id: producer.producer_id,
+                    sequence: producer.sequence,
+                });
+            }
+        }
+        Err(Error::OutOfOrderSequence {
+            producer_id: producer.producer_id,
+            expected,
+            received: producer.sequence,
+        })
+    }
+
+    /// Ensure the supplied producer epoch has not been fenced by a newer one.
+    pub(super) fn check_epoch(&self, producer_id: u64, epoch: u16) -> Result<()> {
+        match self.producers.get(&producer_id) {
+            Some(entry) if epoch < entry.epoch => Err(Error::ProducerFenced {
+                producer_id,
+                epoch,
+                current: entry.epoch,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Record that a batch with the supplied header and `count` records was
+    /// written at `base_offset`.
+    pub(super) fn record(&mut self, header: &BatchHeader, base_offset: u64, count: u32) {
+        self.offset = base_offset + count as u64;
+        let producer = match &header.producer {
+            Some(producer) => producer,
+            None => return,
+        };
+
+        let entry = self
+            .producers
+            .entry(producer.producer_id)
+            .or_insert(ProducerEntry {
+                epoch: producer.epoch,
+                last_batch: None,
+                last_offset: base_offset,
+                txn_start: None,
+            });
+        entry.last_offset = base_offset;
+        if producer.epoch > entry.epoch {
+            entry.epoch = producer.epoch;
+            entry.last_batch = None;
+        }
+
+        match header.control {
+            Some(marker) => {
+                if let Some(first_offset) = entry.txn_start.take() {
+                    if marker == ControlMarker::Abort {
+                        self.aborted.push(AbortedTxn {
+                            producer_id: producer.producer_id,
+                            first_offset,
+                            last_offset: base_offset,
+                        });
+                    }
+                }
+            }
+            None => {
+                entry.last_batch = Some(LastBatch {
+                    first_sequence: producer.sequence,
+                    last_sequence: producer.last_sequence(count),
+                    base_offset,
+                });
+                if producer.transactional && entry.txn_start.is_none() {
+                    entry.txn_start = Some(base_offset);
+                }
+            }
+        }
+    }
+
+    /// Forget the aborted transactions that ended before the supplied offset,
+    /// and the producers with nothing left in the log that have no transaction
+    /// in progress.
+    pub(super) fn prune(&mut self, start_offset: u64) {
+        self.aborted.retain(|txn| txn.last_offset >= start_offset);
+        self.producers.retain(|_, entry| {
+            // Snapshots written before the last offset was tracked only have the
+            // offset of the last batch.
+            let last_offset = entry.last_batch.map_or(entry.last_offset, |last| {
+                last.base_offset.max(entry.last_offset)
+            });
+            entry.txn_start.is_some() || last_offset >= start_offset
+        });
+    }
+
+    /// Forget every producer, restarting the state at the supplied offset.
+    pub(super) fn reset(&mut self, offset: u64) {
+        self.offset = offset;
+        self.producers.clear();
+        self.aborted.clear();
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(tarpaulin_include))]
+mod tests {
+    use super::*;
+
+    fn batch(producer_id: u64, epoch: u16, sequence: u32) -> ProducerBatch {
+        ProducerBatch {
+            producer_id,
+            epoch,
+            sequence,
+            transactional: false,
+        }
+    }
+
+    fn header(producer: ProducerBatch) -> BatchHeader {
+        BatchHeader {
+            producer: Some(producer),
+            control: None,
+            ..BatchHeader::default()
+        }
+    }
+
+    fn marker(producer_id: u64, epoch: u16, marker: ControlMarker) -> BatchHeader {
+        BatchHeader {
+            producer: Some(ProducerBatch {
+                transactional: true,
+                ..batch(producer_id, epoch, 0)
+            }),
+            control: Some(marker),
+            ..BatchHeader::default()
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let mut producers = Producers::default();
+        assert_eq!(None, producers.check(&batch(1, 0, 5), 2).unwrap());
+        producers.record(&header(batch(1, 0, 0)), 10, 2);
+        producers.record(&BatchHeader::default(), 12, 1);
+        assert_eq!(13, producers.offset());
+
+        assert_eq!(None, producers.check(&batch(1, 0, 2), 1).unwrap());
+        assert_eq!(Some(10), producers.check(&batch(1, 0, 0), 2).unwrap());
+        assert!(matches!(
+            producers.check(&batch(1, 0, 1), 1),
+            Err(Error::DuplicateSequence { sequence: 1, .. })
+        ));
+        assert!(matches!(
+            producers.check(&batch(1, 0, 4), 1),
+            Err(Error::OutOfOrderSequence {
+                expected: 2,
+                received: 4,
+                ..
+            })
+        ));
+
+        // A new epoch restarts the sequence, and fences off the old one.
+        assert_eq!(None, producers.check(&batch(1, 1, 0), 1).unwrap());
+        assert!(matches!(
+            producers.check(&batch(1, 1, 2), 1),
+            Err(Error::OutOfOrderSequence { expected: 0, .. })
+        ));
+        producers.record(&header(batch(1, 1, 0)), 13, 1);
+        assert!(matches!(
+            producers.check(&batch(1, 0, 2), 1),
+            Err(Error::ProducerFenced { current: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_transactions() {
+        let mut producers = Producers::default();
+        let txn = |producer_id, sequence| {
+            header(ProducerBatch {
+                transactional: true,
+                ..batch(producer_id, 0, sequence)
+            })
+        };
+        producers.record(&txn(1, 0), 0, 2);
+        producers.record(&txn(2, 0), 2, 1);
+        producers.record(&txn(1, 2), 3, 1);
+        assert_eq!(0, producers.stable_offset());
+
+        producers.record(&marker(1, 0, ControlMarker::Commit), 4, 1);
+        assert_eq!(2, producers.stable_offset());
+        producers.record(&marker(2, 0, ControlMarker::Abort), 5, 1);
+        assert_eq!(6, producers.stable_offset());
+        assert!(producers.aborted(0, 2).is_empty());
+        let aborted = producers.aborted(3, 6);
+        assert_eq!(
+            vec![AbortedTxn {
+                producer_id: 2,
+                first_offset: 2,
+                last_offset: 5
+            }],
+            aborted
+        );
+
+        // An abort marker written with a bumped epoch fences the producer.
+        producers.record(&txn(1, 3), 6, 1);
+        producers.record(&marker(1, 1, ControlMarker::Abort), 7, 1);
+        assert!(matches!(
+            producers.check(&batch(1, 0, 4), 1),
+            Err(Error::ProducerFenced { current: 1, .. })
+        ));
+        assert_eq!(None, producers.check(&batch(1, 1, 0), 1).unwrap());
+        assert_eq!(2, producers.aborted(0, 8).len());
+
+        producers.prune(6);
+        assert_eq!(1, producers.aborted(0, 8).len());
+    }
+
+    #[test]
+    fn test_prune() {
+        let mut producers = Producers::default();
+        producers.record(&header(batch(1, 0, 0)), 0, 2);
+        producers.record(&header(batch(2, 0, 0)), 2, 1);
+        producers.record(
+            &header(ProducerBatch {
+                transactional: true,
+                ..batch(3, 0, 0)
+            }),
+            3,
+            1,
+        );
+        producers.record(&header(batch(1, 0, 2)), 4, 1);
+
+        // Producers are kept while any of their batches remain, and forgotten,
+        // along with their sequences, once retention removed them all.
+        producers.prune(3);
+        assert_eq!(Some(4), producers.check(&batch(1, 0, 2), 1).unwrap());
+        assert_eq!(None, producers.check(&batch(2, 0, 0), 1).unwrap());
+        assert_eq!(2, producers.producers.len());
+
+        // Producers with a transaction in progress are kept regardless.
+        producers.prune(5);
+        assert_eq!(
+            vec![3],
+            producers.producers.keys().copied().collect::<Vec<_>>()
+        );
+        producers.record(&marker(3, 0, ControlMarker::Commit), 5, 1);
+        producers.prune(6);
+        assert!(producers.producers.is_empty());
+    }
+
+    #[test]
+    fn test_store_load() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(Producers::default(), Producers::load(dir.path()).unwrap());
+
+        let mut producers = Producers::default();
+        producers.record(&header(batch(1, 0, 0)), 0, 3);
+        producers.record(&marker(1, 0, ControlMarker::Abort), 3, 1);
+        producers.store(dir.path()).unwrap();
+        assert_eq!(producers, Producers::load(dir.path()).unwrap());
+
+        producers.reset(7);
+        assert_eq!(7, producers.offset());
+        assert_eq!(None, producers.check(&batch(1, 0, 0), 3).unwrap());
+    }
+}
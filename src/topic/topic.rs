@@ -0,0 +1,168 @@
+// (c) Copyright 2022 Christian Saide
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use crate::record::{OffsetRecord, Record, TimestampType};
+use crate::storage::LogConfig;
+
+use super::error::{Error, Result};
+use super::metadata::Metadata;
+use super::metrics::metrics;
+use super::partition::Partition;
+use super::partitioner::{partition_for_key, Partitioning};
+
+/// A named collection of independently ordered partitions.
+pub struct Topic {
+    dir: PathBuf,
+    metadata: Metadata,
+    partitions: Vec<Partition>,
+    round_robin: AtomicU32,
+}
+
+impl Topic {
+    /// Open every partition of the topic described by the supplied metadata.
+    pub(super) fn open(dir: &Path, metadata: Metadata, defaults: &LogConfig) -> Result<Topic> {
+        let cfg = metadata.config.log_config(defaults)?;
+        let timestamp_type = metadata.config.timestamp_type();
+        let compression = metadata.config.compression();
+        let partitions = (0..metadata.partitions)
+            .map(|id| {
+                Partition::open(
+                    dir,
+                    &metadata.name,
+                    id,
+                    cfg.clone(),
+                    timestamp_type,
+                    compression,
+                )
+            })
+            .collect::<Result<Vec<Partition>>>()?;
+
+        Ok(Topic {
+            dir: dir.to_owned(),
+            metadata,
+            partitions,
+            round_robin: AtomicU32::new(0),
+        })
+    }
+
+    /// Returns the name of this topic.
+    pub fn name(&self) -> &str {
+        &self.metadata.name
+    }
+
+    /// Returns the directory this topic is stored in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the persisted metadata describing this topic.
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Returns which message timestamp is authoritative for this topic.
+    pub fn timestamp_type(&self) -> TimestampType {
+        self.metadata.config.timestamp_type()
+    }
+
+    /// Returns whether a record of this topic has outlived its time to live as
+    /// of `now`, in milliseconds since the epoch. A record's TTL header takes
+    /// precedence over the supplied default time to live, which in turn
+    /// overrides the topic's.
+    pub fn expired(&self, record: &OffsetRecord, default_ttl_ms: Option<u64>, now: i64) -> bool {
+        let default_ttl_ms = default_ttl_ms.or_else(|| self.metadata.config.message_ttl_ms());
+        record.is_expired(default_ttl_ms, now)
+    }
+
+    /// Count messages of this topic that were dropped or dead lettered after
+    /// outliving their time to live. Each message should only be counted once,
+    /// when it is given up on, rather than whenever it is found to be expired.
+    pub fn count_expired(&self, count: usize) {
+        metrics()
+            .expired
+            .with_label_values(&[self.name()])
+            .inc_by(count as u64);
+    }
+
+    /// Returns every partition of this topic, ordered by id.
+    pub fn partitions(&self) -> &[Partition] {
+        &self.partitions
+    }
+
+    /// Returns the partition with the supplied id.
+    pub fn partition(&self, id: u32) -> Result<&Partition> {
+        self.partitions
+            .get(id as usize)
+            .ok_or_else(|| Error::PartitionNotFound {
+                topic: self.name().to_owned(),
+                partition: id,
+            })
+    }
+
+    /// Returns the partition a record with the supplied key should be written
+    /// to under the supplied partitioning scheme.
+    pub fn select_partition(&self, partitioning: Partitioning, key: Option<&[u8]>) -> Result<u32> {
+        let count = self.partitions.len() as u32;
+        match (partitioning, key) {
+            (Partitioning::Explicit(id), _) => self.partition(id).map(|p| p.id()),
+            (Partitioning::Key, Some(key)) => Ok(partition_for_key(key, count)),
+            (Partitioning::Key, None) | (Partitioning::RoundRobin, _) => {
+                Ok(self.round_robin.fetch_add(1, Ordering::Relaxed) % count)
+            }
+        }
+    }
+
+    /// Write the supplied records using the supplied partitioning scheme,
+    /// returning the partition and offset assigned to each record in order.
+    ///
+    /// Records destined for the same partition are appended as a single batch.
+    pub fn produce(
+        &self,
+        partitioning: Partitioning,
+        records: Vec<Record>,
+    ) -> Result<Vec<(u32, u64)>> {
+        self.produce_with(partitioning, records, |partition, records| {
+            partition.append(records)
+        })
+    }
+
+    /// Distribute the supplied records across partitions like [Topic::produce],
+    /// appending each partition's batch with the supplied function, which returns
+    /// the offset assigned to the first record of the batch.
+    pub fn produce_with<E, F>(
+        &self,
+        partitioning: Partitioning,
+        records: Vec<Record>,
+        mut append: F,
+    ) -> std::result::Result<Vec<(u32, u64)>, E>
+    where
+        E: From<Error>,
+        F: FnMut(&Partition, &[Record]) -> std::result::Result<u64, E>,
+    {
+        let mut batches: BTreeMap<u32, Vec<(usize, Record)>> = BTreeMap::new();
+        for (idx, record) in records.into_iter().enumerate() {
+            let partition = self.select_partition(partitioning, record.key.as_deref())?;
+            batches.entry(partition).or_default().push((idx, record));
+        }
+
+        let mut assigned = Vec::new();
+        for (partition, batch) in batches {
+            let (indices, records): (Vec<usize>, Vec<Record>) = batch.into_iter().unzip();
+            let base_offset = append(&self.partitions[partition as usize], &records)?;
+            assigned.extend(
+                indices
+                    .into_iter()
+                    .enumerate()
+                    .map(|(delta, idx)| (idx, (partition, base_offset + delta as u64))),
+            );
+        }
+        assigned.sort_unstable_by_key(|(idx, _)| *idx);
+        Ok(assigned.into_iter().map(|(_, assigned)| assigned).collect())
+    }
+}